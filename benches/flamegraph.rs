@@ -34,11 +34,43 @@ macro_rules! flamegraph_benchmarks {
         )*
 
         criterion_group!(benches, $($name),*);
-        criterion_main!(benches);
     }
 }
 
+// Merges the stacks of the example with every stack given many different leaves, so that most
+// lines share all but their last frame with the line before them.
+fn flamegraph_merge(c: &mut Criterion) {
+    let infile = "tests/data/collapse-perf/results/example-perf-stacks-collapsed.txt";
+    let input = std::fs::read_to_string(infile).expect("Could not read file");
+    let mut lines = Vec::new();
+    for line in input.lines() {
+        let (stack, count) = line.rsplit_once(' ').expect("Invalid line");
+        lines.extend((0..64).map(|leaf| format!("{};leaf{} {}", stack, leaf, count)));
+    }
+    lines.sort();
+    let bytes: usize = lines.iter().map(|line| line.len() + 1).sum();
+
+    let mut group = c.benchmark_group("flamegraph_merge");
+    let mut opt = Options::default();
+    opt.no_sort = true;
+    group
+        .bench_function("flamegraph", |b| {
+            b.iter(|| {
+                let lines = lines.iter().map(String::as_str);
+                let _folder = flamegraph::from_lines::<_, _, usize>(&mut opt, lines, io::sink());
+            })
+        })
+        .throughput(Throughput::Bytes(bytes as u64));
+
+    group.finish();
+}
+
+criterion_group!(merge, flamegraph_merge);
+criterion_main!(benches, merge);
+
 flamegraph_benchmarks! {
     flamegraph: ("tests/data/collapse-perf/results/example-perf-stacks-collapsed.txt",
-                 { let mut opt = Options::default(); opt.reverse_stack_order = true; opt })
+                 { let mut opt = Options::default(); opt.reverse_stack_order = true; opt }),
+    flamegraph_sorted: ("tests/data/collapse-perf/results/example-perf-stacks-collapsed.txt",
+                        Options::default())
 }
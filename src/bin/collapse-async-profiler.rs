@@ -0,0 +1,84 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::async_profiler::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-async-profiler",
+    about,
+    after_help = "\
+[1] This processes the collapsed output of async-profiler whose stacks start with frames for
+    their event type and thread state, such as [cpu];[RUNNABLE];[main tid=1];..., as when the
+    JFR recordings of Spark executors are converted with jfrconv.
+
+    Stacks of different event types count different things, such as samples and bytes, so
+    their event frames are kept unless a single event type is selected with --event.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Leave out the frames of thread states, such as [RUNNABLE]
+    #[clap(long = "strip-state")]
+    strip_state: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Only fold the stacks of this event type, such as cpu, wall, alloc or lock
+    #[clap(long = "event", value_name = "EVENT")]
+    event: Option<String>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Collapsed output of async-profiler, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.event = self.event;
+        options.strip_state = self.strip_state;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-async-profiler");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
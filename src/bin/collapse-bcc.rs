@@ -0,0 +1,85 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::bcc::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-bcc",
+    about,
+    after_help = "\
+[1] This processes the multi-line stacks that bcc's profile and offcputime tools print when
+    they are run without -f, for example:
+
+        profile -d 30 > out.profile
+        offcputime -d 30 > out.offcputime
+
+    The output of stackcount, which has no process names, is understood as well.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Annotate kernel functions with a `_[k]`
+    #[clap(long = "kernel")]
+    kernel: bool,
+
+    /// Include PID with process names
+    #[clap(long = "pid")]
+    pid: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// bcc tool output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.annotate_kernel = self.kernel;
+        options.include_pid = self.pid;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-bcc");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
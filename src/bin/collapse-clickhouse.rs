@@ -0,0 +1,88 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::clickhouse::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-clickhouse",
+    about,
+    after_help = "\
+[1] This processes TabSeparatedWithNames exports of ClickHouse's system.trace_log table, with
+    a trace column of addresses and optionally a symbols column of their names:
+
+        clickhouse-client --allow_introspection_functions=1 --query \"
+            SELECT query_id, trace_type, trace,
+                   arrayMap(x -> demangle(addressToSymbol(x)), trace) AS symbols
+            FROM system.trace_log WHERE event_date = today()
+            FORMAT TabSeparatedWithNames\" > trace_log.tsv
+
+    Every row counts one sample, or the value of its samples column if it has one.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Include the id of the query as the root frame
+    #[clap(long = "query-id")]
+    query_id: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Only fold the samples of this trace type, such as CPU, Real or Memory
+    #[clap(long = "trace-type", value_name = "TYPE")]
+    trace_type: Option<String>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Export of system.trace_log, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.include_query_id = self.query_id;
+        options.trace_type = self.trace_type;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-clickhouse");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
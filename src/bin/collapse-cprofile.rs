@@ -0,0 +1,79 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::cprofile::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-cprofile",
+    about,
+    after_help = "\
+[1] This processes either a binary `pstats` dump, created as follows:
+        python -m cProfile -o result.pstats <script>
+    or the text table printed by `python -m cProfile <script>`. Since cProfile only records
+    direct caller/callee relationships, the stacks are reconstructed approximately from
+    cumulative times. The text table has no caller information, so each function is reported
+    as a single frame weighted by its own time. All counts are in microseconds.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Don't include file names and line numbers with function names
+    #[clap(long = "no-filenames")]
+    no_filenames: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// cProfile output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.no_filenames = self.no_filenames;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-cprofile");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
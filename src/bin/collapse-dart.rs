@@ -0,0 +1,76 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::dart::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-dart",
+    about,
+    after_help = "\
+[1] This processes a Dart CPU profile in JSON, either a CpuSamples object from the Dart VM
+    service or a CPU profile timeline with stackFrames and traceEvents, as exported from
+    DevTools. Several profiles may be given as a JSON array.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Start every stack with the isolate it was sampled in
+    #[clap(long = "include-isolate")]
+    include_isolate: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Dart CPU profile file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.include_isolate = self.include_isolate;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-dart");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
@@ -28,6 +28,10 @@ struct Opt {
     #[clap(long = "includeoffset")]
     includeoffset: bool,
 
+    /// Escape semicolons in function names as `\;` instead of replacing them with colons
+    #[clap(long = "escape-semicolons")]
+    escape_semicolons: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -48,6 +52,9 @@ struct Opt {
     )]
     nthreads: usize,
 
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -60,13 +67,14 @@ impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
         options.includeoffset = self.includeoffset;
+        options.escape_semicolons = self.escape_semicolons;
         options.nthreads = self.nthreads;
         (self.infile, options)
     }
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("collapse-dtrace");
 
     // Initialize logger
     if !opt.quiet {
@@ -80,6 +88,8 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let mut output = opt.output.open()?;
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
 }
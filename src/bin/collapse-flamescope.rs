@@ -0,0 +1,125 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::flamescope::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-flamescope",
+    about,
+    after_help = "\
+[1] This processes the output of perf script with the time of every sample, as FlameScope
+    loads it, and counts the samples of every stack in each time bucket:
+
+        perf script -F comm,pid,tid,cpu,time,event,ip,sym,dso > out.perf
+        inferno-collapse-flamescope --bucket 0.02 --start 12.4 --end 13 out.perf > out.folded
+
+    Every line ends with a count for every bucket. -v logs the number of buckets, which is what
+    inferno-flamegraph --time-windows takes to draw them.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Include raw addresses where symbols can't be found
+    #[clap(long = "addrs")]
+    addrs: bool,
+
+    /// All annotations (--kernel --jit)
+    #[clap(long = "all")]
+    all: bool,
+
+    /// Annotate jit functions with a `_[j]`
+    #[clap(long = "jit")]
+    jit: bool,
+
+    /// Annotate kernel functions with a `_[k]`
+    #[clap(long = "kernel")]
+    kernel: bool,
+
+    /// Include PID with process names
+    #[clap(long = "pid")]
+    pid: bool,
+
+    /// Include TID and PID with process names
+    #[clap(long = "tid")]
+    tid: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Length of every time bucket, in seconds
+    #[clap(long = "bucket", default_value = "1", value_name = "SECONDS")]
+    bucket: f64,
+
+    /// Leave out the samples before this offset from the first sample, in seconds
+    #[clap(long = "start", value_name = "SECONDS")]
+    start: Option<f64>,
+
+    /// Leave out the samples from this offset from the first sample on, in seconds
+    #[clap(long = "end", value_name = "SECONDS")]
+    end: Option<f64>,
+
+    /// Event filter [default: first encountered event]
+    #[clap(long = "event-filter", value_name = "STRING")]
+    event_filter: Option<String>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Perf script output file with the time of every sample, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.bucket = self.bucket;
+        options.start = self.start;
+        options.end = self.end;
+        options.perf.include_pid = self.pid;
+        options.perf.include_tid = self.tid;
+        options.perf.include_addrs = self.addrs;
+        options.perf.annotate_jit = self.jit || self.all;
+        options.perf.annotate_kernel = self.kernel || self.all;
+        options.perf.event_filter = self.event_filter;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-flamescope");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
@@ -0,0 +1,86 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::builder::TypedValueParser;
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::go_trace::{Folder, Options, Stacks};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-go-trace",
+    about,
+    after_help = "\
+[1] This processes Go execution traces, as written by runtime/trace or with:
+
+        go test -trace=trace.out
+
+    Counts are how long goroutines ran for, in nanoseconds. Traces of Go 1.22 and later are
+    supported.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Fold the time goroutines ran for by the stack they stopped running in, or by the stack
+    /// that created them
+    #[clap(
+        long = "stacks",
+        value_name = "STACKS",
+        default_value = "running",
+        value_parser = clap::builder::PossibleValuesParser::new(Stacks::VARIANTS).map(|s| s.parse::<Stacks>().unwrap()),
+    )]
+    stacks: Stacks,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Execution trace file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.stacks = self.stacks;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-go-trace");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
@@ -41,6 +41,9 @@ struct Opt {
     )]
     nthreads: usize,
 
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -58,7 +61,7 @@ impl Opt {
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("collapse-guess");
 
     // Initialize logger
     if !opt.quiet {
@@ -72,6 +75,8 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let mut output = opt.output.open()?;
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
 }
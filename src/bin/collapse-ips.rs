@@ -0,0 +1,93 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::ips::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-ips",
+    about,
+    after_help = "\
+[1] This processes the .ips crash and hang reports of macOS and iOS. Every report counts one
+    sample, for the backtrace of the thread that crashed or hung. Given directories, it folds
+    all of the .ips files in them into one dataset:
+
+        inferno-collapse-ips ~/Library/Logs/DiagnosticReports/ > hangs.folded
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Count the backtraces of all threads of every report
+    #[clap(long = "all-threads")]
+    all_threads: bool,
+
+    /// Include the name of the process as the root frame
+    #[clap(long = "process")]
+    process: bool,
+
+    /// Include the image of every frame, as in image`symbol
+    #[clap(long = "image")]
+    image: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Reports, or directories of them, to fold together, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.all_threads = self.all_threads;
+        options.include_process = self.process;
+        options.include_image = self.image;
+        (self.infiles, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-ips");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infiles, options) = opt.into_parts();
+    let mut folder = Folder::from(options);
+    if infiles.is_empty() {
+        folder.collapse_file(None::<PathBuf>, &mut output)?;
+    } else {
+        folder.collapse_paths(&infiles, &mut output)?;
+    }
+    output.finish()
+}
@@ -0,0 +1,91 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::latency::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-latency",
+    about,
+    after_help = "\
+[1] This processes latency traces with a line for every event, with its time and its latency,
+    and optionally the id of its stack or code path, separated by whitespace or commas, as for
+    trace2heatmap.pl:
+
+        1690000000.000120 250 read
+        1690000000.000410 1790 read
+
+    Every event is folded into a frame for the band of its latency, below its stack id.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Count the latency of the events in every band rather than how many there are
+    #[clap(long = "weighted")]
+    weighted: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Unit of the latencies, shown in the frames of their bands
+    #[clap(long = "unit", default_value = "us", value_name = "UNIT")]
+    unit: String,
+
+    /// Make all bands this wide, in the unit of the latencies [default: bands double in width]
+    #[clap(long = "band-width", value_name = "UINT")]
+    band_width: Option<u64>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Latency trace file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.unit = self.unit;
+        options.band_width = self.band_width;
+        options.weighted = self.weighted;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-latency");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
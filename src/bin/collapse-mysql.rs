@@ -0,0 +1,82 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::mysql::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-mysql",
+    about,
+    after_help = "\
+[1] This processes a table of performance_schema events with a header row, as printed by
+    `mysql --batch` or exported as CSV. It needs an EVENT_NAME and a TIMER_WAIT (or
+    SUM_TIMER_WAIT) column, and uses USER, CURRENT_SCHEMA and DIGEST_TEXT columns (or the like)
+    if there are any. Counts are in nanoseconds.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Only keep the first UINT /-separated parts of event names
+    #[clap(long = "event-name-depth", value_name = "UINT")]
+    event_name_depth: Option<usize>,
+
+    /// Cut statements longer than UINT characters short
+    #[clap(long = "statement-width", value_name = "UINT")]
+    statement_width: Option<usize>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Exported performance_schema table, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.event_name_depth = self.event_name_depth;
+        options.statement_width = self.statement_width;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-mysql");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
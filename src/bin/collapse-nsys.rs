@@ -0,0 +1,101 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::nsys::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-nsys",
+    about,
+    after_help = "\
+[1] This processes the CPU samples of Nsight Systems reports, exported to SQLite with
+
+        nsys export --type sqlite report.nsys-rep
+
+    and queried as CSV with
+
+        sqlite3 -csv -header report.sqlite \"
+          SELECT ce.start, ce.globalTid, sc.stackDepth, sy.value AS symbol, mo.value AS module
+          FROM COMPOSITE_EVENTS ce
+          JOIN SAMPLING_CALLCHAINS sc ON sc.id = ce.id
+          LEFT JOIN StringIds sy ON sy.id = sc.symbol
+          LEFT JOIN StringIds mo ON mo.id = sc.module
+          ORDER BY ce.start, ce.globalTid, sc.stackDepth;
+          SELECT n.start, n.end, n.globalTid, COALESCE(n.text, s.value) AS text
+          FROM NVTX_EVENTS n LEFT JOIN StringIds s ON s.id = n.textId
+          WHERE n.end IS NOT NULL;\"
+
+    The second query, for the NVTX ranges, is only needed with --nvtx.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Nest samples in frames for the NVTX ranges they were taken in
+    #[clap(long = "nvtx")]
+    nvtx: bool,
+
+    /// Start every stack with a frame for its thread, as <pid>/<tid>
+    #[clap(long = "thread")]
+    thread: bool,
+
+    /// Prefix function names with the file name of their module
+    #[clap(long = "module")]
+    module: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// CSV file of samples, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.nvtx = self.nvtx;
+        options.include_thread = self.thread;
+        options.include_module = self.module;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-nsys");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
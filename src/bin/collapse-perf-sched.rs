@@ -0,0 +1,96 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::builder::TypedValueParser;
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::perf_sched::{Folder, Measure, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-perf-sched",
+    about,
+    after_help = "\
+[1] This processes the output of `perf sched timehist -g`, or of `perf script` for a recording of
+    scheduler events with call graphs, like:
+
+        perf record -e sched:sched_switch -e sched:sched_wakeup -g -a -- sleep 10
+        perf script | inferno-collapse-perf-sched > out.folded
+
+    Stacks are where tasks were switched out, and counts are in microseconds.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Put the stacks of waking tasks on top of the stacks of the tasks they woke
+    #[clap(long = "wakers")]
+    wakers: bool,
+
+    /// Include TID with task names
+    #[clap(long = "tid")]
+    include_tid: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Measure the time tasks spent off-CPU, or only the time they waited to run after being woken
+    #[clap(
+        long = "measure",
+        value_name = "MEASURE",
+        default_value = "off-cpu",
+        value_parser = clap::builder::PossibleValuesParser::new(Measure::VARIANTS).map(|s| s.parse::<Measure>().unwrap()),
+    )]
+    measure: Measure,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// perf sched timehist or perf script output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.measure = self.measure;
+        options.wakers = self.wakers;
+        options.include_tid = self.include_tid;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-perf-sched");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
@@ -1,13 +1,11 @@
 use std::io;
 use std::path::PathBuf;
 
+use clap::builder::TypedValueParser;
 use clap::{ArgAction, Parser};
 use env_logger::Env;
-use inferno::collapse::perf::{Folder, Options};
-use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
-use once_cell::sync::Lazy;
-
-static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
+use inferno::collapse::perf::{Folder, MemWeight, Options};
+use inferno::collapse::Collapse;
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -18,7 +16,9 @@ static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
         perf script -f comm,pid,tid,cpu,time,event,ip,sym,dso,trace
     for Linux >= 4.1:
         perf script -F comm,pid,tid,cpu,time,event,ip,sym,dso,trace
-    If you save this output add --header on Linux >= 3.14 to include perf info."
+    If you save this output add --header on Linux >= 3.14 to include perf info.
+[2] perf script must emit the data source and weight of each sample; eg:
+        perf script -F comm,pid,tid,cpu,time,period,event,data_src,weight,ip,sym,dso"
 )]
 struct Opt {
     // ************* //
@@ -32,6 +32,10 @@ struct Opt {
     #[clap(long = "all")]
     all: bool,
 
+    /// Escape semicolons in function names as `\;` instead of replacing them with colons
+    #[clap(long = "escape-semicolons")]
+    escape_semicolons: bool,
+
     /// Annotate jit functions with a `_[j]`
     #[clap(long = "jit")]
     jit: bool,
@@ -40,6 +44,14 @@ struct Opt {
     #[clap(long = "kernel")]
     kernel: bool,
 
+    /// Split `perf kvm` profiles into guest and host stacks, grouped by VM and vCPU
+    #[clap(long = "kvm")]
+    kvm: bool,
+
+    /// Demangle OCaml symbols and fold the OCaml runtime's GC and polling frames
+    #[clap(long = "ocaml")]
+    ocaml: bool,
+
     /// Include PID with process names
     #[clap(long = "pid")]
     pid: bool,
@@ -63,14 +75,31 @@ struct Opt {
     #[clap(long = "event-filter", value_name = "STRING")]
     event_filter: Option<String>,
 
-    /// Number of threads to use
+    /// A copy of /proc/kallsyms from the profiled machine, to name the kernel addresses that perf
+    /// script couldn't
+    #[clap(long = "kallsyms", value_name = "PATH")]
+    kallsyms: Option<PathBuf>,
+
+    /// Fold `perf mem` samples by data source, weighted by number of loads or by latency [2]
     #[clap(
-        short = 'n',
-        long = "nthreads",
-        default_value = &**NTHREADS,
-        value_name = "UINT"
+        long = "mem-data-source",
+        value_parser = clap::builder::PossibleValuesParser::new(MemWeight::VARIANTS).map(|s| s.parse::<MemWeight>().unwrap()),
+        value_name = "WEIGHT"
     )]
-    nthreads: usize,
+    mem_data_source: Option<MemWeight>,
+
+    /// Number of threads to use [default: number of logical cores, or 1 for files smaller than
+    /// 16 MiB]
+    #[clap(short = 'n', long = "nthreads", value_name = "UINT")]
+    nthreads: Option<usize>,
+
+    /// A perf map such as /tmp/perf-PID.map, to name the user-space addresses that perf script
+    /// couldn't (may be repeated)
+    #[clap(long = "symbol-map", value_name = "PATH")]
+    symbol_maps: Vec<PathBuf>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
 
     // ************ //
     // *** ARGS *** //
@@ -92,17 +121,26 @@ impl Opt {
         options.include_pid = self.pid;
         options.include_tid = self.tid;
         options.include_addrs = self.addrs;
+        options.escape_semicolons = self.escape_semicolons;
         options.annotate_jit = self.jit || self.all;
         options.annotate_kernel = self.kernel || self.all;
         options.event_filter = self.event_filter;
-        options.nthreads = self.nthreads;
+        options.mem_data_source = self.mem_data_source;
+        if let Some(nthreads) = self.nthreads {
+            options.nthreads = nthreads;
+            options.multithreaded_min_bytes = 0;
+        }
+        options.kvm = self.kvm;
+        options.ocaml = self.ocaml;
+        options.kallsyms = self.kallsyms;
+        options.symbol_maps = self.symbol_maps;
         options.skip_after = self.skip_after;
         (self.infile, options)
     }
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("collapse-perf");
 
     // Initialize logger
     if !opt.quiet {
@@ -116,6 +154,8 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let mut output = opt.output.open()?;
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
 }
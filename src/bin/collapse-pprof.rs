@@ -0,0 +1,117 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::pprof::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-pprof",
+    about,
+    after_help = "\
+[1] This processes pprof profiles, gzipped or not, as fetched from /debug/pprof/ endpoints or
+    stored by continuous profilers such as Parca. Given directories, it folds all of the profiles
+    in them into one dataset, optionally only those taken between --since and --until:
+
+        inferno-collapse-pprof --since 1714557600 --until 1714561200 profiles/ > cpu.folded
+
+    Built with the reqwest feature, it can fetch the profile from the endpoint of a running
+    service with --url:
+
+        inferno-collapse-pprof --url 'http://localhost:6060/debug/pprof/profile?seconds=30'
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// The sample type to count, such as cpu or alloc_space [default: that of each profile]
+    #[clap(long = "sample-type", value_name = "NAME")]
+    sample_type: Option<String>,
+
+    /// Only fold profiles that end at or after this Unix time, in seconds
+    #[clap(long = "since", value_name = "SECONDS", value_parser = parse_time)]
+    since: Option<SystemTime>,
+
+    /// Only fold profiles that start before this Unix time, in seconds
+    #[clap(long = "until", value_name = "SECONDS", value_parser = parse_time)]
+    until: Option<SystemTime>,
+
+    /// Fetch the profile from this URL, such as a /debug/pprof/profile?seconds=30 endpoint
+    #[cfg(feature = "reqwest")]
+    #[clap(long = "url", value_name = "URL", conflicts_with = "infiles")]
+    url: Option<String>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Profiles, or directories of them, to fold together, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.sample_type = self.sample_type;
+        options.since = self.since;
+        options.until = self.until;
+        (self.infiles, options)
+    }
+}
+
+fn parse_time(s: &str) -> Result<SystemTime, String> {
+    let seconds: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))?;
+    Ok(SystemTime::UNIX_EPOCH + duration)
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-pprof");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    #[cfg(feature = "reqwest")]
+    let url = opt.url.clone();
+    let (infiles, options) = opt.into_parts();
+    let mut folder = Folder::from(options);
+    #[cfg(feature = "reqwest")]
+    if let Some(url) = url {
+        folder.collapse_url(&url, &mut output)?;
+        return output.finish();
+    }
+    if infiles.is_empty() {
+        folder.collapse_file(None::<PathBuf>, &mut output)?;
+    } else {
+        folder.collapse_paths(&infiles, &mut output)?;
+    }
+    output.finish()
+}
@@ -20,6 +20,9 @@ struct Opt {
     )]
     nthreads: usize,
 
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
     #[clap(value_name = "PATH")]
     /// Collapse output file, or STDIN if not specified
     infile: Option<PathBuf>,
@@ -34,7 +37,9 @@ impl Opt {
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("collapse-recursive");
+    let mut output = opt.output.open()?;
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
 }
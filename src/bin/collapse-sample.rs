@@ -30,6 +30,12 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -47,7 +53,7 @@ impl Opt {
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("collapse-sample");
 
     // Initialize logger
     if !opt.quiet {
@@ -61,6 +67,8 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let mut output = opt.output.open()?;
     let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
 }
@@ -0,0 +1,86 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::unity::{Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-unity",
+    about,
+    after_help = "\
+[1] This processes Unity Profiler captures exported to JSON by an editor script, which walks
+    the hierarchy of every frame of a .data capture with ProfilerDriver.GetHierarchyFrameDataView:
+
+        {\"frames\": [{\"threads\": [{\"name\": \"Main Thread\", \"samples\": [
+            {\"name\": \"PlayerLoop\", \"totalTime\": 14.2, \"children\": [...]}]}]}]}
+
+    Counts are the microseconds that each sample took itself, leaving out its children, summed
+    over all frames of the capture.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Include the name of the thread as the root frame
+    #[clap(long = "thread-names")]
+    thread_names: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Only fold the samples of the thread with this name, such as "Main Thread"
+    #[clap(long = "thread", value_name = "NAME")]
+    thread: Option<String>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Unity Profiler capture exported to JSON, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.include_thread = self.thread_names;
+        options.thread = self.thread;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-unity");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
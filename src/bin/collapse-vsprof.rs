@@ -25,6 +25,12 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -34,7 +40,7 @@ struct Opt {
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("collapse-vsprof");
 
     // Initialize logger
     if !opt.quiet {
@@ -48,5 +54,7 @@ fn main() -> io::Result<()> {
         .init();
     }
 
-    Folder::default().collapse_file_to_stdout(opt.infile)
+    let mut output = opt.output.open()?;
+    Folder::default().collapse_file(opt.infile, &mut output)?;
+    output.finish()
 }
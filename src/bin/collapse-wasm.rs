@@ -0,0 +1,83 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::wasm::{self, Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-wasm",
+    about,
+    after_help = "\
+[1] This processes the guest profiles of WebAssembly programs that wasmtime writes with:
+
+        wasmtime run --profile=guest,out.json,1ms app.wasm
+
+    Functions that are named by their index, as in wasm-function[12], are named after the
+    function names in the name section of the module given with --module, such as a debug
+    build of the same program.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// WebAssembly module to read function names from
+    #[clap(long = "module", value_name = "PATH")]
+    module: Option<PathBuf>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Guest profile file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> io::Result<(Option<PathBuf>, Options)> {
+        let mut options = Options::default();
+        if let Some(module) = &self.module {
+            options.function_names = wasm::function_names(&fs::read(module)?)?;
+        }
+        Ok((self.infile, options))
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-wasm");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts()?;
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
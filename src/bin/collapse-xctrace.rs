@@ -28,6 +28,12 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -37,7 +43,7 @@ struct Opt {
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("collapse-xctrace");
 
     // Initialize logger
     if !opt.quiet {
@@ -51,5 +57,7 @@ fn main() -> io::Result<()> {
         .init();
     }
 
-    Folder::default().collapse_file_to_stdout(opt.infile.as_ref())
+    let mut output = opt.output.open()?;
+    Folder::default().collapse_file(opt.infile.as_ref(), &mut output)?;
+    output.finish()
 }
@@ -0,0 +1,89 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::xray::{self, Folder, Options};
+use inferno::collapse::Collapse;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-xray",
+    about,
+    after_help = "\
+[1] This processes the basic mode logs of LLVM XRay, as written by programs built with
+    -fxray-instrument when run with:
+
+        XRAY_OPTIONS=\"patch_premain=true xray_mode=xray-basic\" ./app
+
+    or the YAML that llvm-xray converts logs of any mode to with:
+
+        llvm-xray convert -symbolize -instr_map=./app -output-format=yaml xray-log.app.XXXXXX
+
+    Counts are how long functions ran for themselves, in nanoseconds. Functions of binary logs
+    are named after the instrumentation map given with --instr-map, as written by:
+
+        llvm-xray extract -symbolize ./app
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Output of `llvm-xray extract -symbolize` to read function names from
+    #[clap(long = "instr-map", value_name = "PATH")]
+    instr_map: Option<PathBuf>,
+
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// XRay log or YAML file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> io::Result<(Option<PathBuf>, Options)> {
+        let mut options = Options::default();
+        if let Some(instr_map) = &self.instr_map {
+            options.function_names = xray::function_names(&fs::read_to_string(instr_map)?);
+        }
+        Ok((self.infile, options))
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt: Opt = inferno::cli::parse("collapse-xray");
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let mut output = opt.output.open()?;
+    let (infile, options) = opt.into_parts()?;
+    Folder::from(options).collapse_file(infile.as_ref(), &mut output)?;
+    output.finish()
+}
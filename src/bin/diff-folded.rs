@@ -1,9 +1,10 @@
-use std::io::{self, IsTerminal};
+use std::io::{self, Write};
 use std::path::PathBuf;
 
 use clap::{ArgAction, Parser};
 use env_logger::Env;
 use inferno::differential::{self, Options};
+use inferno::folded;
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -38,6 +39,10 @@ struct Opt {
     #[clap(short = 's', long = "strip-hex")]
     strip_hex: bool,
 
+    /// Reverse the stacks of the output, as inferno-flamegraph --reverse does
+    #[clap(short = 'r', long = "reverse")]
+    reverse: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -46,6 +51,12 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    #[clap(flatten)]
+    output: inferno::cli::OutArg,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -72,7 +83,7 @@ impl Opt {
 }
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let opt: Opt = inferno::cli::parse("diff-folded");
 
     // Initialize logger
     if !opt.quiet {
@@ -86,16 +97,17 @@ fn main() -> io::Result<()> {
         .init();
     }
 
+    let mut output = opt.output.open()?;
+    let reverse = opt.reverse;
     let (folded1, folded2, options) = opt.into_parts();
-
-    if std::io::stdout().is_terminal() {
-        differential::from_files(options, folded1, folded2, io::stdout().lock())
+    if reverse {
+        let mut differential = Vec::new();
+        differential::from_files(options, folded1, folded2, &mut differential)?;
+        for line in folded::reverse(String::from_utf8_lossy(&differential).lines()) {
+            writeln!(output, "{}", line)?;
+        }
     } else {
-        differential::from_files(
-            options,
-            folded1,
-            folded2,
-            io::BufWriter::new(io::stdout().lock()),
-        )
+        differential::from_files(options, folded1, folded2, &mut output)?;
     }
+    output.finish()
 }
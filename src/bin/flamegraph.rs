@@ -1,16 +1,25 @@
-use std::io::{self, IsTerminal};
+use std::io;
 use std::path::{Path, PathBuf};
 
 use clap::builder::TypedValueParser;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, CommandFactory, Parser};
 use env_logger::Env;
+use inferno::cli::{AtomicFile, Output};
 use inferno::flamegraph::color::{
-    parse_hex_color, BackgroundColor, Color, PaletteMap, SearchColor, StrokeColor,
+    parse_hex_color, BackgroundColor, Color, ColorModifier, PaletteMap, PaletteRange, SearchColor,
+    StrokeColor,
+};
+use inferno::flamegraph::report::{self, GateFormat, ReportFormat};
+use inferno::flamegraph::{
+    self, defaults, Corner, Direction, FrameWidthSource, HotColdLayout, Options, Palette,
+    PrimaryMetric, ScriptSource, StackedScale, SuffixAnnotation, TextTruncateDirection, Watermark,
+    WatermarkImage, WidthSizing,
 };
-use inferno::flamegraph::{self, defaults, Direction, Options, Palette, TextTruncateDirection, FrameWidthSource};
 
 #[cfg(feature = "nameattr")]
 use inferno::flamegraph::FuncFrameAttrsMap;
+#[cfg(feature = "annotations")]
+use inferno::flamegraph::{FuncFrameAnnotations, GroupRules};
 
 #[derive(Debug, Parser)]
 #[clap(name = "inferno-flamegraph", about)]
@@ -39,6 +48,40 @@ struct Opt {
     #[clap(long = "truncate-text-right")]
     truncate_text_right: bool,
 
+    /// If text doesn't fit in frame, truncate the middle, keeping its start and end
+    #[clap(long = "truncate-text-middle", conflicts_with = "truncate_text_right")]
+    truncate_text_middle: bool,
+
+    /// If text doesn't fit in frame, shrink it down to --min-fontsize before truncating it
+    #[clap(long = "shrink-text")]
+    shrink_text: bool,
+
+    /// Put the title, details and controls in a single row above the frames
+    #[clap(long = "compact-header")]
+    compact_header: bool,
+
+    /// Leave out the details line that shows the frame under the mouse
+    #[clap(long = "hide-status-bar")]
+    hide_status_bar: bool,
+
+    /// Text of the details line, with {nametype}, {name}, {details}, {samples} and {percent}
+    /// replaced by those of the frame under the mouse
+    #[clap(long = "details-template", value_name = "TEMPLATE")]
+    details_template: Option<String>,
+
+    /// Leave out the "all" frame, and draw the roots of the stacks in its row
+    #[clap(long = "omit-root")]
+    omit_root: bool,
+
+    /// Draw a bar for every row of frames, to their right, with the share of samples that reach
+    /// its depth
+    #[clap(long = "depth-histogram")]
+    depth_histogram: bool,
+
+    /// Shade the bottom of each frame in proportion to its share of self samples
+    #[clap(long = "self-time-shading")]
+    self_time_shading: bool,
+
     /// Switch differential hues (green<->red)
     #[clap(long = "negate")]
     negate: bool,
@@ -60,6 +103,15 @@ struct Opt {
     #[clap(long = "pretty-xml")]
     pretty_xml: bool,
 
+    /// Compress the SVG with gzip, as an .svgz file. Implied by an --output PATH ending in .svgz
+    #[clap(long = "svgz")]
+    svgz: bool,
+
+    /// Leave out the XML declaration and DOCTYPE, for inclusion in HTML. Use --width-sizing
+    /// viewbox to leave out the fixed width and height too
+    #[clap(long = "fragment")]
+    fragment: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -81,9 +133,38 @@ struct Opt {
     #[clap(short = 'd', long = "detailed-tooltips")]
     detailed_tooltips: bool,
 
+    /// Show each frame's percentage of its parent's samples in its tooltip
+    #[clap(long = "show-pct-of-parent")]
+    show_pct_of_parent: bool,
+
+    /// Which count to show first in the tooltip of each frame; the total moves to a line of its
+    /// own otherwise
+    #[clap(
+        long = "primary-metric",
+        value_enum,
+        default_value_t = PrimaryMetric::Total,
+        value_name = "METRIC"
+    )]
+    primary_metric: PrimaryMetric,
+
+    /// Label the "all" frame with the total samples, distinct stacks and, if --countname is a
+    /// unit of time, the time they cover
+    #[clap(long = "all-frame-stats")]
+    all_frame_stats: bool,
+
     #[clap(short = 'n', long = "normalize")]
     normalize: bool,
 
+    /// Accept negative sample counts, summing the counts of identical stacks and clamping
+    /// stacks that end up negative to zero
+    #[clap(long = "signed-counts")]
+    signed_counts: bool,
+
+    /// Take a missing second sample count of differential input to be zero, rather than
+    /// ignoring the line
+    #[clap(long = "missing-count-as-zero")]
+    missing_count_as_zero: bool,
+
     /// Verbose logging mode (-v, -vv, -vvv)
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
@@ -105,10 +186,29 @@ struct Opt {
     )]
     colors: Palette,
 
+    /// Change the colors of the palette, keeping their hue: self-ratio saturates frames by the
+    /// share of their samples that are their own, washing out frames that only pass calls on
+    #[clap(
+        long = "color-modifier",
+        value_parser = clap::builder::PossibleValuesParser::new(ColorModifier::VARIANTS).map(|s| s.parse::<ColorModifier>().unwrap()),
+        value_name = "MODIFIER"
+    )]
+    color_modifier: Option<ColorModifier>,
+
+    /// Pick the colors of a basic palette between two colors rather than from its own range,
+    /// as in hot=#0050a0..#40c0ff; may be given once for each palette
+    #[clap(long = "palette-range", value_name = "PALETTE=FROM..TO")]
+    palette_range: Vec<PaletteRange>,
+
     /// Color frames based on their width, highlighting expensive codepaths
     #[clap(long = "colordiffusion", conflicts_with = "colors")]
     color_diffusion: bool,
 
+    /// Color frames of a differential flame graph by whether they are new, removed or in both
+    /// profiles
+    #[clap(long = "color-by-age", conflicts_with_all = ["colors", "color_diffusion"])]
+    color_by_age: bool,
+
     /// Count type label
     #[clap(
         long = "countname",
@@ -149,6 +249,14 @@ struct Opt {
     )]
     fontwidth: f64,
 
+    /// Smallest font size that --shrink-text shrinks text to
+    #[clap(
+        long = "min-fontsize",
+        default_value = &**defaults::str::MIN_FONT_SIZE,
+        value_name = "UINT"
+    )]
+    min_fontsize: usize,
+
     /// Color of UI text such as the search and reset zoom buttons
     #[clap(
         long = "uicolor",
@@ -177,6 +285,25 @@ struct Opt {
     )]
     minwidth: f64,
 
+    /// Aggregate the children omitted by --minwidth into one "(narrow)" frame per parent. Ignored
+    /// with --flamechart
+    #[clap(long = "aggregate-narrow")]
+    aggregate_narrow: bool,
+
+    /// Leave out the labels of frames narrower than `<FLOAT>` pixels, which makes wide flame
+    /// graphs render faster
+    #[clap(
+        long = "min-frame-pixels",
+        default_value = &**defaults::str::MIN_FRAME_PIXELS,
+        value_name = "FLOAT"
+    )]
+    min_frame_pixels: f64,
+
+    /// Give the frames narrower than --min-frame-pixels tooltips of just their names, which makes
+    /// huge flame graphs render faster and smaller
+    #[clap(long = "short-narrow-tooltips")]
+    short_narrow_tooltips: bool,
+
     /// File containing attributes to use for the SVG frames of particular functions.
     /// Each line in the file should be a function name followed by a tab,
     /// then a sequence of tab separated name=value pairs
@@ -184,6 +311,96 @@ struct Opt {
     #[clap(long = "nameattr", value_name = "PATH")]
     nameattr: Option<PathBuf>,
 
+    /// JSON or TOML file of annotations (tooltip text, badges and CSS classes) for the SVG frames
+    /// of functions matching particular regular expressions
+    #[cfg(feature = "annotations")]
+    #[clap(long = "annotations", value_name = "PATH")]
+    annotations: Option<PathBuf>,
+
+    /// File of stack weights, where each line is a regular expression matching whole stacks
+    /// followed by the factor to multiply their sample counts by
+    #[cfg(feature = "annotations")]
+    #[clap(long = "weights", value_name = "PATH")]
+    weights: Option<PathBuf>,
+
+    /// Group the frames of functions matching the regular expression REGEX into a single frame
+    /// labeled LABEL, merging the stacks that become the same (may be repeated; the first
+    /// matching group wins)
+    #[cfg(feature = "annotations")]
+    #[clap(
+        long = "group",
+        value_parser = |s: &str| {
+            let (label, regex) = s
+                .split_once('=')
+                .ok_or_else(|| format!("Expected LABEL=REGEX, got: {}", s))?;
+            GroupRules::default()
+                .add(regex, label)
+                .map_err(|e| e.to_string())?;
+            Ok::<_, String>((label.to_string(), regex.to_string()))
+        },
+        value_name = "LABEL=REGEX"
+    )]
+    group: Vec<(String, String)>,
+
+    /// List the frames with attributes or annotations in a panel that zooms to them
+    #[clap(long = "annotations-panel")]
+    annotations_panel: bool,
+
+    /// List the five frames of a differential flame graph that grew and shrank the most below
+    /// the frames, zooming to them when clicked
+    #[clap(long = "diff-summary")]
+    diff_summary: bool,
+
+    /// Bookmark that zooms to the first frame of FUNCTION when clicked, shown as a chip labeled
+    /// LABEL below the title (may be repeated)
+    #[clap(
+        long = "bookmark",
+        value_parser = |s: &str| {
+            s.split_once('=')
+                .map(|(label, function)| (label.to_string(), function.to_string()))
+                .ok_or_else(|| format!("Expected LABEL=FUNCTION, got: {}", s))
+        },
+        value_name = "LABEL=FUNCTION"
+    )]
+    bookmark: Vec<(String, String)>,
+
+    /// Text to draw in a row of its own at the bottom, such as a build identifier
+    #[clap(long = "footer", value_name = "STRING")]
+    footer: Option<String>,
+
+    /// Image to draw faintly over a corner, as a data URI (data:image/png;base64,...)
+    #[clap(
+        long = "watermark",
+        value_parser = |s: &str| {
+            if s.starts_with("data:") {
+                Ok(s.to_string())
+            } else {
+                Err(format!("Expected a data URI, got: {}", s))
+            }
+        },
+        value_name = "URI"
+    )]
+    watermark: Option<String>,
+
+    /// Corner to draw the watermark in
+    #[clap(
+        long = "watermark-corner",
+        value_enum,
+        default_value_t = Corner::BottomRight,
+        value_name = "CORNER",
+        requires = "watermark"
+    )]
+    watermark_corner: Corner,
+
+    /// Where the interactive script comes from: embedded (the built-in one, inlined), none, or
+    /// the URL of a script to load instead, such as a centrally hosted copy
+    #[clap(
+        long = "script-source",
+        default_value = "embedded",
+        value_name = "SOURCE"
+    )]
+    script_source: ScriptSource,
+
     /// Name type label
     #[clap(
         long = "nametype",
@@ -196,6 +413,10 @@ struct Opt {
     #[clap(long = "notes", value_name = "STRING")]
     notes: Option<String>,
 
+    /// Append a summary of ignored lines and truncated samples to the notes in the SVG
+    #[clap(long = "embed-warnings")]
+    embed_warnings: bool,
+
     /// Search color
     #[clap(
         long = "search-color",
@@ -204,6 +425,14 @@ struct Opt {
     )]
     search_color: SearchColor,
 
+    /// Sync searches and zooms with other flame graphs of this group embedded in the same page
+    #[clap(long = "sync-group", value_name = "NAME")]
+    sync_group: Option<String>,
+
+    /// Update the flame graph in place with the patches sent by a stream of server-sent events
+    #[clap(long = "live-updates", value_name = "URL")]
+    live_updates: Option<String>,
+
     /// Adds an outline to every frame
     #[clap(
         long = "stroke-color",
@@ -212,6 +441,30 @@ struct Opt {
     )]
     stroke_color: StrokeColor,
 
+    /// Width of the outline of every frame, in pixels
+    #[clap(
+        long = "stroke-width",
+        default_value = &**defaults::str::STROKE_WIDTH,
+        value_name = "FLOAT"
+    )]
+    stroke_width: f64,
+
+    /// Radius of the rounded corners of frames, in pixels
+    #[clap(
+        long = "frame-radius",
+        default_value = &**defaults::str::FRAME_RADIUS,
+        value_name = "FLOAT"
+    )]
+    frame_radius: f64,
+
+    /// Gap between frames next to each other, in pixels
+    #[clap(
+        long = "frame-gap",
+        default_value = &**defaults::str::FRAME_GAP,
+        value_name = "FLOAT"
+    )]
+    frame_gap: f64,
+
     /// Second level title (optional)
     #[clap(long = "subtitle", value_name = "STRING")]
     subtitle: Option<String>,
@@ -228,6 +481,159 @@ struct Opt {
     #[clap(long = "width", value_name = "UINT")]
     width: Option<usize>,
 
+    /// How to express the image width when embedded: N%, Nvw, max:N (fluid up to N pixels), or
+    /// viewbox (scale to fit using only the viewBox)
+    #[clap(long = "width-sizing", value_name = "SIZING")]
+    width_sizing: Option<WidthSizing>,
+
+    /// Horizontal padding on the left and right of the frames
+    #[clap(
+        long = "xpad",
+        default_value = &**defaults::str::XPAD,
+        value_name = "UINT"
+    )]
+    xpad: usize,
+
+    /// Render a compact flame strip of only the bottom DEPTH levels, without title or controls
+    #[clap(long = "strip", value_name = "DEPTH")]
+    strip: Option<usize>,
+
+    /// Make the flame strip link to the full flame graph at URL
+    #[clap(long = "strip-link", value_name = "URL", requires = "strip")]
+    strip_link: Option<String>,
+
+    /// Off-CPU collapsed stacks to draw together with the on-CPU stacks in PATH as a hot/cold
+    /// flame graph
+    #[clap(long = "off-cpu", value_name = "PATH")]
+    off_cpu: Vec<PathBuf>,
+
+    /// Set color palette for off-CPU frames
+    #[clap(
+        long = "off-cpu-colors",
+        default_value = "blue",
+        value_parser = clap::builder::PossibleValuesParser::new(Palette::VARIANTS).map(|s| s.parse::<Palette>().unwrap()),
+        value_name = "STRING"
+    )]
+    off_cpu_colors: Palette,
+
+    /// Factor to scale off-CPU sample counts by to match the on-CPU sample counts
+    #[clap(long = "off-cpu-factor", default_value = "1.0", value_name = "FLOAT")]
+    off_cpu_factor: f64,
+
+    /// How to lay out on-CPU and off-CPU stacks
+    #[clap(
+        long = "hot-cold-layout",
+        value_enum,
+        default_value_t = HotColdLayout::SideBySide,
+        value_name = "LAYOUT"
+    )]
+    hot_cold_layout: HotColdLayout,
+
+    /// Draw every input file as a band of its own, under a frame named after the file
+    #[clap(long = "stacked-inputs", conflicts_with = "off_cpu")]
+    stacked_inputs: bool,
+
+    /// How to scale the bands of --stacked-inputs: to their own samples, or all to the same width
+    #[clap(
+        long = "stacked-scale",
+        value_enum,
+        default_value_t = StackedScale::Common,
+        value_name = "SCALE",
+        requires = "stacked_inputs"
+    )]
+    stacked_scale: StackedScale,
+
+    /// Annotate frames whose names end in SUFFIX: strip it, show LABEL in their tooltips, and
+    /// color them with PALETTE. Takes precedence over the built-in _[k], _[w], _[i] and _[j]
+    #[clap(long = "annotation", value_name = "SUFFIX[=LABEL][:PALETTE]")]
+    annotation: Vec<SuffixAnnotation>,
+
+    /// Instead of a flame graph, write a report of the functions with the most self and total
+    /// samples in FORMAT
+    #[clap(long = "report", value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+
+    /// Number of functions in each table of the report
+    #[clap(
+        long = "report-top",
+        default_value_t = report::defaults::TOP,
+        value_name = "UINT",
+        requires = "report"
+    )]
+    report_top: usize,
+
+    /// Instead of a flame graph, check differential input for functions whose share of all
+    /// samples grew by more than --fail-threshold-pct, write the verdict in FORMAT, and exit
+    /// with a nonzero status if any did. With --include-children, total samples are compared
+    #[clap(
+        long = "gate",
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with = "report"
+    )]
+    gate: Option<GateFormat>,
+
+    /// Growth of a function's share of all samples, in percentage points, above which the gate
+    /// fails
+    #[clap(
+        long = "fail-threshold-pct",
+        default_value_t = report::defaults::FAIL_THRESHOLD_PCT,
+        value_name = "PCT",
+        requires = "gate"
+    )]
+    fail_threshold_pct: f64,
+
+    /// Aggregate samples by leaf function, like perf report, by reversing stacks and cutting
+    /// them after DEPTH frames. With --report, list the functions with their callers instead
+    #[clap(
+        long = "bottom-up",
+        value_name = "DEPTH",
+        conflicts_with = "flame_chart"
+    )]
+    bottom_up: Option<usize>,
+
+    /// Keep only the N heaviest distinct stacks, and fold all others into one "(other stacks)"
+    /// stack, for small flame graphs of huge profiles
+    #[clap(long = "top-stacks", value_name = "N", conflicts_with = "flame_chart")]
+    top_stacks: Option<usize>,
+
+    /// Read N sample counts from every line, one for each of a series of time windows, and show
+    /// the trend of every frame over them in its tooltip
+    #[clap(long = "time-windows", value_name = "N")]
+    time_windows: Option<usize>,
+
+    /// Size frames by their samples in this time window, counting from 0, rather than in all
+    /// of them
+    #[clap(
+        long = "width-window",
+        value_name = "WINDOW",
+        requires = "time_windows"
+    )]
+    width_window: Option<usize>,
+
+    /// Write the flame graph, or the report, to PATH instead of STDOUT, replacing PATH only once
+    /// all of it is written
+    #[clap(
+        short = 'o',
+        long = "output",
+        visible_alias = "out",
+        value_name = "PATH"
+    )]
+    output: Option<PathBuf>,
+
+    /// Also write the merged stacks as JSON (in the d3-flame-graph format) to PATH
+    #[clap(long = "json-output", value_name = "PATH", conflicts_with = "off_cpu")]
+    json_output: Option<PathBuf>,
+
+    /// Also write the stacks as folded, after reversing, cutting them at --base and the like, to
+    /// PATH
+    #[clap(
+        long = "folded-output",
+        value_name = "PATH",
+        conflicts_with = "off_cpu"
+    )]
+    folded_output: Option<PathBuf>,
+
     /// Omit samples whose stacks do not contain this symbol. When this symbol is in a sample's
     /// stack, truncate the call stack so that this is the bottom-most symbol.
     /// This is particularly useful when you want to profile a specific function in a codebase that
@@ -254,6 +660,24 @@ struct Opt {
         conflicts_with = "reverse"
     )]
     flame_chart: bool,
+
+    /// Show how many input lines in a row make up each frame of a flame chart in its tooltip
+    #[clap(long = "show-occurrences", requires = "flame_chart")]
+    show_occurrences: bool,
+
+    /// Only draw the part of a flame chart from time FROM to time TO, in the units of its start
+    /// and end times
+    #[clap(
+        long = "time-range",
+        requires = "flame_chart",
+        value_parser = |s: &str| {
+            s.split_once("..")
+                .and_then(|(from, to)| Some((from.parse::<f64>().ok()?, to.parse::<f64>().ok()?)))
+                .ok_or_else(|| format!("Expected FROM..TO, got: {}", s))
+        },
+        value_name = "FROM..TO"
+    )]
+    time_range: Option<(f64, f64)>,
 }
 
 impl<'a> Opt {
@@ -261,11 +685,14 @@ impl<'a> Opt {
         let mut options = Options::default();
         options.title = self.title.clone();
         options.colors = self.colors;
+        options.color_modifier = self.color_modifier;
+        options.palette_ranges = self.palette_range.clone();
         options.bgcolors = self.bgcolors;
         options.hash = self.hash;
         options.deterministic = self.deterministic;
 
         self.set_func_frameattrs(&mut options);
+        self.set_func_annotations(&mut options);
 
         if self.inverted {
             options.direction = Direction::Inverted;
@@ -276,19 +703,40 @@ impl<'a> Opt {
         if self.truncate_text_right {
             options.text_truncate_direction = TextTruncateDirection::Right;
         }
+        if self.truncate_text_middle {
+            options.text_truncate_direction = TextTruncateDirection::Middle;
+        }
         options.negate_differentials = self.negate;
         options.factor = self.factor;
         options.pretty_xml = self.pretty_xml;
+        options.svgz = self.svgz
+            || self
+                .output
+                .as_ref()
+                .is_some_and(|path| path.extension().is_some_and(|ext| ext == "svgz"));
+        options.fragment = self.fragment;
         options.no_sort = self.no_sort;
         options.no_javascript = self.no_javascript;
         options.color_diffusion = self.color_diffusion;
+        options.color_by_age = self.color_by_age;
         options.reverse_stack_order = self.reverse;
+        options.bottom_up = self.bottom_up;
+        options.top_stacks = self.top_stacks;
+        options.time_windows = self.time_windows.unwrap_or_default();
+        options.width_window = self.width_window;
         options.flame_chart = self.flame_chart;
+        options.show_occurrences = self.show_occurrences;
+        options.time_range = self.time_range;
         options.base = self.base;
         options.include_children = self.include_children;
         options.frame_width_source = self.frame_width_source;
         options.detailed_tooltips = self.detailed_tooltips;
+        options.show_pct_of_parent = self.show_pct_of_parent;
+        options.primary_metric = self.primary_metric;
+        options.all_frame_stats = self.all_frame_stats;
         options.normalize = self.normalize;
+        options.signed_counts = self.signed_counts;
+        options.missing_count_as_zero = self.missing_count_as_zero;
 
         if self.flame_chart && self.title == defaults::TITLE {
             options.title = defaults::CHART_TITLE.to_owned();
@@ -297,20 +745,62 @@ impl<'a> Opt {
         // set style options
         options.subtitle = self.subtitle;
         options.image_width = self.width;
+        options.width_sizing = self.width_sizing;
+        options.xpad = self.xpad;
+        options.strip_depth = self.strip;
+        options.full_graph_link = self.strip_link;
+        options.omit_root = self.omit_root;
+        options.depth_histogram = self.depth_histogram;
+        options.off_cpu_colors = self.off_cpu_colors;
+        options.off_cpu_factor = self.off_cpu_factor;
+        options.hot_cold_layout = self.hot_cold_layout;
+        options.stacked_inputs = self.stacked_inputs;
+        options.stacked_scale = self.stacked_scale;
+        if !self.annotation.is_empty() {
+            options.suffix_annotations = self.annotation;
+            options
+                .suffix_annotations
+                .extend(SuffixAnnotation::defaults());
+        }
         options.frame_height = self.height;
         options.min_width = self.minwidth;
+        options.aggregate_narrow_frames = self.aggregate_narrow;
+        options.min_frame_pixels = self.min_frame_pixels;
+        options.short_narrow_tooltips = self.short_narrow_tooltips;
         options.font_type = self.fonttype;
         options.font_size = self.fontsize;
         options.font_width = self.fontwidth;
+        options.shrink_text_to_fit = self.shrink_text;
+        options.compact_header = self.compact_header;
+        options.show_status_bar = !self.hide_status_bar;
+        options.details_template = self.details_template;
+        options.self_time_shading = self.self_time_shading;
+        options.annotations_panel = self.annotations_panel;
+        options.diff_summary = self.diff_summary;
+        options.bookmarks = self.bookmark;
+        options.footer = self.footer;
+        options.watermark = self.watermark.map(|uri| {
+            let mut watermark = Watermark::new(WatermarkImage::DataUri(uri));
+            watermark.corner = self.watermark_corner;
+            watermark
+        });
+        options.script_source = self.script_source;
+        options.min_font_size = self.min_fontsize;
         options.count_name = self.countname;
         options.name_type = self.nametype;
         if let Some(notes) = self.notes {
             options.notes = notes;
         }
+        options.embed_warnings = self.embed_warnings;
         options.negate_differentials = self.negate;
         options.factor = self.factor;
         options.search_color = self.search_color;
+        options.sync_group = self.sync_group;
+        options.live_updates = self.live_updates;
         options.stroke_color = self.stroke_color;
+        options.stroke_width = self.stroke_width;
+        options.frame_radius = self.frame_radius;
+        options.frame_gap = self.frame_gap;
         options.uicolor = self.uicolor;
         (self.infiles, options)
     }
@@ -329,12 +819,70 @@ impl<'a> Opt {
 
     #[cfg(not(feature = "nameattr"))]
     fn set_func_frameattrs(&self, _: &mut Options) {}
+
+    #[cfg(feature = "annotations")]
+    fn set_func_annotations(&self, options: &mut Options) {
+        if let Some(file) = &self.annotations {
+            match FuncFrameAnnotations::from_file(file) {
+                Ok(a) => {
+                    options.func_annotations = a;
+                }
+                Err(e) => panic!("Error reading {}: {:?}", file.display(), e),
+            }
+        };
+        options.weights_file = self.weights.clone();
+        for (label, regex) in &self.group {
+            // The patterns were checked when parsing the arguments.
+            options.group_rules.add(regex, label).unwrap();
+        }
+    }
+
+    #[cfg(not(feature = "annotations"))]
+    fn set_func_annotations(&self, _: &mut Options) {}
+}
+
+// Reads the options of a config file with `Options::from_toml_str`, and overrides them with
+// those given on the command line, which are the ones that differ from the defaults of the CLI.
+fn with_config<'a>(options: Options<'a>, path: &Path) -> io::Result<Options<'a>> {
+    let to_table = |options: &Options| {
+        toml::Table::try_from(options).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+
+    let config = Options::from_toml_str(&std::fs::read_to_string(path)?)?;
+    let (_, defaults) = Opt::parse_from(["inferno-flamegraph"]).into_parts();
+    let defaults = to_table(&defaults)?;
+    let mut merged = to_table(&config)?;
+    for (key, value) in to_table(&options)? {
+        if defaults.get(&key) != Some(&value) {
+            merged.insert(key, value);
+        }
+    }
+    #[cfg_attr(
+        not(any(feature = "nameattr", feature = "annotations")),
+        allow(unused_mut)
+    )]
+    let mut merged: Options = merged
+        .try_into()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    // Options that are read from other files are not part of the config.
+    #[cfg(feature = "nameattr")]
+    {
+        merged.func_frameattrs = options.func_frameattrs;
+    }
+    #[cfg(feature = "annotations")]
+    {
+        merged.func_annotations = options.func_annotations;
+        merged.stack_weights = options.stack_weights;
+        merged.group_rules = options.group_rules;
+    }
+    Ok(merged)
 }
 
 const PALETTE_MAP_FILE: &str = "palette.map"; // default name for the palette map file
 
 fn main() -> io::Result<()> {
-    let opt = Opt::parse();
+    let (opt, config) = inferno::cli::parse_with_config_path::<Opt>("flamegraph");
 
     // Initialize logger
     if !opt.quiet {
@@ -353,19 +901,61 @@ fn main() -> io::Result<()> {
         Err(e) => panic!("Error reading {}: {:?}", PALETTE_MAP_FILE, e),
     };
 
+    let mut output = Output::new(opt.output.as_deref())?;
+
+    if let Some(format) = opt.report {
+        let mut report_options = report::Options::default();
+        report_options.format = format;
+        report_options.top = opt.report_top;
+        report_options.bottom_up = opt.bottom_up;
+        report::from_files(&report_options, &opt.infiles, &mut output)?;
+        return output.finish();
+    }
+
+    if let Some(format) = opt.gate {
+        let mut gate_options = report::GateOptions::default();
+        gate_options.format = format;
+        gate_options.fail_threshold_pct = opt.fail_threshold_pct;
+        gate_options.include_children = opt.include_children;
+        let verdict = report::gate_from_files(&gate_options, &opt.infiles, &mut output)?;
+        output.finish()?;
+        if verdict == report::Verdict::Fail {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let off_cpu = opt.off_cpu.clone();
+    let json_output = opt.json_output.clone();
+    let folded_output = opt.folded_output.clone();
     let (infiles, mut options) = opt.into_parts();
+    if let Some(path) = config {
+        options = with_config(options, &path)
+            .unwrap_or_else(|e| inferno::cli::config_error(&mut Opt::command(), &path, e));
+    }
 
     options.palette_map = palette_map.as_mut();
 
-    if std::io::stdout().is_terminal() {
-        flamegraph::from_files(&mut options, &infiles, io::stdout().lock())?;
+    if json_output.is_some() || folded_output.is_some() {
+        let mut json = json_output.map(AtomicFile::create).transpose()?;
+        let mut folded = folded_output.map(AtomicFile::create).transpose()?;
+        let mut sinks = vec![flamegraph::OutputSink::Svg(&mut output)];
+        if let Some(json) = json.as_mut() {
+            sinks.push(flamegraph::OutputSink::Json(json));
+        }
+        if let Some(folded) = folded.as_mut() {
+            sinks.push(flamegraph::OutputSink::Folded(folded));
+        }
+        flamegraph::render_files(&mut options, &infiles, &mut sinks)?;
+        drop(sinks);
+        json.map(AtomicFile::commit).transpose()?;
+        folded.map(AtomicFile::commit).transpose()?;
+    } else if !off_cpu.is_empty() {
+        flamegraph::from_hot_cold_files(&mut options, &infiles, &off_cpu, &mut output)?;
     } else {
-        flamegraph::from_files(
-            &mut options,
-            &infiles,
-            io::BufWriter::new(io::stdout().lock()),
-        )?;
+        flamegraph::from_files(&mut options, &infiles, &mut output)?;
     }
+    output.finish()?;
 
     save_consistent_palette_if_needed(&palette_map, PALETTE_MAP_FILE)
 }
@@ -400,7 +990,11 @@ fn save_consistent_palette_if_needed(
 mod tests {
     use super::Opt;
     use clap::Parser;
-    use inferno::flamegraph::{color, Direction, Options, Palette, TextTruncateDirection, FrameWidthSource};
+    use inferno::flamegraph::{
+        color, Corner, Direction, FrameWidthSource, HotColdLayout, Options, Palette, PrimaryMetric,
+        ScriptSource, StackedScale, SuffixAnnotation, TextTruncateDirection, Watermark,
+        WatermarkImage, WidthSizing,
+    };
     use pretty_assertions::assert_eq;
     use std::path::PathBuf;
     use std::str::FromStr;
@@ -419,24 +1013,70 @@ mod tests {
             "inferno-flamegraph",
             "--inverted",
             "--truncate-text-right",
+            "--shrink-text",
+            "--compact-header",
+            "--min-fontsize",
+            "8",
             "--colors",
             "purple",
+            "--color-modifier",
+            "self-ratio",
+            "--palette-range",
+            "hot=#0050a0..#40c0ff",
             "--bgcolors",
             "blue",
             "--hash",
             "--cp",
             "--search-color",
             "#203040",
+            "--sync-group",
+            "before-after",
+            "--live-updates",
+            "/updates",
             "--title",
             "Test Title",
             "--subtitle",
             "Test Subtitle",
             "--width",
             "100",
+            "--width-sizing",
+            "max:1600",
+            "--xpad",
+            "20",
+            "--strip",
+            "2",
+            "--strip-link",
+            "full.svg",
+            "--omit-root",
+            "--hide-status-bar",
+            "--details-template",
+            "{name}: {percent}%",
+            "--depth-histogram",
+            "--off-cpu-colors",
+            "io",
+            "--off-cpu-factor",
+            "0.0099",
+            "--hot-cold-layout",
+            "interleaved",
+            "--stacked-inputs",
+            "--stacked-scale",
+            "independent",
+            "--annotation",
+            "_[g]=GPU:green",
             "--height",
             "500",
             "--minwidth",
             "90.1",
+            "--aggregate-narrow",
+            "--min-frame-pixels",
+            "4.5",
+            "--short-narrow-tooltips",
+            "--stroke-width",
+            "0.5",
+            "--frame-radius",
+            "3",
+            "--frame-gap",
+            "1.5",
             "--fonttype",
             "Helvetica",
             "--fontsize",
@@ -449,17 +1089,50 @@ mod tests {
             "test name type",
             "--notes",
             "Test notes",
+            "--embed-warnings",
             "--negate",
             "--factor",
             "0.1",
             "--pretty-xml",
+            "--fragment",
+            "--output",
+            "flamegraph.svgz",
             "--reverse",
             "--no-javascript",
             "--include-children",
             "--frame-width-source",
             "difference",
             "--detailed-tooltips",
+            "--show-pct-of-parent",
+            "--primary-metric",
+            "self",
+            "--all-frame-stats",
             "--normalize",
+            "--signed-counts",
+            "--missing-count-as-zero",
+            "--bottom-up",
+            "2",
+            "--top-stacks",
+            "50",
+            "--time-windows",
+            "4",
+            "--width-window",
+            "3",
+            "--self-time-shading",
+            "--annotations-panel",
+            "--diff-summary",
+            "--bookmark",
+            "Parser=parse",
+            "--bookmark",
+            "Hot loop=main::run",
+            "--footer",
+            "build 1234",
+            "--watermark",
+            "data:image/png;base64,iVBORw0KGgo=",
+            "--watermark-corner",
+            "top-left",
+            "--script-source",
+            "https://example.com/inferno/flamegraph-1.js",
             "test_infile1",
             "test_infile2",
         ];
@@ -467,25 +1140,60 @@ mod tests {
         let (infiles, options) = opt.into_parts();
         let mut expected_options = Options::default();
         expected_options.colors = Palette::from_str("purple").unwrap();
+        expected_options.color_modifier = Some(color::ColorModifier::SelfRatio);
+        expected_options.palette_ranges =
+            vec![color::PaletteRange::from_str("hot=#0050a0..#40c0ff").unwrap()];
         expected_options.search_color = color::SearchColor::from_str("#203040").unwrap();
+        expected_options.sync_group = Some("before-after".to_string());
+        expected_options.live_updates = Some("/updates".to_string());
         expected_options.title = "Test Title".to_string();
         expected_options.image_width = Some(100);
+        expected_options.width_sizing = Some(WidthSizing::FluidMax(1600));
+        expected_options.xpad = 20;
+        expected_options.strip_depth = Some(2);
+        expected_options.full_graph_link = Some("full.svg".to_string());
+        expected_options.omit_root = true;
+        expected_options.depth_histogram = true;
+        expected_options.off_cpu_colors = Palette::from_str("io").unwrap();
+        expected_options.off_cpu_factor = 0.0099;
+        expected_options.hot_cold_layout = HotColdLayout::Interleaved;
+        expected_options.stacked_inputs = true;
+        expected_options.stacked_scale = StackedScale::Independent;
+        expected_options.suffix_annotations =
+            vec![SuffixAnnotation::from_str("_[g]=GPU:green").unwrap()];
+        expected_options
+            .suffix_annotations
+            .extend(SuffixAnnotation::defaults());
         expected_options.frame_height = 500;
         expected_options.min_width = 90.1;
+        expected_options.aggregate_narrow_frames = true;
+        expected_options.min_frame_pixels = 4.5;
+        expected_options.short_narrow_tooltips = true;
+        expected_options.stroke_width = 0.5;
+        expected_options.frame_radius = 3.0;
+        expected_options.frame_gap = 1.5;
         expected_options.font_type = "Helvetica".to_string();
         expected_options.font_size = 13;
         expected_options.font_width = 10.5;
         expected_options.text_truncate_direction = TextTruncateDirection::Right;
+        expected_options.shrink_text_to_fit = true;
+        expected_options.compact_header = true;
+        expected_options.show_status_bar = false;
+        expected_options.details_template = Some("{name}: {percent}%".to_string());
+        expected_options.min_font_size = 8;
         expected_options.count_name = "test count name".to_string();
         expected_options.name_type = "test name type".to_string();
         expected_options.factor = 0.1;
         expected_options.notes = "Test notes".to_string();
+        expected_options.embed_warnings = true;
         expected_options.subtitle = Some("Test Subtitle".to_string());
         expected_options.bgcolors = Some(color::BackgroundColor::Blue);
         expected_options.hash = true;
         expected_options.direction = Direction::Inverted;
         expected_options.negate_differentials = true;
         expected_options.pretty_xml = true;
+        expected_options.svgz = true;
+        expected_options.fragment = true;
         expected_options.no_sort = false;
         expected_options.reverse_stack_order = true;
         expected_options.no_javascript = true;
@@ -493,7 +1201,31 @@ mod tests {
         expected_options.include_children = true;
         expected_options.frame_width_source = FrameWidthSource::Difference;
         expected_options.detailed_tooltips = true;
+        expected_options.show_pct_of_parent = true;
+        expected_options.primary_metric = PrimaryMetric::SelfCount;
+        expected_options.all_frame_stats = true;
         expected_options.normalize = true;
+        expected_options.signed_counts = true;
+        expected_options.missing_count_as_zero = true;
+        expected_options.bottom_up = Some(2);
+        expected_options.top_stacks = Some(50);
+        expected_options.time_windows = 4;
+        expected_options.width_window = Some(3);
+        expected_options.self_time_shading = true;
+        expected_options.annotations_panel = true;
+        expected_options.diff_summary = true;
+        expected_options.bookmarks = vec![
+            ("Parser".to_string(), "parse".to_string()),
+            ("Hot loop".to_string(), "main::run".to_string()),
+        ];
+        expected_options.footer = Some("build 1234".to_string());
+        let mut watermark = Watermark::new(WatermarkImage::DataUri(
+            "data:image/png;base64,iVBORw0KGgo=".to_string(),
+        ));
+        watermark.corner = Corner::TopLeft;
+        expected_options.watermark = Some(watermark);
+        expected_options.script_source =
+            ScriptSource::External("https://example.com/inferno/flamegraph-1.js".to_string());
 
 
         assert_eq!(options, expected_options);
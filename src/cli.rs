@@ -0,0 +1,306 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::error::ErrorKind;
+use clap::{Arg, Args, Command, Parser};
+
+/// Parses the command line of the binary named `inferno-<section>`, adding a `--config` option.
+///
+/// `--config` names a TOML file whose `[<section>]` table gives default values for the binary's
+/// other options, keyed by their long names (e.g. `no-modules = true` under `[collapse-vtune]`).
+/// Options given on the command line override the values from the file.
+pub fn parse<T: Parser>(section: &str) -> T {
+    let args: Vec<OsString> = env::args_os().collect();
+    let mut command = with_config_arg(T::command(), section);
+
+    if let Some(path) = config_path(&args) {
+        command = match with_config_defaults(command.clone(), &path, section) {
+            Ok(command) => command,
+            Err(message) => config_error(&mut command, &path, message),
+        };
+    }
+
+    let matches = command.get_matches_from(args);
+    T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+/// Parses the command line of the binary named `inferno-<section>` like [`parse`], but leaves
+/// reading the `--config` file to the binary, and returns its path along with the options.
+///
+/// This is for binaries whose library options can be read from the `[<section>]` table by
+/// themselves, such as `[flamegraph]` with [`Options::from_toml_str`], so that the file has the
+/// same keys whether it is given to the library or to the binary.
+///
+///   [`Options::from_toml_str`]: crate::flamegraph::Options::from_toml_str
+pub fn parse_with_config_path<T: Parser>(section: &str) -> (T, Option<PathBuf>) {
+    let args: Vec<OsString> = env::args_os().collect();
+    let path = config_path(&args);
+    let matches = with_config_arg(T::command(), section).get_matches_from(args);
+    let opt = T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    (opt, path)
+}
+
+/// Exits with the usage error for a config file at `path` that can't be used.
+pub fn config_error(command: &mut Command, path: &Path, message: impl std::fmt::Display) -> ! {
+    clap::Error::raw(
+        ErrorKind::InvalidValue,
+        format!("{}: {}", path.display(), message),
+    )
+    .format(command)
+    .exit()
+}
+
+fn with_config_arg(command: Command, section: &str) -> Command {
+    command.arg(
+        Arg::new("config")
+            .long("config")
+            .value_name("PATH")
+            .help(format!(
+                "Read default values for these options from the [{}] table of a TOML file",
+                section
+            )),
+    )
+}
+
+// The config file has to be known before the command line is parsed for real, so we look for it
+// by hand. Everything after `--` is positional.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn with_config_defaults(
+    mut command: Command,
+    path: &Path,
+    section: &str,
+) -> Result<Command, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let config: toml::Table = contents
+        .parse()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+    let table = match config.get(section) {
+        Some(toml::Value::Table(table)) => table,
+        Some(_) => return Err(format!("[{}] is not a table", section)),
+        None => return Ok(command),
+    };
+
+    for (key, value) in table {
+        let id = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()) && key != "config")
+            .map(|arg| arg.get_id().clone())
+            .ok_or_else(|| format!("unknown option `{}` in [{}]", key, section))?;
+        let values = match value {
+            toml::Value::Array(values) => values.iter().map(scalar).collect(),
+            value => vec![scalar(value)],
+        };
+        let values = values
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("unsupported value for `{}` in [{}]", key, section))?;
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+    Ok(command)
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// The `--out FILE` option of the binaries, to flatten into their options.
+#[derive(Debug, Args)]
+pub struct OutArg {
+    /// Write the output to FILE instead of STDOUT, replacing FILE only once all of it is written
+    #[clap(long = "out", value_name = "FILE")]
+    pub out: Option<PathBuf>,
+}
+
+impl OutArg {
+    /// Opens the output of the binary.
+    pub fn open(&self) -> io::Result<Output> {
+        Output::new(self.out.as_deref())
+    }
+}
+
+/// Where a binary writes its output: STDOUT, or an [`AtomicFile`].
+///
+/// Call [`finish`](Output::finish) once all of the output has been written.
+pub enum Output {
+    /// STDOUT, which is only buffered when it isn't a terminal.
+    Stdout(Box<dyn Write>),
+    /// A file that is replaced when the output is finished.
+    File(AtomicFile),
+}
+
+impl Output {
+    /// Opens the file at `path`, or STDOUT if there is none.
+    pub fn new(path: Option<&Path>) -> io::Result<Self> {
+        Ok(match path {
+            Some(path) => Output::File(AtomicFile::create(path)?),
+            None if io::stdout().is_terminal() => Output::Stdout(Box::new(io::stdout().lock())),
+            None => Output::Stdout(Box::new(io::BufWriter::new(io::stdout().lock()))),
+        })
+    }
+
+    /// Flushes the output, and puts the file in place.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Stdout(mut stdout) => stdout.flush(),
+            Output::File(file) => file.commit(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(file) => file.flush(),
+        }
+    }
+}
+
+/// A file that is written next to its destination, and only renamed over it on
+/// [`commit`](AtomicFile::commit).
+///
+/// If it's dropped without being committed, as when generating its contents fails, the temporary
+/// file is removed and whatever was at the destination is left as it was. This way, something
+/// that polls the destination never sees a partial file.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: Option<io::BufWriter<File>>,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file for `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "output is not a file"))?;
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(name);
+        tmp_name.push(format!(".{}.tmp", process::id()));
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let file = File::create(&tmp_path)?;
+        Ok(AtomicFile {
+            path,
+            tmp_path,
+            file: Some(io::BufWriter::new(file)),
+        })
+    }
+
+    /// Writes out what's buffered, and renames the temporary file over the destination.
+    pub fn commit(mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            let file = file.into_inner().map_err(io::IntoInnerError::into_error)?;
+            file.sync_all()?;
+        }
+        fs::rename(&self.tmp_path, &self.path)
+    }
+
+    fn file(&mut self) -> &mut io::BufWriter<File> {
+        self.file.as_mut().expect("file is only taken on commit")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // After a successful commit, there's nothing left to remove.
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{config_path, AtomicFile};
+    use std::env;
+    use std::ffi::OsString;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn finds_config_path() {
+        assert_eq!(
+            config_path(&args(&[
+                "inferno-flamegraph",
+                "--config",
+                "a.toml",
+                "in.txt"
+            ])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            config_path(&args(&["inferno-flamegraph", "--config=a.toml"])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            config_path(&args(&["inferno-flamegraph", "--", "--config", "a.toml"])),
+            None
+        );
+        assert_eq!(config_path(&args(&["inferno-flamegraph", "in.txt"])), None);
+    }
+
+    #[test]
+    fn atomic_file_replaces_only_on_commit() {
+        let dir = env::temp_dir().join(format!("inferno-atomic-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("flamegraph.svg");
+        fs::write(&path, "old").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+//! Stack collapsing for the collapsed output of [async-profiler] with the event type and the
+//! thread state of every stack, such as when JFR recordings of Spark executors are converted with
+//! `jfrconv`.
+//!
+//! The input is already folded, but the stacks of several event types are mixed in it, and start
+//! with frames for the type of their event and the state of their thread. For example:
+//!
+//! ```text
+//! [cpu];[RUNNABLE];[Executor task launch worker for task 3 tid=81];java/lang/Thread.run;... 12
+//! [wall];[SLEEPING];[dispatcher-event-loop-1 tid=23];java/lang/Thread.run;... 40
+//! [alloc];[RUNNABLE];java/lang/Thread.run;...;byte[] 2048
+//! ```
+//!
+//! Event frames name the event type, as `[cpu]`, `[wall]`, `[alloc]`, `[lock]` and others, and
+//! state frames the state of the thread, as `[RUNNABLE]`, `[SLEEPING]`, `[WAITING]` and others.
+//! Stacks of different event types count different things, such as samples and bytes, so the
+//! event frames are kept unless a single event type is folded with [`Options::event`]. State
+//! frames are kept unless they are left out with [`Options::strip_state`]. All other frames,
+//! including those of threads, are kept as they are.
+//!
+//!   [async-profiler]: https://github.com/async-profiler/async-profiler
+
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// The event types of async-profiler, as in the `--cpu`, `--wall`, ... options of `jfrconv`.
+const EVENTS: &[&str] = &[
+    "cpu",
+    "wall",
+    "alloc",
+    "live",
+    "lock",
+    "itimer",
+    "ctimer",
+    "nativemem",
+];
+
+/// `async-profiler` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Only fold the stacks of this event type, such as `cpu`, `wall`, `alloc` or `lock`, and
+    /// leave out their event frames.
+    ///
+    /// Default is `None`, which folds the stacks of all event types, below their event frames.
+    pub event: Option<String>,
+
+    /// Leave out the frames of thread states, such as `[RUNNABLE]` or `[SLEEPING]`, which merges
+    /// the stacks of all states.
+    ///
+    /// Default is `false`.
+    pub strip_state: bool,
+}
+
+/// A stack collapser for the collapsed output of async-profiler with event and state frames.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `async_profiler::Folder::default()` or create an [`Options`] and
+/// use `async_profiler::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// A frame at the start of a stack, before its first frame of code.
+enum Prefix<'a> {
+    Event(&'a str),
+    State,
+    Other,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut events: Vec<String> = Vec::new();
+        let mut invalid = 0;
+        let mut without_event = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((stack, count)) = line
+                .rsplit_once(' ')
+                .and_then(|(stack, count)| Some((stack.trim_end(), count.parse::<usize>().ok()?)))
+            else {
+                invalid += 1;
+                continue;
+            };
+
+            let mut event = None;
+            let mut folded = String::with_capacity(stack.len());
+            // Prefix frames end at the first frame of code.
+            let mut in_prefix = true;
+            for frame in stack.split(';') {
+                match in_prefix.then(|| prefix(frame)).flatten() {
+                    Some(Prefix::Event(name)) if event.is_none() => {
+                        event = Some(name);
+                        if self.opt.event.is_some() {
+                            continue;
+                        }
+                    }
+                    Some(Prefix::State) if self.opt.strip_state => continue,
+                    Some(_) => {}
+                    None => in_prefix = false,
+                }
+                folded.push_str(frame);
+                folded.push(';');
+            }
+            folded.pop();
+
+            match (event, &self.opt.event) {
+                (Some(event), Some(only)) if event != only => continue,
+                (None, Some(_)) => {
+                    without_event += 1;
+                    continue;
+                }
+                (Some(event), None) if !events.iter().any(|e| e == event) => {
+                    events.push(event.to_string());
+                }
+                _ => {}
+            }
+            if folded.is_empty() || count == 0 {
+                continue;
+            }
+            occurrences.insert_or_add(folded, count);
+        }
+
+        if invalid > 0 {
+            warn!("Ignored {} lines without a valid sample count", invalid);
+        }
+        if without_event > 0 {
+            warn!(
+                "Skipped {} stacks without an event frame, such as [cpu]",
+                without_event
+            );
+        }
+        if events.len() > 1 {
+            warn!(
+                "Folded stacks of several event types ({}), which don't add up; select one of \
+                 them to fold",
+                events.join(", ")
+            );
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a stack that starts with an event frame, such as `[cpu]`.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let line = input.lines().find(|line| !line.trim().is_empty())?;
+        let first = line.trim_start().split(';').next()?;
+        Some(matches!(prefix(first), Some(Prefix::Event(_))))
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Classifies a frame in brackets, such as `[cpu]`, `[RUNNABLE]` or `[main tid=1]`.
+fn prefix(frame: &str) -> Option<Prefix<'_>> {
+    let name = frame.strip_prefix('[')?.strip_suffix(']')?;
+    if EVENTS.contains(&name) {
+        Some(Prefix::Event(name))
+    } else if !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_') {
+        Some(Prefix::State)
+    } else {
+        Some(Prefix::Other)
+    }
+}
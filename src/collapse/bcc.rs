@@ -0,0 +1,228 @@
+//! Stack collapsing for the multi-line output of the `profile` and `offcputime` tools of bcc.
+//!
+//! Without `-f`, these tools print each stack as a block of frames, innermost first, with the
+//! kernel stack above the user stack (optionally separated by a `--` line with `-d`), followed by
+//! a line with the name and PID of the process and a line with the count:
+//!
+//! ```text
+//!     finish_task_switch+0x1a [kernel]
+//!     schedule+0x36 [kernel]
+//!     --
+//!     __select+0x13 [libc.so.6]
+//!     main+0x2c [app]
+//!     -                app (4711)
+//!         1022
+//! ```
+//!
+//! Frames are normalized by dropping their addresses, offsets and module names. Kernel frames,
+//! which bcc marks with a `[kernel]` module or puts above the `--` delimiter, can be annotated
+//! with a `_[k]` suffix. The root frame of each stack is the name of its process. Blocks without
+//! a process line, as printed by `stackcount`, are collapsed as well.
+
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+/// `bcc` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Annotate kernel functions with a `_[k]` suffix.
+    ///
+    /// Default is `false`.
+    pub annotate_kernel: bool,
+
+    /// Include the PID with the process name in the root frame.
+    ///
+    /// Default is `false`.
+    pub include_pid: bool,
+}
+
+/// A stack collapser for the multi-line output of bcc's `profile` and `offcputime`.
+///
+/// See the [module-level documentation](self) for details.
+///
+/// To construct one, either use `bcc::Folder::default()` or create an [`Options`] and use
+/// `bcc::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Frames of the current stack, innermost first.
+    frames: Vec<String>,
+
+    /// Name of the process of the current stack, if its line has been read.
+    process: Option<String>,
+
+    /// Number of stacks found so far.
+    nstacks: usize,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            self.on_line(l.trim_end(), &mut occurrences);
+        }
+        if !self.frames.is_empty() {
+            warn!("Input ended in the middle of a stack, which was left out");
+        }
+
+        if self.nstacks == 0 {
+            warn!("No stacks found in the input");
+        }
+        occurrences.write_and_clear(writer)?;
+
+        // Reset the state...
+        self.reset_stack();
+        self.nstacks = 0;
+        Ok(())
+    }
+
+    /// Check for the process line at the end of a stack.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let l = line.trim_end();
+            if l.starts_with("    ") && parse_process(l.trim_start()).is_some() {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn on_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        // Everything that belongs to a stack is indented; the rest are messages of the tool, such
+        // as "Sampling at 49 Hertz of all threads..." or warnings about lost stacks.
+        if !line.starts_with("    ") {
+            if !line.is_empty() {
+                self.reset_stack();
+            }
+            return;
+        }
+        let content = line.trim();
+
+        if content == "--" {
+            // Everything above the delimiter is the kernel stack, or a marker of its absence.
+            if self.opt.annotate_kernel {
+                for frame in &mut self.frames {
+                    if !frame.starts_with('[') && !frame.ends_with("_[k]") {
+                        frame.push_str("_[k]");
+                    }
+                }
+            }
+        } else if let Ok(count) = content.parse::<usize>() {
+            self.end_stack(count, occurrences);
+        } else if let Some((comm, pid)) = parse_process(content) {
+            let mut process = comm.replace(' ', "_");
+            if self.opt.include_pid {
+                process.push('-');
+                process.push_str(pid);
+            }
+            self.process = Some(process);
+        } else {
+            let frame = self.parse_frame(content);
+            self.frames.push(frame);
+        }
+    }
+
+    /// Normalizes a frame, such as `ffffffff8108c5e1 finish_task_switch+0x1a [kernel]`.
+    fn parse_frame(&self, content: &str) -> String {
+        // Missing stacks are shown as `[Missed Kernel Stack]` and the like, which we keep as is.
+        if content.starts_with('[') {
+            return content.replace(';', ":");
+        }
+
+        let mut content = content;
+        if let Some((address, rest)) = content.split_once(' ') {
+            if address.len() >= 8 && address.bytes().all(|b| b.is_ascii_hexdigit()) {
+                content = rest.trim_start();
+            }
+        }
+
+        let (symbol, module) = match content.rsplit_once(" [") {
+            Some((symbol, module)) if module.ends_with(']') => {
+                (symbol, &module[..module.len() - 1])
+            }
+            _ => (content, ""),
+        };
+        let symbol = match symbol.rsplit_once("+0x") {
+            Some((name, offset)) if offset.bytes().all(|b| b.is_ascii_hexdigit()) => name,
+            _ => symbol,
+        };
+
+        let mut frame = symbol.replace(';', ":");
+        if self.opt.annotate_kernel && module == "kernel" && !frame.ends_with("_[k]") {
+            frame.push_str("_[k]");
+        }
+        frame
+    }
+
+    fn end_stack(&mut self, count: usize, occurrences: &mut Occurrences) {
+        if !self.frames.is_empty() {
+            let mut stack = String::new();
+            if let Some(process) = &self.process {
+                stack.push_str(process);
+                stack.push(';');
+            }
+            for (i, frame) in self.frames.iter().rev().enumerate() {
+                if i > 0 {
+                    stack.push(';');
+                }
+                stack.push_str(frame);
+            }
+            occurrences.insert_or_add(stack, count);
+            self.nstacks += 1;
+        }
+        self.reset_stack();
+    }
+
+    fn reset_stack(&mut self) {
+        self.frames.clear();
+        self.process = None;
+    }
+}
+
+/// Parses the process line of a stack, `-                app (4711)`, into its name and PID.
+fn parse_process(content: &str) -> Option<(&str, &str)> {
+    let rest = content.strip_prefix("- ")?.trim_start();
+    let (comm, pid) = rest.strip_suffix(')')?.rsplit_once(" (")?;
+    if comm.is_empty() || pid.is_empty() || !pid.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((comm, pid))
+}
@@ -0,0 +1,241 @@
+//! Stack collapsing for the query profiles that [ClickHouse] keeps in its `system.trace_log`
+//! table.
+//!
+//! The input is a `TabSeparatedWithNames` (or `TabSeparatedWithNamesAndTypes`) export of the
+//! table, with a `trace` column of the addresses of the stack of every sample, innermost first,
+//! and optionally a `symbols` column of their names. For example:
+//!
+//! ```sql
+//! SELECT query_id, trace_type, trace,
+//!        arrayMap(x -> demangle(addressToSymbol(x)), trace) AS symbols
+//! FROM system.trace_log
+//! WHERE event_date = today()
+//! FORMAT TabSeparatedWithNames
+//! SETTINGS allow_introspection_functions = 1
+//! ```
+//!
+//! Frames are named by their symbols, or by their addresses where they have none. Every row counts
+//! one sample, or the value of its `samples` (or `count()`) column if it has one, so that rows can
+//! be aggregated in the query already, as with `count() AS samples ... GROUP BY trace`, or weighed
+//! by allocated bytes with `sum(size) AS samples`.
+//!
+//! The table holds several types of traces, such as `CPU` and `Real` samples and `Memory`
+//! allocations, which don't add up; select one with [`Options::trace_type`] or in the query.
+//!
+//!   [ClickHouse]: https://clickhouse.com/docs/en/operations/system-tables/trace_log
+
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const TRACE_COLUMN: &str = "trace";
+const SYMBOLS_COLUMN: &str = "symbols";
+const QUERY_ID_COLUMN: &str = "query_id";
+const TRACE_TYPE_COLUMN: &str = "trace_type";
+const SAMPLES_COLUMNS: &[&str] = &["samples", "count()", "count"];
+
+/// `clickhouse` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Include the id of the query that a sample was taken in as the root frame of every stack,
+    /// which gives every query a flame graph of its own.
+    ///
+    /// Default is `false`.
+    pub include_query_id: bool,
+
+    /// Only fold the samples of this type of trace, such as `CPU`, `Real` or `Memory`.
+    ///
+    /// Default is `None`, which folds all samples.
+    pub trace_type: Option<String>,
+}
+
+/// A stack collapser for exports of ClickHouse's `system.trace_log` table.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `clickhouse::Folder::default()` or create an [`Options`] and use
+/// `clickhouse::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// Where the columns the folder reads are in every row.
+struct Columns {
+    trace: Option<usize>,
+    symbols: Option<usize>,
+    query_id: Option<usize>,
+    trace_type: Option<usize>,
+    samples: Option<usize>,
+}
+
+impl Columns {
+    fn new(header: &str) -> Self {
+        let names: Vec<&str> = header.split('\t').map(str::trim).collect();
+        let find = |name: &str| names.iter().position(|&n| n == name);
+        Columns {
+            trace: find(TRACE_COLUMN),
+            symbols: find(SYMBOLS_COLUMN),
+            query_id: find(QUERY_ID_COLUMN),
+            trace_type: find(TRACE_TYPE_COLUMN),
+            samples: SAMPLES_COLUMNS.iter().find_map(|&name| find(name)),
+        }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut lines = reader.lines();
+        let Some(header) = lines.next().transpose()? else {
+            return invalid_data_error!("No header found in input");
+        };
+        let columns = Columns::new(&header);
+        if columns.trace.is_none() && columns.symbols.is_none() {
+            return invalid_data_error!(
+                "Expected a `{}` or `{}` column in the header",
+                TRACE_COLUMN,
+                SYMBOLS_COLUMN
+            );
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        let mut trace_types: Vec<String> = Vec::new();
+        let mut invalid = 0;
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let field = |column: Option<usize>| column.and_then(|c| fields.get(c).copied());
+            // The second line of `TabSeparatedWithNamesAndTypes` holds the types of the columns.
+            if i == 0
+                && field(columns.trace.or(columns.symbols)).is_some_and(|t| t.starts_with("Array("))
+            {
+                continue;
+            }
+
+            if let Some(trace_type) = field(columns.trace_type) {
+                if let Some(only) = &self.opt.trace_type {
+                    if trace_type != only {
+                        continue;
+                    }
+                } else if !trace_types.iter().any(|t| t == trace_type) {
+                    trace_types.push(trace_type.to_string());
+                }
+            }
+            let samples = match field(columns.samples) {
+                Some(samples) => match samples.parse::<usize>() {
+                    Ok(samples) => samples,
+                    Err(_) => {
+                        invalid += 1;
+                        continue;
+                    }
+                },
+                None => 1,
+            };
+            let addresses = field(columns.trace).map(parse_array).unwrap_or_default();
+            let symbols = field(columns.symbols).map(parse_array).unwrap_or_default();
+            if addresses.is_empty() && symbols.is_empty() {
+                invalid += 1;
+                continue;
+            }
+            if samples == 0 {
+                continue;
+            }
+
+            let mut stack = String::new();
+            if self.opt.include_query_id {
+                let query_id = field(columns.query_id).filter(|id| !id.is_empty());
+                stack.push_str(&query_id.unwrap_or("[unknown]").replace(';', ":"));
+                stack.push(';');
+            }
+            for frame in (0..addresses.len().max(symbols.len())).rev() {
+                match symbols.get(frame).filter(|symbol| !symbol.is_empty()) {
+                    Some(symbol) => stack.push_str(&symbol.replace(';', ":")),
+                    None => match addresses.get(frame).and_then(|a| a.parse::<u64>().ok()) {
+                        Some(address) => stack.push_str(&format!("{:#x}", address)),
+                        None => stack.push_str("[unknown]"),
+                    },
+                }
+                stack.push(';');
+            }
+            stack.pop();
+            occurrences.insert_or_add(stack, samples);
+        }
+
+        if invalid > 0 {
+            warn!(
+                "Ignored {} rows without a trace or a valid sample count",
+                invalid
+            );
+        }
+        if trace_types.len() > 1 {
+            warn!(
+                "Folded samples of several trace types ({}), which don't add up; select one of \
+                 them to fold",
+                trace_types.join(", ")
+            );
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a header with a `trace` or `symbols` column.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let header = input.lines().next()?;
+        if !header.contains('\t') {
+            return Some(false);
+        }
+        let columns = Columns::new(header);
+        Some(columns.trace.is_some() || columns.symbols.is_some())
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Parses an array as ClickHouse writes it in TSV, such as `[1,2,3]` or `['a','b\'c']`.
+fn parse_array(array: &str) -> Vec<String> {
+    let Some(inner) = array
+        .trim()
+        .strip_prefix('[')
+        .and_then(|a| a.strip_suffix(']'))
+    else {
+        return Vec::new();
+    };
+
+    let mut elements = Vec::new();
+    let mut chars = inner.chars();
+    let mut element = String::new();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => match chars.next() {
+                Some('t') => element.push('\t'),
+                Some('n') => element.push('\n'),
+                Some('r') => element.push('\r'),
+                Some('0') => element.push('\0'),
+                Some(c) => element.push(c),
+                None => {}
+            },
+            '\'' => quoted = !quoted,
+            ',' if !quoted => elements.push(std::mem::take(&mut element)),
+            c => element.push(c),
+        }
+    }
+    if !inner.trim().is_empty() {
+        elements.push(element);
+    }
+    elements
+}
@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 use std::io;
+use std::iter;
 #[cfg(feature = "multithreaded")]
 use std::mem;
 #[cfg(feature = "multithreaded")]
@@ -144,6 +145,14 @@ pub trait CollapsePrivate: Send + Sized {
     /// Sets the number of threads to use.
     fn set_nthreads(&mut self, n: usize);
 
+    /// Returns the number of threads to use for an input file of `len` bytes.
+    ///
+    /// Formats for which threads only pay off with large inputs can collapse smaller files on a
+    /// single thread. The default is to always use [`nthreads`](Self::nthreads).
+    fn nthreads_for_len(&self, _len: u64) -> usize {
+        self.nthreads()
+    }
+
     // *********************************************************** //
     // ******************** PROVIDED METHODS ********************* //
     // *********************************************************** //
@@ -563,6 +572,158 @@ pub(crate) fn fix_partially_demangled_rust_symbol(symbol: &str) -> Cow<str> {
     Cow::Owned(demangled)
 }
 
+/// Demangles OCaml symbols, which profilers like `perf` leave mangled.
+///
+/// For example:
+///     `camlStdlib__List__map_279` (OCaml < 5.1) and `camlStdlib.List.map_279` (OCaml >= 5.1)
+/// both become
+///     `Stdlib.List.map`
+///
+/// The `_NNN` stamp that the compiler appends is stripped so that identical functions from
+/// different compilations merge, and `$xx` escapes (used for operators) are decoded. Anything that
+/// isn't a mangled OCaml symbol, including the `caml_` functions of the runtime, is returned
+/// unchanged.
+pub(crate) fn demangle_ocaml_symbol(symbol: &str) -> Cow<'_, str> {
+    // Mangled OCaml symbols start with `caml` followed by a capitalized module name.
+    let rest = match symbol.strip_prefix("caml") {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_uppercase()) => rest,
+        _ => return Cow::Borrowed(symbol),
+    };
+
+    // Strip off the trailing stamp.
+    let rest = match rest.rfind('_') {
+        Some(i) if i + 1 < rest.len() && rest[i + 1..].bytes().all(|b| b.is_ascii_digit()) => {
+            &rest[..i]
+        }
+        _ => rest,
+    };
+
+    let mut demangled = String::with_capacity(rest.len());
+    let mut chars = rest.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '_' if rest[i + 1..].starts_with('_') => {
+                chars.next();
+                demangled.push('.');
+            }
+            '$' => {
+                let escaped = rest
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .filter(u8::is_ascii);
+                if let Some(escaped) = escaped {
+                    chars.next();
+                    chars.next();
+                    demangled.push(escaped as char);
+                } else {
+                    demangled.push(c);
+                }
+            }
+            c => demangled.push(c),
+        }
+    }
+
+    Cow::Owned(demangled)
+}
+
+/// Splits a row of CSV into its values, each along with whether it was quoted.
+///
+/// Quoted values can't span lines.
+pub(crate) fn split_csv(row: &str) -> Vec<(String, bool)> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if value.is_empty() => {
+                in_quotes = true;
+                quoted = true;
+            }
+            ',' if !in_quotes => {
+                values.push((std::mem::take(&mut value), quoted));
+                quoted = false;
+            }
+            c => value.push(c),
+        }
+    }
+    values.push((value, quoted));
+    values
+}
+
+/// Escapes the semicolons of a frame as `\;`, so that they don't split it, and doubles the
+/// backslashes in front of them and at the end of the frame, so that those don't escape the
+/// semicolon after them.
+///
+/// [`flamegraph`](crate::flamegraph) turns them back into semicolons and backslashes when it shows
+/// the frame. Other backslashes are left alone.
+///
+/// Only the perf and DTrace collapsers escape frames, and only with their `escape_semicolons`
+/// option; the others still write the semicolons of frame names as they are or as colons.
+pub(crate) fn escape_frame(frame: &str) -> String {
+    let mut escaped = String::with_capacity(frame.len());
+    let mut rest = frame;
+    while let Some(i) = rest.find(['\\', ';']) {
+        escaped.push_str(&rest[..i]);
+        let backslashes = rest[i..].bytes().take_while(|&b| b == b'\\').count();
+        rest = &rest[i + backslashes..];
+        let doubled = rest.is_empty() || rest.starts_with(';');
+        let kept = if doubled {
+            2 * backslashes
+        } else {
+            backslashes
+        };
+        escaped.extend(iter::repeat('\\').take(kept));
+        if let Some(after) = rest.strip_prefix(';') {
+            escaped.push_str("\\;");
+            rest = after;
+        }
+    }
+    escaped.push_str(rest);
+    escaped
+}
+
+/// Turns a frame in the `module!function+offset` notation of vendor profilers, such as
+/// `libc.so.6!memcpy+0x1a`, into the name of its function, or the module within brackets
+/// (`[libc.so.6]`) if the function isn't known.
+///
+/// With `include_module`, function names are prefixed by their module, as in
+/// ``libc.so.6`memcpy``. Semicolons, which would split the frame, become colons.
+pub(crate) fn module_function_frame(frame: &str, include_module: bool) -> String {
+    let frame = frame.trim();
+    let (module, function) = match frame.split_once('!') {
+        Some((module, function)) => (Some(module.trim()), function.trim()),
+        None => (None, frame),
+    };
+    let is_number = |s: &str| {
+        let digits = s.strip_prefix("0x").unwrap_or(s);
+        !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    // The offset may be in hex, as in `+0x1a`, or in decimal.
+    let function = match function.rsplit_once('+') {
+        Some((name, offset)) if !name.is_empty() && is_number(offset) => name,
+        _ => function,
+    };
+    // Unknown functions may be left out, or be given by their address.
+    let unknown = function.is_empty() || (function.starts_with("0x") && is_number(function));
+
+    let frame = match module {
+        Some(module) if unknown && !module.is_empty() => format!("[{}]", module),
+        _ if unknown => "[unknown]".to_string(),
+        Some(module) if include_module && !module.is_empty() => {
+            format!("{}`{}", module, function)
+        }
+        _ => function.to_string(),
+    };
+    frame.replace(';', ":")
+}
+
 #[cfg(test)]
 pub(crate) mod testing {
     use std::collections::HashMap;
@@ -797,6 +958,22 @@ Initialise it with `git submodule update --init flamegraph`."
 
 #[cfg(test)]
 mod tests {
+    use crate::folded::line::{split_frames, unescape_frame};
+
+    #[test]
+    fn escaped_frames_round_trip() {
+        let frames = ["main", r"C:\dir\", "a;b", r"\;", r"x\\y", r"\\;\", r"\"];
+        let stack = frames
+            .iter()
+            .map(|frame| super::escape_frame(frame))
+            .collect::<Vec<_>>()
+            .join(";");
+        let split: Vec<_> = split_frames(&stack).map(unescape_frame).collect();
+        assert_eq!(split, frames);
+        let reversed: Vec<_> = split_frames(&stack).rev().map(unescape_frame).collect();
+        assert!(reversed.iter().rev().eq(frames.iter()));
+    }
+
     macro_rules! t {
         ($a:expr, $b:expr) => {
             assert!(ok($a, $b))
@@ -876,4 +1053,37 @@ mod tests {
         t_unchanged!("<grep_searcher::searcher::glue::ReadByLine<'s, M, R, S>>::run");
         t_unchanged!("<alloc::raw_vec::RawVec<T, A>>::reserve_internal");
     }
+
+    #[test]
+    fn demangle_ocaml_symbols() {
+        let t = |sym, expected| assert_eq!(super::demangle_ocaml_symbol(sym), expected);
+        t("camlStdlib__List__map_279", "Stdlib.List.map");
+        t("camlStdlib.List.map_279", "Stdlib.List.map");
+        t("camlDune__exe__Main__fun_1234", "Dune.exe.Main.fun");
+        t("camlStdlib__$40_155", "Stdlib.@");
+        t("camlMain__entry", "Main.entry");
+        t("camlMain__code_begin", "Main.code_begin");
+        t("caml_call_gc", "caml_call_gc");
+        t("caml_start_program", "caml_start_program");
+        t("main", "main");
+    }
+
+    #[test]
+    fn module_function_frames() {
+        let t = |frame, include_module, expected| {
+            assert_eq!(
+                super::module_function_frame(frame, include_module),
+                expected
+            )
+        };
+        t("libc.so.6!memcpy+0x1a", false, "memcpy");
+        t("libc.so.6!memcpy+0x1a", true, "libc.so.6`memcpy");
+        t("app.exe!Foo::bar+42", false, "Foo::bar");
+        t("app.exe!operator+", false, "operator+");
+        t("ntoskrnl.exe!0x7ff6a1b2", false, "[ntoskrnl.exe]");
+        t("ntoskrnl.exe!", true, "[ntoskrnl.exe]");
+        t("main", false, "main");
+        t("0x401000", false, "[unknown]");
+        t("app!f<a;b>+0x1", false, "f<a:b>");
+    }
 }
@@ -0,0 +1,536 @@
+//! Reconstruction of approximate stacks from Python `cProfile` / `pstats` output.
+//!
+//! `cProfile` does not record full call stacks. It only records, for every function, its own
+//! (`tottime`) and cumulative (`cumtime`) time, along with the cumulative time spent in it on
+//! behalf of each of its *direct* callers. The folded stacks emitted here are therefore an
+//! approximation: starting from every function that has no recorded caller, we walk the
+//! caller → callee edges and assume that a function's time is distributed among its children in
+//! the same proportions regardless of which path led to it. Recursive edges (a function that is
+//! already on the current path) are not followed, and the time of the callees of a function is
+//! scaled down if it adds up to more than the time the function spent in them. Paths are not
+//! expanded past a depth of 256 frames, or once they carry less than a 100,000th of the total
+//! time, in which case all of their time is attributed to their last frame; this keeps the
+//! number of paths walked in check for call graphs with many ways to reach the same function.
+//! Sample counts are in microseconds.
+//!
+//! Two input formats are understood:
+//!
+//!  - the binary `marshal` dump written by `cProfile.Profile.dump_stats` or
+//!    `python -m cProfile -o <file>`, which contains the full caller tables and is what you
+//!    should use to get a useful flame graph; and
+//!  - the text table printed by `python -m cProfile <script>` (or `pstats.Stats.print_stats`).
+//!    It has no caller information, so each function becomes a single-frame stack weighted by
+//!    its `tottime`.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// The function table in the text output begins after this column header.
+static TEXT_HEADER: &str = "filename:lineno(function)";
+
+// Paths whose weight falls below this many microseconds, or below this fraction of the total
+// weight, or that are this deep, are not expanded any further.
+const MIN_WEIGHT_US: f64 = 0.5;
+const MIN_WEIGHT_FRACTION: f64 = 1e-5;
+const MAX_DEPTH: usize = 256;
+
+/// `cprofile` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Don't include file names and line numbers with function names.
+    ///
+    /// Default is `false`.
+    pub no_filenames: bool,
+}
+
+/// A stack collapser for Python `cProfile` / `pstats` output.
+///
+/// The stacks produced are an approximation reconstructed from the profiler's caller/callee
+/// tables; see the [module-level documentation](self) for details.
+///
+/// To construct one, either use `cprofile::Folder::default()` or create an [`Options`] and use
+/// `cprofile::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let mut occurrences = Occurrences::new(1);
+        if is_marshal(&data) == Some(true) {
+            self.collapse_pstats(&data, &mut occurrences)?;
+        } else {
+            self.collapse_text(&data, &mut occurrences)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the start of a `pstats` dump or the `cProfile` text table header.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        match is_marshal(input.as_bytes()) {
+            Some(true) => return Some(true),
+            None => return None,
+            Some(false) => {}
+        }
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let l = line.trim();
+            if l.starts_with("ncalls") && l.ends_with(TEXT_HEADER) {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Checks for the start of a `pstats` dump, which is a marshalled dict whose keys are
+// `(file, line, function)` tuples. Returns `None` if there is not enough data to tell.
+fn is_marshal(data: &[u8]) -> Option<bool> {
+    let code = |i: usize| data.get(i).map(|&b| b & !FLAG_REF);
+    if code(0)? != b'{' {
+        return Some(false);
+    }
+    let first_field = match code(1)? {
+        // The dict of an empty profile.
+        b'0' => return Some(data.len() == 2),
+        b')' if *data.get(2)? == 3 => 3,
+        b'(' if data.get(2..6)? == [3, 0, 0, 0] => 6,
+        _ => return Some(false),
+    };
+    Some(matches!(
+        code(first_field)?,
+        b's' | b't' | b'u' | b'a' | b'A' | b'z' | b'Z' | b'r'
+    ))
+}
+
+/// One entry of the `pstats` function table.
+struct Function {
+    name: String,
+    tottime: f64,
+    cumtime: f64,
+    /// `(callee, cumulative time spent in callee on behalf of this function)`.
+    callees: Vec<(usize, f64)>,
+    has_callers: bool,
+}
+
+impl Folder {
+    fn frame_name(&self, file: &str, line: i64, func: &str) -> String {
+        let name = if self.opt.no_filenames || (file == "~" && line == 0) {
+            func.to_string()
+        } else {
+            format!("{} ({}:{})", func, file, line)
+        };
+        name.replace(';', ":")
+    }
+
+    fn collapse_pstats(&self, data: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        let stats = match Unmarshaller::new(data).read()? {
+            Value::Dict(entries) => entries,
+            _ => return invalid_data_error!("pstats dump does not contain a dict"),
+        };
+
+        let mut index: HashMap<(String, i64, String), usize> = HashMap::new();
+        let mut functions = Vec::with_capacity(stats.len());
+        for (key, _) in &stats {
+            let key = function_key(key)?;
+            let name = self.frame_name(&key.0, key.1, &key.2);
+            index.insert(key, functions.len());
+            functions.push(Function {
+                name,
+                tottime: 0.0,
+                cumtime: 0.0,
+                callees: Vec::new(),
+                has_callers: false,
+            });
+        }
+
+        for (key, value) in &stats {
+            let callee = index[&function_key(key)?];
+            let (tottime, cumtime, callers) = match value {
+                Value::Tuple(fields) if fields.len() == 5 => {
+                    (fields[2].as_f64(), fields[3].as_f64(), &fields[4])
+                }
+                _ => return invalid_data_error!("Unexpected pstats entry: {:?}", value),
+            };
+            let (tottime, cumtime) = match (tottime, cumtime) {
+                (Some(tt), Some(ct)) => (tt, ct),
+                _ => return invalid_data_error!("Unexpected pstats timings: {:?}", value),
+            };
+            functions[callee].tottime = tottime;
+            functions[callee].cumtime = cumtime;
+
+            let callers = match callers {
+                Value::Dict(callers) => callers,
+                _ => return invalid_data_error!("Unexpected pstats callers: {:?}", callers),
+            };
+            for (caller_key, edge) in callers {
+                let caller = match index.get(&function_key(caller_key)?) {
+                    Some(&caller) => caller,
+                    None => {
+                        warn!("Caller {:?} has no entry of its own", caller_key);
+                        continue;
+                    }
+                };
+                // Caller entries are `(cc, nc, tt, ct)`; very old versions only stored a count.
+                let edge_cumtime = match edge {
+                    Value::Tuple(fields) if fields.len() == 4 => fields[3].as_f64(),
+                    _ => None,
+                };
+                if let Some(edge_cumtime) = edge_cumtime {
+                    functions[caller].callees.push((callee, edge_cumtime));
+                }
+                if caller != callee {
+                    functions[callee].has_callers = true;
+                }
+            }
+        }
+
+        // Recursion counts the time of callees more than once, so they may add up to more than
+        // the time spent in them, which would otherwise be conserved along every path.
+        for function in &mut functions {
+            let children = (function.cumtime - function.tottime).max(0.0);
+            let total: f64 = function.callees.iter().map(|&(_, time)| time).sum();
+            if total > children {
+                for (_, time) in &mut function.callees {
+                    *time *= children / total;
+                }
+            }
+        }
+
+        let roots: Vec<usize> = (0..functions.len())
+            .filter(|&i| !functions[i].has_callers)
+            .collect();
+        let total_weight: f64 = roots.iter().map(|&i| functions[i].cumtime * 1e6).sum();
+        let min_weight = (total_weight * MIN_WEIGHT_FRACTION).max(MIN_WEIGHT_US);
+        let mut path = Vec::new();
+        for root in roots {
+            let weight = functions[root].cumtime * 1e6;
+            walk(&functions, root, weight, min_weight, &mut path, occurrences);
+        }
+
+        Ok(())
+    }
+
+    fn collapse_text(&self, data: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        let mut lines = data.split(|&b| b == b'\n');
+
+        // Consume the header...
+        loop {
+            match lines.next() {
+                Some(line) => {
+                    let line = String::from_utf8_lossy(line);
+                    if line.trim_end().ends_with(TEXT_HEADER) {
+                        break;
+                    }
+                }
+                None => {
+                    warn!("File ended before header");
+                    return Ok(());
+                }
+            }
+        }
+
+        // Process the table...
+        for line in lines {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            // ncalls tottime percall cumtime percall filename:lineno(function)
+            let mut rest = line;
+            let mut fields = [""; 5];
+            for field in &mut fields {
+                let (f, r) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                *field = f;
+                rest = r.trim_start();
+            }
+            let (tottime, location) = (fields[1], rest);
+            if location.is_empty() {
+                return invalid_data_error!("Unable to parse function line:\n{}", line);
+            }
+            let tottime: f64 = match tottime.parse() {
+                Ok(tottime) => tottime,
+                Err(_) => return invalid_data_error!("Invalid `tottime` field: {}", tottime),
+            };
+
+            let name = match parse_location(location) {
+                Some((file, line, func)) => self.frame_name(file, line, func),
+                // Built-ins are printed as `{name}` but keyed as `<name>` in the dump.
+                None => match location.strip_prefix('{').and_then(|l| l.strip_suffix('}')) {
+                    Some(builtin) => format!("<{}>", builtin.replace(';', ":")),
+                    None => location.replace(';', ":"),
+                },
+            };
+            let time_us = (tottime * 1e6).round() as usize;
+            if time_us > 0 {
+                occurrences.insert_or_add(name, time_us);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Walks the reconstructed call graph from `node`, which is reached with `weight` microseconds
+/// of cumulative time, attributing the matching share of its self time to the current path.
+///
+/// Paths lighter than `min_weight` or deeper than `MAX_DEPTH` are not expanded any further, and
+/// all of their weight is attributed to the current path instead.
+fn walk(
+    functions: &[Function],
+    node: usize,
+    weight: f64,
+    min_weight: f64,
+    path: &mut Vec<usize>,
+    occurrences: &mut Occurrences,
+) {
+    let function = &functions[node];
+    if function.cumtime <= 0.0 {
+        return;
+    }
+    let share = weight / function.cumtime;
+
+    path.push(node);
+    let expand = weight >= min_weight && path.len() < MAX_DEPTH;
+    let self_us = if expand {
+        (function.tottime * share).round() as usize
+    } else {
+        weight.round() as usize
+    };
+    if self_us > 0 {
+        let stack = path
+            .iter()
+            .map(|&i| functions[i].name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        occurrences.insert_or_add(stack, self_us);
+    }
+    if expand {
+        for &(callee, edge_cumtime) in &function.callees {
+            if path.contains(&callee) {
+                continue;
+            }
+            let weight = edge_cumtime * share;
+            walk(functions, callee, weight, min_weight, path, occurrences);
+        }
+    }
+    path.pop();
+}
+
+/// Splits `file:lineno(function)` into its parts.
+fn parse_location(location: &str) -> Option<(&str, i64, &str)> {
+    let location = location.strip_suffix(')')?;
+    let open = location.find('(')?;
+    let (file_line, func) = (&location[..open], &location[open + 1..]);
+    let colon = file_line.rfind(':')?;
+    let line = file_line[colon + 1..].parse().ok()?;
+    Some((&file_line[..colon], line, func))
+}
+
+fn function_key(value: &Value) -> io::Result<(String, i64, String)> {
+    match value {
+        Value::Tuple(fields) if fields.len() == 3 => {
+            match (&fields[0], fields[1].as_f64(), &fields[2]) {
+                (Value::Str(file), Some(line), Value::Str(func)) => {
+                    Ok((file.clone(), line as i64, func.clone()))
+                }
+                _ => invalid_data_error!("Unexpected pstats function key: {:?}", value),
+            }
+        }
+        _ => invalid_data_error!("Unexpected pstats function key: {:?}", value),
+    }
+}
+
+// Values that can appear in a `pstats` marshal dump.
+#[derive(Clone, Debug)]
+enum Value {
+    None,
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Tuple(Vec<Value>),
+    Dict(Vec<(Value, Value)>),
+}
+
+impl Value {
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Int(i) => Some(i as f64),
+            Value::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+}
+
+const FLAG_REF: u8 = 0x80;
+
+/// A reader for the subset of Python's `marshal` format used by `pstats` dumps.
+struct Unmarshaller<'a> {
+    data: &'a [u8],
+    pos: usize,
+    refs: Vec<Value>,
+}
+
+impl<'a> Unmarshaller<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Unmarshaller {
+            data,
+            pos: 0,
+            refs: Vec::new(),
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated pstats dump",
+            ));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(i32::from_le_bytes(buf))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let n = self.i32()?;
+        if n < 0 {
+            return invalid_data_error!("Negative length in pstats dump");
+        }
+        Ok(n as usize)
+    }
+
+    fn string(&mut self, n: usize) -> io::Result<Value> {
+        let bytes = self.bytes(n)?;
+        Ok(Value::Str(String::from_utf8_lossy(bytes).into_owned()))
+    }
+
+    fn read(&mut self) -> io::Result<Value> {
+        let code = self.u8()?;
+        let flag = code & FLAG_REF != 0;
+        // Containers may refer to themselves, so the slot is reserved before reading them.
+        let slot = if flag {
+            self.refs.push(Value::None);
+            Some(self.refs.len() - 1)
+        } else {
+            None
+        };
+
+        let value = match code & !FLAG_REF {
+            b'N' => Value::None,
+            b'F' => Value::Int(0),
+            b'T' => Value::Int(1),
+            b'i' => Value::Int(i64::from(self.i32()?)),
+            b'l' => {
+                let n = self.i32()?;
+                let mut value = 0i64;
+                for shift in 0..n.unsigned_abs() {
+                    let mut buf = [0; 2];
+                    buf.copy_from_slice(self.bytes(2)?);
+                    let digit = i64::from(u16::from_le_bytes(buf));
+                    value = value.saturating_add(digit.checked_shl(15 * shift).unwrap_or(0));
+                }
+                Value::Int(if n < 0 { -value } else { value })
+            }
+            b'g' => {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(self.bytes(8)?);
+                Value::Float(f64::from_le_bytes(buf))
+            }
+            b'f' => {
+                let n = self.u8()? as usize;
+                let text = String::from_utf8_lossy(self.bytes(n)?);
+                match text.parse() {
+                    Ok(f) => Value::Float(f),
+                    Err(_) => return invalid_data_error!("Invalid float in pstats dump: {}", text),
+                }
+            }
+            b's' | b't' | b'u' | b'a' | b'A' => {
+                let n = self.len()?;
+                self.string(n)?
+            }
+            b'z' | b'Z' => {
+                let n = self.u8()? as usize;
+                self.string(n)?
+            }
+            b'(' | b'[' => {
+                let n = self.len()?;
+                Value::Tuple((0..n).map(|_| self.read()).collect::<io::Result<_>>()?)
+            }
+            b')' => {
+                let n = self.u8()? as usize;
+                Value::Tuple((0..n).map(|_| self.read()).collect::<io::Result<_>>()?)
+            }
+            b'{' => {
+                let mut entries = Vec::new();
+                while self.data.get(self.pos) != Some(&b'0') {
+                    let key = self.read()?;
+                    let value = self.read()?;
+                    entries.push((key, value));
+                }
+                self.pos += 1;
+                Value::Dict(entries)
+            }
+            b'r' => {
+                let index = self.len()?;
+                match self.refs.get(index) {
+                    Some(value) => value.clone(),
+                    None => return invalid_data_error!("Invalid reference in pstats dump"),
+                }
+            }
+            code => {
+                return invalid_data_error!(
+                    "Unsupported marshal type {:?} in pstats dump",
+                    code as char
+                )
+            }
+        };
+
+        if let Some(slot) = slot {
+            self.refs[slot] = value.clone();
+        }
+        Ok(value)
+    }
+}
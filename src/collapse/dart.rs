@@ -0,0 +1,235 @@
+//! Stack collapsing for the CPU profiles of Dart and Flutter programs.
+//!
+//! The input is JSON, as returned by the [VM service] or exported from Dart DevTools, in either of
+//! two forms. A `CpuSamples` object has a list of `functions`, and a list of `samples` whose
+//! stacks are indices into it, innermost first:
+//!
+//! ```text
+//! {"type":"CpuSamples","samplePeriod":250,"functions":[{"kind":"Dart","inclusiveTicks":2,
+//!   "exclusiveTicks":1,"function":{"type":"@Function","name":"build","owner":{"type":"@Class",
+//!   "name":"MyApp"}}},...],"samples":[{"tid":1,"timestamp":100,"stack":[0,1,2]},...]}
+//! ```
+//!
+//! A CPU profile timeline has a tree of `stackFrames`, each with a `parent`, and `traceEvents`
+//! for the samples, each with the stack frame it was taken in as its `sf`:
+//!
+//! ```text
+//! {"type":"_CpuProfileTimeline","samplePeriod":250,"stackFrames":{"1-1":{"category":"Dart",
+//!   "name":"main","resolvedUrl":"file:///app/main.dart"},"1-2":{"category":"Dart",
+//!   "name":"MyApp.build","parent":"1-1"},...},"traceEvents":[{"ph":"P","sf":"1-2",...},...]}
+//! ```
+//!
+//! Every sample counts once. The inclusive and exclusive ticks of functions follow from the
+//! samples, so they aren't needed. Frames for VM and user tags are left out.
+//!
+//! Profiles are taken of one isolate at a time. The input may hold several of them, as a JSON
+//! array or one after the other, and with [`Options::include_isolate`] the stacks of each are
+//! kept apart under a frame named after their isolate, which is taken from the `isolate` or
+//! `isolateId` field of the profile. Profiles may also be nested in other objects, such as those
+//! of DevTools snapshots.
+//!
+//!   [VM service]: https://github.com/dart-lang/sdk/blob/main/runtime/vm/service/service.md
+
+use std::io;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const TAG_KIND: &str = "Tag";
+const SAMPLE_PERIOD_FIELD: &str = "\"samplePeriod\"";
+
+/// `dart` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Start every stack with a frame for the isolate it was sampled in.
+    ///
+    /// Default is `false`.
+    pub include_isolate: bool,
+}
+
+/// A stack collapser for Dart VM CPU profiles.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `dart::Folder::default()` or create an [`Options`] and use
+/// `dart::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Number of samples whose stacks refer to frames that aren't in the profile.
+    ignored: usize,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+
+        let mut profiles = 0;
+        for value in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+            profiles += self.on_value(&value?, &mut occurrences);
+        }
+        if profiles == 0 {
+            return invalid_data_error!("No Dart CPU profile found in input");
+        }
+        if self.ignored > 0 {
+            warn!("Ignored {} samples with unknown stack frames", self.ignored);
+            self.ignored = 0;
+        }
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a JSON document with a sample period.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if !input.trim_start().starts_with(['{', '[']) {
+            return Some(false);
+        }
+        if input.contains(SAMPLE_PERIOD_FIELD) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    // Collapses the profiles in a value, and returns how many there were.
+    fn on_value(&mut self, value: &Value, occurrences: &mut Occurrences) -> usize {
+        if value["samples"].is_array() && value["functions"].is_array() {
+            self.on_cpu_samples(value, occurrences);
+            1
+        } else if value["traceEvents"].is_array() && value["stackFrames"].is_object() {
+            self.on_timeline(value, occurrences);
+            1
+        } else {
+            match value {
+                Value::Array(values) => values.iter().map(|v| self.on_value(v, occurrences)).sum(),
+                Value::Object(values) => values
+                    .values()
+                    .filter(|v| v.is_object() || v.is_array())
+                    .map(|v| self.on_value(v, occurrences))
+                    .sum(),
+                _ => 0,
+            }
+        }
+    }
+
+    fn on_cpu_samples(&mut self, profile: &Value, occurrences: &mut Occurrences) {
+        let functions: Vec<Option<String>> = profile["functions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|function| {
+                if function["kind"] == TAG_KIND {
+                    None
+                } else {
+                    Some(function_name(&function["function"]))
+                }
+            })
+            .collect();
+        let isolate = self.isolate(profile);
+
+        for sample in profile["samples"].as_array().into_iter().flatten() {
+            let mut stack = isolate.clone();
+            let mut known = true;
+            let frames = sample["stack"].as_array().into_iter().flatten().rev();
+            for frame in frames {
+                match frame.as_u64().and_then(|i| functions.get(i as usize)) {
+                    Some(Some(name)) => stack.push(name.as_str()),
+                    Some(None) => {}
+                    None => known = false,
+                }
+            }
+            self.on_stack(&stack, known, occurrences);
+        }
+    }
+
+    fn on_timeline(&mut self, profile: &Value, occurrences: &mut Occurrences) {
+        let frames = &profile["stackFrames"];
+        let isolate = self.isolate(profile);
+
+        for event in profile["traceEvents"].as_array().into_iter().flatten() {
+            let Some(mut id) = event["sf"].as_str().map(str::to_string) else {
+                continue;
+            };
+            let mut stack = Vec::new();
+            let mut known = true;
+            // Guard against cycles, which no tree of stack frames has.
+            for _ in 0..frames.as_object().map_or(0, |frames| frames.len()) {
+                let frame = &frames[&id];
+                if !frame.is_object() {
+                    known = false;
+                    break;
+                }
+                if frame["category"] != TAG_KIND {
+                    stack.push(frame["name"].as_str().unwrap_or("[unknown]"));
+                }
+                match &frame["parent"] {
+                    Value::String(parent) => id = parent.clone(),
+                    Value::Number(parent) => id = parent.to_string(),
+                    _ => break,
+                }
+            }
+            stack.extend(isolate.iter().copied());
+            stack.reverse();
+            self.on_stack(&stack, known, occurrences);
+        }
+    }
+
+    fn isolate<'a>(&self, profile: &'a Value) -> Vec<&'a str> {
+        if !self.opt.include_isolate {
+            return Vec::new();
+        }
+        let isolate = &profile["isolate"];
+        let name = isolate["name"]
+            .as_str()
+            .or_else(|| isolate["id"].as_str())
+            .or_else(|| profile["isolateId"].as_str())
+            .unwrap_or("[unknown]");
+        vec![name]
+    }
+
+    fn on_stack(&mut self, stack: &[&str], known: bool, occurrences: &mut Occurrences) {
+        if !known {
+            self.ignored += 1;
+            return;
+        }
+        if stack.is_empty() {
+            return;
+        }
+        let stack: Vec<_> = stack.iter().map(|frame| frame.replace(';', ":")).collect();
+        occurrences.insert_or_add(stack.join(";"), 1);
+    }
+}
+
+// Names functions after the class (or function, for closures) they are in, as Dart does.
+fn function_name(function: &Value) -> String {
+    let Some(name) = function["name"].as_str() else {
+        return "[unknown]".to_string();
+    };
+    let owner = &function["owner"];
+    match (owner["type"].as_str(), owner["name"].as_str()) {
+        (Some("@Class" | "Class" | "@Function" | "Function"), Some(owner)) => {
+            format!("{}.{}", owner, name)
+        }
+        _ => name.to_string(),
+    }
+}
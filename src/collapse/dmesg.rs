@@ -0,0 +1,331 @@
+//! Stack collapsing for stack traces found in kernel logs.
+//!
+//! The kernel prints a stack trace with most of its reports: warnings (`WARNING: ...`), oopses
+//! and `BUG: ...` reports, lockdep splats, hung task reports, and so on. This collapser scans a
+//! log for such traces and counts how often each one occurs, so that recurring problems show up
+//! as wide towers. Stack traces recorded by ftrace (the `stacktrace` trigger or trace option) are
+//! picked up as well.
+//!
+//! Logs can come straight from `dmesg`, or from the system log or `journalctl -k`; timestamps and
+//! syslog prefixes are ignored. Frames are normalized by dropping their addresses and offsets
+//! (`foo_bar+0x12/0x40 [foo]` becomes `foo_bar [foo]`), and frames the kernel marks as unreliable
+//! with a `?` are left out by default. The root frame of each stack is the title of the report it
+//! belongs to, with numbers that vary between occurrences (CPUs, PIDs, addresses, ...) replaced
+//! by `*`. For ftrace stacks, it is the name of the task the trace was taken in.
+
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// Lines that start a report whose title becomes the root frame of its stack trace.
+static REPORT_PREFIXES: &[&str] = &[
+    "WARNING:",
+    "BUG:",
+    "kernel BUG",
+    "INFO:",
+    "Oops",
+    "general protection fault",
+    "Unable to handle kernel",
+    "watchdog:",
+    "UBSAN:",
+];
+
+// Lines that start the list of frames of a stack trace.
+static TRACE_HEADERS: &[&str] = &[
+    "Call Trace:",
+    "Call trace:",
+    "Backtrace:",
+    "stack backtrace:",
+];
+
+// Markers that separate the task, interrupt and exception parts of a stack trace.
+static CONTEXT_MARKERS: &[&str] = &[
+    "<TASK>",
+    "</TASK>",
+    "<IRQ>",
+    "</IRQ>",
+    "<NMI>",
+    "</NMI>",
+    "<EOI>",
+    "<SOFTIRQ>",
+    "</SOFTIRQ>",
+];
+
+/// `dmesg` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Keep the frames that the kernel marks as unreliable with a `?`.
+    ///
+    /// Default is `false`.
+    pub include_unreliable: bool,
+
+    /// Don't include module names with function names.
+    ///
+    /// Default is `false`.
+    pub no_modules: bool,
+}
+
+/// A stack collapser for stack traces in kernel logs.
+///
+/// See the [module-level documentation](self) for details.
+///
+/// To construct one, either use `dmesg::Folder::default()` or create an [`Options`] and use
+/// `dmesg::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Title of the report that is currently being read, if any.
+    title: Option<String>,
+
+    /// Whether we are reading the frames of a stack trace.
+    in_trace: bool,
+
+    /// Frames of the current stack trace, innermost first.
+    frames: Vec<String>,
+
+    /// Number of stack traces found so far.
+    ntraces: usize,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            self.on_line(strip_log_prefix(l.trim_end()), &mut occurrences);
+        }
+        self.end_trace(&mut occurrences);
+
+        if self.ntraces == 0 {
+            warn!("No stack traces found in the log");
+        }
+        occurrences.write_and_clear(writer)?;
+
+        // Reset the state...
+        self.title = None;
+        self.ntraces = 0;
+        Ok(())
+    }
+
+    /// Check for the header of a stack trace.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let l = strip_log_prefix(line.trim_end()).trim();
+            if TRACE_HEADERS.contains(&l) || l.ends_with(": <stack trace>") {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn on_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        let content = line.trim();
+
+        if self.in_trace {
+            if CONTEXT_MARKERS.contains(&content) {
+                return;
+            }
+            if let Some((frame, reliable)) = self.parse_frame(content) {
+                if reliable || self.opt.include_unreliable {
+                    self.frames.push(frame);
+                }
+                return;
+            }
+            self.end_trace(occurrences);
+        }
+
+        if REPORT_PREFIXES.iter().any(|p| content.starts_with(p)) {
+            // Reports often go on with more lines that look like the start of a report (such as
+            // the `Oops: ...` line after a `BUG: ...`), but it is the first one that says what
+            // happened.
+            if self.title.is_none() {
+                self.title = Some(report_title(content));
+            }
+        } else if TRACE_HEADERS.contains(&content) {
+            self.in_trace = true;
+        } else if let Some(task) = content.strip_suffix(": <stack trace>") {
+            // `<comm>-<pid> [<cpu>] <flags> <timestamp>: <stack trace>`
+            let comm = task.split_whitespace().next().unwrap_or_default();
+            let comm = comm.rsplit_once('-').map_or(comm, |(comm, _)| comm);
+            self.title = Some(comm.replace(';', ":"));
+            self.in_trace = true;
+        } else if content.starts_with("---[ end trace") {
+            self.title = None;
+        }
+    }
+
+    /// Parses a frame of a stack trace, returning its normalized name and whether it is reliable.
+    fn parse_frame(&self, content: &str) -> Option<(String, bool)> {
+        // ftrace: `=> foo_bar`
+        if let Some(symbol) = content.strip_prefix("=> ") {
+            let symbol = symbol.split('+').next().unwrap_or_default().trim();
+            return (!symbol.is_empty()).then(|| (symbol.replace(';', ":"), true));
+        }
+
+        // Older kernels print the address first: `[<ffffffff8106b4d5>] ? foo_bar+0x85/0xc0`
+        let mut content = content;
+        if let Some(rest) = content.strip_prefix("[<") {
+            content = rest.split_once(">] ")?.1;
+        }
+        let (content, reliable) = match content.strip_prefix("? ") {
+            Some(content) => (content, false),
+            None => (content, true),
+        };
+
+        // `foo_bar+0x12/0x40 [foo]`
+        let (symbol, rest) = content.split_once("+0x")?;
+        let (offsets, module) = rest.split_once(' ').unwrap_or((rest, ""));
+        let (offset, size) = offsets.split_once("/0x")?;
+        let is_hex = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit());
+        if symbol.is_empty() || symbol.contains(' ') || !is_hex(offset) || !is_hex(size) {
+            return None;
+        }
+
+        let module = module.trim();
+        let name = if module.is_empty() || self.opt.no_modules {
+            symbol.to_string()
+        } else {
+            format!("{} {}", symbol, module)
+        };
+        Some((name.replace(';', ":"), reliable))
+    }
+
+    fn end_trace(&mut self, occurrences: &mut Occurrences) {
+        if !self.frames.is_empty() {
+            let mut stack = String::new();
+            if let Some(title) = &self.title {
+                stack.push_str(title);
+                stack.push(';');
+            }
+            for (i, frame) in self.frames.iter().rev().enumerate() {
+                if i > 0 {
+                    stack.push(';');
+                }
+                stack.push_str(frame);
+            }
+            occurrences.insert_or_add(stack, 1);
+            self.ntraces += 1;
+            // A report has a single stack trace, so any further ones belong to something else.
+            self.title = None;
+        }
+        self.frames.clear();
+        self.in_trace = false;
+    }
+}
+
+/// Strips syslog prefixes (`Oct 16 10:00:00 host kernel: `), timestamps (`[  123.456789]`) and
+/// caller IDs (`[ T123]`) from a kernel log line.
+fn strip_log_prefix(line: &str) -> &str {
+    let mut line = match line.find("kernel: ") {
+        Some(i) => &line[i + "kernel: ".len()..],
+        None => line,
+    };
+    for is_prefix in [is_timestamp, is_caller_id] {
+        if let Some((bracketed, rest)) = line.strip_prefix('[').and_then(|l| l.split_once(']')) {
+            if is_prefix(bracketed.trim()) {
+                line = rest;
+            }
+        }
+    }
+    line
+}
+
+fn is_timestamp(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+}
+
+fn is_caller_id(s: &str) -> bool {
+    matches!(s.as_bytes().first(), Some(b'T' | b'C'))
+        && s.len() > 1
+        && s[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Turns the first line of a report into a title that is the same for every occurrence.
+fn report_title(content: &str) -> String {
+    // `WARNING: CPU: 2 PID: 1234 at drivers/gpu/drm/foo.c:123 foo_bar+0x12/0x40 [foo]`
+    let (kind, content) = match content.strip_prefix("WARNING: ") {
+        Some(rest) if rest.starts_with("CPU: ") || rest.starts_with("at ") => match rest
+            .split_once(" at ")
+            .or_else(|| rest.strip_prefix("at ").map(|r| ("", r)))
+        {
+            Some((_, location)) => ("WARNING at ", location),
+            None => ("", content),
+        },
+        _ => ("", content),
+    };
+
+    let mut title = kind.to_string();
+    for (i, word) in content.split_whitespace().enumerate() {
+        if i > 0 {
+            title.push(' ');
+        }
+        if let Some((symbol, _)) = word.split_once("+0x") {
+            title.push_str(symbol);
+        } else if is_source_location(word)
+            || is_module(word)
+            || !word.bytes().any(|b| b.is_ascii_digit())
+        {
+            title.push_str(word);
+        } else {
+            title.push('*');
+        }
+    }
+    title.replace(';', ":")
+}
+
+// `[i915]`
+fn is_module(word: &str) -> bool {
+    word.strip_prefix('[')
+        .and_then(|w| w.strip_suffix(']'))
+        .is_some_and(|m| !m.is_empty() && m.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'))
+}
+
+// `drivers/gpu/drm/foo.c:123`, possibly followed by punctuation.
+fn is_source_location(word: &str) -> bool {
+    let word = word.trim_end_matches(|c: char| !c.is_ascii_alphanumeric());
+    match word.rsplit_once(':') {
+        Some((file, line)) => {
+            let has_extension = file.rsplit_once('.').is_some_and(|(_, ext)| {
+                !ext.is_empty() && ext.bytes().all(|b| b.is_ascii_alphabetic())
+            });
+            has_extension && !line.is_empty() && line.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
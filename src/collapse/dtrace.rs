@@ -15,6 +15,14 @@ pub struct Options {
     /// Default is `false`.
     pub includeoffset: bool,
 
+    /// Escape semicolons in function names, such as those of Rust array types like `[u8; 8]`, as
+    /// `\;` rather than replacing them with colons, so that the names are shown as they are.
+    ///
+    /// Tools other than inferno may not understand the escaped semicolons.
+    ///
+    /// Default is `false`.
+    pub escape_semicolons: bool,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
@@ -25,6 +33,7 @@ impl Default for Options {
     fn default() -> Self {
         Self {
             includeoffset: false,
+            escape_semicolons: false,
             nthreads: *common::DEFAULT_NTHREADS,
         }
     }
@@ -329,6 +338,15 @@ impl Folder {
     //     genunix`syscall_mstate+0x5d
     //     unix`sys_syscall+0x10e
     //       1
+    // Semicolons would split a frame, so they're escaped or replaced.
+    fn tidy_semicolons(&self, func: &str) -> String {
+        if self.opt.escape_semicolons {
+            common::escape_frame(func)
+        } else {
+            func.replace(';', ":")
+        }
+    }
+
     fn on_stack_line(&mut self, line: &str) {
         let (has_inlines, could_be_cpp, has_semicolon, mut frame) = if self.opt.includeoffset {
             (true, true, true, line)
@@ -350,7 +368,7 @@ impl Folder {
             let mut inline = false;
             for func in frame.split("->") {
                 let mut func = if has_semicolon {
-                    func.trim_start_matches('L').replace(';', ":")
+                    self.tidy_semicolons(func.trim_start_matches('L'))
                 } else {
                     func.trim_start_matches('L').to_owned()
                 };
@@ -365,7 +383,7 @@ impl Folder {
                 self.stack.push_front(func);
             }
         } else if has_semicolon {
-            self.stack.push_front(frame.replace(';', ":"))
+            self.stack.push_front(self.tidy_semicolons(&frame))
         } else {
             self.stack.push_front(frame.to_string())
         }
@@ -568,6 +586,7 @@ mod tests {
             let nstacks_per_job = rng.random_range(1..=500);
             let options = Options {
                 includeoffset: rng.random(),
+                escape_semicolons: rng.random(),
                 nthreads: rng.random_range(2..=32),
             };
 
@@ -0,0 +1,204 @@
+//! Stack collapsing for `perf script` output into time buckets, for sub-second analysis of
+//! profiles as with [FlameScope].
+//!
+//! The input is the output of `perf script` with the time of every sample, which is what
+//! FlameScope loads too. Samples are put in buckets of [`Options::bucket`] seconds by their
+//! offset from the first sample, the way FlameScope offsets the columns and rows of its heatmap,
+//! and the samples of every bucket are folded as [`perf`](crate::collapse::perf) folds them.
+//! Every line ends with one count for every bucket, from the first to the last:
+//!
+//! ```text
+//! java;start_thread;run;compute 12 30 0 4
+//! ```
+//!
+//! [`flamegraph`](crate::flamegraph) reads these lines with
+//! [`time_windows`](crate::flamegraph::Options::time_windows) set to the number of buckets, and
+//! shows the trend of every frame over them. To draw only the time range that stands out in
+//! the heatmap of FlameScope, pass its start and end offsets as [`Options::start`] and
+//! [`Options::end`].
+//!
+//!   [FlameScope]: https://github.com/Netflix/flamescope
+
+use std::collections::BTreeMap;
+use std::io;
+
+use log::{info, warn};
+
+use crate::collapse::perf;
+use crate::collapse::Collapse;
+use crate::folded::Line;
+
+/// `flamescope` folder configuration options.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    /// The length of every time bucket, in seconds.
+    ///
+    /// Default is `1.0`, which is a column of the heatmap of FlameScope. Its rows are `0.02`.
+    pub bucket: f64,
+
+    /// Leave out the samples before this offset from the first sample, in seconds, and count
+    /// the buckets from it.
+    ///
+    /// Default is `None`, which starts at the first sample.
+    pub start: Option<f64>,
+
+    /// Leave out the samples from this offset from the first sample on, in seconds.
+    ///
+    /// Default is `None`, which ends after the last sample.
+    pub end: Option<f64>,
+
+    /// How the samples of every bucket are folded.
+    ///
+    /// The number of threads is ignored, as buckets are folded one at a time.
+    pub perf: perf::Options,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            bucket: 1.0,
+            start: None,
+            end: None,
+            perf: perf::Options::default(),
+        }
+    }
+}
+
+/// A stack collapser for `perf script` output that counts the samples of every stack in time
+/// buckets.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `flamescope::Folder::default()` or create an [`Options`] and use
+/// `flamescope::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        // Times are compared in nanoseconds, so that samples on the edge of a bucket don't fall
+        // into the one before it by a rounding error.
+        let nanos = |seconds: f64| (seconds * 1e9).round() as i64;
+        let bucket_ns = nanos(self.opt.bucket);
+        if bucket_ns <= 0 {
+            return invalid_data_error!("Time buckets must be at least a nanosecond long");
+        }
+        let start = nanos(self.opt.start.unwrap_or(0.0));
+        let end = self.opt.end.map_or(i64::MAX, nanos);
+
+        // The events of every bucket, as `perf script` wrote them.
+        let mut buckets: BTreeMap<usize, String> = BTreeMap::new();
+        let mut first_time = None;
+        let mut event_filter = self.opt.perf.event_filter.clone();
+        let mut without_time = 0;
+        // The bucket of the event that the current line belongs to, if it's in the time range.
+        let mut bucket = None;
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with('#') {
+                continue;
+            }
+            if !line.is_empty() && !line.starts_with(char::is_whitespace) {
+                bucket = None;
+                let Some((time, event)) = event_line_parts(&line) else {
+                    without_time += 1;
+                    continue;
+                };
+                // Buckets are folded separately, so they have to agree on the event type.
+                if !event.is_empty() {
+                    event_filter.get_or_insert_with(|| event.to_string());
+                }
+                let offset = nanos(time - *first_time.get_or_insert(time));
+                if offset >= start && offset < end {
+                    bucket = Some(((offset - start) / bucket_ns) as usize);
+                }
+            }
+            if let Some(bucket) = bucket {
+                let events = buckets.entry(bucket).or_default();
+                events.push_str(&line);
+                events.push('\n');
+            }
+        }
+
+        if without_time > 0 {
+            warn!(
+                "Ignored {} events without a time; run perf script with -F ...,time,...",
+                without_time
+            );
+        }
+        if first_time.is_none() {
+            return invalid_data_error!("No events with a time found in input");
+        }
+
+        let nbuckets = buckets.keys().next_back().map_or(0, |&last| last + 1);
+        info!(
+            "Counted samples in {} buckets; draw them with --time-windows {}",
+            nbuckets, nbuckets
+        );
+
+        let mut perf_opt = self.opt.perf.clone();
+        perf_opt.event_filter = event_filter;
+        perf_opt.nthreads = 1;
+        let mut counts: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        let mut folded = Vec::new();
+        for (bucket, events) in buckets {
+            folded.clear();
+            perf::Folder::from(perf_opt.clone()).collapse(events.as_bytes(), &mut folded)?;
+            for line in String::from_utf8_lossy(&folded).lines() {
+                let Some(line) = Line::parse(line, false) else {
+                    continue;
+                };
+                let bucket_counts = counts
+                    .entry(line.stack().to_string())
+                    .or_insert_with(|| vec![0; nbuckets]);
+                bucket_counts[bucket] += line.samples();
+            }
+        }
+
+        for (stack, bucket_counts) in counts {
+            write!(writer, "{}", stack)?;
+            for count in bucket_counts {
+                write!(writer, " {}", count)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Check for `perf script` output with the time of every sample.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let event_line = input
+            .lines()
+            .find(|line| !line.is_empty() && !line.starts_with(['#', ' ', '\t']))?;
+        if event_line_parts(event_line).is_none() {
+            return Some(false);
+        }
+        perf::Folder::default().is_applicable(input)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Finds the time and the event type of an event line of `perf script`, as in
+// `java 10488 [003] 9962.502413:   10101010 cpu-clock:`.
+fn event_line_parts(line: &str) -> Option<(f64, &str)> {
+    let (time, rest) = line.split_whitespace().find_map(|field| {
+        let time = field.strip_suffix(':').filter(|t| t.contains('.'))?;
+        // Fields are slices of the line, so this is the rest of the line after the time.
+        let end = field.as_ptr() as usize - line.as_ptr() as usize + field.len();
+        Some((time.parse().ok()?, &line[end..]))
+    })?;
+    let event = rest.split(':').next()?.trim().rsplit(' ').next()?;
+    Some((time, event))
+}
@@ -0,0 +1,455 @@
+//! Stack collapsing for Go execution traces, as written by `runtime/trace` or `go test -trace`.
+//!
+//! Where a CPU profile samples what is on the CPU, an execution trace records every time the Go
+//! scheduler starts and stops running a goroutine. This collapser adds up how long goroutines ran
+//! for, from being started on a P until they block, yield, make a system call or exit, and folds
+//! that time by one of two stacks:
+//!
+//!  - the stack the goroutine was at when it stopped running ([`Stacks::Running`]), which shows
+//!    the code that ran until the goroutine gave up its P. Goroutines exit without a stack, so
+//!    their last run is folded under the function they started in; or
+//!  - the stack of the `go` statement that created the goroutine, followed by the function the
+//!    goroutine started in ([`Stacks::Creation`]), which shows which goroutines kept the Ps busy.
+//!    Goroutines created before tracing started are folded under `[unknown]`.
+//!
+//! Counts are in nanoseconds. Only traces in the format of Go 1.22 and later are understood.
+
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// Traces start with a header like `go 1.22 trace\x00\x00\x00`.
+const HEADER_PREFIX: &[u8] = b"go 1.";
+const HEADER_LEN: usize = 16;
+const MIN_MINOR_VERSION: u32 = 22;
+
+// The frame for stacks that aren't in the trace.
+const UNKNOWN_FRAME: &str = "[unknown]";
+
+// Types of the batches and events of the trace, as defined by `internal/trace` of Go.
+const EV_EVENT_BATCH: u8 = 1;
+const EV_STACKS: u8 = 2;
+const EV_STACK: u8 = 3;
+const EV_STRINGS: u8 = 4;
+const EV_STRING: u8 = 5;
+const EV_CPU_SAMPLES: u8 = 6;
+const EV_FREQUENCY: u8 = 8;
+const EV_GO_CREATE: u8 = 14;
+const EV_GO_START: u8 = 16;
+const EV_GO_DESTROY: u8 = 17;
+const EV_GO_STOP: u8 = 19;
+const EV_GO_BLOCK: u8 = 20;
+const EV_GO_SYSCALL_BEGIN: u8 = 22;
+const EV_GO_SYSCALL_END: u8 = 23;
+const EV_GO_SYSCALL_END_BLOCKED: u8 = 24;
+const EV_GO_STATUS: u8 = 25;
+const EV_GO_SWITCH: u8 = 45;
+const EV_GO_SWITCH_DESTROY: u8 = 46;
+const EV_GO_CREATE_BLOCKED: u8 = 47;
+const EV_GO_STATUS_STACK: u8 = 48;
+const EV_EXPERIMENTAL_BATCH: u8 = 49;
+const EV_SYNC: u8 = 50;
+
+// The status of a goroutine that is running, in `EvGoStatus` events.
+const GO_STATUS_RUNNING: u64 = 2;
+
+// The number of arguments of each type of event, from `EvProcsChange` (9) to
+// `EvClockSnapshot` (51). Events in batches are their type followed by these many unsigned
+// LEB128 numbers, the first of which is the time since the previous event.
+const NARGS: [usize; 43] = [
+    3, 3, 1, 4, 3, // procs
+    4, 2, 3, 1, 1, 3, 3, 4, 3, 1, 1, 4, // goroutines
+    3, 1, // stop-the-world
+    2, 3, 2, 2, 2, 3, 2, 2, 1, 2, 2, // GC
+    2, 5, 3, 4, 4, 5, // annotations
+    3, 3, 4, 5, // goroutines of Go 1.23
+    0, 0, 4, // batches of Go 1.23 and 1.25, which aren't events, and clock snapshots
+];
+
+/// Which stacks the time goroutines ran for is folded by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Stacks {
+    /// The stack a goroutine was at when it stopped running.
+    #[default]
+    Running,
+    /// The stack that created a goroutine, followed by the function the goroutine started in.
+    Creation,
+}
+
+impl Stacks {
+    /// The valid set of stacks (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["running", "creation"];
+}
+
+impl FromStr for Stacks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(Stacks::Running),
+            "creation" => Ok(Stacks::Creation),
+            _ => Err(format!("unknown stacks: {}", s)),
+        }
+    }
+}
+
+/// `go_trace` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Which stacks the time goroutines ran for is folded by.
+    ///
+    /// Default is [`Stacks::Running`].
+    pub stacks: Stacks,
+}
+
+/// A stack collapser for Go execution traces.
+///
+/// See the [module-level documentation](self) for details.
+///
+/// To construct one, either use `go_trace::Folder::default()` or create an [`Options`] and use
+/// `go_trace::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let Some(minor) = version(&data) else {
+            return invalid_data_error!("Not a Go execution trace");
+        };
+        if minor < MIN_MINOR_VERSION {
+            return invalid_data_error!(
+                "Go 1.{} execution traces are not supported, only those of Go 1.{} and later",
+                minor,
+                MIN_MINOR_VERSION
+            );
+        }
+
+        let mut trace = Trace::default();
+        let mut data = TraceReader(&data[HEADER_LEN..]);
+        while !data.0.is_empty() {
+            match data.byte()? {
+                EV_EVENT_BATCH => {
+                    let (gen, m, time) = (data.uleb128()?, data.uleb128()?, data.uleb128()?);
+                    let size = data.uleb128()? as usize;
+                    let batch = TraceReader(data.bytes(size)?);
+                    trace.on_batch(gen, m, time, batch)?;
+                }
+                EV_EXPERIMENTAL_BATCH => {
+                    // The data of experiments is in a format of its own, which we don't need.
+                    data.byte()?;
+                    let _ = (data.uleb128()?, data.uleb128()?, data.uleb128()?);
+                    let size = data.uleb128()? as usize;
+                    data.bytes(size)?;
+                }
+                typ => return invalid_data_error!("Unexpected batch of type {}", typ),
+            }
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        self.fold(&trace, &mut occurrences);
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the header of an execution trace.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        match version(input.as_bytes()) {
+            Some(minor) => Some(minor >= MIN_MINOR_VERSION),
+            None if input.len() < HEADER_LEN => None,
+            None => Some(false),
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn fold(&self, trace: &Trace, occurrences: &mut Occurrences) {
+        let mut missing_frequency = false;
+        for (&(gen, g, stack), &ticks) in &trace.ran {
+            let ns = match trace.frequencies.get(&gen) {
+                Some(&frequency) if frequency > 0 => {
+                    (u128::from(ticks) * 1_000_000_000 / u128::from(frequency)) as usize
+                }
+                _ => {
+                    missing_frequency = true;
+                    ticks as usize
+                }
+            };
+            if ns == 0 {
+                continue;
+            }
+
+            let mut frames: Vec<&str> = Vec::new();
+            match self.opt.stacks {
+                Stacks::Running => {
+                    trace.push_frames(gen, stack, &mut frames);
+                    // Goroutines exit without a stack, so their time is put in their function.
+                    if frames.is_empty() {
+                        frames.extend(trace.start_function(g));
+                    }
+                }
+                Stacks::Creation => {
+                    if let Some(created) = trace.created.get(&g) {
+                        trace.push_frames(created.gen, created.stack, &mut frames);
+                        frames.extend(trace.start_function(g));
+                    }
+                }
+            }
+            if frames.is_empty() {
+                frames.push(UNKNOWN_FRAME);
+            }
+            occurrences.insert_or_add(frames.join(";"), ns);
+        }
+        if missing_frequency {
+            warn!("The trace is missing its clock frequency, so counts are in clock ticks");
+        }
+    }
+}
+
+// Reads the minor version of Go from the header of a trace.
+fn version(data: &[u8]) -> Option<u32> {
+    let header = data.get(..HEADER_LEN)?;
+    let version = header.strip_prefix(HEADER_PREFIX)?;
+    let end = version.iter().position(|&b| b == b' ')?;
+    if !version[end..].starts_with(b" trace\0") {
+        return None;
+    }
+    std::str::from_utf8(&version[..end]).ok()?.parse().ok()
+}
+
+#[derive(Default)]
+struct Trace {
+    /// Clock ticks per second, by generation.
+    frequencies: HashMap<u64, u64>,
+
+    /// Strings, by generation and ID.
+    strings: HashMap<(u64, u64), String>,
+
+    /// Function names (as string IDs) of the frames of stacks, innermost first, by generation
+    /// and ID.
+    stacks: HashMap<(u64, u64), Vec<u64>>,
+
+    /// Where goroutines were created, by goroutine ID.
+    created: HashMap<u64, Created>,
+
+    /// The goroutine that is running on each M, and since when.
+    running: HashMap<u64, (u64, u64)>,
+
+    /// The goroutine that is in a system call on each M.
+    in_syscall: HashMap<u64, u64>,
+
+    /// Clock ticks goroutines ran for, by the generation and stack they stopped in.
+    ran: HashMap<(u64, u64, u64), u64>,
+}
+
+struct Created {
+    gen: u64,
+    /// The stack of the `go` statement.
+    stack: u64,
+    /// The stack the goroutine starts in, which is only its function.
+    start_stack: u64,
+}
+
+impl Trace {
+    fn on_batch(
+        &mut self,
+        gen: u64,
+        m: u64,
+        mut time: u64,
+        mut batch: TraceReader,
+    ) -> io::Result<()> {
+        let Some(&typ) = batch.0.first() else {
+            return Ok(());
+        };
+        match typ {
+            EV_STRINGS => {
+                batch.byte()?;
+                while !batch.0.is_empty() {
+                    batch.expect(EV_STRING)?;
+                    let id = batch.uleb128()?;
+                    let len = batch.uleb128()? as usize;
+                    let string = String::from_utf8_lossy(batch.bytes(len)?).into_owned();
+                    self.strings.insert((gen, id), string);
+                }
+            }
+            EV_STACKS => {
+                batch.byte()?;
+                while !batch.0.is_empty() {
+                    batch.expect(EV_STACK)?;
+                    let id = batch.uleb128()?;
+                    let nframes = batch.uleb128()?;
+                    let mut funcs = Vec::new();
+                    for _ in 0..nframes {
+                        let (_pc, func) = (batch.uleb128()?, batch.uleb128()?);
+                        let (_file, _line) = (batch.uleb128()?, batch.uleb128()?);
+                        funcs.push(func);
+                    }
+                    self.stacks.insert((gen, id), funcs);
+                }
+            }
+            EV_CPU_SAMPLES => {}
+            EV_FREQUENCY | EV_SYNC => {
+                while !batch.0.is_empty() {
+                    match batch.byte()? {
+                        EV_SYNC => {}
+                        EV_FREQUENCY => {
+                            self.frequencies.insert(gen, batch.uleb128()?);
+                        }
+                        typ => batch.skip_event(typ)?,
+                    }
+                }
+            }
+            _ => {
+                while !batch.0.is_empty() {
+                    let typ = batch.byte()?;
+                    let mut args = [0; 6];
+                    let nargs = nargs(typ)?;
+                    for arg in &mut args[..nargs] {
+                        *arg = batch.uleb128()?;
+                    }
+                    time += args[0];
+                    self.on_event(gen, m, time, typ, &args[1..nargs]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_event(&mut self, gen: u64, m: u64, time: u64, typ: u8, args: &[u64]) {
+        match typ {
+            EV_GO_CREATE | EV_GO_CREATE_BLOCKED => {
+                let created = Created {
+                    gen,
+                    stack: args[2],
+                    start_stack: args[1],
+                };
+                self.created.insert(args[0], created);
+            }
+            EV_GO_START => self.start(m, args[0], time),
+            // Goroutines that were already running on an M when the generation started.
+            EV_GO_STATUS | EV_GO_STATUS_STACK if args[2] == GO_STATUS_RUNNING => {
+                self.running.entry(args[1]).or_insert((args[0], time));
+            }
+            EV_GO_STOP | EV_GO_BLOCK => self.stop(gen, m, time, args[1]),
+            EV_GO_DESTROY => self.stop(gen, m, time, 0),
+            EV_GO_SYSCALL_BEGIN => {
+                if let Some(&(g, _)) = self.running.get(&m) {
+                    self.in_syscall.insert(m, g);
+                }
+                self.stop(gen, m, time, args[1]);
+            }
+            EV_GO_SYSCALL_END => {
+                if let Some(g) = self.in_syscall.remove(&m) {
+                    self.start(m, g, time);
+                }
+            }
+            EV_GO_SYSCALL_END_BLOCKED => {
+                // The goroutine lost its P during the call, and is started again on one later.
+                self.in_syscall.remove(&m);
+            }
+            EV_GO_SWITCH | EV_GO_SWITCH_DESTROY => {
+                self.stop(gen, m, time, 0);
+                self.start(m, args[0], time);
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, m: u64, g: u64, time: u64) {
+        self.running.insert(m, (g, time));
+    }
+
+    fn stop(&mut self, gen: u64, m: u64, time: u64, stack: u64) {
+        if let Some((g, start)) = self.running.remove(&m) {
+            *self.ran.entry((gen, g, stack)).or_default() += time.saturating_sub(start);
+        }
+    }
+
+    fn string(&self, gen: u64, id: u64) -> &str {
+        match self.strings.get(&(gen, id)) {
+            Some(string) if !string.is_empty() => string,
+            _ => UNKNOWN_FRAME,
+        }
+    }
+
+    fn start_function(&self, g: u64) -> Option<&str> {
+        let created = self.created.get(&g)?;
+        let start = self.stacks.get(&(created.gen, created.start_stack))?;
+        Some(self.string(created.gen, *start.first()?))
+    }
+
+    // Adds the frames of a stack, outermost first.
+    fn push_frames<'a>(&'a self, gen: u64, stack: u64, frames: &mut Vec<&'a str>) {
+        if let Some(funcs) = self.stacks.get(&(gen, stack)) {
+            frames.extend(funcs.iter().rev().map(|&func| self.string(gen, func)));
+        }
+    }
+}
+
+fn nargs(typ: u8) -> io::Result<usize> {
+    match typ {
+        9..=51 if NARGS[usize::from(typ - 9)] > 0 => Ok(NARGS[usize::from(typ - 9)]),
+        _ => invalid_data_error!("Unexpected event of type {}", typ),
+    }
+}
+
+struct TraceReader<'a>(&'a [u8]);
+
+impl<'a> TraceReader<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return invalid_data_error!("Unexpected end of trace");
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn expect(&mut self, typ: u8) -> io::Result<()> {
+        match self.byte()? {
+            t if t == typ => Ok(()),
+            t => invalid_data_error!("Unexpected event of type {} where {} belongs", t, typ),
+        }
+    }
+
+    fn uleb128(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..70).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        invalid_data_error!("Invalid integer in trace")
+    }
+
+    fn skip_event(&mut self, typ: u8) -> io::Result<()> {
+        for _ in 0..nargs(typ)? {
+            self.uleb128()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+//! Reconstruction of approximate stacks from the output of [GNU `gprof`].
+//!
+//! `gprof` does not record call stacks. Its call graph only records, for every function, its own
+//! (`self`) time and the time of the functions it calls (`children`), along with how much of
+//! the time of each of its *direct* callees was spent on its behalf. The folded stacks emitted
+//! here are therefore an approximation: starting from every function that has no recorded
+//! caller, we walk the caller → callee edges and assume that a function's time is distributed
+//! among its callees in the same proportions regardless of which path led to it. Recursive calls
+//! are not followed. Paths are not expanded past a depth of 256 frames, or once they carry less
+//! than a 100,000th of the total time, in which case all of their time is attributed to their
+//! last frame, which keeps the number of paths walked in check for call graphs with many ways
+//! to reach the same function. Sample counts are in microseconds.
+//!
+//! Functions that call each other in a cycle are lumped together by `gprof`, whose times are only
+//! known for the cycle as a whole. They are folded below a `<cycle N as a whole>` frame, which is
+//! where calls into any of the functions of the cycle go.
+//!
+//! The input is the text that `gprof` prints, with the flat profile, the call graph, or both. If
+//! there is no call graph, as with `gprof -p`, or if [`Options::flat`] is set, every function of
+//! the flat profile becomes a single-frame stack weighted by its self time instead, which is
+//! exact.
+//!
+//!   [GNU `gprof`]: https://sourceware.org/binutils/docs/gprof/
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// Paths whose weight falls below this many microseconds, or below this fraction of the total
+// weight, or that are this deep, are not expanded any further.
+const MIN_WEIGHT_US: f64 = 0.5;
+const MIN_WEIGHT_FRACTION: f64 = 1e-5;
+const MAX_DEPTH: usize = 256;
+
+/// `gprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Only use the flat profile, where every function is a single frame weighted by its self
+    /// time, rather than reconstructing approximate stacks from the call graph.
+    ///
+    /// Default is `false`.
+    pub flat: bool,
+}
+
+/// A stack collapser for the output of GNU `gprof`.
+///
+/// The stacks produced are an approximation reconstructed from the call graph; see the
+/// [module-level documentation](self) for details.
+///
+/// To construct one, either use `gprof::Folder::default()` or create an [`Options`] and use
+/// `gprof::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut flat = Vec::new();
+        let mut entries = Vec::new();
+        let mut section = Section::None;
+        let mut entry = Entry::default();
+        // Whether any rows of the current table have been read yet.
+        let mut started = false;
+        for line in reader.lines() {
+            let line = line?;
+            let trimmed = line.trim();
+            match section {
+                Section::None | Section::Done => {
+                    started = false;
+                    if is_flat_header(trimmed) {
+                        section = Section::Flat;
+                    } else if is_call_graph_header(trimmed) {
+                        section = Section::CallGraph;
+                    }
+                    continue;
+                }
+                // Both tables end at the first blank line after their rows.
+                _ if trimmed.is_empty() => {
+                    if started {
+                        section = Section::Done;
+                    }
+                    continue;
+                }
+                Section::Flat => match parse_flat_line(trimmed) {
+                    Some(function) => flat.push(function),
+                    None => {
+                        return invalid_data_error!("Unable to parse flat profile line:\n{}", line)
+                    }
+                },
+                Section::CallGraph if trimmed.chars().all(|c| c == '-') => {
+                    entries.push(std::mem::take(&mut entry));
+                }
+                Section::CallGraph => {
+                    if !entry.add_line(trimmed) {
+                        return invalid_data_error!("Unable to parse call graph line:\n{}", line);
+                    }
+                }
+            }
+            started = true;
+        }
+        if entry.primary.is_some() {
+            entries.push(entry);
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        if !entries.is_empty() && !self.opt.flat {
+            fold_call_graph(&entries, &mut occurrences);
+        } else if !flat.is_empty() {
+            for (name, self_time) in flat {
+                let self_us = (self_time * 1e6).round() as usize;
+                if self_us > 0 {
+                    occurrences.insert_or_add(name.replace(';', ":"), self_us);
+                }
+            }
+        } else if entries.is_empty() {
+            return invalid_data_error!("No gprof flat profile or call graph found in input");
+        } else {
+            warn!("Input has no flat profile to fold");
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the headers of the flat profile or the call graph of `gprof`.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        for line in input.lines() {
+            let line = line.trim();
+            if line == "Flat profile:"
+                || line.starts_with("Call graph (explanation follows)")
+                || is_call_graph_header(line)
+            {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Section {
+    None,
+    Flat,
+    CallGraph,
+    Done,
+}
+
+// The column headers of the flat profile end in a line like:
+//
+//      time   seconds   seconds    calls  ms/call  ms/call  name
+fn is_flat_header(line: &str) -> bool {
+    line.starts_with("time") && line.ends_with("name")
+}
+
+// The call graph starts after a line like:
+//
+//     index % time    self  children    called     name
+fn is_call_graph_header(line: &str) -> bool {
+    line.starts_with("index") && line.contains("time") && line.ends_with("name")
+}
+
+// Splits the leading numeric columns of a line, such as times and call counts like `3`, `1/3`
+// and `1+2`, from the name that follows them, which may have spaces of its own.
+fn split_columns(line: &str) -> (Vec<&str>, &str) {
+    let mut columns = Vec::new();
+    let mut rest = line;
+    while let Some((column, r)) = rest.split_once(char::is_whitespace) {
+        let numeric = column
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '/' | '+'));
+        if !numeric {
+            break;
+        }
+        columns.push(column);
+        rest = r.trim_start();
+    }
+    (columns, rest)
+}
+
+// Parses a line of the flat profile into the name of its function and its self seconds:
+//
+//      40.00      0.30     0.30        3   100.00   100.00  compute
+//       6.67      0.75     0.05                             main
+fn parse_flat_line(line: &str) -> Option<(String, f64)> {
+    let (columns, name) = split_columns(line);
+    if columns.len() < 3 || name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), columns[2].parse().ok()?))
+}
+
+// Splits the `[N]` index off the end of the name of a call graph line.
+fn split_index(name: &str) -> Option<(&str, &str)> {
+    let (name, index) = name.strip_suffix(']')?.rsplit_once('[')?;
+    Some((name.trim_end(), index))
+}
+
+// An entry of the call graph, which is the lines between two lines of dashes: the callers of the
+// function, its primary line, and its callees.
+#[derive(Default)]
+struct Entry {
+    // The index, name, self and children time of the function.
+    primary: Option<(String, String, f64, f64)>,
+    callers: Vec<String>,
+    // The callees by their index, with the self and children time spent in them on behalf of
+    // this function, if any.
+    callees: Vec<(String, Option<(f64, f64)>)>,
+}
+
+impl Entry {
+    fn add_line(&mut self, line: &str) -> bool {
+        // Functions that were called from outside of the profiled code have this as their caller.
+        if line == "<spontaneous>" {
+            return true;
+        }
+        if let Some(rest) = line.strip_prefix('[') {
+            // [2]     40.0    0.10    0.20       1         run [2]
+            let Some((index, rest)) = rest.split_once(']') else {
+                return false;
+            };
+            let (columns, name) = split_columns(rest.trim_start());
+            let (Some((name, _)), [_, self_time, children, ..]) = (split_index(name), &*columns)
+            else {
+                return false;
+            };
+            let (Ok(self_time), Ok(children)) = (self_time.parse(), children.parse()) else {
+                return false;
+            };
+            self.primary = Some((index.to_string(), name.to_string(), self_time, children));
+            return true;
+        }
+
+        //                 0.20    0.00       2/3           compute [3]
+        //                                    2             a <cycle 1> [8]
+        let (columns, name) = split_columns(line);
+        let Some((_, index)) = split_index(name) else {
+            return false;
+        };
+        let times = match &*columns {
+            [self_time, children, _, ..] => match (self_time.parse(), children.parse()) {
+                (Ok(self_time), Ok(children)) => Some((self_time, children)),
+                _ => return false,
+            },
+            _ => None,
+        };
+        if self.primary.is_none() {
+            self.callers.push(index.to_string());
+        } else {
+            self.callees.push((index.to_string(), times));
+        }
+        true
+    }
+}
+
+// A function of the call graph.
+struct Function<'a> {
+    name: &'a str,
+    self_time: f64,
+    total_time: f64,
+    // The cycle the function is a member of, if any.
+    cycle: Option<&'a str>,
+    // `(callee, time spent in callee on behalf of this function)`.
+    callees: Vec<(usize, f64)>,
+    has_callers: bool,
+}
+
+// The number of the cycle of a function that is part of one, as in `a <cycle 1>`.
+fn cycle_member(name: &str) -> Option<&str> {
+    let (_, cycle) = name.strip_suffix('>')?.rsplit_once(" <cycle ")?;
+    Some(cycle)
+}
+
+// The number of the cycle of the entry for a cycle as a whole, as in `<cycle 1 as a whole>`.
+fn cycle_as_a_whole(name: &str) -> Option<&str> {
+    name.strip_prefix("<cycle ")?.strip_suffix(" as a whole>")
+}
+
+fn fold_call_graph(entries: &[Entry], occurrences: &mut Occurrences) {
+    let mut index = HashMap::new();
+    let mut cycles = HashMap::new();
+    let mut functions = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let Some((i, name, self_time, children)) = &entry.primary else {
+            continue;
+        };
+        let cycle = cycle_as_a_whole(name);
+        if let Some(cycle) = cycle {
+            cycles.insert(cycle, functions.len());
+        }
+        index.insert(i.as_str(), functions.len());
+        functions.push(Function {
+            name,
+            // The time of the functions of a cycle is that of its members, below it.
+            self_time: if cycle.is_some() { 0.0 } else { *self_time },
+            total_time: self_time + children,
+            cycle: cycle_member(name),
+            callees: Vec::new(),
+            has_callers: false,
+        });
+    }
+
+    for entry in entries {
+        let Some((i, _, _, _)) = &entry.primary else {
+            continue;
+        };
+        let caller = index[i.as_str()];
+        if entry.callers.iter().any(|c| c != i) {
+            functions[caller].has_callers = true;
+        }
+        for (callee, times) in &entry.callees {
+            let Some(mut callee) = index.get(callee.as_str()).copied() else {
+                warn!("Callee [{}] has no entry of its own", callee);
+                continue;
+            };
+            // Calls into a cycle from outside of it go to the cycle as a whole, and calls within
+            // it have no times.
+            if let Some(cycle) = functions[callee].cycle {
+                if functions[caller].cycle == Some(cycle) {
+                    continue;
+                }
+                if cycle_as_a_whole(functions[caller].name) != Some(cycle) {
+                    callee = cycles.get(cycle).copied().unwrap_or(callee);
+                }
+            }
+            if let Some((self_time, children)) = times {
+                functions[caller]
+                    .callees
+                    .push((callee, self_time + children));
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..functions.len())
+        .filter(|&i| !functions[i].has_callers)
+        .collect();
+    let total_weight: f64 = roots.iter().map(|&i| functions[i].total_time * 1e6).sum();
+    let min_weight = (total_weight * MIN_WEIGHT_FRACTION).max(MIN_WEIGHT_US);
+    let mut path = Vec::new();
+    for root in roots {
+        let weight = functions[root].total_time * 1e6;
+        walk(&functions, root, weight, min_weight, &mut path, occurrences);
+    }
+}
+
+/// Walks the reconstructed call graph from `node`, which is reached with `weight` microseconds
+/// of total time, attributing the matching share of its self time to the current path.
+///
+/// Paths lighter than `min_weight` or deeper than `MAX_DEPTH` are not expanded any further, and
+/// all of their weight is attributed to the current path instead.
+fn walk(
+    functions: &[Function<'_>],
+    node: usize,
+    weight: f64,
+    min_weight: f64,
+    path: &mut Vec<usize>,
+    occurrences: &mut Occurrences,
+) {
+    let function = &functions[node];
+    if function.total_time <= 0.0 {
+        return;
+    }
+    let share = weight / function.total_time;
+
+    path.push(node);
+    let expand = weight >= min_weight && path.len() < MAX_DEPTH;
+    let self_us = if expand {
+        (function.self_time * share).round() as usize
+    } else {
+        weight.round() as usize
+    };
+    if self_us > 0 {
+        let stack = path
+            .iter()
+            .map(|&i| functions[i].name.replace(';', ":"))
+            .collect::<Vec<_>>()
+            .join(";");
+        occurrences.insert_or_add(stack, self_us);
+    }
+    if expand {
+        for &(callee, time) in &function.callees {
+            if path.contains(&callee) {
+                continue;
+            }
+            let weight = time * share;
+            walk(functions, callee, weight, min_weight, path, occurrences);
+        }
+    }
+    path.pop();
+}
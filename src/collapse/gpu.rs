@@ -0,0 +1,291 @@
+//! Stack collapsing for the GPU timings of graphics debuggers such as [RenderDoc] and [PIX].
+//!
+//! The input is the hierarchy of the passes (marker regions) and draws of a frame with the time
+//! the GPU spent on each, exported either as CSV with a header row:
+//!
+//! ```text
+//! EID,Name,Depth,Duration (µs)
+//! 1,Frame,0,
+//! 2,Shadow Pass,1,1200.5
+//! 3,DrawIndexed(36),2,400.25
+//! ```
+//!
+//! or as JSON, with the events nested in the `children` of their parents:
+//!
+//! ```text
+//! [{"name":"Frame","children":[{"name":"Shadow Pass","duration_us":1200.5,"children":[
+//!   {"name":"DrawIndexed(36)","duration_us":400.25}]}]}]
+//! ```
+//!
+//! In CSV, events are nested by a `Depth` (or `Level`) column, or, if there's none, by the
+//! indentation of their names. The duration of an event is taken from the first column (or key)
+//! whose name contains `duration`, in the unit that the name ends with: `ns`, `us` (or `µs`),
+//! `ms` or `s`, as in `GPU Duration (ns)` or `durationMs`, or else in microseconds. Events
+//! without a duration, such as marker regions, last as long as their children.
+//!
+//! Every event counts the time the GPU spent on it outside of its children, in nanoseconds, so
+//! that the width of its frame in a flame graph is its whole duration.
+//!
+//!   [RenderDoc]: https://renderdoc.org
+//!   [PIX]: https://devblogs.microsoft.com/pix/
+
+use std::io;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::{split_csv, Occurrences};
+use crate::collapse::Collapse;
+
+const DURATION: &str = "duration";
+const CHILDREN_FIELD: &str = "\"children\"";
+
+/// `gpu` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Name calls without their arguments, as in `DrawIndexed` for `DrawIndexed(36)`, so that
+    /// the calls of the same function add up.
+    ///
+    /// Default is `false`.
+    pub strip_arguments: bool,
+}
+
+/// A stack collapser for GPU timings of passes and draws.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `gpu::Folder::default()` or create an [`Options`] and use
+/// `gpu::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// A pass or draw, with its duration in nanoseconds if it has one.
+struct Event {
+    name: String,
+    duration: Option<f64>,
+    children: Vec<Event>,
+}
+
+// Where the values are in the rows of a CSV export, and the unit of its durations in nanoseconds.
+struct Columns {
+    name: usize,
+    depth: Option<usize>,
+    duration: usize,
+    unit: f64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let first = data.iter().find(|b| !b.is_ascii_whitespace());
+        let events = if matches!(first, Some(b'{' | b'[')) {
+            let mut events = Vec::new();
+            for value in serde_json::Deserializer::from_slice(&data).into_iter::<Value>() {
+                self.on_value(&value?, &mut events);
+            }
+            events
+        } else {
+            self.read_csv(&String::from_utf8_lossy(&data))?
+        };
+        if events.is_empty() {
+            return invalid_data_error!("No GPU events found in input");
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        let mut stack = Vec::new();
+        for event in &events {
+            fold(event, &mut stack, &mut occurrences);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a CSV header with a name and a duration, or for JSON events with children.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.starts_with(['{', '[']) {
+            let lowercase = input.to_ascii_lowercase();
+            return (lowercase.contains(CHILDREN_FIELD) && lowercase.contains(DURATION))
+                .then_some(true);
+        }
+        let header = input.lines().next()?;
+        Some(header_columns(&split_csv(header)).is_some())
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn read_csv(&self, input: &str) -> io::Result<Vec<Event>> {
+        let mut lines = input.lines().filter(|line| !line.trim().is_empty());
+        let Some(header) = lines.next() else {
+            return Ok(Vec::new());
+        };
+        let Some(columns) = header_columns(&split_csv(header)) else {
+            return invalid_data_error!(
+                "Expected a header row with a name and a duration column, got: {}",
+                header
+            );
+        };
+
+        // The events that haven't ended yet, with their depth, outermost first.
+        let mut open: Vec<(usize, Event)> = Vec::new();
+        let mut events = Vec::new();
+        let mut ignored = 0;
+        for line in lines {
+            let values = split_csv(line);
+            let value = |i: usize| values.get(i).map_or("", |(v, _)| v.as_str());
+            let name = value(columns.name);
+            let depth = match columns.depth {
+                Some(depth) => value(depth).trim().parse().ok(),
+                None => Some(name.len() - name.trim_start().len()),
+            };
+            let Some(depth) = depth.filter(|_| !name.trim().is_empty()) else {
+                ignored += 1;
+                continue;
+            };
+            let duration = value(columns.duration).trim().parse::<f64>().ok();
+
+            while open.last().is_some_and(|&(d, _)| d >= depth) {
+                close(&mut open, &mut events);
+            }
+            open.push((
+                depth,
+                Event {
+                    name: self.frame(name),
+                    duration: duration.map(|d| d * columns.unit),
+                    children: Vec::new(),
+                },
+            ));
+        }
+        while !open.is_empty() {
+            close(&mut open, &mut events);
+        }
+        if ignored > 0 {
+            warn!("Ignored {} rows with invalid format", ignored);
+        }
+        Ok(events)
+    }
+
+    // Adds the events of a JSON value, which is an event, an array of them, or an object that
+    // holds them.
+    fn on_value(&self, value: &Value, events: &mut Vec<Event>) {
+        match value {
+            Value::Array(values) => {
+                for value in values {
+                    self.on_value(value, events);
+                }
+            }
+            Value::Object(object) => match object.get("name").and_then(Value::as_str) {
+                Some(name) => {
+                    let mut children = Vec::new();
+                    if let Some(values) = object.get("children") {
+                        self.on_value(values, &mut children);
+                    }
+                    let duration = object.iter().find_map(|(key, value)| {
+                        let unit = duration_unit(key)?;
+                        Some(value.as_f64()? * unit)
+                    });
+                    events.push(Event {
+                        name: self.frame(name),
+                        duration,
+                        children,
+                    });
+                }
+                None => {
+                    for value in object.values().filter(|v| v.is_array() || v.is_object()) {
+                        self.on_value(value, events);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn frame(&self, name: &str) -> String {
+        let name = name.trim();
+        let name = match name.find('(') {
+            Some(i) if self.opt.strip_arguments && i > 0 && name.ends_with(')') => &name[..i],
+            _ => name,
+        };
+        name.trim_end().replace(';', ":")
+    }
+}
+
+// Ends the innermost open event, which becomes a child of the one it's in.
+fn close(open: &mut Vec<(usize, Event)>, events: &mut Vec<Event>) {
+    let (_, event) = open.pop().unwrap();
+    match open.last_mut() {
+        Some((_, parent)) => parent.children.push(event),
+        None => events.push(event),
+    }
+}
+
+// Adds the time spent on an event outside of its children to its stack, and returns its duration.
+fn fold(event: &Event, stack: &mut Vec<String>, occurrences: &mut Occurrences) -> f64 {
+    stack.push(event.name.clone());
+    let children: f64 = event
+        .children
+        .iter()
+        .map(|child| fold(child, stack, occurrences))
+        .sum();
+    let duration = event.duration.unwrap_or(children);
+    let own = (duration - children).round();
+    if own >= 1.0 {
+        occurrences.insert_or_add(stack.join(";"), own as usize);
+    }
+    stack.pop();
+    duration
+}
+
+// Returns the columns of a CSV header row, or `None` if it has no name or no duration column.
+fn header_columns(values: &[(String, bool)]) -> Option<Columns> {
+    let names: Vec<String> = values
+        .iter()
+        .map(|(name, _)| name.trim().to_lowercase())
+        .collect();
+    let column = |name: &str| names.iter().position(|n| n == name);
+
+    let name = column("name").or_else(|| column("event"))?;
+    let depth = column("depth").or_else(|| column("level"));
+    let (duration, unit) = names
+        .iter()
+        .enumerate()
+        .find_map(|(i, name)| Some((i, duration_unit(name)?)))?;
+    Some(Columns {
+        name,
+        depth,
+        duration,
+        unit,
+    })
+}
+
+// Returns how many nanoseconds the unit of a duration column or key is, or `None` if it isn't
+// one, as in `GPU Duration (µs)`, `duration_ns` or `durationMs`.
+fn duration_unit(name: &str) -> Option<f64> {
+    let name = name.to_lowercase();
+    let at = name.find(DURATION)?;
+    let unit: String = name[at + DURATION.len()..]
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .collect();
+    match unit.as_str() {
+        "ns" => Some(1.0),
+        "" | "us" | "µs" | "μs" => Some(1e3),
+        "ms" => Some(1e6),
+        "s" => Some(1e9),
+        _ => None,
+    }
+}
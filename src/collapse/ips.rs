@@ -0,0 +1,249 @@
+//! Stack collapsing for the `.ips` crash and hang reports of Apple platforms.
+//!
+//! An `.ips` report is a line of JSON metadata followed by a JSON body, as written by macOS and
+//! iOS since macOS 12 and iOS 15, and as exported by Xcode. Reports whose body lists the
+//! backtraces of their `threads`, such as crash reports, are folded; others are skipped with a
+//! warning. The input may hold any number of reports one after another, and with
+//! [`Folder::collapse_paths`], whole directories of reports are folded into one dataset.
+//!
+//! Every report counts one sample, for the backtrace of the thread that crashed or hung: the one
+//! marked as `triggered`, or else the first thread, which is the main thread. With
+//! [`Options::all_threads`], every thread of every report counts one sample instead. Frames are
+//! named by their symbols, or by the image they are in and their offset in it if they were not
+//! symbolicated, as in `MyApp+0x1f2c`.
+//!
+//! This shows which backtraces dominate a collection of reports, such as the hang reports of an
+//! app in the field.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+/// `ips` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Count the backtraces of all threads of every report, rather than only that of the thread
+    /// that crashed or hung.
+    ///
+    /// Default is `false`.
+    pub all_threads: bool,
+
+    /// Include the name of the process that a report is about as the root frame of its stacks.
+    ///
+    /// Default is `false`.
+    pub include_process: bool,
+
+    /// Include the image (the binary or library) of every frame, as in
+    /// ``libsystem_kernel.dylib`mach_msg2_trap``.
+    ///
+    /// Default is `false`.
+    pub include_image: bool,
+}
+
+/// A stack collapser for the `.ips` crash and hang reports of Apple platforms.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `ips::Folder::default()` or create an [`Options`] and use
+/// `ips::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// Counts what was folded and skipped over all reports, to warn about at the end.
+#[derive(Default)]
+struct Reports {
+    folded: usize,
+    without_backtrace: usize,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let mut occurrences = Occurrences::new(1);
+        let mut reports = Reports::default();
+        self.fold_reports(&data, &mut occurrences, &mut reports)?;
+        self.finish(occurrences, reports, writer)
+    }
+
+    /// Check for the metadata line that every `.ips` report starts with.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let header = input.trim_start().lines().next()?;
+        let header: Value = match serde_json::from_str(header) {
+            Ok(header) => header,
+            Err(_) => return Some(false),
+        };
+        Some(header.get("bug_type").is_some())
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    /// Collapses the reports in the given files and directories into one dataset, and writes
+    /// its folded stack lines to `writer`.
+    ///
+    /// Directories are read in the order of their file names, without descending into
+    /// subdirectories, and only their `.ips` files are read.
+    pub fn collapse_paths<P, W>(&mut self, paths: &[P], writer: W) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut reports = Reports::default();
+        for path in paths {
+            let path = path.as_ref();
+            if !path.is_dir() {
+                self.fold_reports(&fs::read(path)?, &mut occurrences, &mut reports)?;
+                continue;
+            }
+
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let path = entry?.path();
+                if path.is_file() && path.extension().is_some_and(|ext| ext == "ips") {
+                    files.push(path);
+                }
+            }
+            files.sort();
+            for file in files {
+                if let Err(e) = self.fold_reports(&fs::read(&file)?, &mut occurrences, &mut reports)
+                {
+                    warn!("Skipped the rest of {}: {}", file.display(), e);
+                }
+            }
+        }
+        self.finish(occurrences, reports, writer)
+    }
+
+    fn finish<W: io::Write>(
+        &self,
+        mut occurrences: Occurrences,
+        reports: Reports,
+        writer: W,
+    ) -> io::Result<()> {
+        if reports.without_backtrace > 0 {
+            warn!(
+                "Skipped {} reports without thread backtraces",
+                reports.without_backtrace
+            );
+        }
+        if reports.folded == 0 {
+            return invalid_data_error!("No reports with thread backtraces found in input");
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    // Folds the reports of one input, each of which is a line of metadata followed by a body.
+    fn fold_reports(
+        &self,
+        data: &[u8],
+        occurrences: &mut Occurrences,
+        reports: &mut Reports,
+    ) -> io::Result<()> {
+        let mut header: Option<Value> = None;
+        for value in serde_json::Deserializer::from_slice(data).into_iter::<Value>() {
+            let value = value?;
+            if value.get("bug_type").is_some() && value.get("threads").is_none() {
+                if header.take().is_some() {
+                    // the previous report had no body we could read
+                    reports.without_backtrace += 1;
+                }
+                header = Some(value);
+                continue;
+            }
+            let header = header.take().unwrap_or(Value::Null);
+            if self.fold_report(&header, &value, occurrences) {
+                reports.folded += 1;
+            } else {
+                reports.without_backtrace += 1;
+            }
+        }
+        if header.is_some() {
+            reports.without_backtrace += 1;
+        }
+        Ok(())
+    }
+
+    // Adds the backtraces of a report to the folded stacks. Returns false if it has none.
+    fn fold_report(&self, header: &Value, body: &Value, occurrences: &mut Occurrences) -> bool {
+        let Some(threads) = body["threads"].as_array().filter(|t| !t.is_empty()) else {
+            return false;
+        };
+        let images = body["usedImages"].as_array().map_or(&[][..], Vec::as_slice);
+        let process = body["procName"]
+            .as_str()
+            .or_else(|| header["app_name"].as_str())
+            .or_else(|| header["name"].as_str())
+            .unwrap_or("[unknown]");
+
+        let threads: Vec<&Value> = if self.opt.all_threads {
+            threads.iter().collect()
+        } else {
+            let thread = threads
+                .iter()
+                .find(|thread| thread["triggered"].as_bool() == Some(true))
+                .unwrap_or(&threads[0]);
+            vec![thread]
+        };
+
+        let mut folded = false;
+        for thread in threads {
+            let frames = thread["frames"].as_array().map_or(&[][..], Vec::as_slice);
+            if frames.is_empty() {
+                continue;
+            }
+            let mut stack = String::new();
+            if self.opt.include_process {
+                stack.push_str(&process.replace(';', ":"));
+                stack.push(';');
+            }
+            for frame in frames.iter().rev() {
+                stack.push_str(&self.frame_name(frame, images).replace(';', ":"));
+                stack.push(';');
+            }
+            stack.pop();
+            occurrences.insert_or_add(stack, 1);
+            folded = true;
+        }
+        folded
+    }
+
+    // Names a frame by its symbol, or else by its image and offset.
+    fn frame_name(&self, frame: &Value, images: &[Value]) -> String {
+        let image = frame["imageIndex"]
+            .as_u64()
+            .and_then(|i| images.get(i as usize))
+            .and_then(|image| image["name"].as_str());
+        match (frame["symbol"].as_str(), image) {
+            (Some(symbol), Some(image)) if self.opt.include_image => {
+                format!("{}`{}", image, symbol)
+            }
+            (Some(symbol), _) => symbol.to_string(),
+            (None, Some(image)) => {
+                let offset = frame["imageOffset"].as_u64().unwrap_or(0);
+                format!("{}+0x{:x}", image, offset)
+            }
+            (None, None) => "[unknown]".to_string(),
+        }
+    }
+}
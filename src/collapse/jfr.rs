@@ -0,0 +1,349 @@
+//! Stack collapsing for the allocation events of [Java Flight Recorder] recordings.
+//!
+//! The input is what `jfr print` makes of a recording, either as text:
+//!
+//! ```text
+//! jdk.ObjectAllocationSample {
+//!   startTime = 14:43:02.384 (2023-03-01)
+//!   objectClass = byte[] (classLoader = bootstrap)
+//!   weight = 2.5 MB
+//!   eventThread = "main" (javaThreadId = 1)
+//!   stackTrace = [
+//!     java.util.Arrays.copyOf(byte[], int) line: 3537
+//!     com.example.Parser.parse(String) line: 42
+//!   ]
+//! }
+//! ```
+//!
+//! or as JSON, with `jfr print --json`. Only the `jdk.ObjectAllocationSample`,
+//! `jdk.ObjectAllocationInNewTLAB` and `jdk.ObjectAllocationOutsideTLAB` events are collapsed;
+//! execution samples and all other events are skipped.
+//!
+//! Every event counts the bytes it stands for, with the class of the allocated objects as the
+//! leaf frame of its stack: the estimated `weight` of an allocation sample, the `tlabSize` of an
+//! allocation in a new TLAB (thread-local allocation buffer), or the `allocationSize` of an
+//! allocation outside of one. Since the allocation samples and the TLAB events describe the same
+//! allocations, only the allocation samples are collapsed when a recording has both.
+//!
+//! `jfr print` only prints the top 5 frames of every stack unless given a larger
+//! `--stack-depth`.
+//!
+//!   [Java Flight Recorder]: https://docs.oracle.com/en/java/java-components/jdk-mission-control/
+
+use std::io;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const ALLOCATION_SAMPLE: &str = "jdk.ObjectAllocationSample";
+const ALLOCATION_IN_NEW_TLAB: &str = "jdk.ObjectAllocationInNewTLAB";
+const ALLOCATION_OUTSIDE_TLAB: &str = "jdk.ObjectAllocationOutsideTLAB";
+const ALLOCATION_EVENT_PREFIX: &str = "jdk.ObjectAllocation";
+
+/// `jfr` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Include the name of the thread that allocated as the root frame of every stack.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+}
+
+/// A stack collapser for the allocation events of Java Flight Recorder recordings.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `jfr::Folder::default()` or create an [`Options`] and use
+/// `jfr::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// An allocation event, with its frames innermost first.
+#[derive(Default)]
+struct Event {
+    kind: String,
+    class: Option<String>,
+    bytes: Option<u64>,
+    thread: Option<String>,
+    frames: Vec<String>,
+    truncated: bool,
+}
+
+// The bytes allocated by each stack, kept apart for allocation samples and for TLAB events.
+struct Allocations {
+    samples: Occurrences,
+    nsamples: usize,
+    tlabs: Occurrences,
+    ntlabs: usize,
+    truncated: usize,
+    invalid: usize,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let input = String::from_utf8_lossy(&data);
+
+        let mut allocations = Allocations {
+            samples: Occurrences::new(1),
+            nsamples: 0,
+            tlabs: Occurrences::new(1),
+            ntlabs: 0,
+            truncated: 0,
+            invalid: 0,
+        };
+        if input.trim_start().starts_with('{') {
+            let value: Value = serde_json::from_str(&input)?;
+            let events = value
+                .pointer("/recording/events")
+                .and_then(Value::as_array)
+                .map_or(&[][..], Vec::as_slice);
+            for event in events {
+                self.on_event(json_event(event), &mut allocations);
+            }
+        } else {
+            for event in TextEvents::new(&input) {
+                self.on_event(event, &mut allocations);
+            }
+        }
+
+        if allocations.invalid > 0 {
+            warn!(
+                "Ignored {} allocation events without a stack, class or size",
+                allocations.invalid
+            );
+        }
+        if allocations.truncated > 0 {
+            warn!(
+                "{} stacks were truncated; give `jfr print` a larger --stack-depth to keep \
+                 them whole",
+                allocations.truncated
+            );
+        }
+        if allocations.nsamples > 0 {
+            if allocations.ntlabs > 0 {
+                warn!(
+                    "Skipped {} TLAB allocation events, which the allocation samples already \
+                     account for",
+                    allocations.ntlabs
+                );
+            }
+            allocations.samples.write_and_clear(writer)
+        } else if allocations.ntlabs > 0 {
+            allocations.tlabs.write_and_clear(writer)
+        } else {
+            invalid_data_error!("No allocation events found in input")
+        }
+    }
+
+    /// Check for allocation events printed by `jfr print`.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.starts_with('{') {
+            return input
+                .contains(&format!("\"{}", ALLOCATION_EVENT_PREFIX))
+                .then_some(true);
+        }
+        let header = input.lines().next()?;
+        Some(header.starts_with(ALLOCATION_EVENT_PREFIX) && header.trim_end().ends_with('{'))
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn on_event(&self, event: Event, allocations: &mut Allocations) {
+        let (occurrences, count) = match event.kind.as_str() {
+            ALLOCATION_SAMPLE => (&mut allocations.samples, &mut allocations.nsamples),
+            ALLOCATION_IN_NEW_TLAB | ALLOCATION_OUTSIDE_TLAB => {
+                (&mut allocations.tlabs, &mut allocations.ntlabs)
+            }
+            _ => return,
+        };
+        let (Some(class), Some(bytes)) = (event.class, event.bytes) else {
+            allocations.invalid += 1;
+            return;
+        };
+        if event.frames.is_empty() {
+            allocations.invalid += 1;
+            return;
+        }
+        *count += 1;
+        if event.truncated {
+            allocations.truncated += 1;
+        }
+        if bytes == 0 {
+            return;
+        }
+
+        let mut stack = String::new();
+        if self.opt.include_thread {
+            let thread = event.thread.as_deref().unwrap_or("[unknown]");
+            stack.push_str(&thread.replace(';', ":"));
+            stack.push(';');
+        }
+        for frame in event.frames.iter().rev() {
+            stack.push_str(&frame.replace(';', ":"));
+            stack.push(';');
+        }
+        stack.push_str(&class.replace(';', ":"));
+        occurrences.insert_or_add(stack, bytes as usize);
+    }
+}
+
+// Parses the events of the text output of `jfr print`.
+struct TextEvents<'a> {
+    lines: std::str::Lines<'a>,
+}
+
+impl<'a> TextEvents<'a> {
+    fn new(input: &'a str) -> Self {
+        TextEvents {
+            lines: input.lines(),
+        }
+    }
+}
+
+impl Iterator for TextEvents<'_> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        // Find the start of the next event, as in `jdk.ObjectAllocationSample {`.
+        let kind = loop {
+            let line = self.lines.next()?;
+            if let Some(kind) = line.strip_suffix('{') {
+                if !line.starts_with(char::is_whitespace) {
+                    break kind.trim();
+                }
+            }
+        };
+
+        let mut event = Event {
+            kind: kind.to_string(),
+            ..Event::default()
+        };
+        let mut in_stack = false;
+        for line in self.lines.by_ref() {
+            let line = line.trim();
+            if in_stack {
+                match line {
+                    "]" => in_stack = false,
+                    "..." => event.truncated = true,
+                    _ => event.frames.push(frame_name(line)),
+                }
+                continue;
+            }
+            if line == "}" {
+                break;
+            }
+            let Some((key, value)) = line.split_once(" = ") else {
+                continue;
+            };
+            match key {
+                "stackTrace" => in_stack = value == "[",
+                "objectClass" => event.class = Some(class_name(value)),
+                "eventThread" => event.thread = Some(thread_name(value)),
+                "weight" if event.kind == ALLOCATION_SAMPLE => event.bytes = parse_bytes(value),
+                "tlabSize" if event.kind == ALLOCATION_IN_NEW_TLAB => {
+                    event.bytes = parse_bytes(value)
+                }
+                "allocationSize" if event.kind == ALLOCATION_OUTSIDE_TLAB => {
+                    event.bytes = parse_bytes(value)
+                }
+                _ => {}
+            }
+        }
+        Some(event)
+    }
+}
+
+// Reads an event of the JSON output of `jfr print --json`.
+fn json_event(event: &Value) -> Event {
+    let kind = event
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let values = &event["values"];
+    let size_field = match kind {
+        ALLOCATION_SAMPLE => "weight",
+        ALLOCATION_IN_NEW_TLAB => "tlabSize",
+        _ => "allocationSize",
+    };
+    let stack = &values["stackTrace"];
+    let frames = stack["frames"].as_array().map_or(&[][..], Vec::as_slice);
+    Event {
+        kind: kind.to_string(),
+        class: values["objectClass"]["name"]
+            .as_str()
+            .map(|name| name.replace('/', ".")),
+        bytes: values[size_field].as_u64(),
+        thread: values["eventThread"]["javaName"]
+            .as_str()
+            .or_else(|| values["eventThread"]["osName"].as_str())
+            .map(str::to_string),
+        frames: frames
+            .iter()
+            .map(|frame| {
+                let method = &frame["method"];
+                let class = method["type"]["name"].as_str().unwrap_or("[unknown]");
+                let name = method["name"].as_str().unwrap_or("[unknown]");
+                format!("{}.{}", class.replace('/', "."), name)
+            })
+            .collect(),
+        truncated: stack["truncated"].as_bool().unwrap_or(false),
+    }
+}
+
+// Returns the name of the method of a frame such as `java.util.Arrays.copyOf(byte[], int) line: 3537`.
+fn frame_name(frame: &str) -> String {
+    let end = frame
+        .find('(')
+        .unwrap_or_else(|| frame.find(" line:").unwrap_or(frame.len()));
+    frame[..end].trim().to_string()
+}
+
+// Returns the name of a class such as `java.lang.String (classLoader = bootstrap)`.
+fn class_name(class: &str) -> String {
+    let end = class.find(" (").unwrap_or(class.len());
+    class[..end].trim().replace('/', ".")
+}
+
+// Returns the name of a thread such as `"main" (javaThreadId = 1)`.
+fn thread_name(thread: &str) -> String {
+    match thread.strip_prefix('"').and_then(|t| t.rsplit_once('"')) {
+        Some((name, _)) => name.to_string(),
+        None => class_name(thread),
+    }
+}
+
+// Parses a size as `jfr print` formats it, such as `1 byte`, `24 bytes` or `2.5 MB`.
+fn parse_bytes(size: &str) -> Option<u64> {
+    let (number, unit) = size.trim().split_once(' ').unwrap_or((size, "bytes"));
+    let number: f64 = number.parse().ok()?;
+    let exponent = match unit {
+        "byte" | "bytes" => 0,
+        "kB" => 1,
+        "MB" => 2,
+        "GB" => 3,
+        "TB" => 4,
+        "PB" => 5,
+        "EB" => 6,
+        _ => return None,
+    };
+    Some((number * 1024f64.powi(exponent)).round() as u64)
+}
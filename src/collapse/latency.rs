@@ -0,0 +1,200 @@
+//! Stack collapsing for latency traces, as drawn as heatmaps by [trace2heatmap.pl].
+//!
+//! The input has a line for every traced event, with the time of the event and its latency,
+//! separated by whitespace or commas, and optionally an id of the stack or code path of the event,
+//! such as from `bpftrace` or a request log:
+//!
+//! ```text
+//! # time latency stack
+//! 1690000000.000120 250 read
+//! 1690000000.000410 1790 read
+//! 1690000000.001200 40 write
+//! ```
+//!
+//! Every event is folded into a synthetic frame for the band of latencies it falls in, below the
+//! frame of its stack id, if it has one, as in `read;0256-0512 us`. Bands double in size by
+//! default, or are all as wide as [`Options::band_width`]. Their bounds are padded with zeros so
+//! that they are drawn in order, from the lowest latencies to the highest, and every count is the
+//! number of events in a band, or the latency they add up to with [`Options::weighted`].
+//!
+//!   [trace2heatmap.pl]: https://github.com/brendangregg/HeatMap
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+/// `latency` folder configuration options.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    /// The unit of the latencies, which is only shown in the frames of their bands.
+    ///
+    /// Default is `us`.
+    pub unit: String,
+
+    /// Make all bands this wide, in the unit of the latencies, rather than doubling their
+    /// widths from one band to the next.
+    ///
+    /// Default is `None`.
+    pub band_width: Option<u64>,
+
+    /// Count the latency of the events in every band, rather than how many there are, to show
+    /// where the time goes rather than how latencies are distributed.
+    ///
+    /// Default is `false`.
+    pub weighted: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            unit: "us".to_string(),
+            band_width: None,
+            weighted: false,
+        }
+    }
+}
+
+/// A stack collapser for latency traces, with a frame for the band of every latency.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `latency::Folder::default()` or create an [`Options`] and use
+/// `latency::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        if self.opt.band_width == Some(0) {
+            return invalid_data_error!("Latency bands must be wider than 0");
+        }
+
+        // The count of every band of every stack, by stack and band.
+        let mut counts: HashMap<(String, u32), usize> = HashMap::new();
+        let mut max_band = 0;
+        let mut invalid = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((latency, stack)) = parse_line(line) else {
+                invalid += 1;
+                continue;
+            };
+            let band = self.band(latency);
+            max_band = max_band.max(band);
+            let count = if self.opt.weighted {
+                latency.round() as usize
+            } else {
+                1
+            };
+            let stack = stack.map(|s| s.replace(';', ":")).unwrap_or_default();
+            *counts.entry((stack, band)).or_default() += count;
+        }
+
+        if invalid > 0 {
+            warn!("Ignored {} lines without a valid time and latency", invalid);
+        }
+
+        let width = self.bounds(max_band).1.to_string().len();
+        let mut occurrences = Occurrences::new(1);
+        for ((mut stack, band), count) in counts {
+            if count == 0 {
+                continue;
+            }
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            let (low, high) = self.bounds(band);
+            stack.push_str(&format!(
+                "{:0width$}-{:0width$} {}",
+                low, high, self.opt.unit
+            ));
+            occurrences.insert_or_add(stack, count);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for lines of a time and a latency, and maybe a stack id.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let line = input
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+        Some(parse_line(line).is_some())
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // The band of a latency: the multiple of the band width it is in, or, for bands that double
+    // in width, 0 for latencies below 1, 1 for those below 2, 2 for those below 4, and so on.
+    fn band(&self, latency: f64) -> u32 {
+        match self.opt.band_width {
+            Some(width) => (latency / width as f64).min(u32::MAX as f64) as u32,
+            None if latency < 1.0 => 0,
+            None => (latency.log2().floor() as u32).saturating_add(1).min(64),
+        }
+    }
+
+    // The lowest latency of a band, and the lowest of the band after it.
+    fn bounds(&self, band: u32) -> (u64, u64) {
+        match self.opt.band_width {
+            Some(width) => {
+                let low = u64::from(band).saturating_mul(width);
+                (low, low.saturating_add(width))
+            }
+            None if band == 0 => (0, 1),
+            None => (
+                1u64 << (band - 1),
+                1u64.checked_shl(band).unwrap_or(u64::MAX),
+            ),
+        }
+    }
+}
+
+// Splits a line into its latency and its stack id, if it has one, checking that it starts with
+// a time.
+fn parse_line(line: &str) -> Option<(f64, Option<&str>)> {
+    let (time, rest) = next_field(line)?;
+    time.parse::<f64>().ok()?;
+    let (latency, rest) = next_field(rest)?;
+    let latency: f64 = latency.parse().ok()?;
+    if latency < 0.0 || !latency.is_finite() {
+        return None;
+    }
+    let stack = rest.trim_start_matches(is_separator).trim_end();
+    Some((latency, Some(stack).filter(|s| !s.is_empty())))
+}
+
+// Splits the first field off a line, skipping the separators before it.
+fn next_field(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start_matches(is_separator);
+    let end = line.find(is_separator).unwrap_or(line.len());
+    if end == 0 {
+        return None;
+    }
+    Some(line.split_at(end))
+}
+
+fn is_separator(c: char) -> bool {
+    c == ',' || c.is_whitespace()
+}
@@ -21,6 +21,9 @@ pub mod perf;
 /// Internal string match helper functions for perf
 pub(crate) mod matcher;
 
+/// Internal symbol tables for naming the raw addresses of perf offline
+pub(crate) mod symbols;
+
 /// Stack collapsing for the output of [`sample`](https://gist.github.com/loderunner/36724cc9ee8db66db305#profiling-with-sample) on macOS.
 ///
 /// See the [crate-level documentation] for details.
@@ -74,6 +77,228 @@ pub mod xctrace;
 ///   [crate-level documentation]: ../../index.html
 pub mod ghcprof;
 
+/// Stack collapsing for the output of Python's [`cProfile`](https://docs.python.org/3/library/profile.html).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod cprofile;
+
+/// Stack collapsing for stacks exported from Windows [PerfView](https://github.com/microsoft/perfview).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod perfview;
+
+/// Stack collapsing for stack traces in kernel logs, such as the output of `dmesg`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod dmesg;
+
+/// Stack collapsing for scheduler events from [`perf sched timehist`](https://man7.org/linux/man-pages/man1/perf-sched.1.html) or `perf script`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod perf_sched;
+
+/// Stack collapsing for MySQL and MariaDB [performance_schema](https://dev.mysql.com/doc/refman/8.0/en/performance-schema.html) events.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod mysql;
+
+/// Stack collapsing for samples of PHP programs taken with [phpspy](https://github.com/adsr/phpspy) or [Excimer](https://www.mediawiki.org/wiki/Excimer).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod php;
+
+/// Stack collapsing for the span close events of Rust programs instrumented with [tracing](https://docs.rs/tracing).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod tracing;
+
+/// Stack collapsing for call stacks exported as CSV from [AMD uProf](https://www.amd.com/en/developer/uprof.html).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod uprof;
+
+/// Stack collapsing for stack dumps of samples taken with Intel SEP.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod sep;
+
+/// Stack collapsing for the CPU profiles of [Dart](https://dart.dev) and Flutter programs, from the Dart VM service or DevTools.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod dart;
+
+/// Stack collapsing for the multi-line output of the `profile` and `offcputime` tools of [bcc](https://github.com/iovisor/bcc).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod bcc;
+
+/// Stack collapsing for the guest profiles of WebAssembly programs run with [wasmtime](https://wasmtime.dev).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod wasm;
+
+/// Stack collapsing for the CPU samples of NVIDIA [Nsight Systems](https://developer.nvidia.com/nsight-systems) profiles.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod nsys;
+
+/// Stack collapsing for Go execution traces, as written by [`runtime/trace`](https://pkg.go.dev/runtime/trace).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod go_trace;
+
+/// Stack collapsing for the function call traces of [LLVM XRay](https://llvm.org/docs/XRay.html).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod xray;
+
+/// Stack collapsing for the intervals of [os_signpost](https://developer.apple.com/documentation/os/logging/recording_performance_data) on macOS and iOS, from `log show` or Instruments.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod signpost;
+
+/// Stack collapsing for the GPU timings of passes and draws exported from graphics debuggers such as [RenderDoc](https://renderdoc.org) and [PIX](https://devblogs.microsoft.com/pix/).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod gpu;
+
+/// Stack collapsing for [pprof](https://github.com/google/pprof) profiles, such as the dumps stored by continuous profilers like [Parca](https://www.parca.dev).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "gzip")]
+pub mod pprof;
+
+/// Stack collapsing for the allocation events of [Java Flight Recorder](https://docs.oracle.com/en/java/java-components/jdk-mission-control/) recordings, as printed by `jfr print`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod jfr;
+
+/// Stack collapsing for the profiles exported by the Profiler of [React DevTools](https://react.dev/learn/react-developer-tools), weighted by render time.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod react;
+
+/// Stack collapsing for the `.ips` crash and hang reports of Apple platforms, counting one sample per report.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod ips;
+
+/// Stack collapsing for the query profiles in the `system.trace_log` table of [ClickHouse](https://clickhouse.com), as exported with `FORMAT TabSeparatedWithNames`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod clickhouse;
+
+/// Stack collapsing for the captures of the [Unity Profiler](https://docs.unity3d.com/Manual/Profiler.html), as exported to JSON from the Unity Editor.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod unity;
+
+/// Stack collapsing for the timing events of [Unreal Insights](https://dev.epicgames.com/documentation/en-us/unreal-engine/unreal-insights-in-unreal-engine) traces, as exported to CSV.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod unreal;
+
+/// Stack collapsing for the collapsed output of [async-profiler](https://github.com/async-profiler/async-profiler) with event type and thread state frames, such as that of `jfrconv`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod async_profiler;
+
+/// Stack collapsing for the boot-time data of [systemd-analyze](https://www.freedesktop.org/software/systemd/man/latest/systemd-analyze.html), as drawn by `systemd-analyze plot` and printed by `systemd-analyze dump`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod systemd;
+
+/// Stack collapsing for `perf script` output into time buckets, as for [FlameScope](https://github.com/Netflix/flamescope).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod flamescope;
+
+/// Stack collapsing for latency traces, as drawn as heatmaps by [trace2heatmap.pl](https://github.com/brendangregg/HeatMap), into bands of latencies.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod latency;
+
+/// Stack collapsing for the span trees of distributed traces from [Jaeger](https://www.jaegertracing.io), [Zipkin](https://zipkin.io) and [OpenTelemetry](https://opentelemetry.io), weighted by the self time of every span.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+#[cfg(feature = "json")]
+pub mod spans;
+
+/// Stack collapsing for the output of [GNU `gprof`](https://sourceware.org/binutils/docs/gprof/), with approximate stacks reconstructed from its call graph.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod gprof;
+
 // DEFAULT_NTHREADS is public because we use it in the help text of the binaries,
 // but it doesn't need to be exposed to library users, hence #[doc(hidden)].
 #[doc(hidden)]
@@ -160,6 +385,28 @@ where
         <Self as CollapsePrivate>::collapse(self, reader, writer)
     }
 
+    fn collapse_file<P, W>(&mut self, infile: Option<P>, writer: W) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        W: io::Write,
+    {
+        let Some(path) = infile else {
+            let stdin = io::stdin();
+            let stdin_guard = stdin.lock();
+            let reader = io::BufReader::with_capacity(CAPACITY_READER, stdin_guard);
+            return self.collapse(reader, writer);
+        };
+        let file = File::open(path)?;
+        // The size of the input is only known for files, which may be too small to be worth
+        // collapsing on several threads.
+        let nthreads = self.nthreads();
+        self.set_nthreads(self.nthreads_for_len(file.metadata()?.len()));
+        let reader = io::BufReader::with_capacity(CAPACITY_READER, file);
+        let result = self.collapse(reader, writer);
+        self.set_nthreads(nthreads);
+        result
+    }
+
     fn is_applicable(&mut self, input: &str) -> Option<bool> {
         <Self as CollapsePrivate>::is_applicable(self, input)
     }
@@ -0,0 +1,307 @@
+//! Stack collapsing for MySQL and MariaDB [performance_schema] events.
+//!
+//! The input is a table of events exported with a header row, either tab-separated (as printed by
+//! `mysql --batch`) or comma-separated. Every row becomes a stack of up to four frames:
+//!
+//! ```text
+//! user;db;statement;event
+//! ```
+//!
+//! weighted by the time the event waited, in nanoseconds. Each frame comes from the first of
+//! these columns that the table has, and levels for which the table has none of the columns are
+//! left out:
+//!
+//!  - user: `USER`, `PROCESSLIST_USER`;
+//!  - db: `CURRENT_SCHEMA`, `SCHEMA_NAME`, `PROCESSLIST_DB`, `OBJECT_SCHEMA`, `DB`;
+//!  - statement: `DIGEST_TEXT`, `SQL_TEXT`, `DIGEST`;
+//!  - event: `EVENT_NAME`, such as `wait/io/table/sql/handler` or `statement/sql/select`.
+//!
+//! The weight is `TIMER_WAIT`, or `SUM_TIMER_WAIT` for summary tables, which performance_schema
+//! gives in picoseconds. So `events_waits_history_long` joined with `threads` (for the user and
+//! db) and `events_statements_history_long` (for the statement) breaks down what each query
+//! waited on, while `events_statements_summary_by_digest` alone shows where statement time went:
+//!
+//! ```sql
+//! SELECT t.PROCESSLIST_USER, s.CURRENT_SCHEMA, s.DIGEST_TEXT, w.EVENT_NAME, w.TIMER_WAIT
+//! FROM performance_schema.events_waits_history_long w
+//! JOIN performance_schema.threads t USING (THREAD_ID)
+//! LEFT JOIN performance_schema.events_statements_history_long s
+//!   ON s.THREAD_ID = w.THREAD_ID AND s.EVENT_ID = w.NESTING_EVENT_ID;
+//! ```
+//!
+//!   [performance_schema]: https://dev.mysql.com/doc/refman/8.0/en/performance-schema.html
+
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::{split_csv, Occurrences};
+use crate::collapse::Collapse;
+
+const USER_COLUMNS: &[&str] = &["USER", "PROCESSLIST_USER"];
+const DB_COLUMNS: &[&str] = &[
+    "CURRENT_SCHEMA",
+    "SCHEMA_NAME",
+    "PROCESSLIST_DB",
+    "OBJECT_SCHEMA",
+    "DB",
+];
+const STATEMENT_COLUMNS: &[&str] = &["DIGEST_TEXT", "SQL_TEXT", "DIGEST"];
+const EVENT_COLUMNS: &[&str] = &["EVENT_NAME"];
+const TIMER_COLUMNS: &[&str] = &["TIMER_WAIT", "SUM_TIMER_WAIT"];
+
+// Timer waits are in picoseconds, and counts in nanoseconds.
+const PICOSECONDS_PER_COUNT: u64 = 1_000;
+
+/// `mysql` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Only keep this many of the `/`-separated parts of event names, so that, say, with `3`
+    /// the waits on `wait/io/file/innodb/innodb_data_file` and `wait/io/file/innodb/innodb_log_file`
+    /// are both counted as `wait/io/file`.
+    ///
+    /// Default is `None`, which keeps whole event names.
+    pub event_name_depth: Option<usize>,
+
+    /// Cut statements longer than this many characters short.
+    ///
+    /// Default is `None`, which keeps whole statements.
+    pub statement_width: Option<usize>,
+}
+
+/// A stack collapser for exported performance_schema events.
+///
+/// See the [module-level documentation](self) for the supported tables.
+///
+/// To construct one, either use `mysql::Folder::default()` or create an [`Options`] and use
+/// `mysql::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// Where the frames and the weight of a stack are in each row.
+struct Columns {
+    frames: Vec<usize>,
+    statement: Option<usize>,
+    event: usize,
+    timer: usize,
+    tabs: bool,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut columns = None;
+        let mut ignored = 0;
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end_matches(['\r', '\n']);
+            if l.trim().is_empty() {
+                continue;
+            }
+
+            let Some(columns) = &columns else {
+                columns = Some(Columns::from_header(l)?);
+                continue;
+            };
+            if !self.on_row(columns, l, &mut occurrences) {
+                ignored += 1;
+            }
+        }
+        if ignored > 0 {
+            warn!("Ignored {} rows with invalid format", ignored);
+        }
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a header row with an event name and a timer wait.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(Columns::from_header(line.trim_end()).is_ok());
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Columns {
+    fn from_header(header: &str) -> io::Result<Self> {
+        let tabs = header.contains('\t');
+        let names: Vec<String> = split_row(header, tabs)
+            .into_iter()
+            .map(|name| name.unwrap_or_default().trim().to_ascii_uppercase())
+            .collect();
+        let find = |candidates: &[&str]| {
+            candidates
+                .iter()
+                .find_map(|c| names.iter().position(|name| name == c))
+        };
+
+        let (Some(event), Some(timer)) = (find(EVENT_COLUMNS), find(TIMER_COLUMNS)) else {
+            return invalid_data_error!(
+                "Header has no EVENT_NAME and TIMER_WAIT (or SUM_TIMER_WAIT) columns: {}",
+                header
+            );
+        };
+        let statement = find(STATEMENT_COLUMNS);
+        let frames = [find(USER_COLUMNS), find(DB_COLUMNS), statement, Some(event)]
+            .into_iter()
+            .flatten()
+            .collect();
+        Ok(Columns {
+            frames,
+            statement,
+            event,
+            timer,
+            tabs,
+        })
+    }
+}
+
+impl Folder {
+    // Returns whether the row could be parsed.
+    fn on_row(&self, columns: &Columns, row: &str, occurrences: &mut Occurrences) -> bool {
+        let values = split_row(row, columns.tabs);
+        let Some(timer) = values.get(columns.timer) else {
+            return false;
+        };
+        // Events that are still in progress haven't been timed yet.
+        let Some(timer) = timer else {
+            return true;
+        };
+        let Ok(picoseconds) = timer.trim().parse::<u64>() else {
+            return false;
+        };
+        let count = (picoseconds + PICOSECONDS_PER_COUNT / 2) / PICOSECONDS_PER_COUNT;
+        if count == 0 {
+            return true;
+        }
+
+        let mut stack = String::new();
+        for &i in &columns.frames {
+            let Some(value) = values.get(i) else {
+                return false;
+            };
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            let value = value.as_deref().map(str::trim).filter(|v| !v.is_empty());
+            match value {
+                None => stack.push_str("[unknown]"),
+                Some(value) if Some(i) == columns.statement => {
+                    stack.push_str(&self.statement(value));
+                }
+                Some(value) if i == columns.event => {
+                    stack.push_str(&self.event_name(value).replace(';', ":"));
+                }
+                Some(value) => stack.push_str(&value.replace(';', ":")),
+            }
+        }
+        occurrences.insert_or_add(stack, count as usize);
+        true
+    }
+
+    fn statement(&self, statement: &str) -> String {
+        // Statements often span several lines.
+        let statement = statement
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace(';', ":");
+        match self.opt.statement_width {
+            Some(width) if statement.chars().count() > width => {
+                let mut short: String = statement.chars().take(width.saturating_sub(3)).collect();
+                short.push_str("...");
+                short
+            }
+            _ => statement,
+        }
+    }
+
+    fn event_name<'a>(&self, event: &'a str) -> &'a str {
+        match self.opt.event_name_depth {
+            Some(depth) => match event.match_indices('/').nth(depth.saturating_sub(1)) {
+                Some((i, _)) if depth > 0 => &event[..i],
+                _ => event,
+            },
+            None => event,
+        }
+    }
+}
+
+// Splits a row into its values, where `None` is SQL's `NULL`.
+//
+// Tab-separated rows are escaped like `mysql --batch` does, and comma-separated ones like CSV,
+// with `NULL` or `\N` for `NULL`. Quoted CSV values can't span lines.
+fn split_row(row: &str, tabs: bool) -> Vec<Option<String>> {
+    let values = if tabs {
+        row.split('\t').map(unescape_batch).collect()
+    } else {
+        split_csv(row)
+    };
+    values
+        .into_iter()
+        .map(|(value, quoted)| {
+            if !quoted && (value == "NULL" || value == "\\N") {
+                None
+            } else {
+                Some(value)
+            }
+        })
+        .collect()
+}
+
+fn unescape_batch(value: &str) -> (String, bool) {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('0') => unescaped.push('\0'),
+            Some('\\') => unescaped.push('\\'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    (unescaped, false)
+}
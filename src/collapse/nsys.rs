@@ -0,0 +1,317 @@
+//! Stack collapsing for the CPU samples of NVIDIA [Nsight Systems] profiles.
+//!
+//! The input is CSV queried from the SQLite export of a report (`nsys export --type sqlite`),
+//! with a table of the frames of the callchains of the samples, innermost (`stackDepth` 0) first,
+//! optionally followed by a table of the NVTX ranges. Each table starts with a header row, as
+//! `sqlite3 -csv -header` prints for each query:
+//!
+//! ```text
+//! sqlite3 -csv -header report.sqlite "
+//!   SELECT ce.start, ce.globalTid, sc.stackDepth, sy.value AS symbol, mo.value AS module
+//!   FROM COMPOSITE_EVENTS ce
+//!   JOIN SAMPLING_CALLCHAINS sc ON sc.id = ce.id
+//!   LEFT JOIN StringIds sy ON sy.id = sc.symbol
+//!   LEFT JOIN StringIds mo ON mo.id = sc.module
+//!   ORDER BY ce.start, ce.globalTid, sc.stackDepth;
+//!   SELECT n.start, n.end, n.globalTid, COALESCE(n.text, s.value) AS text
+//!   FROM NVTX_EVENTS n LEFT JOIN StringIds s ON s.id = n.textId
+//!   WHERE n.end IS NOT NULL;"
+//! ```
+//!
+//! which looks like:
+//!
+//! ```text
+//! start,globalTid,stackDepth,symbol,module
+//! 1000,281749854618457,0,cudaLaunchKernel,/usr/lib/libcudart.so.12
+//! 1000,281749854618457,1,train_step,/app/train
+//! start,end,globalTid,text
+//! 900,5000,281749854618457,epoch
+//! ```
+//!
+//! Every sample counts once. With [`Options::nvtx`], the NVTX ranges that were open on the thread
+//! of a sample when it was taken become frames below its callchain, outermost first, so that
+//! samples are grouped by the phases of the program that its developers marked.
+//!
+//!   [Nsight Systems]: https://developer.nvidia.com/nsight-systems
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::{split_csv, Occurrences};
+use crate::collapse::Collapse;
+
+const STACK_DEPTH_COLUMN: &str = "stackdepth";
+
+/// `nsys` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Nest samples in frames for the NVTX ranges they were taken in.
+    ///
+    /// Default is `false`.
+    pub nvtx: bool,
+
+    /// Start every stack with a frame for its thread, as `<pid>/<tid>`.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+
+    /// Prefix function names with the file name of their module, as in ``libc.so.6`memcpy``.
+    ///
+    /// Default is `false`.
+    pub include_module: bool,
+}
+
+/// A stack collapser for the CPU samples of Nsight Systems profiles.
+///
+/// See the [module-level documentation](self) for the supported format.
+///
+/// To construct one, either use `nsys::Folder::default()` or create an [`Options`] and use
+/// `nsys::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// Where the values are in the rows of each of the tables.
+enum Columns {
+    Samples(SampleColumns),
+    Ranges(RangeColumns),
+}
+
+struct SampleColumns {
+    start: usize,
+    thread: usize,
+    depth: usize,
+    symbol: usize,
+    module: Option<usize>,
+}
+
+struct RangeColumns {
+    start: usize,
+    end: usize,
+    thread: usize,
+    text: usize,
+}
+
+struct Sample {
+    start: u64,
+    thread: u64,
+    // Frames, with their depth, innermost first once sorted.
+    frames: Vec<(u64, String)>,
+}
+
+struct Range {
+    start: u64,
+    end: u64,
+    text: String,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut samples: Vec<Sample> = Vec::new();
+        let mut ranges: HashMap<u64, Vec<Range>> = HashMap::new();
+        let mut columns = None;
+        let mut ignored = 0;
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end_matches(['\r', '\n']);
+            if l.trim().is_empty() {
+                continue;
+            }
+
+            let values = split_csv(l);
+            if let Some(header) = header_columns(&values) {
+                columns = Some(header);
+                continue;
+            }
+            let parsed = match &columns {
+                Some(Columns::Samples(columns)) => {
+                    self.on_sample_row(columns, &values, &mut samples)
+                }
+                Some(Columns::Ranges(columns)) => on_range_row(columns, &values, &mut ranges),
+                None => return invalid_data_error!("No header row before: {}", l),
+            };
+            if !parsed {
+                ignored += 1;
+            }
+        }
+        if ignored > 0 {
+            warn!("Ignored {} rows with invalid format", ignored);
+        }
+        if samples.is_empty() {
+            warn!("No CPU samples found in input");
+        }
+
+        // Outer ranges first, which start first, or end last if they start together.
+        for ranges in ranges.values_mut() {
+            ranges.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        for mut sample in samples {
+            let mut stack: Vec<&str> = Vec::new();
+            // Global thread ids hold the PID and TID in their lower bytes.
+            let thread = self.opt.include_thread.then(|| {
+                format!(
+                    "{}/{}",
+                    (sample.thread >> 24) & 0xFF_FFFF,
+                    sample.thread & 0xFF_FFFF
+                )
+            });
+            stack.extend(thread.as_deref());
+            if self.opt.nvtx {
+                let ranges = ranges.get(&sample.thread).map_or(&[][..], Vec::as_slice);
+                let started = ranges.partition_point(|range| range.start <= sample.start);
+                stack.extend(
+                    ranges[..started]
+                        .iter()
+                        .filter(|range| range.end > sample.start)
+                        .map(|range| range.text.as_str()),
+                );
+            }
+            sample.frames.sort_by_key(|&(depth, _)| depth);
+            stack.extend(sample.frames.iter().rev().map(|(_, frame)| frame.as_str()));
+            occurrences.insert_or_add(stack.join(";"), 1);
+        }
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the header row of a table of samples.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            if let Some(Columns::Samples(_)) = header_columns(&split_csv(line.trim_end())) {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Adds a frame to the callchain of its sample, and returns whether the row could be parsed.
+    fn on_sample_row(
+        &self,
+        columns: &SampleColumns,
+        values: &[(String, bool)],
+        samples: &mut Vec<Sample>,
+    ) -> bool {
+        let (Some(start), Some(thread), Some(depth)) = (
+            number(values, columns.start),
+            number(values, columns.thread),
+            number(values, columns.depth),
+        ) else {
+            return false;
+        };
+        let value = |i: Option<usize>| i.and_then(|i| values.get(i)).map_or("", |(v, _)| v);
+        let frame = self.frame(value(Some(columns.symbol)), value(columns.module));
+
+        match samples.last_mut() {
+            Some(last) if last.start == start && last.thread == thread => {
+                last.frames.push((depth, frame));
+            }
+            _ => samples.push(Sample {
+                start,
+                thread,
+                frames: vec![(depth, frame)],
+            }),
+        }
+        true
+    }
+
+    // Names a frame after its function, or its module if the function isn't known.
+    fn frame(&self, symbol: &str, module: &str) -> String {
+        let module = module.rsplit(['/', '\\']).next().unwrap_or_default();
+        let unknown = symbol.is_empty() || symbol.starts_with("0x");
+        let frame = match (unknown, module.is_empty()) {
+            (true, true) => "[unknown]".to_string(),
+            (true, false) => format!("[{}]", module),
+            (false, false) if self.opt.include_module => format!("{}`{}", module, symbol),
+            (false, _) => symbol.to_string(),
+        };
+        frame.replace(';', ":")
+    }
+}
+
+// Returns the columns of a header row, or `None` for any other row.
+fn header_columns(values: &[(String, bool)]) -> Option<Columns> {
+    let names: Vec<String> = values
+        .iter()
+        .map(|(name, _)| name.trim().to_ascii_lowercase())
+        .collect();
+    let column = |name: &str| names.iter().position(|n| n == name);
+
+    let start = column("start")?;
+    let thread = column("globaltid")?;
+    if let Some(depth) = column(STACK_DEPTH_COLUMN) {
+        return Some(Columns::Samples(SampleColumns {
+            start,
+            thread,
+            depth,
+            symbol: column("symbol")?,
+            module: column("module"),
+        }));
+    }
+    Some(Columns::Ranges(RangeColumns {
+        start,
+        end: column("end")?,
+        thread,
+        text: column("text")?,
+    }))
+}
+
+// Adds an NVTX range to those of its thread, and returns whether the row could be parsed.
+fn on_range_row(
+    columns: &RangeColumns,
+    values: &[(String, bool)],
+    ranges: &mut HashMap<u64, Vec<Range>>,
+) -> bool {
+    let (Some(start), Some(end), Some(thread), Some((text, _))) = (
+        number(values, columns.start),
+        number(values, columns.end),
+        number(values, columns.thread),
+        values.get(columns.text),
+    ) else {
+        return false;
+    };
+    ranges.entry(thread).or_default().push(Range {
+        start,
+        end,
+        text: text.replace(';', ":"),
+    });
+    true
+}
+
+fn number(values: &[(String, bool)], i: usize) -> Option<u64> {
+    values.get(i)?.0.trim().parse().ok()
+}
@@ -1,12 +1,34 @@
+use std::borrow::Cow;
 use std::collections::VecDeque;
 use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use crate::collapse::common::{self, CollapsePrivate, Occurrences};
 use crate::collapse::matcher::is_kernel;
+use crate::collapse::symbols::Symbols;
 
 const TIDY_GENERIC: bool = true;
 const TIDY_JAVA: bool = true;
 
+// Files smaller than this are collapsed on a single thread by default.
+const DEFAULT_MULTITHREADED_MIN_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Entry points into the OCaml runtime's garbage collector and signal/effect polling. Frames
+/// called from these are folded into them when `Options::ocaml` is set.
+const OCAML_RUNTIME_ENTRIES: &[&str] = &[
+    "caml_call_gc",
+    "caml_call_realloc_stack",
+    "caml_alloc_small_dispatch",
+    "caml_garbage_collection",
+    "caml_poll",
+    "caml_process_pending_actions",
+    "caml_do_pending_actions",
+    "caml_major_collection_slice",
+    "caml_minor_collection",
+];
+
 mod logging {
     use log::{info, warn};
 
@@ -21,6 +43,37 @@ mod logging {
     pub(super) fn weird_stack_line(line: &str) {
         warn!("Weird stack line: {}", line);
     }
+
+    pub(super) fn missing_data_source(line: &str) {
+        warn!("No data source found on event line: {}", line);
+    }
+}
+
+/// How to weigh the samples of a `perf mem` / `perf c2c` profile when folding them by data
+/// source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemWeight {
+    /// Each sample counts as one load (or its period, if `perf script` printed one).
+    Loads,
+    /// Each sample counts as its access latency in cycles (the `weight` field).
+    Latency,
+}
+
+impl MemWeight {
+    /// The valid set of weights (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["loads", "latency"];
+}
+
+impl FromStr for MemWeight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "loads" => Ok(MemWeight::Loads),
+            "latency" => Ok(MemWeight::Latency),
+            _ => Err(format!("unknown memory weight: {}", s)),
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -66,11 +119,75 @@ pub struct Options {
     /// Default is `false`.
     pub include_tid: bool,
 
+    /// Fold `perf mem` / `perf c2c` samples by the memory hierarchy level that served them.
+    ///
+    /// When set, the `data_src` field of each event line (as printed by `perf script -F
+    /// ...,data_src,weight`) is added to the stack as a synthetic leaf frame such as `[mem L1]`,
+    /// `[mem LFB]`, `[mem RAM]` or `[mem Remote RAM]`, and samples are weighed as given.
+    ///
+    /// Default is `None`.
+    pub mem_data_source: Option<MemWeight>,
+
+    /// Split `perf kvm` profiles (recorded with `perf kvm --guest --host record`) into guest and
+    /// host samples.
+    ///
+    /// When set, each stack gets a synthetic `[guest]` or `[host]` root frame. Guest stacks are
+    /// further grouped by `[vm <pid>]` and `[vcpu <n>]` frames when `perf kvm script` prints the
+    /// `machine_pid` and `vcpu` fields, and frames from guest kernel modules (like
+    /// `[guest.kernel.kallsyms]`) are annotated with a `_[g]` suffix.
+    ///
+    /// Default is `false`.
+    pub kvm: bool,
+
+    /// Escape semicolons in function names, such as those of Rust array types like `[u8; 8]`, as
+    /// `\;` rather than replacing them with colons, so that the names are shown as they are.
+    ///
+    /// Tools other than inferno may not understand the escaped semicolons.
+    ///
+    /// Default is `false`.
+    pub escape_semicolons: bool,
+
+    /// Demangle OCaml symbols (e.g., `camlStdlib__List__map_279` becomes `Stdlib.List.map`), and
+    /// fold everything called from the OCaml runtime's GC and polling entry points (like
+    /// `caml_call_gc`) into a single frame for that entry point, annotated with a `_[k]` suffix.
+    ///
+    /// Default is `false`.
+    pub ocaml: bool,
+
+    /// A copy of `/proc/kallsyms` from the profiled machine, to name the kernel addresses that
+    /// `perf script` left as `[unknown]` or without a symbol, as when it ran on another machine
+    /// or couldn't read the kernel's symbols, which is common on RISC-V and ARM boards.
+    ///
+    /// Default is `None`.
+    pub kallsyms: Option<PathBuf>,
+
+    /// Symbol maps in the format of the `/tmp/perf-PID.map` files of perf-map-agent and JIT
+    /// compilers, with a `START SIZE name` line in hexadecimal per symbol, to name the user-space
+    /// addresses that `perf script` left as `[unknown]` or without a symbol. Maps named
+    /// `perf-PID.map` only name the addresses of process PID, if `perf script` printed PIDs.
+    ///
+    /// Default is none.
+    pub symbol_maps: Vec<PathBuf>,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
     pub nthreads: usize,
 
+    /// Collapse input files smaller than this many bytes on a single thread rather than on
+    /// [`nthreads`](Self::nthreads), as handing their stacks out to other threads takes about as
+    /// long as collapsing them. Larger files are split into jobs at event boundaries, which are
+    /// collapsed across the threads and merged at the end.
+    ///
+    /// The size of the input is only known to [`Collapse::collapse_file`]; input given to
+    /// [`Collapse::collapse`] always uses `nthreads`.
+    ///
+    /// Default is 16 MiB. Set it to 0 to always use `nthreads`.
+    ///
+    ///   [`Collapse::collapse_file`]: crate::collapse::Collapse::collapse_file
+    ///   [`Collapse::collapse`]: crate::collapse::Collapse::collapse
+    pub multithreaded_min_bytes: u64,
+
     /// If a stack function name is equal to any of the specified strings it will omit all the
     /// following stackframes for that event.
     /// In case no function is matched the whole stack is returned.
@@ -83,12 +200,19 @@ impl Default for Options {
         Self {
             annotate_jit: false,
             annotate_kernel: false,
+            escape_semicolons: false,
             event_filter: None,
             include_addrs: false,
             include_pid: false,
             include_tid: false,
+            kallsyms: None,
+            kvm: false,
+            mem_data_source: None,
             nthreads: *common::DEFAULT_NTHREADS,
+            multithreaded_min_bytes: DEFAULT_MULTITHREADED_MIN_BYTES,
+            ocaml: false,
             skip_after: Vec::default(),
+            symbol_maps: Vec::default(),
         }
     }
 }
@@ -133,6 +257,21 @@ pub struct Folder {
     /// period of current event
     period: Option<usize>,
 
+    /// memory hierarchy level that served the current event, if folding by data source
+    data_source: Option<String>,
+
+    /// `[vm <pid>];[vcpu <n>]` frames of the current event, if it was sampled in a guest
+    machine: Option<String>,
+
+    /// whether the current event has frames from a guest kernel
+    in_guest: bool,
+
+    /// PID of the current event, or `?` if it wasn't printed, if naming addresses offline
+    pid: String,
+
+    /// Symbols to name addresses with, once loaded from the files given in the options
+    symbols: Option<Arc<Symbols>>,
+
     // Options...
     opt: Options,
 }
@@ -152,6 +291,11 @@ impl From<Options> for Folder {
             stack_filter: StackFilter::Keep,
             stack: VecDeque::default(),
             period: None,
+            data_source: None,
+            machine: None,
+            in_guest: false,
+            pid: String::default(),
+            symbols: None,
             opt,
         }
     }
@@ -168,6 +312,13 @@ impl CollapsePrivate for Folder {
     where
         R: io::BufRead,
     {
+        if self.symbols.is_none()
+            && (self.opt.kallsyms.is_some() || !self.opt.symbol_maps.is_empty())
+        {
+            let symbols = Symbols::from_files(self.opt.kallsyms.as_deref(), &self.opt.symbol_maps)?;
+            self.symbols = Some(Arc::new(symbols));
+        }
+
         // If user has provided an event filter, do nothing...
         if self.event_filter.is_some() {
             return Ok(());
@@ -267,6 +418,11 @@ impl CollapsePrivate for Folder {
             stack_filter: StackFilter::Keep,
             stack: VecDeque::default(),
             period: None,
+            data_source: None,
+            machine: None,
+            in_guest: false,
+            pid: String::new(),
+            symbols: self.symbols.clone(),
             opt: self.opt.clone(),
         }
     }
@@ -286,6 +442,14 @@ impl CollapsePrivate for Folder {
     fn set_nthreads(&mut self, n: usize) {
         self.opt.nthreads = n;
     }
+
+    fn nthreads_for_len(&self, len: u64) -> usize {
+        if len < self.opt.multithreaded_min_bytes {
+            1
+        } else {
+            self.opt.nthreads
+        }
+    }
 }
 
 impl Folder {
@@ -375,9 +539,19 @@ impl Folder {
     //     V8 WorkerThread 24636/25607 [000] 94564.109216: cycles:
     //     vote   913    72.176760:     257597 cycles:uppp:
     //     false 64414 20110.539270:      34467 cycles:u:  ffffffff9aa3c8de [unknown] ([unknown])
-    fn on_event_line(&mut self, line: &str) {
+    //
+    // with `perf kvm script -F +machine_pid,+vcpu`, guest events are prefixed by the VM and vCPU:
+    //
+    //     VM: 4021 VCPU:001 qemu-system-x86  4021/4035 [003] 1234.567890: cycles:
+    fn on_event_line(&mut self, mut line: &str) {
         self.in_event = true;
 
+        if self.opt.kvm {
+            let (machine, rest) = machine_parts(line);
+            self.machine = machine;
+            line = rest;
+        }
+
         if let Some((comm, pid, tid, end)) = Self::event_line_parts(line) {
             let mut by_colons = line[end..].splitn(3, ':').skip(1);
             let event = by_colons.next().and_then(|period_and_event| {
@@ -415,7 +589,23 @@ impl Folder {
                 // comes first, and then evaluate from there.
                 let post_event_start = post_event.find([':', ' ']).map(|i| i + 1).unwrap_or(0);
                 let post_event = post_event[post_event_start..].trim();
-                if !post_event.is_empty() {
+                if let Some(weight) = self.opt.mem_data_source {
+                    // with data sources, the rest of the line holds `data_src` and `weight`:
+                    //
+                    //     5080144 |OP LOAD|LVL L1 hit|SNP None|TLB L1 or L2 hit|LCK No|BLK  N/A       38
+                    //
+                    // followed by the stack if it only has one frame.
+                    if let Some((data_source, latency, stack)) = data_source_parts(post_event) {
+                        self.data_source = Some(data_source);
+                        if weight == MemWeight::Latency {
+                            self.period = latency;
+                        }
+                        Some(stack).filter(|stack| !stack.is_empty())
+                    } else {
+                        logging::missing_data_source(line);
+                        None
+                    }
+                } else if !post_event.is_empty() {
                     // we have a stack!
                     Some(post_event)
                 } else {
@@ -425,6 +615,11 @@ impl Folder {
                 None
             };
 
+            if self.symbols.is_some() {
+                self.pid.clear();
+                self.pid.push_str(pid);
+            }
+
             // XXX: re-use existing memory in pname if possible
             self.pname = comm.replace(' ', "_");
             if self.opt.include_tid {
@@ -492,7 +687,18 @@ impl Folder {
             return;
         }
 
+        let symbols = self.symbols.clone();
         if let Some((pc, mut rawfunc, module)) = Self::stack_line_parts(line) {
+            // Name the addresses that perf couldn't from the symbols we were given
+            if matches!(rawfunc, "[unknown]" | " ") {
+                if let Some(symbol) = symbols
+                    .as_deref()
+                    .and_then(|symbols| symbols.lookup(&self.pid, module, pc))
+                {
+                    rawfunc = symbol;
+                }
+            }
+
             // Strip off symbol offsets
             if let Some(offset) = rawfunc.rfind("+0x") {
                 let end = &rawfunc[(offset + 3)..];
@@ -517,8 +723,20 @@ impl Folder {
             // See https://github.com/brendangregg/FlameGraph/pull/89.
             for func in rawfunc.split("->") {
                 let mut func = with_module_fallback(module, func, pc, self.opt.include_addrs);
+                if self.opt.ocaml {
+                    if let Cow::Owned(demangled) = common::demangle_ocaml_symbol(&func) {
+                        func = demangled;
+                    } else if OCAML_RUNTIME_ENTRIES.contains(&func.as_str()) {
+                        // everything this runtime entry point called is folded into it
+                        self.stack.clear();
+                        self.cache_line.clear();
+                        func.push_str("_[k]");
+                        self.cache_line.push(func);
+                        continue;
+                    }
+                }
                 if TIDY_GENERIC {
-                    func = tidy_generic(func);
+                    func = tidy_generic(func, self.opt.escape_semicolons);
                 }
 
                 if TIDY_JAVA && self.pname.starts_with("java") {
@@ -541,6 +759,9 @@ impl Folder {
                     if !func.contains("_[i]") {
                         func.push_str("_[i]"); // inlined
                     }
+                } else if self.opt.kvm && module.starts_with("[guest.") {
+                    self.in_guest = true;
+                    func.push_str("_[g]"); // guest kernel
                 } else if self.opt.annotate_kernel && is_kernel(module) {
                     func.push_str("_[k]"); // kernel
                 } else if self.opt.annotate_jit
@@ -574,11 +795,27 @@ impl Folder {
     fn after_event(&mut self, occurrences: &mut Occurrences) {
         // end of stack, so emit stack entry
         if !self.stack.is_empty() {
+            if let Some(data_source) = self.data_source.take() {
+                self.stack.push_back(data_source);
+            }
+
             // allocate a string that is long enough to hold the entire stack string
             let mut stack_str = String::with_capacity(
                 self.pname.len() + self.stack.iter().fold(0, |a, s| a + s.len() + 1),
             );
 
+            if self.opt.kvm {
+                if self.machine.is_some() || self.in_guest {
+                    stack_str.push_str("[guest];");
+                } else {
+                    stack_str.push_str("[host];");
+                }
+                if let Some(machine) = self.machine.take() {
+                    stack_str.push_str(&machine);
+                    stack_str.push(';');
+                }
+            }
+
             // If we skip remaining frames we want to skip pname as well.
             if self.stack_filter != StackFilter::SkipRemaining {
                 // add the comm name
@@ -602,7 +839,85 @@ impl Folder {
         self.stack_filter = StackFilter::Keep;
         self.stack.clear();
         self.period = None;
+        self.data_source = None;
+        self.machine = None;
+        self.in_guest = false;
+    }
+}
+
+// split the `VM:` and `VCPU:` fields that `perf kvm script` prints before the comm of guest events
+// off an event line, turning them into `[vm <pid>];[vcpu <n>]` frames.
+fn machine_parts(line: &str) -> (Option<String>, &str) {
+    let field = |line: &'_ str, name: &str| -> Option<(u64, usize)> {
+        let trimmed = line.trim_start();
+        let value = trimmed.strip_prefix(name)?.trim_start();
+        let end = value.find(' ').unwrap_or(value.len());
+        let n = value[..end].parse().ok()?;
+        Some((n, line.len() - value.len() + end))
+    };
+
+    let (vm, rest) = match field(line, "VM:") {
+        Some((vm, end)) => (vm, &line[end..]),
+        None => return (None, line),
+    };
+    let mut machine = format!("[vm {}]", vm);
+    let rest = match field(rest, "VCPU:") {
+        Some((vcpu, end)) => {
+            machine.push_str(&format!(";[vcpu {}]", vcpu));
+            &rest[end..]
+        }
+        None => rest,
+    };
+    (Some(machine), rest.trim_start())
+}
+
+// extract the memory hierarchy level and the latency from the `data_src` and `weight` fields that
+// `perf script` prints for `perf mem` samples, like:
+//
+//     5080144 |OP LOAD|LVL L1 hit|SNP None|TLB L1 or L2 hit|LCK No|BLK  N/A       38
+//     68100142 |OP LOAD|LVL Local RAM or RAM hit|SNP Hit|TLB L1 or L2 hit|LCK No|BLK  N/A      305
+//
+// the level is turned into a synthetic frame such as `[mem L1]` or `[mem Remote RAM]`. whatever
+// follows the weight is returned as well, since it is the stack of samples with only one frame.
+fn data_source_parts(fields: &str) -> Option<(String, Option<usize>, &str)> {
+    let start = fields.find('|')?;
+    let level = fields[start..]
+        .split('|')
+        .find_map(|field| field.strip_prefix("LVL "))?;
+
+    // multiple levels are listed as "A or B hit"; the first one is the most specific
+    let level = level.split(" or ").next()?.trim();
+    let level = level
+        .strip_suffix(" hit")
+        .or_else(|| level.strip_suffix(" miss"))
+        .unwrap_or(level);
+    // drop hop counts like "Remote RAM (1 hop)" and collapse "Local RAM" into "RAM"
+    let level = level.split(" (").next()?.trim();
+    let level = level.strip_prefix("Local ").unwrap_or(level);
+    let level = match level {
+        "LFB/MAB" => "LFB",
+        level => level,
+    };
+
+    // the weight is the first number after the last data source field, which itself may contain
+    // spaces, but no digits
+    let mut rest = &fields[fields.rfind('|')? + 1..];
+    let mut latency = None;
+    while let Some(word_start) = rest.find(|c: char| !c.is_whitespace()) {
+        let word = &rest[word_start..];
+        let word_end = word.find(char::is_whitespace).unwrap_or(word.len());
+        rest = &word[word_end..];
+        if let Ok(weight) = word[..word_end].parse::<usize>() {
+            latency = Some(weight);
+            break;
+        }
     }
+
+    let mut frame = String::with_capacity(level.len() + 6);
+    frame.push_str("[mem ");
+    frame.push_str(&level.replace(';', ":"));
+    frame.push(']');
+    Some((frame, latency, rest.trim()))
 }
 
 // massage function name to be nicer
@@ -641,8 +956,12 @@ fn with_module_fallback(module: &str, func: &str, pc: &str, include_addrs: bool)
     res
 }
 
-fn tidy_generic(mut func: String) -> String {
-    func = func.replace(';', ":");
+fn tidy_generic(mut func: String, escape_semicolons: bool) -> String {
+    func = if escape_semicolons {
+        common::escape_frame(&func)
+    } else {
+        func.replace(';', ":")
+    };
     // remove argument list from function name, but _don't_ remove:
     //
     //  - Go method names like "net/http.(*Client).Do".
@@ -749,7 +1068,7 @@ mod tests {
         ];
 
         for (input, expected) in test_expectations.iter() {
-            assert_eq!(&tidy_generic(input.to_string()), expected);
+            assert_eq!(&tidy_generic(input.to_string(), false), expected);
         }
     }
 
@@ -798,6 +1117,20 @@ mod tests {
         <Folder as Collapse>::collapse(&mut folder, &bytes[..], io::sink())
     }
 
+    #[test]
+    fn test_small_files_single_threaded() {
+        let mut options = Options {
+            nthreads: 4,
+            ..Default::default()
+        };
+        let folder = Folder::from(options.clone());
+        assert_eq!(folder.nthreads_for_len(1024), 1);
+        assert_eq!(folder.nthreads_for_len(DEFAULT_MULTITHREADED_MIN_BYTES), 4);
+
+        options.multithreaded_min_bytes = 0;
+        assert_eq!(Folder::from(options).nthreads_for_len(1024), 4);
+    }
+
     #[test]
     fn test_one_skip_after() -> io::Result<()> {
         let path = "./tests/data/collapse-perf/go-stacks.txt";
@@ -886,12 +1219,19 @@ mod tests {
             let options = Options {
                 annotate_jit: rng.random(),
                 annotate_kernel: rng.random(),
+                escape_semicolons: rng.random(),
                 event_filter: None,
                 include_addrs: rng.random(),
                 include_pid: rng.random(),
                 include_tid: rng.random(),
+                kallsyms: None,
+                kvm: rng.random(),
+                mem_data_source: None,
                 nthreads: rng.random_range(2..=32),
+                multithreaded_min_bytes: 0,
+                ocaml: rng.random(),
                 skip_after: Vec::default(),
+                symbol_maps: Vec::default(),
             };
 
             for (path, input) in inputs.iter() {
@@ -0,0 +1,506 @@
+//! Stack collapsing for Linux scheduler events, as reported by `perf sched timehist` or
+//! `perf script`.
+//!
+//! Instead of counting samples, this collapser measures how long tasks spend off-CPU, and folds
+//! that time by the stack each task was in when it was switched out. This makes for off-CPU and
+//! scheduler latency flame graphs straight from `perf`, without bcc tools like `offcputime`. Two
+//! inputs are understood:
+//!
+//!  - the output of `perf sched timehist -g`, where each line is a task being switched out,
+//!    followed by the stack it was switched out in. The `wait time` (or `sch delay`) column of
+//!    the task's next line is how long it then stayed off-CPU (or waited to run); and
+//!  - the output of `perf script` for a recording of `sched:sched_switch` and
+//!    `sched:sched_wakeup` (or `sched:sched_waking`) events with call graphs, such as
+//!    `perf record -e sched:sched_switch -e sched:sched_wakeup -g -a`. Here the stack of the task
+//!    that woke a blocked task up is known too, so wakeup chains can be folded: the stack of the
+//!    waker is put on top of the stack of the task it woke, separated by a `--` frame, like
+//!    `offwaketime` does.
+//!
+//! The root frame of each stack is the name of its task, and counts are in microseconds. The
+//! idle task is left out.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// The `perf script` events we act on, and what they tell us.
+static SWITCH_EVENTS: &[&str] = &["sched:sched_switch:"];
+static WAKEUP_EVENTS: &[&str] = &[
+    "sched:sched_waking:",
+    "sched:sched_wakeup:",
+    "sched:sched_wakeup_new:",
+];
+
+// Frame that separates the stack of a blocked task from the stack of the task that woke it.
+static WAKER_SEPARATOR: &str = "--";
+
+/// What the counts of the folded stacks measure.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Measure {
+    /// The time tasks spent off-CPU, from being switched out until being switched back in.
+    #[default]
+    OffCpu,
+    /// The scheduler latency of tasks, from being woken up (or preempted) until being switched
+    /// back in.
+    Delay,
+}
+
+impl Measure {
+    /// The valid set of measures (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["off-cpu", "delay"];
+}
+
+impl FromStr for Measure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off-cpu" => Ok(Measure::OffCpu),
+            "delay" => Ok(Measure::Delay),
+            _ => Err(format!("unknown measure: {}", s)),
+        }
+    }
+}
+
+/// `perf sched` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// What the counts of the folded stacks measure.
+    ///
+    /// Default is [`Measure::OffCpu`].
+    pub measure: Measure,
+
+    /// Put the stack of the task that woke a task up on top of the task's off-CPU stack.
+    ///
+    /// This only works for `perf script` input, as `perf sched timehist` doesn't print the
+    /// stacks of wakeups.
+    ///
+    /// Default is `false`.
+    pub wakers: bool,
+
+    /// Include the TID of tasks in their root frame.
+    ///
+    /// Default is `false`.
+    pub include_tid: bool,
+}
+
+/// A stack collapser for scheduler events recorded with `perf`.
+///
+/// See the [module-level documentation](self) for details.
+///
+/// To construct one, either use `perf_sched::Folder::default()` or create an [`Options`] and use
+/// `perf_sched::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Tasks that are off-CPU, by TID.
+    switched_out: HashMap<String, SwitchedOut>,
+
+    /// The `perf script` event whose stack is being read, if it is one we act on.
+    event: Option<SchedEvent>,
+
+    /// Whether we are reading the stack of a `perf script` event.
+    in_event: bool,
+
+    /// Frames of the stack being read, innermost first.
+    stack: Vec<String>,
+
+    /// Number of stacks folded so far.
+    nstacks: usize,
+
+    opt: Options,
+}
+
+#[derive(Clone)]
+struct SwitchedOut {
+    task: String,
+    time: f64,
+    /// Whether the task was still runnable, i.e. preempted rather than blocked.
+    runnable: bool,
+    /// Frames of the stack the task was switched out in, outermost first.
+    stack: Vec<String>,
+    waker: Option<Waker>,
+}
+
+#[derive(Clone)]
+struct Waker {
+    task: String,
+    time: f64,
+    /// Frames of the stack the task was woken from, outermost first.
+    stack: Vec<String>,
+}
+
+#[derive(Clone)]
+enum SchedEvent {
+    Switch {
+        time: f64,
+        prev_task: String,
+        prev_tid: String,
+        prev_runnable: bool,
+        next_tid: String,
+    },
+    Wakeup {
+        time: f64,
+        task: String,
+        tid: String,
+    },
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            self.on_line(l.trim_end(), &mut occurrences);
+        }
+        self.end_event(&mut occurrences);
+
+        if self.nstacks == 0 {
+            warn!("No scheduler events found");
+        }
+        occurrences.write_and_clear(writer)?;
+
+        // Reset the state...
+        self.switched_out.clear();
+        self.nstacks = 0;
+        Ok(())
+    }
+
+    /// Check for `perf script` scheduler events or the header of `perf sched timehist`.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            if line.contains("wait time") && line.contains("sch delay") {
+                return Some(true);
+            }
+            if SWITCH_EVENTS.iter().any(|e| line.contains(e)) {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn on_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        if line.trim().is_empty() {
+            self.end_event(occurrences);
+        } else if self.in_event && is_stack_line(line) {
+            if self.event.is_some() {
+                self.stack.push(stack_line_frame(line));
+            }
+        } else if !self.on_timehist_line(line, occurrences) {
+            self.end_event(occurrences);
+            self.event = parse_event_line(line);
+            self.in_event = true;
+        }
+    }
+
+    // `perf sched timehist -g` prints a line like
+    //
+    //     79371.874569 [0011]  gcc[31949]    0.014    0.000    1.148   do_wait <- kernel_wait4
+    //
+    // every time a task is switched out, where the columns are the time, CPU, task, wait time,
+    // scheduling delay and run time, and the stack is the one the task was switched out in.
+    fn on_timehist_line(&mut self, line: &str, occurrences: &mut Occurrences) -> bool {
+        let mut words = line.split_whitespace();
+        let is_cpu = |w: &str| {
+            w.strip_prefix('[')
+                .and_then(|w| w.strip_suffix(']'))
+                .is_some_and(|w| !w.is_empty() && w.bytes().all(|b| b.is_ascii_digit()))
+        };
+        match (words.next(), words.next()) {
+            (Some(time), Some(cpu)) if time.parse::<f64>().is_ok() && is_cpu(cpu) => {}
+            _ => return false,
+        }
+
+        // The task name can contain spaces, so it ends where the three time columns start.
+        let words: Vec<&str> = words.collect();
+        let is_msecs = |w: &&str| w.parse::<f64>().is_ok();
+        let Some(start) = (1..words.len().saturating_sub(2))
+            .find(|&i| words[i..i + 3].iter().all(is_msecs) && words[i - 1].ends_with(']'))
+        else {
+            // Lines for wakeups (`-w`) and migrations (`-M`) don't have a stack.
+            return true;
+        };
+        let task = words[..start].join(" ");
+        let Some((comm, tid)) = timehist_task(&task) else {
+            return true;
+        };
+        if tid == "0" {
+            return true;
+        }
+
+        let column = match self.opt.measure {
+            Measure::OffCpu => start,
+            Measure::Delay => start + 1,
+        };
+        let msecs: f64 = words[column].parse().unwrap_or_default();
+
+        // `--state` adds a column with the state the task was switched out in.
+        let mut rest = &words[start + 3..];
+        if rest.first().is_some_and(|w| w.len() == 1) {
+            rest = &rest[1..];
+        }
+        let mut stack: Vec<String> = rest
+            .join(" ")
+            .split("<-")
+            .map(|f| f.trim().replace(';', ":"))
+            .filter(|f| !f.is_empty())
+            .collect();
+        stack.reverse();
+
+        // The time in this line is what the task spent off-CPU since it was last switched out.
+        let task = self.task_frame(comm, tid);
+        if let Some(out) = self.switched_out.remove(tid) {
+            let usecs = (msecs * 1000.0).round();
+            if usecs >= 1.0 {
+                occurrences.insert_or_add(self.folded(&out, None), usecs as usize);
+                self.nstacks += 1;
+            }
+        }
+        self.switched_out.insert(
+            tid.to_string(),
+            SwitchedOut {
+                task,
+                time: 0.0,
+                runnable: false,
+                stack,
+                waker: None,
+            },
+        );
+        true
+    }
+
+    fn end_event(&mut self, occurrences: &mut Occurrences) {
+        self.stack.reverse();
+        let stack = std::mem::take(&mut self.stack);
+        match self.event.take() {
+            Some(SchedEvent::Switch {
+                time,
+                prev_task,
+                prev_tid,
+                prev_runnable,
+                next_tid,
+            }) => {
+                if let Some(out) = self.switched_out.remove(&next_tid) {
+                    self.on_switch_in(out, time, occurrences);
+                }
+                if prev_tid != "0" {
+                    let task = self.task_frame(&prev_task, &prev_tid);
+                    self.switched_out.insert(
+                        prev_tid,
+                        SwitchedOut {
+                            task,
+                            time,
+                            runnable: prev_runnable,
+                            stack,
+                            waker: None,
+                        },
+                    );
+                }
+            }
+            Some(SchedEvent::Wakeup { time, task, tid }) => {
+                // The waker is the task the event happened in, whose stack we just read.
+                if let Some(out) = self.switched_out.get_mut(&tid) {
+                    if out.waker.is_none() {
+                        out.waker = Some(Waker { task, time, stack });
+                    }
+                }
+            }
+            None => {}
+        }
+        self.in_event = false;
+    }
+
+    fn on_switch_in(&mut self, out: SwitchedOut, time: f64, occurrences: &mut Occurrences) {
+        let since = match self.opt.measure {
+            Measure::OffCpu => Some(out.time),
+            Measure::Delay => match &out.waker {
+                Some(waker) => Some(waker.time),
+                None if out.runnable => Some(out.time),
+                None => None,
+            },
+        };
+        let Some(since) = since else {
+            return;
+        };
+        let usecs = ((time - since) * 1_000_000.0).round();
+        if usecs >= 1.0 {
+            let waker = out.waker.as_ref().filter(|_| self.opt.wakers);
+            occurrences.insert_or_add(self.folded(&out, waker), usecs as usize);
+            self.nstacks += 1;
+        }
+    }
+
+    fn task_frame(&self, comm: &str, tid: &str) -> String {
+        let comm = comm.replace(';', ":");
+        if self.opt.include_tid {
+            format!("{}-{}", comm, tid)
+        } else {
+            comm
+        }
+    }
+
+    fn folded(&self, out: &SwitchedOut, waker: Option<&Waker>) -> String {
+        let mut frames = vec![out.task.as_str()];
+        frames.extend(out.stack.iter().map(String::as_str));
+        if let Some(waker) = waker {
+            frames.push(WAKER_SEPARATOR);
+            frames.extend(waker.stack.iter().rev().map(String::as_str));
+            frames.push(&waker.task);
+        }
+        frames.join(";")
+    }
+}
+
+/// Splits a `perf sched timehist` task like `gcc[31949]` or `gcc[31949/31948]` into its name and
+/// TID.
+fn timehist_task(task: &str) -> Option<(&str, &str)> {
+    let (comm, ids) = task.strip_suffix(']')?.rsplit_once('[')?;
+    Some((comm, ids.split('/').next()?))
+}
+
+// A `perf script` event line looks like
+//
+//     gcc 31949/31949 [011] 79371.874569: sched:sched_switch: prev_comm=gcc prev_pid=31949 ...
+//
+// and tells us the task and time the event happened in, as well as its fields.
+fn parse_event_line(line: &str) -> Option<SchedEvent> {
+    let find = |events: &[&str]| {
+        events
+            .iter()
+            .find_map(|e| line.find(e).map(|i| (&line[..i], &line[i + e.len()..])))
+    };
+
+    if let Some((header, fields)) = find(SWITCH_EVENTS) {
+        // prev_comm=gcc prev_pid=31949 prev_prio=120 prev_state=S ==> next_comm=swapper/11 ...
+        let time = header_time(header)?;
+        let prev_task = field(fields, "prev_comm", Some("prev_pid"))?.to_string();
+        let prev_tid = field(fields, "prev_pid", None)?.to_string();
+        let prev_runnable = field(fields, "prev_state", None)?.starts_with('R');
+        let next_tid = field(fields, "next_pid", None)?.to_string();
+        Some(SchedEvent::Switch {
+            time,
+            prev_task,
+            prev_tid,
+            prev_runnable,
+            next_tid,
+        })
+    } else if let Some((header, fields)) = find(WAKEUP_EVENTS) {
+        // comm=gcc pid=31949 prio=120 target_cpu=011
+        let time = header_time(header)?;
+        let task = header_task(header)?.to_string();
+        let tid = field(fields, "pid", None)?.to_string();
+        Some(SchedEvent::Wakeup { time, task, tid })
+    } else {
+        None
+    }
+}
+
+/// The time at the end of an event header like `gcc 31949/31949 [011] 79371.874569: `.
+fn header_time(header: &str) -> Option<f64> {
+    header
+        .split_whitespace()
+        .last()?
+        .trim_end_matches(':')
+        .parse()
+        .ok()
+}
+
+/// The name of the task at the start of an event header like `gcc 31949/31949 [011] 79371.874569: `.
+fn header_task(header: &str) -> Option<&str> {
+    let mut words: Vec<&str> = header.split_whitespace().collect();
+    words.pop()?;
+    if words.last()?.starts_with('[') {
+        words.pop();
+    }
+    // The PID/TID follows the name, which can contain spaces.
+    let ids = words.pop()?;
+    let start = header.find(words.first()?)?;
+    let end = header[start..].rfind(ids)? + start;
+    Some(header[start..end].trim())
+}
+
+/// The value of `key=` in the fields of a tracepoint.
+///
+/// Values end at the next space, or, for values that can contain spaces (like task names), where
+/// the field named `until` starts.
+fn field<'a>(fields: &'a str, key: &str, until: Option<&str>) -> Option<&'a str> {
+    let start = fields.match_indices(key).map(|(i, _)| i).find(|&i| {
+        (i == 0 || fields.as_bytes()[i - 1] == b' ') && fields[i + key.len()..].starts_with('=')
+    })? + key.len()
+        + 1;
+    let value = &fields[start..];
+    let end = match until {
+        Some(until) => value.find(&format!(" {}=", until))?,
+        None => value.find(' ').unwrap_or(value.len()),
+    };
+    Some(&value[..end])
+}
+
+// Event lines can be indented too, as `perf script` right-aligns task names.
+fn is_stack_line(line: &str) -> bool {
+    let pc = line.split_whitespace().next().unwrap_or_default();
+    line.starts_with(char::is_whitespace)
+        && pc.bytes().all(|b| b.is_ascii_hexdigit())
+        && line.ends_with(')')
+}
+
+/// The function name of a `perf script` stack line like
+/// `ffffffff81a4b2c5 __schedule+0x2e5 ([kernel.kallsyms])`.
+fn stack_line_frame(line: &str) -> String {
+    let line = line.trim();
+    let rest = line.split_once(' ').map_or("", |(_, rest)| rest);
+    let (symbol, module) = match rest.rsplit_once(" (") {
+        Some((symbol, module)) => (symbol.trim(), module.trim_end_matches(')')),
+        None => (rest.trim(), ""),
+    };
+    let symbol = match symbol.rsplit_once("+0x") {
+        Some((name, offset)) if offset.bytes().all(|b| b.is_ascii_hexdigit()) => name,
+        _ => symbol,
+    };
+    let frame = if (symbol.is_empty() || symbol == "[unknown]") && module != "[unknown]" {
+        // Use the file name of the module if the symbol is unknown.
+        match module.rsplit('/').next() {
+            Some(module) if !module.is_empty() => format!("[{}]", module),
+            _ => "[unknown]".to_string(),
+        }
+    } else {
+        symbol.to_string()
+    };
+    frame.replace(';', ":")
+}
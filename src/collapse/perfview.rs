@@ -0,0 +1,378 @@
+//! Stack collapsing for stacks exported from Windows [PerfView] (and other TraceEvent-based
+//! tools).
+//!
+//! Two input formats are understood:
+//!
+//!  - the XML stack format PerfView writes with "File → Save View As" (`.perfView.xml`, or the
+//!    zipped `.perfView.xml.zip`, whose deflated entries need the `gzip` feature). It contains a table of frames, a table of stacks that each
+//!    point to a frame and a caller stack, and a list of samples that each point to a stack.
+//!    Every sample contributes its `Metric` to its stack; and
+//!  - the tab-separated call tree text that PerfView puts on the clipboard when copying from a
+//!    CallTree view. Here only the `Inc` (inclusive) column is used: a node's count is its
+//!    inclusive metric minus the inclusive metric of its children, so nodes that were not
+//!    expanded in the view keep all of their time.
+//!
+//! Metrics are rounded to whole numbers, so if you are exporting a view whose metric is in
+//! milliseconds, stacks that accumulate less than half a millisecond are dropped.
+//!
+//!   [PerfView]: https://github.com/microsoft/perfview
+
+use std::collections::HashMap;
+#[cfg(feature = "gzip")]
+use std::io::Read;
+use std::io::{self, BufRead};
+
+use log::warn;
+use quick_xml::events::attributes::Attributes;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// Zip local file header signature.
+static ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+// The call tree text begins after a header row starting with this column.
+static TEXT_NAME_COLUMN: &str = "Name";
+static TEXT_INC_COLUMN: &str = "Inc";
+
+// Caller ID of stacks that have no caller in the XML format.
+const NO_CALLER: i64 = -1;
+
+/// `perfview` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Don't include modules with function names.
+    ///
+    /// PerfView names frames `module!function`; with this set, only `function` is kept.
+    ///
+    /// Default is `false`.
+    pub no_modules: bool,
+}
+
+/// A stack collapser for stacks exported from PerfView.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `perfview::Folder::default()` or create an [`Options`] and use
+/// `perfview::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.starts_with(ZIP_MAGIC) {
+            data = unzip_xml(&data)?;
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        if is_xml(&data) {
+            self.collapse_xml(&data, &mut occurrences)?;
+        } else {
+            self.collapse_text(&data, &mut occurrences)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the root elements of the XML format or the header row of the call tree text.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if input.contains("<StackWindow") || input.contains("<StackSource") {
+            return Some(true);
+        }
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            if text_header(&line).is_some() {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+fn is_xml(data: &[u8]) -> bool {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    data.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<')
+}
+
+/// Returns the index of the `Inc` column if `line` is the header row of the call tree text.
+fn text_header(line: &str) -> Option<usize> {
+    let mut columns = line.trim_end().split('\t').map(str::trim);
+    if columns.next()? != TEXT_NAME_COLUMN {
+        return None;
+    }
+    columns.position(|c| c == TEXT_INC_COLUMN).map(|i| i + 1)
+}
+
+/// Extracts the first `.xml` file from a zip archive.
+fn unzip_xml(mut data: &[u8]) -> io::Result<Vec<u8>> {
+    fn u16_at(data: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([data[at], data[at + 1]]) as usize
+    }
+    fn u32_at(data: &[u8], at: usize) -> usize {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+    }
+
+    // Local file headers are 30 bytes followed by the file name and an extra field.
+    while data.len() >= 30 && data.starts_with(ZIP_MAGIC) {
+        let flags = u16_at(data, 6);
+        let method = u16_at(data, 8);
+        let compressed_size = u32_at(data, 18);
+        let name_len = u16_at(data, 26);
+        let extra_len = u16_at(data, 28);
+        let start = 30 + name_len + extra_len;
+        if data.len() < start {
+            break;
+        }
+        let name = String::from_utf8_lossy(&data[30..30 + name_len]);
+        let body = &data[start..];
+
+        if name.ends_with(".xml") {
+            let mut xml = Vec::new();
+            match method {
+                0 if flags & 0x08 == 0 && compressed_size <= body.len() => {
+                    xml.extend_from_slice(&body[..compressed_size])
+                }
+                #[cfg(feature = "gzip")]
+                8 => {
+                    libflate::deflate::Decoder::new(body).read_to_end(&mut xml)?;
+                }
+                #[cfg(not(feature = "gzip"))]
+                8 => {
+                    return invalid_data_error!(
+                        "Reading the deflated {} in zip archive needs the `gzip` feature",
+                        name
+                    )
+                }
+                _ => {
+                    return invalid_data_error!(
+                        "Unsupported compression of {} in zip archive",
+                        name
+                    )
+                }
+            }
+            return Ok(xml);
+        }
+
+        // Without the sizes in the local header we can't find the next entry.
+        if flags & 0x08 != 0 || compressed_size > body.len() {
+            break;
+        }
+        data = &body[compressed_size..];
+    }
+    invalid_data_error!("No .xml file found in zip archive")
+}
+
+fn attribute(attributes: &Attributes, key: &[u8]) -> Option<String> {
+    attributes
+        .clone()
+        .filter_map(|a| a.ok())
+        .find(|a| a.key.into_inner() == key)
+        .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+}
+
+fn parse_attribute<T: std::str::FromStr>(attributes: &Attributes, key: &str) -> io::Result<T> {
+    match attribute(attributes, key.as_bytes()).map(|v| v.parse()) {
+        Some(Ok(v)) => Ok(v),
+        Some(Err(_)) => invalid_data_error!("Invalid {} attribute", key),
+        None => invalid_data_error!("Missing {} attribute", key),
+    }
+}
+
+impl Folder {
+    fn frame_name(&self, name: &str) -> String {
+        let name = if self.opt.no_modules {
+            name.split_once('!').map_or(name, |(_, func)| func)
+        } else {
+            name
+        };
+        name.trim().replace(';', ":")
+    }
+
+    fn collapse_xml(&self, data: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        let mut reader = Reader::from_reader(data);
+        let mut buf = Vec::new();
+
+        let mut frames: HashMap<i64, String> = HashMap::new();
+        // stack ID -> (caller stack ID, frame ID)
+        let mut stacks: HashMap<i64, (i64, i64)> = HashMap::new();
+        // stack ID -> summed metric
+        let mut metrics: HashMap<i64, f64> = HashMap::new();
+        let mut frame_id = None;
+
+        loop {
+            let event = match reader.read_event_into(&mut buf) {
+                Ok(event) => event,
+                Err(e) => return invalid_data_error!("Read xml event failed: {:?}", e),
+            };
+            match event {
+                Event::Start(ref e) | Event::Empty(ref e) => {
+                    let attributes = e.attributes();
+                    match e.name().into_inner() {
+                        b"Frame" => {
+                            let id = parse_attribute(&attributes, "ID")?;
+                            frames.insert(id, String::new());
+                            frame_id = Some(id);
+                        }
+                        b"Stack" => {
+                            let id = parse_attribute(&attributes, "ID")?;
+                            let caller = parse_attribute(&attributes, "CallerID")?;
+                            let frame = parse_attribute(&attributes, "FrameID")?;
+                            stacks.insert(id, (caller, frame));
+                        }
+                        b"Sample" => {
+                            let stack = parse_attribute(&attributes, "StackID")?;
+                            let metric = match attribute(&attributes, b"Metric") {
+                                Some(_) => parse_attribute(&attributes, "Metric")?,
+                                None => 1.0,
+                            };
+                            *metrics.entry(stack).or_default() += metric;
+                        }
+                        _ => {}
+                    }
+                    if let Event::Empty(_) = event {
+                        frame_id = None;
+                    }
+                }
+                Event::Text(ref e) => {
+                    if let Some(id) = frame_id {
+                        let text = String::from_utf8_lossy(e);
+                        match quick_xml::escape::unescape(&text) {
+                            Ok(text) => frames.entry(id).or_default().push_str(&text),
+                            Err(e) => return invalid_data_error!("Invalid frame name: {:?}", e),
+                        }
+                    }
+                }
+                Event::End(_) => frame_id = None,
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+
+        let mut path = Vec::new();
+        for (stack, metric) in metrics {
+            let count = metric.round();
+            if count < 1.0 {
+                continue;
+            }
+
+            path.clear();
+            let mut id = stack;
+            while id != NO_CALLER {
+                let Some(&(caller, frame)) = stacks.get(&id) else {
+                    return invalid_data_error!("Unknown stack ID {}", id);
+                };
+                let Some(name) = frames.get(&frame) else {
+                    return invalid_data_error!("Unknown frame ID {}", frame);
+                };
+                // PerfView roots every stack in a synthetic `ROOT` frame, which we leave out.
+                if caller != NO_CALLER || name != "ROOT" {
+                    path.push(self.frame_name(name));
+                }
+                if path.len() > stacks.len() {
+                    return invalid_data_error!("Stack {} has a cycle", stack);
+                }
+                id = caller;
+            }
+            path.reverse();
+            occurrences.insert(path.join(";"), count as usize);
+        }
+        Ok(())
+    }
+
+    fn collapse_text(&self, data: &[u8], occurrences: &mut Occurrences) -> io::Result<()> {
+        let text = String::from_utf8_lossy(data);
+        let mut lines = text.lines();
+        let Some(inc_column) = lines.by_ref().find_map(text_header) else {
+            warn!("File ended before header");
+            return Ok(());
+        };
+
+        // The nodes on the path to the current row.
+        let mut stack: Vec<TreeNode> = Vec::new();
+        for line in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let columns: Vec<&str> = line.split('\t').collect();
+            let (Some(name), Some(inc)) = (columns.first(), columns.get(inc_column)) else {
+                return invalid_data_error!("Unable to parse call tree line:\n{}", line);
+            };
+            let Ok(inc) = inc.trim().replace(',', "").parse::<f64>() else {
+                return invalid_data_error!("Invalid `Inc` field: {}", inc);
+            };
+
+            // Each level of the tree indents the `+` that marks a node by one more character.
+            let prefix_len = name.len() - name.trim_start_matches([' ', '|', '+']).len();
+            let depth = match name[..prefix_len].rfind('+') {
+                Some(depth) => depth,
+                None if name.trim() == "ROOT" => continue,
+                None => 0,
+            };
+
+            while stack.last().is_some_and(|node| node.depth >= depth) {
+                self.pop_node(&mut stack, occurrences);
+            }
+            if let Some(parent) = stack.last_mut() {
+                parent.children += inc;
+            }
+            stack.push(TreeNode {
+                depth,
+                name: self.frame_name(&name[prefix_len..]),
+                inc,
+                children: 0.0,
+            });
+        }
+        while !stack.is_empty() {
+            self.pop_node(&mut stack, occurrences);
+        }
+        Ok(())
+    }
+
+    fn pop_node(&self, stack: &mut Vec<TreeNode>, occurrences: &mut Occurrences) {
+        let node = stack.last().expect("popped an empty call tree");
+        let exclusive = (node.inc - node.children).round();
+        if exclusive >= 1.0 {
+            let path = stack
+                .iter()
+                .map(|node| node.name.as_str())
+                .collect::<Vec<_>>()
+                .join(";");
+            occurrences.insert(path, exclusive as usize);
+        }
+        stack.pop();
+    }
+}
+
+struct TreeNode {
+    depth: usize,
+    name: String,
+    inc: f64,
+    children: f64,
+}
@@ -0,0 +1,249 @@
+//! Stack collapsing for samples of PHP (and HHVM) programs taken with [phpspy] or [Excimer].
+//!
+//! Two input formats are understood:
+//!
+//!  - phpspy's default output, in which every sample is a list of frames like
+//!    `0 Foo\Bar::baz /srv/app/src/Bar.php:42`, innermost first and numbered by depth, followed
+//!    by optional `# key = value` lines with details of the request (such as the `uri` printed
+//!    with `-r u`) and a blank line; and
+//!  - the JSON that Excimer's `ExcimerLog::getSpeedscopeData()` returns, which has a table of
+//!    frames and, for each profile, a list of samples that each list the frames of a stack
+//!    outermost first, with a weight.
+//!
+//! Frames are named after their function (`Class::method` or `function`). Code outside of any
+//! function is named `<main>` by phpspy, and after its file by Excimer.
+//!
+//!   [phpspy]: https://github.com/adsr/phpspy
+//!   [Excimer]: https://www.mediawiki.org/wiki/Excimer
+
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// Key of the phpspy request detail line that holds the request URI.
+static URI_KEY: &str = "uri";
+
+/// `php` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Strip namespaces from class and function names, so that `Foo\Bar::baz` becomes
+    /// `Bar::baz`.
+    ///
+    /// Default is `false`.
+    pub strip_namespaces: bool,
+
+    /// Root the stacks of each request in a frame for its URI, without the query string.
+    ///
+    /// This only works for phpspy output of samples with their request URI (`phpspy -r u`).
+    /// Stacks of samples without a URI are left as they are.
+    ///
+    /// Default is `false`.
+    pub group_by_uri: bool,
+}
+
+/// A stack collapser for phpspy and Excimer samples.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `php::Folder::default()` or create an [`Options`] and use
+/// `php::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Frames of the current sample, innermost first.
+    stack: Vec<String>,
+
+    /// Request URI of the current sample, if any.
+    uri: Option<String>,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+
+        let buf = reader.fill_buf()?;
+        if buf.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+            let json: serde_json::Value = serde_json::from_reader(reader)?;
+            self.collapse_speedscope(&json, &mut occurrences)?;
+        } else {
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if reader.read_until(0x0A, &mut line)? == 0 {
+                    break;
+                }
+                let l = String::from_utf8_lossy(&line);
+                self.on_phpspy_line(l.trim_end(), &mut occurrences);
+            }
+            self.end_sample(&mut occurrences);
+        }
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a phpspy frame line or the speedscope schema of Excimer's JSON.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if input.trim_start().starts_with('{') {
+            return Some(input.contains("speedscope"));
+        }
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            return Some(phpspy_frame(line).is_some());
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn on_phpspy_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        if line.is_empty() {
+            self.end_sample(occurrences);
+        } else if let Some(detail) = line.strip_prefix('#') {
+            // `# uri = /index.php?title=Main_Page`
+            if let Some((key, value)) = detail.split_once('=') {
+                if key.trim() == URI_KEY {
+                    let uri = value.trim();
+                    let path = uri.split_once('?').map_or(uri, |(path, _)| path);
+                    self.uri = Some(path.to_string());
+                }
+            }
+        } else if let Some(function) = phpspy_frame(line) {
+            self.stack.push(self.frame_name(function));
+        } else {
+            warn!("Weird phpspy line: {}", line);
+        }
+    }
+
+    fn end_sample(&mut self, occurrences: &mut Occurrences) {
+        let uri = self.uri.take();
+        if self.stack.is_empty() {
+            return;
+        }
+
+        let mut stack = String::new();
+        if let Some(uri) = uri.filter(|_| self.opt.group_by_uri) {
+            stack.push_str(&uri.replace(';', ":"));
+            stack.push(';');
+        }
+        for (i, frame) in self.stack.iter().rev().enumerate() {
+            if i > 0 {
+                stack.push(';');
+            }
+            stack.push_str(frame);
+        }
+        occurrences.insert_or_add(stack, 1);
+        self.stack.clear();
+    }
+
+    fn collapse_speedscope(
+        &self,
+        json: &serde_json::Value,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()> {
+        let Some(frames) = json["shared"]["frames"].as_array() else {
+            return invalid_data_error!("No shared frames in speedscope data");
+        };
+        let frames: Vec<String> = frames
+            .iter()
+            .map(|frame| self.frame_name(frame["name"].as_str().unwrap_or("[unknown]")))
+            .collect();
+        let Some(profiles) = json["profiles"].as_array() else {
+            return invalid_data_error!("No profiles in speedscope data");
+        };
+
+        for profile in profiles {
+            if profile["type"] != "sampled" {
+                warn!("Skipping profile of type {}", profile["type"]);
+                continue;
+            }
+            let Some(samples) = profile["samples"].as_array() else {
+                return invalid_data_error!("Sampled profile without samples");
+            };
+            let weights = &profile["weights"];
+            for (i, sample) in samples.iter().enumerate() {
+                let Some(sample) = sample.as_array() else {
+                    return invalid_data_error!("Invalid sample: {}", sample);
+                };
+                let mut stack = Vec::with_capacity(sample.len());
+                for index in sample {
+                    match index.as_u64().and_then(|i| frames.get(i as usize)) {
+                        Some(frame) => stack.push(frame.as_str()),
+                        None => return invalid_data_error!("Invalid frame index: {}", index),
+                    }
+                }
+                let weight = weights[i].as_f64().unwrap_or(1.0).round();
+                if !stack.is_empty() && weight >= 1.0 {
+                    occurrences.insert_or_add(stack.join(";"), weight as usize);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn frame_name(&self, function: &str) -> String {
+        let function = if self.opt.strip_namespaces {
+            strip_namespace(function)
+        } else {
+            function.to_string()
+        };
+        function.replace(';', ":")
+    }
+}
+
+/// The function of a phpspy frame line like `1 Foo\Bar::baz /srv/app/src/Bar.php:42`.
+fn phpspy_frame(line: &str) -> Option<&str> {
+    let (depth, rest) = line.split_once(' ')?;
+    if depth.is_empty() || !depth.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // The location is `file:line`, where the line is -1 if unknown.
+    let (function, location) = rest.rsplit_once(' ')?;
+    let (_, line) = location.rsplit_once(':')?;
+    line.parse::<i64>().ok()?;
+    Some(function)
+}
+
+// `Foo\Bar::baz` becomes `Bar::baz`, and `Foo\baz` becomes `baz`.
+fn strip_namespace(function: &str) -> String {
+    let (class, method) = match function.split_once("::") {
+        Some((class, method)) => (class, Some(method)),
+        None => (function, None),
+    };
+    let class = class.rsplit('\\').next().unwrap_or(class);
+    match method {
+        Some(method) => format!("{}::{}", class, method),
+        None => class.to_string(),
+    }
+}
@@ -0,0 +1,490 @@
+//! Stack collapsing for [pprof] profiles, as stored by continuous profilers such as [Parca] or
+//! the Prometheus-style conprof.
+//!
+//! The input is a pprof profile, a protocol buffer that is usually gzipped, as fetched from a
+//! `/debug/pprof/` endpoint or kept in the storage of a continuous profiler. With
+//! [`Folder::collapse_paths`], the profiles of whole directories of such dumps are folded into
+//! one dataset, optionally only those taken between [`Options::since`] and [`Options::until`].
+//!
+//! Every sample is folded from the root of its stack to its leaf, with inlined functions as
+//! frames of their own, and counts its value of one of the sample types of its profile, such as
+//! `cpu` or `alloc_space`: that of [`Options::sample_type`] if given, or else the default of the
+//! profile (or its last one).
+//!
+//! With the `reqwest` feature, [`Folder::collapse_url`] fetches a profile from the endpoint of a
+//! running service and folds it in one go.
+//!
+//!   [pprof]: https://github.com/google/pprof/blob/main/proto/profile.proto
+//!   [Parca]: https://www.parca.dev
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use libflate::gzip::Decoder;
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// `pprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// The sample type whose values to count, such as `cpu`, `samples` or `alloc_space`.
+    ///
+    /// Profiles without it are skipped. If not given, every profile counts its default sample
+    /// type, or its last one if it has no default.
+    ///
+    /// Default is `None`.
+    pub sample_type: Option<String>,
+
+    /// Only fold profiles that end at or after this time.
+    ///
+    /// Profiles without a time are skipped if this or [`Options::until`] is given.
+    ///
+    /// Default is `None`.
+    pub since: Option<SystemTime>,
+
+    /// Only fold profiles that start before this time.
+    ///
+    /// Default is `None`.
+    pub until: Option<SystemTime>,
+}
+
+/// A stack collapser for pprof profiles.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `pprof::Folder::default()` or create an [`Options`] and use
+/// `pprof::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// Why profiles were left out of the folded dataset.
+#[derive(Default)]
+struct Skipped {
+    out_of_range: usize,
+    without_time: usize,
+    without_sample_type: usize,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        self.collapse_profile(data, writer)
+    }
+
+    /// Check for an uncompressed profile; gzipped ones are never valid UTF-8.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        Some(Profile::decode(input.as_bytes()).is_ok())
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    /// Collapses the profiles of the given files and directories into one dataset, and writes
+    /// its folded stack lines to `writer`.
+    ///
+    /// Directories are read in the order of their file names, without descending into
+    /// subdirectories, and their files that aren't profiles are skipped with a warning. Files
+    /// given by path must be profiles.
+    pub fn collapse_paths<P, W>(&mut self, paths: &[P], writer: W) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut skipped = Skipped::default();
+        let mut profiles = 0;
+        for path in paths {
+            let path = path.as_ref();
+            if !path.is_dir() {
+                let profile = read_profile(path)?;
+                self.fold(&profile, &mut occurrences, &mut skipped);
+                profiles += 1;
+                continue;
+            }
+
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if entry.file_type()?.is_file() && !hidden {
+                    files.push(entry.path());
+                }
+            }
+            files.sort();
+            for file in files {
+                match read_profile(&file) {
+                    Ok(profile) => {
+                        self.fold(&profile, &mut occurrences, &mut skipped);
+                        profiles += 1;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        warn!("Skipped {}, which is not a profile: {}", file.display(), e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        self.finish(occurrences, skipped, profiles, writer)
+    }
+
+    /// Fetches a profile from a `/debug/pprof/` endpoint, such as
+    /// `http://localhost:6060/debug/pprof/profile?seconds=30` of a Go service or
+    /// `http://localhost:9901/debug/pprof/profile` of Envoy, and writes its folded stack lines to
+    /// `writer`.
+    ///
+    /// Such endpoints profile for as long as they are asked to before they respond, so the request
+    /// never times out.
+    #[cfg(feature = "reqwest")]
+    pub fn collapse_url<W: io::Write>(&mut self, url: &str, writer: W) -> io::Result<()> {
+        let other = |e: reqwest::Error| io::Error::new(io::ErrorKind::Other, e);
+        let client = reqwest::blocking::Client::builder()
+            .timeout(None)
+            .build()
+            .map_err(other)?;
+        let response = client
+            .get(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(other)?;
+        let data = response.bytes().map_err(other)?;
+        self.collapse_profile(data.to_vec(), writer)
+    }
+
+    fn collapse_profile<W: io::Write>(&self, data: Vec<u8>, writer: W) -> io::Result<()> {
+        let mut occurrences = Occurrences::new(1);
+        let mut skipped = Skipped::default();
+        let profile = Profile::decode(&decompress(data)?)?;
+        self.fold(&profile, &mut occurrences, &mut skipped);
+        self.finish(occurrences, skipped, 1, writer)
+    }
+
+    fn finish<W: io::Write>(
+        &self,
+        mut occurrences: Occurrences,
+        skipped: Skipped,
+        profiles: usize,
+        writer: W,
+    ) -> io::Result<()> {
+        if profiles == 0 {
+            return invalid_data_error!("No profiles found in input");
+        }
+        if skipped.out_of_range > 0 {
+            warn!(
+                "Skipped {} profiles outside of the time range",
+                skipped.out_of_range
+            );
+        }
+        if skipped.without_time > 0 {
+            warn!(
+                "Skipped {} profiles without a time, which can't be filtered by time",
+                skipped.without_time
+            );
+        }
+        if skipped.without_sample_type > 0 {
+            warn!(
+                "Skipped {} profiles without the sample type {}",
+                skipped.without_sample_type,
+                self.opt.sample_type.as_deref().unwrap_or_default()
+            );
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    // Adds the samples of a profile to the folded stacks, if it's in the time range.
+    fn fold(&self, profile: &Profile, occurrences: &mut Occurrences, skipped: &mut Skipped) {
+        if self.opt.since.is_some() || self.opt.until.is_some() {
+            if profile.time_nanos <= 0 {
+                skipped.without_time += 1;
+                return;
+            }
+            let start = SystemTime::UNIX_EPOCH + Duration::from_nanos(profile.time_nanos as u64);
+            let end = start + Duration::from_nanos(profile.duration_nanos.max(0) as u64);
+            let before = self.opt.since.is_some_and(|since| end < since);
+            let after = self.opt.until.is_some_and(|until| start >= until);
+            if before || after {
+                skipped.out_of_range += 1;
+                return;
+            }
+        }
+
+        let string = |i: i64| profile.strings.get(i as usize).map_or("", String::as_str);
+        let sample_type = match &self.opt.sample_type {
+            Some(name) => profile.sample_types.iter().position(|&t| string(t) == name),
+            None => profile
+                .sample_types
+                .iter()
+                .position(|&t| t == profile.default_sample_type && t != 0)
+                .or_else(|| profile.sample_types.len().checked_sub(1)),
+        };
+        let Some(sample_type) = sample_type else {
+            skipped.without_sample_type += 1;
+            return;
+        };
+
+        let mut stack = String::new();
+        for sample in &profile.samples {
+            let value = sample.values.get(sample_type).copied().unwrap_or(0);
+            if value <= 0 {
+                continue;
+            }
+            stack.clear();
+            // Locations are listed from the leaf up, and so are the lines of inlined functions.
+            for id in sample.locations.iter().rev() {
+                let location = profile.locations.get(id);
+                let functions = location.map_or(&[][..], |l| &l.functions[..]);
+                if functions.is_empty() {
+                    let address = location.map_or(0, |l| l.address);
+                    push_frame(&mut stack, &format!("{:#x}", address));
+                }
+                for function in functions.iter().rev() {
+                    let name = profile.functions.get(function).map_or("", |&n| string(n));
+                    push_frame(&mut stack, if name.is_empty() { "[unknown]" } else { name });
+                }
+            }
+            if !stack.is_empty() {
+                occurrences.insert_or_add(stack.clone(), value as usize);
+            }
+        }
+    }
+}
+
+fn push_frame(stack: &mut String, name: &str) {
+    if !stack.is_empty() {
+        stack.push(';');
+    }
+    stack.push_str(&name.replace(';', ":"));
+}
+
+fn read_profile(path: &Path) -> io::Result<Profile> {
+    Profile::decode(&decompress(fs::read(path)?)?)
+}
+
+fn decompress(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !data.starts_with(&GZIP_MAGIC) {
+        return Ok(data);
+    }
+    let mut decompressed = Vec::new();
+    Decoder::new(&data[..])?.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+// The parts of a pprof profile that are needed to fold its samples.
+#[derive(Default)]
+struct Profile {
+    // The string table indices of the names of the sample types.
+    sample_types: Vec<i64>,
+    samples: Vec<Sample>,
+    locations: HashMap<u64, Location>,
+    // The string table indices of the names of functions, by function ID.
+    functions: HashMap<u64, i64>,
+    strings: Vec<String>,
+    time_nanos: i64,
+    duration_nanos: i64,
+    default_sample_type: i64,
+}
+
+#[derive(Default)]
+struct Sample {
+    locations: Vec<u64>,
+    values: Vec<i64>,
+}
+
+#[derive(Default)]
+struct Location {
+    address: u64,
+    // The function IDs of its lines, innermost first.
+    functions: Vec<u64>,
+}
+
+impl Profile {
+    fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut profile = Profile::default();
+        let mut message = Message(data);
+        while let Some((field, value)) = message.field()? {
+            match (field, value) {
+                (1, Value::Bytes(sample_type)) => {
+                    let mut sample_type = Message(sample_type);
+                    let mut name = 0;
+                    while let Some((field, value)) = sample_type.field()? {
+                        if let (1, Value::Varint(n)) = (field, value) {
+                            name = n as i64;
+                        }
+                    }
+                    profile.sample_types.push(name);
+                }
+                (2, Value::Bytes(sample)) => profile.samples.push(Sample::decode(sample)?),
+                (4, Value::Bytes(location)) => {
+                    let (id, location) = Location::decode(location)?;
+                    profile.locations.insert(id, location);
+                }
+                (5, Value::Bytes(function)) => {
+                    let mut function = Message(function);
+                    let (mut id, mut name) = (0, 0);
+                    while let Some((field, value)) = function.field()? {
+                        match (field, value) {
+                            (1, Value::Varint(n)) => id = n,
+                            (2, Value::Varint(n)) => name = n as i64,
+                            _ => {}
+                        }
+                    }
+                    profile.functions.insert(id, name);
+                }
+                (6, Value::Bytes(string)) => {
+                    profile
+                        .strings
+                        .push(String::from_utf8_lossy(string).into_owned());
+                }
+                (9, Value::Varint(n)) => profile.time_nanos = n as i64,
+                (10, Value::Varint(n)) => profile.duration_nanos = n as i64,
+                (14, Value::Varint(n)) => profile.default_sample_type = n as i64,
+                _ => {}
+            }
+        }
+        if profile.sample_types.is_empty() {
+            return invalid_data_error!("Invalid profile: no sample types");
+        }
+        if profile.strings.first().is_some_and(|s| !s.is_empty()) {
+            return invalid_data_error!("Invalid profile: the string table must start empty");
+        }
+        Ok(profile)
+    }
+}
+
+impl Sample {
+    fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut sample = Sample::default();
+        let mut message = Message(data);
+        while let Some((field, value)) = message.field()? {
+            match field {
+                1 => value.repeated(&mut sample.locations)?,
+                2 => {
+                    let mut values = Vec::new();
+                    value.repeated(&mut values)?;
+                    sample.values.extend(values.into_iter().map(|v| v as i64));
+                }
+                _ => {}
+            }
+        }
+        Ok(sample)
+    }
+}
+
+impl Location {
+    fn decode(data: &[u8]) -> io::Result<(u64, Self)> {
+        let mut id = 0;
+        let mut location = Location::default();
+        let mut message = Message(data);
+        while let Some((field, value)) = message.field()? {
+            match (field, value) {
+                (1, Value::Varint(n)) => id = n,
+                (3, Value::Varint(n)) => location.address = n,
+                (4, Value::Bytes(line)) => {
+                    let mut line = Message(line);
+                    while let Some((field, value)) = line.field()? {
+                        if let (1, Value::Varint(n)) = (field, value) {
+                            location.functions.push(n);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((id, location))
+    }
+}
+
+// The rest of a protocol buffer message that is yet to be read.
+struct Message<'a>(&'a [u8]);
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Message<'a> {
+    // Reads the next field number and its value, or `None` at the end of the message.
+    fn field(&mut self) -> io::Result<Option<(u64, Value<'a>)>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => self.skip(8)?,
+            2 => {
+                let len = self.varint()?;
+                match usize::try_from(len).ok().filter(|&len| len <= self.0.len()) {
+                    Some(len) => {
+                        let (bytes, rest) = self.0.split_at(len);
+                        self.0 = rest;
+                        Value::Bytes(bytes)
+                    }
+                    None => return invalid_data_error!("Invalid profile: truncated field"),
+                }
+            }
+            5 => self.skip(4)?,
+            wire_type => {
+                return invalid_data_error!("Invalid profile: unknown wire type {}", wire_type)
+            }
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut n = 0;
+        for (i, &byte) in self.0.iter().enumerate().take(10) {
+            n |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(n);
+            }
+        }
+        invalid_data_error!("Invalid profile: truncated varint")
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<Value<'a>> {
+        if self.0.len() < len {
+            return invalid_data_error!("Invalid profile: truncated field");
+        }
+        self.0 = &self.0[len..];
+        Ok(Value::Fixed)
+    }
+}
+
+impl Value<'_> {
+    // Adds the values of a repeated integer field, which are either packed or one per field.
+    fn repeated(self, into: &mut Vec<u64>) -> io::Result<()> {
+        match self {
+            Value::Varint(n) => into.push(n),
+            Value::Bytes(packed) => {
+                let mut packed = Message(packed);
+                while !packed.0.is_empty() {
+                    into.push(packed.varint()?);
+                }
+            }
+            Value::Fixed => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! Stack collapsing for the profiles that the Profiler of [React DevTools] exports.
+//!
+//! The input is the JSON file that the Profiler tab saves with its "Save profile..." button. It
+//! holds the commits that React made while profiling every root of the page, and for every commit
+//! the components (fibers) that rendered in it, each with the time it spent rendering itself.
+//!
+//! Every stack runs from the top of a React root down through the component tree to a component
+//! that rendered, as the tree was at the time of the commit, and counts the microseconds that
+//! component spent rendering itself in that commit, leaving out the time of its children. The
+//! width of a component in a flame graph is thus its actual render duration, summed over all
+//! commits.
+//!
+//! The component tree is rebuilt from the snapshot the export takes when profiling starts and the
+//! tree operations of every commit, which add, move and remove components. Components are named as
+//! in the component tree of DevTools, without the higher-order components that wrap them.
+//!
+//!   [React DevTools]: https://react.dev/learn/react-developer-tools
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// The tree operations of React DevTools, as in `react-devtools-shared/src/constants.js`.
+const TREE_OPERATION_ADD: i64 = 1;
+const TREE_OPERATION_REMOVE: i64 = 2;
+const TREE_OPERATION_REORDER_CHILDREN: i64 = 3;
+const TREE_OPERATION_UPDATE_TREE_BASE_DURATION: i64 = 4;
+const TREE_OPERATION_UPDATE_ERRORS_OR_WARNINGS: i64 = 5;
+const TREE_OPERATION_REMOVE_ROOT: i64 = 6;
+const TREE_OPERATION_SET_SUBTREE_MODE: i64 = 7;
+
+const ELEMENT_TYPE_ROOT: i64 = 11;
+
+/// `react` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Include the commit that a component rendered in, as `commit 3`, as the root frame of
+    /// every stack.
+    ///
+    /// Commits are numbered from 1 for every React root, as in the commit selector of the
+    /// Profiler.
+    ///
+    /// Default is `false`.
+    pub include_commit: bool,
+}
+
+/// A stack collapser for the profiles exported by the Profiler of React DevTools.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `react::Folder::default()` or create an [`Options`] and use
+/// `react::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// A component in the tree of a React root.
+struct Node {
+    name: Option<String>,
+    parent: u64,
+}
+
+// What could not be folded as it should, to warn about at the end.
+#[derive(Default)]
+struct Skipped {
+    unknown_fibers: usize,
+    unknown_operations: usize,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let profile: Value = serde_json::from_slice(&data)?;
+        let Some(roots) = profile.get("dataForRoots").and_then(Value::as_array) else {
+            return invalid_data_error!("No React roots found in input");
+        };
+
+        let mut occurrences = Occurrences::new(1);
+        let mut skipped = Skipped::default();
+        for root in roots {
+            self.fold_root(root, &mut occurrences, &mut skipped);
+        }
+
+        if skipped.unknown_operations > 0 {
+            warn!(
+                "Stopped rebuilding the component tree in {} commits at tree operations that \
+                 are not supported; some components may be placed under the wrong parent",
+                skipped.unknown_operations
+            );
+        }
+        if skipped.unknown_fibers > 0 {
+            warn!(
+                "{} components that rendered are missing from the component tree; they are \
+                 folded under [unknown]",
+                skipped.unknown_fibers
+            );
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a profile exported by the React DevTools Profiler.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        input.contains("\"dataForRoots\"").then_some(true)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn fold_root(&self, root: &Value, occurrences: &mut Occurrences, skipped: &mut Skipped) {
+        let mut tree = snapshot_tree(&root["snapshots"]);
+        let operations = root["operations"].as_array().map_or(&[][..], Vec::as_slice);
+        let commits = root["commitData"].as_array().map_or(&[][..], Vec::as_slice);
+
+        for (i, commit) in commits.iter().enumerate() {
+            if let Some(operations) = operations.get(i).and_then(Value::as_array) {
+                if !apply_operations(&mut tree, operations) {
+                    skipped.unknown_operations += 1;
+                }
+            }
+
+            let durations = commit["fiberSelfDurations"]
+                .as_array()
+                .map_or(&[][..], Vec::as_slice);
+            for duration in durations {
+                let (Some(id), Some(ms)) = (duration[0].as_u64(), duration[1].as_f64()) else {
+                    continue;
+                };
+                let us = (ms * 1000.0).round();
+                if us < 1.0 {
+                    continue;
+                }
+
+                let mut frames = Vec::new();
+                let mut next = id;
+                // Guard against cycles of a tree that was not rebuilt correctly.
+                while next != 0 && frames.len() <= tree.len() {
+                    let Some(node) = tree.get(&next) else {
+                        skipped.unknown_fibers += 1;
+                        frames.push("[unknown]");
+                        break;
+                    };
+                    // The root itself is left out, as in the flame chart of the Profiler.
+                    let Some(name) = &node.name else {
+                        break;
+                    };
+                    frames.push(name.as_str());
+                    next = node.parent;
+                }
+
+                let mut stack = String::new();
+                if self.opt.include_commit {
+                    stack.push_str(&format!("commit {};", i + 1));
+                }
+                for frame in frames.iter().rev() {
+                    stack.push_str(&frame.replace(';', ":"));
+                    stack.push(';');
+                }
+                stack.pop();
+                occurrences.insert_or_add(stack, us as usize);
+            }
+        }
+    }
+}
+
+// Builds the component tree of a root as it was when profiling started.
+fn snapshot_tree(snapshots: &Value) -> HashMap<u64, Node> {
+    let snapshots = snapshots.as_array().map_or(&[][..], Vec::as_slice);
+    let mut tree = HashMap::with_capacity(snapshots.len());
+    let mut children = Vec::new();
+    for snapshot in snapshots {
+        let node = &snapshot[1];
+        let Some(id) = node["id"].as_u64() else {
+            continue;
+        };
+        let name = match node["type"].as_i64() {
+            Some(ELEMENT_TYPE_ROOT) => None,
+            _ => Some(
+                node["displayName"]
+                    .as_str()
+                    .unwrap_or("Anonymous")
+                    .to_string(),
+            ),
+        };
+        tree.insert(id, Node { name, parent: 0 });
+        let ids = node["children"].as_array().map_or(&[][..], Vec::as_slice);
+        children.extend(
+            ids.iter()
+                .filter_map(Value::as_u64)
+                .map(|child| (child, id)),
+        );
+    }
+    for (child, parent) in children {
+        if let Some(node) = tree.get_mut(&child) {
+            node.parent = parent;
+        }
+    }
+    tree
+}
+
+// Applies the tree operations of a commit to the component tree of its root, as the Profiler
+// does in `CommitTreeBuilder.js`. Returns false if it had to stop at an operation it does not
+// know, or at operations that are cut short.
+fn apply_operations(tree: &mut HashMap<u64, Node>, operations: &[Value]) -> bool {
+    let Some(ops) = operations
+        .iter()
+        .map(Value::as_i64)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    // The operations start with the renderer and the root, and a table of the strings they use,
+    // each as its length followed by its code points. String 0 is null.
+    let Some(&table_len) = ops.get(2) else {
+        return ops.is_empty();
+    };
+    let table_end = 3 + table_len.max(0) as usize;
+    let Some(table) = ops.get(3..table_end) else {
+        return false;
+    };
+    let mut strings = vec![None];
+    let mut i = 0;
+    while i < table.len() {
+        let len = table[i].max(0) as usize;
+        let codes = table.get(i + 1..i + 1 + len).unwrap_or(&[]);
+        let string = codes
+            .iter()
+            .map(|&c| char::from_u32(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect::<String>();
+        strings.push(Some(string));
+        i += 1 + len;
+    }
+
+    let mut i = table_end;
+    while i < ops.len() {
+        let arg = |n: usize| ops.get(i + n).copied();
+        match ops[i] {
+            TREE_OPERATION_ADD => {
+                let (Some(id), Some(kind)) = (arg(1), arg(2)) else {
+                    return false;
+                };
+                // A root is followed by its strict mode compliance, profiling support, strict
+                // mode support and owner metadata; any other component by its parent, owner,
+                // name and key.
+                let node = if kind == ELEMENT_TYPE_ROOT {
+                    Node {
+                        name: None,
+                        parent: 0,
+                    }
+                } else {
+                    let (Some(parent), Some(name)) = (arg(3), arg(5)) else {
+                        return false;
+                    };
+                    let name = strings.get(name.max(0) as usize).cloned().flatten();
+                    Node {
+                        name: Some(name.unwrap_or_else(|| "Anonymous".to_string())),
+                        parent: parent as u64,
+                    }
+                };
+                tree.insert(id as u64, node);
+                i += 7;
+            }
+            TREE_OPERATION_REMOVE => {
+                let Some(n) = arg(1) else {
+                    return false;
+                };
+                let n = n.max(0) as usize;
+                for id in ops.iter().skip(i + 2).take(n) {
+                    tree.remove(&(*id as u64));
+                }
+                i += 2 + n;
+            }
+            TREE_OPERATION_REORDER_CHILDREN => {
+                let Some(n) = arg(2) else {
+                    return false;
+                };
+                i += 3 + n.max(0) as usize;
+            }
+            TREE_OPERATION_UPDATE_TREE_BASE_DURATION | TREE_OPERATION_SET_SUBTREE_MODE => i += 3,
+            TREE_OPERATION_UPDATE_ERRORS_OR_WARNINGS => i += 4,
+            TREE_OPERATION_REMOVE_ROOT => i += 1,
+            _ => return false,
+        }
+    }
+    true
+}
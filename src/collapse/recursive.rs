@@ -1,4 +1,5 @@
 use super::common::{self, CollapsePrivate};
+use crate::flamegraph::merge::split_frames;
 use std::{borrow::Cow, io};
 
 /// Recursive backtrace folder configuration options.
@@ -130,7 +131,7 @@ impl Folder {
         // String.
         let mut result = String::with_capacity(stack.len());
         let mut last = None;
-        for frame in stack.split(';') {
+        for frame in split_frames(&stack) {
             if last != Some(frame) {
                 result.push_str(frame);
                 result.push(';')
@@ -147,7 +148,7 @@ impl Folder {
     /// Determine whether or not a stack contains direct recursion.
     fn is_recursive(stack: &str) -> bool {
         let mut last = None;
-        for current in stack.split(';') {
+        for current in split_frames(stack) {
             match last {
                 None => {
                     last = Some(current);
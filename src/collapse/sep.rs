@@ -0,0 +1,181 @@
+//! Stack collapsing for the stack dumps of samples taken with Intel [SEP] (and EMON).
+//!
+//! Every sample in the dump starts with a line that isn't indented, followed by the frames of its
+//! stack on indented lines, innermost first, in `module!function+offset` notation:
+//!
+//! ```text
+//! Sample 1: cpu=0 pid=4242 tid=4243 event=CPU_CLK_UNHALTED.THREAD count=2
+//!     libc.so.6!memcpy+0x1a
+//!     app!work+0x44
+//!     app!main+0x1c
+//! ```
+//!
+//! Of the `key=value` fields of the first line, `event` names the event that was sampled, and
+//! `count` is the weight of the sample (1 if it's left out). Samples of different events are
+//! usually best kept apart with [`Options::event`].
+//!
+//!   [SEP]: https://www.intel.com/content/www/us/en/developer/tools/oneapi/vtune-profiler.html
+
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::{module_function_frame, Occurrences};
+use crate::collapse::Collapse;
+
+const EVENT_FIELD: &str = "event";
+const COUNT_FIELD: &str = "count";
+
+/// `sep` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Prefix function names with their module, as in ``libc.so.6`memcpy``.
+    ///
+    /// Default is `false`.
+    pub include_module: bool,
+
+    /// Only collapse samples of this event, such as `CPU_CLK_UNHALTED.THREAD`.
+    ///
+    /// Default is `None`, which collapses the samples of all events.
+    pub event: Option<String>,
+}
+
+/// A stack collapser for SEP stack dumps.
+///
+/// See the [module-level documentation](self) for the supported format.
+///
+/// To construct one, either use `sep::Folder::default()` or create an [`Options`] and use
+/// `sep::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Frames of the current sample, innermost first.
+    stack: Vec<String>,
+
+    /// Weight of the current sample, or `None` if it's skipped.
+    count: Option<usize>,
+
+    /// Number of samples with a count that couldn't be parsed.
+    ignored: usize,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            self.on_line(l.trim_end(), &mut occurrences);
+        }
+        self.end_sample(&mut occurrences);
+        if self.ignored > 0 {
+            warn!("Ignored {} samples with invalid counts", self.ignored);
+            self.ignored = 0;
+        }
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a sample line followed by a frame in `module!function` notation.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        let mut in_sample = false;
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let l = line.trim_end();
+            if l.trim().is_empty() {
+                in_sample = false;
+            } else if !l.starts_with(char::is_whitespace) {
+                in_sample = true;
+            } else if in_sample {
+                return Some(l.contains('!'));
+            } else {
+                return Some(false);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn on_line(&mut self, line: &str, occurrences: &mut Occurrences) {
+        if line.trim().is_empty() {
+            self.end_sample(occurrences);
+        } else if line.starts_with(char::is_whitespace) {
+            if self.count.is_some() {
+                self.stack
+                    .push(module_function_frame(line, self.opt.include_module));
+            }
+        } else {
+            self.end_sample(occurrences);
+            self.count = self.sample_count(line);
+        }
+    }
+
+    // The weight of the sample that starts with the given line, or `None` if it's skipped.
+    fn sample_count(&mut self, line: &str) -> Option<usize> {
+        let mut event = None;
+        let mut count = None;
+        for (key, value) in line
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+        {
+            match key {
+                EVENT_FIELD => event = Some(value),
+                COUNT_FIELD => count = Some(value),
+                _ => {}
+            }
+        }
+        if let Some(wanted) = &self.opt.event {
+            if event != Some(wanted.as_str()) {
+                return None;
+            }
+        }
+        match count.map(str::parse) {
+            None => Some(1),
+            Some(Ok(count)) => Some(count),
+            Some(Err(_)) => {
+                self.ignored += 1;
+                None
+            }
+        }
+    }
+
+    fn end_sample(&mut self, occurrences: &mut Occurrences) {
+        let count = self.count.take();
+        if let Some(count) = count.filter(|&count| count > 0 && !self.stack.is_empty()) {
+            self.stack.reverse();
+            occurrences.insert_or_add(self.stack.join(";"), count);
+        }
+        self.stack.clear();
+    }
+}
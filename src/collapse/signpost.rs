@@ -0,0 +1,421 @@
+//! Stack collapsing for the intervals of [os_signpost] on macOS and iOS.
+//!
+//! Apps mark intervals of their work with `os_signpost` (or `OSSignposter`), named and logged to
+//! an `OSLog` of a subsystem and category. This collapser reads those intervals from either of two
+//! exports. The JSON of `log show`, as either of:
+//!
+//! ```text
+//! log show --signpost --style ndjson --predicate 'subsystem == "com.example.app"'
+//! log show --signpost --style json --last 5m
+//! ```
+//!
+//! which pairs the begin and end events of each interval:
+//!
+//! ```text
+//! {"eventType":"signpostEvent","signpostType":"begin","signpostID":17,"signpostName":"Load",
+//!   "subsystem":"com.example.app","category":"Networking","processID":4711,
+//!   "processImagePath":"/Applications/Example.app/Contents/MacOS/Example",
+//!   "timestamp":"2024-05-01 10:00:00.120000-0700",...}
+//! ```
+//!
+//! Or the `os-signpost-interval` table of an Instruments trace, exported as XML with:
+//!
+//! ```text
+//! xctrace export --input app.trace \
+//!   --xpath '/trace-toc/run/data/table[@schema="os-signpost-interval"]'
+//! ```
+//!
+//! which holds the intervals with their duration, in the columns of its schema.
+//!
+//! Every interval is folded as `subsystem;category;name`, and counts its duration in nanoseconds.
+//! With [`Options::include_process`], stacks start with a frame for the process that logged them.
+//!
+//!   [os_signpost]: https://developer.apple.com/documentation/os/logging/recording_performance_data
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+use quick_xml::events::attributes::Attributes;
+use quick_xml::events::Event;
+use quick_xml::reader::Reader;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const SIGNPOST_TYPE_FIELD: &str = "\"signpostType\"";
+const INTERVAL_SCHEMA: &str = "os-signpost-interval";
+
+/// `signpost` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Start every stack with a frame for the process that logged the interval.
+    ///
+    /// Default is `false`.
+    pub include_process: bool,
+}
+
+/// A stack collapser for os_signpost intervals.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `signpost::Folder::default()` or create an [`Options`] and use
+/// `signpost::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// What identifies an interval between its begin and end events: the process, the subsystem and
+// category of the log, the name, and the signpost ID.
+type IntervalKey = (u64, String, String, String, u64);
+
+#[derive(Default)]
+struct Intervals {
+    // Begin times of the intervals that haven't ended yet, in nanoseconds.
+    open: HashMap<IntervalKey, Vec<i128>>,
+    // Durations of the intervals, in nanoseconds, by stack.
+    durations: HashMap<Vec<String>, u64>,
+    signposts: usize,
+    unmatched_ends: usize,
+    ignored: usize,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let mut intervals = Intervals::default();
+        let first = data.iter().find(|b| !b.is_ascii_whitespace());
+        if first == Some(&b'<') {
+            self.read_xml(&data, &mut intervals)?;
+        } else {
+            for value in serde_json::Deserializer::from_slice(&data).into_iter::<Value>() {
+                self.on_value(&value?, &mut intervals);
+            }
+        }
+        if intervals.signposts == 0 {
+            return invalid_data_error!("No signpost intervals found in input");
+        }
+        if intervals.ignored > 0 {
+            warn!(
+                "Ignored {} signposts with invalid format",
+                intervals.ignored
+            );
+        }
+        if intervals.unmatched_ends > 0 {
+            warn!(
+                "Ignored {} signpost intervals that ended without beginning",
+                intervals.unmatched_ends
+            );
+        }
+        let unended: usize = intervals.open.values().map(Vec::len).sum();
+        if unended > 0 {
+            warn!("Ignored {} signpost intervals that didn't end", unended);
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        for (stack, duration) in intervals.durations {
+            if duration > 0 {
+                occurrences.insert_or_add(stack.join(";"), duration as usize);
+            }
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for signpost events of `log show`, or for an interval table of Instruments.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.starts_with('<') {
+            return input.contains(INTERVAL_SCHEMA).then_some(true);
+        }
+        if !input.starts_with(['{', '[']) {
+            return Some(false);
+        }
+        if input.contains(SIGNPOST_TYPE_FIELD) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Handles an event of `log show`, or each of an array of them.
+    fn on_value(&self, value: &Value, intervals: &mut Intervals) {
+        let event = match value {
+            Value::Array(values) => {
+                for value in values {
+                    self.on_value(value, intervals);
+                }
+                return;
+            }
+            Value::Object(event) => event,
+            _ => return,
+        };
+        if event.get("eventType").and_then(Value::as_str) != Some("signpostEvent") {
+            return;
+        }
+        intervals.signposts += 1;
+
+        let text = |key: &str| event.get(key).and_then(Value::as_str).unwrap_or_default();
+        let number = |key: &str| event.get(key).and_then(Value::as_u64);
+        let (Some(id), Some(time)) = (number("signpostID"), timestamp(text("timestamp"))) else {
+            intervals.ignored += 1;
+            return;
+        };
+        let key = (
+            number("processID").unwrap_or(0),
+            text("subsystem").to_string(),
+            text("category").to_string(),
+            text("signpostName").to_string(),
+            id,
+        );
+
+        match text("signpostType") {
+            "begin" => intervals.open.entry(key).or_default().push(time),
+            "end" => {
+                let Some(begin) = intervals.open.get_mut(&key).and_then(Vec::pop) else {
+                    intervals.unmatched_ends += 1;
+                    return;
+                };
+                let process = text("processImagePath").rsplit('/').next().unwrap_or("");
+                let stack = self.stack(process, &key.1, &key.2, &key.3);
+                let duration = u64::try_from(time - begin).unwrap_or(0);
+                *intervals.durations.entry(stack).or_default() += duration;
+            }
+            // Events mark a point in time, rather than an interval.
+            _ => {}
+        }
+    }
+
+    fn read_xml(&self, data: &[u8], intervals: &mut Intervals) -> io::Result<()> {
+        let mut reader = Reader::from_reader(data);
+        let mut buf = Vec::new();
+
+        // The mnemonics of the columns of the interval table, in order.
+        let mut columns: Vec<String> = Vec::new();
+        let mut in_table = false;
+        let mut in_mnemonic = false;
+        // Values that later rows refer to by ID.
+        let mut values: HashMap<String, String> = HashMap::new();
+        let mut row: Option<Vec<String>> = None;
+        // The column being read, and how deep in it the reader is.
+        let mut cell: Option<Cell> = None;
+        let mut depth = 0;
+
+        loop {
+            let event = match reader.read_event_into(&mut buf) {
+                Ok(event) => event,
+                Err(e) => return invalid_data_error!("Read xml event failed: {:?}", e),
+            };
+            match event {
+                Event::Start(start) => match (start.name().into_inner(), &mut row, &mut cell) {
+                    (_, Some(_), Some(_)) => depth += 1,
+                    (_, Some(_), None) => {
+                        cell = Some(Cell::new(&start.attributes(), &values));
+                        depth = 0;
+                    }
+                    (b"schema", None, _) => {
+                        in_table = attribute(&start.attributes(), b"name").as_deref()
+                            == Some(INTERVAL_SCHEMA);
+                        if in_table {
+                            columns.clear();
+                        }
+                    }
+                    (b"mnemonic", None, _) => in_mnemonic = in_table,
+                    (b"row", None, _) if in_table => row = Some(Vec::new()),
+                    _ => {}
+                },
+                Event::Empty(empty) => match (&mut row, empty.name().into_inner()) {
+                    (Some(row), _) if cell.is_none() => {
+                        row.push(Cell::new(&empty.attributes(), &values).value());
+                    }
+                    (None, b"row") if in_table => intervals.ignored += 1,
+                    _ => {}
+                },
+                Event::Text(text) => {
+                    let text = match text.unescape() {
+                        Ok(text) => text,
+                        Err(e) => return invalid_data_error!("Invalid xml text: {:?}", e),
+                    };
+                    match &mut cell {
+                        // Only the text of the column itself is its value.
+                        Some(cell) if depth == 0 => cell.text.push_str(text.trim()),
+                        Some(_) => {}
+                        None if in_mnemonic => columns.push(text.trim().to_string()),
+                        None => {}
+                    }
+                }
+                Event::End(end) => match (end.name().into_inner(), &mut row) {
+                    (_, Some(_)) if depth > 0 => depth -= 1,
+                    (_, Some(row)) if cell.is_some() => {
+                        let cell = cell.take().unwrap();
+                        let id = cell.id.clone();
+                        let value = cell.value();
+                        if let Some(id) = id {
+                            values.insert(id, value.clone());
+                        }
+                        row.push(value);
+                    }
+                    (b"row", Some(_)) => {
+                        let row = row.take().unwrap();
+                        self.on_row(&columns, &row, intervals);
+                    }
+                    (b"mnemonic", None) => in_mnemonic = false,
+                    (b"node", None) => in_table = false,
+                    _ => {}
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+        Ok(())
+    }
+
+    // Adds the duration of an interval of an Instruments table to its stack.
+    fn on_row(&self, columns: &[String], row: &[String], intervals: &mut Intervals) {
+        intervals.signposts += 1;
+        let column = |name: &str| {
+            columns
+                .iter()
+                .position(|c| c == name)
+                .and_then(|i| row.get(i))
+                .map_or("", String::as_str)
+        };
+        let Ok(duration) = column("duration").parse::<u64>() else {
+            intervals.ignored += 1;
+            return;
+        };
+        // Processes are formatted as `Example (4711)`.
+        let process = column("process");
+        let process = match process.rsplit_once(" (") {
+            Some((name, pid)) if pid.ends_with(')') => name,
+            _ => process,
+        };
+        let stack = self.stack(
+            process,
+            column("subsystem"),
+            column("category"),
+            column("name"),
+        );
+        *intervals.durations.entry(stack).or_default() += duration;
+    }
+
+    fn stack(&self, process: &str, subsystem: &str, category: &str, name: &str) -> Vec<String> {
+        let process = self.opt.include_process.then_some(process);
+        process
+            .into_iter()
+            .chain([subsystem, category, name])
+            .map(|frame| match frame {
+                "" => "[unknown]".to_string(),
+                frame => frame.replace(';', ":"),
+            })
+            .collect()
+    }
+}
+
+// A column of a row of an Instruments table, while it's read.
+struct Cell {
+    // The ID that later rows can refer to its value by.
+    id: Option<String>,
+    text: String,
+    // The formatted value, or the value referred to, for columns without text.
+    fmt: String,
+}
+
+impl Cell {
+    // Sentinels have an empty value.
+    fn new(attributes: &Attributes, values: &HashMap<String, String>) -> Self {
+        let reference = attribute(attributes, b"ref");
+        let fmt = match reference {
+            Some(reference) => values.get(&reference).cloned(),
+            None => attribute(attributes, b"fmt"),
+        };
+        Cell {
+            id: attribute(attributes, b"id"),
+            text: String::new(),
+            fmt: fmt.unwrap_or_default(),
+        }
+    }
+
+    fn value(self) -> String {
+        if self.text.is_empty() {
+            self.fmt
+        } else {
+            self.text
+        }
+    }
+}
+
+fn attribute(attributes: &Attributes, key: &[u8]) -> Option<String> {
+    attributes
+        .clone()
+        .filter_map(Result::ok)
+        .find(|attribute| attribute.key.into_inner() == key)
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+// Parses a time stamp of `log show`, as in `2024-05-01 10:00:00.120000-0700`, into nanoseconds
+// since the Unix epoch.
+fn timestamp(timestamp: &str) -> Option<i128> {
+    let (date, time) = timestamp.trim().split_once([' ', 'T'])?;
+    let mut date = date.splitn(3, '-').map(|n| n.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    let offset_at = time.find(['+', '-', 'Z']).unwrap_or(time.len());
+    let (time, offset) = time.split_at(offset_at);
+    let offset = match offset.as_bytes().first() {
+        Some(&sign @ (b'+' | b'-')) => {
+            let digits: String = offset[1..].chars().filter(|&c| c != ':').collect();
+            let hours: i64 = digits.get(..2)?.parse().ok()?;
+            let minutes: i64 = digits
+                .get(2..)
+                .filter(|m| !m.is_empty())
+                .map_or(Some(0), |m| m.parse().ok())?;
+            let offset = hours * 3600 + minutes * 60;
+            if sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => 0,
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(|n| n.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let nanos: i128 = format!("{:0<9}", fraction).parse().ok()?;
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds - offset;
+    Some(i128::from(seconds) * 1_000_000_000 + nanos)
+}
+
+// The number of days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,338 @@
+//! Stack collapsing for the span trees of distributed traces, as exported by [Jaeger], [Zipkin]
+//! and [OpenTelemetry].
+//!
+//! The input is JSON with the spans of one or more traces, in any of these formats:
+//!
+//!  - The traces that the Jaeger API and UI export, as in `{"data":[{"traceID":...,"spans":[...],
+//!    "processes":{...}}]}`, or a single one of them.
+//!  - The spans of the Zipkin v2 API, as in `[{"traceId":...,"id":...,"parentId":...,
+//!    "name":...,"timestamp":...,"duration":...,"localEndpoint":{"serviceName":...}}]`, or a list
+//!    of traces that are lists of spans.
+//!  - OTLP traces in their JSON encoding, as in `{"resourceSpans":[{"resource":...,
+//!    "scopeSpans":[{"spans":[...]}]}]}`, such as one per line as the OpenTelemetry Collector's
+//!    file exporter writes them.
+//!
+//! Every span becomes a frame for its operation, below the frames of the spans it was started
+//! from, and below a frame for its service when that differs from the service of its parent, as in
+//! `frontend;HTTP GET /cart;cart;GetCart;redis;HGETALL`. A span is weighted by its self time, in
+//! microseconds: how long it took, minus the time that any of its child spans were running, so
+//! that the width of a frame is how long its span took. Child spans that run concurrently each
+//! count their own time, so their frames can add up to more than that of their parent. Stacks are
+//! added up across all traces.
+//!
+//! Spans whose parent isn't in the trace, as in traces that were only partly sampled, are folded
+//! as roots.
+//!
+//!   [Jaeger]: https://www.jaegertracing.io
+//!   [Zipkin]: https://zipkin.io
+//!   [OpenTelemetry]: https://opentelemetry.io
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// The service of spans that don't name one, as OpenTelemetry names it.
+const UNKNOWN_SERVICE: &str = "unknown_service";
+
+// Fields that only span trees have, in each of the formats.
+const SPAN_FIELDS: &[&str] = &[
+    "\"resourceSpans\"",
+    "\"operationName\"",
+    "\"localEndpoint\"",
+];
+
+/// `spans` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Leave out the frames of services, so that every frame is an operation.
+    ///
+    /// Default is `false`.
+    pub omit_services: bool,
+}
+
+/// A stack collapser for the span trees of Jaeger, Zipkin and OpenTelemetry traces.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `spans::Folder::default()` or create an [`Options`] and use
+/// `spans::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// A span, with its times in nanoseconds.
+struct Span {
+    id: String,
+    parent: Option<String>,
+    service: String,
+    operation: String,
+    start: u64,
+    end: u64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        // The spans of every trace, by the id of the trace, in the order they come in.
+        let mut traces: HashMap<String, Vec<Span>> = HashMap::new();
+        let mut invalid = 0;
+        for value in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+            add_spans(&value?, &mut traces, &mut invalid);
+        }
+        if traces.is_empty() {
+            return invalid_data_error!("No spans found in input");
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        let mut unreachable = 0;
+        for spans in traces.values() {
+            unreachable += self.fold_trace(spans, &mut occurrences);
+        }
+        if invalid > 0 {
+            warn!("Ignored {} spans without an id, a name or times", invalid);
+        }
+        if unreachable > 0 {
+            warn!("Ignored {} spans whose parents form a cycle", unreachable);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for JSON with the fields of Jaeger, Zipkin or OTLP spans.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if !input.trim_start().starts_with(['{', '[']) {
+            return Some(false);
+        }
+        if SPAN_FIELDS.iter().any(|field| input.contains(field)) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Folds the spans of a trace, from its roots down, and returns how many of them weren't
+    // reached from a root.
+    fn fold_trace(&self, spans: &[Span], occurrences: &mut Occurrences) -> usize {
+        let by_id: HashMap<&str, usize> = spans
+            .iter()
+            .enumerate()
+            .map(|(i, span)| (span.id.as_str(), i))
+            .collect();
+        let mut children = vec![Vec::new(); spans.len()];
+        let mut roots = Vec::new();
+        for (i, span) in spans.iter().enumerate() {
+            match span.parent.as_deref().and_then(|parent| by_id.get(parent)) {
+                Some(&parent) if parent != i => children[parent].push(i),
+                _ => roots.push(i),
+            }
+        }
+
+        let mut reached = 0;
+        // The spans to fold, with the stack and the service of their parents.
+        let mut pending: Vec<(usize, String, Option<&str>)> = roots
+            .into_iter()
+            .rev()
+            .map(|i| (i, String::new(), None))
+            .collect();
+        while let Some((i, mut stack, parent_service)) = pending.pop() {
+            reached += 1;
+            let span = &spans[i];
+            if !self.opt.omit_services && parent_service != Some(span.service.as_str()) {
+                push_frame(&mut stack, &span.service);
+            }
+            push_frame(&mut stack, &span.operation);
+
+            let busy = busy_time(span, children[i].iter().map(|&child| &spans[child]));
+            let self_time = (span.end - span.start).saturating_sub(busy);
+            let us = (self_time as f64 / 1e3).round() as usize;
+            if us > 0 {
+                occurrences.insert_or_add(stack.clone(), us);
+            }
+            for &child in children[i].iter().rev() {
+                pending.push((child, stack.clone(), Some(span.service.as_str())));
+            }
+        }
+        spans.len() - reached
+    }
+}
+
+fn push_frame(stack: &mut String, name: &str) {
+    if !stack.is_empty() {
+        stack.push(';');
+    }
+    stack.push_str(&name.replace(';', ":"));
+}
+
+// How long any of the children of a span were running while it was, in nanoseconds.
+fn busy_time<'a>(span: &Span, children: impl Iterator<Item = &'a Span>) -> u64 {
+    let mut intervals: Vec<(u64, u64)> = children
+        .map(|child| (child.start.max(span.start), child.end.min(span.end)))
+        .filter(|(start, end)| start < end)
+        .collect();
+    intervals.sort_unstable();
+    let mut busy = 0;
+    let mut covered = span.start;
+    for (start, end) in intervals {
+        let start = start.max(covered);
+        if end > start {
+            busy += end - start;
+            covered = end;
+        }
+    }
+    busy
+}
+
+// Adds the spans of a JSON value in any of the supported formats to their traces.
+fn add_spans(value: &Value, traces: &mut HashMap<String, Vec<Span>>, invalid: &mut usize) {
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                add_spans(value, traces, invalid);
+            }
+        }
+        Value::Object(object) if object.contains_key("resourceSpans") => {
+            add_otlp_spans(value, traces, invalid);
+        }
+        Value::Object(object) if object.contains_key("data") => {
+            add_spans(&value["data"], traces, invalid);
+        }
+        Value::Object(object) if object.contains_key("spans") => {
+            add_jaeger_spans(value, traces, invalid);
+        }
+        Value::Object(object) if object.contains_key("traceId") => {
+            add_span(traces, invalid, parse_zipkin_span(value));
+        }
+        _ => {}
+    }
+}
+
+fn add_span(
+    traces: &mut HashMap<String, Vec<Span>>,
+    invalid: &mut usize,
+    span: Option<(String, Span)>,
+) {
+    match span {
+        Some((trace, span)) => traces.entry(trace).or_default().push(span),
+        None => *invalid += 1,
+    }
+}
+
+// Adds the spans of a Jaeger trace, whose services are those of the processes of the trace.
+fn add_jaeger_spans(trace: &Value, traces: &mut HashMap<String, Vec<Span>>, invalid: &mut usize) {
+    let processes = &trace["processes"];
+    for span in trace["spans"].as_array().into_iter().flatten() {
+        let process = match span["processID"].as_str() {
+            Some(id) => &processes[id],
+            None => &span["process"],
+        };
+        let service = process["serviceName"].as_str();
+        add_span(traces, invalid, parse_jaeger_span(span, service));
+    }
+}
+
+fn parse_jaeger_span(span: &Value, service: Option<&str>) -> Option<(String, Span)> {
+    let references = span["references"].as_array().map_or(&[][..], Vec::as_slice);
+    // A span is started from the span it's a child of, or else from the one it follows.
+    let parent = references
+        .iter()
+        .find(|reference| reference["refType"] == "CHILD_OF")
+        .or_else(|| references.first())
+        .and_then(|reference| reference["spanID"].as_str())
+        .or_else(|| span["parentSpanID"].as_str())
+        .filter(|parent| !parent.is_empty());
+    let start = number(&span["startTime"])?.checked_mul(1_000)?;
+    let duration = number(&span["duration"])?.checked_mul(1_000)?;
+    let trace = span["traceID"].as_str()?.to_string();
+    let span = Span {
+        id: span["spanID"].as_str()?.to_string(),
+        parent: parent.map(str::to_string),
+        service: service.unwrap_or(UNKNOWN_SERVICE).to_string(),
+        operation: span["operationName"].as_str()?.to_string(),
+        start,
+        end: start.checked_add(duration)?,
+    };
+    Some((trace, span))
+}
+
+fn parse_zipkin_span(span: &Value) -> Option<(String, Span)> {
+    let start = number(&span["timestamp"])?.checked_mul(1_000)?;
+    // Spans that are still running have no duration.
+    let duration = number(&span["duration"]).unwrap_or(0).checked_mul(1_000)?;
+    let trace = span["traceId"].as_str()?.to_string();
+    let span = Span {
+        id: span["id"].as_str()?.to_string(),
+        parent: span["parentId"].as_str().map(str::to_string),
+        service: span["localEndpoint"]["serviceName"]
+            .as_str()
+            .unwrap_or(UNKNOWN_SERVICE)
+            .to_string(),
+        operation: span["name"].as_str()?.to_string(),
+        start,
+        end: start.checked_add(duration)?,
+    };
+    Some((trace, span))
+}
+
+// Adds the spans of OTLP traces, whose services are named by the attributes of their resources.
+fn add_otlp_spans(value: &Value, traces: &mut HashMap<String, Vec<Span>>, invalid: &mut usize) {
+    for resource_spans in value["resourceSpans"].as_array().into_iter().flatten() {
+        let service = resource_spans["resource"]["attributes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|attribute| attribute["key"] == "service.name")
+            .and_then(|attribute| attribute["value"]["stringValue"].as_str());
+        // Older versions of OTLP call the scopes of spans instrumentation libraries.
+        let scopes = ["scopeSpans", "instrumentationLibrarySpans"]
+            .iter()
+            .flat_map(|field| resource_spans[field].as_array().into_iter().flatten());
+        for scope_spans in scopes {
+            for span in scope_spans["spans"].as_array().into_iter().flatten() {
+                add_span(traces, invalid, parse_otlp_span(span, service));
+            }
+        }
+    }
+}
+
+fn parse_otlp_span(span: &Value, service: Option<&str>) -> Option<(String, Span)> {
+    let start = number(&span["startTimeUnixNano"])?;
+    let end = number(&span["endTimeUnixNano"])?;
+    let trace = span["traceId"].as_str()?.to_string();
+    let span = Span {
+        id: span["spanId"].as_str()?.to_string(),
+        parent: span["parentSpanId"]
+            .as_str()
+            .filter(|parent| !parent.is_empty())
+            .map(str::to_string),
+        service: service.unwrap_or(UNKNOWN_SERVICE).to_string(),
+        operation: span["name"].as_str()?.to_string(),
+        start,
+        end: end.max(start),
+    };
+    Some((trace, span))
+}
+
+// Reads a number that may be written as a string, as the 64-bit integers of OTLP are.
+fn number(value: &Value) -> Option<u64> {
+    match value {
+        Value::String(s) => s.parse().ok(),
+        _ => value.as_u64(),
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::collapse::matcher::is_kernel;
+
+// Symbols sorted by their start address, each covering the addresses up to its end.
+#[derive(Debug, Default)]
+struct SymbolTable {
+    symbols: Vec<(u64, u64, String)>,
+}
+
+impl SymbolTable {
+    // Reads the symbols of a copy of `/proc/kallsyms`, one per line like:
+    //
+    //     ffffffff81000000 T _stext
+    //     ffffffffc0a01040 t nf_conntrack_in	[nf_conntrack]
+    //
+    // kallsyms has no sizes, so every function covers the addresses up to the next one, as perf
+    // itself has it, and the last one all addresses after it. Symbols that aren't code are left
+    // out.
+    fn from_kallsyms<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut starts = Vec::new();
+        let mut hidden = false;
+        for line in reader.lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let (Some(address), Some(kind), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let Ok(address) = u64::from_str_radix(address, 16) else {
+                continue;
+            };
+            if address == 0 {
+                hidden = true;
+                continue;
+            }
+            if matches!(kind, "T" | "t" | "W" | "w") {
+                starts.push((address, name.to_string()));
+            }
+        }
+        if starts.is_empty() && hidden {
+            return invalid_data_error!(
+                "kallsyms has no addresses, as when it is read without root"
+            );
+        }
+        // Aliases of a function keep the name that comes first.
+        starts.sort_by_key(|&(address, _)| address);
+        starts.dedup_by_key(|&mut (address, _)| address);
+        let ends: Vec<u64> = starts
+            .iter()
+            .skip(1)
+            .map(|&(address, _)| address)
+            .chain(Some(u64::MAX))
+            .collect();
+        let symbols = starts
+            .into_iter()
+            .zip(ends)
+            .map(|((start, name), end)| (start, end, name))
+            .collect();
+        Ok(SymbolTable { symbols })
+    }
+
+    // Reads a perf map, as written for `perf` by JIT compilers and perf-map-agent, with a symbol
+    // per line like:
+    //
+    //     7f722d142740 1c0 Ljava/io/PrintStream;::print
+    //
+    // Symbols that come later win over earlier ones at the same address, as for code that was
+    // compiled again.
+    fn from_perf_map<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut symbols = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut fields = line.trim().splitn(3, ' ');
+            let (Some(start), Some(size), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let (Some(start), Some(size)) = (parse_hex(start), parse_hex(size)) else {
+                continue;
+            };
+            symbols.push((start, start.saturating_add(size), name.trim().to_string()));
+        }
+        symbols.sort_by_key(|&(start, _, _)| start);
+        Ok(SymbolTable { symbols })
+    }
+
+    fn lookup(&self, address: u64) -> Option<&str> {
+        let i = self
+            .symbols
+            .partition_point(|&(start, _, _)| start <= address)
+            .checked_sub(1)?;
+        let (_, end, name) = &self.symbols[i];
+        (address < *end).then_some(name.as_str())
+    }
+}
+
+/// Symbols to name the addresses that `perf script` couldn't, read from the files of
+/// `perf::Options::kallsyms` and `perf::Options::symbol_maps`.
+#[derive(Debug, Default)]
+pub(crate) struct Symbols {
+    kernel: SymbolTable,
+    // The symbol maps, with the process they are for, if they are named for one.
+    maps: Vec<(Option<String>, SymbolTable)>,
+}
+
+impl Symbols {
+    pub(crate) fn from_files(kallsyms: Option<&Path>, maps: &[PathBuf]) -> io::Result<Self> {
+        let open = |path: &Path| {
+            File::open(path).map(BufReader::new).map_err(|e| {
+                io::Error::new(e.kind(), format!("Error reading {}: {}", path.display(), e))
+            })
+        };
+        let kernel = match kallsyms {
+            Some(path) => SymbolTable::from_kallsyms(open(path)?)?,
+            None => SymbolTable::default(),
+        };
+        let mut tables = Vec::with_capacity(maps.len());
+        for path in maps {
+            let pid = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("perf-")?.strip_suffix(".map"))
+                .filter(|pid| pid.chars().all(|c| c.is_ascii_digit()))
+                .map(str::to_string);
+            tables.push((pid, SymbolTable::from_perf_map(open(path)?)?));
+        }
+        Ok(Symbols {
+            kernel,
+            maps: tables,
+        })
+    }
+
+    /// Names the address `pc` of a stack line of process `pid` (`?` if unknown), in `module`.
+    ///
+    /// Kernel addresses are looked up in kallsyms, and all others in the symbol maps that are
+    /// for the process or for no process in particular.
+    pub(crate) fn lookup(&self, pid: &str, module: &str, pc: &str) -> Option<&str> {
+        let address = parse_hex(pc)?;
+        let kernel = is_kernel(module)
+            || (module == "[unknown]"
+                && self
+                    .kernel
+                    .symbols
+                    .first()
+                    .is_some_and(|&(start, _, _)| address >= start));
+        if kernel {
+            return self.kernel.lookup(address);
+        }
+        self.maps
+            .iter()
+            .filter(|(map_pid, _)| pid == "?" || map_pid.as_deref().map_or(true, |p| p == pid))
+            .find_map(|(_, table)| table.lookup(address))
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    u64::from_str_radix(s, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kallsyms_lookup() {
+        let kallsyms = "\
+0000000000000000 A fixed_percpu_data
+ffffffff81000000 T _stext
+ffffffff81000000 T startup_64
+ffffffff81000100 t secondary_startup_64
+ffffffff81a00000 D some_data
+ffffffff81000200 T do_idle
+";
+        let table = SymbolTable::from_kallsyms(kallsyms.as_bytes()).unwrap();
+        assert_eq!(table.lookup(0xffffffff81000000), Some("_stext"));
+        assert_eq!(
+            table.lookup(0xffffffff810001ff),
+            Some("secondary_startup_64")
+        );
+        assert_eq!(table.lookup(0xffffffff81000010), Some("_stext"));
+        assert_eq!(table.lookup(0xffffffff81a00000), Some("do_idle"));
+        assert_eq!(table.lookup(0xfffffffe00000000), None);
+
+        let hidden = "0000000000000000 T _stext\n0000000000000000 T do_idle\n";
+        assert!(SymbolTable::from_kallsyms(hidden.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn perf_map_lookup() {
+        let map = "\
+7f0000001000 100 Interpreter
+0x7f0000002000 0x40 LFoo;::bar
+7f0000002000 20 LFoo;::bar recompiled
+";
+        let table = SymbolTable::from_perf_map(map.as_bytes()).unwrap();
+        assert_eq!(table.lookup(0x7f00000010ff), Some("Interpreter"));
+        assert_eq!(table.lookup(0x7f0000001100), None);
+        assert_eq!(table.lookup(0x7f0000002010), Some("LFoo;::bar recompiled"));
+    }
+}
@@ -0,0 +1,203 @@
+//! Stack collapsing for the boot-time data of [systemd-analyze].
+//!
+//! The input is the SVG that `systemd-analyze plot` draws, the text that `systemd-analyze dump`
+//! prints, or both of them concatenated:
+//!
+//! ```text
+//! { systemd-analyze plot; systemd-analyze dump; } > boot.txt
+//! ```
+//!
+//! The plot labels every unit with the time it took to activate, as in
+//! `NetworkManager-wait-online.service (5.120s)`, and the dump lists the targets that want or
+//! require every unit. Every stack runs from the outermost target that (indirectly) wants a unit,
+//! such as `graphical.target;multi-user.target;NetworkManager-wait-online.service`, and counts
+//! the microseconds the unit took to activate. Units that no target wants, or that are not in the
+//! dump, are folded on their own.
+//!
+//! Without a plot, the units of the dump have no activation times to count, and are left out.
+//!
+//!   [systemd-analyze]: https://www.freedesktop.org/software/systemd/man/latest/systemd-analyze.html
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// The types of units, as the suffixes of their names.
+const UNIT_TYPES: &[&str] = &[
+    ".service",
+    ".socket",
+    ".target",
+    ".device",
+    ".mount",
+    ".automount",
+    ".swap",
+    ".timer",
+    ".path",
+    ".slice",
+    ".scope",
+];
+
+// The dependencies in the dump that name the targets a unit is started for.
+const WANTED_BY: &[&str] = &["WantedBy:", "RequiredBy:", "UpheldBy:"];
+
+/// `systemd` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Fold every unit on its own, without the targets that want it.
+    ///
+    /// Default is `false`.
+    pub flat: bool,
+}
+
+/// A stack collapser for the output of `systemd-analyze plot` and `systemd-analyze dump`.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `systemd::Folder::default()` or create an [`Options`] and use
+/// `systemd::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+
+        // The activation time of every unit in the plot, in the order of the plot.
+        let mut units: Vec<(String, usize)> = Vec::new();
+        let mut rest = input.as_str();
+        while let Some(start) = rest.find("<text") {
+            rest = &rest[start..];
+            let Some(end) = rest.find("</text>") else {
+                break;
+            };
+            let text = &rest[..end];
+            rest = &rest[end..];
+            let Some(label) = text.find('>').map(|i| unescape(&text[i + 1..])) else {
+                continue;
+            };
+            if let Some(unit) = parse_label(&label) {
+                units.push(unit);
+            }
+        }
+
+        // The target that wants every unit in the dump.
+        let mut wanted_by: HashMap<&str, &str> = HashMap::new();
+        let mut unit = None;
+        for line in input.lines() {
+            if let Some(name) = line.strip_prefix("-> Unit ") {
+                unit = name.strip_suffix(':');
+                continue;
+            }
+            let Some(unit) = unit else {
+                continue;
+            };
+            let line = line.trim();
+            let Some(target) = WANTED_BY
+                .iter()
+                .find_map(|dependency| line.strip_prefix(dependency))
+                .and_then(|targets| targets.split_whitespace().next())
+            else {
+                continue;
+            };
+            if target.ends_with(".target") {
+                wanted_by.entry(unit).or_insert(target);
+            }
+        }
+
+        if units.is_empty() {
+            if wanted_by.is_empty() {
+                return invalid_data_error!("No units found in input");
+            }
+            warn!("No activation times found; the input needs the output of systemd-analyze plot");
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        for (name, us) in units {
+            if us == 0 {
+                continue;
+            }
+            let mut stack = name.replace(';', ":");
+            if !self.opt.flat {
+                let mut unit = name.as_str();
+                let mut depth = 0;
+                // Dependencies can go around in circles, so they are followed only so far.
+                while let Some(&target) = wanted_by.get(unit).filter(|_| depth < wanted_by.len()) {
+                    stack = format!("{};{}", target.replace(';', ":"), stack);
+                    unit = target;
+                    depth += 1;
+                }
+            }
+            occurrences.insert_or_add(stack, us);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the SVG of `systemd-analyze plot` or the units of `systemd-analyze dump`.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if input.contains("-> Unit ") {
+            return Some(true);
+        }
+        if !input.trim_start().starts_with('<') {
+            return Some(false);
+        }
+        input.contains("Startup finished in").then_some(true)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Parses the label of a unit in the plot, as in `foo.service (1.234s)`, into the unit and its
+// activation time in microseconds.
+fn parse_label(label: &str) -> Option<(String, usize)> {
+    let (name, time) = label.trim().strip_suffix(')')?.split_once(" (")?;
+    if !UNIT_TYPES.iter().any(|unit_type| name.ends_with(unit_type)) {
+        return None;
+    }
+    Some((name.to_string(), parse_timespan(time)?))
+}
+
+// Parses a time span as systemd formats it, such as `1min 2.345s` or `120ms`, into microseconds.
+fn parse_timespan(time: &str) -> Option<usize> {
+    let mut us = 0.0;
+    for part in time.split_whitespace() {
+        let unit_start = part.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let (value, unit) = part.split_at(unit_start);
+        let value: f64 = value.parse().ok()?;
+        let factor = match unit {
+            "us" | "μs" => 1.0,
+            "ms" => 1e3,
+            "s" => 1e6,
+            "min" => 60e6,
+            "h" => 3600e6,
+            "d" => 86400e6,
+            _ => return None,
+        };
+        us += value * factor;
+    }
+    Some(us.round() as usize)
+}
+
+// Replaces the XML entities that unit names can contain.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
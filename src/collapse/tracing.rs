@@ -0,0 +1,331 @@
+//! Stack collapsing for the spans of async (and other) Rust programs instrumented with [tracing].
+//!
+//! The input is the log of a program whose [`tracing-subscriber`] formatter records span close
+//! events, such as with `fmt().with_span_events(FmtSpan::CLOSE)`. Each close event says how long
+//! its span was busy (entered) and idle (open, but not entered), as in:
+//!
+//! ```text
+//! 2024-03-01T12:00:00.000000Z  INFO request{id=7}:query{table="users"}: app::db: close time.busy=1.20ms time.idle=350µs
+//! ```
+//!
+//! Both the default text format (with or without timestamps, levels, targets and ANSI colors) and
+//! the JSON format are understood. Every span becomes a frame named after it, within the spans it
+//! was opened in, and is weighted by how long it was busy, in nanoseconds, minus the time its
+//! child spans were busy. That makes the width of a frame how long its span was busy in total,
+//! which for a task that awaits is how long it was actually polled.
+//!
+//! With [`Options::idle`], spans are weighted by how long they were idle instead. A span being idle
+//! doesn't say anything about the spans within it, so every span is weighted by its own idle time,
+//! and the width of a frame is that plus the idle time of the spans within it.
+//!
+//!   [tracing]: https://docs.rs/tracing
+//!   [`tracing-subscriber`]: https://docs.rs/tracing-subscriber
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const CLOSE_MESSAGE: &str = "close";
+const BUSY_FIELD: &str = "time.busy";
+const IDLE_FIELD: &str = "time.idle";
+const LEVELS: &[&str] = &["TRACE", "DEBUG", "INFO", "WARN", "ERROR"];
+
+/// `tracing` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Weight spans by how long they were idle rather than busy.
+    ///
+    /// Default is `false`.
+    pub idle: bool,
+
+    /// Name frames after their spans along with the fields they were recorded with, such as
+    /// `query{table="users"}`, rather than just the names of the spans.
+    ///
+    /// Default is `false`.
+    pub include_fields: bool,
+}
+
+/// A stack collapser for the span close events of `tracing-subscriber`.
+///
+/// See the [module-level documentation](self) for the supported formats.
+///
+/// To construct one, either use `tracing::Folder::default()` or create an [`Options`] and use
+/// `tracing::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// How long the spans that closed within each stack were busy, by that stack.
+    children_busy: HashMap<String, u64>,
+
+    opt: Options,
+}
+
+// A span close event.
+struct Close {
+    stack: String,
+    busy: u64,
+    idle: u64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut ignored = 0;
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim();
+            if !l.contains(BUSY_FIELD) {
+                continue;
+            }
+            match self.parse_close(l) {
+                Some(close) => self.on_close(close, &mut occurrences),
+                None => ignored += 1,
+            }
+        }
+        if ignored > 0 {
+            warn!("Ignored {} span close events with invalid format", ignored);
+        }
+        self.children_busy.clear();
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a span close event among the first lines of the input.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        let mut has_lines = false;
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            has_lines = true;
+            if line.contains(BUSY_FIELD) && self.parse_close(line).is_some() {
+                return Some(true);
+            }
+        }
+        // Close events may be rare among other events, so don't rule the input out for lack of them.
+        if has_lines {
+            None
+        } else {
+            Some(false)
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn on_close(&mut self, close: Close, occurrences: &mut Occurrences) {
+        let children_busy = self.children_busy.remove(&close.stack).unwrap_or(0);
+        if let Some((parent, _)) = close.stack.rsplit_once(';') {
+            *self.children_busy.entry(parent.to_string()).or_default() += close.busy;
+        }
+
+        let count = if self.opt.idle {
+            close.idle
+        } else {
+            close.busy.saturating_sub(children_busy)
+        };
+        if count > 0 {
+            occurrences.insert_or_add(close.stack, count as usize);
+        }
+    }
+
+    fn parse_close(&self, line: &str) -> Option<Close> {
+        if line.starts_with('{') {
+            self.parse_json_close(line)
+        } else {
+            self.parse_text_close(&strip_ansi(line))
+        }
+    }
+
+    // `[timestamp] [LEVEL] span{fields}:span{fields}: [target:] close time.busy=1ms time.idle=2µs`
+    fn parse_text_close(&self, line: &str) -> Option<Close> {
+        let message = format!(" {} {}=", CLOSE_MESSAGE, BUSY_FIELD);
+        let (context, times) = line.split_once(&message)?;
+        let mut times = times.split_whitespace();
+        let busy = parse_duration(times.next()?)?;
+        let idle = times.find_map(|field| field.strip_prefix(IDLE_FIELD)?.strip_prefix('='))?;
+        let idle = parse_duration(idle)?;
+
+        // Skip the timestamp and the level, if any.
+        let mut context = context.trim_start();
+        for _ in 0..2 {
+            let (word, rest) = context.split_once(' ').unwrap_or((context, ""));
+            if LEVELS.contains(&word) || word.starts_with(|c: char| c.is_ascii_digit()) {
+                context = rest.trim_start();
+            }
+        }
+
+        let mut stack = String::new();
+        let mut rest = context;
+        loop {
+            let end = rest.find(['{', ':'])?;
+            let name = &rest[..end];
+            if name.is_empty() || name.contains(' ') {
+                return None;
+            }
+            rest = &rest[end..];
+            let mut fields = "";
+            if rest.starts_with('{') {
+                let len = fields_len(rest)?;
+                fields = &rest[..len];
+                rest = &rest[len..];
+            }
+            rest = rest.strip_prefix(':')?;
+
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            self.push_frame(&mut stack, name, fields);
+
+            // The target, if any, follows the spans after a space.
+            if rest.is_empty() || rest.starts_with(' ') {
+                break;
+            }
+        }
+
+        Some(Close { stack, busy, idle })
+    }
+
+    // `{"fields":{"message":"close","time.busy":"1ms","time.idle":"2µs"},"spans":[...],...}`
+    fn parse_json_close(&self, line: &str) -> Option<Close> {
+        let event: serde_json::Value = serde_json::from_str(line).ok()?;
+        let fields = &event["fields"];
+        if fields["message"] != CLOSE_MESSAGE {
+            return None;
+        }
+        let busy = parse_duration(fields[BUSY_FIELD].as_str()?)?;
+        let idle = parse_duration(fields[IDLE_FIELD].as_str()?)?;
+
+        // The list of spans includes the one that closed, but is left out if the formatter was
+        // told to, in which case only the span that closed is known.
+        let spans = match event["spans"].as_array() {
+            Some(spans) if !spans.is_empty() => spans.iter().collect(),
+            _ if event["span"].is_object() => vec![&event["span"]],
+            _ => return None,
+        };
+
+        let mut stack = String::new();
+        for span in spans {
+            let name = span["name"].as_str()?;
+            let fields: Vec<_> = span
+                .as_object()?
+                .iter()
+                .filter(|(key, _)| *key != "name")
+                .map(|(key, value)| {
+                    let value = value
+                        .as_str()
+                        .map_or_else(|| value.to_string(), str::to_string);
+                    format!("{}={}", key, value)
+                })
+                .collect();
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            if fields.is_empty() {
+                self.push_frame(&mut stack, name, "");
+            } else {
+                self.push_frame(&mut stack, name, &format!("{{{}}}", fields.join(" ")));
+            }
+        }
+
+        Some(Close { stack, busy, idle })
+    }
+
+    fn push_frame(&self, stack: &mut String, name: &str, fields: &str) {
+        stack.push_str(&name.replace(';', ":"));
+        if self.opt.include_fields {
+            stack.push_str(&fields.replace(';', ":"));
+        }
+    }
+}
+
+// The length of the fields of a span at the start of `s`, braces included, which may themselves
+// contain braces and quoted strings.
+fn fields_len(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '{' if !in_quotes => depth += 1,
+            '}' if !in_quotes => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Durations are formatted like `1.20ms`, `350µs`, `12.0ns` or `3.00s`.
+fn parse_duration(duration: &str) -> Option<u64> {
+    let i = duration.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (value, unit) = duration.split_at(i);
+    let value: f64 = value.parse().ok()?;
+    let nanoseconds_per_unit = match unit {
+        "ns" => 1.0,
+        "µs" | "us" => 1e3,
+        "ms" => 1e6,
+        "s" => 1e9,
+        _ => return None,
+    };
+    Some((value * nanoseconds_per_unit).round() as u64)
+}
+
+// Removes the escape sequences for colors that the text format uses when logging to a terminal.
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
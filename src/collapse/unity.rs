@@ -0,0 +1,146 @@
+//! Stack collapsing for the captures of the [Unity Profiler], as exported to JSON.
+//!
+//! The Profiler saves captures as binary `.data` files, which only the Unity Editor reads. An
+//! editor script can walk the hierarchy of every frame of a capture through
+//! `ProfilerDriver.GetHierarchyFrameDataView` and write it out as JSON of this shape, with the
+//! total (inclusive) time of every sample in milliseconds, as in the Hierarchy view:
+//!
+//! ```json
+//! {
+//!   "frames": [
+//!     {
+//!       "index": 1200,
+//!       "threads": [
+//!         {
+//!           "name": "Main Thread",
+//!           "samples": [
+//!             {
+//!               "name": "PlayerLoop",
+//!               "totalTime": 14.2,
+//!               "children": [{ "name": "Update.ScriptRunBehaviourUpdate", "totalTime": 9.6 }]
+//!             }
+//!           ]
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Every stack runs from a root sample of a thread down through its timer scopes, and counts the
+//! microseconds the last of them took itself, leaving out the time of its children, summed over
+//! all frames of the capture.
+//!
+//!   [Unity Profiler]: https://docs.unity3d.com/Manual/Profiler.html
+
+use std::io;
+
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+/// `unity` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Include the name of the thread, such as `Main Thread` or `Render Thread`, as the root
+    /// frame of every stack.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+
+    /// Only fold the samples of the thread with this name.
+    ///
+    /// Default is `None`, which folds the samples of all threads.
+    pub thread: Option<String>,
+}
+
+/// A stack collapser for Unity Profiler captures exported to JSON.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `unity::Folder::default()` or create an [`Options`] and use
+/// `unity::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let capture: Value = serde_json::from_slice(&data)?;
+        let Some(frames) = capture.get("frames").and_then(Value::as_array) else {
+            return invalid_data_error!("No frames found in input");
+        };
+
+        let mut occurrences = Occurrences::new(1);
+        let mut stack = String::new();
+        for frame in frames {
+            let threads = frame["threads"].as_array().map_or(&[][..], Vec::as_slice);
+            for thread in threads {
+                let name = thread["name"].as_str().unwrap_or("[unknown]");
+                if self.opt.thread.as_deref().is_some_and(|only| only != name) {
+                    continue;
+                }
+                stack.clear();
+                if self.opt.include_thread {
+                    stack.push_str(&name.replace(';', ":"));
+                }
+                let samples = thread["samples"].as_array().map_or(&[][..], Vec::as_slice);
+                for sample in samples {
+                    fold_sample(sample, &mut stack, &mut occurrences);
+                }
+            }
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a capture with the frames and sample times of the Unity Profiler.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        (input.contains("\"frames\"") && input.contains("\"totalTime\"")).then_some(true)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Adds the self time of a sample and of all of its children to the folded stacks, below the
+// samples in `stack`.
+fn fold_sample(sample: &Value, stack: &mut String, occurrences: &mut Occurrences) {
+    let len = stack.len();
+    if !stack.is_empty() {
+        stack.push(';');
+    }
+    let name = sample["name"].as_str().filter(|name| !name.is_empty());
+    stack.push_str(&name.unwrap_or("[unknown]").replace(';', ":"));
+
+    let children = sample["children"].as_array().map_or(&[][..], Vec::as_slice);
+    let children_time: f64 = children
+        .iter()
+        .filter_map(|child| child["totalTime"].as_f64())
+        .sum();
+    let total_time = sample["totalTime"].as_f64().unwrap_or(0.0);
+    // Children can add up to a little more than their parent, as times are rounded.
+    let us = ((total_time - children_time) * 1000.0).round();
+    if us >= 1.0 {
+        occurrences.insert_or_add(stack.clone(), us as usize);
+    }
+    for child in children {
+        fold_sample(child, stack, occurrences);
+    }
+    stack.truncate(len);
+}
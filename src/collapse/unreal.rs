@@ -0,0 +1,272 @@
+//! Stack collapsing for the timing events of [Unreal Insights] traces.
+//!
+//! The input is the CSV (or TSV) file that Unreal Insights writes with its
+//! `TimingInsights.ExportTimingEvents` command, with the names of threads and timers included in
+//! the columns. For example:
+//!
+//! ```text
+//! UnrealInsights.exe -OpenTraceFile=Game.utrace -NoUI -AutoQuit
+//!     -ExecOnAnalysisCompleteCmd="TimingInsights.ExportTimingEvents events.csv
+//!     -columns=ThreadId,ThreadName,TimerId,TimerName,StartTime,EndTime,Depth"
+//! ```
+//!
+//! Every event is a timer scope on a thread, with its start and end in seconds. Scopes nest
+//! within the scopes that enclose them on the same thread, and every stack runs from an outermost
+//! scope down to a scope that counts the microseconds it took itself, leaving out the time of the
+//! scopes within it, summed over the whole trace. Timers without a `TimerName` column are named
+//! by their ids, as `timer 42`.
+//!
+//!   [Unreal Insights]: https://dev.epicgames.com/documentation/en-us/unreal-engine/unreal-insights-in-unreal-engine
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const THREAD_ID_COLUMN: &str = "ThreadId";
+const THREAD_NAME_COLUMN: &str = "ThreadName";
+const TIMER_ID_COLUMN: &str = "TimerId";
+const TIMER_NAME_COLUMN: &str = "TimerName";
+const START_TIME_COLUMN: &str = "StartTime";
+const END_TIME_COLUMN: &str = "EndTime";
+const DEPTH_COLUMN: &str = "Depth";
+
+/// `unreal` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Include the thread that a scope ran on, by its name or as `thread 2`, as the root frame of
+    /// every stack.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+}
+
+/// A stack collapser for the timing events that Unreal Insights exports.
+///
+/// See the [module-level documentation](self) for the input and how it is folded.
+///
+/// To construct one, either use `unreal::Folder::default()` or create an [`Options`] and use
+/// `unreal::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// Where the columns the folder reads are in every row.
+struct Columns {
+    delimiter: char,
+    thread_id: Option<usize>,
+    thread_name: Option<usize>,
+    timer_id: Option<usize>,
+    timer_name: Option<usize>,
+    start_time: Option<usize>,
+    end_time: Option<usize>,
+    depth: Option<usize>,
+}
+
+impl Columns {
+    fn new(header: &str) -> Self {
+        let delimiter = if header.contains('\t') { '\t' } else { ',' };
+        let names = split_fields(header, delimiter);
+        let find = |name: &str| names.iter().position(|n| n.trim() == name);
+        Columns {
+            delimiter,
+            thread_id: find(THREAD_ID_COLUMN),
+            thread_name: find(THREAD_NAME_COLUMN),
+            timer_id: find(TIMER_ID_COLUMN),
+            timer_name: find(TIMER_NAME_COLUMN),
+            start_time: find(START_TIME_COLUMN),
+            end_time: find(END_TIME_COLUMN),
+            depth: find(DEPTH_COLUMN),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.start_time.is_some()
+            && self.end_time.is_some()
+            && (self.timer_id.is_some() || self.timer_name.is_some())
+    }
+}
+
+// A timer scope on a thread.
+struct Event {
+    timer: String,
+    start: f64,
+    end: f64,
+    depth: usize,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut lines = reader.lines();
+        let Some(header) = lines.next().transpose()? else {
+            return invalid_data_error!("No header found in input");
+        };
+        let columns = Columns::new(&header);
+        if !columns.is_valid() {
+            return invalid_data_error!(
+                "Expected `{}`, `{}` and `{}` or `{}` columns in the header",
+                START_TIME_COLUMN,
+                END_TIME_COLUMN,
+                TIMER_NAME_COLUMN,
+                TIMER_ID_COLUMN
+            );
+        }
+
+        // The events of every thread, by the frame of the thread, in the order threads appear.
+        let mut threads: Vec<(String, Vec<Event>)> = Vec::new();
+        let mut thread_index: HashMap<String, usize> = HashMap::new();
+        let mut invalid = 0;
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_fields(&line, columns.delimiter);
+            let field = |column: Option<usize>| {
+                column
+                    .and_then(|c| fields.get(c))
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+            };
+            let time = |column| field(column).and_then(|t| t.parse::<f64>().ok());
+            let (Some(start), Some(end)) = (time(columns.start_time), time(columns.end_time))
+            else {
+                invalid += 1;
+                continue;
+            };
+            let timer = match (field(columns.timer_name), field(columns.timer_id)) {
+                (Some(name), _) => name.replace(';', ":"),
+                (None, Some(id)) => format!("timer {}", id),
+                (None, None) => {
+                    invalid += 1;
+                    continue;
+                }
+            };
+            let depth = field(columns.depth)
+                .and_then(|d| d.parse().ok())
+                .unwrap_or(0);
+
+            let thread = match (field(columns.thread_name), field(columns.thread_id)) {
+                (Some(name), _) => name.replace(';', ":"),
+                (None, Some(id)) => format!("thread {}", id),
+                (None, None) => "[unknown]".to_string(),
+            };
+            let i = *thread_index.entry(thread.clone()).or_insert_with(|| {
+                threads.push((thread, Vec::new()));
+                threads.len() - 1
+            });
+            threads[i].1.push(Event {
+                timer,
+                start,
+                end: end.max(start),
+                depth,
+            });
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        for (thread, mut events) in threads {
+            // Enclosing scopes start no later than the scopes within them, and are shallower.
+            events.sort_by(|a, b| {
+                a.start
+                    .total_cmp(&b.start)
+                    .then(a.depth.cmp(&b.depth))
+                    .then(b.end.total_cmp(&a.end))
+            });
+            let root = self.opt.include_thread.then_some(thread.as_str());
+            fold_thread(root, &events, &mut occurrences);
+        }
+
+        if invalid > 0 {
+            warn!(
+                "Ignored {} rows without a timer or valid start and end times",
+                invalid
+            );
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a header with the columns of exported timing events.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let header = input.lines().next()?;
+        Some(Columns::new(header).is_valid())
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Folds the events of a thread, sorted by where they start, into stacks of the scopes that
+// enclose each one, and adds the time every scope took itself.
+fn fold_thread(root: Option<&str>, events: &[Event], occurrences: &mut Occurrences) {
+    // The open scopes, with the seconds they took themselves so far.
+    let mut open: Vec<(&Event, f64)> = Vec::new();
+    let mut add = |open: &[(&Event, f64)], self_time: f64| {
+        let us = (self_time * 1e6).round();
+        if us < 1.0 {
+            return;
+        }
+        let mut stack = String::new();
+        if let Some(root) = root {
+            stack.push_str(root);
+            stack.push(';');
+        }
+        for (event, _) in open {
+            stack.push_str(&event.timer);
+            stack.push(';');
+        }
+        stack.pop();
+        occurrences.insert_or_add(stack, us as usize);
+    };
+
+    for event in events {
+        while let Some(&(top, self_time)) = open.last() {
+            if top.end > event.start {
+                break;
+            }
+            add(&open, self_time);
+            open.pop();
+        }
+        if let Some((top, self_time)) = open.last_mut() {
+            // A scope ends with the scope that encloses it at the latest.
+            *self_time -= event.end.min(top.end) - event.start;
+        }
+        open.push((event, event.end - event.start));
+    }
+    while let Some(&(_, self_time)) = open.last() {
+        add(&open, self_time);
+        open.pop();
+    }
+}
+
+// Splits a row of CSV into its fields, which may be quoted with `"` and contain `""` for a `"`.
+fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
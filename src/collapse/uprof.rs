@@ -0,0 +1,199 @@
+//! Stack collapsing for call stacks exported as CSV from [AMD uProf].
+//!
+//! The input is a CSV report with a table of call stacks: a header row with a call stack column
+//! (one with `STACK` in its name, such as `CALL STACK`), followed by a row for each call stack with
+//! its sample counts. Frames are in `module!function+offset` notation, and are separated by `<-`
+//! when the innermost frame comes first, or by `->` when the outermost one does:
+//!
+//! ```text
+//! CALL STACK,CYCLES_NOT_IN_HALT SAMPLES,RETIRED_INST SAMPLES
+//! "ucrtbase.dll!memcpy+0x1a <- app.exe!work+0x44 <- app.exe!main+0x1c",120,95
+//! ```
+//!
+//! Rows are weighted by the first column with `SAMPLES` in its name, unless
+//! [`Options::event`] says otherwise, or by their first number if there's no such column. Lines
+//! before the header row, such as those that describe the profile session, are skipped, and a
+//! blank line ends the table.
+//!
+//!   [AMD uProf]: https://www.amd.com/en/developer/uprof.html
+
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::{module_function_frame, split_csv, Occurrences};
+use crate::collapse::Collapse;
+
+const STACK_COLUMN: &str = "STACK";
+const SAMPLES_COLUMN: &str = "SAMPLES";
+
+/// `uprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Prefix function names with their module, as in ``libc.so.6`memcpy``.
+    ///
+    /// Default is `false`.
+    pub include_module: bool,
+
+    /// The column to weight call stacks by, such as `CYCLES_NOT_IN_HALT SAMPLES`. Case is
+    /// ignored.
+    ///
+    /// Default is `None`, which uses the first column of sample counts.
+    pub event: Option<String>,
+}
+
+/// A stack collapser for AMD uProf call stack reports.
+///
+/// See the [module-level documentation](self) for the supported format.
+///
+/// To construct one, either use `uprof::Folder::default()` or create an [`Options`] and use
+/// `uprof::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+// Where the call stack and its weight are in each row.
+struct Columns {
+    stack: usize,
+    weight: Option<usize>,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut columns = None;
+        let mut ignored = 0;
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(0x0A, &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end_matches(['\r', '\n']);
+            if l.trim().trim_matches(',').is_empty() {
+                columns = None;
+                continue;
+            }
+
+            let Some(columns) = &columns else {
+                columns = self.columns(l)?;
+                continue;
+            };
+            if !self.on_row(columns, l, &mut occurrences) {
+                ignored += 1;
+            }
+        }
+        if ignored > 0 {
+            warn!("Ignored {} call stacks with invalid format", ignored);
+        }
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a header row with a call stack column.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut input = input.as_bytes();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if let Ok(n) = input.read_line(&mut line) {
+                if n == 0 {
+                    break;
+                }
+            } else {
+                return Some(false);
+            }
+
+            if let Ok(Some(_)) = self.columns(line.trim_end()) {
+                return Some(true);
+            }
+        }
+        Some(false)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Returns the columns of a header row, or `None` for any other row.
+    fn columns(&self, row: &str) -> io::Result<Option<Columns>> {
+        let names: Vec<String> = split_csv(row)
+            .into_iter()
+            .map(|(name, _)| name.trim().to_ascii_uppercase())
+            .collect();
+        let Some(stack) = names.iter().position(|name| name.contains(STACK_COLUMN)) else {
+            return Ok(None);
+        };
+
+        let weight = match &self.opt.event {
+            Some(event) => {
+                let event = event.to_ascii_uppercase();
+                match names.iter().position(|name| *name == event) {
+                    Some(weight) => Some(weight),
+                    None => return invalid_data_error!("No {} column in header: {}", event, row),
+                }
+            }
+            None => names.iter().position(|name| name.contains(SAMPLES_COLUMN)),
+        };
+        Ok(Some(Columns { stack, weight }))
+    }
+
+    // Returns whether the row could be parsed.
+    fn on_row(&self, columns: &Columns, row: &str, occurrences: &mut Occurrences) -> bool {
+        let values = split_csv(row);
+        let Some((stack, _)) = values.get(columns.stack) else {
+            return false;
+        };
+        let count = match columns.weight {
+            Some(i) => values.get(i).and_then(|(value, _)| parse_count(value)),
+            None => values
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != columns.stack)
+                .find_map(|(_, (value, _))| parse_count(value)),
+        };
+        let Some(count) = count else {
+            return false;
+        };
+
+        let (frames, innermost_first): (Vec<_>, _) = if stack.contains("<-") {
+            (stack.split("<-").collect(), true)
+        } else {
+            (stack.split("->").collect(), false)
+        };
+        let mut frames: Vec<String> = frames
+            .into_iter()
+            .filter(|frame| !frame.trim().is_empty())
+            .map(|frame| module_function_frame(frame, self.opt.include_module))
+            .collect();
+        if frames.is_empty() {
+            return false;
+        }
+        if innermost_first {
+            frames.reverse();
+        }
+        if count > 0 {
+            occurrences.insert_or_add(frames.join(";"), count);
+        }
+        true
+    }
+}
+
+// Counts may have thousands separators, or be fractional.
+fn parse_count(value: &str) -> Option<usize> {
+    let value = value.trim().replace(',', "");
+    let count: f64 = value.parse().ok()?;
+    (count >= 0.0).then(|| count.round() as usize)
+}
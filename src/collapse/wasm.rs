@@ -0,0 +1,265 @@
+//! Stack collapsing for the guest profiles of WebAssembly programs.
+//!
+//! The input is the JSON that `wasmtime run --profile=guest` writes, which is a profile in the
+//! processed format of the [Firefox Profiler]. Each of its `threads` has tables of `samples`,
+//! stacks, frames and functions, where every stack is a frame and the stack it was called from
+//! (its `prefix`), and function names are indices into a table of strings:
+//!
+//! ```text
+//! {"meta":{...},"threads":[{"name":"main","samples":{"stack":[2,2,3],"weight":null,...},
+//!   "stackTable":{"prefix":[null,0,1,1],"frame":[0,1,2,3],...},"frameTable":{"func":[0,1,2,3],
+//!   ...},"funcTable":{"name":[0,1,2,3],...},...}],"shared":{"stringArray":["_start",
+//!   "wasm-function[7]","wasm-function[12]","memcpy"]}}
+//! ```
+//!
+//! Every sample counts as many times as its weight, or once if the profile has no weights.
+//!
+//! Functions of modules without a name section are named by their index, as in
+//! `wasm-function[12]`. Given the names of the functions of the module in
+//! [`Options::function_names`], which [`function_names`] reads from the name section of a
+//! module that has one (such as a debug build of the same program), those are replaced by their
+//! names.
+//!
+//! Profiles of the code that wasmtime compiles can also be taken with `perf`, using
+//! `--profile=jitdump` or `--profile=perfmap`, and collapsed with the `perf` collapser.
+//!
+//!   [Firefox Profiler]: https://profiler.firefox.com
+
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+const UNNAMED_FUNCTION_PREFIX: &str = "wasm-function[";
+const STACK_TABLE_FIELD: &str = "\"stackTable\"";
+
+/// `wasm` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Names of the functions of the module, by index, for functions that the profile names
+    /// `wasm-function[<index>]`.
+    ///
+    /// These can be read from the name section of the module with [`function_names`].
+    ///
+    /// Default is empty.
+    pub function_names: HashMap<u32, String>,
+}
+
+/// A stack collapser for the guest profiles of WebAssembly programs.
+///
+/// See the [module-level documentation](self) for details.
+///
+/// To construct one, either use `wasm::Folder::default()` or create an [`Options`] and use
+/// `wasm::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    /// Number of samples whose stacks refer to entries that aren't in the tables of the profile.
+    ignored: usize,
+
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let profile: Value = serde_json::from_reader(reader)?;
+        let Some(threads) = profile["threads"].as_array() else {
+            return invalid_data_error!("No profile threads found in input");
+        };
+
+        let mut occurrences = Occurrences::new(1);
+        let shared_strings = &profile["shared"]["stringArray"];
+        for thread in threads {
+            let strings = if thread["stringArray"].is_array() {
+                &thread["stringArray"]
+            } else {
+                shared_strings
+            };
+            self.on_thread(thread, strings, &mut occurrences);
+        }
+        if self.ignored > 0 {
+            warn!("Ignored {} samples with unknown stacks", self.ignored);
+            self.ignored = 0;
+        }
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a JSON document with a stack table.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if !input.trim_start().starts_with('{') {
+            return Some(false);
+        }
+        if input.contains(STACK_TABLE_FIELD) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
+}
+
+impl Folder {
+    fn on_thread(&mut self, thread: &Value, strings: &Value, occurrences: &mut Occurrences) {
+        let prefixes = &thread["stackTable"]["prefix"];
+        let stack_frames = &thread["stackTable"]["frame"];
+        let frame_funcs = &thread["frameTable"]["func"];
+        let func_names = &thread["funcTable"]["name"];
+
+        // Names of the frames, by index, with the functions of the module named.
+        let names: Vec<Option<String>> = frame_funcs
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|func| {
+                let name = func_names[index(func)?].as_u64()? as usize;
+                let name = strings[name].as_str()?;
+                Some(self.function_name(name).replace(';', ":"))
+            })
+            .collect();
+        let nstacks = stack_frames.as_array().map_or(0, Vec::len);
+
+        let samples = &thread["samples"];
+        let weights = samples["weight"].as_array();
+        for (i, stack) in samples["stack"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let Some(mut id) = index(stack) else {
+                // Samples taken outside of the guest have no stack.
+                continue;
+            };
+            let weight = match weights {
+                Some(weights) => weights.get(i).and_then(Value::as_u64).unwrap_or(0) as usize,
+                None => 1,
+            };
+
+            let mut frames = Vec::new();
+            let mut known = true;
+            // Guard against cycles, which no stack table has.
+            for _ in 0..nstacks {
+                match index(&stack_frames[id]).and_then(|frame| names.get(frame)) {
+                    Some(Some(name)) => frames.push(name.as_str()),
+                    _ => {
+                        known = false;
+                        break;
+                    }
+                }
+                match index(&prefixes[id]) {
+                    Some(prefix) => id = prefix,
+                    None => break,
+                }
+            }
+
+            if !known || frames.is_empty() {
+                self.ignored += 1;
+                continue;
+            }
+            if weight > 0 {
+                frames.reverse();
+                occurrences.insert_or_add(frames.join(";"), weight);
+            }
+        }
+    }
+
+    /// Replaces a function index, as in `wasm-function[12]`, with the function's name, if known.
+    fn function_name<'a>(&'a self, name: &'a str) -> &'a str {
+        let index = name
+            .strip_prefix(UNNAMED_FUNCTION_PREFIX)
+            .and_then(|rest| rest.strip_suffix(']'))
+            .and_then(|index| index.parse::<u32>().ok());
+        index
+            .and_then(|index| self.opt.function_names.get(&index))
+            .map_or(name, String::as_str)
+    }
+}
+
+fn index(value: &Value) -> Option<usize> {
+    value.as_u64().map(|i| i as usize)
+}
+
+/// Reads the names of the functions of a WebAssembly module, by index, from its name section.
+///
+/// Modules without a name section have no function names, and give an empty map.
+pub fn function_names(module: &[u8]) -> io::Result<HashMap<u32, String>> {
+    let mut reader = ModuleReader(module);
+    if reader.bytes(4)? != b"\0asm" {
+        return invalid_data_error!("Not a WebAssembly module");
+    }
+    reader.bytes(4)?;
+
+    let mut names = HashMap::new();
+    while !reader.0.is_empty() {
+        let id = reader.bytes(1)?[0];
+        let size = reader.leb128()? as usize;
+        let mut section = ModuleReader(reader.bytes(size)?);
+        // The name section is a custom section, with an id of 0, named "name".
+        if id != 0 || section.name()? != "name" {
+            continue;
+        }
+        while !section.0.is_empty() {
+            let id = section.bytes(1)?[0];
+            let size = section.leb128()? as usize;
+            let mut subsection = ModuleReader(section.bytes(size)?);
+            // Function names are subsection 1.
+            if id != 1 {
+                continue;
+            }
+            for _ in 0..subsection.leb128()? {
+                let index = subsection.leb128()?;
+                let name = subsection.name()?;
+                names.insert(index, name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+struct ModuleReader<'a>(&'a [u8]);
+
+impl<'a> ModuleReader<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return invalid_data_error!("Unexpected end of WebAssembly module");
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn leb128(&mut self) -> io::Result<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            value |= u32::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        invalid_data_error!("Invalid integer in WebAssembly module")
+    }
+
+    fn name(&mut self) -> io::Result<&'a str> {
+        let len = self.leb128()? as usize;
+        std::str::from_utf8(self.bytes(len)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
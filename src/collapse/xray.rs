@@ -0,0 +1,374 @@
+//! Stack collapsing for the function call traces of [LLVM XRay] in basic mode.
+//!
+//! Programs built with `clang -fxray-instrument` log every entry into and exit from their
+//! instrumented functions, with the time stamp counter (TSC) of each, when run with
+//! `XRAY_OPTIONS="patch_premain=true xray_mode=xray-basic"`. This collapser reads either that
+//! binary log, or the YAML that `llvm-xray convert` converts it (or a log of any other mode) to:
+//!
+//! ```text
+//! llvm-xray convert -symbolize -instr_map=./app -output-format=yaml xray-log.app.XXXXXX
+//! ```
+//!
+//! which looks like:
+//!
+//! ```text
+//! ---
+//! header:
+//!   version:         3
+//!   type:            0
+//!   constant-tsc:    true
+//!   nonstop-tsc:     true
+//!   cycle-frequency: 1000000000
+//! records:
+//!   - { type: 0, func-id: 1, function: main, cpu: 3, thread: 4711, process: 4711, kind: function-enter, tsc: 1000 }
+//!   - { type: 0, func-id: 2, function: 'parse(char const*)', cpu: 3, thread: 4711, process: 4711, kind: function-enter, tsc: 1200 }
+//! ```
+//!
+//! The time between consecutive events of a thread counts towards the stack of the functions that
+//! were entered but not yet exited on that thread at the time, so that every stack counts the time
+//! its innermost function spent running itself. Counts are in nanoseconds, or in TSC ticks if the
+//! cycle frequency isn't known.
+//!
+//! The binary log identifies functions by their ID only. Given the names of the functions by ID in
+//! [`Options::function_names`], which [`function_names`] reads from the output of
+//! `llvm-xray extract -symbolize ./app`, those are named; otherwise they are named `#<id>`.
+//!
+//!   [LLVM XRay]: https://llvm.org/docs/XRay.html
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// Binary logs start with a header of this many bytes, and consist of records of this many bytes.
+const HEADER_LEN: usize = 32;
+const RECORD_LEN: usize = 32;
+const MAX_VERSION: u16 = 3;
+
+// Types of logs, and of the events of records in basic mode logs.
+const LOG_BASIC: u16 = 0;
+const ENTRY_ENTER: u8 = 0;
+const ENTRY_EXIT: u8 = 1;
+const ENTRY_TAIL_EXIT: u8 = 2;
+const ENTRY_ENTER_ARG: u8 = 3;
+
+/// `xray` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Names of the instrumented functions, by function ID.
+    ///
+    /// These can be read from the output of `llvm-xray extract -symbolize` with
+    /// [`function_names`]. They take precedence over the names in YAML traces.
+    ///
+    /// Default is empty.
+    pub function_names: HashMap<i32, String>,
+}
+
+/// A stack collapser for LLVM XRay traces.
+///
+/// See the [module-level documentation](self) for details.
+///
+/// To construct one, either use `xray::Folder::default()` or create an [`Options`] and use
+/// `xray::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Enter,
+    Exit,
+}
+
+#[derive(Default)]
+struct Trace {
+    cycle_frequency: u64,
+    // Names of the functions given by YAML traces.
+    names: HashMap<i32, String>,
+    // Stacks of the threads, by process and thread ID.
+    threads: HashMap<(u32, u32), Thread>,
+    // TSC ticks spent in each stack of function IDs, outermost first.
+    ticks: HashMap<Vec<i32>, u64>,
+    unmatched_exits: usize,
+}
+
+#[derive(Default)]
+struct Thread {
+    // Functions that were entered and not yet exited, outermost first.
+    stack: Vec<i32>,
+    tsc: u64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let mut trace = Trace::default();
+        if binary_version(&data).is_some() {
+            trace.read_binary(&data)?;
+        } else {
+            trace.read_yaml(&String::from_utf8_lossy(&data))?;
+        }
+        if trace.unmatched_exits > 0 {
+            warn!(
+                "Ignored {} exits from functions that weren't entered",
+                trace.unmatched_exits
+            );
+        }
+        if trace.cycle_frequency == 0 {
+            warn!("Unknown cycle frequency, counts are in TSC ticks");
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        for (stack, ticks) in &trace.ticks {
+            let count = match trace.cycle_frequency {
+                0 => *ticks as usize,
+                frequency => (u128::from(*ticks) * 1_000_000_000 / u128::from(frequency)) as usize,
+            };
+            if count == 0 {
+                continue;
+            }
+            let stack: Vec<Cow<'_, str>> = stack
+                .iter()
+                .map(|&id| self.function_name(id, &trace.names))
+                .collect();
+            occurrences.insert_or_add(stack.join(";"), count);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the header of a binary log, or for function entries in YAML.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if binary_version(input.as_bytes()).is_some() {
+            return Some(true);
+        }
+        for line in input.lines() {
+            if let Some(fields) = flow_mapping(line) {
+                return Some(field(&fields, "kind").is_some_and(|k| k.starts_with("function-")));
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn function_name<'a>(&'a self, id: i32, names: &'a HashMap<i32, String>) -> Cow<'a, str> {
+        match self.opt.function_names.get(&id).or_else(|| names.get(&id)) {
+            Some(name) => Cow::Borrowed(name),
+            None => Cow::Owned(format!("#{}", id)),
+        }
+    }
+}
+
+impl Trace {
+    fn read_binary(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() < HEADER_LEN {
+            return invalid_data_error!("Unexpected end of XRay log header");
+        }
+        if u16::from_le_bytes([data[2], data[3]]) != LOG_BASIC {
+            return invalid_data_error!(
+                "Only basic mode XRay logs are supported, convert others to YAML with `llvm-xray convert`"
+            );
+        }
+        self.cycle_frequency = u64::from_le_bytes(data[8..16].try_into().unwrap());
+
+        let records = data[HEADER_LEN..].chunks_exact(RECORD_LEN);
+        if !records.remainder().is_empty() {
+            warn!("Ignored a truncated record at the end of the XRay log");
+        }
+        for record in records {
+            // Records of type 1 hold the arguments of the function entry before them.
+            if u16::from_le_bytes([record[0], record[1]]) != 0 {
+                continue;
+            }
+            let kind = match record[3] {
+                ENTRY_ENTER | ENTRY_ENTER_ARG => Kind::Enter,
+                ENTRY_EXIT | ENTRY_TAIL_EXIT => Kind::Exit,
+                _ => continue,
+            };
+            let id = i32::from_le_bytes(record[4..8].try_into().unwrap());
+            let tsc = u64::from_le_bytes(record[8..16].try_into().unwrap());
+            let tid = u32::from_le_bytes(record[16..20].try_into().unwrap());
+            let pid = u32::from_le_bytes(record[20..24].try_into().unwrap());
+            self.on_event((pid, tid), id, kind, tsc);
+        }
+        Ok(())
+    }
+
+    fn read_yaml(&mut self, input: &str) -> io::Result<()> {
+        let mut records = 0;
+        let mut ignored = 0;
+        for line in input.lines() {
+            if let Some(frequency) = line.trim().strip_prefix("cycle-frequency:") {
+                self.cycle_frequency = frequency.trim().parse().unwrap_or(0);
+                continue;
+            }
+            let Some(fields) = flow_mapping(line) else {
+                continue;
+            };
+            records += 1;
+            let number = |key: &str| field(&fields, key).and_then(|v| v.parse::<u64>().ok());
+            let id = field(&fields, "func-id").and_then(|v| v.parse::<i32>().ok());
+            let (Some(id), Some(kind), Some(tsc)) = (id, field(&fields, "kind"), number("tsc"))
+            else {
+                ignored += 1;
+                continue;
+            };
+            let kind = match kind {
+                "function-enter" | "function-enter-arg" => Kind::Enter,
+                "function-exit" | "function-tail-exit" => Kind::Exit,
+                _ => continue,
+            };
+            // Functions of traces converted without `-symbolize` are named by their ID.
+            if let Some(name) = field(&fields, "function") {
+                if !name.is_empty() && name.parse::<i32>().ok() != Some(id) {
+                    self.names
+                        .entry(id)
+                        .or_insert_with(|| name.replace(';', ":"));
+                }
+            }
+            let thread = (
+                number("process").unwrap_or(0) as u32,
+                number("thread").unwrap_or(0) as u32,
+            );
+            self.on_event(thread, id, kind, tsc);
+        }
+        if records == 0 {
+            return invalid_data_error!("No XRay records found in input");
+        }
+        if ignored > 0 {
+            warn!("Ignored {} records with invalid format", ignored);
+        }
+        Ok(())
+    }
+
+    // Counts the time since the previous event of the thread towards its stack, then enters or
+    // exits the function.
+    fn on_event(&mut self, thread: (u32, u32), id: i32, kind: Kind, tsc: u64) {
+        let thread = self.threads.entry(thread).or_default();
+        let elapsed = tsc.saturating_sub(thread.tsc);
+        if !thread.stack.is_empty() && elapsed > 0 {
+            match self.ticks.get_mut(thread.stack.as_slice()) {
+                Some(ticks) => *ticks += elapsed,
+                None => {
+                    self.ticks.insert(thread.stack.clone(), elapsed);
+                }
+            }
+        }
+        thread.tsc = tsc;
+
+        match kind {
+            Kind::Enter => thread.stack.push(id),
+            // Exits skip the functions whose exits weren't logged, such as those that threw.
+            Kind::Exit => match thread.stack.iter().rposition(|&f| f == id) {
+                Some(i) => thread.stack.truncate(i),
+                None => self.unmatched_exits += 1,
+            },
+        }
+    }
+}
+
+/// Reads the names of the instrumented functions of a program, by function ID, from the output of
+/// `llvm-xray extract -symbolize`.
+///
+/// That output lists the instrumentation points of the functions, one per line, as in
+/// `- { id: 1, address: 0x401A40, function: 0x401A40, kind: function-enter,
+/// always-instrument: false, function-name: main }`. Lines without a function name are ignored.
+pub fn function_names(instr_map: &str) -> HashMap<i32, String> {
+    let mut names = HashMap::new();
+    for line in instr_map.lines() {
+        let Some(fields) = flow_mapping(line) else {
+            continue;
+        };
+        let id = field(&fields, "id").and_then(|id| id.parse().ok());
+        let name = field(&fields, "function-name").filter(|name| !name.is_empty());
+        if let (Some(id), Some(name)) = (id, name) {
+            names.entry(id).or_insert_with(|| name.replace(';', ":"));
+        }
+    }
+    names
+}
+
+// Returns the version of a binary log from its header, or `None` if it isn't one.
+fn binary_version(data: &[u8]) -> Option<u16> {
+    let version = u16::from_le_bytes([*data.first()?, *data.get(1)?]);
+    let typ = u16::from_le_bytes([*data.get(2)?, *data.get(3)?]);
+    ((1..=MAX_VERSION).contains(&version) && typ <= 1).then_some(version)
+}
+
+// Splits a YAML flow mapping on a line of its own, as in `- { id: 1, function: 'main' }`, into
+// its keys and values, or returns `None` for any other line.
+fn flow_mapping(line: &str) -> Option<Vec<(&str, Cow<'_, str>)>> {
+    let mut rest = line
+        .trim()
+        .strip_prefix('-')?
+        .trim_start()
+        .strip_prefix('{')?
+        .strip_suffix('}')?
+        .trim();
+    let mut fields = Vec::new();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once(':')?;
+        let value = value.trim_start();
+        let (value, after) = match value.chars().next() {
+            // Single quotes are escaped by doubling them.
+            Some('\'') => {
+                let mut unquoted = String::new();
+                let mut chars = value.char_indices().skip(1);
+                loop {
+                    match chars.next()? {
+                        (i, '\'') if value[i + 1..].starts_with('\'') => {
+                            unquoted.push('\'');
+                            chars.next();
+                        }
+                        (i, '\'') => break (Cow::Owned(unquoted), &value[i + 1..]),
+                        (_, c) => unquoted.push(c),
+                    }
+                }
+            }
+            Some('"') => {
+                let mut unquoted = String::new();
+                let mut chars = value.char_indices().skip(1);
+                loop {
+                    match chars.next()? {
+                        (_, '\\') => unquoted.push(chars.next()?.1),
+                        (i, '"') => break (Cow::Owned(unquoted), &value[i + 1..]),
+                        (_, c) => unquoted.push(c),
+                    }
+                }
+            }
+            _ => {
+                let end = value.find(',').unwrap_or(value.len());
+                (Cow::Borrowed(value[..end].trim_end()), &value[end..])
+            }
+        };
+        fields.push((key.trim(), value));
+        let after = after.trim_start();
+        rest = after.strip_prefix(',').unwrap_or(after).trim_start();
+    }
+    Some(fields)
+}
+
+fn field<'a>(fields: &'a [(&str, Cow<'_, str>)], key: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value.as_ref())
+}
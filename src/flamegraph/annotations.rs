@@ -0,0 +1,282 @@
+#[cfg(all(feature = "annotations", any(feature = "json", feature = "serde")))]
+use std::io;
+#[cfg(all(feature = "annotations", any(feature = "json", feature = "serde")))]
+use std::path::Path;
+
+#[cfg(all(feature = "annotations", any(feature = "json", feature = "serde")))]
+use log::warn;
+#[cfg(feature = "annotations")]
+use regex::Regex;
+
+/// Attaches annotations to the frames of every function whose name matches a regular expression.
+///
+/// Unlike `FuncFrameAttrsMap`, which sets raw SVG attributes on frames whose function name matches
+/// exactly, this is meant for marking frames with human-readable notes, like "known issue
+/// FOO-123". When several patterns match the same function, all of their annotations apply.
+///
+/// Annotations can be loaded from JSON with the `json` feature, and from TOML with the `serde`
+/// feature.
+#[cfg(feature = "annotations")]
+#[derive(Debug, Default)]
+pub struct FuncFrameAnnotations(Vec<(Regex, FrameAnnotation)>);
+
+/// An annotation to attach to matching frames through [`FuncFrameAnnotations`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+#[cfg_attr(not(feature = "annotations"), allow(dead_code, unreachable_pub))]
+pub struct FrameAnnotation {
+    /// Text appended to the frame's tooltip, on a line of its own.
+    pub tooltip: Option<String>,
+
+    /// A short glyph drawn at the right end of the frame.
+    pub badge: Option<String>,
+
+    /// CSS classes added to the frame's SVG element.
+    pub class: Option<String>,
+}
+
+#[cfg(feature = "annotations")]
+impl PartialEq for FuncFrameAnnotations {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|((re1, a1), (re2, a2))| re1.as_str() == re2.as_str() && a1 == a2)
+    }
+}
+
+#[cfg(feature = "annotations")]
+impl Eq for FuncFrameAnnotations {}
+
+#[cfg(feature = "annotations")]
+impl FuncFrameAnnotations {
+    /// Annotate every frame whose function name matches `pattern`.
+    pub fn add(&mut self, pattern: &str, annotation: FrameAnnotation) -> Result<(), regex::Error> {
+        self.0.push((Regex::new(pattern)?, annotation));
+        Ok(())
+    }
+
+    /// Parse frame annotations from a JSON file, or from a TOML file if its extension is `.toml`.
+    ///
+    /// See [`FuncFrameAnnotations::from_reader`] and [`FuncFrameAnnotations::from_toml_str`] for
+    /// the expected formats. Reading JSON needs the `json` feature, and reading TOML the `serde`
+    /// feature.
+    #[cfg(any(feature = "json", feature = "serde"))]
+    pub fn from_file(path: &Path) -> io::Result<FuncFrameAnnotations> {
+        let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+        #[cfg(feature = "serde")]
+        if is_toml {
+            return FuncFrameAnnotations::from_toml_str(&std::fs::read_to_string(path)?);
+        }
+        #[cfg(feature = "json")]
+        if !is_toml {
+            let file = io::BufReader::new(std::fs::File::open(path)?);
+            return FuncFrameAnnotations::from_reader(file);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "reading frame annotations from {} needs the `{}` feature",
+                path.display(),
+                if is_toml { "serde" } else { "json" }
+            ),
+        ))
+    }
+
+    /// Parse frame annotations from JSON.
+    ///
+    /// The input should be an object keyed by function name regular expressions, where each value
+    /// is an object with any of the string fields `tooltip`, `badge` and `class`:
+    ///
+    /// ```json
+    /// {
+    ///     "^tokio::runtime::park": { "tooltip": "known issue FOO-123", "badge": "!" },
+    ///     "alloc": { "class": "alloc" }
+    /// }
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn from_reader<R: io::Read>(reader: R) -> io::Result<FuncFrameAnnotations> {
+        let serde_json::Value::Object(patterns) = serde_json::from_reader(reader)? else {
+            return Err(invalid("frame annotations must be an object".to_string()));
+        };
+
+        let mut annotations = FuncFrameAnnotations::default();
+        for (pattern, fields) in patterns {
+            let serde_json::Value::Object(fields) = fields else {
+                return Err(not_an_object(&pattern));
+            };
+            annotations.add_fields(
+                &pattern,
+                fields.into_iter().map(|(name, value)| match value {
+                    serde_json::Value::String(value) => (name, Some(value)),
+                    _ => (name, None),
+                }),
+            )?;
+        }
+
+        Ok(annotations)
+    }
+
+    /// Parse frame annotations from TOML.
+    ///
+    /// The input has the same structure as the JSON of [`FuncFrameAnnotations::from_reader`], with
+    /// a table for every regular expression:
+    ///
+    /// ```toml
+    /// ["^tokio::runtime::park"]
+    /// tooltip = "known issue FOO-123"
+    /// badge = "!"
+    ///
+    /// [alloc]
+    /// class = "alloc"
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(toml: &str) -> io::Result<FuncFrameAnnotations> {
+        let patterns: toml::Table = toml
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut annotations = FuncFrameAnnotations::default();
+        for (pattern, fields) in patterns {
+            let toml::Value::Table(fields) = fields else {
+                return Err(not_an_object(&pattern));
+            };
+            annotations.add_fields(
+                &pattern,
+                fields.into_iter().map(|(name, value)| match value {
+                    toml::Value::String(value) => (name, Some(value)),
+                    _ => (name, None),
+                }),
+            )?;
+        }
+
+        Ok(annotations)
+    }
+
+    // Adds an annotation for `pattern` from its fields, those that aren't strings being `None`.
+    #[cfg(any(feature = "json", feature = "serde"))]
+    fn add_fields<I>(&mut self, pattern: &str, fields: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (String, Option<String>)>,
+    {
+        let mut annotation = FrameAnnotation::default();
+        for (name, value) in fields {
+            let Some(value) = value else {
+                warn!("ignoring non-string annotation {} for `{}`", name, pattern);
+                continue;
+            };
+            match name.as_str() {
+                "tooltip" => annotation.tooltip = Some(value),
+                "badge" => annotation.badge = Some(value),
+                "class" => annotation.class = Some(value),
+                _ => warn!("invalid annotation {} found for `{}`", name, pattern),
+            }
+        }
+        self.add(pattern, annotation)
+            .map_err(|e| invalid(format!("invalid pattern `{}`: {}", pattern, e)))
+    }
+
+    /// Return the combined annotations of all patterns matching the given function name.
+    pub(super) fn annotation_for_func(&self, func: &str) -> Option<FrameAnnotation> {
+        fn join(into: &mut Option<String>, from: &Option<String>, sep: &str) {
+            match (into.as_mut(), from) {
+                (Some(into), Some(from)) => {
+                    into.push_str(sep);
+                    into.push_str(from);
+                }
+                (None, Some(from)) => *into = Some(from.clone()),
+                (_, None) => {}
+            }
+        }
+
+        let mut combined: Option<FrameAnnotation> = None;
+        for (_, annotation) in self.0.iter().filter(|(re, _)| re.is_match(func)) {
+            let combined = combined.get_or_insert_with(FrameAnnotation::default);
+            join(&mut combined.tooltip, &annotation.tooltip, "\n");
+            join(&mut combined.badge, &annotation.badge, "");
+            join(&mut combined.class, &annotation.class, " ");
+        }
+        combined
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(all(feature = "annotations", any(feature = "json", feature = "serde")))]
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(all(feature = "annotations", any(feature = "json", feature = "serde")))]
+fn not_an_object(pattern: &str) -> io::Error {
+    invalid(format!("annotation for `{}` must be an object", pattern))
+}
+
+#[cfg(all(test, feature = "annotations"))]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn func_frame_annotations_from_reader() {
+        let json = r#"{
+            "^tokio::": { "tooltip": "known issue FOO-123", "badge": "!" },
+            "park": { "tooltip": "idle", "class": "idle" },
+            "^std::": { "class": "std", "color": "red" }
+        }"#;
+
+        let annotations = FuncFrameAnnotations::from_reader(json.as_bytes()).unwrap();
+
+        assert_eq!(
+            annotations.annotation_for_func("tokio::runtime::park"),
+            Some(FrameAnnotation {
+                tooltip: Some("known issue FOO-123\nidle".to_owned()),
+                badge: Some("!".to_owned()),
+                class: Some("idle".to_owned()),
+            })
+        );
+        assert_eq!(
+            annotations.annotation_for_func("std::io::read"),
+            Some(FrameAnnotation {
+                class: Some("std".to_owned()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(annotations.annotation_for_func("main"), None);
+
+        assert!(FuncFrameAnnotations::from_reader(r#"{ "(": {} }"#.as_bytes()).is_err());
+        assert!(FuncFrameAnnotations::from_reader("[]".as_bytes()).is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "json", feature = "serde"))]
+    fn func_frame_annotations_from_toml_str() {
+        let toml = r#"
+            ["^tokio::"]
+            tooltip = "known issue FOO-123"
+            badge = "!"
+
+            [park]
+            tooltip = "idle"
+            class = "idle"
+        "#;
+
+        let annotations = FuncFrameAnnotations::from_toml_str(toml).unwrap();
+        let json = r#"{
+            "^tokio::": { "tooltip": "known issue FOO-123", "badge": "!" },
+            "park": { "tooltip": "idle", "class": "idle" }
+        }"#;
+        assert_eq!(
+            annotations,
+            FuncFrameAnnotations::from_reader(json.as_bytes()).unwrap()
+        );
+
+        assert!(FuncFrameAnnotations::from_toml_str("park = 1").is_err());
+        assert!(FuncFrameAnnotations::from_toml_str("[(]").is_err());
+    }
+}
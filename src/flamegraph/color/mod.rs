@@ -26,6 +26,23 @@ pub(super) const DGREY: Color = Color {
     b: 200,
 };
 
+// Colors of the frames of a flame graph colored by age.
+pub(super) const AGE_NEW: Color = Color {
+    r: 80,
+    g: 190,
+    b: 80,
+};
+pub(super) const AGE_REMOVED: Color = Color {
+    r: 130,
+    g: 160,
+    b: 240,
+};
+pub(super) const AGE_PERSISTENT: Color = Color {
+    r: 225,
+    g: 225,
+    b: 225,
+};
+
 const YELLOW_GRADIENT: (&str, &str) = ("#eeeeee", "#eeeeb0");
 const BLUE_GRADIENT: (&str, &str) = ("#eeeeee", "#e0e0ff");
 const GREEN_GRADIENT: (&str, &str) = ("#eef2ee", "#e0ffe0");
@@ -76,8 +93,8 @@ pub enum Palette {
 impl Palette {
     /// The valid set of palettes (via `FromStr`).
     pub const VARIANTS: &'static [&'static str] = &[
-        "aqua", "blue", "green", "hot", "io", "java", "js", "mem", "orange", "perl", "python",
-        "purple", "red", "rust", "wakeup", "yellow",
+        "aqua", "blue", "cvd", "green", "hot", "io", "java", "js", "mem", "orange", "perl",
+        "python", "purple", "red", "rust", "wakeup", "yellow",
     ];
 }
 
@@ -115,6 +132,13 @@ pub enum BasicPalette {
     Purple,
     /// A palette in which colors are chosen from a orange spectrum.
     Orange,
+    /// A palette for readers with color vision deficiencies, in which colors are chosen from an
+    /// orange-yellow spectrum that varies in lightness rather than between red and green.
+    ///
+    /// Differential flame graphs, and those with
+    /// [`color_diffusion`](super::Options::color_diffusion), use a blue-orange scale instead of
+    /// the usual blue-red one with this palette.
+    Cvd,
 }
 
 /// A semantic color palette in which different hues are used to signify semantic aspects of
@@ -214,6 +238,113 @@ impl FromStr for StrokeColor {
     }
 }
 
+/// A change to the color that a frame gets from the palette, which keeps its hue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ColorModifier {
+    /// Saturate the color of a frame by the share of its samples that are its own rather than
+    /// those of its children, so that the frames that do work stand out from the frames that
+    /// only pass calls on, which are washed out.
+    SelfRatio,
+}
+
+impl FromStr for ColorModifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "self-ratio" => Ok(ColorModifier::SelfRatio),
+            _ => Err(format!("unknown color modifier: {}", s)),
+        }
+    }
+}
+
+impl ColorModifier {
+    /// The valid set of color modifiers (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["self-ratio"];
+
+    #[cfg(feature = "serde")]
+    fn name(&self) -> &'static str {
+        match self {
+            ColorModifier::SelfRatio => "self-ratio",
+        }
+    }
+
+    // Modifies the palette color of a frame with the given self and total samples.
+    pub(super) fn apply(self, color: Color, self_count: usize, total_count: usize) -> Color {
+        match self {
+            ColorModifier::SelfRatio => {
+                let ratio = if total_count == 0 {
+                    0.0
+                } else {
+                    (self_count as f64 / total_count as f64).min(1.0)
+                };
+                // Frames without samples of their own keep a little of their hue.
+                desaturate(color, MIN_SATURATION + (1.0 - MIN_SATURATION) * ratio)
+            }
+        }
+    }
+}
+
+const MIN_SATURATION: f64 = 0.2;
+
+// Blends a color with the gray of the same lightness, keeping `saturation` of the color.
+fn desaturate(color: Color, saturation: f64) -> Color {
+    let gray = 0.299 * color.r as f64 + 0.587 * color.g as f64 + 0.114 * color.b as f64;
+    let blend = |c: u8| {
+        (gray + (c as f64 - gray) * saturation)
+            .round()
+            .clamp(0.0, 255.0) as u8
+    };
+    Color {
+        r: blend(color.r),
+        g: blend(color.g),
+        b: blend(color.b),
+    }
+}
+
+/// A range of colors to pick the colors of a basic palette from, in place of its own, such as
+/// to shift [`BasicPalette::Hot`] towards the colors of a brand.
+///
+/// Every channel of a frame's color is picked between its values in `from` and `to` the way the
+/// palette picks it between its own bounds, so frames that get similar colors from the palette
+/// still do. Palettes such as [`MultiPalette::Java`] that color frames with basic palettes use
+/// the ranges of those.
+///
+/// Expressed in string form as `PALETTE=#RRGGBB..#RRGGBB`, as in `hot=#0050a0..#40c0ff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaletteRange {
+    /// The palette whose colors are replaced.
+    pub palette: BasicPalette,
+    /// The color that the palette picks for the lowest values.
+    pub from: Color,
+    /// The color that the palette picks for the highest values.
+    pub to: Color,
+}
+
+impl FromStr for PaletteRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (palette, range) = s
+            .split_once('=')
+            .ok_or_else(|| format!("palette range is not PALETTE=FROM..TO: {}", s))?;
+        let palette = match palette.parse()? {
+            Palette::Basic(palette) => palette,
+            Palette::Multi(_) => return Err(format!("not a basic palette: {}", palette)),
+        };
+        let (from, to) = range
+            .split_once("..")
+            .ok_or_else(|| format!("palette range is not PALETTE=FROM..TO: {}", s))?;
+        let color = |c: &str| parse_hex_color(c).ok_or_else(|| format!("unknown color: {}", c));
+        Ok(PaletteRange {
+            palette,
+            from: color(from)?,
+            to: color(to)?,
+        })
+    }
+}
+
 impl FromStr for Palette {
     type Err = String;
 
@@ -235,11 +366,122 @@ impl FromStr for Palette {
             "yellow" => Ok(Palette::Basic(BasicPalette::Yellow)),
             "purple" => Ok(Palette::Basic(BasicPalette::Purple)),
             "orange" => Ok(Palette::Basic(BasicPalette::Orange)),
+            "cvd" => Ok(Palette::Basic(BasicPalette::Cvd)),
             unknown => Err(format!("unknown color palette: {}", unknown)),
         }
     }
 }
 
+/// Formats a color as `#rrggbb`, the form accepted by [`parse_hex_color`].
+#[cfg(feature = "serde")]
+fn to_hex_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+#[cfg(feature = "serde")]
+impl Palette {
+    pub(super) fn name(&self) -> &'static str {
+        Palette::VARIANTS
+            .iter()
+            .find(|name| Palette::from_str(name).as_ref() == Ok(self))
+            .expect("every palette has a name")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl BackgroundColor {
+    fn name(&self) -> Cow<'static, str> {
+        match self {
+            BackgroundColor::Yellow => "yellow".into(),
+            BackgroundColor::Blue => "blue".into(),
+            BackgroundColor::Green => "green".into(),
+            BackgroundColor::Grey => "grey".into(),
+            BackgroundColor::Flat(color) => to_hex_color(*color).into(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl StrokeColor {
+    fn name(&self) -> Cow<'static, str> {
+        match self {
+            StrokeColor::Color(color) => to_hex_color(*color).into(),
+            StrokeColor::None => "none".into(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl PaletteRange {
+    fn name(&self) -> String {
+        format!(
+            "{}={}..{}",
+            Palette::Basic(self.palette).name(),
+            to_hex_color(self.from),
+            to_hex_color(self.to)
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl SearchColor {
+    fn name(&self) -> String {
+        to_hex_color(self.0)
+    }
+}
+
+// Colors and palettes are (de)serialized in the same string form the command-line tools accept.
+#[cfg(feature = "serde")]
+macro_rules! serde_via_str {
+    ($($t:ty),*) => {
+        $(
+            impl serde::Serialize for $t {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_str(&self.name())
+                }
+            }
+
+            impl<'de> serde::Deserialize<'de> for $t {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let s = String::deserialize(deserializer)?;
+                    s.parse().map_err(serde::de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "serde")]
+serde_via_str!(
+    Palette,
+    BackgroundColor,
+    StrokeColor,
+    SearchColor,
+    ColorModifier,
+    PaletteRange
+);
+
+/// (De)serializes a [`Color`] as `#rrggbb`, for use with `#[serde(with = ...)]`.
+#[cfg(feature = "serde")]
+pub(crate) mod hex_color {
+    use super::Color;
+
+    pub(crate) fn serialize<S: serde::Serializer>(
+        color: &Color,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex_color(*color))
+    }
+
+    pub(crate) fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Color, D::Error> {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        super::parse_hex_color(&s)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown color: {}", s)))
+    }
+}
+
 struct NamehashVariables {
     vector: f32,
     weight: f32,
@@ -317,12 +559,6 @@ fn namehash<I: Iterator<Item = u8>>(mut name: I) -> f32 {
     namehash_variables.result()
 }
 
-macro_rules! t {
-    ($b:expr, $a:expr, $x:expr) => {
-        $b + ($a * $x) as u8
-    };
-}
-
 macro_rules! color {
     ($r:expr, $g:expr, $b:expr) => {
         Color {
@@ -333,7 +569,30 @@ macro_rules! color {
     };
 }
 
-fn rgb_components_for_palette(palette: Palette, name: &str, v1: f32, v2: f32, v3: f32) -> Color {
+// The lowest and highest value of the red, green and blue channels of every basic palette, and
+// which of the three variables of a frame picks the value of the channel between them.
+fn channels(palette: BasicPalette) -> [(u8, u8, usize); 3] {
+    match palette {
+        BasicPalette::Hot => [(205, 255, 2), (0, 230, 0), (0, 55, 1)],
+        BasicPalette::Mem => [(0, 0, 2), (190, 240, 1), (0, 210, 0)],
+        BasicPalette::Io => [(80, 140, 0), (80, 140, 0), (190, 245, 1)],
+        BasicPalette::Red => [(200, 255, 0), (50, 130, 0), (50, 130, 0)],
+        BasicPalette::Green => [(50, 110, 0), (200, 255, 0), (50, 110, 0)],
+        BasicPalette::Blue => [(80, 140, 0), (80, 140, 0), (205, 255, 0)],
+        BasicPalette::Yellow => [(175, 230, 0), (175, 230, 0), (50, 70, 0)],
+        BasicPalette::Purple => [(190, 255, 0), (80, 140, 0), (190, 255, 0)],
+        BasicPalette::Aqua => [(50, 110, 0), (165, 220, 0), (165, 220, 0)],
+        BasicPalette::Orange => [(190, 255, 0), (90, 155, 0), (0, 0, 0)],
+        BasicPalette::Cvd => [(225, 255, 2), (140, 230, 0), (0, 120, 1)],
+    }
+}
+
+fn rgb_components_for_palette(
+    palette: Palette,
+    ranges: &[PaletteRange],
+    name: &str,
+    vs: [f32; 3],
+) -> Color {
     let basic_palette = match palette {
         Palette::Basic(basic) => basic,
         Palette::Multi(MultiPalette::Java) => palettes::java::resolve(name),
@@ -344,26 +603,23 @@ fn rgb_components_for_palette(palette: Palette, name: &str, v1: f32, v2: f32, v3
         Palette::Multi(MultiPalette::Rust) => palettes::rust::resolve(name),
     };
 
-    match basic_palette {
-        BasicPalette::Hot => color!(t!(205, 50_f32, v3), t!(0, 230_f32, v1), t!(0, 55_f32, v2)),
-        BasicPalette::Mem => color!(t!(0, 0_f32, v3), t!(190, 50_f32, v2), t!(0, 210_f32, v1)),
-        BasicPalette::Io => color!(t!(80, 60_f32, v1), t!(80, 60_f32, v1), t!(190, 55_f32, v2)),
-        BasicPalette::Red => color!(t!(200, 55_f32, v1), t!(50, 80_f32, v1), t!(50, 80_f32, v1)),
-        BasicPalette::Green => color!(t!(50, 60_f32, v1), t!(200, 55_f32, v1), t!(50, 60_f32, v1)),
-        BasicPalette::Blue => color!(t!(80, 60_f32, v1), t!(80, 60_f32, v1), t!(205, 50_f32, v1)),
-        BasicPalette::Yellow => {
-            color!(t!(175, 55_f32, v1), t!(175, 55_f32, v1), t!(50, 20_f32, v1))
-        }
-        BasicPalette::Purple => {
-            color!(t!(190, 65_f32, v1), t!(80, 60_f32, v1), t!(190, 65_f32, v1))
-        }
-        BasicPalette::Aqua => color!(t!(50, 60_f32, v1), t!(165, 55_f32, v1), t!(165, 55_f32, v1)),
-        BasicPalette::Orange => color!(t!(190, 65_f32, v1), t!(90, 65_f32, v1), t!(0, 0_f32, v1)),
+    let mut channels = channels(basic_palette);
+    // The last range given for a palette wins.
+    if let Some(range) = ranges.iter().rev().find(|r| r.palette == basic_palette) {
+        channels[0] = (range.from.r, range.to.r, channels[0].2);
+        channels[1] = (range.from.g, range.to.g, channels[1].2);
+        channels[2] = (range.from.b, range.to.b, channels[2].2);
     }
+    let [r, g, b] = channels.map(|(from, to, v)| {
+        let span = to as f32 - from as f32;
+        (from as f32 + (span * vs[v]).trunc()) as u8
+    });
+    color!(r, g, b)
 }
 
 pub(super) fn color(
     palette: Palette,
+    ranges: &[PaletteRange],
     hash: bool,
     deterministic: bool,
     name: &str,
@@ -399,16 +655,26 @@ pub(super) fn color(
         (rng(), rng(), rng())
     };
 
-    rgb_components_for_palette(palette, name, v1, v2, v3)
+    rgb_components_for_palette(palette, ranges, name, [v1, v2, v3])
 }
 
-pub(super) fn color_scale(value: isize, max: usize) -> Color {
+pub(super) fn color_scale(value: isize, max: usize, palette: Palette) -> Color {
     match value.cmp(&0) {
         Ordering::Equal => Color {
             r: 250,
             g: 250,
             b: 250,
         },
+        Ordering::Greater if palette == Palette::Basic(BasicPalette::Cvd) => {
+            // Red and blue are told apart by lightness alone by some readers with color vision
+            // deficiencies, so we use an orange hue, which stays clear of blue for everyone.
+            let c = 250 * (max as isize - value) / max as isize;
+            Color {
+                r: 255,
+                g: (140 + c * 11 / 25) as u8,
+                b: c as u8,
+            }
+        }
         Ordering::Greater => {
             // A positive value indicates _more_ samples,
             // and hence more time spent, so we give it a red hue.
@@ -436,7 +702,8 @@ fn default_bg_color_for(palette: Palette) -> BackgroundColor {
         | Palette::Basic(BasicPalette::Aqua)
         | Palette::Basic(BasicPalette::Yellow)
         | Palette::Basic(BasicPalette::Purple)
-        | Palette::Basic(BasicPalette::Orange) => BackgroundColor::Grey,
+        | Palette::Basic(BasicPalette::Orange)
+        | Palette::Basic(BasicPalette::Cvd) => BackgroundColor::Grey,
         _ => BackgroundColor::Yellow,
     }
 }
@@ -471,7 +738,7 @@ pub(super) fn bgcolor_for<'a>(
 mod tests {
     use super::namehash;
     use super::parse_hex_color;
-    use super::Color;
+    use super::{color, color_scale, BasicPalette, Color, ColorModifier, Palette, PaletteRange};
     use pretty_assertions::assert_eq;
 
     #[test]
@@ -511,4 +778,80 @@ mod tests {
         test_hash!("``0xfffffffffb8001d6", 0.418_131_17);
         test_hash!("", 1.0);
     }
+
+    #[test]
+    fn self_ratio_modifier_test() {
+        let orange = color!(225, 140, 0);
+        // Frames with only samples of their own keep their color.
+        assert_eq!(ColorModifier::SelfRatio.apply(orange, 10, 10), orange);
+        // The others are washed out towards gray, keeping their hue and lightness.
+        let washed_out = ColorModifier::SelfRatio.apply(orange, 0, 10);
+        assert_eq!(washed_out, color!(165, 148, 120));
+        let halfway = ColorModifier::SelfRatio.apply(orange, 5, 10);
+        assert!(halfway.r > washed_out.r && halfway.r < orange.r);
+        assert!(halfway.b < washed_out.b && halfway.b > orange.b);
+        assert_eq!(ColorModifier::SelfRatio.apply(orange, 0, 0), washed_out);
+    }
+
+    #[test]
+    fn cvd_color_test() {
+        let cvd = Palette::Basic(BasicPalette::Cvd);
+        for name in ["main", "std::io::Read::read", "genunix`kmem_cache_free", ""] {
+            let Color { r, g, b } = color(cvd, &[], true, false, name, || unreachable!());
+            // Orange to yellow: red dominates, and blue never does.
+            assert!(r >= 225 && r >= g && g >= b, "{}", name);
+        }
+        assert_eq!(
+            color(cvd, &[], false, false, "main", || 0.0),
+            color!(225, 140, 0)
+        );
+        assert_eq!(
+            color(cvd, &[], false, false, "main", || 1.0),
+            color!(255, 230, 120)
+        );
+    }
+
+    #[test]
+    fn palette_range_test() {
+        let range: PaletteRange = "hot=#0050a0..#40c0ff".parse().unwrap();
+        assert_eq!(range.palette, BasicPalette::Hot);
+        let hot = Palette::Basic(BasicPalette::Hot);
+        let ranges = [range];
+        assert_eq!(
+            color(hot, &ranges, false, false, "main", || 0.0),
+            color!(0x00, 0x50, 0xa0)
+        );
+        assert_eq!(
+            color(hot, &ranges, false, false, "main", || 1.0),
+            color!(0x40, 0xc0, 0xff)
+        );
+        // Ranges can run from lighter to darker colors too.
+        let ranges = ["hot=#ffffff..#000000".parse().unwrap()];
+        assert_eq!(
+            color(hot, &ranges, false, false, "main", || 0.5),
+            color!(128, 128, 128)
+        );
+        // Other palettes keep their own.
+        let cvd = Palette::Basic(BasicPalette::Cvd);
+        assert_eq!(
+            color(cvd, &ranges, false, false, "main", || 0.0),
+            color!(225, 140, 0)
+        );
+        assert!("java=#000000..#ffffff".parse::<PaletteRange>().is_err());
+        assert!("hot=#000000".parse::<PaletteRange>().is_err());
+        assert!("hot".parse::<PaletteRange>().is_err());
+    }
+
+    #[test]
+    fn cvd_color_scale_test() {
+        let cvd = Palette::Basic(BasicPalette::Cvd);
+        let hot = Palette::Basic(BasicPalette::Hot);
+        assert_eq!(color_scale(0, 100, cvd), color!(250, 250, 250));
+        assert_eq!(color_scale(100, 100, cvd), color!(255, 140, 0));
+        assert_eq!(color_scale(50, 100, cvd), color!(255, 195, 125));
+        assert_eq!(color_scale(1, 100, cvd), color!(255, 248, 247));
+        assert_eq!(color_scale(-100, 100, cvd), color!(100, 100, 255));
+        assert_eq!(color_scale(-100, 100, cvd), color_scale(-100, 100, hot));
+        assert_eq!(color_scale(100, 100, hot), color!(255, 100, 100));
+    }
 }
@@ -1,15 +1,24 @@
 "use strict";
-var details, searchbtn, unzoombtn, matchedtxt, svg, searching, frames, known_font_width;
+var details, searchbtn, unzoombtn, matchedtxt, filterbtn, svg, searching, filtering, frames, known_font_width, syncchannel, search_matches, zoomed;
 function init(evt) {
-    details = document.getElementById("details").firstChild;
+    // flame graphs that load this script from elsewhere say which version they were made for
+    if (typeof scriptversion != "undefined" && scriptversion.split(".")[0] != "1") {
+        console.warn("This script is for version 1 flame graphs, not version " + scriptversion);
+    }
+    details = document.getElementById("details");
+    if (details) details = details.firstChild;
     searchbtn = document.getElementById("search");
     unzoombtn = document.getElementById("unzoom");
     matchedtxt = document.getElementById("matched");
+    filterbtn = document.getElementById("filter");
     svg = document.getElementsByTagName("svg")[0];
     frames = document.getElementById("frames");
     known_font_width = get_monospace_width(frames);
     total_samples = parseInt(frames.attributes.total_samples.value);
     searching = 0;
+    filtering = 0;
+    if (typeof syncgroup != "undefined") sync_init();
+    if (typeof liveupdates != "undefined") live_init();
 
     // Use GET parameters to restore a flamegraph's state.
     var restore_state = function() {
@@ -18,16 +27,24 @@ function init(evt) {
             zoom(find_group(document.querySelector('[*|x="' + params.x + '"][y="' + params.y + '"]')));
         if (params.s)
             search(params.s);
+        if (params.f)
+            filter(parseFloat(params.f));
     };
 
     if (fluiddrawing) {
-        // Make width dynamic so the SVG fits its parent's width.
-        svg.removeAttribute("width");
+        // Make width dynamic so the SVG fits its parent's width, unless the width was already
+        // given in relative units (e.g. "80%" or "80vw").
+        if (/^[\d.]+$/.test(svg.getAttribute("width"))) {
+            svg.removeAttribute("width");
+        }
         // Edge requires us to have a viewBox that gets updated with size changes.
         var isEdge = /Edge\/\d./i.test(navigator.userAgent);
         if (!isEdge) {
             svg.removeAttribute("viewBox");
         }
+        // Keep the filter button and the matched text at a fixed distance from the search button.
+        var filter_offset = filterbtn ? searchbtn.attributes.x.value - filterbtn.attributes.x.value : 0;
+        var matched_offset = searchbtn.attributes.x.value - matchedtxt.attributes.x.value;
         var update_for_width_change = function() {
             if (isEdge) {
                 svg.attributes.viewBox.value = "0 0 " + svg.width.baseVal.value + " " + svg.height.baseVal.value;
@@ -35,6 +52,12 @@ function init(evt) {
 
             // Keep consistent padding on left and right of frames container.
             frames.attributes.width.value = svg.width.baseVal.value - xpad * 2;
+            // The depth histogram stays to the right of the frames.
+            if (typeof depth_histogram != "undefined") {
+                frames.attributes.width.value -= depth_histogram;
+                document.getElementById("depth_histogram").attributes.x.value =
+                    svg.width.baseVal.value - xpad - depth_histogram;
+            }
 
             // Text truncation needs to be adjusted for the current width.
             update_text_for_elements(frames.children);
@@ -42,7 +65,9 @@ function init(evt) {
             // Keep search elements at a fixed distance from right edge.
             var svgWidth = svg.width.baseVal.value;
             searchbtn.attributes.x.value = svgWidth - xpad;
-            matchedtxt.attributes.x.value = svgWidth - xpad;
+            matchedtxt.attributes.x.value = svgWidth - xpad - matched_offset;
+            if (filterbtn)
+                filterbtn.attributes.x.value = svgWidth - xpad - filter_offset;
         };
         window.addEventListener('resize', function() {
             update_for_width_change();
@@ -61,12 +86,21 @@ function init(evt) {
 window.addEventListener("click", function(e) {
     var target = find_group(e.target);
     if (target) {
+        // shift-click copies the frame's stack, alt-click its function name
+        if (e.shiftKey || e.altKey) {
+            e.preventDefault();
+            if (e.shiftKey) copy_to_clipboard(g_to_stack(target), "stack");
+            else copy_to_clipboard(g_to_name(target), "function name");
+            return;
+        }
         if (target.nodeName == "a") {
             if (e.ctrlKey === false) return;
             e.preventDefault();
         }
         if (target.classList.contains("parent")) unzoom();
         zoom(target);
+        sync_send({ zoom: g_to_name(target) });
+        notify_frameclick(target);
 
         // set parameters for zoom state
         var el = target.querySelector("rect");
@@ -79,6 +113,7 @@ window.addEventListener("click", function(e) {
     }
     else if (e.target.id == "unzoom") {
         unzoom();
+        sync_send({ zoom: null });
 
         // remove zoom state
         var params = get_params();
@@ -87,17 +122,27 @@ window.addEventListener("click", function(e) {
         history.replaceState(null, null, parse_params(params));
     }
     else if (e.target.id == "search") search_prompt();
+    else if (e.target.id == "matched") export_search();
+    else if (e.target.id == "filter") filter_prompt();
+    else if (e.target.id == "annotations") toggle_annotations();
+    else if (e.target.parentElement && e.target.parentElement.id == "annotations-list" && e.target.attributes["fg:x"])
+        zoom_to_annotated(e.target);
+    else if (e.target.parentElement && e.target.parentElement.id == "diff-summary" && e.target.attributes["fg:x"])
+        zoom_to_position(e.target);
+    else if (e.target.parentElement && e.target.parentElement.classList.contains("bookmark"))
+        zoom_to_bookmark(e.target.parentElement);
 }, false)
 // mouse-over for info
 // show
 window.addEventListener("mouseover", function(e) {
     var target = find_group(e.target);
-    if (target) details.nodeValue = nametype + " " + g_to_text(target);
+    if (target) show_details(details_text(target));
+    else if (e.target.id == "matched" && searching) show_details("Click to download the matching stacks");
 }, false)
 // clear
 window.addEventListener("mouseout", function(e) {
     var target = find_group(e.target);
-    if (target) details.nodeValue = ' ';
+    if (target || e.target.id == "matched") show_details(' ');
 }, false)
 // ctrl-F for search
 window.addEventListener("keydown",function (e) {
@@ -154,16 +199,126 @@ function g_to_text(e) {
     var text = find_child(e, "title").firstChild.nodeValue;
     return (text)
 }
+function g_to_details(e) {
+    // frames with short tooltips leave their counts to be worked out here
+    var text = g_to_text(e);
+    if (find_child(e, "title").attributes["fg:short"] == undefined) return text;
+    var r = find_child(e, "rect");
+    var w = parseInt(r.attributes["fg:w"].value);
+    var span = "";
+    if (r.attributes["data-start"] != undefined) span = "; " + r.attributes["data-start"].value + "–" + r.attributes["data-end"].value + " " + countname;
+    return text + " (" + scaled_count(w).toLocaleString("en") + " " + countname + ", " + scaled_pct(w).toFixed(2) + "%" + span + ")";
+}
+// counts are scaled by the factor the flame graph was made with, as in the full tooltips
+function scaled_count(w) {
+    return typeof factor == "undefined" ? w : Math.round(w * factor);
+}
+function scaled_pct(w) {
+    return typeof factor == "undefined" ? 100 * w / total_samples : 100 * scaled_count(w) / (total_samples * factor);
+}
+function details_text(e) {
+    if (typeof detailstemplate == "undefined") return nametype + " " + g_to_details(e);
+    var w = parseInt(find_child(e, "rect").attributes["fg:w"].value);
+    var values = {
+        nametype: nametype,
+        name: g_to_name(e),
+        details: g_to_details(e),
+        samples: scaled_count(w).toLocaleString("en"),
+        percent: scaled_pct(w).toFixed(2)
+    };
+    return detailstemplate.replace(/\{(\w+)\}/g, function(m, key) {
+        return key in values ? values[key] : m;
+    });
+}
+function show_details(text) {
+    // the details line may have been left out
+    if (details) details.nodeValue = text;
+}
 function g_to_func(e) {
     var func = g_to_text(e);
     // if there's any manipulation we want to do to the function
     // name before it's searched, do it here before returning.
     return (func);
 }
+function g_to_name(e) {
+    // strip annotations, and the counts that follow the name unless the tooltip is short, as in
+    // "name (1,234 samples, 5.67%)" or "name (self: 12 samples, 0.06%; +1.00%)"
+    var title = find_child(e, "title");
+    var text = title.firstChild.nodeValue.split("\n", 1)[0];
+    if (title.attributes["fg:short"] != undefined) return text;
+    return text.replace(/ \((self: )?([\d,]+ [^()]*?, )?([\d.]+%|\d+ stacks?)([,;][^()]*)?\)$/, "");
+}
+// escape the frame name the way folded stacks do, so that copied stacks can be read back
+function escape_frame(name) {
+    return name.replace(/(\\*)(;|$)/g, function(m, backslashes, semicolon) {
+        return backslashes + backslashes + (semicolon ? "\\;" : "");
+    });
+}
+function g_to_path(e) {
+    // collect the frame and its ancestors, which are the frames below it (or
+    // above it, when inverted) that span it, and list their names root first.
+    var attr = find_child(e, "rect").attributes;
+    var xmin = parseInt(attr["fg:x"].value);
+    var xmax = xmin + parseInt(attr["fg:w"].value);
+    var ymin = parseFloat(attr.y.value);
+    var stack = [];
+    var el = frames.children;
+    for (var i = 0; i < el.length; i++) {
+        var a = find_child(el[i], "rect").attributes;
+        var ex = parseInt(a["fg:x"].value);
+        var ew = parseInt(a["fg:w"].value);
+        var ey = parseFloat(a.y.value);
+        var upstack = inverted ? ey <= ymin : ey >= ymin;
+        if (upstack && ex <= xmin && (ex + ew) >= xmax)
+            stack.push({ y: ey, name: g_to_name(el[i]) });
+    }
+    stack.sort(function(a, b) {
+        return inverted ? a.y - b.y : b.y - a.y;
+    });
+    // the first frame is the root frame, which is not part of any input stack,
+    // unless it was left out of the flame graph
+    if (typeof omitroot == "undefined") stack.shift();
+    return stack.map(function(f) { return f.name; });
+}
+function g_to_stack(e) {
+    var width = find_child(e, "rect").attributes["fg:w"].value;
+    return g_to_path(e).map(escape_frame).join(";") + " " + width;
+}
+// tell the page the flame graph is embedded in which frame was clicked, so
+// that it can show related information, with an "inferno:frameclick" event
+function notify_frameclick(e) {
+    var detail = {
+        name: g_to_name(e),
+        stack: g_to_path(e),
+        samples: scaled_count(parseInt(find_child(e, "rect").attributes["fg:w"].value)),
+    };
+    // an inline SVG is part of the page, so the event bubbles up to its document
+    e.dispatchEvent(new CustomEvent("inferno:frameclick", { bubbles: true, detail: detail }));
+    // an <object> or <iframe> gets the event on its element, if the page may see it
+    try {
+        var host = window.frameElement;
+        if (host) {
+            var view = host.ownerDocument.defaultView;
+            host.dispatchEvent(new view.CustomEvent("inferno:frameclick", { bubbles: true, detail: detail }));
+        }
+    } catch (err) {}
+}
+function copy_to_clipboard(text, what) {
+    var done = function() {
+        show_details("Copied " + what + " to clipboard");
+    };
+    if (navigator.clipboard && navigator.clipboard.writeText) {
+        navigator.clipboard.writeText(text).then(done, function() {
+            prompt("Copy " + what + ":", text);
+        });
+    } else {
+        prompt("Copy " + what + ":", text);
+    }
+}
 function get_monospace_width(frames) {
     // Given the id="frames" element, return the width of text characters if
     // this is a monospace font, otherwise return 0.
-    text = find_child(frames.children[0], "text");
+    text = find_label(frames.children[0]);
     originalContent = text.textContent;
     text.textContent = "!";
     bangWidth = text.getComputedTextLength();
@@ -176,6 +331,23 @@ function get_monospace_width(frames) {
         return 0;
     }
 }
+function find_label(e) {
+    // frames that were too narrow for a label have no text element until they are zoomed into
+    var t = find_child(e, "text");
+    if (t) return t;
+    var r = find_child(e, "rect");
+    t = document.createElementNS("http://www.w3.org/2000/svg", "text");
+    t.setAttribute("x", r.attributes.x.value);
+    t.setAttribute("y", (3 + parseFloat(r.attributes.y.value) + parseFloat(r.attributes.height.value) / 2).toFixed(2));
+    r.parentNode.insertBefore(t, r.nextSibling);
+    return t;
+}
+function frame_label(e) {
+    // frames with a label of their own keep it, others are labeled with their title
+    var t = find_label(e);
+    if (t.attributes["fg:label"] != undefined) return t.attributes["fg:label"].value;
+    return find_child(e, "title").textContent.split("\n", 1)[0].replace(/\([^(]*\)$/,"");
+}
 function update_text_for_elements(elements) {
     // In order to render quickly in the browser, you want to do one pass of
     // reading attributes, and one pass of mutating attributes. See
@@ -194,25 +366,34 @@ function update_text_for_elements(elements) {
     for (var i = 0; i < elements.length; i++) {
         var e = elements[i];
         var r = find_child(e, "rect");
-        var t = find_child(e, "text");
+        var t = find_label(e);
         var w = parseFloat(r.attributes.width.value) * frames.attributes.width.value / 100 - 3;
-        var txt = find_child(e, "title").textContent.split("\n", 1)[0].replace(/\([^(]*\)$/,"");
+        var txt = frame_label(e);
         var newX = format_percent((parseFloat(r.attributes.x.value) + (100 * 3 / frames.attributes.width.value)));
+        // Text that was shrunk to fit its frame has its own font size
+        var font_width = known_font_width;
+        if (t.attributes["font-size"] != undefined) {
+            font_width *= parseFloat(t.attributes["font-size"].value) / fontsize;
+        }
 
         // Smaller than this size won't fit anything
-        if (w < 2 * known_font_width) {
+        if (w < 2 * font_width) {
             textElemNewAttributes.push([newX, ""]);
             continue;
         }
 
         // Fit in full text width
-        if (txt.length * known_font_width < w) {
+        if (txt.length * font_width < w) {
             textElemNewAttributes.push([newX, txt]);
             continue;
         }
 
-        var substringLength = Math.floor(w / known_font_width) - 2;
-        if (truncate_text_right) {
+        var substringLength = Math.floor(w / font_width) - 2;
+        if (typeof truncate_text_middle != "undefined") {
+            // Truncate the middle of the text.
+            textElemNewAttributes.push([newX, truncate_middle(txt, substringLength)]);
+            continue;
+        } else if (truncate_text_right) {
             // Truncate the right side of the text.
             textElemNewAttributes.push([newX, txt.substring(0, substringLength) + ".."]);
             continue;
@@ -229,18 +410,27 @@ function update_text_for_elements(elements) {
     for (var i = 0; i < elements.length; i++) {
         var e = elements[i];
         var values = textElemNewAttributes[i];
-        var t = find_child(e, "text");
+        var t = find_label(e);
         t.attributes.x.value = values[0];
         t.textContent = values[1];
+        update_badge(e);
     }
 }
+function update_badge(e) {
+    // keep annotation badges at the right end of their frame
+    var b = find_child(e, "text.badge");
+    if (!b) return;
+    var r = find_child(e, "rect");
+    b.attributes.x.value = format_percent(parseFloat(r.attributes.x.value) + parseFloat(r.attributes.width.value) - (100 * 3 / frames.attributes.width.value));
+}
 
 function update_text(e) {
     var r = find_child(e, "rect");
-    var t = find_child(e, "text");
+    var t = find_label(e);
     var w = parseFloat(r.attributes.width.value) * frames.attributes.width.value / 100 - 3;
-    var txt = find_child(e, "title").textContent.replace(/\([^(]*\)$/,"");
+    var txt = frame_label(e);
     t.attributes.x.value = format_percent((parseFloat(r.attributes.x.value) + (100 * 3 / frames.attributes.width.value)));
+    update_badge(e);
 
     // Smaller than this size won't fit anything
     if (w < 2 * fontsize * fontwidth) {
@@ -251,7 +441,13 @@ function update_text(e) {
     // Fit in full text width
     if (t.getComputedTextLength() < w)
         return;
-    if (truncate_text_right) {
+    if (typeof truncate_text_middle != "undefined") {
+        // Truncate the middle of the text.
+        for (var x = txt.length - 2; x > 0; x--) {
+            t.textContent = truncate_middle(txt, x);
+            if (t.getComputedTextLength() <= w) return;
+        }
+    } else if (truncate_text_right) {
         // Truncate the right side of the text.
         for (var x = txt.length - 2; x > 0; x--) {
             if (t.getSubStringLength(0, x + 2) <= w) {
@@ -270,6 +466,11 @@ function update_text(e) {
     }
     t.textContent = "";
 }
+// keeps `keep` characters of the start and end of the text around ".."
+function truncate_middle(txt, keep) {
+    var tail = keep - Math.floor(keep / 2);
+    return txt.substring(0, keep - tail) + ".." + txt.substring(txt.length - tail, txt.length);
+}
 // zoom
 function zoom_reset(e) {
     if (e.tagName == "rect") {
@@ -282,7 +483,9 @@ function zoom_reset(e) {
     }
 }
 function zoom_child(e, x, zoomed_width_samples) {
-    if (e.tagName == "text") {
+    if (e.tagName == "text" && e.classList.contains("badge")) {
+        // placed by update_text once the frame has its new width
+    } else if (e.tagName == "text") {
         var parent_x = parseFloat(find_child(e.parentNode, "rect[x]").attributes.x.value);
         e.attributes.x.value = format_percent(parent_x + (100 * 3 / frames.attributes.width.value));
     } else if (e.tagName == "rect") {
@@ -295,6 +498,13 @@ function zoom_child(e, x, zoomed_width_samples) {
     }
 }
 function zoom_parent(e) {
+    if (e.tagName == "rect" && e.classList.contains("self")) {
+        // the self samples of a parent keep their share of its (now full) width
+        var w = parseInt(find_child(e.parentNode, "rect").attributes["fg:w"].value);
+        e.attributes.x.value = "0.0%";
+        e.attributes.width.value = format_percent(100 * parseInt(e.attributes["fg:w"].value) / w);
+        return;
+    }
     if (e.attributes) {
         if (e.attributes.x != undefined) {
             e.attributes.x.value = "0.0%";
@@ -314,6 +524,7 @@ function zoom(node) {
     var xmin = parseInt(attr["fg:x"].value);
     var xmax = xmin + width;
     var ymin = parseFloat(attr.y.value);
+    zoomed = node;
     unzoombtn.classList.remove("hide");
     var el = frames.children;
     var to_update_text = [];
@@ -353,7 +564,52 @@ function zoom(node) {
     }
     update_text_for_elements(to_update_text);
 }
+// annotations panel
+function toggle_annotations() {
+    document.getElementById("annotations-list").classList.toggle("hide");
+}
+function zoom_to_annotated(e) {
+    if (zoom_to_position(e)) toggle_annotations();
+}
+// zoom into the frame at the fg:x and fg:y of an entry of a list, such as the diff summary
+function zoom_to_position(e) {
+    var x = e.attributes["fg:x"].value;
+    var y = e.attributes["fg:y"].value;
+    var target = find_group(document.querySelector('#frames rect[*|x="' + x + '"][y="' + y + '"]'));
+    if (!target) return false;
+    unzoom();
+    zoom(target);
+    sync_send({ zoom: g_to_name(target) });
+
+    // set parameters for zoom state
+    var params = get_params();
+    params.x = x;
+    params.y = y;
+    history.replaceState(null, null, parse_params(params));
+    return true;
+}
+// bookmarks
+function zoom_to_bookmark(e) {
+    // zoom into the first frame of the bookmarked function
+    var func = e.attributes["fg:func"].value;
+    var el = frames.children;
+    for (var i = 0; i < el.length; i++) {
+        if (g_to_name(el[i]) != func) continue;
+        unzoom();
+        zoom(el[i]);
+        sync_send({ zoom: func });
+
+        // set parameters for zoom state
+        var rect = find_child(el[i], "rect");
+        var params = get_params();
+        params.x = rect.attributes["fg:x"].value;
+        params.y = rect.attributes.y.value;
+        history.replaceState(null, null, parse_params(params));
+        return;
+    }
+}
 function unzoom() {
+    zoomed = undefined;
     unzoombtn.classList.add("hide");
     var el = frames.children;
     for(var i = 0; i < el.length; i++) {
@@ -379,21 +635,26 @@ function search_prompt() {
             "allowed, eg: ^ext4_)", "");
         if (term != null) {
             search(term)
+            sync_send({ search: term });
         }
     } else {
-        reset_search();
-        searching = 0;
-        searchbtn.classList.remove("show");
-        searchbtn.firstChild.nodeValue = "Search"
-        matchedtxt.classList.add("hide");
-        matchedtxt.firstChild.nodeValue = ""
+        clear_search();
+        sync_send({ search: null });
     }
 }
+function clear_search() {
+    reset_search();
+    searching = 0;
+    searchbtn.classList.remove("show");
+    searchbtn.firstChild.nodeValue = "Search"
+    matchedtxt.classList.add("hide");
+    matchedtxt.firstChild.nodeValue = ""
+    search_matches = [];
+}
 function search(term) {
     var re = new RegExp(term);
     var el = frames.children;
     var matches = new Object();
-    var maxwidth = 0;
     for (var i = 0; i < el.length; i++) {
         var e = el[i];
         // Skip over frames which are either not visible, or below the zoomed-to frame
@@ -404,10 +665,7 @@ function search(term) {
         var rect = find_child(e, "rect");
         if (func == null || rect == null)
             continue;
-        // Save max width. Only works as we have a root frame
         var w = parseInt(rect.attributes["fg:w"].value);
-        if (w > maxwidth)
-            maxwidth = w;
         if (func.match(re)) {
             // highlight
             var x = parseInt(rect.attributes["fg:x"].value);
@@ -415,11 +673,11 @@ function search(term) {
             rect.attributes.fill.value = searchcolor;
             // remember matches
             if (matches[x] == undefined) {
-                matches[x] = w;
+                matches[x] = { w: w, e: e };
             } else {
-                if (w > matches[x]) {
+                if (w > matches[x].w) {
                     // overwrite with parent
-                    matches[x] = w;
+                    matches[x] = { w: w, e: e };
                 }
             }
             searching = 1;
@@ -435,6 +693,7 @@ function search(term) {
     searchbtn.firstChild.nodeValue = "Reset Search";
     // calculate percent matched, excluding vertical overlap
     var count = 0;
+    search_matches = [];
     var lastx = -1;
     var lastw = 0;
     var keys = Array();
@@ -452,19 +711,217 @@ function search(term) {
     // where children are always smaller than their parents.
     for (var k in keys) {
         var x = parseInt(keys[k]);
-        var w = matches[keys[k]];
+        var w = matches[keys[k]].w;
         if (x >= lastx + lastw) {
             count += w;
+            search_matches.push(matches[keys[k]].e);
             lastx = x;
             lastw = w;
         }
     }
-    // display matched percent
+    // display matched percent, of the zoomed-to frame if there is one; the root
+    // frame may have been left out, so the total is taken from all samples
     matchedtxt.classList.remove("hide");
-    var pct = 100 * count / maxwidth;
+    var total = zoomed ? parseInt(find_child(zoomed, "rect").attributes["fg:w"].value) : total_samples;
+    var pct = 100 * count / total;
     if (pct != 100) pct = pct.toFixed(1);
     matchedtxt.firstChild.nodeValue = "Matched: " + pct + "%";
 }
+// download the stacks of the outermost matching frames, with their sample counts, as folded
+// stack lines
+function export_search() {
+    if (!searching) return;
+    var lines = search_matches.map(g_to_stack);
+    var url = "data:text/plain;charset=utf-8," + encodeURIComponent(lines.join("\n") + "\n");
+    var a = document.createElementNS("http://www.w3.org/1999/xhtml", "a");
+    if (typeof a.download == "undefined") {
+        window.open(url);
+        return;
+    }
+    a.href = url;
+    a.download = "search-matches.txt";
+    svg.appendChild(a);
+    a.click();
+    svg.removeChild(a);
+}
+// filter
+function filter_prompt() {
+    if (!filtering) {
+        var threshold = prompt("Hide frames below this percentage " +
+            "of all samples (eg: 0.5)", "");
+        if (threshold != null && !isNaN(parseFloat(threshold))) {
+            filter(parseFloat(threshold));
+        }
+    } else {
+        reset_filter();
+    }
+}
+function filter(threshold) {
+    var el = frames.children;
+    for (var i = 0; i < el.length; i++) {
+        var rect = find_child(el[i], "rect");
+        if (rect == null || rect.attributes["fg:w"] == undefined)
+            continue;
+        var pct = 100 * parseInt(rect.attributes["fg:w"].value) / total_samples;
+        if (pct < threshold) {
+            el[i].classList.add("filtered");
+        } else {
+            el[i].classList.remove("filtered");
+        }
+    }
+    filtering = 1;
+    var params = get_params();
+    params.f = threshold;
+    history.replaceState(null, null, parse_params(params));
+
+    filterbtn.classList.add("show");
+    filterbtn.firstChild.nodeValue = "Reset Filter (<" + threshold + "%)";
+}
+function reset_filter() {
+    var el = document.querySelectorAll("#frames > .filtered");
+    for (var i = 0; i < el.length; i++) {
+        el[i].classList.remove("filtered");
+    }
+    filtering = 0;
+    var params = get_params();
+    delete params.f;
+    history.replaceState(null, null, parse_params(params));
+
+    filterbtn.classList.remove("show");
+    filterbtn.firstChild.nodeValue = "Filter";
+}
+// sync with the other flame graphs of the same group on the page
+function sync_init() {
+    if (typeof BroadcastChannel != "undefined") {
+        syncchannel = new BroadcastChannel("inferno-sync");
+        syncchannel.onmessage = function(e) { sync_receive(e.data); };
+    } else {
+        window.addEventListener("message", function(e) { sync_receive(e.data); }, false);
+    }
+}
+function sync_send(msg) {
+    if (typeof syncgroup == "undefined") return;
+    msg.inferno_sync = syncgroup;
+    if (syncchannel) {
+        syncchannel.postMessage(msg);
+        return;
+    }
+    // without BroadcastChannel, post to the frames of the embedding page instead
+    var page = window.parent;
+    if (page === window) return;
+    for (var i = 0; i < page.frames.length; i++) {
+        if (page.frames[i] !== window) page.frames[i].postMessage(msg, "*");
+    }
+}
+function sync_receive(msg) {
+    // messages are only applied here, never passed on, so they can't bounce between graphs
+    if (!msg || msg.inferno_sync !== syncgroup) return;
+    if ("search" in msg) {
+        if (searching) clear_search();
+        if (msg.search != null) search(msg.search);
+    }
+    if ("zoom" in msg) {
+        unzoom();
+        if (msg.zoom == null) return;
+        // zoom into the widest frame of the function, if this graph has it
+        var target, maxwidth = 0;
+        var el = frames.children;
+        for (var i = 0; i < el.length; i++) {
+            var w = parseInt(find_child(el[i], "rect").attributes["fg:w"].value);
+            if (w > maxwidth && g_to_name(el[i]) == msg.zoom) {
+                target = el[i];
+                maxwidth = w;
+            }
+        }
+        if (target) zoom(target);
+    }
+}
+// live updates, which patch the frames as the profile changes
+function live_init() {
+    var source = new EventSource(liveupdates);
+    source.onmessage = function(e) { apply_patch(JSON.parse(e.data)); };
+}
+function frame_keys() {
+    // key frames by their stacks, root first, row by row from the root: the parent of a frame
+    // is the last frame of the row nearer the root that starts no later than it does
+    var rows = {}, keys = Object.create(null);
+    var el = frames.children;
+    for (var i = 0; i < el.length; i++) {
+        var a = find_child(el[i], "rect").attributes;
+        var y = a.y.value;
+        if (rows[y] == undefined) rows[y] = [];
+        rows[y].push({ x: parseInt(a["fg:x"].value), e: el[i] });
+    }
+    var ys = Object.keys(rows).sort(function(a, b) {
+        return inverted ? a - b : b - a;
+    });
+    var below = [];
+    for (var i = 0; i < ys.length; i++) {
+        var row = rows[ys[i]].sort(function(a, b) { return a.x - b.x; });
+        for (var j = 0, k = 0; j < row.length; j++) {
+            while (k < below.length && below[k].x <= row[j].x) k++;
+            var key = (k > 0 ? below[k - 1].key + ";" : "") + g_to_name(row[j].e);
+            // frames of a stack that comes up more than once, as in flame charts, are counted
+            var unique = key;
+            for (var n = 1; keys[unique] != undefined; n++) unique = key + "#" + n;
+            row[j].key = unique;
+            keys[unique] = row[j].e;
+        }
+        below = row;
+    }
+    return keys;
+}
+function apply_patch(patch) {
+    if (patch.reload) {
+        location.reload();
+        return;
+    }
+    // take off the zoom, search and filter, and put them back on the patched frames
+    var keys = frame_keys();
+    var zoomed_key;
+    for (var key in keys) {
+        if (keys[key] === zoomed) zoomed_key = key;
+    }
+    var params = get_params();
+    if (searching) clear_search();
+    if (filtering) reset_filter();
+
+    for (var i = 0; i < patch.removed.length; i++) {
+        var e = keys[patch.removed[i]];
+        if (e) frames.removeChild(e);
+    }
+    var parser = new DOMParser();
+    for (var i = 0; i < patch.frames.length; i++) {
+        var f = patch.frames[i];
+        var doc = parser.parseFromString('<svg xmlns="http://www.w3.org/2000/svg" ' +
+            'xmlns:xlink="http://www.w3.org/1999/xlink" xmlns:fg="http://github.com/jonhoo/inferno">' +
+            f.svg + '</svg>', "image/svg+xml");
+        var e = document.importNode(doc.documentElement.firstElementChild, true);
+        if (keys[f.key]) {
+            frames.replaceChild(e, keys[f.key]);
+        } else {
+            frames.appendChild(e);
+        }
+    }
+    total_samples = patch.total_samples;
+    frames.attributes.total_samples.value = total_samples;
+
+    // frames are laid out anew for the new total, and their labels fitted to their widths
+    unzoom();
+    var target = zoomed_key && frame_keys()[zoomed_key];
+    if (target) {
+        zoom(target);
+        var rect = find_child(target, "rect");
+        params.x = rect.attributes["fg:x"].value;
+        params.y = rect.attributes.y.value;
+    } else {
+        delete params.x;
+        delete params.y;
+    }
+    history.replaceState(null, null, parse_params(params));
+    if (params.s) search(params.s);
+    if (params.f) filter(parseFloat(params.f));
+}
 function format_percent(n) {
     return n.toFixed(4) + "%";
 }
@@ -0,0 +1,131 @@
+use std::borrow::Cow;
+
+use ahash::AHashMap;
+use regex::Regex;
+
+use super::merge;
+
+/// Rewrites the frames of functions matching regular expressions to the labels of the groups
+/// they belong to, such as `stdlib` for everything under `std::` and `alloc::`.
+///
+/// This makes a coarse flame graph by subsystem rather than by function: a run of frames of the
+/// same group in a stack becomes a single frame, and stacks that end up the same are merged. The
+/// first matching pattern decides the group of a frame, and frames that match none keep their
+/// names.
+#[derive(Clone, Debug, Default)]
+pub struct GroupRules(Vec<(Regex, String)>);
+
+impl PartialEq for GroupRules {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|((re1, l1), (re2, l2))| re1.as_str() == re2.as_str() && l1 == l2)
+    }
+}
+
+impl From<Vec<(Regex, String)>> for GroupRules {
+    fn from(rules: Vec<(Regex, String)>) -> Self {
+        GroupRules(rules)
+    }
+}
+
+impl GroupRules {
+    /// Group every frame whose function name matches `pattern` under `label`.
+    pub fn add(&mut self, pattern: &str, label: &str) -> Result<(), regex::Error> {
+        self.0.push((Regex::new(pattern)?, label.to_string()));
+        Ok(())
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Rewrite the stacks of folded lines with `counts` sample counts each. Lines that have no
+    /// frames to group are kept as they are.
+    pub(super) fn group_lines<'a, I>(&self, lines: I, counts: usize) -> Vec<Cow<'a, str>>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        // The labels of frames that were seen before, as `;` would separate them.
+        let labels: Vec<String> = self.0.iter().map(|(_, l)| l.replace(';', "\\;")).collect();
+        let mut groups: AHashMap<&'a str, Option<usize>> = AHashMap::default();
+        lines
+            .into_iter()
+            .map(|line| {
+                let Some(end) = stack_end(line, counts) else {
+                    return Cow::Borrowed(line);
+                };
+                let (stack, counts) = line.split_at(end);
+                let mut grouped = String::with_capacity(line.len());
+                let mut last_group = None;
+                for frame in merge::split_frames(stack) {
+                    let group = *groups.entry(frame).or_insert_with(|| {
+                        self.0.iter().position(|(regex, _)| regex.is_match(frame))
+                    });
+                    if group.is_some() && group == last_group {
+                        continue;
+                    }
+                    if !grouped.is_empty() {
+                        grouped.push(';');
+                    }
+                    grouped.push_str(group.map_or(frame, |g| &labels[g]));
+                    last_group = group;
+                }
+                if grouped.len() == stack.len() && grouped == stack {
+                    return Cow::Borrowed(line);
+                }
+                grouped.push_str(counts);
+                Cow::Owned(grouped)
+            })
+            .collect()
+    }
+}
+
+// Returns where the stack of a folded line ends, before its sample counts.
+fn stack_end(line: &str, counts: usize) -> Option<usize> {
+    let (samplesi, _, _) = merge::rfind_signed_samples(line)?;
+    let mut end = samplesi - 1;
+    if counts > 1 {
+        if let Some((samplesi, _, _)) = merge::rfind_signed_samples(line[..end].trim_end()) {
+            end = samplesi - 1;
+        }
+    }
+    Some(line[..end].trim_end().len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn group_lines() {
+        let mut rules = GroupRules::default();
+        rules.add("^(std|core)::", "stdlib").unwrap();
+        rules.add("^std::io::", "io").unwrap();
+        rules.add("^tokio::", "async; runtime").unwrap();
+
+        let lines = [
+            "main;app::run;std::io::read;core::ptr::copy 5",
+            "main;app::run;app::parse 3",
+            "tokio::spawn;tokio::poll;app::run;std::fmt::write 2",
+            "main;core::fmt::write 1 4",
+            "no samples",
+        ];
+        let grouped = rules.group_lines(lines, 2);
+        assert_eq!(
+            grouped,
+            [
+                "main;app::run;stdlib 5",
+                "main;app::run;app::parse 3",
+                "async\\; runtime;app::run;stdlib 2",
+                "main;stdlib 1 4",
+                "no samples",
+            ]
+        );
+        assert!(matches!(grouped[1], Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+use super::color::Color;
+
+/// A frame about to be drawn, as seen by a [`FrameHook`].
+///
+/// The first fields describe the frame and are informational; the remaining fields are
+/// pre-filled with what would otherwise be drawn and can be changed by the hook.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct FrameInfo<'a> {
+    /// The function name of the frame, exactly as it appears in the input.
+    ///
+    /// This is empty for the frame that spans the whole graph (`all`).
+    pub function: &'a str,
+
+    /// The depth of the frame in the stack, where `0` is the `all` frame.
+    pub depth: usize,
+
+    /// The number of samples the width of the frame represents.
+    pub samples: usize,
+
+    /// The text drawn on the frame, which is truncated if it does not fit.
+    pub name: String,
+
+    /// The tooltip of the frame.
+    pub tooltip: String,
+
+    /// The fill color of the frame, overriding the one picked by the palette.
+    pub color: Option<Color>,
+
+    /// A link to follow when the frame is clicked.
+    pub href: Option<String>,
+}
+
+/// A function that is called for every frame before it is drawn.
+///
+/// The hook can change the name, tooltip, color and link of the frame through the
+/// [`FrameInfo`] it is given, or return `false` to leave the frame out of the graph. The
+/// frame's children are drawn either way.
+///
+/// Like [`Options::palette_map`](super::Options::palette_map), the hook is borrowed, so it can
+/// keep state that outlives the flame graph:
+///
+/// ```
+/// use inferno::flamegraph::{FrameHook, Options};
+///
+/// let mut skipped = 0;
+/// let mut hook = |frame: &mut inferno::flamegraph::FrameInfo<'_>| {
+///     if frame.function.starts_with("std::") {
+///         frame.href = Some(format!("https://doc.rust-lang.org/std/?search={}", frame.name));
+///     }
+///     if frame.function.starts_with("[unknown]") {
+///         skipped += 1;
+///         return false;
+///     }
+///     true
+/// };
+///
+/// let mut options = Options::default();
+/// options.frame_hook = Some(FrameHook::new(&mut hook));
+/// ```
+pub struct FrameHook<'a>(&'a mut dyn FnMut(&mut FrameInfo<'_>) -> bool);
+
+impl<'a> FrameHook<'a> {
+    /// Wrap a closure as a frame hook.
+    pub fn new(hook: &'a mut dyn FnMut(&mut FrameInfo<'_>) -> bool) -> Self {
+        FrameHook(hook)
+    }
+
+    pub(super) fn call(&mut self, frame: &mut FrameInfo<'_>) -> bool {
+        (self.0)(frame)
+    }
+}
+
+impl fmt::Debug for FrameHook<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FrameHook(..)")
+    }
+}
+
+// Closures can't be compared, so two hooks are only equal if they are the same hook.
+impl PartialEq for FrameHook<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            &*self.0 as *const _ as *const u8,
+            &*other.0 as *const _ as *const u8,
+        )
+    }
+}
@@ -0,0 +1,151 @@
+//! Structured comparisons of the layouts of two flame graphs.
+//!
+//! Putting two flame graphs side by side shows that something moved, but not what, and not by
+//! how much. [`diff`] lays out two sets of folded stack lines the way a flame graph would, with
+//! the same [`Options`], and lists the frames whose width changed by more than a threshold, with
+//! their stacks, so that regression dashboards and the like can show what changed without
+//! comparing SVGs.
+//!
+//! ```
+//! use inferno::flamegraph::{layout, Options};
+//!
+//! let before = ["main;parse 50", "main;eval 50"];
+//! let after = ["main;parse 80", "main;eval 20"];
+//! let changes = layout::diff(&mut Options::default(), before, after, 10.0).unwrap();
+//! assert_eq!(changes.len(), 2);
+//! assert_eq!(changes[0].stack, "main;eval");
+//! assert_eq!(changes[0].delta_pct(), -30.0);
+//! ```
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+
+use super::merge::{
+    split_frames, unescape_frame, StackSampleCount, StackSampleCountExt, TimedFrame,
+};
+use super::{render_layout, Options};
+
+/// A frame whose width changed from one flame graph to another.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct FrameChange {
+    /// The functions of the frame and of all of its parents, from the root down, as a folded
+    /// stack: separated by `;`, with the frames escaped as they are in the input. See
+    /// [`frames`](Self::frames) for the functions themselves.
+    pub stack: String,
+
+    /// The width of the frame in the first flame graph, as a percentage of the width of all
+    /// frames, or 0 if it isn't in it.
+    pub before_pct: f64,
+
+    /// The width of the frame in the second flame graph, as a percentage of the width of all
+    /// frames, or 0 if it isn't in it.
+    pub after_pct: f64,
+}
+
+impl FrameChange {
+    /// How much wider the frame got, in percentage points of the width of all frames. Frames
+    /// that got narrower have a negative delta.
+    pub fn delta_pct(&self) -> f64 {
+        self.after_pct - self.before_pct
+    }
+
+    /// The functions of the frame and of all of its parents, from the root down, with escaped
+    /// semicolons turned back into semicolons.
+    pub fn frames(&self) -> impl DoubleEndedIterator<Item = Cow<'_, str>> {
+        split_frames(&self.stack).map(unescape_frame)
+    }
+}
+
+/// Compare the layouts of the flame graphs of two sets of folded stack lines.
+///
+/// Both sets are merged as [`render`](super::render) merges them with `opt`, so the frames are
+/// those of the flame graphs that `opt` draws, before frames are pruned for being too narrow to
+/// draw. Frames whose width changed by more than `threshold_pct` percentage points of the width
+/// of all frames are returned, including those that are in only one of the flame graphs, from the
+/// largest change to the smallest.
+pub fn diff<'a, A, B>(
+    opt: &mut Options<'_>,
+    before: A,
+    after: B,
+    threshold_pct: f64,
+) -> io::Result<Vec<FrameChange>>
+where
+    A: IntoIterator<Item = &'a str>,
+    B: IntoIterator<Item = &'a str>,
+{
+    let before = render_layout(opt, before)?.widths();
+    let after = render_layout(opt, after)?.widths();
+    let stacks: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let mut changes: Vec<_> = stacks
+        .into_iter()
+        .map(|stack| FrameChange {
+            stack: stack.clone(),
+            before_pct: before.get(stack).copied().unwrap_or(0.0),
+            after_pct: after.get(stack).copied().unwrap_or(0.0),
+        })
+        .filter(|change| change.delta_pct().abs() > threshold_pct)
+        .collect();
+    // Stacks are in order already, which breaks ties.
+    changes.sort_by(|a, b| b.delta_pct().abs().total_cmp(&a.delta_pct().abs()));
+    Ok(changes)
+}
+
+// The frames of a flame graph, with their stacks and widths, in samples.
+#[derive(Debug, Default)]
+pub(super) struct Layout {
+    total: usize,
+    frames: Vec<LaidOutFrame>,
+}
+
+#[derive(Debug)]
+struct LaidOutFrame {
+    stack: String,
+    width: usize,
+}
+
+impl Layout {
+    // Finds the stack of every merged frame, going by where it starts and how deep it is.
+    // Frames start after their parents, or with them, and a frame's parent is the frame one
+    // level up that started last. Frames without any width are left out, as they'd make that
+    // ambiguous.
+    pub(super) fn new<CountType>(frames: &[TimedFrame<'_, CountType>], total: usize) -> Self
+    where
+        StackSampleCount<CountType>: StackSampleCountExt,
+    {
+        let mut frames: Vec<_> = frames
+            .iter()
+            .filter(|frame| frame.location.depth > 0 && frame.visual_samples() > 0)
+            .collect();
+        frames.sort_by_key(|frame| (frame.start_time.visual(), frame.location.depth));
+
+        let mut path: Vec<&str> = Vec::new();
+        let frames = frames
+            .into_iter()
+            .map(|frame| {
+                path.truncate(frame.location.depth - 1);
+                path.push(frame.location.function);
+                LaidOutFrame {
+                    stack: path.join(";"),
+                    width: frame.visual_samples(),
+                }
+            })
+            .collect();
+        Layout { total, frames }
+    }
+
+    // The width of every frame, by its stack, as a percentage of the width of all frames.
+    fn widths(&self) -> HashMap<String, f64> {
+        let mut widths = HashMap::new();
+        if self.total == 0 {
+            return widths;
+        }
+        for frame in &self.frames {
+            // Flame charts have a frame for every run of a stack, which are added up.
+            *widths.entry(frame.stack.clone()).or_default() +=
+                frame.width as f64 * 100.0 / self.total as f64;
+        }
+        widths
+    }
+}
@@ -1,14 +1,18 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::io;
 use std::iter;
 use std::ops::Add;
 use std::ops::AddAssign;
 
+use ahash::AHashMap;
 use log::warn;
 
 use crate::flamegraph::FrameWidthSource;
+pub(super) use crate::folded::line::rfind_samples;
+pub(crate) use crate::folded::line::{split_frames, unescape_frame};
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(super) struct Frame<'a> {
     pub(super) function: &'a str,
     pub(super) depth: usize,
@@ -24,12 +28,20 @@ impl<T> CountTypeRequirements for T where
 {
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(super) struct TimedFrame<'a, CountType> {
     pub(super) location: Frame<'a>,
     pub(super) start_time: StackSampleCount<CountType>,
     pub(super) end_time: StackSampleCount<CountType>,
     pub(super) self_and_total_sample_counts: FrameSelfAndTotalCounts<CountType>,
+    /// Whether this frame ends a stack whose net (signed) sample count was negative and had to be
+    /// clamped to zero.
+    pub(super) clamped: bool,
+    /// The number of input lines whose stacks end on this frame, which is more than one where
+    /// identical stacks follow each other.
+    pub(super) occurrences: usize,
+    /// The weights applied to the stacks through this frame.
+    pub(super) weights: AppliedWeights,
 }
 
 impl<'a, CountType> TimedFrame<'a, CountType>
@@ -57,10 +69,42 @@ where
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(super) struct UnclosedFrame<CountType> {
     pub(super) start_time: StackSampleCount<CountType>,
     pub(super) sample_count: FrameSelfAndTotalCounts<CountType>,
+    pub(super) clamped: bool,
+    pub(super) occurrences: usize,
+    pub(super) weights: AppliedWeights,
+}
+
+/// The distinct factors that the sample counts of the stacks through a frame were multiplied by.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct AppliedWeights {
+    /// The factors other than one, in the order they were first applied.
+    pub(super) factors: Vec<f64>,
+    /// Whether some of the stacks through the frame weren't weighted.
+    pub(super) partial: bool,
+}
+
+impl AppliedWeights {
+    fn apply(&mut self, factor: Option<f64>) {
+        match factor.filter(|&f| f != 1.0) {
+            Some(factor) => {
+                if !self.factors.contains(&factor) {
+                    self.factors.push(factor);
+                }
+            }
+            None => self.partial = true,
+        }
+    }
+
+    fn merge(&mut self, other: &AppliedWeights) {
+        for &factor in &other.factors {
+            self.apply(Some(factor));
+        }
+        self.partial |= other.partial;
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -221,8 +265,20 @@ pub trait StackSampleCountExt: Default {
         stripped_fractional_samples: &mut bool,
         frame_width_source: FrameWidthSource,
     ) -> Option<Self>;
+    /// Like `parse_from_line`, but for a line that is missing its last sample count, which is
+    /// taken to be zero. Single counts are never missing.
+    fn parse_with_missing_count_from_line(
+        line: &mut &str,
+        stripped_fractional_samples: &mut bool,
+        frame_width_source: FrameWidthSource,
+    ) -> Option<Self>;
     fn last_count(&self) -> usize;
     fn split(&self) -> StackSampleCountEnum;
+    fn from_net_count(net: isize) -> Self;
+    /// Move this point in time to the given visual offset, as when frames are laid out anew.
+    fn with_visual(self, visual: usize) -> Self;
+    /// Multiply the counts by a weight, rounding them to whole samples.
+    fn weighted(self, factor: f64) -> Self;
 }
 
 impl<T> Default for StackSampleCount<T>
@@ -257,12 +313,28 @@ impl StackSampleCountExt for StackSampleCount<usize> {
         };
         Some(StackSampleCount(col1))
     }
+    fn parse_with_missing_count_from_line(
+        _line: &mut &str,
+        _stripped_fractional_samples: &mut bool,
+        _frame_width_source: FrameWidthSource,
+    ) -> Option<Self> {
+        None
+    }
     fn last_count(&self) -> usize {
         self.0
     }
     fn split(&self) -> StackSampleCountEnum {
         StackSampleCountEnum::Single(self.0)
     }
+    fn from_net_count(net: isize) -> Self {
+        StackSampleCount(net.max(0) as usize)
+    }
+    fn with_visual(self, visual: usize) -> Self {
+        StackSampleCount(visual)
+    }
+    fn weighted(self, factor: f64) -> Self {
+        StackSampleCount(weigh(self.0, factor))
+    }
 }
 impl StackSampleCountExt for StackSampleCount<DiffCount> {
     fn delta(&self) -> Option<isize> {
@@ -294,12 +366,41 @@ impl StackSampleCountExt for StackSampleCount<DiffCount> {
             frame_width_source,
         )))
     }
+    fn parse_with_missing_count_from_line(
+        line: &mut &str,
+        stripped_fractional_samples: &mut bool,
+        frame_width_source: FrameWidthSource,
+    ) -> Option<Self> {
+        let col1 = parse_nsamples(line, stripped_fractional_samples)?;
+        Some(StackSampleCount(DiffCount::new(
+            col1,
+            0,
+            frame_width_source,
+        )))
+    }
     fn last_count(&self) -> usize {
         self.0.after
     }
     fn split(&self) -> StackSampleCountEnum {
         StackSampleCountEnum::Diff(self.0)
     }
+    fn from_net_count(_net: isize) -> Self {
+        unreachable!("signed sample counts are only supported for single-column input")
+    }
+    fn with_visual(self, visual: usize) -> Self {
+        StackSampleCount(DiffCount { visual, ..self.0 })
+    }
+    fn weighted(self, factor: f64) -> Self {
+        StackSampleCount(DiffCount {
+            before: weigh(self.0.before, factor),
+            after: weigh(self.0.after, factor),
+            visual: weigh(self.0.visual, factor),
+        })
+    }
+}
+
+fn weigh(count: usize, factor: f64) -> usize {
+    (count as f64 * factor).round() as usize
 }
 
 impl<CountType: Add<Output = CountType>> Add for StackSampleCount<CountType> {
@@ -350,87 +451,100 @@ impl MaxAbsDelta {
     }
 }
 
-fn flow<'a, LI, TI, CountType>(
-    open_raw_frames: &mut HashMap<Frame<'a>, UnclosedFrame<CountType>>,
+/// Identifies a function name in an [`Interner`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(super) struct FuncId(u32);
+
+/// Gives every distinct function name of the input a small id, so that open frames are tracked
+/// by id and all frames of a function share a single name.
+///
+/// This doesn't do away with comparing names: every frame of a stack is still compared with the
+/// name of the open frame at its depth to find the frames it shares with the previous stack, and
+/// the frames after those are looked up by name.
+#[derive(Debug, Default)]
+pub(super) struct Interner<'a> {
+    ids: AHashMap<&'a str, FuncId>,
+    names: Vec<&'a str>,
+}
+
+impl<'a> Interner<'a> {
+    pub(super) fn intern(&mut self, name: &'a str) -> FuncId {
+        let next = FuncId(self.names.len() as u32);
+        let id = *self.ids.entry(name).or_insert(next);
+        if id == next {
+            self.names.push(name);
+        }
+        id
+    }
+
+    pub(super) fn name(&self, id: FuncId) -> &'a str {
+        self.names[id.0 as usize]
+    }
+}
+
+// Updates the open frames, which are those of the previous stack by depth, for the next stack:
+// the frames the two share take its samples, the rest of the previous stack is closed, and the
+// rest of the next stack is opened.
+#[allow(clippy::too_many_arguments)]
+fn flow<'a, CountType>(
+    interner: &Interner<'a>,
+    open_frames: &mut Vec<(FuncId, UnclosedFrame<CountType>)>,
     closed_frames: &mut Vec<TimedFrame<'a, CountType>>,
-    last: LI,
-    this: TI,
+    this: &[FuncId],
     total_sample_count_so_far: Option<StackSampleCount<CountType>>,
     sample_count_for_this_line: Option<StackSampleCount<CountType>>,
+    clamped: bool,
+    weight: Option<f64>,
 ) where
-    LI: IntoIterator<Item = &'a str>,
-    TI: IntoIterator<Item = &'a str>,
     CountType: CountTypeRequirements,
 {
-    let mut this = this.into_iter().peekable();
-    let mut last = last.into_iter().peekable();
-
     let accumulated_samples = match total_sample_count_so_far {
         None => Some(Default::default()),
         x => x,
     };
-    // remove common prefix
-    let mut shared_depth = 0;
-    while last.peek() == this.peek() {
-        // they must both be None, so let's stop looping
-        if last.peek().is_none() {
-            break;
+    // common prefix
+    let shared_depth = open_frames
+        .iter()
+        .zip(this)
+        .take_while(|((open, _), this)| open == *this)
+        .count();
+    for (depth, (_, frame_time)) in open_frames[..shared_depth].iter_mut().enumerate() {
+        let sample_count = sample_count_for_this_line.unwrap();
+        let ends_here = depth + 1 == this.len();
+        // Identical stacks end on a frame that's already open.
+        if ends_here {
+            frame_time.sample_count.self_count =
+                frame_time.sample_count.self_count + sample_count.0;
+            frame_time.occurrences += 1;
         }
-
-        let key = Frame {
-            function: this.peek().unwrap(),
-            depth: shared_depth,
-        };
-        open_raw_frames
-            .get_mut(&key)
-            .map(|frame_time| frame_time.sample_count += sample_count_for_this_line.unwrap());
-
-        // move along prefix iterators
-        last.next();
-        this.next();
-        shared_depth += 1;
+        frame_time.sample_count += sample_count;
+        // A stack can end on a frame that's already open if the input isn't sorted.
+        frame_time.clamped |= clamped && ends_here;
+        frame_time.weights.apply(weight);
     }
 
-    // TODO: document this..
-
     // Closing off frames
-    for (i, func) in last.enumerate() {
-        let key = Frame {
-            function: func,
-            depth: shared_depth + i,
-        };
-
-        //eprintln!("at {} ending frame {:?}", time, key);
-        let unclosed_frame = open_raw_frames.remove(&key).unwrap_or_else(|| {
-            unreachable!("did not have start time for {:?}", key);
-        });
-
+    for (i, (id, unclosed_frame)) in open_frames.drain(shared_depth..).enumerate() {
         let frame = TimedFrame {
-            location: key,
+            location: Frame {
+                function: interner.name(id),
+                depth: shared_depth + i,
+            },
             start_time: unclosed_frame.start_time,
             end_time: accumulated_samples.unwrap(),
             self_and_total_sample_counts: unclosed_frame.sample_count,
+            clamped: unclosed_frame.clamped,
+            occurrences: unclosed_frame.occurrences,
+            weights: unclosed_frame.weights,
         };
-        // TODO: delete
-        // if frame.self_and_total_sample_counts.is_diff() && i != 0 {
-        //     let total_delta = frame.self_and_total_sample_counts.total_delta().unwrap();
-        //     let self_delta = frame.self_and_total_sample_counts.self_delta().unwrap();
-        // }
         closed_frames.push(frame);
     }
 
     // Opening new frames
-    let mut i = 0;
-    while this.peek().is_some() {
-        let func = this.next().unwrap();
-        let key = Frame {
-            function: func,
-            depth: shared_depth + i,
-        };
+    for (i, &id) in this.iter().enumerate().skip(shared_depth) {
+        let is_last_frame_of_stack = i + 1 == this.len();
 
-        let is_last_frame_of_stack = this.peek().is_none();
-
-        let frame_time = UnclosedFrame {
+        let mut frame_time = UnclosedFrame {
             start_time: accumulated_samples.unwrap(),
             // For some reason the Perl version does a `+=` for `delta`, but I can't figure out why.
             // See https://github.com/brendangregg/FlameGraph/blob/1b1c6deede9c33c5134c920bdb7a44cc5528e9a7/flamegraph.pl#L588
@@ -442,28 +556,51 @@ fn flow<'a, LI, TI, CountType>(
                 },
                 total_count: sample_count_for_this_line.unwrap().0,
             },
+            clamped: clamped && is_last_frame_of_stack,
+            occurrences: usize::from(is_last_frame_of_stack),
+            weights: Default::default(),
         };
+        frame_time.weights.apply(weight);
+        open_frames.push((id, frame_time));
+    }
+}
 
-        //eprintln!("stored tmp for time {}: {:?}", time, key);
-        if let Some(frame_time) = open_raw_frames.insert(key, frame_time) {
-            unreachable!(
-                "start time {:?} already registered for frame",
-                frame_time.start_time
-            );
-        }
+/// The number of ignored lines to keep around for reporting.
+pub(super) const REPORTED_IGNORED_LINES: usize = 5;
+
+/// Input problems that were worked around while merging stacks.
+#[derive(Debug, Default)]
+pub(super) struct Ignored<'a> {
+    /// The number of lines that were ignored because of their invalid format.
+    pub(super) count: usize,
+    /// The first few of the lines that were ignored.
+    pub(super) first_lines: Vec<&'a str>,
+    /// Whether non-zero fractional sample counts were truncated.
+    pub(super) stripped_fractional_samples: bool,
+    /// The number of stacks whose net sample count was negative, and was clamped to zero.
+    pub(super) clamped_stacks: usize,
+}
 
-        i += 1;
+impl<'a> Ignored<'a> {
+    fn line(&mut self, line: &'a str) {
+        self.count += 1;
+        if self.first_lines.len() < REPORTED_IGNORED_LINES {
+            self.first_lines.push(line);
+        }
     }
 }
 
-pub(super) fn frames<'a, I, CountType>(
+pub(super) fn frames<'a, I, CountType, W>(
     lines: I,
     suppress_sort_check: bool,
     frame_width_source: FrameWidthSource,
+    signed_counts: bool,
+    missing_count_as_zero: bool,
+    weight: W,
 ) -> io::Result<(
     Vec<TimedFrame<'a, CountType>>,
     Option<StackSampleCount<CountType>>,
-    usize,
+    Ignored<'a>,
     Option<MaxAbsDelta>,
 )>
 where
@@ -471,20 +608,30 @@ where
     CountType: CountTypeRequirements,
     StackSampleCount<CountType>: StackSampleCountExt,
     FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
+    W: Fn(&str) -> Option<f64>,
 {
     let mut sample_count_before_this_line = None;
-    let mut ignored = 0;
-    let mut last = "";
-    let mut open_frames = Default::default();
+    let mut ignored = Ignored::default();
+    let mut interner = Interner::default();
+    let mut stack_ids = Vec::new();
+    let mut open_frames = Vec::new();
     let mut closed_frames = Default::default();
     let mut stripped_fractional_samples = false;
     let mut prev_line = None;
-    for line in lines {
-        let mut line = line.trim();
+    let lines: Vec<&'a str> = lines.into_iter().map(str::trim).collect();
+    let mut net_counts = if signed_counts {
+        net_counts(&lines)
+    } else {
+        AHashMap::default()
+    };
+    for mut line in lines {
+        let full_line = line;
 
         if !suppress_sort_check {
             if let Some(prev_line) = prev_line {
-                if prev_line > line {
+                // Lines sorted as a whole, as by `sort`, are fine too, so lines are only out of
+                // order if they are in neither order.
+                if prev_line > line && cmp_lines(prev_line, line) == Ordering::Greater {
                     return Err(io::Error::new(
                         io::ErrorKind::InvalidData,
                         "unsorted input lines detected",
@@ -497,47 +644,78 @@ where
         // Usually there will only be one samples column at the end of a line,
         // but for differentials there will be two. When there are two we compute the
         // delta between them and use the second one.
-        let Some(sample_count_for_this_line) = StackSampleCount::parse_from_line(
-            &mut line,
-            &mut stripped_fractional_samples,
-            frame_width_source,
-        ) else {
-            ignored += 1;
-            continue;
+        let (sample_count_for_this_line, clamped) = if signed_counts {
+            if parse_signed_nsamples(&mut line, &mut stripped_fractional_samples).is_none() {
+                ignored.line(full_line);
+                continue;
+            }
+            // The first line of a stack carries the net count of all of its lines, which need
+            // not be adjacent with `no_sort` or in flame charts, and the rest are left out.
+            let Some(net) = net_counts.remove(line) else {
+                continue;
+            };
+            // A clamped stack that ends on a leaf has no width left, so its frame isn't drawn,
+            // and this count is all that's left of it.
+            if net < 0 {
+                ignored.clamped_stacks += 1;
+            }
+            (StackSampleCount::from_net_count(net), net < 0)
+        } else {
+            let mut sample_count_for_this_line = StackSampleCount::parse_from_line(
+                &mut line,
+                &mut stripped_fractional_samples,
+                frame_width_source,
+            );
+            if sample_count_for_this_line.is_none() && missing_count_as_zero {
+                line = full_line;
+                sample_count_for_this_line = StackSampleCount::parse_with_missing_count_from_line(
+                    &mut line,
+                    &mut stripped_fractional_samples,
+                    frame_width_source,
+                );
+            }
+            let Some(sample_count_for_this_line) = sample_count_for_this_line else {
+                ignored.line(full_line);
+                continue;
+            };
+            (sample_count_for_this_line, false)
         };
 
         if line.is_empty() {
-            ignored += 1;
+            ignored.line(full_line);
             continue;
         }
         let stack = line;
+        let factor = weight(stack);
+        let sample_count_for_this_line = factor.map_or(sample_count_for_this_line, |f| {
+            sample_count_for_this_line.weighted(f)
+        });
 
         // inject empty first-level stack frame to capture "all"
-        let this = iter::once("").chain(stack.split(';'));
-        if last.is_empty() {
-            // need to special-case this, because otherwise iter("") + "".split(';') == ["", ""]
-            //eprintln!("flow(_, {}, {})", stack, time);
-            flow(
-                &mut open_frames,
-                &mut closed_frames,
-                None,
-                this,
-                sample_count_before_this_line,
-                Some(sample_count_for_this_line),
-            );
-        } else {
-            //eprintln!("flow({}, {}, {})", last, stack, time);
-            flow(
-                &mut open_frames,
-                &mut closed_frames,
-                iter::once("").chain(last.split(';')),
-                this,
-                sample_count_before_this_line,
-                Some(sample_count_for_this_line),
-            );
+        stack_ids.clear();
+        // Frames shared with the previous stack keep their ids, without being looked up again.
+        let mut shared = true;
+        for (depth, func) in iter::once("").chain(split_frames(stack)).enumerate() {
+            let id = match open_frames.get(depth) {
+                Some(&(id, _)) if shared && interner.name(id) == func => id,
+                _ => {
+                    shared = false;
+                    interner.intern(func)
+                }
+            };
+            stack_ids.push(id);
         }
+        flow(
+            &interner,
+            &mut open_frames,
+            &mut closed_frames,
+            &stack_ids,
+            sample_count_before_this_line,
+            Some(sample_count_for_this_line),
+            clamped,
+            factor,
+        );
 
-        last = stack;
         sample_count_before_this_line = match sample_count_before_this_line {
             Some(x) => Some(x + sample_count_for_this_line),
             None => Some(sample_count_for_this_line),
@@ -546,15 +724,16 @@ where
     }
 
     // Close off remaining open frames at the end
-    if !last.is_empty() {
-        //eprintln!("flow({}, _, {})", last, time);
+    if !open_frames.is_empty() {
         flow(
+            &interner,
             &mut open_frames,
             &mut closed_frames,
-            iter::once("").chain(last.split(';')),
-            None,
+            &[],
             sample_count_before_this_line,
             None,
+            false,
+            None,
         );
     }
 
@@ -567,6 +746,7 @@ where
         None
     };
 
+    ignored.stripped_fractional_samples = stripped_fractional_samples;
     Ok((
         closed_frames,
         sample_count_before_this_line,
@@ -575,6 +755,194 @@ where
     ))
 }
 
+/// The name of the frame that stands in for the pruned children of a frame.
+pub(super) const NARROW_FRAME: &str = "(narrow)";
+
+/// Prune the frames that aren't `wide`, aggregating the pruned children of every remaining frame
+/// into a single [`NARROW_FRAME`] child that is as wide as all of them together.
+///
+/// To make room for it, the remaining children of a frame with pruned children are packed
+/// together at its start (in their original order), followed by the aggregate; the frame's own
+/// self time ends up at its end. Children of frames without pruned children stay where they are.
+pub(super) fn aggregate_narrow_frames<'a, CountType, F>(
+    frames: Vec<TimedFrame<'a, CountType>>,
+    wide: F,
+) -> Vec<TimedFrame<'a, CountType>>
+where
+    CountType: CountTypeRequirements,
+    StackSampleCount<CountType>: StackSampleCountExt,
+    F: Fn(&TimedFrame<'a, CountType>) -> bool,
+{
+    let is_wide: Vec<bool> = frames.iter().map(wide).collect();
+
+    // the frames at each depth, ordered by where they start
+    let max_depth = frames.iter().map(|f| f.location.depth).max().unwrap_or(0);
+    let mut by_depth = vec![Vec::new(); max_depth + 1];
+    for (i, frame) in frames.iter().enumerate() {
+        by_depth[frame.location.depth].push(i);
+    }
+    for level in &mut by_depth {
+        level.sort_by_key(|&i| frames[i].start_time.visual());
+    }
+
+    // how far each remaining frame moves, which it passes on to its children
+    let mut shift = vec![0isize; frames.len()];
+    let mut narrow_frames = Vec::new();
+    for depth in 0..max_depth {
+        for &p in &by_depth[depth] {
+            if !is_wide[p] {
+                continue;
+            }
+            let parent = &frames[p];
+            let (start, end) = (parent.start_time.visual(), parent.end_time.visual());
+            let level = &by_depth[depth + 1];
+            let first = level.partition_point(|&c| frames[c].start_time.visual() < start);
+            let last = level.partition_point(|&c| frames[c].start_time.visual() < end);
+            let children = &level[first..last];
+
+            if children.iter().all(|&c| is_wide[c]) {
+                for &c in children {
+                    shift[c] = shift[p];
+                }
+                continue;
+            }
+
+            let mut cursor = (start as isize + shift[p]) as usize;
+            let mut narrow_width = 0;
+            let mut narrow_count: Option<CountType> = None;
+            let mut narrow_weights = AppliedWeights::default();
+            for &c in children {
+                let child = &frames[c];
+                if is_wide[c] {
+                    shift[c] = cursor as isize - child.start_time.visual() as isize;
+                    cursor += child.visual_samples();
+                } else {
+                    let count = child.self_and_total_sample_counts.total_count;
+                    narrow_count = Some(narrow_count.map_or(count, |n| n + count));
+                    narrow_width += child.visual_samples();
+                    narrow_weights.merge(&child.weights);
+                }
+            }
+
+            let count = narrow_count.expect("some child was pruned");
+            narrow_frames.push(TimedFrame {
+                location: Frame {
+                    function: NARROW_FRAME,
+                    depth: depth + 1,
+                },
+                start_time: parent.start_time.with_visual(cursor),
+                end_time: parent.end_time.with_visual(cursor + narrow_width),
+                self_and_total_sample_counts: FrameSelfAndTotalCounts {
+                    self_count: count,
+                    total_count: count,
+                },
+                clamped: false,
+                occurrences: 0,
+                weights: narrow_weights,
+            });
+        }
+    }
+
+    let mut kept: Vec<_> = frames
+        .into_iter()
+        .zip(is_wide.iter().zip(shift))
+        .filter(|(_, (&is_wide, _))| is_wide)
+        .map(|(mut frame, (_, shift))| {
+            if shift != 0 {
+                let start = (frame.start_time.visual() as isize + shift) as usize;
+                let end = (frame.end_time.visual() as isize + shift) as usize;
+                frame.start_time = frame.start_time.with_visual(start);
+                frame.end_time = frame.end_time.with_visual(end);
+            }
+            frame
+        })
+        .collect();
+    kept.append(&mut narrow_frames);
+    kept
+}
+
+/// The name of the stack that stands in for the stacks left out by [`top_stacks`].
+pub(super) const OTHER_STACKS: &str = "(other stacks)";
+
+/// Keep only the `n` heaviest distinct stacks of folded lines with `counts` sample counts each,
+/// and sum the counts of all other stacks into a single [`OTHER_STACKS`] line.
+///
+/// Identical stacks are merged first, and stacks are weighed by the sum of their counts, the
+/// earlier stack going first among stacks of the same weight. The kept stacks stay in the order
+/// they first appear in, and the other stacks go where [`cmp_lines`] sorts them, so that sorted
+/// lines stay sorted. Lines without valid sample counts are kept as they are, to be reported as
+/// such when the lines are merged into frames.
+pub(super) fn top_stacks<'a, I>(lines: I, n: usize, counts: usize) -> Vec<Cow<'a, str>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // Every distinct stack, with where it first appears, its summed counts, and its only line
+    // if it has just the one.
+    let mut stacks: AHashMap<&'a str, (usize, Vec<f64>, Option<&'a str>)> = AHashMap::default();
+    let mut invalid = Vec::new();
+    for (i, line) in lines.into_iter().enumerate() {
+        let mut stack = line;
+        let mut sums = vec![0.0; counts];
+        let parsed = (0..counts).rev().all(|c| {
+            let Some((samplesi, _, _)) = rfind_signed_samples(stack) else {
+                return false;
+            };
+            let Ok(count) = stack[samplesi..].parse::<f64>() else {
+                return false;
+            };
+            sums[c] = count;
+            stack = stack[..samplesi].trim_end();
+            true
+        });
+        if !parsed || stack.is_empty() {
+            invalid.push((i, Cow::Borrowed(line)));
+            continue;
+        }
+        stacks
+            .entry(stack)
+            .and_modify(|(_, summed, only)| {
+                summed.iter_mut().zip(&sums).for_each(|(s, c)| *s += c);
+                *only = None;
+            })
+            .or_insert((i, sums, Some(line)));
+    }
+
+    let weight = |sums: &[f64]| sums.iter().map(|c| c.abs()).sum::<f64>();
+    let mut ranked: Vec<_> = stacks.into_iter().collect();
+    ranked.sort_by(|(_, (a, a_sums, _)), (_, (b, b_sums, _))| {
+        weight(b_sums).total_cmp(&weight(a_sums)).then(a.cmp(b))
+    });
+    let format = |stack: &str, sums: &[f64]| {
+        let mut line = stack.to_string();
+        for sum in sums {
+            line.push_str(&format!(" {}", sum));
+        }
+        line
+    };
+
+    let others = ranked.split_off(n.min(ranked.len()));
+    let mut kept: Vec<(usize, Cow<'a, str>)> = ranked
+        .into_iter()
+        .map(|(stack, (i, sums, only))| match only {
+            Some(line) => (i, Cow::Borrowed(line)),
+            None => (i, Cow::Owned(format(stack, &sums))),
+        })
+        .chain(invalid)
+        .collect();
+    kept.sort_unstable_by_key(|&(i, _)| i);
+    let mut lines: Vec<Cow<'a, str>> = kept.into_iter().map(|(_, line)| line).collect();
+    if !others.is_empty() {
+        let mut sums = vec![0.0; counts];
+        for (_, (_, other, _)) in &others {
+            sums.iter_mut().zip(other).for_each(|(s, c)| *s += c);
+        }
+        let other = format(OTHER_STACKS, &sums);
+        let at = lines.partition_point(|line| cmp_lines(line, &other) == Ordering::Less);
+        lines.insert(at, Cow::Owned(other));
+    }
+    lines
+}
+
 fn max_deltas<CountType: CountTypeRequirements>(
     frames: &[TimedFrame<CountType>],
     overall_total_samples: StackSampleCount<CountType>,
@@ -623,30 +991,46 @@ where
     ))
 }
 
+/// The order that folded lines are sorted in before they are merged into frames.
+///
+/// Lines are ordered by their stacks, compared as whole strings, so that their counts don't
+/// take part, and sorting them is as fast as sorting the lines themselves.
+pub(super) fn cmp_lines(a: &str, b: &str) -> Ordering {
+    stack_of(a).cmp(stack_of(b))
+}
+
+/// Sorts folded lines in the order of [`cmp_lines`], keeping lines of the same stack in the order
+/// they were first seen in, whatever their counts, so that the output doesn't depend on the
+/// order of the input lines.
+pub(super) fn sort_lines(lines: &mut [&str]) {
+    let mut stacks: Vec<(&str, usize, &str)> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, &line)| (stack_of(line), i, line))
+        .collect();
+    // The index breaks ties between lines of the same stack, so an unstable sort will do.
+    stacks.sort_unstable();
+    for (line, (_, _, sorted)) in lines.iter_mut().zip(stacks) {
+        *line = sorted;
+    }
+}
+
+// The stack of a folded line, without its sample counts. Differential lines have two.
+fn stack_of(line: &str) -> &str {
+    let mut stack = line.trim();
+    for _ in 0..2 {
+        match rfind_signed_samples(stack) {
+            Some((samplesi, _, _)) => stack = stack[..samplesi].trim_end(),
+            None => break,
+        }
+    }
+    stack
+}
+
 // Parse and remove the number of samples from the end of a line.
 fn parse_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Option<usize> {
     if let Some((samplesi, doti)) = rfind_samples(line) {
-        let mut samples = &line[samplesi..];
-        // Strip fractional part (if any);
-        // foobar 1.klwdjlakdj
-        //
-        // The Perl version keeps the fractional part but this can be problematic
-        // because of cumulative floating point errors. Instead we recommend to
-        // use the --factor option. See https://github.com/brendangregg/FlameGraph/pull/18
-        //
-        // Warn if we're stripping a non-zero fractional part, but only the first time.
-        if !*stripped_fractional_samples
-            && doti < samples.len() - 1
-            && !samples[doti + 1..].chars().all(|c| c == '0')
-        {
-            *stripped_fractional_samples = true;
-            warn!(
-                "The input data has fractional sample counts that will be truncated to integers. \
-                 If you need to retain the extra precision you can scale up the sample data and \
-                 use the --factor option to scale it back down."
-            );
-        }
-        samples = &samples[..doti];
+        let samples = strip_fractional_part(&line[samplesi..], doti, stripped_fractional_samples);
         let nsamples = samples.parse::<usize>().ok()?;
         // remove nsamples part we just parsed from line
         *line = line[..samplesi].trim_end();
@@ -656,31 +1040,78 @@ fn parse_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Op
     }
 }
 
-// Tries to find a sample count at the end of a line.
-//
-// On success, the first value of the returned tuple will be the index to the sample count.
-// If the sample count is fractional, the second value will be the offset of the dot within
-// the sample count.
-// If the sample count is not fractional, the second value returned is the offset
-// to the last digit in the sample count.
+// Sum the possibly negative sample counts of the lines of each stack.
+fn net_counts<'a>(lines: &[&'a str]) -> AHashMap<&'a str, isize> {
+    // Fractional sample counts are warned about when the lines are merged.
+    let mut stripped_fractional_samples = true;
+    let mut net_counts = AHashMap::default();
+    for &line in lines {
+        let mut stack = line;
+        if let Some(nsamples) = parse_signed_nsamples(&mut stack, &mut stripped_fractional_samples)
+        {
+            *net_counts.entry(stack).or_default() += nsamples;
+        }
+    }
+    net_counts
+}
+
+// Parse and remove a possibly negative number of samples from the end of a line.
+fn parse_signed_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Option<isize> {
+    let (samplesi, negative, doti) = rfind_signed_samples(line)?;
+    let samples = &line[samplesi + usize::from(negative)..];
+    let samples = strip_fractional_part(samples, doti, stripped_fractional_samples);
+    let nsamples = samples.parse::<isize>().ok()?;
+    *line = line[..samplesi].trim_end();
+    Some(if negative { -nsamples } else { nsamples })
+}
+
+// Strip the fractional part (if any) of a sample count.
+fn strip_fractional_part<'a>(
+    samples: &'a str,
+    doti: usize,
+    stripped_fractional_samples: &mut bool,
+) -> &'a str {
+    // foobar 1.klwdjlakdj
+    //
+    // The Perl version keeps the fractional part but this can be problematic
+    // because of cumulative floating point errors. Instead we recommend to
+    // use the --factor option. See https://github.com/brendangregg/FlameGraph/pull/18
+    //
+    // Warn if we're stripping a non-zero fractional part, but only the first time.
+    if !*stripped_fractional_samples
+        && doti < samples.len() - 1
+        && !samples[doti + 1..].chars().all(|c| c == '0')
+    {
+        *stripped_fractional_samples = true;
+        warn!(
+            "The input data has fractional sample counts that will be truncated to integers. \
+             If you need to retain the extra precision you can scale up the sample data and \
+             use the --factor option to scale it back down."
+        );
+    }
+    &samples[..doti]
+}
+
+// Like `rfind_samples`, but also accepts a sample count with a leading minus sign.
 //
-// If no sample count is found, `None` will be returned.
-pub(super) fn rfind_samples(line: &str) -> Option<(usize, usize)> {
+// On success, the returned tuple holds the index to the sample count (including its sign),
+// whether the sample count is negative, and the offset of the dot (or of the end) within the
+// unsigned part of the sample count.
+pub(super) fn rfind_signed_samples(line: &str) -> Option<(usize, bool, usize)> {
     let samplesi = line.rfind(' ')? + 1;
-    let samples = &line[samplesi..];
-    if let Some(doti) = samples.find('.') {
-        if samples[..doti]
+    let (negative, samples) = match line[samplesi..].strip_prefix('-') {
+        Some(samples) => (true, samples),
+        None => (false, &line[samplesi..]),
+    };
+    let doti = samples.find('.').unwrap_or(samples.len());
+    if samples.is_empty()
+        || !samples
             .chars()
-            .chain(samples[doti + 1..].chars())
-            .all(|c| c.is_ascii_digit())
-        {
-            Some((samplesi, doti))
-        } else {
-            None
-        }
-    } else if !samples.chars().all(|c| c.is_ascii_digit()) {
+            .enumerate()
+            .all(|(i, c)| i == doti || c.is_ascii_digit())
+    {
         None
     } else {
-        Some((samplesi, line.len() - samplesi))
+        Some((samplesi, negative, doti))
     }
 }
@@ -4,14 +4,29 @@ macro_rules! args {
     }};
 }
 
+mod annotations;
 #[cfg(feature = "nameattr")]
 mod attrs;
 
 pub mod color;
-mod merge;
+#[cfg(feature = "annotations")]
+mod groups;
+mod hook;
+pub mod layout;
+pub(crate) mod merge;
+mod output;
+#[cfg(feature = "json")]
+pub mod patch;
 mod rand;
+pub mod report;
 mod svg;
+mod trend;
+#[cfg(feature = "annotations")]
+mod weights;
 
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, BufReader};
@@ -19,6 +34,7 @@ use std::iter;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+#[cfg(feature = "cli")]
 use clap::ValueEnum;
 
 use log::{error, warn};
@@ -34,16 +50,27 @@ use str_stack::StrStack;
 #[cfg(feature = "nameattr")]
 use self::attrs::FrameAttrs;
 
+#[cfg(feature = "annotations")]
+pub use self::annotations::{FrameAnnotation, FuncFrameAnnotations};
 #[cfg(feature = "nameattr")]
 pub use self::attrs::FuncFrameAttrsMap;
+#[cfg(feature = "annotations")]
+pub use self::groups::GroupRules;
+#[cfg(feature = "annotations")]
+pub use self::weights::StackWeights;
 
 pub use self::color::Palette;
 use self::color::{Color, SearchColor, StrokeColor};
-use self::svg::{Dimension, StyleOptions};
+pub use self::hook::{FrameHook, FrameInfo};
+pub use self::output::OutputSink;
+pub use self::rand::{RandomSource, SeededRandom};
+use self::svg::{Dimension, StyleOptions, SvgOutput};
 
-const XPAD: usize = 10; // pad left and right
 const FRAMEPAD: usize = 1; // vertical padding for frames
 
+// Marks the frames of off-CPU stacks in a hot/cold flame graph.
+const OFF_CPU_ANNOTATION: &str = "_[o]";
+
 // If no image width is given, this will be the initial width, but the embedded JavaScript will set
 // the width to 100% when it loads to make the width "fluid". The reason we give an initial width
 // even when the width will be "fluid" is so it looks good in previewers or viewers that don't run
@@ -84,21 +111,37 @@ pub mod defaults {
         SEARCH_COLOR: &str = "#e600e6",
         UI_COLOR: &str = "#000000",
         STROKE_COLOR: &str = "none",
+        STROKE_WIDTH: f64 = 1.0,
+        FRAME_RADIUS: f64 = 0.0,
+        FRAME_GAP: f64 = 0.0,
         TITLE: &str = "Flame Graph",
         CHART_TITLE: &str = "Flame Chart",
         FRAME_HEIGHT: usize = 16,
         MIN_WIDTH: f64 = 0.01,
+        MIN_FRAME_PIXELS: f64 = 0.0,
         FONT_TYPE: &str = "monospace",
         FONT_SIZE: usize = 12,
         FONT_WIDTH: f64 = 0.59,
+        MIN_FONT_SIZE: usize = 7,
         COUNT_NAME: &str = "samples",
         NAME_TYPE: &str = "Function:",
-        FACTOR: f64 = 1.0
+        FACTOR: f64 = 1.0,
+        XPAD: usize = 10
     }
 }
 
 /// Configure the flame graph.
+///
+/// With the `serde` feature, the options can be (de)serialized, for example to keep a shared
+/// configuration file; see [`Options::from_toml_str`]. Fields that refer to other files or to
+/// in-memory state (`palette_map`, `func_frameattrs`, `func_annotations`, `stack_weights`,
+/// `group_rules`, `frame_hook` and `random_source`) are skipped, and missing fields take their default values.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
 #[non_exhaustive]
 pub struct Options<'a> {
     /// The color palette to use when plotting.
@@ -110,8 +153,26 @@ pub struct Options<'a> {
     pub bgcolors: Option<color::BackgroundColor>,
 
     /// The color of UI text such as the search and reset view button. Defaults to black
+    #[cfg_attr(feature = "serde", serde(with = "color::hex_color"))]
     pub uicolor: color::Color,
 
+    /// A change made to the colors that frames get from the palette, such as saturating them by
+    /// how much of their samples are their own with
+    /// [`SelfRatio`](color::ColorModifier::SelfRatio).
+    ///
+    /// Frames that are colored by anything other than the palette, such as those of
+    /// differential flame graphs, are left as they are.
+    ///
+    /// Defaults to `None`.
+    pub color_modifier: Option<color::ColorModifier>,
+
+    /// Ranges of colors that basic palettes pick the colors of frames from, in place of their
+    /// own, such as to shift the `hot` palette towards the colors of a brand. A palette that has
+    /// several ranges uses the last one.
+    ///
+    /// Defaults to empty, which keeps the ranges of all palettes.
+    pub palette_ranges: Vec<color::PaletteRange>,
+
     /// Choose names based on the hashes of function names.
     ///
     /// This will cause similar functions to be colored similarly.
@@ -131,6 +192,7 @@ pub struct Options<'a> {
     ///
     /// This feature was first implemented [by Shawn
     /// Sterling](https://github.com/brendangregg/FlameGraph/pull/25).
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub palette_map: Option<&'a mut color::PaletteMap>,
 
     /// Assign extra attributes to particular functions.
@@ -138,8 +200,55 @@ pub struct Options<'a> {
     /// In particular, if a function appears in the given map, it will have extra attributes set in
     /// the resulting SVG based on its value in the map.
     #[cfg(feature = "nameattr")]
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub func_frameattrs: FuncFrameAttrsMap,
 
+    /// Annotate the frames of functions matching particular patterns.
+    ///
+    /// Matching frames get extra tooltip text, a badge, and CSS classes as described by
+    /// [`FrameAnnotation`]. Only available with the `annotations` feature.
+    #[cfg(feature = "annotations")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub func_annotations: FuncFrameAnnotations,
+
+    /// Multiply the sample counts of stacks matching particular patterns while they are merged.
+    ///
+    /// The factors applied to the stacks of a frame are noted in its tooltip. See
+    /// [`StackWeights`] for how patterns match. Only available with the `annotations` feature,
+    /// which brings in the regular expressions.
+    #[cfg(feature = "annotations")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub stack_weights: StackWeights,
+
+    /// A file of stack weights to apply along with [`Options::stack_weights`].
+    ///
+    /// The file is read when the flame graph is made; see [`StackWeights::from_reader`] for its
+    /// format. Only available with the `annotations` feature.
+    #[cfg(feature = "annotations")]
+    pub weights_file: Option<PathBuf>,
+
+    /// Group the frames of functions matching particular patterns under a single label, such as
+    /// all of `std::` and `core::` under `stdlib`, for a coarse view of a profile by module.
+    ///
+    /// See [`GroupRules`] for how frames are rewritten and merged. The patterns are regular
+    /// expressions, so this needs the `annotations` feature.
+    #[cfg(feature = "annotations")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub group_rules: GroupRules,
+
+    /// Post-process each frame before it is drawn.
+    ///
+    /// See [`FrameHook`] for what the hook can change.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub frame_hook: Option<FrameHook<'a>>,
+
+    /// The source of the random numbers that pick frame colors when neither [`Options::hash`]
+    /// nor [`Options::deterministic`] is set.
+    ///
+    /// Defaults to a generator shared by the flame graphs of the thread; see [`RandomSource`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub random_source: Option<&'a mut dyn RandomSource>,
+
     /// Whether to plot a plot that grows top-to-bottom or bottom-up (the default).
     pub direction: Direction,
 
@@ -148,11 +257,54 @@ pub struct Options<'a> {
     /// [Default value](defaults::SEARCH_COLOR).
     pub search_color: SearchColor,
 
+    /// Keep searches and zooms in sync with other flame graphs of the same group.
+    ///
+    /// When several flame graphs are embedded in one page (say, before and after a change, or
+    /// one per service) and share a group name, searching in one of them highlights the matches
+    /// in all of them, and zooming into a function zooms the others into the same function.
+    /// The flame graphs talk to each other through a `BroadcastChannel`, or by posting messages
+    /// to the other frames of the embedding page where that is not available, so they have to be
+    /// embedded with `<object>` or `<iframe>` rather than inlined.
+    ///
+    /// Defaults to None.
+    pub sync_group: Option<String>,
+
+    /// Follow a stream of [server-sent events] at this URL, and update the flame graph in place
+    /// with the [patches](patch::Patch) it sends.
+    ///
+    /// This is for flame graphs that are served while their profile is still being recorded, so
+    /// that they follow it smoothly, without being reloaded. See the [`patch`] module for how
+    /// the patches are made.
+    ///
+    /// Defaults to None.
+    ///
+    ///   [server-sent events]: https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events
+    pub live_updates: Option<String>,
+
     /// The stroke color for flame graph.
     ///
     /// [Default value](defaults::STROKE_COLOR).
     pub stroke_color: StrokeColor,
 
+    /// The width, in pixels, of the outline of every frame, if it has one; see
+    /// [`stroke_color`](Self::stroke_color).
+    ///
+    /// [Default value](defaults::STROKE_WIDTH).
+    pub stroke_width: f64,
+
+    /// The radius, in pixels, of the rounded corners of frames.
+    ///
+    /// [Default value](defaults::FRAME_RADIUS).
+    pub frame_radius: f64,
+
+    /// The gap, in pixels, between frames that are next to each other.
+    ///
+    /// It is cut off the right end of every frame when it is displayed, so it doesn't change the
+    /// layout of the flame graph.
+    ///
+    /// [Default value](defaults::FRAME_GAP).
+    pub frame_gap: f64,
+
     /// The title for the flame graph.
     ///
     /// [Default value](defaults::TITLE).
@@ -163,11 +315,54 @@ pub struct Options<'a> {
     /// Defaults to None.
     pub subtitle: Option<String>,
 
+    /// Put the title, subtitle, details line and interactive controls in a single row above the
+    /// frames, in the regular font size, leaving as much room as possible for the frames, such as
+    /// for small dashboard tiles.
+    ///
+    /// The "Reset Zoom" button takes the place of the title while zoomed in.
+    ///
+    /// Defaults to false.
+    pub compact_header: bool,
+
+    /// Show the details line, where hovering over a frame shows its name and counts.
+    ///
+    /// Turn this off for minimal embeds whose host page shows the details of frames itself, such
+    /// as from the `inferno:frameclick` events of clicked frames. The room the details line takes
+    /// up is kept for the count of search matches.
+    ///
+    /// Defaults to true.
+    pub show_status_bar: bool,
+
+    /// The text of the details line for the frame under the mouse.
+    ///
+    /// These placeholders are replaced by the details of the frame:
+    ///
+    ///  - `{nametype}`: the [`name_type`](Self::name_type), such as `Function:`
+    ///  - `{name}`: the name of the function
+    ///  - `{details}`: the name with the counts, as in the tooltip
+    ///  - `{samples}`: the number of samples in the frame, scaled by [`factor`](Self::factor)
+    ///  - `{percent}`: the percentage of all samples in the frame, without a `%` sign
+    ///
+    /// Defaults to None, which shows `{nametype} {details}`.
+    pub details_template: Option<String>,
+
     /// Width of the flame graph
     ///
     /// Defaults to None, which means the width will be "fluid".
     pub image_width: Option<usize>,
 
+    /// How the width of the SVG element is expressed in its header.
+    ///
+    /// The layout is always computed for `image_width` (or a default width if that is `None`);
+    /// this only changes how the resulting image is sized when embedded in a page. Defaults to
+    /// `None`, which uses `image_width` in pixels, or a fluid width if that is `None` too.
+    pub width_sizing: Option<WidthSizing>,
+
+    /// Horizontal padding, in pixels, on the left and right of the frames.
+    ///
+    /// [Default value](defaults::XPAD).
+    pub xpad: usize,
+
     /// Height of each frame.
     ///
     /// [Default value](defaults::FRAME_HEIGHT).
@@ -178,6 +373,32 @@ pub struct Options<'a> {
     /// [Default value](defaults::MIN_WIDTH).
     pub min_width: f64,
 
+    /// Instead of dropping frames narrower than `min_width`, aggregate the dropped children of
+    /// each frame into a single `(narrow)` child frame that is as wide as all of them together.
+    ///
+    /// Without this, parents of pruned frames appear to have self time they don't have.
+    ///
+    /// This option is ignored for [flame charts](Self::flame_chart), where the dropped children of
+    /// a frame are spread out over time, and can't be put together in one place.
+    pub aggregate_narrow_frames: bool,
+
+    /// Smallest width, in pixels, of the frames that get a label.
+    ///
+    /// Narrower frames are still drawn, but without their names, and without working out how much
+    /// of their names would fit. This makes wide flame graphs with many tiny frames faster to
+    /// render and smaller. The embedded JavaScript labels these frames once they are zoomed into.
+    ///
+    /// [Default value](defaults::MIN_FRAME_PIXELS).
+    pub min_frame_pixels: f64,
+
+    /// Give the frames narrower than [`min_frame_pixels`](Self::min_frame_pixels) tooltips of just
+    /// their names, without their counts and percentages.
+    ///
+    /// Formatting those takes much of the time it takes to render a flame graph of a huge profile,
+    /// most of whose frames are too narrow to hover over anyway. The embedded JavaScript works out
+    /// the count and percentage of such frames for the details line instead.
+    pub short_narrow_tooltips: bool,
+
     /// The font type for the flame graph.
     ///
     /// [Default value](defaults::FONT_TYPE).
@@ -193,9 +414,23 @@ pub struct Options<'a> {
     /// [Default value](defaults::FONT_WIDTH).
     pub font_width: f64,
 
-    /// When text doesn't fit in a frame, should we cut off left side (the default) or right side?
+    /// When text doesn't fit in a frame, should we cut off left side (the default), right side or
+    /// the middle?
     pub text_truncate_direction: TextTruncateDirection,
 
+    /// Shrink the text of frames that are too narrow for their names, down to
+    /// [`min_font_size`](Self::min_font_size), before cutting it off.
+    ///
+    /// This makes more names readable in flame graphs that are printed or converted to images,
+    /// where they can't be zoomed in on.
+    pub shrink_text_to_fit: bool,
+
+    /// The smallest font size that text is shrunk to with
+    /// [`shrink_text_to_fit`](Self::shrink_text_to_fit).
+    ///
+    /// [Default value](defaults::MIN_FONT_SIZE).
+    pub min_font_size: usize,
+
     /// Count type label for the flame graph.
     ///
     /// [Default value](defaults::COUNT_NAME).
@@ -209,6 +444,16 @@ pub struct Options<'a> {
     /// The notes for the flame graph.
     pub notes: String,
 
+    /// Append a summary of input problems to the notes embedded in the flame graph.
+    ///
+    /// When input lines are ignored or fractional sample counts are truncated, the notes get a
+    /// machine-readable summary like `[inferno warnings: ignored_lines=3 first_ignored=4,9,12
+    /// truncated_fractional_samples=false]` so that the SVG carries that context when reviewed
+    /// later. Line numbers are 1-based and count every line passed in, including blank ones. With
+    /// [`signed_counts`](Options::signed_counts), the summary ends with the number of stacks that
+    /// were clamped to zero, like `clamped_stacks=2`, if there are any.
+    pub embed_warnings: bool,
+
     /// By default, if [differential] samples are included in the provided stacks, the resulting
     /// flame graph will compute and show differentials as `sample#2 - sample#1`. If this option is
     /// set, the differential is instead computed using `sample#1 - sample#2`.
@@ -231,6 +476,25 @@ pub struct Options<'a> {
     /// Pretty print XML with newlines and indentation.
     pub pretty_xml: bool,
 
+    /// Compress the SVG with gzip as it is written, as for an `.svgz` file.
+    ///
+    /// Flame graphs are mostly repetitive markup, so this makes large ones about ten times
+    /// smaller. Browsers open `.svgz` files directly, but only when served with
+    /// `Content-Encoding: gzip` or read from disk. Without the `gzip` feature, making a flame
+    /// graph with this set fails.
+    ///
+    /// Defaults to false.
+    pub svgz: bool,
+
+    /// Leave out the XML declaration and DOCTYPE, so that the `<svg>` element can be included
+    /// as is in an HTML page.
+    ///
+    /// To also leave out the fixed width and height of the image, so that it scales with the page,
+    /// set [`width_sizing`](Self::width_sizing) to [`WidthSizing::ViewBox`].
+    ///
+    /// Defaults to false.
+    pub fragment: bool,
+
     /// Don't sort the input lines.
     ///
     /// If you know for sure that your folded stack lines are sorted you can set this flag to get
@@ -238,7 +502,9 @@ pub struct Options<'a> {
     /// regardless.
     ///
     /// Note that if you use `from_lines` directly, the it is always your responsibility to make
-    /// sure the lines are sorted.
+    /// sure the lines are sorted. Lines may be sorted by their stacks frame by frame, as they are
+    /// sorted without this flag, or as whole lines, as by `sort`; an error is only returned for
+    /// two lines that are out of order both ways.
     pub no_sort: bool,
 
     /// Generate stack-reversed flame graph.
@@ -247,6 +513,102 @@ pub struct Options<'a> {
     /// option will be ignored.
     pub reverse_stack_order: bool,
 
+    /// Aggregate samples by their leaf function, like the default view of `perf report`.
+    ///
+    /// Stacks are reversed, as with [`reverse_stack_order`](Self::reverse_stack_order), and then
+    /// cut short after this many frames, so that with `1` there's a frame for each leaf function,
+    /// and with `2` those frames are split further by the functions that called them. See also
+    /// [`report::Options::bottom_up`], which lists the same aggregation as a table.
+    ///
+    /// Defaults to `None`, which keeps whole stacks.
+    pub bottom_up: Option<usize>,
+
+    /// Keep only this many of the heaviest distinct stacks, and sum the samples of all other
+    /// stacks into a single `(other stacks)` stack.
+    ///
+    /// Identical stacks are merged before they are weighed, so this keeps the stacks that make up
+    /// most of the profile, which makes small flame graphs of huge profiles that are easy to share.
+    /// Ignored for flame charts, where stacks follow each other in time.
+    ///
+    /// Defaults to `None`, which keeps all stacks.
+    pub top_stacks: Option<usize>,
+
+    /// Read this many sample counts from every input line, one for each of a series of
+    /// consecutive time windows, and show in the tooltip of every frame a sparkline of its
+    /// samples over them, as in `Trend: ▁▃▅█ (12 → 80 samples)`.
+    ///
+    /// Frames are as wide as their samples in [`width_window`](Self::width_window), or in all
+    /// windows together. Trends are those of the stacks as they are in the input, so they are
+    /// left out when stacks are rewritten, as by [`bottom_up`](Self::bottom_up), `group_rules`
+    /// or [`reverse_stack_order`](Self::reverse_stack_order), and of flame charts.
+    ///
+    /// Defaults to 0, which reads one sample count per line, or two for differential input.
+    pub time_windows: usize,
+
+    /// The time window, counting from 0, whose samples set the widths of frames when
+    /// [`time_windows`](Self::time_windows) is set.
+    ///
+    /// Defaults to `None`, which sums the samples of all windows.
+    pub width_window: Option<usize>,
+
+    /// Shade the bottom of every frame, from its left end, in proportion to the share of its
+    /// samples that are its own (self) rather than those of the functions it called.
+    ///
+    /// That sets the frames of functions that do the work themselves apart from those that are
+    /// merely wide because of what they call. For differential input, the shares are those of the
+    /// frame widths set by [`frame_width_source`](Self::frame_width_source).
+    ///
+    /// Defaults to false.
+    pub self_time_shading: bool,
+
+    /// List the annotated frames, those with attributes from `func_frameattrs` or annotations
+    /// from `func_annotations`, in a panel that opens from a button in a row below the frames.
+    ///
+    /// Every frame in the list shows its badge and tooltip annotations, or its title attribute,
+    /// and clicking it zooms to the frame, so that reviewers can find the flagged functions
+    /// without hunting for them. Frames are only annotated with the `nameattr` and `annotations`
+    /// features.
+    ///
+    /// Defaults to false.
+    pub annotations_panel: bool,
+
+    /// List the frames of a differential flame graph whose share of the samples grew and shrank
+    /// the most, five of each, in rows of their own below the frames.
+    ///
+    /// Every entry shows the change of its frame in percentage points, as in its tooltip, and
+    /// clicking it zooms to the frame, so that readers see what changed without hovering over
+    /// every frame. Frames are ranked by their total or self samples as with
+    /// `include_children`. Input that isn't differential has no summary.
+    ///
+    /// Defaults to false.
+    pub diff_summary: bool,
+
+    /// Bookmarks, as `(label, function)` pairs, shown as chips in a row below the title.
+    ///
+    /// Clicking a chip zooms to the first frame of its function, which is named as in the
+    /// tooltips of its frames, so that readers of a shared flame graph are guided to its
+    /// interesting areas. Bookmarks need the JavaScript of the flame graph to work.
+    ///
+    /// Defaults to none.
+    pub bookmarks: Vec<(String, String)>,
+
+    /// Text drawn centered in a row of its own at the bottom of the flame graph, such as a build
+    /// identifier or the name of whoever made the graph.
+    ///
+    /// Defaults to None.
+    pub footer: Option<String>,
+
+    /// A logo or other image drawn faintly over a corner of the flame graph, so that graphs
+    /// shared outside of the team that made them carry its branding.
+    ///
+    /// Defaults to None.
+    pub watermark: Option<Watermark>,
+
+    /// Where the interactive script comes from: the built-in one, an external one, or none.
+    ///
+    /// Defaults to [`ScriptSource::Embedded`].
+    pub script_source: ScriptSource,
+
     /// Don't include static JavaScript in flame graph.
     /// This is only meant to be used in tests.
     #[doc(hidden)]
@@ -260,11 +622,85 @@ pub struct Options<'a> {
     /// flamegraphs to someone for the first time.
     pub color_diffusion: bool,
 
+    /// Color the frames of a [differential] flame graph by whether their code paths are new
+    /// (only in the second profile), removed (only in the first profile) or in both, rather than
+    /// by how much their counts changed.
+    ///
+    /// This makes for a "what appeared since the last release" view: join an older and a current
+    /// profile with [`differential`](crate::differential), and draw them with this set. New code
+    /// paths are green, and all others are grey. Removed code paths are blue, but have no width
+    /// unless [`frame_width_source`](Self::frame_width_source) takes the first profile into
+    /// account (as [`FrameWidthSource::Max`] does). [`negate_differentials`] swaps the roles of
+    /// the two profiles.
+    ///
+    /// [differential]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
+    /// [`negate_differentials`]: Self::negate_differentials
+    pub color_by_age: bool,
+
+    /// Render a compact "flame strip" for embedding in dashboards or PR comments.
+    ///
+    /// Only frames up to the given depth are drawn, with the `(all)` frame at depth 0 serving as
+    /// a summary bar. The title, subtitle, details line, interactive controls, and embedded
+    /// JavaScript are left out, and there is no padding above or below the frames. Frame tooltips
+    /// still work.
+    ///
+    /// Defaults to `None`, which renders the full flame graph.
+    pub strip_depth: Option<usize>,
+
+    /// A link to the full flame graph for a [flame strip](Options::strip_depth).
+    ///
+    /// If set, clicking anywhere on the strip opens this URL. Ignored unless `strip_depth` is set.
+    pub full_graph_link: Option<String>,
+
+    /// Leave out the `all` frame that spans all samples, and draw the roots of the stacks in its
+    /// row instead.
+    ///
+    /// This saves a row of height, such as for dashboards of many small graphs. The tooltips of
+    /// the roots still show their share of the whole profile.
+    ///
+    /// Defaults to false.
+    pub omit_root: bool,
+
+    /// Draw a slim histogram to the right of the frames, with a bar for every row of frames as
+    /// long as the share of all samples whose stacks reach its depth, which shows at a glance
+    /// how deep the stacks typically go.
+    ///
+    /// Defaults to false.
+    pub depth_histogram: bool,
+
     /// Produce a flame chart (sort by time, do not merge stacks)
     ///
     /// Note that stack is not sorted and will be reversed
+    ///
+    /// The tooltip of every frame of a flame chart notes when it starts and ends, counted from the
+    /// first sample and scaled by [`Options::factor`], and its rectangle has them in `data-start`
+    /// and `data-end` attributes, so that frames can be matched to the timestamps of the trace.
     pub flame_chart: bool,
 
+    /// In a flame chart, show in the tooltip of a frame how many input lines in a row had its
+    /// stack, if more than one.
+    ///
+    /// Identical stacks that follow each other, such as those of the samples of a tight loop,
+    /// are drawn as a single wider block, so a flame chart only shows changes of stacks in
+    /// time order. This tells how many samples (or calls, for input with one line per call)
+    /// such a block aggregates. Ignored unless [`flame_chart`](Self::flame_chart) is set.
+    ///
+    /// Defaults to false.
+    pub show_occurrences: bool,
+
+    /// In a flame chart, draw only the time from the first to the second of these, in the units
+    /// of the start and end times of frames, so counted from the first sample and scaled by
+    /// [`Options::factor`].
+    ///
+    /// Frames that end before the range or start after it are left out, and those that are partly
+    /// in it are cut at its ends, so a slice of a long recording can be drawn without cutting the
+    /// input to it first. Frames still note when they start and end counted from the first
+    /// sample of the whole recording. Ignored unless [`flame_chart`](Self::flame_chart) is set,
+    /// and for differential input.
+    ///
+    /// Defaults to None.
+    pub time_range: Option<(f64, f64)>,
+
     /// Base symbols
     pub base: Vec<String>,
 
@@ -277,38 +713,181 @@ pub struct Options<'a> {
     /// More details in tooltips.  Implied if frame_width_source is other than 'before' or 'after'
     pub detailed_tooltips: bool,
 
+    /// Show in the tooltip of every frame its share of the samples of its parent, next to its
+    /// share of all samples.
+    ///
+    /// That is how much of the time of the calling function went into this call. It is not shown
+    /// for differential flame graphs.
+    ///
+    /// Defaults to false.
+    pub show_pct_of_parent: bool,
+
+    /// Which count comes first in the summary of a frame's tooltip.
+    ///
+    /// With anything but [`PrimaryMetric::Total`], the total count of the frame moves to a line
+    /// of its own below the summary. The "all" frame and differential flame graphs always show
+    /// totals.
+    pub primary_metric: PrimaryMetric,
+
+    /// Label the "all" frame with the total samples, the number of distinct stacks, and, if
+    /// [`Options::count_name`] is a unit of time such as `ms`, the time they cover.
+    ///
+    /// Counts are scaled by [`Options::factor`], so that sample counts can be turned into time
+    /// with a factor of the sampling period and a count name of its unit. In flame charts, every
+    /// run of the same stack counts as a distinct stack.
+    ///
+    /// Defaults to false.
+    pub all_frame_stats: bool,
+
     /// Compare differential samples based on percent of total rather than absolute number of
     /// samples
     pub normalize: bool,
+
+    /// Accept negative sample counts (credits) in single-column input.
+    ///
+    /// The counts of identical stacks are summed before plotting, so a negative count can be used
+    /// to subtract samples from a stack. This holds even when the lines of a stack aren't adjacent,
+    /// as with [`no_sort`](Options::no_sort) or in a [`flame_chart`](Options::flame_chart), where
+    /// the stack is drawn once, where its first line is. If a stack still has a negative count after that, its
+    /// width is clamped to zero and its frame is marked as clamped in the tooltip. Frames left
+    /// without any width aren't drawn, so the number of clamped stacks is also logged, and added
+    /// to the notes with [`embed_warnings`](Options::embed_warnings).
+    ///
+    /// This option is ignored for differential input.
+    pub signed_counts: bool,
+
+    /// For differential input, treat lines with only one sample count as if their second count
+    /// was zero, instead of ignoring them.
+    ///
+    /// This is for stacks that were only seen in the first profile, when whatever produced the
+    /// input left out the second count rather than give it as zero.
+    pub missing_count_as_zero: bool,
+
+    /// The color palette for off-CPU frames in a hot/cold flame graph.
+    ///
+    /// See [`from_hot_cold_readers`].
+    pub off_cpu_colors: color::Palette,
+
+    /// How on-CPU and off-CPU stacks are laid out in a hot/cold flame graph.
+    pub hot_cold_layout: HotColdLayout,
+
+    /// Draw every input file of [`from_files`] or [`render_files`] as a band of its own, under a
+    /// root frame named after the file, so that the profiles of, say, the shards of a service can
+    /// be compared side by side in one flame graph.
+    ///
+    /// How wide the bands are is set by [`stacked_scale`](Self::stacked_scale). Differential
+    /// input is not supported.
+    ///
+    /// Defaults to false.
+    pub stacked_inputs: bool,
+
+    /// How the bands of [`stacked_inputs`](Self::stacked_inputs) are scaled.
+    pub stacked_scale: StackedScale,
+
+    /// The frame name suffixes that annotate frames, such as `_[k]` for kernel frames.
+    ///
+    /// Annotations are left out of the text drawn on frames. An annotation with a label is
+    /// shown as a badge in the tooltip of its frames, and one with a palette overrides the colors
+    /// of its frames. The first matching annotation applies.
+    ///
+    /// By default this holds the annotations added by `inferno-collapse-*`: `_[k]` (kernel),
+    /// `_[w]` (waker), `_[i]` (inlined) and `_[j]` (JIT), without labels or palettes; the
+    /// semantic palettes color these frames on their own.
+    pub suffix_annotations: Vec<SuffixAnnotation>,
+
+    /// Factor to scale off-CPU sample counts by in a hot/cold flame graph, so that they are in
+    /// the same unit as the on-CPU sample counts.
+    ///
+    /// For example, if off-CPU time is given in microseconds and on-CPU stacks were sampled at
+    /// 99 Hz, a factor of `99.0 / 1_000_000.0` makes both count samples. Scaled counts are
+    /// rounded to whole samples.
+    pub off_cpu_factor: f64,
 }
 
 impl Options<'_> {
+    /// Parse options from a TOML document, such as a shared `inferno.toml` of house style.
+    ///
+    /// Keys are the field names of `Options`, and colors and palettes are given in the same form
+    /// as on the command line. Keys that are not given keep their default values:
+    ///
+    /// ```toml
+    /// colors = "java"
+    /// title = "Service CPU"
+    /// font_type = "Helvetica"
+    /// min_width = 0.5
+    /// ```
+    ///
+    /// If the document has a `[flamegraph]` table, the options are read from it instead, and the
+    /// rest of the document is ignored. This is the table that `inferno-flamegraph --config`
+    /// reads, so the same file can be shared between the library and the binaries.
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(s: &str) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut document: toml::Table = toml::from_str(s).map_err(invalid)?;
+        match document.remove("flamegraph") {
+            Some(toml::Value::Table(table)) => table.try_into(),
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "[flamegraph] is not a table",
+                ))
+            }
+            None => document.try_into(),
+        }
+        .map_err(invalid)
+    }
+
     /// Calculate pad top, including title and subtitle
     pub(super) fn ypad1(&self) -> usize {
+        if self.strip_depth.is_some() {
+            return 0;
+        }
+        // The bookmarks get a row of their own below the title (and subtitle).
+        let bookmarks_height = if self.bookmarks.is_empty() {
+            0
+        } else {
+            self.font_size * 2
+        };
+        if self.compact_header {
+            return self.font_size * 2 + bookmarks_height;
+        }
         let subtitle_height = if self.subtitle.is_some() {
             self.font_size * 2
         } else {
             0
         };
         if self.direction == Direction::Straight {
-            self.font_size * 3 + subtitle_height
+            self.font_size * 3 + subtitle_height + bookmarks_height
         } else {
             // Inverted (icicle) mode, put the details on top. The +4 is to add
             // a little bit more space between the title (or subtitle if there
             // is one) and the details.
-            self.font_size * 4 + subtitle_height + 4
+            self.font_size * 4 + subtitle_height + bookmarks_height + 4
         }
     }
 
     /// Calculate pad bottom, including labels
     pub(super) fn ypad2(&self) -> usize {
-        if self.direction == Direction::Straight {
+        if self.strip_depth.is_some() {
+            return 0;
+        }
+        // The button of the annotations panel and the footer share a row of their own at the
+        // very bottom.
+        let panel_height = if self.annotations_panel || self.footer.is_some() {
+            self.font_size * 2
+        } else {
+            0
+        };
+        let labels_height = if self.compact_header {
+            self.font_size / 2
+        } else if self.direction == Direction::Straight {
             self.font_size * 2 + 10
         } else {
             // Inverted (icicle) mode, put the details on top, so don't need
             // room at the bottom.
             self.font_size + 10
-        }
+        };
+        labels_height + panel_height
     }
 }
 
@@ -317,47 +896,111 @@ impl Default for Options<'_> {
         Options {
             colors: Palette::from_str(defaults::COLORS).unwrap(),
             search_color: SearchColor::from_str(defaults::SEARCH_COLOR).unwrap(),
+            sync_group: Default::default(),
+            live_updates: Default::default(),
             stroke_color: StrokeColor::from_str(defaults::STROKE_COLOR).unwrap(),
+            stroke_width: defaults::STROKE_WIDTH,
+            frame_radius: defaults::FRAME_RADIUS,
+            frame_gap: defaults::FRAME_GAP,
             title: defaults::TITLE.to_string(),
+            show_status_bar: true,
+            details_template: Default::default(),
             frame_height: defaults::FRAME_HEIGHT,
             min_width: defaults::MIN_WIDTH,
+            aggregate_narrow_frames: false,
+            min_frame_pixels: defaults::MIN_FRAME_PIXELS,
+            short_narrow_tooltips: false,
             font_type: defaults::FONT_TYPE.to_string(),
             font_size: defaults::FONT_SIZE,
             font_width: defaults::FONT_WIDTH,
             text_truncate_direction: Default::default(),
+            shrink_text_to_fit: false,
+            min_font_size: defaults::MIN_FONT_SIZE,
             count_name: defaults::COUNT_NAME.to_string(),
             name_type: defaults::NAME_TYPE.to_string(),
             factor: defaults::FACTOR,
             image_width: Default::default(),
+            width_sizing: Default::default(),
+            xpad: defaults::XPAD,
             notes: Default::default(),
+            embed_warnings: Default::default(),
             subtitle: Default::default(),
+            compact_header: Default::default(),
             bgcolors: Default::default(),
             uicolor: Default::default(),
+            color_modifier: Default::default(),
+            palette_ranges: Vec::new(),
             hash: Default::default(),
             deterministic: Default::default(),
             palette_map: Default::default(),
+            frame_hook: Default::default(),
+            random_source: Default::default(),
             direction: Default::default(),
             negate_differentials: Default::default(),
             pretty_xml: Default::default(),
+            svgz: Default::default(),
+            fragment: Default::default(),
             no_sort: Default::default(),
             reverse_stack_order: Default::default(),
+            bottom_up: Default::default(),
+            top_stacks: Default::default(),
+            time_windows: Default::default(),
+            width_window: Default::default(),
+            self_time_shading: Default::default(),
+            annotations_panel: Default::default(),
+            diff_summary: Default::default(),
+            bookmarks: Default::default(),
+            footer: Default::default(),
+            watermark: Default::default(),
+            script_source: Default::default(),
             no_javascript: Default::default(),
             color_diffusion: Default::default(),
+            color_by_age: Default::default(),
             flame_chart: Default::default(),
+            show_occurrences: Default::default(),
+            time_range: Default::default(),
+            strip_depth: Default::default(),
+            full_graph_link: Default::default(),
+            omit_root: Default::default(),
+            depth_histogram: Default::default(),
             base: Default::default(),
             include_children: Default::default(),
             frame_width_source: Default::default(),
             detailed_tooltips: false,
+            show_pct_of_parent: false,
+            primary_metric: Default::default(),
+            all_frame_stats: false,
             normalize: false,
+            signed_counts: false,
+            missing_count_as_zero: false,
+            off_cpu_colors: Palette::Basic(color::BasicPalette::Blue),
+            hot_cold_layout: Default::default(),
+            stacked_inputs: false,
+            stacked_scale: Default::default(),
+            off_cpu_factor: 1.0,
+            suffix_annotations: SuffixAnnotation::defaults(),
 
             #[cfg(feature = "nameattr")]
             func_frameattrs: Default::default(),
+            #[cfg(feature = "annotations")]
+            func_annotations: Default::default(),
+            #[cfg(feature = "annotations")]
+            stack_weights: Default::default(),
+            #[cfg(feature = "annotations")]
+            weights_file: Default::default(),
+            #[cfg(feature = "annotations")]
+            group_rules: Default::default(),
         }
     }
 }
 
 /// The direction the plot should grow.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
 pub enum Direction {
     /// Stacks grow from the bottom to the top.
     ///
@@ -371,8 +1014,104 @@ pub enum Direction {
     Inverted,
 }
 
+/// A corner of the flame graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Corner {
+    /// The top left corner.
+    TopLeft,
+    /// The top right corner.
+    TopRight,
+    /// The bottom left corner.
+    BottomLeft,
+    /// The bottom right corner.
+    #[default]
+    BottomRight,
+}
+
+/// The image of a [`Watermark`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+#[non_exhaustive]
+pub enum WatermarkImage {
+    /// An SVG path, as in the `d` attribute of a `<path>`, filled with the UI color. The path is
+    /// scaled to the watermark from the box of the given width and height its coordinates are
+    /// in, such as 24 by 24 for most icon sets.
+    Path {
+        /// The path data.
+        data: String,
+        /// The width of the box of the path's coordinates.
+        width: f64,
+        /// The height of the box of the path's coordinates.
+        height: f64,
+    },
+
+    /// An image as a data URI, such as `data:image/png;base64,...`, which keeps the flame graph
+    /// a single self-contained file.
+    DataUri(String),
+}
+
+/// An image drawn faintly over a corner of the flame graph; see [`Options::watermark`].
+///
+/// The image is scaled to fit a box of [`Watermark::width`] by [`Watermark::height`] pixels,
+/// keeping its aspect ratio, and lets clicks through to the frames under it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Watermark {
+    /// The image to draw.
+    pub image: WatermarkImage,
+
+    /// The corner to draw the image in.
+    ///
+    /// Defaults to [`Corner::BottomRight`].
+    pub corner: Corner,
+
+    /// The width of the box the image is drawn in, in pixels.
+    ///
+    /// Defaults to 48.
+    pub width: usize,
+
+    /// The height of the box the image is drawn in, in pixels.
+    ///
+    /// Defaults to 48.
+    pub height: usize,
+
+    /// The opacity of the image, from `0.0` (invisible) to `1.0`.
+    ///
+    /// Defaults to 0.3.
+    pub opacity: f64,
+}
+
+impl Watermark {
+    /// A watermark of `image`, with the default placement, size and opacity.
+    pub fn new(image: WatermarkImage) -> Self {
+        Watermark {
+            image,
+            corner: Corner::default(),
+            width: 48,
+            height: 48,
+            opacity: 0.3,
+        }
+    }
+}
+
 /// The direction text is truncated when it's too long.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
 pub enum TextTruncateDirection {
     /// Truncate text on the left.
     #[default]
@@ -380,72 +1119,373 @@ pub enum TextTruncateDirection {
 
     /// Truncate text on the right.
     Right,
+
+    /// Truncate text in the middle, keeping both its start and its end, such as the namespace
+    /// and the function of `my::really::long::..::do_work`.
+    Middle,
 }
 
-/// Source of frame widths for differential flamegraphs, chosen on a per-stack basis.  Assumes two
-/// columns
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, ValueEnum)]
-pub enum FrameWidthSource {
-    /// Take shape from the first dataset.  Functions that have been added will not be visible. No
-    /// shape distortion.
-    Before,
-    #[default]
-    /// Take shape from the second dataset. Functions that have been removed will not be visible.
-    /// No shape distortion.
-    After,
-    /// Show only the differences between the two flamegraphs
-    Difference,
-    /// Show only commonalities between the two flamegraphs
-    Common,
-    /// Use all samples from both flamegraphs.
-    AllSamples,
-    /// Maximum (same as common + difference)
-    Max,
+/// A frame name suffix that annotates frames, as in [`Options::suffix_annotations`].
+///
+/// It can be parsed from `SUFFIX`, `SUFFIX=LABEL`, `SUFFIX=LABEL:PALETTE` or `SUFFIX=:PALETTE`,
+/// for example `_[g]=GPU:green`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SuffixAnnotation {
+    /// The suffix, such as `_[k]`.
+    pub suffix: String,
+
+    /// Shown as a badge in the tooltip of annotated frames, such as `kernel`.
+    pub label: Option<String>,
+
+    /// The palette to color annotated frames with, instead of the palette of the flame graph.
+    pub colors: Option<Palette>,
 }
 
-impl FrameWidthSource {
-    /// Apply the appropriate function for the frame width source
-    pub fn apply(&self, before: usize, after: usize) -> usize {
-        use FrameWidthSource::*;
-        match self {
-            Before => before,
-            After => after,
-            Difference => {
-                if before > after {
-                    before - after
-                } else {
-                    after - before
-                }
-            }
-            Common => {
-                if before < after {
-                    before
-                } else {
-                    after
-                }
-            }
-            AllSamples => before + after,
-            Max => {
-                if before > after {
-                    before
-                } else {
-                    after
-                }
-            }
+impl SuffixAnnotation {
+    /// An annotation with the given suffix that is only stripped from frame names.
+    pub fn new(suffix: impl Into<String>) -> Self {
+        SuffixAnnotation {
+            suffix: suffix.into(),
+            label: None,
+            colors: None,
         }
     }
+
+    /// The default annotations; see [`Options::suffix_annotations`].
+    pub fn defaults() -> Vec<Self> {
+        ["_[k]", "_[w]", "_[i]", "_[j]"]
+            .into_iter()
+            .map(SuffixAnnotation::new)
+            .collect()
+    }
 }
 
-struct Rectangle {
-    x1_samples: usize,
-    x1_pct: f64,
-    y1: usize,
-    x2_samples: usize,
-    x2_pct: f64,
-    y2: usize,
+impl FromStr for SuffixAnnotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (suffix, rest) = s.split_once('=').unwrap_or((s, ""));
+        if suffix.is_empty() {
+            return Err(format!("annotation {} has an empty suffix", s));
+        }
+        let mut annotation = SuffixAnnotation::new(suffix);
+        let (label, colors) = match rest.rsplit_once(':') {
+            Some((label, colors)) => (label, Some(colors.parse::<Palette>()?)),
+            None => (rest, None),
+        };
+        annotation.label = Some(label.to_string()).filter(|l| !l.is_empty());
+        annotation.colors = colors;
+        Ok(annotation)
+    }
 }
 
-impl Rectangle {
+#[cfg(feature = "serde")]
+impl fmt::Display for SuffixAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.suffix)?;
+        if self.label.is_some() || self.colors.is_some() {
+            write!(f, "={}", self.label.as_deref().unwrap_or_default())?;
+        }
+        if let Some(colors) = self.colors {
+            write!(f, ":{}", colors.name())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SuffixAnnotation {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SuffixAnnotation {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// How the width of the SVG element is expressed, for embedding in responsive pages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WidthSizing {
+    /// A percentage of the width of the containing element (e.g. `80%`).
+    Percent(f64),
+
+    /// A percentage of the viewport width (e.g. `80vw`).
+    ViewportWidth(f64),
+
+    /// Fill the containing element, but never grow wider than this many pixels (e.g. `max:1600`).
+    FluidMax(usize),
+
+    /// Omit `width` and `height` altogether and let the `viewBox` scale the image to fit its
+    /// container (`viewbox`).
+    ViewBox,
+}
+
+impl WidthSizing {
+    /// Whether the embedded JavaScript should re-layout the frames as the width changes.
+    fn is_fluid(&self) -> bool {
+        !matches!(self, WidthSizing::ViewBox)
+    }
+}
+
+impl fmt::Display for WidthSizing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WidthSizing::Percent(p) => write!(f, "{}%", p),
+            WidthSizing::ViewportWidth(vw) => write!(f, "{}vw", vw),
+            WidthSizing::FluidMax(max) => write!(f, "max:{}", max),
+            WidthSizing::ViewBox => write!(f, "viewbox"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for WidthSizing {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for WidthSizing {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for WidthSizing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |n: &str| {
+            n.parse::<f64>()
+                .ok()
+                .filter(|n| *n > 0.0)
+                .ok_or_else(|| format!("invalid width: {}", s))
+        };
+        if s.eq_ignore_ascii_case("viewbox") {
+            Ok(WidthSizing::ViewBox)
+        } else if let Some(max) = s.strip_prefix("max:") {
+            max.parse()
+                .map(WidthSizing::FluidMax)
+                .map_err(|_| format!("invalid maximum width: {}", s))
+        } else if let Some(percent) = s.strip_suffix('%') {
+            number(percent).map(WidthSizing::Percent)
+        } else if let Some(vw) = s.strip_suffix("vw") {
+            number(vw).map(WidthSizing::ViewportWidth)
+        } else {
+            Err(format!(
+                "unknown width sizing {} (expected N%, Nvw, max:N, or viewbox)",
+                s
+            ))
+        }
+    }
+}
+
+/// The version of the interface between a flame graph and its interactive script: the
+/// variables, element ids and `fg:` attributes that the script relies on.
+///
+/// It follows semantic versioning, so a script made for one version works with the flame graphs
+/// of any later version with the same major version. Flame graphs that load an
+/// [external script](ScriptSource::External) set the `scriptversion` variable to it, for the
+/// script to check; the built-in script warns about those of another major version.
+pub const SCRIPT_VERSION: &str = "1.0.0";
+
+/// The built-in interactive script of flame graphs, to serve as an
+/// [external script](ScriptSource::External) or to start one from.
+pub const SCRIPT: &str = include_str!("flamegraph.js");
+
+/// Where the interactive script of a flame graph comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ScriptSource {
+    /// Inline the built-in script (`embedded`).
+    #[default]
+    Embedded,
+
+    /// Load the script from this URL, such as that of a copy of [`SCRIPT`] that is upgraded or
+    /// extended centrally. This keeps the script out of every flame graph, but they are only
+    /// interactive where the URL can be loaded.
+    External(String),
+
+    /// Leave out the script altogether (`none`), for a static image.
+    None,
+}
+
+impl fmt::Display for ScriptSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptSource::Embedded => write!(f, "embedded"),
+            ScriptSource::External(url) => write!(f, "{}", url),
+            ScriptSource::None => write!(f, "none"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ScriptSource {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ScriptSource {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for ScriptSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("embedded") {
+            Ok(ScriptSource::Embedded)
+        } else if s.eq_ignore_ascii_case("none") {
+            Ok(ScriptSource::None)
+        } else if s.is_empty() {
+            Err("expected embedded, none, or the URL of a script".to_string())
+        } else {
+            Ok(ScriptSource::External(s.to_string()))
+        }
+    }
+}
+
+/// The layout of a hot/cold flame graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum HotColdLayout {
+    /// All on-CPU stacks under an `on-CPU` frame, followed by all off-CPU stacks under an
+    /// `off-CPU` frame.
+    #[default]
+    SideBySide,
+    /// On-CPU and off-CPU stacks are sorted together, so that the on-CPU and off-CPU time of a
+    /// function are drawn next to each other.
+    Interleaved,
+}
+
+/// How the bands of [`Options::stacked_inputs`] are scaled.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum StackedScale {
+    /// Every band is as wide as its samples, so that the bands of busier inputs are wider.
+    #[default]
+    Common,
+    /// The samples of every band are scaled to the total of the largest input, so that all bands
+    /// are equally wide, and their shapes can be compared.
+    Independent,
+}
+
+/// The count shown first in the summary of a frame's tooltip; see [`Options::primary_metric`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum PrimaryMetric {
+    /// The samples of the frame and its children, and their share of all samples, as in
+    /// `main (1,500 samples, 75.00%)`.
+    #[default]
+    Total,
+    /// The samples of the frame itself, outside of its children, and their share of all samples,
+    /// as in `main (self: 300 samples, 15.00%)`, such as the bytes a function allocated itself in
+    /// a memory profile.
+    #[cfg_attr(feature = "cli", value(name = "self"))]
+    #[cfg_attr(feature = "serde", serde(rename = "self"))]
+    SelfCount,
+    /// Only the share of all samples of the frame and its children, as in `main (75.00%)`.
+    Percent,
+}
+
+/// Source of frame widths for differential flamegraphs, chosen on a per-stack basis.  Assumes two
+/// columns
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum FrameWidthSource {
+    /// Take shape from the first dataset.  Functions that have been added will not be visible. No
+    /// shape distortion.
+    Before,
+    #[default]
+    /// Take shape from the second dataset. Functions that have been removed will not be visible.
+    /// No shape distortion.
+    After,
+    /// Show only the differences between the two flamegraphs
+    Difference,
+    /// Show only commonalities between the two flamegraphs
+    Common,
+    /// Use all samples from both flamegraphs.
+    AllSamples,
+    /// Maximum (same as common + difference)
+    Max,
+}
+
+impl FrameWidthSource {
+    /// Apply the appropriate function for the frame width source
+    pub fn apply(&self, before: usize, after: usize) -> usize {
+        use FrameWidthSource::*;
+        match self {
+            Before => before,
+            After => after,
+            Difference => {
+                if before > after {
+                    before - after
+                } else {
+                    after - before
+                }
+            }
+            Common => {
+                if before < after {
+                    before
+                } else {
+                    after
+                }
+            }
+            AllSamples => before + after,
+            Max => {
+                if before > after {
+                    before
+                } else {
+                    after
+                }
+            }
+        }
+    }
+}
+
+struct Rectangle {
+    x1_samples: usize,
+    x1_pct: f64,
+    y1: usize,
+    x2_samples: usize,
+    x2_pct: f64,
+    y2: usize,
+}
+
+impl Rectangle {
     fn width_pct(&self) -> f64 {
         self.x2_pct - self.x1_pct
     }
@@ -476,142 +1516,531 @@ fn tidy_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> impl IntoIterator
 /// The resulting flame graph will be written out to `writer` in SVG format.
 ///
 /// [differential flame graph]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
-#[allow(clippy::cognitive_complexity)]
-pub fn from_lines<'a, I, W, CountType>(opt: &mut Options<'_>, lines: I, writer: W) -> io::Result<()>
+pub fn from_lines<'a, I, W, CountType>(
+    opt: &mut Options<'_>,
+    lines: I,
+    mut writer: W,
+) -> io::Result<()>
 where
     I: IntoIterator<Item = &'a str>,
     W: Write,
     CountType: CountTypeRequirements,
     StackSampleCount<CountType>: StackSampleCountExt,
     FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
+{
+    render_lines::<_, CountType>(opt, lines, &mut [OutputSink::Svg(&mut writer)], None)
+}
+
+/// Produce several outputs from an iterator over folded stack lines, merging the stacks only once.
+///
+/// See [`from_lines`] for the expected format of each line. Every [`OutputSink`] in `sinks` is
+/// written to in turn, so a flame graph, the merged stacks as JSON, and the stacks as folded after
+/// the transformations asked for in `opt` can all be produced from a single pass over a large
+/// input:
+///
+/// ```
+/// use inferno::flamegraph::{self, Options, OutputSink};
+///
+/// let lines = ["main;parse 3", "main;eval 5", "main;parse 1"];
+/// let (mut svg, mut json, mut folded) = (Vec::new(), Vec::new(), Vec::new());
+/// flamegraph::render(
+///     &mut Options::default(),
+///     lines,
+///     &mut [
+///         OutputSink::Svg(&mut svg),
+///         OutputSink::Json(&mut json),
+///         OutputSink::Folded(&mut folded),
+///     ],
+/// )
+/// .unwrap();
+/// assert_eq!(String::from_utf8(folded).unwrap(), "main;eval 5\nmain;parse 4\n");
+/// ```
+pub fn render<'a, I>(
+    opt: &mut Options<'_>,
+    lines: I,
+    sinks: &mut [OutputSink<'_>],
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let lines: Vec<&str> = lines.into_iter().collect();
+    if opt.time_windows < 2 && is_diff_case(lines.iter().copied()) {
+        render_lines::<_, DiffCount>(opt, lines, sinks, None)
+    } else {
+        render_lines::<_, usize>(opt, lines, sinks, None)
+    }
+}
+
+// Merges the stacks the way `render` does, and lays out their frames rather than writing anything.
+fn render_layout<'a, I>(opt: &mut Options<'_>, lines: I) -> io::Result<layout::Layout>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let lines: Vec<&str> = lines.into_iter().collect();
+    let mut layout = layout::Layout::default();
+    if opt.time_windows < 2 && is_diff_case(lines.iter().copied()) {
+        render_lines::<_, DiffCount>(opt, lines, &mut [], Some(&mut layout))?;
+    } else {
+        render_lines::<_, usize>(opt, lines, &mut [], Some(&mut layout))?;
+    }
+    Ok(layout)
+}
+
+/// Produce several outputs from files that contain folded stack lines.
+///
+/// If files is empty, STDIN will be used as input. See [`render`] for details.
+pub fn render_files(
+    opt: &mut Options<'_>,
+    files: &[PathBuf],
+    sinks: &mut [OutputSink<'_>],
+) -> io::Result<()> {
+    if opt.stacked_inputs {
+        let input = read_stacked_inputs(opt, files)?;
+        return render_lines::<_, usize>(opt, input.lines(), sinks, None);
+    }
+
+    let mut input = String::new();
+    let mut stdin_read = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_read {
+                read_input(opt, io::stdin().lock(), &mut input)?;
+                stdin_read = true;
+            }
+        } else {
+            read_input(opt, File::open(infile)?, &mut input)?;
+        }
+    }
+    if files.is_empty() {
+        read_input(opt, io::stdin().lock(), &mut input)?;
+    }
+    render(opt, input.lines(), sinks)
+}
+
+fn render_lines<'a, I, CountType>(
+    opt: &mut Options<'_>,
+    lines: I,
+    sinks: &mut [OutputSink<'_>],
+    layout: Option<&mut layout::Layout>,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    CountType: CountTypeRequirements,
+    StackSampleCount<CountType>: StackSampleCountExt,
+    FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
 {
     let mut reversed = StrStack::new();
-    let lines = tidy_lines(lines);
-
-    let (mut frames, overall_total_sample_count, ignored, delta_max) = if opt.reverse_stack_order {
-        if opt.no_sort {
-            warn!(
-                "Input lines are always sorted when `reverse_stack_order` is `true`. \
-                 The `no_sort` option is being ignored."
-            );
-        }
-        // Reverse order of stacks and sort.
-        let mut stack = String::new();
-        for line in lines {
-            stack.clear();
-            let samples_idx = merge::rfind_samples(line)
-                .map(|(i, _)| i)
-                .unwrap_or_else(|| line.len());
-            let samples_idx = merge::rfind_samples(&line[..samples_idx - 1])
-                .map(|(i, _)| i)
-                .unwrap_or(samples_idx);
-            for (i, func) in line[..samples_idx].trim().split(';').rev().enumerate() {
-                if i != 0 {
-                    stack.push(';');
+
+    // Remember where each input line lives, and its line number, so ignored lines can be
+    // reported by line number.
+    let mut line_spans = Vec::new();
+    let lines = tidy_lines(lines.into_iter().inspect(|line| {
+        if opt.embed_warnings {
+            let line_number = line_spans.len() + 1;
+            line_spans.push((line.as_ptr() as usize, line.len(), line_number));
+        }
+    }));
+
+    let windowed: Vec<Cow<'_, str>>;
+    let mut trends = None;
+    let lines: Box<dyn Iterator<Item = &str>> = if opt.time_windows > 1 {
+        let (split, windows) = trend::Trends::split(lines, opt.time_windows, opt.width_window);
+        windowed = split;
+        #[cfg(feature = "annotations")]
+        let grouped = !opt.group_rules.is_empty();
+        #[cfg(not(feature = "annotations"))]
+        let grouped = false;
+        if opt.reverse_stack_order || opt.bottom_up.is_some() || grouped || opt.flame_chart {
+            warn!("Trends are only shown for the stacks of the input, and are left out.");
+        } else {
+            trends = Some(windows);
+        }
+        Box::new(windowed.iter().map(|line| line.as_ref()))
+    } else {
+        // Mapping the lines shortens their lifetime to that of `windowed`.
+        #[allow(clippy::map_identity)]
+        Box::new(lines.into_iter().map(|line| line))
+    };
+
+    #[cfg(feature = "annotations")]
+    let grouped: Vec<Cow<'_, str>>;
+    #[cfg(feature = "annotations")]
+    let lines: Box<dyn Iterator<Item = &str>> = if opt.group_rules.is_empty() {
+        // Mapping the lines shortens their lifetime to that of `grouped`.
+        #[allow(clippy::map_identity)]
+        Box::new(lines.into_iter().map(|line| line))
+    } else {
+        let counts = if StackSampleCount::<CountType>::default().is_diff() {
+            2
+        } else {
+            1
+        };
+        grouped = opt.group_rules.group_lines(lines, counts);
+        Box::new(grouped.iter().map(|line| line.as_ref()))
+    };
+
+    let top: Vec<Cow<'_, str>>;
+    let lines: Box<dyn Iterator<Item = &str>> = match opt.top_stacks {
+        Some(n) if !opt.flame_chart => {
+            let counts = if StackSampleCount::<CountType>::default().is_diff() {
+                2
+            } else {
+                1
+            };
+            top = merge::top_stacks(lines, n, counts);
+            Box::new(top.iter().map(|line| line.as_ref()))
+        }
+        Some(_) => {
+            warn!("Flame charts keep all stacks. The `top_stacks` option is being ignored.");
+            #[allow(clippy::map_identity)]
+            Box::new(lines.into_iter().map(|line| line))
+        }
+        // Mapping the lines shortens their lifetime to that of `top`.
+        #[allow(clippy::map_identity)]
+        None => Box::new(lines.into_iter().map(|line| line)),
+    };
+
+    let signed_counts = if opt.signed_counts && StackSampleCount::<CountType>::default().is_diff() {
+        warn!("Signed sample counts are not supported for differential input and will be ignored.");
+        false
+    } else {
+        opt.signed_counts
+    };
+
+    let time_window = if opt.time_range.is_some() && !opt.flame_chart {
+        warn!("Time ranges are only supported for flame charts and will be ignored.");
+        None
+    } else if opt.time_range.is_some() && StackSampleCount::<CountType>::default().is_diff() {
+        warn!("Time ranges are not supported for differential input and will be ignored.");
+        None
+    } else {
+        time_window(opt)
+    };
+    let clipped: Vec<Cow<'_, str>>;
+
+    #[cfg(feature = "annotations")]
+    let stack_weights = stack_weights(opt)?;
+    #[cfg(feature = "annotations")]
+    let weight = |stack: &str| stack_weights.factor_for(stack);
+    #[cfg(not(feature = "annotations"))]
+    let weight = |_: &str| None;
+
+    let (mut frames, overall_total_sample_count, ignored, delta_max) =
+        if opt.reverse_stack_order || opt.bottom_up.is_some() {
+            if opt.no_sort {
+                warn!(
+                    "Input lines are always sorted when `reverse_stack_order` is `true`. \
+                     The `no_sort` option is being ignored."
+                );
+            }
+            let depth = opt.bottom_up.map_or(usize::MAX, |depth| depth.max(1));
+            // Reverse order of stacks and sort.
+            let rfind_samples = |line: &str| {
+                if signed_counts {
+                    merge::rfind_signed_samples(line).map(|(i, _, _)| i)
+                } else {
+                    merge::rfind_samples(line).map(|(i, _)| i)
                 }
-                stack.push_str(func);
+            };
+            let mut stack = String::new();
+            for line in lines {
+                reversed.push(crate::folded::reverse::reverse_line(
+                    line,
+                    depth,
+                    rfind_samples,
+                    &mut stack,
+                ));
             }
-            stack.push(' ');
-            stack.push_str(&line[samples_idx..]);
-            // Trim to handle the case where functions names internally contain `;`.
-            // This can happen, for example, with types like `[u8; 8]` in Rust.
-            // See https://github.com/jonhoo/inferno/pull/338.
-            let stack = stack.trim();
-            reversed.push(stack);
-        }
-        let mut reversed: Vec<&str> = reversed.iter().collect();
-        reversed.sort_unstable();
-        merge::frames::<_, CountType>(reversed, false, opt.frame_width_source)?
-    } else if opt.flame_chart {
-        // In flame chart mode, just reverse the data so time moves from left to right.
-        let mut lines: Vec<&str> = lines.into_iter().collect();
-        lines.reverse();
-        merge::frames::<_, CountType>(lines, true, opt.frame_width_source)?
-    } else if opt.no_sort {
-        // Lines don't need sorting.
-        merge::frames::<_, CountType>(lines, false, opt.frame_width_source)?
-    } else {
-        // Sort lines by default.
-        let mut lines: Vec<&str> = if opt.base.is_empty() {
-            lines.into_iter().collect()
+            let mut reversed: Vec<&str> = reversed.iter().collect();
+            merge::sort_lines(&mut reversed);
+            merge::frames::<_, CountType, _>(
+                reversed,
+                false,
+                opt.frame_width_source,
+                signed_counts,
+                opt.missing_count_as_zero,
+                weight,
+            )?
+        } else if opt.flame_chart {
+            // In flame chart mode, just reverse the data so time moves from left to right.
+            let mut lines: Vec<&str> = lines.into_iter().collect();
+            lines.reverse();
+            if let Some(window) = time_window {
+                clipped = clip_to_time_window(&lines, window);
+                lines = clipped.iter().map(|line| line.as_ref()).collect();
+            }
+            merge::frames::<_, CountType, _>(
+                lines,
+                true,
+                opt.frame_width_source,
+                signed_counts,
+                opt.missing_count_as_zero,
+                weight,
+            )?
+        } else if opt.no_sort {
+            // Lines don't need sorting.
+            merge::frames::<_, CountType, _>(
+                lines,
+                false,
+                opt.frame_width_source,
+                signed_counts,
+                opt.missing_count_as_zero,
+                weight,
+            )?
         } else {
-            lines
-                .into_iter()
-                .filter_map(|line| {
-                    let mut cursor = line.len();
-                    for symbol in line.rsplit(';') {
-                        cursor -= symbol.len();
-                        if opt.base.iter().any(|b| b == symbol) {
-                            break;
+            // Sort lines by default.
+            let mut lines: Vec<&str> = if opt.base.is_empty() {
+                lines.into_iter().collect()
+            } else {
+                lines
+                    .into_iter()
+                    .filter_map(|line| {
+                        let mut cursor = line.len();
+                        for symbol in merge::split_frames(line).rev() {
+                            cursor -= symbol.len();
+                            if opt.base.iter().any(|b| b == symbol) {
+                                break;
+                            }
+                            cursor = cursor.saturating_sub(1);
                         }
-                        cursor = cursor.saturating_sub(1);
-                    }
-                    if cursor == 0 {
-                        None
-                    } else {
-                        Some(&line[cursor..])
-                    }
-                })
-                .collect()
+                        if cursor == 0 {
+                            None
+                        } else {
+                            Some(&line[cursor..])
+                        }
+                    })
+                    .collect()
+            };
+            merge::sort_lines(&mut lines);
+            merge::frames::<_, CountType, _>(
+                lines,
+                false,
+                opt.frame_width_source,
+                signed_counts,
+                opt.missing_count_as_zero,
+                weight,
+            )?
         };
-        lines.sort_unstable();
-        merge::frames::<_, CountType>(lines, false, opt.frame_width_source)?
-    };
 
-    if ignored != 0 {
-        warn!("Ignored {} lines with invalid format", ignored);
+    if ignored.count != 0 {
+        warn!("Ignored {} lines with invalid format", ignored.count);
+    }
+    if ignored.clamped_stacks != 0 {
+        warn!(
+            "Clamped {} stacks with negative counts to zero",
+            ignored.clamped_stacks
+        );
     }
 
-    let mut buffer = StrStack::new();
-
-    // let's start writing the svg!
-    let mut svg = if opt.pretty_xml {
-        Writer::new_with_indent(writer, b' ', 4)
+    let warnings_summary = if opt.embed_warnings
+        && (ignored.count != 0
+            || ignored.stripped_fractional_samples
+            || ignored.clamped_stacks != 0)
+    {
+        Some(warnings_summary(&ignored, &line_spans))
     } else {
-        Writer::new(writer)
+        None
     };
 
-    if overall_total_sample_count.is_none() {
+    let Some(sample_count_max) = overall_total_sample_count else {
         error!("No stack counts found");
-        // emit an error message SVG, for tools automating flamegraph use
-        let imageheight = opt.font_size * 5;
-        svg::write_header(&mut svg, imageheight, opt)?;
-        svg::write_str(
-            &mut svg,
-            &mut buffer,
-            svg::TextItem {
-                x: Dimension::Percent(50.0),
-                y: (opt.font_size * 2) as f64,
-                text: "ERROR: No valid input provided to flamegraph".into(),
-                extra: None,
-            },
-        )?;
-        svg.write_event(Event::End(BytesEnd::new("svg")))?;
-        svg.write_event(Event::Eof)?;
+        for sink in sinks.iter_mut() {
+            if let OutputSink::Svg(writer) = sink {
+                write_error_svg(opt, writer, warnings_summary.as_deref())?;
+            }
+        }
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
             "No stack counts found",
         ));
+    };
+
+    let mut svgs = 0;
+    for sink in sinks.iter_mut() {
+        match sink {
+            OutputSink::Svg(_) => svgs += 1,
+            OutputSink::Json(writer) => output::write_json(&frames, writer)?,
+            OutputSink::Folded(writer) => output::write_folded(&frames, writer)?,
+        }
     }
+    if let Some(layout) = layout {
+        *layout = layout::Layout::new(&frames, sample_count_max.visual());
+    }
+    for sink in sinks.iter_mut() {
+        if let OutputSink::Svg(writer) = sink {
+            // Drawing prunes the frames, so only the last flame graph gets to have the originals.
+            svgs -= 1;
+            let frames = if svgs > 0 {
+                frames.clone()
+            } else {
+                std::mem::take(&mut frames)
+            };
+            write_svg(
+                opt,
+                writer,
+                frames,
+                sample_count_max,
+                delta_max.as_ref(),
+                trends.as_ref(),
+                warnings_summary.as_deref(),
+                time_window.map_or(0, |(start, _)| start),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// The weights of `Options::stack_weights`, followed by those of `Options::weights_file`.
+#[cfg(feature = "annotations")]
+fn stack_weights<'a>(opt: &'a Options<'_>) -> io::Result<Cow<'a, StackWeights>> {
+    let Some(path) = &opt.weights_file else {
+        return Ok(Cow::Borrowed(&opt.stack_weights));
+    };
+    let from_file = StackWeights::from_file(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Error reading stack weights from {}: {}", path.display(), e),
+        )
+    })?;
+    let mut weights = opt.stack_weights.clone();
+    weights.extend(from_file);
+    Ok(Cow::Owned(weights))
+}
+
+// Emit an error message SVG, for tools automating flamegraph use.
+fn write_error_svg(
+    opt: &Options<'_>,
+    writer: &mut dyn Write,
+    warnings_summary: Option<&str>,
+) -> io::Result<()> {
+    let mut buffer = StrStack::new();
+    let writer = SvgOutput::new(writer, opt.svgz)?;
+    let mut svg = if opt.pretty_xml {
+        Writer::new_with_indent(writer, b' ', 4)
+    } else {
+        Writer::new(writer)
+    };
+    let imageheight = opt.font_size * 5;
+    svg::write_header(&mut svg, imageheight, opt, warnings_summary)?;
+    svg::write_str(
+        &mut svg,
+        &mut buffer,
+        svg::TextItem {
+            x: Dimension::Percent(50.0),
+            y: (opt.font_size * 2) as f64,
+            text: "ERROR: No valid input provided to flamegraph".into(),
+            extra: None,
+        },
+    )?;
+    svg.write_event(Event::End(BytesEnd::new("svg")))?;
+    svg.write_event(Event::Eof)?;
+    svg.into_inner().finish()
+}
+
+#[allow(clippy::cognitive_complexity)]
+#[allow(clippy::too_many_arguments)]
+fn write_svg<CountType>(
+    opt: &mut Options<'_>,
+    writer: &mut dyn Write,
+    mut frames: Vec<merge::TimedFrame<'_, CountType>>,
+    sample_count_max: StackSampleCount<CountType>,
+    delta_max: Option<&merge::MaxAbsDelta>,
+    trends: Option<&trend::Trends<'_>>,
+    warnings_summary: Option<&str>,
+    time_offset: usize,
+) -> io::Result<()>
+where
+    CountType: CountTypeRequirements,
+    StackSampleCount<CountType>: StackSampleCountExt,
+    FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
+{
+    let mut buffer = StrStack::new();
+
+    // let's start writing the svg!
+    let writer = SvgOutput::new(writer, opt.svgz)?;
+    let mut svg = if opt.pretty_xml {
+        Writer::new_with_indent(writer, b' ', 4)
+    } else {
+        Writer::new(writer)
+    };
 
     let image_width = opt.image_width.unwrap_or(DEFAULT_IMAGE_WIDTH) as f64;
-    let sample_count_max = overall_total_sample_count.unwrap();
     let minwidth_time = opt.min_width;
+    let all_label = opt
+        .all_frame_stats
+        .then(|| all_frame_label(opt, &frames, sample_count_max.visual()));
+
+    // the samples that reach every depth, counting the frames that are pruned below
+    let mut depth_samples = Vec::new();
+    if opt.depth_histogram && opt.strip_depth.is_none() {
+        for frame in &frames {
+            let depth = frame.location.depth;
+            if depth_samples.len() <= depth {
+                depth_samples.resize(depth + 1, 0);
+            }
+            depth_samples[depth] += frame.end_time.visual() - frame.start_time.visual();
+        }
+    }
 
     // prune blocks that are too narrow
     let mut depthmax = 0;
-    frames.retain(|frame| {
-        if frame.visual_width(overall_total_sample_count.unwrap()) < minwidth_time {
-            false
-        } else {
-            depthmax = std::cmp::max(depthmax, frame.location.depth);
-            true
-        }
-    });
+    let aggregate_narrow_frames = opt.aggregate_narrow_frames && !opt.flame_chart;
+    if opt.aggregate_narrow_frames && opt.flame_chart {
+        warn!(
+            "Flame charts keep their frames in time order. \
+             The `aggregate_narrow_frames` option is being ignored."
+        );
+    }
+    if aggregate_narrow_frames {
+        frames = merge::aggregate_narrow_frames(frames, |frame| {
+            frame.visual_width(sample_count_max) >= minwidth_time
+        });
+        depthmax = frames.iter().map(|f| f.location.depth).max().unwrap_or(0);
+    } else {
+        frames.retain(|frame| {
+            if frame.visual_width(sample_count_max) < minwidth_time {
+                false
+            } else {
+                depthmax = std::cmp::max(depthmax, frame.location.depth);
+                true
+            }
+        });
+    }
+
+    // a flame strip only shows the bottom-most levels
+    if let Some(strip_depth) = opt.strip_depth {
+        frames.retain(|frame| frame.location.depth <= strip_depth);
+        depthmax = std::cmp::min(depthmax, strip_depth);
+    }
+
+    // the roots take the row of the `all` frame if it is left out
+    let omitted_rows = usize::from(opt.omit_root);
+    if opt.omit_root {
+        frames.retain(|frame| frame.location.depth > 0);
+    }
+
+    let diff_summary = if opt.diff_summary && !StackSampleCount::<CountType>::default().is_diff() {
+        warn!("The diff summary needs differential input and is being left out.");
+        false
+    } else {
+        opt.diff_summary && opt.strip_depth.is_none()
+    };
 
     // draw canvas, and embed interactive JavaScript program
-    let imageheight = ((depthmax + 1) * opt.frame_height) + opt.ypad1() + opt.ypad2();
-    svg::write_header(&mut svg, imageheight, opt)?;
+    let rows = (depthmax + 1).saturating_sub(omitted_rows);
+    let imageheight = (rows * opt.frame_height) + opt.ypad1() + opt.ypad2();
+    // The diff summary goes below everything else, which is laid out as if it weren't there.
+    let diff_summary_height = if diff_summary {
+        svg::diff_summary_height(opt)
+    } else {
+        0
+    };
+    svg::write_header(
+        &mut svg,
+        imageheight + diff_summary_height,
+        opt,
+        warnings_summary,
+    )?;
 
     let (bgcolor1, bgcolor2) = color::bgcolor_for(opt.bgcolors, opt.colors);
     let strokecolor = match opt.stroke_color {
@@ -629,11 +2058,6 @@ where
 
     svg::write_prelude(&mut svg, &style_options, opt)?;
 
-    // Used when picking color parameters at random, when no option determines how to pick these
-    // parameters. We instantiate it here because it may be called once for each iteration in the
-    // frames loop.
-    let mut thread_rng = rand::thread_rng();
-
     // structs to reuse across loops to avoid allocations
     let mut cache_g = Event::Start(BytesStart::new("g"));
     let mut cache_a = Event::Start(BytesStart::new("a"));
@@ -641,9 +2065,28 @@ where
     let cache_g_end = Event::End(BytesEnd::new("g"));
     let cache_a_end = Event::End(BytesEnd::new("a"));
 
+    // a flame strip links to the full graph as a whole
+    let strip_link = opt
+        .full_graph_link
+        .as_deref()
+        .filter(|_| opt.strip_depth.is_some());
+    if let Some(link) = strip_link {
+        svg.write_event(Event::Start(
+            BytesStart::new("a").with_attributes(vec![("href", link), ("target", "_top")]),
+        ))?;
+    }
+
     // create frames container
-    let container_x = format!("{}", XPAD);
-    let container_width = format!("{}", image_width as usize - XPAD - XPAD);
+    let container_x = format!("{}", opt.xpad);
+    let histogram_width = if depth_samples.is_empty() {
+        0
+    } else {
+        svg::DEPTH_HISTOGRAM_WIDTH
+    };
+    let container_width = format!(
+        "{}",
+        (image_width as usize).saturating_sub(2 * opt.xpad + histogram_width)
+    );
     svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
         ("id", "frames"),
         ("x", &container_x),
@@ -666,7 +2109,7 @@ where
         // let abs_delta_pct = (pct2 - pct1).abs();
         format!("{pct:.2}%")
     };
-    let get_count_and_pct_txt = |s, s_max, is_the_all_frame: bool| -> String {
+    let get_count_and_pct = |s, s_max, is_the_all_frame: bool| -> (String, String) {
         let samples = (s as f64 * opt.factor).round() as usize;
         // add thousands separators to `samples`
         let mut samples_txt_buffer = num_format::Buffer::default();
@@ -677,7 +2120,11 @@ where
         if is_the_all_frame && &pct_text == "100.00%" {
             pct_text = "100%".to_string()
         }
-        format!("{samples_txt} {}, {pct_text}", opt.count_name)
+        (format!("{samples_txt} {}", opt.count_name), pct_text)
+    };
+    let get_count_and_pct_txt = |s, s_max, is_the_all_frame: bool| -> String {
+        let (count_txt, pct_txt) = get_count_and_pct(s, s_max, is_the_all_frame);
+        format!("{count_txt}, {pct_txt}")
     };
     let get_delta_pct_txt = |pct: f64| -> String {
         let abs_pct = pct.abs();
@@ -691,22 +2138,37 @@ where
         // let abs_delta_pct = (pct2 - pct1).abs();
         format!("{sign_txt}{abs_pct:.2}%")
     };
-    for frame in frames {
-        let (x1_pct, x2_pct) = frame.visual_start_and_end_pct(overall_total_sample_count.unwrap());
-
-        let (y1, y2) = match opt.direction {
-            Direction::Straight => {
-                let y1 = imageheight - opt.ypad2() - (frame.location.depth + 1) * opt.frame_height
-                    + FRAMEPAD;
-                let y2 = imageheight - opt.ypad2() - frame.location.depth * opt.frame_height;
-                (y1, y2)
-            }
-            Direction::Inverted => {
-                let y1 = opt.ypad1() + frame.location.depth * opt.frame_height;
-                let y2 = opt.ypad1() + (frame.location.depth + 1) * opt.frame_height - FRAMEPAD;
-                (y1, y2)
-            }
-        };
+    let (direction, ypad1, ypad2, frame_height) =
+        (opt.direction, opt.ypad1(), opt.ypad2(), opt.frame_height);
+    let row_y = move |row: usize| match direction {
+        Direction::Straight => {
+            let y1 = imageheight - ypad2 - (row + 1) * frame_height + FRAMEPAD;
+            let y2 = imageheight - ypad2 - row * frame_height;
+            (y1, y2)
+        }
+        Direction::Inverted => {
+            let y1 = ypad1 + row * frame_height;
+            let y2 = ypad1 + (row + 1) * frame_height - FRAMEPAD;
+            (y1, y2)
+        }
+    };
+    let mut annotated_frames = Vec::new();
+    let mut diff_frames = Vec::new();
+    let parent_totals = if opt.show_pct_of_parent {
+        parent_totals(&frames)
+    } else {
+        vec![None; frames.len()]
+    };
+    let frame_trends = match trends {
+        Some(trends) => trends.of_frames(&frames),
+        None => vec![None; frames.len()],
+    };
+    for ((frame, parent_total), frame_trend) in
+        frames.into_iter().zip(parent_totals).zip(frame_trends)
+    {
+        let (x1_pct, x2_pct) = frame.visual_start_and_end_pct(sample_count_max);
+
+        let (y1, y2) = row_y(frame.location.depth - omitted_rows);
 
         let rect = Rectangle {
             x1_pct,
@@ -718,25 +2180,95 @@ where
         };
 
         let is_the_all_frame = frame.location.function.is_empty() && frame.location.depth == 0;
-        let function_name = if is_the_all_frame {
-            "all"
-        } else {
-            deannotate(frame.location.function)
+        let (deannotated, annotation) =
+            deannotate(frame.location.function, &opt.suffix_annotations);
+        let unescaped = merge::unescape_frame(deannotated);
+        let function_name: Cow<'_, str> = match annotation.and_then(|a| a.label.as_deref()) {
+            _ if is_the_all_frame => "all".into(),
+            Some(label) => format!("{} [{}]", unescaped, label).into(),
+            None => unescaped.clone(),
         };
+        if let (true, false, Some(counts), Some(overall_total_count)) = (
+            diff_summary,
+            is_the_all_frame,
+            frame.self_and_total_sample_counts.to_diff(),
+            sample_count_max.to_diff(),
+        ) {
+            let counts = if opt.include_children {
+                counts.total_count
+            } else {
+                counts.self_count
+            };
+            let mut delta_pct = if opt.normalize {
+                counts.delta_pct_pt(overall_total_count)
+            } else {
+                counts.delta_pct_pt_assuming_both_datasets_have_the_same_number_of_samples(
+                    overall_total_count.after,
+                )
+            };
+            if opt.negate_differentials {
+                delta_pct = -delta_pct;
+            }
+            diff_frames.push(svg::DiffFrame {
+                name: function_name.to_string(),
+                delta_pct,
+                x_samples: rect.x1_samples,
+                y: rect.y1,
+            });
+        }
+
+        let labeled = rect.width_pct() * image_width / 100.0 >= opt.min_frame_pixels;
+        let short_tooltip = opt.short_narrow_tooltips && !labeled && !is_the_all_frame;
         let info = match (
             frame.self_and_total_sample_counts.split(),
-            overall_total_sample_count.unwrap().split(),
+            sample_count_max.split(),
         ) {
+            _ if short_tooltip => write!(buffer, "{function_name}"),
             (
                 FrameSelfAndTotalCountsEnum::Single(frame_self_and_total_counts),
                 StackSampleCountEnum::Single(overall_total),
             ) => {
-                let samples_txt = get_count_and_pct_txt(
+                let (count_txt, mut pct_txt) = get_count_and_pct(
                     frame_self_and_total_counts.total_count,
                     overall_total,
                     is_the_all_frame,
                 );
-                write!(buffer, "{} ({})", function_name, samples_txt)
+                if let Some(parent_total) = parent_total.filter(|&total| total > 0) {
+                    let pct = 100.0 * frame_self_and_total_counts.total_count as f64
+                        / parent_total as f64;
+                    pct_txt = format!("{pct_txt}, {} of parent", get_pct_txt(pct));
+                }
+                // The summary leads with the primary metric, and the total follows if it isn't.
+                let (samples_txt, total_txt) = match opt.primary_metric {
+                    PrimaryMetric::Total => (format!("{count_txt}, {pct_txt}"), String::new()),
+                    _ if is_the_all_frame => (format!("{count_txt}, {pct_txt}"), String::new()),
+                    PrimaryMetric::SelfCount => {
+                        let self_txt = get_count_and_pct_txt(
+                            frame_self_and_total_counts.self_count,
+                            overall_total,
+                            false,
+                        );
+                        (
+                            format!("self: {self_txt}"),
+                            format!("\nTotal: {count_txt}, {pct_txt}"),
+                        )
+                    }
+                    PrimaryMetric::Percent => (pct_txt, format!("\nTotal: {count_txt}")),
+                };
+                if frame.clamped {
+                    write!(
+                        buffer,
+                        "{function_name} ({samples_txt}; self count clamped to zero){total_txt}"
+                    )
+                } else if opt.flame_chart && opt.show_occurrences && frame.occurrences > 1 {
+                    let occurrences = frame.occurrences;
+                    write!(
+                        buffer,
+                        "{function_name} ({samples_txt}, {occurrences} occurrences){total_txt}"
+                    )
+                } else {
+                    write!(buffer, "{} ({}){}", function_name, samples_txt, total_txt)
+                }
             }
             (
                 FrameSelfAndTotalCountsEnum::Diff(frame_self_and_total_counts),
@@ -870,24 +2402,119 @@ where
             }
             e => unreachable!("Invalid sample counts: {e:#?}"),
         };
+        let info = match weights_note(&frame.weights).filter(|_| !short_tooltip) {
+            Some(note) => {
+                let tooltip = format!("{}\n{}", &buffer[info], note);
+                write!(buffer, "{}", tooltip)
+            }
+            None => info,
+        };
+        let span = opt.flame_chart.then(|| {
+            (
+                scaled_time(rect.x1_samples + time_offset, opt.factor),
+                scaled_time(rect.x2_samples + time_offset, opt.factor),
+            )
+        });
+        let info = match frame_trend.filter(|_| !short_tooltip) {
+            Some(series) => {
+                let scaled = |count: usize| (count as f64 * opt.factor).round() as usize;
+                let tooltip = format!(
+                    "{}\nTrend: {} ({} → {} {})",
+                    &buffer[info],
+                    trend::sparkline(series),
+                    scaled(series.first().copied().unwrap_or(0)),
+                    scaled(series.last().copied().unwrap_or(0)),
+                    opt.count_name
+                );
+                write!(buffer, "{}", tooltip)
+            }
+            None => info,
+        };
+        let info = match span.filter(|_| !short_tooltip) {
+            Some((start, end)) => {
+                let tooltip = format!(
+                    "{}\nSpan: {}–{} {}",
+                    &buffer[info], start, end, opt.count_name
+                );
+                write!(buffer, "{}", tooltip)
+            }
+            None => info,
+        };
 
-        let (has_href, title) = write_container_start(
+        let hooked = match opt.frame_hook {
+            Some(ref mut hook) => {
+                let mut frame_info = FrameInfo {
+                    function: frame.location.function,
+                    depth: frame.location.depth,
+                    samples: frame.visual_samples(),
+                    name: deannotated.to_string(),
+                    tooltip: buffer[info].to_string(),
+                    color: None,
+                    href: None,
+                };
+                if !hook.call(&mut frame_info) {
+                    buffer.clear();
+                    continue;
+                }
+                Some(frame_info)
+            }
+            None => None,
+        };
+
+        let (has_href, title, badge) = write_container_start(
             opt,
             &mut svg,
             &mut cache_a,
             &mut cache_g,
             &frame,
-            &buffer[info],
+            hooked.as_ref().map_or(&buffer[info], |h| &h.tooltip),
+            hooked.as_ref().and_then(|h| h.href.as_deref()),
         )?;
 
-        svg.write_event(Event::Start(BytesStart::new("title")))?;
-        svg.write_event(Event::Text(BytesText::new(title)))?;
+        if short_tooltip {
+            svg.write_event(Event::Start(
+                BytesStart::new("title").with_attributes(iter::once(("fg:short", ""))),
+            ))?;
+        } else {
+            svg.write_event(Event::Start(BytesStart::new("title")))?;
+        }
+        svg.write_event(Event::Text(BytesText::new(&title)))?;
         svg.write_event(Event::End(BytesEnd::new("title")))?;
 
+        if opt.annotations_panel && !is_the_all_frame {
+            if let Some(detail) = annotation_detail(opt, frame.location.function) {
+                let name = hooked.as_ref().map_or(&*function_name, |h| h.name.as_str());
+                let mut text = match &badge {
+                    Some(badge) => format!("{} {}", badge, name),
+                    None => name.to_string(),
+                };
+                if !detail.is_empty() {
+                    text = format!("{}: {}", text, detail);
+                }
+                annotated_frames.push(svg::AnnotatedFrame {
+                    text,
+                    x_samples: rect.x1_samples,
+                    y: rect.y1,
+                });
+            }
+        }
+
         // select the color of the rectangle
-        let color = if frame.location.function == "--" {
+        let hooked_color = hooked.as_ref().and_then(|h| h.color);
+        let palette_color = hooked_color.is_none()
+            && !matches!(frame.location.function, "--" | "-")
+            && frame.location.function != merge::NARROW_FRAME
+            && frame.location.function != merge::OTHER_STACKS
+            && !opt.color_diffusion
+            && !frame.self_and_total_sample_counts.is_diff();
+        let color = if let Some(color) = hooked_color {
+            color
+        } else if frame.location.function == "--" {
             color::VDGREY
-        } else if frame.location.function == "-" {
+        } else if frame.location.function == "-"
+            || frame.location.function == merge::NARROW_FRAME
+            || frame.location.function == merge::OTHER_STACKS
+        {
             color::DGREY
         } else if opt.color_diffusion {
             // We want to visually highlight high priority regions for
@@ -895,11 +2522,35 @@ where
             // a frame that is 50% of width is high priority, so it seems wrong
             // to give it half the saturation of 100%. So we use sqrt to make
             // the red dropoff less linear.
-            color::color_scale((((x2_pct - x1_pct) / 100.0).sqrt() * 2000.0) as isize, 2000)
+            color::color_scale(
+                (((x2_pct - x1_pct) / 100.0).sqrt() * 2000.0) as isize,
+                2000,
+                opt.colors,
+            )
+        } else if let Some(function) = frame.location.function.strip_suffix(OFF_CPU_ANNOTATION) {
+            color::color(
+                opt.off_cpu_colors,
+                &opt.palette_ranges,
+                opt.hash,
+                opt.deterministic,
+                function,
+                || rand::next(&mut opt.random_source),
+            )
+        } else if let (true, Some(counts)) = (
+            opt.color_by_age,
+            frame.self_and_total_sample_counts.to_diff(),
+        ) {
+            let (mut older, mut newer) = (counts.total_count.before, counts.total_count.after);
+            if opt.negate_differentials {
+                std::mem::swap(&mut older, &mut newer);
+            }
+            match (older, newer) {
+                (0, n) if n > 0 => color::AGE_NEW,
+                (o, 0) if o > 0 => color::AGE_REMOVED,
+                _ => color::AGE_PERSISTENT,
+            }
         } else if frame.self_and_total_sample_counts.is_diff() {
-            let Some(overall_total_diff_counts) =
-                overall_total_sample_count.map(|x| x.to_diff().unwrap())
-            else {
+            let Some(overall_total_diff_counts) = sample_count_max.to_diff() else {
                 unreachable!("already confirmed is diff case");
             };
             let (mut delta, delta_max) = if opt.normalize {
@@ -911,9 +2562,9 @@ where
                     .unwrap();
 
                 let delta_max = if opt.include_children {
-                    delta_max.as_ref().unwrap().max_abs_total_delta_pct_pt
+                    delta_max.unwrap().max_abs_total_delta_pct_pt
                 } else {
-                    delta_max.as_ref().unwrap().max_abs_self_delta_pct_pt
+                    delta_max.unwrap().max_abs_self_delta_pct_pt
                 } / 100.0;
 
                 // Convert to integers for colour mapping purposes
@@ -926,9 +2577,9 @@ where
                     .delta(opt.include_children)
                     .unwrap();
                 let delta_max = if opt.include_children {
-                    delta_max.as_ref().unwrap().max_abs_total_delta
+                    delta_max.unwrap().max_abs_total_delta
                 } else {
-                    delta_max.as_ref().unwrap().max_abs_self_delta
+                    delta_max.unwrap().max_abs_self_delta
                 };
                 (delta, delta_max)
             };
@@ -941,31 +2592,94 @@ where
             if delta.unsigned_abs() > delta_max {
                 delta = delta_max as isize * delta.signum();
             }
-            color::color_scale(delta, delta_max)
+            color::color_scale(delta, delta_max, opt.colors)
+        } else if let Some(colors) = annotation.and_then(|a| a.colors) {
+            color::color(
+                colors,
+                &opt.palette_ranges,
+                opt.hash,
+                opt.deterministic,
+                deannotated,
+                || rand::next(&mut opt.random_source),
+            )
         } else if let Some(ref mut palette_map) = opt.palette_map {
             let colors = opt.colors;
             let hash = opt.hash;
             let deterministic = opt.deterministic;
+            let palette_ranges = &opt.palette_ranges;
+            let random_source = &mut opt.random_source;
             palette_map.find_color_for(frame.location.function, |name| {
-                color::color(colors, hash, deterministic, name, &mut thread_rng)
+                color::color(colors, palette_ranges, hash, deterministic, name, || {
+                    rand::next(random_source)
+                })
             })
         } else {
             color::color(
                 opt.colors,
+                &opt.palette_ranges,
                 opt.hash,
                 opt.deterministic,
                 frame.location.function,
-                &mut thread_rng,
+                || rand::next(&mut opt.random_source),
             )
         };
-        filled_rectangle(&mut svg, &mut buffer, &rect, color, &mut cache_rect)?;
+        let color = match opt.color_modifier {
+            Some(modifier) if palette_color => {
+                let (self_count, total_count) = match frame.self_and_total_sample_counts.split() {
+                    FrameSelfAndTotalCountsEnum::Single(counts) => {
+                        (counts.self_count, counts.total_count)
+                    }
+                    FrameSelfAndTotalCountsEnum::Diff(counts) => {
+                        (counts.self_count.visual, counts.total_count.visual)
+                    }
+                };
+                modifier.apply(color, self_count, total_count)
+            }
+            _ => color,
+        };
+        filled_rectangle(&mut svg, &mut buffer, &rect, color, span, &mut cache_rect)?;
+        if opt.self_time_shading {
+            let self_samples = match frame.self_and_total_sample_counts.split() {
+                FrameSelfAndTotalCountsEnum::Single(counts) => counts.self_count,
+                FrameSelfAndTotalCountsEnum::Diff(counts) => counts.self_count.visual,
+            };
+            self_time_shade(&mut svg, &mut buffer, &rect, self_samples, &mut cache_rect)?;
+        }
 
-        let fitchars = (rect.width_pct()
-            / (100.0 * opt.font_size as f64 * opt.font_width / image_width))
-            .trunc() as usize;
+        // frames too narrow for a label get no text element at all, not even an empty one
+        if !labeled {
+            buffer.clear();
+            if has_href {
+                svg.write_event(cache_a_end.borrow())?;
+            } else {
+                svg.write_event(cache_g_end.borrow())?;
+            }
+            continue;
+        }
+
+        let mut f = hooked.as_ref().map_or(&*unescaped, |h| &h.name);
+        // the label of the "all" frame is kept through zooming, rather than taken from its title
+        let label = all_label
+            .as_deref()
+            .filter(|_| is_the_all_frame && f.is_empty());
+        if let Some(label) = label {
+            f = label;
+        }
+        let fitchars_at = |font_size: usize| {
+            (rect.width_pct() / (100.0 * font_size as f64 * opt.font_width / image_width)).trunc()
+                as usize
+        };
+        let mut font_size = opt.font_size;
+        if opt.shrink_text_to_fit && f.len() >= fitchars_at(font_size) {
+            // the largest size at which the whole name fits, if there is one
+            font_size = (opt.min_font_size..opt.font_size)
+                .rev()
+                .find(|&size| f.len() < fitchars_at(size))
+                .unwrap_or_else(|| opt.min_font_size.min(opt.font_size));
+        }
+        let fitchars = fitchars_at(font_size);
         let text: svg::TextArgument<'_> = if fitchars >= 3 {
             // room for one char plus two dots
-            let f = deannotate(frame.location.function);
 
             // TODO: use Unicode grapheme clusters instead
             if f.len() < fitchars {
@@ -975,10 +2689,20 @@ where
                 // need to truncate :'(
                 use std::fmt::Write;
                 let mut w = buffer.writer();
-                for c in f.chars().take(fitchars - 2) {
+                let keep = fitchars - 2;
+                // the end of the name, after the dots, if it is kept too
+                let tail = match opt.text_truncate_direction {
+                    TextTruncateDirection::Middle => keep - keep / 2,
+                    _ => 0,
+                };
+                for c in f.chars().take(keep - tail) {
                     w.write_char(c).expect("writing to buffer shouldn't fail");
                 }
                 w.write_str("..").expect("writing to buffer shouldn't fail");
+                let skip = f.chars().count().saturating_sub(tail);
+                for c in f.chars().skip(skip) {
+                    w.write_char(c).expect("writing to buffer shouldn't fail");
+                }
                 w.finish().into()
             }
         } else {
@@ -987,6 +2711,16 @@ where
         };
 
         // write the text
+        let font_size_attr;
+        let extra = if font_size != opt.font_size {
+            font_size_attr = font_size.to_string();
+            Some(("font-size", font_size_attr.as_str()))
+        } else {
+            None
+        };
+        let extra = extra
+            .into_iter()
+            .chain(label.map(|label| ("fg:label", label)));
         svg::write_str(
             &mut svg,
             &mut buffer,
@@ -994,10 +2728,24 @@ where
                 x: Dimension::Percent(rect.x1_pct + 100.0 * 3.0 / image_width),
                 y: 3.0 + (rect.y1 + rect.y2) as f64 / 2.0,
                 text,
-                extra: None,
+                extra,
             },
         )?;
 
+        // write the annotation badge, if any, at the right end of the frame
+        if let Some(badge) = badge {
+            svg::write_str(
+                &mut svg,
+                &mut buffer,
+                svg::TextItem {
+                    x: Dimension::Percent(rect.x2_pct - 100.0 * 3.0 / image_width),
+                    y: 3.0 + (rect.y1 + rect.y2) as f64 / 2.0,
+                    text: badge.into(),
+                    extra: args!("class" => "badge", "text-anchor" => "end"),
+                },
+            )?;
+        }
+
         buffer.clear();
         if has_href {
             svg.write_event(cache_a_end.borrow())?;
@@ -1007,75 +2755,254 @@ where
     }
 
     svg.write_event(Event::End(BytesEnd::new("svg")))?;
+    if !depth_samples.is_empty() {
+        let rows: Vec<_> = (omitted_rows..=depthmax)
+            .map(|depth| {
+                let (y1, y2) = row_y(depth - omitted_rows);
+                let samples = depth_samples.get(depth).copied().unwrap_or(0);
+                let share = samples as f64 / sample_count_max.visual().max(1) as f64;
+                svg::DepthRow {
+                    depth,
+                    y1,
+                    y2,
+                    share,
+                }
+            })
+            .collect();
+        svg::write_depth_histogram(&mut svg, &style_options, opt, &rows)?;
+    }
+    if opt.annotations_panel && opt.strip_depth.is_none() {
+        svg::write_annotations_panel(&mut svg, &style_options, opt, annotated_frames)?;
+    }
+    if opt.strip_depth.is_none() {
+        svg::write_footer(&mut svg, &style_options, opt)?;
+    }
+    if diff_summary {
+        svg::write_diff_summary(&mut svg, &style_options, opt, imageheight, diff_frames)?;
+    }
+    if strip_link.is_some() {
+        svg.write_event(Event::End(BytesEnd::new("a")))?;
+    }
     svg.write_event(Event::End(BytesEnd::new("svg")))?;
     svg.write_event(Event::Eof)?;
 
-    svg.into_inner().flush()?;
-    Ok(())
+    svg.into_inner().finish()
 }
 
 #[cfg(feature = "nameattr")]
-fn write_container_start<'a, W: Write, CountType>(
-    opt: &'a Options<'a>,
+fn write_container_start<'a, 'e, W: Write, CountType>(
+    opt: &'a Options<'_>,
     svg: &mut Writer<W>,
-    cache_a: &mut Event<'_>,
-    cache_g: &mut Event<'_>,
+    cache_a: &mut Event<'e>,
+    cache_g: &mut Event<'e>,
     frame: &merge::TimedFrame<'_, CountType>,
-    mut title: &'a str,
-) -> io::Result<(bool, &'a str)> {
+    title: &'a str,
+    href: Option<&str>,
+) -> io::Result<(bool, Cow<'a, str>, Option<String>)> {
     let frame_attributes = opt
         .func_frameattrs
         .frameattrs_for_func(frame.location.function);
+    let annotation = func_annotation(opt, frame.location.function);
+    let extra_class = annotation.as_ref().and_then(|a| a.class.as_deref());
 
+    let mut title = Cow::Borrowed(title);
     let mut has_href = false;
     if let Some(frame_attributes) = frame_attributes {
-        if frame_attributes.attrs.contains_key("xlink:href") {
-            write_container_attributes(cache_a, frame_attributes);
+        if href.is_some() || frame_attributes.attrs.contains_key("xlink:href") {
+            write_container_attributes(cache_a, frame_attributes, extra_class, href);
             svg.write_event(cache_a.borrow())?;
             has_href = true;
         } else {
-            write_container_attributes(cache_g, frame_attributes);
+            write_container_attributes(cache_g, frame_attributes, extra_class, None);
             svg.write_event(cache_g.borrow())?;
         }
         if let Some(ref t) = frame_attributes.title {
-            title = t.as_str();
+            title = Cow::Borrowed(t.as_str());
         }
-    } else if let Event::Start(ref mut c) = cache_g {
-        c.clear_attributes();
-        svg.write_event(cache_g.borrow())?;
+    } else {
+        let cache = if href.is_some() { cache_a } else { cache_g };
+        if let Event::Start(ref mut c) = cache {
+            c.clear_attributes();
+            if let Some(class) = extra_class {
+                c.push_attribute(("class", class));
+            }
+            if let Some(href) = href {
+                c.push_attribute(("xlink:href", href));
+            }
+        }
+        svg.write_event(cache.borrow())?;
+        has_href = href.is_some();
     }
 
-    Ok((has_href, title))
+    let mut badge = None;
+    if let Some(annotation) = annotation {
+        if let Some(tooltip) = annotation.tooltip {
+            title = Cow::Owned(format!("{}\n{}", title, tooltip));
+        }
+        badge = annotation.badge;
+    }
+
+    Ok((has_href, title, badge))
 }
 
 #[cfg(not(feature = "nameattr"))]
-fn write_container_start<'a, W: Write>(
-    _opt: &Options<'_>,
+fn write_container_start<'a, 'e, W: Write, CountType>(
+    opt: &Options<'_>,
     svg: &mut Writer<W>,
-    _cache_a: &mut Event<'_>,
-    cache_g: &mut Event<'_>,
-    _frame: &merge::TimedFrame<'_>,
+    cache_a: &mut Event<'e>,
+    cache_g: &mut Event<'e>,
+    frame: &merge::TimedFrame<'_, CountType>,
     title: &'a str,
-) -> io::Result<(bool, &'a str)> {
-    if let Event::Start(ref mut c) = cache_g {
+    href: Option<&str>,
+) -> io::Result<(bool, Cow<'a, str>, Option<String>)> {
+    let annotation = func_annotation(opt, frame.location.function);
+    let cache = if href.is_some() { cache_a } else { cache_g };
+    if let Event::Start(ref mut c) = cache {
         c.clear_attributes();
-        svg.write_event(cache_g.borrow())?;
+        if let Some(class) = annotation.as_ref().and_then(|a| a.class.as_deref()) {
+            c.push_attribute(("class", class));
+        }
+        if let Some(href) = href {
+            c.push_attribute(("xlink:href", href));
+        }
+        svg.write_event(cache.borrow())?;
+    }
+
+    let mut title = Cow::Borrowed(title);
+    let mut badge = None;
+    if let Some(annotation) = annotation {
+        if let Some(tooltip) = annotation.tooltip {
+            title = Cow::Owned(format!("{}\n{}", title, tooltip));
+        }
+        badge = annotation.badge;
+    }
+
+    Ok((href.is_some(), title, badge))
+}
+
+// The combined annotation of the frames of a function from `Options::func_annotations`.
+#[cfg(feature = "annotations")]
+fn func_annotation(opt: &Options<'_>, function: &str) -> Option<annotations::FrameAnnotation> {
+    if opt.func_annotations.is_empty() {
+        None
+    } else {
+        opt.func_annotations.annotation_for_func(function)
+    }
+}
+
+#[cfg(not(feature = "annotations"))]
+fn func_annotation(_opt: &Options<'_>, _function: &str) -> Option<annotations::FrameAnnotation> {
+    None
+}
+
+// Notes the stack weights applied to the counts of a frame, as in `Partly weighted ×0.5, ×2`.
+fn weights_note(weights: &merge::AppliedWeights) -> Option<String> {
+    if weights.factors.is_empty() {
+        return None;
     }
+    let factors: Vec<String> = weights.factors.iter().map(|f| format!("×{}", f)).collect();
+    let weighted = if weights.partial {
+        "Partly weighted"
+    } else {
+        "Weighted"
+    };
+    Some(format!("{} {}", weighted, factors.join(", ")))
+}
+
+// Labels the "all" frame with the total samples, the number of distinct stacks, and the time
+// they cover if the count name is a unit of time, as in `all (1,500 ms, 12 stacks, 1.50 s)`.
+fn all_frame_label<CountType>(
+    opt: &Options<'_>,
+    frames: &[merge::TimedFrame<'_, CountType>],
+    total: usize,
+) -> String {
+    let samples = (total as f64 * opt.factor).round() as usize;
+    let mut samples_txt = num_format::Buffer::default();
+    let _ = samples_txt.write_formatted(&samples, &Locale::en);
+    // every stack ends in the one frame it has to itself
+    let stacks = frames.iter().filter(|frame| frame.occurrences > 0).count();
+    let stacks_txt = if stacks == 1 { "stack" } else { "stacks" };
+    let label = format!(
+        "all ({} {}, {} {}",
+        samples_txt.as_str(),
+        opt.count_name,
+        stacks,
+        stacks_txt
+    );
+
+    let unit_seconds = match opt.count_name.trim().to_ascii_lowercase().as_str() {
+        "ns" | "nanosecond" | "nanoseconds" => 1e-9,
+        "us" | "µs" | "μs" | "microsecond" | "microseconds" => 1e-6,
+        "ms" | "millisecond" | "milliseconds" => 1e-3,
+        "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+        "min" | "mins" | "minute" | "minutes" => 60.0,
+        "h" | "hour" | "hours" => 3600.0,
+        _ => return label + ")",
+    };
+    const UNITS: [(f64, &str); 6] = [
+        (3600.0, "h"),
+        (60.0, "min"),
+        (1.0, "s"),
+        (1e-3, "ms"),
+        (1e-6, "µs"),
+        (1e-9, "ns"),
+    ];
+    let seconds = total as f64 * opt.factor * unit_seconds;
+    let (scale, unit) = UNITS
+        .into_iter()
+        .find(|&(scale, _)| seconds >= scale)
+        .unwrap_or(UNITS[UNITS.len() - 1]);
+    format!("{}, {:.2} {})", label, seconds / scale, unit)
+}
 
-    Ok((false, title))
+// Describes a frame in the annotations panel by its tooltip annotations, or else its title
+// attribute, or returns `None` if the frame has neither annotations nor attributes.
+fn annotation_detail(opt: &Options<'_>, function: &str) -> Option<String> {
+    let annotation = func_annotation(opt, function);
+    #[cfg(feature = "nameattr")]
+    let title = opt
+        .func_frameattrs
+        .frameattrs_for_func(function)
+        .map(|a| a.title.clone());
+    #[cfg(not(feature = "nameattr"))]
+    let title: Option<Option<String>> = None;
+    if annotation.is_none() && title.is_none() {
+        return None;
+    }
+    let detail = annotation.and_then(|a| a.tooltip).or(title.flatten());
+    Some(detail.map_or_else(String::new, |d| d.replace('\n', "; ")))
 }
 
 /// Writes attributes to the container, container could be g or a
+///
+/// `extra_class` is appended to any `class` attribute the frame attributes already set, and
+/// `href` replaces any link they set.
 #[cfg(feature = "nameattr")]
-fn write_container_attributes(event: &mut Event<'_>, frame_attributes: &FrameAttrs) {
+fn write_container_attributes(
+    event: &mut Event<'_>,
+    frame_attributes: &FrameAttrs,
+    extra_class: Option<&str>,
+    href: Option<&str>,
+) {
     if let Event::Start(ref mut c) = event {
         c.clear_attributes();
         c.extend_attributes(
             frame_attributes
                 .attrs
                 .iter()
+                .filter(|(k, _)| extra_class.is_none() || k.as_str() != "class")
+                .filter(|(k, _)| href.is_none() || k.as_str() != "xlink:href")
                 .map(|(k, v)| (k.as_str(), v.as_str())),
         );
+        if let Some(href) = href {
+            c.push_attribute(("xlink:href", href));
+        }
+        if let Some(extra_class) = extra_class {
+            match frame_attributes.attrs.get("class") {
+                Some(class) => c.push_attribute(("class", &*format!("{} {}", class, extra_class))),
+                None => c.push_attribute(("class", extra_class)),
+            }
+        }
     } else {
         unreachable!("cache wrapper was of wrong type: {:?}", event);
     }
@@ -1106,47 +3033,286 @@ where
     W: Write,
 {
     let mut input = String::new();
-    for mut reader in readers {
-        reader.read_to_string(&mut input)?;
+    for reader in readers {
+        read_input(opt, reader, &mut input)?;
     }
 
-    if is_diff_case(&input) {
+    if opt.time_windows < 2 && is_diff_case(input.lines()) {
         from_lines::<_, _, DiffCount>(opt, input.lines(), writer)
     } else {
         from_lines::<_, _, usize>(opt, input.lines(), writer)
     }
 }
 
-fn is_diff_case(input: &str) -> bool {
-    for mut line in tidy_lines(input.lines()) {
-        let mut skip_warning = true;
-        let found2 = StackSampleCount::<DiffCount>::parse_from_line(
-            &mut line,
-            &mut skip_warning,
-            FrameWidthSource::After,
-        )
-        .is_some();
-        if found2 {
-            return true;
-        }
-        let found1 = StackSampleCount::<usize>::parse_from_line(
-            &mut line,
-            &mut skip_warning,
-            FrameWidthSource::After,
-        )
-        .is_some();
-        if found1 {
-            return false;
+// Reads folded stack lines, which may be in a folded file whose metadata then fills in `opt`.
+#[cfg(feature = "zstd")]
+fn read_input<R: Read>(opt: &mut Options<'_>, mut reader: R, input: &mut String) -> io::Result<()> {
+    let mut start = Vec::with_capacity(4);
+    reader.by_ref().take(4).read_to_end(&mut start)?;
+    let mut reader = (&start[..]).chain(reader);
+    if crate::folded::is_folded(&start) {
+        let mut folded = crate::folded::Reader::new(reader)?;
+        folded.metadata().apply_to(opt);
+        folded.read_to_string(input)?;
+    } else {
+        reader.read_to_string(input)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "zstd"))]
+fn read_input<R: Read>(_: &mut Options<'_>, mut reader: R, input: &mut String) -> io::Result<()> {
+    reader.read_to_string(input).map(|_| ())
+}
+
+/// Produce a hot/cold flame graph from readers that contain on-CPU and off-CPU folded stacks.
+///
+/// The two sets of stacks are drawn in a single flame graph whose width is their combined
+/// count, so the off-CPU counts should be in (or be scaled with [`Options::off_cpu_factor`] to)
+/// the same unit as the on-CPU counts. How they are arranged is determined by
+/// [`Options::hot_cold_layout`], and off-CPU frames are colored with
+/// [`Options::off_cpu_colors`]. Differential input is not supported.
+pub fn from_hot_cold_readers<R1, R2, W>(
+    opt: &mut Options<'_>,
+    on_cpu: R1,
+    off_cpu: R2,
+    writer: W,
+) -> io::Result<()>
+where
+    R1: IntoIterator,
+    R1::Item: Read,
+    R2: IntoIterator,
+    R2::Item: Read,
+    W: Write,
+{
+    let mut on_cpu_input = String::new();
+    for mut reader in on_cpu {
+        reader.read_to_string(&mut on_cpu_input)?;
+    }
+    let mut off_cpu_input = String::new();
+    for mut reader in off_cpu {
+        reader.read_to_string(&mut off_cpu_input)?;
+    }
+
+    let side_by_side = opt.hot_cold_layout == HotColdLayout::SideBySide;
+    // When interleaving, the frames that off-CPU stacks share with on-CPU stacks are merged, and
+    // only the rest of each off-CPU stack is drawn as off-CPU.
+    let mut on_cpu_paths = HashSet::new();
+    let mut input = String::with_capacity(on_cpu_input.len() + 2 * off_cpu_input.len());
+    for line in tidy_lines(on_cpu_input.lines()) {
+        if side_by_side {
+            input.push_str("on-CPU;");
+        } else if let Some((samplesi, _)) = merge::rfind_samples(line) {
+            let stack = line[..samplesi].trim_end();
+            let mut end = 0;
+            for frame in merge::split_frames(stack) {
+                end += frame.len();
+                on_cpu_paths.insert(&stack[..end]);
+                end += 1;
+            }
+        }
+        input.push_str(line);
+        input.push('\n');
+    }
+    for line in tidy_lines(off_cpu_input.lines()) {
+        let Some((samplesi, _)) = merge::rfind_samples(line) else {
+            // leave it to `from_lines` to warn about
+            input.push_str(line);
+            input.push('\n');
+            continue;
+        };
+        let count: f64 = line[samplesi..].parse().unwrap_or_default();
+        let count = (count * opt.off_cpu_factor).round() as usize;
+        if count == 0 {
+            continue;
+        }
+
+        if side_by_side {
+            input.push_str("off-CPU");
+            input.push_str(OFF_CPU_ANNOTATION);
+            input.push(';');
+        }
+        let stack = line[..samplesi].trim_end();
+        let mut shared = !side_by_side;
+        let mut end = 0;
+        for (i, frame) in merge::split_frames(stack).enumerate() {
+            if i > 0 {
+                input.push(';');
+                end += 1;
+            }
+            input.push_str(frame);
+            end += frame.len();
+            shared = shared && on_cpu_paths.contains(&stack[..end]);
+            if !shared {
+                input.push_str(OFF_CPU_ANNOTATION);
+            }
+        }
+        input.push(' ');
+        input.push_str(&count.to_string());
+        input.push('\n');
+    }
+
+    from_lines::<_, _, usize>(opt, input.lines(), writer)
+}
+
+/// Produce a hot/cold flame graph from files that contain on-CPU and off-CPU folded stacks
+/// and write the result to provided `writer`.
+///
+/// If `on_cpu` is empty, or is `-`, STDIN will be used for the on-CPU stacks. See
+/// [`from_hot_cold_readers`] for details.
+pub fn from_hot_cold_files<W: Write>(
+    opt: &mut Options<'_>,
+    on_cpu: &[PathBuf],
+    off_cpu: &[PathBuf],
+    writer: W,
+) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut on_cpu_readers: Vec<Box<dyn Read>> = Vec::with_capacity(on_cpu.len());
+    if on_cpu.is_empty() || on_cpu.len() == 1 && on_cpu[0].to_str() == Some("-") {
+        on_cpu_readers.push(Box::new(BufReader::with_capacity(128 * 1024, stdin.lock())));
+    } else {
+        for infile in on_cpu {
+            on_cpu_readers.push(Box::new(File::open(infile)?));
+        }
+    }
+    let mut off_cpu_readers = Vec::with_capacity(off_cpu.len());
+    for infile in off_cpu {
+        off_cpu_readers.push(File::open(infile)?);
+    }
+
+    from_hot_cold_readers(opt, on_cpu_readers, off_cpu_readers, writer)
+}
+
+// Reads the lines of every file, or of STDIN if there are none, into the stacks of a band named
+// after the file, for `Options::stacked_inputs`.
+fn read_stacked_inputs(opt: &mut Options<'_>, files: &[PathBuf]) -> io::Result<String> {
+    let stdin = [PathBuf::from("-")];
+    let files = if files.is_empty() { &stdin[..] } else { files };
+    let mut inputs = Vec::with_capacity(files.len());
+    for file in files {
+        let mut input = String::new();
+        let band = if file.to_str() == Some("-") {
+            read_input(opt, io::stdin().lock(), &mut input)?;
+            "stdin".into()
+        } else {
+            read_input(opt, File::open(file)?, &mut input)?;
+            file.file_name()
+                .unwrap_or(file.as_os_str())
+                .to_string_lossy()
+                .replace(';', ":")
+        };
+        inputs.push((band, input));
+    }
+
+    let count = |line: &str| {
+        let (samplesi, _) = merge::rfind_samples(line)?;
+        line[samplesi..].parse::<f64>().ok()
+    };
+    let totals: Vec<f64> = inputs
+        .iter()
+        .map(|(_, input)| {
+            tidy_lines(input.lines())
+                .into_iter()
+                .filter_map(count)
+                .sum()
+        })
+        .collect();
+    let max_total = totals.iter().copied().fold(0.0, f64::max);
+
+    let mut stacked = String::with_capacity(inputs.iter().map(|(_, input)| input.len()).sum());
+    for ((band, input), total) in inputs.iter().zip(totals) {
+        for line in tidy_lines(input.lines()) {
+            let Some((samplesi, _)) = merge::rfind_samples(line) else {
+                // leave it to `from_lines` to warn about
+                stacked.push_str(line);
+                stacked.push('\n');
+                continue;
+            };
+            stacked.push_str(band);
+            stacked.push(';');
+            match opt.stacked_scale {
+                StackedScale::Independent if total > 0.0 => {
+                    let count = count(line).unwrap_or_default() * max_total / total;
+                    stacked.push_str(line[..samplesi].trim_end());
+                    stacked.push(' ');
+                    stacked.push_str(&(count.round() as usize).to_string());
+                }
+                _ => stacked.push_str(line),
+            }
+            stacked.push('\n');
+        }
+    }
+    Ok(stacked)
+}
+
+// Whether the input is differential, going by its first line with sample counts.
+//
+// The lines that have a different number of sample counts than the first are reported, since
+// they are either misread (as with one count too many) or ignored (unless
+// `Options::missing_count_as_zero` is set).
+fn is_diff_case<'a>(lines: impl IntoIterator<Item = &'a str>) -> bool {
+    let mut diff = None;
+    let mut mismatched = 0;
+    let mut first_mismatched = Vec::new();
+    for (i, line) in lines.into_iter().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("# ") {
+            continue;
+        }
+        let Some(found2) = crate::folded::Line::is_differential(line) else {
+            continue;
+        };
+        match diff {
+            None => diff = Some(found2),
+            Some(diff) if diff != found2 => {
+                mismatched += 1;
+                if first_mismatched.len() < merge::REPORTED_IGNORED_LINES {
+                    first_mismatched.push((i + 1).to_string());
+                }
+            }
+            Some(_) => {}
         }
     }
-    return false;
+
+    let diff = diff.unwrap_or(false);
+    if mismatched != 0 {
+        warn!(
+            "{} lines have {} sample counts where the first line has {} (lines {}{})",
+            mismatched,
+            if diff { "one" } else { "two" },
+            if diff { "two" } else { "one" },
+            first_mismatched.join(", "),
+            if mismatched > first_mismatched.len() {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+    }
+    diff
 }
 
 /// Produce a flame graph from files that contain folded stack lines
 /// and write the result to provided `writer`.
 ///
 /// If files is empty, STDIN will be used as input.
+///
+/// With the `mmap` feature, regular files are memory-mapped rather than read into memory, which
+/// halves the peak memory use for very large inputs. The files must not be changed while the
+/// flame graph is made from them.
 pub fn from_files<W: Write>(opt: &mut Options<'_>, files: &[PathBuf], writer: W) -> io::Result<()> {
+    if opt.stacked_inputs {
+        let input = read_stacked_inputs(opt, files)?;
+        return from_lines::<_, _, usize>(opt, input.lines(), writer);
+    }
+
+    #[cfg(feature = "mmap")]
+    if !files.is_empty() && files.iter().all(|file| file.to_str() != Some("-")) {
+        if let Some(maps) = map_files(files)? {
+            return from_maps(opt, &maps, writer);
+        }
+    }
+
     if files.is_empty() || files.len() == 1 && files[0].to_str() == Some("-") {
         let stdin = io::stdin();
         let r = BufReader::with_capacity(128 * 1024, stdin.lock());
@@ -1175,15 +3341,103 @@ pub fn from_files<W: Write>(opt: &mut Options<'_>, files: &[PathBuf], writer: W)
     }
 }
 
-fn deannotate(f: &str) -> &str {
-    if f.ends_with(']') {
-        if let Some(ai) = f.rfind("_[") {
-            if f[ai..].len() == 4 && "kwij".contains(&f[ai + 2..ai + 3]) {
-                return &f[..ai];
-            }
+// Memory-maps the given files, or returns `None` if any of them can't be used as they are, such as
+// pipes and folded files, which have to be read instead.
+#[cfg(feature = "mmap")]
+fn map_files(files: &[PathBuf]) -> io::Result<Option<Vec<memmap2::Mmap>>> {
+    let mut maps = Vec::with_capacity(files.len());
+    for file in files {
+        let file = File::open(file)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        if metadata.len() == 0 {
+            continue;
+        }
+        // SAFETY: the map is only read from, and stays valid for as long as it lives. The
+        // documentation of `from_files` requires that the file isn't changed in the meantime.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        #[cfg(feature = "zstd")]
+        if crate::folded::is_folded(&map[..map.len().min(4)]) {
+            return Ok(None);
         }
+        maps.push(map);
+    }
+    Ok(Some(maps))
+}
+
+// Makes a flame graph from the lines of memory-mapped files, in place.
+#[cfg(feature = "mmap")]
+fn from_maps<W: Write>(opt: &mut Options<'_>, maps: &[memmap2::Mmap], writer: W) -> io::Result<()> {
+    let inputs = maps
+        .iter()
+        .map(|map| {
+            std::str::from_utf8(map).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                )
+            })
+        })
+        .collect::<io::Result<Vec<&str>>>()?;
+    let lines = || inputs.iter().flat_map(|input| input.lines());
+
+    if opt.time_windows < 2 && is_diff_case(lines()) {
+        from_lines::<_, _, DiffCount>(opt, lines(), writer)
+    } else {
+        from_lines::<_, _, usize>(opt, lines(), writer)
+    }
+}
+
+// Summarize the input problems worked around by `merge::frames` for embedding in the notes.
+//
+// Ignored lines are located in the original input by the `(address, length, line number)` span of
+// the input line that contains them, which works because they are sub-slices of the input lines.
+// The spans are searched one by one rather than by address, as the lines of separate inputs, such
+// as memory-mapped files, aren't in address order. Lines that were rewritten (as with
+// `reverse_stack_order`) can't be located, and are left out of the list of line numbers.
+fn warnings_summary(ignored: &merge::Ignored<'_>, line_spans: &[(usize, usize, usize)]) -> String {
+    let mut line_numbers: Vec<usize> = ignored
+        .first_lines
+        .iter()
+        .filter_map(|line| {
+            let addr = line.as_ptr() as usize;
+            line_spans
+                .iter()
+                .find(|&&(start, len, _)| start <= addr && addr + line.len() <= start + len)
+                .map(|&(_, _, line_number)| line_number)
+        })
+        .collect();
+    line_numbers.sort_unstable();
+
+    let mut summary = format!("[inferno warnings: ignored_lines={}", ignored.count);
+    if !line_numbers.is_empty() {
+        summary.push_str(" first_ignored=");
+        let line_numbers: Vec<String> = line_numbers.iter().map(usize::to_string).collect();
+        summary.push_str(&line_numbers.join(","));
     }
-    f
+    summary.push_str(&format!(
+        " truncated_fractional_samples={}",
+        ignored.stripped_fractional_samples
+    ));
+    if ignored.clamped_stacks != 0 {
+        summary.push_str(&format!(" clamped_stacks={}", ignored.clamped_stacks));
+    }
+    summary.push(']');
+    summary
+}
+
+// Strips the annotation off a frame name, returning the bare name and the annotation (if any).
+fn deannotate<'a, 'b>(
+    f: &'a str,
+    annotations: &'b [SuffixAnnotation],
+) -> (&'a str, Option<&'b SuffixAnnotation>) {
+    let f = f.strip_suffix(OFF_CPU_ANNOTATION).unwrap_or(f);
+    annotations
+        .iter()
+        .find_map(|a| Some((f.strip_suffix(a.suffix.as_str())?, Some(a))))
+        .unwrap_or((f, None))
 }
 
 fn filled_rectangle<W: Write>(
@@ -1191,6 +3445,7 @@ fn filled_rectangle<W: Write>(
     buffer: &mut StrStack,
     rect: &Rectangle,
     color: Color,
+    span: Option<(f64, f64)>,
     cache_rect: &mut Event<'_>,
 ) -> io::Result<()> {
     let x = write!(buffer, "{:.4}%", rect.x1_pct);
@@ -1213,6 +3468,142 @@ fn filled_rectangle<W: Write>(
             "fg:x" => &buffer[x_samples],
             "fg:w" => &buffer[width_samples]
         ));
+        if let Some((start, end)) = span {
+            let start = write!(buffer, "{}", start);
+            let end = write!(buffer, "{}", end);
+            bytes_start.extend_attributes(args!(
+                "data-start" => &buffer[start],
+                "data-end" => &buffer[end]
+            ));
+        }
+    } else {
+        unreachable!("cache wrapper was of wrong type: {:?}", cache_rect);
+    }
+    svg.write_event(cache_rect.borrow())
+}
+
+// Scales a sample count by the factor, rounding away the noise that fractional factors such as
+// `0.001` leave.
+fn scaled_time(samples: usize, factor: f64) -> f64 {
+    (samples as f64 * factor * 1e6).round() / 1e6
+}
+
+// The samples of a flame chart from the start of `Options::time_range` up to its end.
+fn time_window(opt: &Options<'_>) -> Option<(usize, usize)> {
+    let (from, to) = opt.time_range?;
+    let sample = |time: f64| (time / opt.factor).round().max(0.0) as usize;
+    Some((sample(from), sample(to)))
+}
+
+// Cuts the lines of a flame chart, in time order, to the samples from `start` up to `end`. Lines
+// that are outside of them are left out, and those that are partly in them keep the samples that
+// are.
+fn clip_to_time_window<'a>(lines: &[&'a str], (start, end): (usize, usize)) -> Vec<Cow<'a, str>> {
+    let mut clipped = Vec::new();
+    let mut time = 0;
+    for &line in lines {
+        let line = line.trim();
+        let Some((samplesi, doti)) = merge::rfind_samples(line) else {
+            // Lines without a count are left for merging to ignore.
+            clipped.push(Cow::Borrowed(line));
+            continue;
+        };
+        let samples = line[samplesi..samplesi + doti].parse().unwrap_or(0);
+        let (line_start, line_end) = (time, time + samples);
+        time = line_end;
+        let (from, to) = (line_start.max(start), line_end.min(end));
+        if from >= to {
+            continue;
+        }
+        if (from, to) == (line_start, line_end) {
+            clipped.push(Cow::Borrowed(line));
+        } else {
+            clipped.push(Cow::Owned(format!(
+                "{} {}",
+                line[..samplesi].trim_end(),
+                to - from
+            )));
+        }
+    }
+    clipped
+}
+
+// Finds the total sample count of the parent of every frame, if it has one.
+//
+// Frames come in no useful order, so they are visited by where they start, parents before their
+// children, keeping the frames that are open at the start of each one on a stack.
+fn parent_totals<CountType>(frames: &[merge::TimedFrame<'_, CountType>]) -> Vec<Option<usize>>
+where
+    StackSampleCount<CountType>: StackSampleCountExt,
+    FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
+{
+    let mut order: Vec<usize> = (0..frames.len()).collect();
+    order.sort_by_key(|&i| (frames[i].start_time.visual(), frames[i].location.depth));
+
+    let mut totals = vec![None; frames.len()];
+    let mut open: Vec<usize> = Vec::new();
+    for i in order {
+        let frame = &frames[i];
+        while let Some(&top) = open.last() {
+            let parent = &frames[top];
+            if parent.location.depth < frame.location.depth
+                && parent.end_time.visual() > frame.start_time.visual()
+            {
+                break;
+            }
+            open.pop();
+        }
+        if let Some(&top) = open.last() {
+            let parent = &frames[top];
+            if parent.location.depth + 1 == frame.location.depth {
+                if let FrameSelfAndTotalCountsEnum::Single(counts) =
+                    parent.self_and_total_sample_counts.split()
+                {
+                    totals[i] = Some(counts.total_count);
+                }
+            }
+        }
+        open.push(i);
+    }
+    totals
+}
+
+// Shades the bottom quarter of a frame for its self samples, in a rect that zooms like frames do.
+fn self_time_shade<W: Write>(
+    svg: &mut Writer<W>,
+    buffer: &mut StrStack,
+    rect: &Rectangle,
+    self_samples: usize,
+    cache_rect: &mut Event<'_>,
+) -> io::Result<()> {
+    let width_samples = rect.x2_samples - rect.x1_samples;
+    let self_samples = self_samples.min(width_samples);
+    if self_samples == 0 {
+        return Ok(());
+    }
+    let height = (rect.height() / 4).max(1);
+    let x = write!(buffer, "{:.4}%", rect.x1_pct);
+    let y = write_usize(buffer, rect.y2 - height);
+    let width = write!(
+        buffer,
+        "{:.4}%",
+        rect.width_pct() * self_samples as f64 / width_samples as f64
+    );
+    let height = write_usize(buffer, height);
+    let x_samples = write_usize(buffer, rect.x1_samples);
+    let self_samples = write_usize(buffer, self_samples);
+
+    if let Event::Empty(bytes_start) = cache_rect {
+        bytes_start.clear_attributes();
+        bytes_start.extend_attributes(args!(
+            "class" => "self",
+            "x" => &buffer[x],
+            "y" => &buffer[y],
+            "width" => &buffer[width],
+            "height" => &buffer[height],
+            "fg:x" => &buffer[x_samples],
+            "fg:w" => &buffer[self_samples]
+        ));
     } else {
         unreachable!("cache wrapper was of wrong type: {:?}", cache_rect);
     }
@@ -1225,7 +3616,14 @@ fn write_usize(buffer: &mut StrStack, value: usize) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use super::{Direction, Options};
+    use super::{Direction, Options, SCRIPT, SCRIPT_VERSION};
+
+    // The built-in script checks for the major version it was made for.
+    #[test]
+    fn script_knows_its_major_version() {
+        let major = SCRIPT_VERSION.split('.').next().unwrap();
+        assert!(SCRIPT.contains(&format!(r#"scriptversion.split(".")[0] != "{}""#, major)));
+    }
 
     // If there's a subtitle, we need to adjust the top height:
     #[test]
@@ -1256,4 +3654,155 @@ mod tests {
         assert!(inverted.ypad1() > regular.ypad1());
         assert!(inverted.ypad2() < regular.ypad2());
     }
+
+    // A compact header takes up a single row, whatever the direction or subtitle.
+    #[test]
+    fn compact_header_ypadding() {
+        let regular = Options::default();
+        for direction in [Direction::Straight, Direction::Inverted] {
+            let compact = Options {
+                compact_header: true,
+                subtitle: Some(String::from("hello!")),
+                direction,
+                ..Default::default()
+            };
+            assert!(compact.ypad1() < regular.ypad1());
+            assert!(compact.ypad1() + compact.ypad2() < regular.ypad1() + regular.ypad2());
+        }
+    }
+
+    #[test]
+    fn warnings_summary_of_separate_inputs() {
+        let first = String::from("main;a 1\nbad line");
+        let second = String::from("oops\nmain;b 2");
+        // The second input comes first in memory as often as not.
+        let lines: Vec<&str> = first.lines().chain(second.lines()).collect();
+        let spans: Vec<_> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| (line.as_ptr() as usize, line.len(), i + 1))
+            .collect();
+        let ignored = super::merge::Ignored {
+            count: 2,
+            first_lines: vec![&lines[2][..2], lines[1]],
+            stripped_fractional_samples: false,
+            clamped_stacks: 0,
+        };
+        assert_eq!(
+            super::warnings_summary(&ignored, &spans),
+            "[inferno warnings: ignored_lines=2 first_ignored=2,3 truncated_fractional_samples=false]"
+        );
+    }
+
+    #[test]
+    fn lines_sort_by_stacks() {
+        let mut lines = [
+            "main;parse;lex 3",
+            "main;parse 2",
+            "main 4",
+            "main;parse 1",
+            "main;parse::lex 2",
+            "main;parse 3",
+        ];
+        super::merge::sort_lines(&mut lines);
+        assert_eq!(
+            lines,
+            [
+                "main 4",
+                "main;parse 2",
+                "main;parse 1",
+                "main;parse 3",
+                "main;parse::lex 2",
+                "main;parse;lex 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn split_escaped_frames() {
+        let stack = r"main;<[u8\; 8] as Debug>::fmt;\;;write";
+        let frames: Vec<_> = super::merge::split_frames(stack).collect();
+        assert_eq!(
+            frames,
+            ["main", r"<[u8\; 8] as Debug>::fmt", r"\;", "write"]
+        );
+        let reversed: Vec<_> = super::merge::split_frames(stack).rev().collect();
+        assert_eq!(
+            reversed,
+            ["write", r"\;", r"<[u8\; 8] as Debug>::fmt", "main"]
+        );
+        assert_eq!(super::merge::split_frames("").collect::<Vec<_>>(), [""]);
+        assert_eq!(
+            super::merge::unescape_frame(frames[1]),
+            "<[u8; 8] as Debug>::fmt"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn options_from_toml() {
+        use super::color::{BackgroundColor, Palette};
+        use std::str::FromStr;
+
+        let options = Options::from_toml_str(
+            r##"
+            colors = "java"
+            bgcolors = "#102030"
+            title = "House Style"
+            font_type = "Helvetica"
+            min_width = 0.5
+            direction = "inverted"
+            width_sizing = "80%"
+            "##,
+        )
+        .unwrap();
+
+        let expected = Options {
+            colors: Palette::from_str("java").unwrap(),
+            bgcolors: Some(BackgroundColor::from_str("#102030").unwrap()),
+            title: "House Style".to_string(),
+            font_type: "Helvetica".to_string(),
+            min_width: 0.5,
+            direction: Direction::Inverted,
+            width_sizing: Some(super::WidthSizing::Percent(80.0)),
+            ..Default::default()
+        };
+        assert_eq!(options, expected);
+
+        let in_table = Options::from_toml_str(
+            r##"
+            [flamegraph]
+            title = "House Style"
+            min_width = 0.5
+
+            [collapse-perf]
+            all = true
+            "##,
+        )
+        .unwrap();
+        assert_eq!(in_table.title, "House Style");
+        assert_eq!(in_table.min_width, 0.5);
+
+        assert!(Options::from_toml_str("colours = \"java\"").is_err());
+        assert!(Options::from_toml_str("flamegraph = \"java\"").is_err());
+        assert!(Options::from_toml_str("colors = \"plaid\"").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn options_toml_round_trip() {
+        let options = Options::from_toml_str(
+            r##"
+            colors = "rust"
+            uicolor = "#aabbcc"
+            stroke_color = "#000000"
+            search_color = "#203040"
+            palette_ranges = ["hot=#0050a0..#40c0ff"]
+            frame_width_source = "all-samples"
+            "##,
+        )
+        .unwrap();
+        let serialized = toml::to_string(&options).unwrap();
+        assert_eq!(Options::from_toml_str(&serialized).unwrap(), options);
+    }
 }
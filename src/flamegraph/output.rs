@@ -0,0 +1,227 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use super::merge::{
+    FrameSelfAndTotalCounts, FrameSelfAndTotalCountsEnum, FrameSelfAndTotalCountsExt,
+    StackSampleCount, StackSampleCountEnum, StackSampleCountExt, TimedFrame,
+};
+
+/// A destination for one of the outputs of [`render`](super::render).
+#[non_exhaustive]
+pub enum OutputSink<'a> {
+    /// The flame graph, in SVG format, exactly as [`from_lines`](super::from_lines) draws it.
+    Svg(&'a mut dyn Write),
+
+    /// The merged stacks as a tree of frames in JSON, in the format used by [d3-flame-graph].
+    ///
+    /// Every frame is an object with the `name` of its function, its `value` (the number of
+    /// samples in it and its children), its `self` samples and its `children`. The root frame is
+    /// named `all`. For differential input, the counts are those of the second profile, and
+    /// every frame also has the `delta` of its `value` from the first. Frames are not pruned by
+    /// [`Options::min_width`](super::Options::min_width) or the like.
+    ///
+    /// [d3-flame-graph]: https://github.com/spiermar/d3-flame-graph
+    Json(&'a mut dyn Write),
+
+    /// The merged stacks as folded stack lines, after the transformations of the input asked for
+    /// in the [`Options`](super::Options), such as reversing stacks or cutting them at a base
+    /// function. Identical stacks are combined into one line.
+    Folded(&'a mut dyn Write),
+}
+
+impl fmt::Debug for OutputSink<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSink::Svg(_) => f.write_str("Svg(..)"),
+            OutputSink::Json(_) => f.write_str("Json(..)"),
+            OutputSink::Folded(_) => f.write_str("Folded(..)"),
+        }
+    }
+}
+
+struct Node<'a> {
+    function: &'a str,
+    counts: FrameSelfAndTotalCountsEnum,
+    children: Vec<Node<'a>>,
+}
+
+// Rebuild the tree of frames from the merged frames, which come in no useful order.
+//
+// Frames are ordered by where they start, parents before their children. Zero-width frames make
+// that ambiguous, so the position of a frame counts the samples of both profiles of differential
+// input, and frames without any samples are left out.
+fn tree<'a, CountType>(frames: &[TimedFrame<'a, CountType>]) -> Option<Node<'a>>
+where
+    StackSampleCount<CountType>: StackSampleCountExt,
+    FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
+{
+    let position = |count: &StackSampleCount<CountType>| match count.split() {
+        StackSampleCountEnum::Single(count) => count,
+        StackSampleCountEnum::Diff(count) => count.before + count.after,
+    };
+    let mut frames: Vec<_> = frames
+        .iter()
+        .filter(|frame| position(&frame.end_time) > position(&frame.start_time))
+        .collect();
+    frames.sort_by_key(|frame| (position(&frame.start_time), frame.location.depth));
+
+    let mut stack: Vec<Node<'a>> = Vec::new();
+    for frame in frames {
+        while stack.len() > frame.location.depth {
+            close(&mut stack);
+        }
+        stack.push(Node {
+            function: frame.location.function,
+            counts: frame.self_and_total_sample_counts.split(),
+            children: Vec::new(),
+        });
+    }
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    stack.pop()
+}
+
+fn close(stack: &mut Vec<Node<'_>>) {
+    let node = stack.pop().expect("closing a frame of an empty stack");
+    if let Some(parent) = stack.last_mut() {
+        parent.children.push(node);
+    }
+}
+
+pub(super) fn write_json<CountType>(
+    frames: &[TimedFrame<'_, CountType>],
+    writer: &mut dyn Write,
+) -> io::Result<()>
+where
+    StackSampleCount<CountType>: StackSampleCountExt,
+    FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
+{
+    if let Some(root) = tree(frames) {
+        write_json_node(&root, writer)?;
+    }
+    writeln!(writer)?;
+    writer.flush()
+}
+
+fn write_json_node(node: &Node<'_>, writer: &mut dyn Write) -> io::Result<()> {
+    let name = if node.function.is_empty() {
+        "all"
+    } else {
+        node.function
+    };
+    write!(writer, "{{\"name\":")?;
+    write_json_string(writer, name)?;
+    match node.counts {
+        FrameSelfAndTotalCountsEnum::Single(counts) => write!(
+            writer,
+            ",\"value\":{},\"self\":{}",
+            counts.total_count, counts.self_count
+        )?,
+        FrameSelfAndTotalCountsEnum::Diff(counts) => write!(
+            writer,
+            ",\"value\":{},\"self\":{},\"delta\":{}",
+            counts.total_count.after,
+            counts.self_count.after,
+            counts.total_count.after as isize - counts.total_count.before as isize
+        )?,
+    }
+    write!(writer, ",\"children\":[")?;
+    for (i, child) in node.children.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        write_json_node(child, writer)?;
+    }
+    write!(writer, "]}}")
+}
+
+// Writes `s` as a JSON string, escaping it the way serde_json does.
+pub(super) fn write_json_string(writer: &mut dyn Write, s: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '\u{8}' => "\\b",
+            '\u{c}' => "\\f",
+            c if c < ' ' => "",
+            _ => continue,
+        };
+        writer.write_all(&s.as_bytes()[start..i])?;
+        if escaped.is_empty() {
+            write!(writer, "\\u{:04x}", c as u32)?;
+        } else {
+            writer.write_all(escaped.as_bytes())?;
+        }
+        start = i + c.len_utf8();
+    }
+    writer.write_all(&s.as_bytes()[start..])?;
+    writer.write_all(b"\"")
+}
+
+pub(super) fn write_folded<CountType>(
+    frames: &[TimedFrame<'_, CountType>],
+    writer: &mut dyn Write,
+) -> io::Result<()>
+where
+    StackSampleCount<CountType>: StackSampleCountExt,
+    FrameSelfAndTotalCounts<CountType>: FrameSelfAndTotalCountsExt,
+{
+    if let Some(root) = tree(frames) {
+        let mut stack = Vec::new();
+        for child in &root.children {
+            write_folded_node(child, &mut stack, writer)?;
+        }
+    }
+    writer.flush()
+}
+
+fn write_folded_node<'a>(
+    node: &Node<'a>,
+    stack: &mut Vec<&'a str>,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    stack.push(node.function);
+    match node.counts {
+        FrameSelfAndTotalCountsEnum::Single(counts) if counts.self_count > 0 => {
+            writeln!(writer, "{} {}", stack.join(";"), counts.self_count)?;
+        }
+        FrameSelfAndTotalCountsEnum::Diff(counts)
+            if counts.self_count.before > 0 || counts.self_count.after > 0 =>
+        {
+            writeln!(
+                writer,
+                "{} {} {}",
+                stack.join(";"),
+                counts.self_count.before,
+                counts.self_count.after
+            )?;
+        }
+        _ => {}
+    }
+    for child in &node.children {
+        write_folded_node(child, stack, writer)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_string_escapes() {
+        let mut json = Vec::new();
+        write_json_string(&mut json, "a\"b\\c\nd\u{1}é\u{7f}").unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "\"a\\\"b\\\\c\\nd\\u0001é\u{7f}\""
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! Patches that update a flame graph in place, for flame graphs that are redrawn as their profile
+//! changes.
+//!
+//! Redrawing a live flame graph, such as one that follows a running profiler, by reloading its
+//! SVG is slow for large profiles, and loses what the viewer zoomed into or searched for. A
+//! [`Snapshot`] holds the frames of a flame graph as [`Snapshot::render`] drew them, and
+//! [`Snapshot::patch`] works out the frames that changed from one snapshot to the next, as a small
+//! JSON message that the script of the flame graph applies to the frames it shows: frames that
+//! were resized, recolored or relabeled are replaced, new frames are added and gone frames are
+//! removed, and zooms, searches and filters are put back on the patched frames.
+//!
+//! Flame graphs drawn with [`Options::live_updates`] set subscribe to a stream of
+//! [server-sent events] at that URL, and apply the patches that the stream sends, or reload when
+//! sent [`RELOAD`].
+//!
+//! ```
+//! use inferno::flamegraph::{patch::Snapshot, Options};
+//!
+//! let mut options = Options::default();
+//! let mut svg = Vec::new();
+//! let before = Snapshot::render(&mut options, ["main;parse 50", "main;eval 50"], &mut svg).unwrap();
+//! let after = Snapshot::render(&mut options, ["main;parse 80", "main;gc 20"], &mut svg).unwrap();
+//! let patch = before.patch(&after).unwrap();
+//! assert_eq!(patch.removed(), ["all;main;eval"]);
+//! ```
+//!
+//!   [server-sent events]: https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::json;
+
+use super::{render, Direction, Options, OutputSink};
+
+/// The message that tells a flame graph with live updates to reload, for changes that a
+/// [`Patch`] can't make.
+pub const RELOAD: &str = r#"{"reload":true}"#;
+
+/// The frames of a flame graph as it was drawn, to work out [`Patch`]es from.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    // The flame graph without its frames, which patches leave as it is.
+    outside: String,
+    inverted: bool,
+    total_samples: usize,
+    frames: Vec<Frame>,
+}
+
+// A drawn frame, keyed by the names of its stack as the script of the flame graph keys it.
+#[derive(Clone, Debug)]
+struct Frame {
+    key: String,
+    // The frame without what depends on the width of all frames, such as its position and its
+    // label, which the script works out again once it is patched.
+    fingerprint: String,
+    svg: String,
+}
+
+/// The changes to the frames of a flame graph from one [`Snapshot`] to the next.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patch {
+    total_samples: usize,
+    previous_total_samples: usize,
+    frames: Vec<(String, String)>,
+    removed: Vec<String>,
+}
+
+impl Snapshot {
+    /// Draw a flame graph of `lines` to `writer` as [`from_lines`](super::from_lines) would, and
+    /// take a snapshot of its frames.
+    ///
+    /// Flame graphs compressed with [`Options::svgz`] can't be patched, and are refused. Unless
+    /// [`Options::deterministic`] or [`Options::hash`] is set, frames get new colors every time
+    /// they are drawn, and so every frame changes from one snapshot to the next.
+    pub fn render<'a, I, W>(opt: &mut Options<'_>, lines: I, mut writer: W) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+        W: Write,
+    {
+        if opt.svgz {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "compressed flame graphs can't be patched",
+            ));
+        }
+        let mut svg = Vec::new();
+        render(opt, lines, &mut [OutputSink::Svg(&mut svg)])?;
+        writer.write_all(&svg)?;
+        let svg =
+            String::from_utf8(svg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Snapshot::parse(&svg, opt.direction == Direction::Inverted)
+    }
+
+    /// The changes that turn the frames of this snapshot into those of `next`.
+    ///
+    /// Returns `None` if anything but the frames changed, such as the height of the flame graph
+    /// or its title, in which case it has to be reloaded.
+    pub fn patch(&self, next: &Snapshot) -> Option<Patch> {
+        if self.outside != next.outside || self.inverted != next.inverted {
+            return None;
+        }
+        let previous: HashMap<&str, &Frame> = self
+            .frames
+            .iter()
+            .map(|frame| (frame.key.as_str(), frame))
+            .collect();
+        let current: HashSet<&str> = next.frames.iter().map(|frame| frame.key.as_str()).collect();
+        let frames = next
+            .frames
+            .iter()
+            .filter(|frame| {
+                previous
+                    .get(frame.key.as_str())
+                    .map_or(true, |previous| previous.fingerprint != frame.fingerprint)
+            })
+            .map(|frame| (frame.key.clone(), frame.svg.clone()))
+            .collect();
+        let removed = self
+            .frames
+            .iter()
+            .filter(|frame| !current.contains(frame.key.as_str()))
+            .map(|frame| frame.key.clone())
+            .collect();
+        Some(Patch {
+            total_samples: next.total_samples,
+            previous_total_samples: self.total_samples,
+            frames,
+            removed,
+        })
+    }
+
+    fn parse(svg: &str, inverted: bool) -> io::Result<Self> {
+        let mut reader = Reader::from_str(svg);
+        loop {
+            let start = reader.buffer_position() as usize;
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(e) if attribute(&e, b"id")?.as_deref() == Some("frames") => {
+                    let total_samples = attribute(&e, b"total_samples")?
+                        .and_then(|total| total.parse().ok())
+                        .unwrap_or(0);
+                    let frames = read_frames(&mut reader, svg, inverted)?;
+                    let end = reader.buffer_position() as usize;
+                    return Ok(Snapshot {
+                        outside: format!("{}{}", &svg[..start], &svg[end..]),
+                        inverted,
+                        total_samples,
+                        frames,
+                    });
+                }
+                Event::Eof => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "flame graph has no frames",
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Patch {
+    /// Whether the patch leaves the frames as they are.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+            && self.removed.is_empty()
+            && self.total_samples == self.previous_total_samples
+    }
+
+    /// The keys of the frames that the patch adds or replaces, parents first.
+    pub fn changed(&self) -> impl Iterator<Item = &str> {
+        self.frames.iter().map(|(key, _)| key.as_str())
+    }
+
+    /// The keys of the frames that the patch removes.
+    ///
+    /// Frames are keyed by the functions of their stack, root first and separated by `;`, with
+    /// `#` and a count for the frames of a stack that comes up more than once, as in flame charts.
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+
+    /// The patch as the JSON message that flame graphs with live updates apply.
+    pub fn to_json(&self) -> String {
+        let frames: Vec<_> = self
+            .frames
+            .iter()
+            .map(|(key, svg)| json!({ "key": key, "svg": svg }))
+            .collect();
+        json!({
+            "total_samples": self.total_samples,
+            "frames": frames,
+            "removed": self.removed,
+        })
+        .to_string()
+    }
+}
+
+// A frame as it is read, before it is keyed.
+struct DrawnFrame {
+    name: String,
+    y: String,
+    x: usize,
+    fingerprint: String,
+    svg: String,
+}
+
+// Reads the frames of the frames container up to its end, and keys them.
+fn read_frames(reader: &mut Reader<&[u8]>, svg: &str, inverted: bool) -> io::Result<Vec<Frame>> {
+    let mut drawn = Vec::new();
+    loop {
+        let start = reader.buffer_position() as usize;
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(e) => {
+                let mut frame = read_frame(reader, &e)?;
+                frame.svg = svg[start..reader.buffer_position() as usize].to_string();
+                drawn.push(frame);
+            }
+            Event::End(_) | Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(key_frames(drawn, inverted))
+}
+
+// Reads a frame up to its end, from its opening tag.
+fn read_frame(reader: &mut Reader<&[u8]>, open: &BytesStart<'_>) -> io::Result<DrawnFrame> {
+    let mut frame = DrawnFrame {
+        name: String::new(),
+        y: String::new(),
+        x: 0,
+        fingerprint: String::new(),
+        svg: String::new(),
+    };
+    let mut title = None;
+    let mut rect = false;
+    let mut depth = 1;
+    // The depth of the label being skipped, if any.
+    let mut label = None;
+    let mut in_title = false;
+    push_tag(&mut frame.fingerprint, open, false);
+    while depth > 0 {
+        let event = reader.read_event().map_err(xml_error)?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let empty = matches!(event, Event::Empty(_));
+                if !empty {
+                    depth += 1;
+                }
+                let is_label = e.name().as_ref() == b"text"
+                    && !attribute(e, b"class")?.is_some_and(|class| class.contains("badge"));
+                if is_label && label.is_none() && !empty {
+                    label = Some(depth);
+                }
+                if label.is_some() || is_label {
+                    continue;
+                }
+                match e.name().as_ref() {
+                    b"title" => in_title = title.is_none(),
+                    b"rect" if !rect => {
+                        rect = true;
+                        frame.y = attribute(e, b"y")?.unwrap_or_default();
+                        frame.x = attribute(e, b"fg:x")?
+                            .and_then(|x| x.parse().ok())
+                            .unwrap_or(0);
+                    }
+                    _ => {}
+                }
+                push_tag(&mut frame.fingerprint, e, empty);
+            }
+            Event::End(e) => {
+                if label == Some(depth) {
+                    label = None;
+                } else if label.is_none() {
+                    if e.name().as_ref() == b"title" && in_title {
+                        in_title = false;
+                        title.get_or_insert_with(String::new);
+                    }
+                    frame.fingerprint.push_str("</");
+                    frame
+                        .fingerprint
+                        .push_str(&String::from_utf8_lossy(e.name().as_ref()));
+                    frame.fingerprint.push('>');
+                }
+                depth -= 1;
+            }
+            Event::Text(e) if label.is_none() => {
+                let text = e.unescape().map_err(xml_error)?;
+                if in_title && title.is_none() {
+                    title = Some(text.to_string());
+                }
+                frame.fingerprint.push_str(&text);
+            }
+            Event::Eof => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "flame graph ends within a frame",
+                ))
+            }
+            _ => {}
+        }
+    }
+    frame.name = frame_name(title.as_deref().unwrap_or_default()).to_string();
+    Ok(frame)
+}
+
+// Adds a tag to the fingerprint of a frame, without the attributes that place it horizontally.
+fn push_tag(fingerprint: &mut String, e: &BytesStart<'_>, empty: bool) {
+    fingerprint.push('<');
+    fingerprint.push_str(&String::from_utf8_lossy(e.name().as_ref()));
+    for attribute in e.attributes().flatten() {
+        if matches!(attribute.key.as_ref(), b"x" | b"width") {
+            continue;
+        }
+        fingerprint.push(' ');
+        fingerprint.push_str(&String::from_utf8_lossy(attribute.key.as_ref()));
+        fingerprint.push_str("=\"");
+        fingerprint.push_str(&String::from_utf8_lossy(&attribute.value));
+        fingerprint.push('"');
+    }
+    fingerprint.push_str(if empty { "/>" } else { ">" });
+}
+
+// The function of a frame, from its tooltip, as `g_to_name` of the script reads it.
+fn frame_name(title: &str) -> &str {
+    let line = title.split('\n').next().unwrap_or_default();
+    line.rfind(" (").map_or(line, |i| &line[..i])
+}
+
+// Keys frames by their stacks the way `frame_keys` of the script does: row by row from the root,
+// where the parent of a frame is the last frame of the row nearer the root that starts no later.
+fn key_frames(drawn: Vec<DrawnFrame>, inverted: bool) -> Vec<Frame> {
+    let mut rows: Vec<(f64, Vec<DrawnFrame>)> = Vec::new();
+    let mut row_of_y = HashMap::new();
+    for frame in drawn {
+        let row = *row_of_y.entry(frame.y.clone()).or_insert_with(|| {
+            rows.push((frame.y.parse().unwrap_or(0.0), Vec::new()));
+            rows.len() - 1
+        });
+        rows[row].1.push(frame);
+    }
+    rows.sort_by(|(a, _), (b, _)| {
+        if inverted {
+            a.total_cmp(b)
+        } else {
+            b.total_cmp(a)
+        }
+    });
+
+    let mut keys = HashSet::new();
+    let mut frames = Vec::new();
+    // The starts and keys of the frames of the row nearer the root.
+    let mut below: Vec<(usize, String)> = Vec::new();
+    for (_, mut row) in rows {
+        row.sort_by_key(|frame| frame.x);
+        let mut keyed = Vec::with_capacity(row.len());
+        let mut parent = 0;
+        for frame in row {
+            while parent < below.len() && below[parent].0 <= frame.x {
+                parent += 1;
+            }
+            let key = match parent {
+                0 => frame.name,
+                _ => format!("{};{}", below[parent - 1].1, frame.name),
+            };
+            let mut unique = key.clone();
+            let mut n = 0;
+            while keys.contains(&unique) {
+                n += 1;
+                unique = format!("{}#{}", key, n);
+            }
+            keys.insert(unique.clone());
+            keyed.push((frame.x, unique.clone()));
+            frames.push(Frame {
+                key: unique,
+                fingerprint: frame.fingerprint,
+                svg: frame.svg,
+            });
+        }
+        below = keyed;
+    }
+    frames
+}
+
+fn attribute(e: &BytesStart<'_>, name: &[u8]) -> io::Result<Option<String>> {
+    match e.try_get_attribute(name).map_err(xml_error)? {
+        Some(attribute) => Ok(Some(
+            attribute.unescape_value().map_err(xml_error)?.into_owned(),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn xml_error(e: impl Into<quick_xml::Error>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.into())
+}
@@ -1,5 +1,7 @@
 use std::cell::RefCell;
+use std::fmt;
 
+#[derive(Debug)]
 pub(super) struct XorShift64 {
     a: u64,
 }
@@ -42,8 +44,67 @@ fn sample(value: u64) -> f64 {
     scale * (value as f64)
 }
 
-pub(super) fn thread_rng() -> impl Fn() -> f32 {
-    || RNG.with(|rng| rng.borrow_mut().next_f64() as f32)
+/// A source of the random numbers used to pick the colors of frames when no option decides them.
+///
+/// By default, colors are picked with a generator that is shared by all the flame graphs made on
+/// the same thread, so a flame graph without [`Options::hash`](super::Options::hash) or
+/// [`Options::deterministic`](super::Options::deterministic) may come out differently every time
+/// it is made. Giving each flame graph its own source, such as a [`SeededRandom`], makes the
+/// output the same on every run, for example to compare it byte for byte in snapshot tests.
+/// Flame graphs don't embed any timestamps, so this is the only thing that varies between runs.
+pub trait RandomSource {
+    /// Return a number in the range `[0, 1)`.
+    fn next_f32(&mut self) -> f32;
+}
+
+impl fmt::Debug for dyn RandomSource + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RandomSource(..)")
+    }
+}
+
+// Sources can't be compared, so two sources are only equal if they are the same source.
+impl PartialEq for dyn RandomSource + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            self as *const _ as *const u8,
+            other as *const _ as *const u8,
+        )
+    }
+}
+
+/// A [`RandomSource`] that always produces the same numbers for the same seed.
+///
+/// ```
+/// use inferno::flamegraph::{Options, SeededRandom};
+///
+/// let mut random = SeededRandom::new(42);
+/// let mut options = Options::default();
+/// options.random_source = Some(&mut random);
+/// ```
+#[derive(Debug)]
+pub struct SeededRandom(XorShift64);
+
+impl SeededRandom {
+    /// Create a source seeded with `seed`. A seed of `0` is treated as `1`, since the generator
+    /// would otherwise only produce zeros.
+    pub fn new(seed: u64) -> Self {
+        SeededRandom(XorShift64::new(seed.max(1)))
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_f32(&mut self) -> f32 {
+        self.0.next_f64() as f32
+    }
+}
+
+// Returns the next number of `source`, or of the thread's generator if there's none.
+pub(super) fn next(source: &mut Option<&mut dyn RandomSource>) -> f32 {
+    match source {
+        Some(source) => source.next_f32(),
+        None => RNG.with(|rng| rng.borrow_mut().next_f64() as f32),
+    }
 }
 
 #[test]
@@ -0,0 +1,803 @@
+//! Plain text reports of the hottest functions in a profile.
+//!
+//! Sometimes a flame graph is more than you need, as when posting the effect of a change in a pull
+//! request comment. A report lists the functions with the most samples of their own (self) and
+//! the most samples under them (total) in two tables, formatted as aligned plain text or as
+//! Markdown, from the same folded stack lines a flame graph is made from. For differential input
+//! (two sample counts per line), functions are ranked by the second count, and the tables also
+//! show how each count changed from the first.
+//!
+//! ```
+//! use inferno::flamegraph::report::{self, Options, ReportFormat};
+//!
+//! let mut options = Options::default();
+//! options.format = ReportFormat::Markdown;
+//! options.top = 5;
+//!
+//! let lines = ["main;parse;lex 30", "main;parse 10", "main;eval 60"];
+//! let mut out = Vec::new();
+//! report::from_lines(&options, lines, &mut out).unwrap();
+//! let out = String::from_utf8(out).unwrap();
+//! assert!(out.contains("| 1 | 60 | 60.00% | 60.00% | `eval` |"));
+//! ```
+//!
+//! For continuous integration, [`gate_from_lines`] checks differential input for functions whose
+//! share of all samples grew by more than a threshold, and writes a machine-readable verdict as
+//! JSON or OpenMetrics, so that a pipeline can fail a build on a performance regression.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use log::warn;
+use num_format::{Buffer, Locale};
+
+use super::merge::{
+    split_frames, unescape_frame, DiffCount, StackSampleCount, StackSampleCountExt,
+};
+use super::output::write_json_string;
+use super::{is_diff_case, tidy_lines, FrameWidthSource};
+
+/// Default values for [`Options`].
+pub mod defaults {
+    /// The number of functions in each table of a report.
+    pub const TOP: usize = 10;
+
+    /// The width at which function names are cut short.
+    pub const NAME_WIDTH: usize = 80;
+
+    /// The growth of a function's share of all samples, in percentage points, above which a
+    /// gate fails.
+    pub const FAIL_THRESHOLD_PCT: f64 = 1.0;
+}
+
+/// The format of a report.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum ReportFormat {
+    /// Columns aligned with spaces, for terminals and log files.
+    #[default]
+    Text,
+    /// Markdown tables, for pull request comments and the like.
+    Markdown,
+}
+
+/// Configure the report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Options {
+    /// The number of functions in each table.
+    ///
+    /// [Default value](defaults::TOP).
+    pub top: usize,
+
+    /// The format of the report.
+    ///
+    /// Defaults to [`ReportFormat::Text`].
+    pub format: ReportFormat,
+
+    /// Function names longer than this many characters are cut short, keeping their end, so
+    /// that reports of profiles with long (say, C++ template) names stay within a comment's
+    /// size limits.
+    ///
+    /// [Default value](defaults::NAME_WIDTH).
+    pub name_width: usize,
+
+    /// Instead of the tables of functions by self and total samples, list the functions with the
+    /// most samples of their own along with the functions that called them, up to this many
+    /// frames deep, like the default view of `perf report`. With `1`, that's just the table by
+    /// self samples.
+    ///
+    /// Callers are listed under the function they called, indented and marked with `←`, with
+    /// the samples of the function that they account for.
+    ///
+    /// Defaults to `None`.
+    pub bottom_up: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            top: defaults::TOP,
+            format: Default::default(),
+            name_width: defaults::NAME_WIDTH,
+            bottom_up: None,
+        }
+    }
+}
+
+/// The format of the verdict of a gate.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum GateFormat {
+    /// A JSON object with the `verdict` (`pass` or `fail`) and the `regressions` above the
+    /// threshold, for scripts.
+    #[default]
+    Json,
+    /// Gauges in the OpenMetrics text format, for metrics pipelines.
+    #[cfg_attr(feature = "cli", value(name = "openmetrics"))]
+    OpenMetrics,
+}
+
+/// Configure a gate; see [`gate_from_lines`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct GateOptions {
+    /// The gate fails if any function's share of all samples grows by more than this many
+    /// percentage points.
+    ///
+    /// [Default value](defaults::FAIL_THRESHOLD_PCT).
+    pub fail_threshold_pct: f64,
+
+    /// The number of regressions to list in the verdict.
+    ///
+    /// [Default value](defaults::TOP).
+    pub top: usize,
+
+    /// The format of the verdict.
+    ///
+    /// Defaults to [`GateFormat::Json`].
+    pub format: GateFormat,
+
+    /// Compare the total samples of functions, including those of the functions they call,
+    /// instead of their self samples. Callers then regress along with the functions they call.
+    ///
+    /// Defaults to `false`.
+    pub include_children: bool,
+}
+
+impl Default for GateOptions {
+    fn default() -> Self {
+        GateOptions {
+            fail_threshold_pct: defaults::FAIL_THRESHOLD_PCT,
+            top: defaults::TOP,
+            format: Default::default(),
+            include_children: false,
+        }
+    }
+}
+
+/// Whether a gate passed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[must_use]
+pub enum Verdict {
+    /// No function regressed by more than the threshold.
+    Pass,
+    /// At least one function regressed by more than the threshold.
+    Fail,
+}
+
+impl Verdict {
+    fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Fail => "fail",
+        }
+    }
+}
+
+// How the share of all samples of a function changed between two profiles.
+struct Regression {
+    function: String,
+    before: usize,
+    after: usize,
+    before_pct: f64,
+    after_pct: f64,
+}
+
+impl Regression {
+    fn delta_pct(&self) -> f64 {
+        self.after_pct - self.before_pct
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Counts {
+    self_samples: DiffCount,
+    total_samples: DiffCount,
+}
+
+// The samples of a function at the end of a leaf-first stack, and the functions that called it.
+#[derive(Default)]
+struct Callers<'a> {
+    samples: DiffCount,
+    callers: HashMap<&'a str, Callers<'a>>,
+}
+
+// The samples of every function of a profile, and of the profile as a whole.
+struct Profile<'a> {
+    functions: HashMap<&'a str, Counts>,
+    total: DiffCount,
+    diff: bool,
+    bottom_up: Callers<'a>,
+}
+
+/// Produce a report from an iterator over folded stack lines.
+///
+/// See [`from_lines`](super::from_lines) for the expected format of each line. If the lines have
+/// two sample counts, the report shows the differences between them.
+pub fn from_lines<'a, I, W>(opt: &Options, lines: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let Profile {
+        functions,
+        total,
+        diff,
+        bottom_up,
+    } = profile(lines, opt.bottom_up);
+
+    let mut functions: Vec<(&str, Counts)> = functions.into_iter().collect();
+    writeln!(writer, "{}", total_line(opt, total, diff))?;
+    if opt.bottom_up.is_some() {
+        return write_bottom_up_table(opt, &mut writer, &bottom_up, total, diff);
+    }
+
+    // Ties are broken by name so that reports are stable.
+    functions.sort_by(|a, b| (b.1.self_samples.after, a.0).cmp(&(a.1.self_samples.after, b.0)));
+    let by_self = functions
+        .iter()
+        .take_while(|(_, c)| c.self_samples.after > 0);
+    write_table(opt, &mut writer, "self", by_self, total, diff, |c| {
+        (c.self_samples, c.total_samples)
+    })?;
+
+    functions.sort_by(|a, b| (b.1.total_samples.after, a.0).cmp(&(a.1.total_samples.after, b.0)));
+    let by_total = functions
+        .iter()
+        .take_while(|(_, c)| c.total_samples.after > 0);
+    write_table(opt, &mut writer, "total", by_total, total, diff, |c| {
+        (c.total_samples, c.self_samples)
+    })
+}
+
+/// Produce a report from a set of readers that contain folded stack lines.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_readers<R, W>(opt: &Options, readers: R, writer: W) -> io::Result<()>
+where
+    R: IntoIterator,
+    R::Item: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    for mut reader in readers {
+        reader.read_to_string(&mut input)?;
+    }
+    from_lines(opt, input.lines(), writer)
+}
+
+/// Produce a report from files that contain folded stack lines and write it to `writer`.
+///
+/// If files is empty, STDIN will be used as input.
+pub fn from_files<W: Write>(opt: &Options, files: &[PathBuf], writer: W) -> io::Result<()> {
+    from_readers(opt, open_files(files)?, writer)
+}
+
+// Opens the files to read input from, with STDIN for `-` (once) or if there are no files.
+fn open_files(files: &[PathBuf]) -> io::Result<Vec<Box<dyn Read>>> {
+    let mut stdin_added = false;
+    let mut readers: Vec<Box<dyn Read>> = Vec::with_capacity(files.len().max(1));
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                readers.push(Box::new(BufReader::new(io::stdin().lock())));
+                stdin_added = true;
+            }
+        } else {
+            readers.push(Box::new(File::open(infile)?));
+        }
+    }
+    if readers.is_empty() {
+        readers.push(Box::new(BufReader::new(io::stdin().lock())));
+    }
+    Ok(readers)
+}
+
+/// Check differential folded stack lines for regressions, and write the verdict to `writer`.
+///
+/// A function regresses when its share of all samples grows from the first sample count of the
+/// lines to the second, and the gate fails if any function's share grows by more than
+/// [`GateOptions::fail_threshold_pct`] percentage points. Since shares are compared rather than
+/// sample counts, profiles of different lengths can be compared. The verdict lists the largest
+/// regressions above the threshold.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if the lines have a single sample count.
+///
+/// ```
+/// use inferno::flamegraph::report::{self, GateOptions, Verdict};
+///
+/// let mut options = GateOptions::default();
+/// options.fail_threshold_pct = 5.0;
+///
+/// let lines = ["main;parse 50 40", "main;eval 50 60"];
+/// let mut out = Vec::new();
+/// let verdict = report::gate_from_lines(&options, lines, &mut out).unwrap();
+/// assert_eq!(verdict, Verdict::Fail);
+/// let out = String::from_utf8(out).unwrap();
+/// assert!(out.contains(r#""function": "eval""#));
+/// ```
+pub fn gate_from_lines<'a, I, W>(opt: &GateOptions, lines: I, mut writer: W) -> io::Result<Verdict>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let Profile {
+        functions,
+        total,
+        diff,
+        ..
+    } = profile(lines, None);
+    if !diff {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The gate needs differential input, with two sample counts per line",
+        ));
+    }
+
+    let share = |samples: usize, total: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            100.0 * samples as f64 / total as f64
+        }
+    };
+    let mut regressions: Vec<Regression> = functions
+        .into_iter()
+        .map(|(function, counts)| {
+            let samples = if opt.include_children {
+                counts.total_samples
+            } else {
+                counts.self_samples
+            };
+            Regression {
+                function: unescape_frame(function).into_owned(),
+                before: samples.before,
+                after: samples.after,
+                before_pct: share(samples.before, total.before),
+                after_pct: share(samples.after, total.after),
+            }
+        })
+        .filter(|r| r.delta_pct() > opt.fail_threshold_pct)
+        .collect();
+    // Ties are broken by name so that verdicts are stable.
+    regressions.sort_by(|a, b| {
+        b.delta_pct()
+            .total_cmp(&a.delta_pct())
+            .then_with(|| a.function.cmp(&b.function))
+    });
+    let verdict = if regressions.is_empty() {
+        Verdict::Pass
+    } else {
+        Verdict::Fail
+    };
+    let exceeded = regressions.len();
+    regressions.truncate(opt.top);
+
+    match opt.format {
+        GateFormat::Json => {
+            // The keys are sorted, as they were when this went through serde_json.
+            writeln!(writer, "{{")?;
+            writeln!(
+                writer,
+                "  \"fail_threshold_pct\": {},",
+                json_f64(opt.fail_threshold_pct)
+            )?;
+            if regressions.is_empty() {
+                writeln!(writer, "  \"regressions\": [],")?;
+            } else {
+                writeln!(writer, "  \"regressions\": [")?;
+                for (i, r) in regressions.iter().enumerate() {
+                    writeln!(writer, "    {{")?;
+                    writeln!(writer, "      \"after\": {},", r.after)?;
+                    writeln!(writer, "      \"after_pct\": {},", json_f64(r.after_pct))?;
+                    writeln!(writer, "      \"before\": {},", r.before)?;
+                    writeln!(writer, "      \"before_pct\": {},", json_f64(r.before_pct))?;
+                    writeln!(writer, "      \"delta_pct\": {},", json_f64(r.delta_pct()))?;
+                    write!(writer, "      \"function\": ")?;
+                    write_json_string(&mut writer, &r.function)?;
+                    let comma = if i + 1 < regressions.len() { "," } else { "" };
+                    writeln!(writer, "\n    }}{}", comma)?;
+                }
+                writeln!(writer, "  ],")?;
+            }
+            writeln!(writer, "  \"regressions_above_threshold\": {},", exceeded)?;
+            writeln!(writer, "  \"total_after\": {},", total.after)?;
+            writeln!(writer, "  \"total_before\": {},", total.before)?;
+            writeln!(writer, "  \"verdict\": \"{}\"", verdict.as_str())?;
+            writeln!(writer, "}}")?;
+        }
+        GateFormat::OpenMetrics => {
+            writeln!(
+                writer,
+                "# TYPE inferno_gate_failed gauge\n\
+                 # HELP inferno_gate_failed Whether a function regressed by more than the threshold.\n\
+                 inferno_gate_failed {}\n\
+                 # TYPE inferno_gate_threshold_percent gauge\n\
+                 # UNIT inferno_gate_threshold_percent percent\n\
+                 inferno_gate_threshold_percent {}\n\
+                 # TYPE inferno_regression_percent gauge\n\
+                 # UNIT inferno_regression_percent percent\n\
+                 # HELP inferno_regression_percent Growth of a function's share of all samples.",
+                u8::from(verdict == Verdict::Fail),
+                opt.fail_threshold_pct
+            )?;
+            for r in &regressions {
+                writeln!(
+                    writer,
+                    "inferno_regression_percent{{function=\"{}\"}} {}",
+                    escape_label(&r.function),
+                    r.delta_pct()
+                )?;
+            }
+            writeln!(writer, "# EOF")?;
+        }
+    }
+    Ok(verdict)
+}
+
+/// Check files that contain differential folded stack lines for regressions, and write the
+/// verdict to `writer`.
+///
+/// See [`gate_from_lines`] for how regressions are found. If files is empty, STDIN will be used
+/// as input.
+pub fn gate_from_files<W: Write>(
+    opt: &GateOptions,
+    files: &[PathBuf],
+    writer: W,
+) -> io::Result<Verdict> {
+    let mut input = String::new();
+    for mut reader in open_files(files)? {
+        reader.read_to_string(&mut input)?;
+    }
+    gate_from_lines(opt, input.lines(), writer)
+}
+
+// Counts the self and total samples of the functions of folded stack lines, and with
+// `bottom_up_depth`, the samples of their callers up to that many frames from the leaf.
+fn profile<'a, I>(lines: I, bottom_up_depth: Option<usize>) -> Profile<'a>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let lines: Vec<&str> = tidy_lines(lines).into_iter().collect();
+    let diff = is_diff_case(lines.iter().copied());
+
+    let mut functions: HashMap<&str, Counts> = HashMap::new();
+    let mut total = DiffCount::default();
+    let mut ignored = 0;
+    let mut stripped_fractional_samples = false;
+    let mut on_stack = HashSet::new();
+    let mut bottom_up = Callers::default();
+    for line in lines {
+        let mut stack = line;
+        let count = if diff {
+            StackSampleCount::<DiffCount>::parse_from_line(
+                &mut stack,
+                &mut stripped_fractional_samples,
+                FrameWidthSource::After,
+            )
+            .map(|count| count.0)
+        } else {
+            StackSampleCount::<usize>::parse_from_line(
+                &mut stack,
+                &mut stripped_fractional_samples,
+                FrameWidthSource::After,
+            )
+            .map(|count| DiffCount {
+                after: count.0,
+                ..Default::default()
+            })
+        };
+        let Some(count) = count else {
+            ignored += 1;
+            continue;
+        };
+
+        total = add(total, count);
+        if let Some(depth) = bottom_up_depth {
+            let mut callers = &mut bottom_up;
+            for frame in split_frames(stack)
+                .rev()
+                .filter(|f| !f.is_empty())
+                .take(depth.max(1))
+            {
+                callers = callers.callers.entry(frame).or_default();
+                callers.samples = add(callers.samples, count);
+            }
+        }
+        on_stack.clear();
+        let mut frames = split_frames(stack).filter(|f| !f.is_empty()).peekable();
+        while let Some(frame) = frames.next() {
+            let counts = functions.entry(frame).or_default();
+            // Recursive functions only count once towards their total.
+            if on_stack.insert(frame) {
+                counts.total_samples = add(counts.total_samples, count);
+            }
+            if frames.peek().is_none() {
+                counts.self_samples = add(counts.self_samples, count);
+            }
+        }
+    }
+    if ignored > 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+
+    Profile {
+        functions,
+        total,
+        diff,
+        bottom_up,
+    }
+}
+
+fn add(a: DiffCount, b: DiffCount) -> DiffCount {
+    DiffCount {
+        before: a.before + b.before,
+        after: a.after + b.after,
+        visual: a.visual + b.visual,
+    }
+}
+
+fn total_line(opt: &Options, total: DiffCount, diff: bool) -> String {
+    let samples = if diff {
+        format!(
+            "{} → {} samples ({})",
+            thousands(total.before),
+            thousands(total.after),
+            signed(total.after as isize - total.before as isize)
+        )
+    } else {
+        format!("{} samples", thousands(total.after))
+    };
+    match opt.format {
+        ReportFormat::Text => format!("Total: {}", samples),
+        ReportFormat::Markdown => format!("**Total:** {}", samples),
+    }
+}
+
+fn write_table<'a, W, I, F>(
+    opt: &Options,
+    writer: &mut W,
+    by: &str,
+    functions: I,
+    total: DiffCount,
+    diff: bool,
+    counts: F,
+) -> io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = &'a (&'a str, Counts)>,
+    F: Fn(&Counts) -> (DiffCount, DiffCount),
+{
+    let other = if by == "self" { "total" } else { "self" };
+    let mut header = vec![
+        "#".to_string(),
+        capitalize(by),
+        format!("{} %", capitalize(by)),
+    ];
+    if diff {
+        header.push(format!("Δ {}", capitalize(by)));
+    }
+    header.push(format!("{} %", capitalize(other)));
+    header.push("Function".to_string());
+
+    let percent = |samples: usize| {
+        if total.after == 0 {
+            "-".to_string()
+        } else {
+            format!("{:.2}%", 100.0 * samples as f64 / total.after as f64)
+        }
+    };
+    let mut rows = Vec::new();
+    for (i, (function, c)) in functions.take(opt.top).enumerate() {
+        let (ranked, other) = counts(c);
+        let mut row = vec![
+            (i + 1).to_string(),
+            thousands(ranked.after),
+            percent(ranked.after),
+        ];
+        if diff {
+            row.push(signed(ranked.after as isize - ranked.before as isize));
+        }
+        row.push(percent(other.after));
+        row.push(shorten(function, opt.name_width));
+        rows.push(row);
+    }
+
+    writeln!(writer)?;
+    let title = format!("Top {} functions by {} samples", rows.len(), by);
+    match opt.format {
+        ReportFormat::Text => {
+            writeln!(writer, "{}", title)?;
+            write_text_table(writer, &header, &rows)
+        }
+        ReportFormat::Markdown => {
+            writeln!(writer, "### {}", title)?;
+            writeln!(writer)?;
+            write_markdown_table(writer, &header, &rows)
+        }
+    }
+}
+
+fn write_bottom_up_table<W: Write>(
+    opt: &Options,
+    writer: &mut W,
+    bottom_up: &Callers<'_>,
+    total: DiffCount,
+    diff: bool,
+) -> io::Result<()> {
+    let mut header = vec!["#".to_string(), "Self".to_string(), "Self %".to_string()];
+    if diff {
+        header.push("Δ Self".to_string());
+    }
+    header.push("Function".to_string());
+
+    let mut rows = Vec::new();
+    bottom_up_rows(opt, &mut rows, bottom_up, 0, total, diff);
+
+    writeln!(writer)?;
+    let leaves = rows.iter().filter(|row| !row[0].is_empty()).count();
+    let title = format!(
+        "Top {} functions by self samples, with their callers",
+        leaves
+    );
+    match opt.format {
+        ReportFormat::Text => {
+            writeln!(writer, "{}", title)?;
+            write_text_table(writer, &header, &rows)
+        }
+        ReportFormat::Markdown => {
+            writeln!(writer, "### {}", title)?;
+            writeln!(writer)?;
+            write_markdown_table(writer, &header, &rows)
+        }
+    }
+}
+
+// Adds rows for the functions that called the one of `callers`, which is `level` frames above
+// the leaf, busiest first.
+fn bottom_up_rows(
+    opt: &Options,
+    rows: &mut Vec<Vec<String>>,
+    callers: &Callers<'_>,
+    level: usize,
+    total: DiffCount,
+    diff: bool,
+) {
+    let mut sorted: Vec<_> = callers
+        .callers
+        .iter()
+        .filter(|(_, c)| c.samples.after > 0)
+        .collect();
+    // Ties are broken by name so that reports are stable.
+    sorted.sort_by(|a, b| (b.1.samples.after, a.0).cmp(&(a.1.samples.after, b.0)));
+    for (i, (function, c)) in sorted.into_iter().take(opt.top).enumerate() {
+        let mut row = vec![
+            if level == 0 {
+                (i + 1).to_string()
+            } else {
+                String::new()
+            },
+            thousands(c.samples.after),
+            if total.after == 0 {
+                "-".to_string()
+            } else {
+                format!(
+                    "{:.2}%",
+                    100.0 * c.samples.after as f64 / total.after as f64
+                )
+            },
+        ];
+        if diff {
+            row.push(signed(c.samples.after as isize - c.samples.before as isize));
+        }
+        let function = shorten(function, opt.name_width);
+        row.push(if level == 0 {
+            function
+        } else {
+            format!("{}← {}", "  ".repeat(level - 1), function)
+        });
+        rows.push(row);
+        bottom_up_rows(opt, rows, c, level + 1, total, diff);
+    }
+}
+
+// Every column but the last (the function name) holds numbers, so is right-aligned.
+fn write_text_table<W: Write>(
+    writer: &mut W,
+    header: &[String],
+    rows: &[Vec<String>],
+) -> io::Result<()> {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(header).chain(rows.iter().map(Vec::as_slice)) {
+        let (function, numbers) = row.split_last().expect("tables have columns");
+        for (cell, width) in numbers.iter().zip(&widths) {
+            write!(writer, "{:>width$}  ", cell, width = width)?;
+        }
+        writeln!(writer, "{}", function)?;
+    }
+    Ok(())
+}
+
+fn write_markdown_table<W: Write>(
+    writer: &mut W,
+    header: &[String],
+    rows: &[Vec<String>],
+) -> io::Result<()> {
+    writeln!(writer, "| {} |", header.join(" | "))?;
+    let (_, numbers) = header.split_last().expect("tables have columns");
+    writeln!(writer, "|{} :--- |", " ---: |".repeat(numbers.len()))?;
+    for row in rows {
+        let (function, numbers) = row.split_last().expect("tables have columns");
+        let function = function.replace('|', "\\|");
+        let function = if function.contains('`') {
+            function
+        } else {
+            format!("`{}`", function)
+        };
+        writeln!(writer, "| {} | {} |", numbers.join(" | "), function)?;
+    }
+    Ok(())
+}
+
+// Escapes a label value of the OpenMetrics text format.
+// Formats a number for JSON, where infinities and NaN can only be `null`.
+fn json_f64(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        "null".to_string()
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn thousands(n: usize) -> String {
+    let mut buffer = Buffer::new();
+    buffer.write_formatted(&n, &Locale::en);
+    buffer.as_str().to_string()
+}
+
+fn signed(n: isize) -> String {
+    let formatted = thousands(n.unsigned_abs());
+    match n {
+        0 => formatted,
+        n if n > 0 => format!("+{}", formatted),
+        _ => format!("-{}", formatted),
+    }
+}
+
+// Keeps the end of long names, which for most languages is the most specific part.
+fn shorten(name: &str, width: usize) -> String {
+    let name = unescape_frame(name);
+    let len = name.chars().count();
+    if len <= width || width < 2 {
+        name.to_string()
+    } else {
+        let tail: String = name.chars().skip(len - (width - 1)).collect();
+        format!("…{}", tail)
+    }
+}
@@ -3,11 +3,15 @@ use std::cell::RefCell;
 use std::io::{self, prelude::*};
 use std::iter;
 
+use log::warn;
 use quick_xml::events::{BytesCData, BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Writer;
 use str_stack::StrStack;
 
-use super::{Direction, Options, TextTruncateDirection};
+use super::{
+    Corner, Direction, Options, ScriptSource, TextTruncateDirection, WatermarkImage, WidthSizing,
+    SCRIPT, SCRIPT_VERSION,
+};
 
 /// The generic font families should not have quotes around them in the CSS.
 const GENERIC_FONT_FAMILIES: &[&str] = &["cursive", "fantasy", "monospace", "serif", "sans-serif"];
@@ -35,6 +39,64 @@ impl From<usize> for TextArgument<'_> {
     }
 }
 
+/// Where the SVG goes: straight to the caller's writer, or through gzip for `.svgz` output.
+pub(super) enum SvgOutput<'w> {
+    Plain(&'w mut dyn Write),
+    #[cfg(feature = "gzip")]
+    Gzip(libflate::gzip::Encoder<&'w mut dyn Write>),
+}
+
+impl<'w> SvgOutput<'w> {
+    pub(super) fn new(writer: &'w mut dyn Write, svgz: bool) -> io::Result<Self> {
+        match svgz {
+            #[cfg(feature = "gzip")]
+            true => libflate::gzip::Encoder::new(writer).map(SvgOutput::Gzip),
+            #[cfg(not(feature = "gzip"))]
+            true => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "writing .svgz output needs the `gzip` feature",
+            )),
+            false => Ok(SvgOutput::Plain(writer)),
+        }
+    }
+
+    /// Write out whatever gzip still holds back, and flush the caller's writer.
+    pub(super) fn finish(self) -> io::Result<()> {
+        match self {
+            SvgOutput::Plain(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            SvgOutput::Gzip(encoder) => encoder.finish().into_result()?.flush(),
+        }
+    }
+}
+
+impl Write for SvgOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SvgOutput::Plain(writer) => writer.write(buf),
+            #[cfg(feature = "gzip")]
+            SvgOutput::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            SvgOutput::Plain(writer) => writer.write_all(buf),
+            #[cfg(feature = "gzip")]
+            SvgOutput::Gzip(encoder) => encoder.write_all(buf),
+        }
+    }
+
+    // Flushing gzip would end a deflate block at every call, so leave that to `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SvgOutput::Plain(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            SvgOutput::Gzip(_) => Ok(()),
+        }
+    }
+}
+
 pub(super) enum Dimension {
     Pixels(usize),
     Percent(f64),
@@ -47,6 +109,22 @@ pub(super) struct TextItem<'a, I> {
     pub(super) extra: I,
 }
 
+/// A frame listed in the annotations panel, with where its rectangle is to zoom to it.
+pub(super) struct AnnotatedFrame {
+    pub(super) text: String,
+    pub(super) x_samples: usize,
+    pub(super) y: usize,
+}
+
+/// A frame of a differential flame graph for the diff summary: its name, the change of its share
+/// of the samples in percentage points, and where it is.
+pub(super) struct DiffFrame {
+    pub(super) name: String,
+    pub(super) delta_pct: f64,
+    pub(super) x_samples: usize,
+    pub(super) y: usize,
+}
+
 pub(super) struct StyleOptions<'a> {
     pub(super) imageheight: usize,
     pub(super) bgcolor1: Cow<'a, str>,
@@ -59,30 +137,62 @@ pub(super) fn write_header<W>(
     svg: &mut Writer<W>,
     imageheight: usize,
     opt: &Options<'_>,
+    warnings_summary: Option<&str>,
 ) -> io::Result<()>
 where
     W: Write,
 {
-    svg.write_event(Event::Decl(BytesDecl::new("1.0", None, Some("no"))))?;
-    svg.write_event(Event::DocType(BytesText::from_escaped(r#"svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd""#)))?;
+    if !opt.fragment {
+        svg.write_event(Event::Decl(BytesDecl::new("1.0", None, Some("no"))))?;
+        svg.write_event(Event::DocType(BytesText::from_escaped(r#"svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd""#)))?;
+    }
     let imagewidth = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH);
-    svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
-        ("version", "1.1"),
-        ("width", &*format!("{}", imagewidth)),
-        ("height", &*format!("{}", imageheight)),
-        ("onload", "init(evt)"),
-        ("viewBox", &*format!("0 0 {} {}", imagewidth, imageheight)),
+    let width = match opt.width_sizing {
+        None => Some(format!("{}", imagewidth)),
+        Some(WidthSizing::Percent(p)) => Some(format!("{}%", p)),
+        Some(WidthSizing::ViewportWidth(vw)) => Some(format!("{}vw", vw)),
+        Some(WidthSizing::FluidMax(_)) => Some("100%".to_string()),
+        Some(WidthSizing::ViewBox) => None,
+    };
+    let height = format!("{}", imageheight);
+    let style = match opt.width_sizing {
+        Some(WidthSizing::FluidMax(max)) => Some(format!("max-width:{}px", max)),
+        _ => None,
+    };
+    let mut attributes = vec![("version", "1.1")];
+    if let Some(ref width) = width {
+        attributes.push(("width", width));
+        attributes.push(("height", &height));
+    }
+    if let Some(ref style) = style {
+        attributes.push(("style", style));
+    }
+    let view_box = format!("0 0 {} {}", imagewidth, imageheight);
+    // A flame strip carries no script to initialize.
+    if opt.strip_depth.is_none() && opt.script_source != ScriptSource::None {
+        attributes.push(("onload", "init(evt)"));
+    }
+    attributes.extend([
+        ("viewBox", &*view_box),
         ("xmlns", "http://www.w3.org/2000/svg"),
         ("xmlns:xlink", "http://www.w3.org/1999/xlink"),
         ("xmlns:fg", "http://github.com/jonhoo/inferno"),
-    ])))?;
+    ]);
+    svg.write_event(Event::Start(
+        BytesStart::new("svg").with_attributes(attributes),
+    ))?;
     svg.write_event(Event::Comment(BytesText::new(
         "Flame graph stack visualization. \
          See https://github.com/brendangregg/FlameGraph for latest version, \
          and http://www.brendangregg.com/flamegraphs.html for examples.",
     )))?;
+    let notes = match warnings_summary {
+        Some(summary) if opt.notes.is_empty() => Cow::Borrowed(summary),
+        Some(summary) => Cow::Owned(format!("{} {}", opt.notes, summary)),
+        None => Cow::Borrowed(opt.notes.as_str()),
+    };
     svg.write_event(Event::Comment(BytesText::new(
-        format!("NOTES: {}", opt.notes).as_str(),
+        format!("NOTES: {}", notes).as_str(),
     )))?;
     Ok(())
 }
@@ -119,7 +229,11 @@ where
         Cow::Owned(enquote('\"', &opt.font_type))
     };
 
-    let titlesize = &opt.font_size + 5;
+    let titlesize = if opt.compact_header {
+        opt.font_size
+    } else {
+        opt.font_size + 5
+    };
     svg.write_event(Event::Text(BytesText::from_escaped(format!(
         "
 text {{ font-family:{}; font-size:{}px }}
@@ -129,20 +243,83 @@ text {{ font-family:{}; font-size:{}px }}
     ))))?;
     if let Some(strokecolor) = &style_options.strokecolor {
         svg.write_event(Event::Text(BytesText::from_escaped(format!(
-            "#frames > g > rect {{ stroke:{}; stroke-width:1; }}\n",
-            strokecolor
+            "#frames > g > rect {{ stroke:{}; stroke-width:{}; }}\n",
+            strokecolor, opt.stroke_width
         ))))?;
     }
+    if opt.frame_radius > 0.0 || opt.frame_gap > 0.0 {
+        // The gap is clipped off the right end of frames, since their geometry is in percent of
+        // the width and changes when zooming.
+        let radius = opt.frame_radius.max(0.0);
+        let mut style = String::from("#frames > * > rect {");
+        if radius > 0.0 {
+            style.push_str(&format!(" rx:{0}px; ry:{0}px;", radius));
+        }
+        if opt.frame_gap > 0.0 {
+            style.push_str(&format!(
+                " clip-path:inset(0 {}px 0 0 round {}px);",
+                opt.frame_gap, radius
+            ));
+        }
+        style.push_str(" }\n");
+        svg.write_event(Event::Text(BytesText::from_escaped(style)))?;
+    }
     svg.write_event(Event::Text(BytesText::from_escaped(include_str!(
         "flamegraph.css"
     ))))?;
+    if opt.self_time_shading {
+        svg.write_event(Event::Text(BytesText::from_escaped(
+            "#frames rect.self { fill:black; fill-opacity:0.3; stroke:none; pointer-events:none; }\n",
+        )))?;
+    }
+    if opt.compact_header {
+        // The title makes way for the unzoom button, which comes before it.
+        svg.write_event(Event::Text(BytesText::from_escaped(
+            "#title, #subtitle { text-anchor:start; }
+#unzoom:not(.hide) ~ #title, #unzoom:not(.hide) ~ #subtitle { display:none; }
+",
+        )))?;
+    }
+    if opt.annotations_panel && opt.strip_depth.is_none() {
+        svg.write_event(Event::Text(BytesText::from_escaped(
+            "#annotations, #annotations-list > text { cursor:pointer; }
+#annotations-list > text:hover { text-decoration:underline; }
+",
+        )))?;
+    }
+    if opt.diff_summary && opt.strip_depth.is_none() {
+        svg.write_event(Event::Text(BytesText::from_escaped(
+            "#diff-summary > text { cursor:pointer; }
+#diff-summary > text:hover { text-decoration:underline; }
+",
+        )))?;
+    }
+    if !opt.bookmarks.is_empty() && opt.strip_depth.is_none() {
+        svg.write_event(Event::Text(BytesText::from_escaped(
+            ".bookmark { cursor:pointer; }
+.bookmark rect { fill-opacity:0.1; }
+.bookmark:hover rect { fill-opacity:0.3; }
+",
+        )))?;
+    }
+    if has_script(opt) && opt.strip_depth.is_none() {
+        svg.write_event(Event::Text(BytesText::from_escaped(
+            "#filter { text-anchor:end; opacity:0.1; cursor:pointer; }
+#filter:hover, #filter.show { opacity:1; }
+.filtered { display:none; }
+",
+        )))?;
+    }
     svg.write_event(Event::End(BytesEnd::new("style")))?;
 
-    svg.write_event(Event::Start(
-        BytesStart::new("script").with_attributes(iter::once(("type", "text/ecmascript"))),
-    ))?;
-    svg.write_event(Event::CData(BytesCData::new(format!(
-        "
+    // A flame strip has none of the elements the script drives.
+    let strip = opt.strip_depth.is_some();
+    if opt.script_source != ScriptSource::None {
+        svg.write_event(Event::Start(
+            BytesStart::new("script").with_attributes(iter::once(("type", "text/ecmascript"))),
+        ))?;
+        svg.write_event(Event::CData(BytesCData::new(format!(
+            "
         var nametype = {};
         var fontsize = {};
         var fontwidth = {};
@@ -151,19 +328,94 @@ text {{ font-family:{}; font-size:{}px }}
         var searchcolor = '{}';
         var fluiddrawing = {};
         var truncate_text_right = {};\n    ",
-        enquote('\'', &opt.name_type),
-        opt.font_size,
-        opt.font_width,
-        super::XPAD,
-        opt.direction == Direction::Inverted,
-        opt.search_color,
-        opt.image_width.is_none(),
-        opt.text_truncate_direction == TextTruncateDirection::Right
-    ))))?;
-    if !opt.no_javascript {
-        svg.write_event(Event::CData(BytesCData::new(include_str!("flamegraph.js"))))?;
+            enquote('\'', &opt.name_type),
+            opt.font_size,
+            opt.font_width,
+            opt.xpad,
+            opt.direction == Direction::Inverted,
+            opt.search_color,
+            opt.width_sizing
+                .map_or(opt.image_width.is_none(), |sizing| sizing.is_fluid()),
+            opt.text_truncate_direction == TextTruncateDirection::Right
+        ))))?;
+        if opt.short_narrow_tooltips {
+            svg.write_event(Event::CData(BytesCData::new(format!(
+                "
+        var countname = {};\n    ",
+                enquote('\'', &opt.count_name)
+            ))))?;
+        }
+        if opt.factor != 1.0 {
+            svg.write_event(Event::CData(BytesCData::new(format!(
+                "
+        var factor = {};\n    ",
+                opt.factor
+            ))))?;
+        }
+        if opt.text_truncate_direction == TextTruncateDirection::Middle {
+            svg.write_event(Event::CData(BytesCData::new(
+                "
+        var truncate_text_middle = true;\n    ",
+            )))?;
+        }
+        if opt.omit_root {
+            svg.write_event(Event::CData(BytesCData::new(
+                "
+        var omitroot = true;\n    ",
+            )))?;
+        }
+        if opt.depth_histogram && !strip {
+            svg.write_event(Event::CData(BytesCData::new(format!(
+                "
+        var depth_histogram = {};\n    ",
+                DEPTH_HISTOGRAM_WIDTH
+            ))))?;
+        }
+        if let Some(group) = &opt.sync_group {
+            svg.write_event(Event::CData(BytesCData::new(format!(
+                "
+        var syncgroup = {};\n    ",
+                enquote('\'', group)
+            ))))?;
+        }
+        if let Some(url) = opt.live_updates.as_ref().filter(|_| !strip) {
+            svg.write_event(Event::CData(BytesCData::new(format!(
+                "
+        var liveupdates = {};\n    ",
+                enquote('\'', url)
+            ))))?;
+        }
+        if let Some(template) = opt
+            .details_template
+            .as_ref()
+            .filter(|_| opt.show_status_bar)
+        {
+            svg.write_event(Event::CData(BytesCData::new(format!(
+                "
+        var detailstemplate = {};\n    ",
+                enquote('\'', template)
+            ))))?;
+        }
+        if let ScriptSource::External(_) = opt.script_source {
+            svg.write_event(Event::CData(BytesCData::new(format!(
+                "
+        var scriptversion = '{}';\n    ",
+                SCRIPT_VERSION
+            ))))?;
+        }
+        if opt.script_source == ScriptSource::Embedded && !opt.no_javascript && !strip {
+            svg.write_event(Event::CData(BytesCData::new(SCRIPT)))?;
+        }
+        svg.write_event(Event::End(BytesEnd::new("script")))?;
+        if let ScriptSource::External(url) = &opt.script_source {
+            if !strip {
+                svg.write_event(Event::Empty(BytesStart::new("script").with_attributes([
+                    ("type", "text/ecmascript"),
+                    ("xlink:href", url.as_str()),
+                ])))?;
+            }
+        }
     }
-    svg.write_event(Event::End(BytesEnd::new("script")))?;
 
     svg.write_event(Event::Empty(BytesStart::new("rect").with_attributes(vec![
         ("x", "0"),
@@ -173,88 +425,553 @@ text {{ font-family:{}; font-size:{}px }}
         ("fill", "url(#background)"),
     ])))?;
 
+    if strip {
+        return Ok(());
+    }
+
     // We don't care too much about allocating just for the prelude
     let mut buf = StrStack::new();
+    let char_width = opt.font_size as f64 * opt.font_width;
+    // Where the title and the controls go, and with a compact header the subtitle and details.
+    let header_y = if opt.compact_header {
+        (opt.font_size * 3 / 2) as f64
+    } else {
+        (opt.font_size * 2) as f64
+    };
+
+    let unzoom = || TextItem {
+        x: Dimension::Pixels(opt.xpad),
+        y: header_y,
+        text: "Reset Zoom".into(),
+        extra: vec![
+            ("id", "unzoom"),
+            ("class", "hide"),
+            ("fill", &style_options.uicolor),
+        ],
+    };
+    // With a compact header, the title makes way for the unzoom button, which has to come before
+    // it for that.
+    if opt.compact_header {
+        write_str(svg, &mut buf, unzoom())?;
+    }
+
     write_str(
         svg,
         &mut buf,
         TextItem {
-            x: Dimension::Percent(50.0),
-            y: (opt.font_size * 2) as f64,
+            x: if opt.compact_header {
+                Dimension::Pixels(opt.xpad)
+            } else {
+                Dimension::Percent(50.0)
+            },
+            y: header_y,
             text: (&*opt.title).into(),
             extra: vec![("id", "title"), ("fill", &style_options.uicolor)],
         },
     )?;
+    // With a compact header, the subtitle and then the details follow the title.
+    let mut header_x = opt.xpad as f64 + (opt.title.chars().count() + 2) as f64 * char_width;
 
     if let Some(ref subtitle) = opt.subtitle {
         write_str(
             svg,
             &mut buf,
             TextItem {
-                x: Dimension::Percent(50.0),
-                y: (opt.font_size * 4) as f64,
+                x: if opt.compact_header {
+                    Dimension::Pixels(header_x as usize)
+                } else {
+                    Dimension::Percent(50.0)
+                },
+                y: if opt.compact_header {
+                    header_y
+                } else {
+                    (opt.font_size * 4) as f64
+                },
                 text: (&**subtitle).into(),
                 extra: vec![("id", "subtitle")],
             },
         )?;
+        header_x += (subtitle.chars().count() + 2) as f64 * char_width;
     }
 
     let image_width = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH) as f64;
 
-    write_str(
-        svg,
-        &mut buf,
-        TextItem {
-            x: Dimension::Pixels(super::XPAD),
-            y: if opt.direction == Direction::Straight {
-                style_options.imageheight - (opt.ypad2() / 2)
-            } else {
-                // Inverted (icicle) mode, put the details on top:
-                opt.ypad1() - opt.font_size
-            } as f64,
-            text: " ".into(),
-            extra: vec![("id", "details"), ("fill", &style_options.uicolor)],
-        },
-    )?;
+    if opt.show_status_bar {
+        write_str(
+            svg,
+            &mut buf,
+            TextItem {
+                x: if opt.compact_header {
+                    Dimension::Pixels(header_x as usize)
+                } else {
+                    Dimension::Pixels(opt.xpad)
+                },
+                y: if opt.compact_header {
+                    header_y
+                } else if opt.direction == Direction::Straight {
+                    (style_options.imageheight - (opt.ypad2() / 2)) as f64
+                } else {
+                    // Inverted (icicle) mode, put the details on top:
+                    (opt.ypad1() - opt.font_size) as f64
+                },
+                text: " ".into(),
+                extra: vec![("id", "details"), ("fill", &style_options.uicolor)],
+            },
+        )?;
+    }
+
+    if !opt.compact_header {
+        write_str(svg, &mut buf, unzoom())?;
+    }
 
     write_str(
         svg,
         &mut buf,
         TextItem {
-            x: Dimension::Pixels(super::XPAD),
-            y: (opt.font_size * 2) as f64,
-            text: "Reset Zoom".into(),
-            extra: vec![
-                ("id", "unzoom"),
-                ("class", "hide"),
-                ("fill", &style_options.uicolor),
-            ],
+            x: Dimension::Pixels((image_width as usize).saturating_sub(opt.xpad)),
+            y: header_y,
+            text: "Search".into(),
+            extra: vec![("id", "search"), ("fill", &style_options.uicolor)],
         },
     )?;
 
+    // The view-time frame filter is only useful when the script is there to drive it.
+    let filter_offset = (char_width * 10.0) as usize;
+    if has_script(opt) {
+        write_str(
+            svg,
+            &mut buf,
+            TextItem {
+                x: Dimension::Pixels(
+                    (image_width as usize).saturating_sub(opt.xpad + filter_offset),
+                ),
+                y: header_y,
+                text: "Filter".into(),
+                extra: vec![("id", "filter"), ("fill", &style_options.uicolor)],
+            },
+        )?;
+    }
+
     write_str(
         svg,
         &mut buf,
         TextItem {
-            x: Dimension::Pixels(image_width as usize - super::XPAD),
-            y: (opt.font_size * 2) as f64,
-            text: "Search".into(),
-            extra: vec![("id", "search"), ("fill", &style_options.uicolor)],
+            x: if opt.compact_header {
+                // Left of the search and filter buttons.
+                Dimension::Pixels(
+                    (image_width as usize).saturating_sub(opt.xpad + 2 * filter_offset),
+                )
+            } else {
+                Dimension::Pixels((image_width as usize).saturating_sub(opt.xpad))
+            },
+            y: if opt.compact_header {
+                header_y
+            } else {
+                (style_options.imageheight - (opt.ypad2() / 2)) as f64
+            },
+            text: " ".into(),
+            extra: vec![("id", "matched"), ("fill", &style_options.uicolor)],
         },
     )?;
 
+    if !opt.bookmarks.is_empty() {
+        let y = if opt.compact_header {
+            header_y as usize + opt.font_size * 2
+        } else if opt.subtitle.is_some() {
+            opt.font_size * 6
+        } else {
+            opt.font_size * 4
+        };
+        write_bookmarks(svg, &mut buf, style_options, opt, y, char_width)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the footer and the watermark, if any, over everything else.
+pub(super) fn write_footer<W>(
+    svg: &mut Writer<W>,
+    style_options: &StyleOptions,
+    opt: &Options<'_>,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let imageheight = style_options.imageheight;
+    let image_width = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH);
+    if let Some(footer) = &opt.footer {
+        let mut buf = StrStack::new();
+        write_str(
+            svg,
+            &mut buf,
+            TextItem {
+                x: Dimension::Percent(50.0),
+                y: imageheight.saturating_sub(opt.font_size / 2) as f64,
+                text: footer.as_str().into(),
+                extra: vec![
+                    ("id", "footer"),
+                    ("text-anchor", "middle"),
+                    ("fill", &style_options.uicolor),
+                ],
+            },
+        )?;
+    }
+
+    let Some(watermark) = &opt.watermark else {
+        return Ok(());
+    };
+    let x = match watermark.corner {
+        Corner::TopLeft | Corner::BottomLeft => opt.xpad,
+        Corner::TopRight | Corner::BottomRight => {
+            image_width.saturating_sub(opt.xpad + watermark.width)
+        }
+    };
+    let y = match watermark.corner {
+        Corner::TopLeft | Corner::TopRight => opt.xpad,
+        Corner::BottomLeft | Corner::BottomRight => {
+            imageheight.saturating_sub(opt.xpad + watermark.height)
+        }
+    };
+    let (x, y) = (x.to_string(), y.to_string());
+    let (width, height) = (watermark.width.to_string(), watermark.height.to_string());
+    let opacity = watermark.opacity.to_string();
+    let mut attributes = vec![
+        ("id", "watermark"),
+        ("x", x.as_str()),
+        ("y", y.as_str()),
+        ("width", width.as_str()),
+        ("height", height.as_str()),
+        ("opacity", opacity.as_str()),
+        ("pointer-events", "none"),
+    ];
+    match &watermark.image {
+        WatermarkImage::Path {
+            data,
+            width: path_width,
+            height: path_height,
+        } => {
+            let view_box = format!("0 0 {} {}", path_width, path_height);
+            attributes.push(("viewBox", &view_box));
+            svg.write_event(Event::Start(
+                BytesStart::new("svg").with_attributes(attributes),
+            ))?;
+            svg.write_event(Event::Empty(BytesStart::new("path").with_attributes(vec![
+                ("d", data.as_str()),
+                ("fill", style_options.uicolor.as_str()),
+            ])))?;
+            svg.write_event(Event::End(BytesEnd::new("svg")))?;
+        }
+        WatermarkImage::DataUri(uri) => {
+            attributes.push(("xlink:href", uri));
+            svg.write_event(Event::Empty(
+                BytesStart::new("image").with_attributes(attributes),
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the bookmarks as chips from left to right, with the baseline of their labels at `y`.
+/// Those that don't fit the width of the image are left out.
+fn write_bookmarks<W>(
+    svg: &mut Writer<W>,
+    buf: &mut StrStack,
+    style_options: &StyleOptions,
+    opt: &Options<'_>,
+    y: usize,
+    char_width: f64,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let image_width = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH);
+    let mut x = opt.xpad;
+    for (i, (label, function)) in opt.bookmarks.iter().enumerate() {
+        let width = (label.chars().count() as f64 * char_width) as usize + 8;
+        if x + width > image_width.saturating_sub(opt.xpad) {
+            warn!(
+                "Left out {} bookmarks that don't fit the width of the image",
+                opt.bookmarks.len() - i
+            );
+            break;
+        }
+        svg.write_event(Event::Start(BytesStart::new("g").with_attributes(vec![
+            ("class", "bookmark"),
+            ("fg:func", function.as_str()),
+        ])))?;
+        svg.write_event(Event::Empty(BytesStart::new("rect").with_attributes(vec![
+            ("x", &*x.to_string()),
+            ("y", &*y.saturating_sub(opt.font_size).to_string()),
+            ("width", &*width.to_string()),
+            ("height", &*(opt.font_size + 4).to_string()),
+            ("rx", "3"),
+            ("fill", &*style_options.uicolor),
+            ("stroke", &*style_options.uicolor),
+        ])))?;
+        write_str(
+            svg,
+            buf,
+            TextItem {
+                x: Dimension::Pixels(x + 4),
+                y: y as f64,
+                text: label.as_str().into(),
+                extra: vec![("fill", style_options.uicolor.as_str())],
+            },
+        )?;
+        svg.write_event(Event::End(BytesEnd::new("g")))?;
+        x += width + 6;
+    }
+    Ok(())
+}
+
+/// Writes the button of the annotations panel in the bottom row, and the panel itself, which opens
+/// upwards from it over the frames.
+pub(super) fn write_annotations_panel<W>(
+    svg: &mut Writer<W>,
+    style_options: &StyleOptions,
+    opt: &Options<'_>,
+    mut frames: Vec<AnnotatedFrame>,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut buf = StrStack::new();
+    let imageheight = style_options.imageheight;
     write_str(
         svg,
         &mut buf,
         TextItem {
-            x: Dimension::Pixels(image_width as usize - super::XPAD),
-            y: (style_options.imageheight - (opt.ypad2() / 2)) as f64,
-            text: " ".into(),
-            extra: vec![("id", "matched"), ("fill", &style_options.uicolor)],
+            x: Dimension::Pixels(opt.xpad),
+            y: imageheight.saturating_sub(opt.font_size / 2) as f64,
+            text: format!("Annotations ({})", frames.len()).into(),
+            extra: vec![("id", "annotations"), ("fill", &style_options.uicolor)],
         },
     )?;
 
-    Ok(())
+    // Frames of the same function go together, from left to right.
+    frames.sort_by(|a, b| a.text.cmp(&b.text).then(a.x_samples.cmp(&b.x_samples)));
+    // As many rows as fit above the bottom row, with the last one saying how many didn't fit.
+    let row_height = opt.font_size + 4;
+    let bottom = imageheight.saturating_sub(opt.font_size * 2 + 4);
+    let max_rows = (bottom.saturating_sub(opt.ypad1() + opt.font_size) / row_height + 1).max(1);
+    let (shown, more) = if frames.len() > max_rows {
+        (max_rows - 1, frames.len() - (max_rows - 1))
+    } else {
+        (frames.len(), 0)
+    };
+    let rows = shown + usize::from(more > 0);
+    let top = bottom.saturating_sub(rows.saturating_sub(1) * row_height);
+
+    svg.write_event(Event::Start(
+        BytesStart::new("g").with_attributes(vec![("id", "annotations-list"), ("class", "hide")]),
+    ))?;
+    let rect_y = top.saturating_sub(opt.font_size + 2);
+    svg.write_event(Event::Empty(BytesStart::new("rect").with_attributes(vec![
+        ("x", "0"),
+        ("y", &*rect_y.to_string()),
+        ("width", "100%"),
+        ("height", &*(bottom + 6 - rect_y).to_string()),
+        ("fill", &*style_options.bgcolor1),
+        ("stroke", &*style_options.uicolor),
+    ])))?;
+    for (i, frame) in frames[..shown].iter().enumerate() {
+        let x_samples = frame.x_samples.to_string();
+        let y = frame.y.to_string();
+        write_str(
+            svg,
+            &mut buf,
+            TextItem {
+                x: Dimension::Pixels(opt.xpad),
+                y: (top + i * row_height) as f64,
+                text: frame.text.as_str().into(),
+                extra: vec![
+                    ("fill", style_options.uicolor.as_str()),
+                    ("fg:x", &x_samples),
+                    ("fg:y", &y),
+                ],
+            },
+        )?;
+    }
+    if more > 0 {
+        write_str(
+            svg,
+            &mut buf,
+            TextItem {
+                x: Dimension::Pixels(opt.xpad),
+                y: bottom as f64,
+                text: format!("and {} more", more).into(),
+                extra: vec![("fill", style_options.uicolor.as_str())],
+            },
+        )?;
+    }
+    svg.write_event(Event::End(BytesEnd::new("g")))
+}
+
+/// How many frames the diff summary lists of those that grew, and of those that shrank.
+const DIFF_SUMMARY_ENTRIES: usize = 5;
+
+/// The height of the rows of the diff summary, a heading and the entries below it, which go below
+/// everything else.
+pub(super) fn diff_summary_height(opt: &Options<'_>) -> usize {
+    (DIFF_SUMMARY_ENTRIES + 1) * (opt.font_size + 4) + opt.font_size / 2
+}
+
+/// Writes the diff summary below everything else, from `top` down: the frames that grew the most
+/// on the left, and those that shrank the most on the right.
+pub(super) fn write_diff_summary<W>(
+    svg: &mut Writer<W>,
+    style_options: &StyleOptions,
+    opt: &Options<'_>,
+    top: usize,
+    mut frames: Vec<DiffFrame>,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut buf = StrStack::new();
+    let image_width = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH);
+    let char_width = opt.font_size as f64 * opt.font_width;
+    let row_height = opt.font_size + 4;
+    svg.write_event(Event::Empty(BytesStart::new("rect").with_attributes(vec![
+        ("x", "0"),
+        ("y", &*top.to_string()),
+        ("width", "100%"),
+        ("height", &*diff_summary_height(opt).to_string()),
+        ("fill", &*style_options.bgcolor2),
+    ])))?;
+
+    // The biggest changes come first, and the leftmost frame of those that changed as much.
+    frames.sort_by(|a, b| {
+        b.delta_pct
+            .abs()
+            .total_cmp(&a.delta_pct.abs())
+            .then(a.x_samples.cmp(&b.x_samples))
+            .then(a.y.cmp(&b.y))
+    });
+    let column_width = image_width.saturating_sub(2 * opt.xpad) / 2;
+    let max_chars = (column_width as f64 / char_width) as usize;
+    let columns = [
+        ("Biggest regressions", opt.xpad, true),
+        ("Biggest improvements", opt.xpad + column_width, false),
+    ];
+    // The headings go first, so that only the entries are in the group that the script makes
+    // clickable.
+    let y = top + row_height;
+    let mut entries = Vec::new();
+    for (heading, x, grew) in columns {
+        write_str(
+            svg,
+            &mut buf,
+            TextItem {
+                x: Dimension::Pixels(x),
+                y: y as f64,
+                text: heading.into(),
+                extra: vec![("fill", style_options.uicolor.as_str())],
+            },
+        )?;
+        let column: Vec<_> = frames
+            .iter()
+            .filter(|frame| frame.delta_pct != 0.0 && (frame.delta_pct > 0.0) == grew)
+            .take(DIFF_SUMMARY_ENTRIES)
+            .collect();
+        if column.is_empty() {
+            write_str(
+                svg,
+                &mut buf,
+                TextItem {
+                    x: Dimension::Pixels(x),
+                    y: (y + row_height) as f64,
+                    text: "none".into(),
+                    extra: vec![("fill", style_options.uicolor.as_str())],
+                },
+            )?;
+        }
+        for (i, frame) in column.into_iter().enumerate() {
+            entries.push((x, i, frame));
+        }
+    }
+
+    svg.write_event(Event::Start(
+        BytesStart::new("g").with_attributes(vec![("id", "diff-summary")]),
+    ))?;
+    for (x, i, frame) in entries {
+        let mut text = format!("{:+.2}pt {}", frame.delta_pct, frame.name);
+        if text.chars().count() > max_chars {
+            text = text.chars().take(max_chars.saturating_sub(2)).collect();
+            text.push_str("..");
+        }
+        let x_samples = frame.x_samples.to_string();
+        let frame_y = frame.y.to_string();
+        write_str(
+            svg,
+            &mut buf,
+            TextItem {
+                x: Dimension::Pixels(x),
+                y: (y + (i + 1) * row_height) as f64,
+                text: text.into(),
+                extra: vec![
+                    ("fill", style_options.uicolor.as_str()),
+                    ("fg:x", &x_samples),
+                    ("fg:y", &frame_y),
+                ],
+            },
+        )?;
+    }
+    svg.write_event(Event::End(BytesEnd::new("g")))
+}
+
+/// The width of the [depth histogram](Options::depth_histogram), to the right of the frames.
+pub(super) const DEPTH_HISTOGRAM_WIDTH: usize = 40;
+
+// The space between the frames and the bars of the depth histogram.
+const DEPTH_HISTOGRAM_GAP: usize = 6;
+
+/// A row of the depth histogram: the depth of its frames, where the row is, and the share of all
+/// samples whose stacks reach its depth.
+pub(super) struct DepthRow {
+    pub(super) depth: usize,
+    pub(super) y1: usize,
+    pub(super) y2: usize,
+    pub(super) share: f64,
+}
+
+pub(super) fn write_depth_histogram<W>(
+    svg: &mut Writer<W>,
+    style_options: &StyleOptions,
+    opt: &Options<'_>,
+    rows: &[DepthRow],
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let image_width = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH);
+    let x = image_width.saturating_sub(opt.xpad + DEPTH_HISTOGRAM_WIDTH);
+    svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
+        ("id", "depth_histogram"),
+        ("x", &*x.to_string()),
+        ("width", &*DEPTH_HISTOGRAM_WIDTH.to_string()),
+    ])))?;
+    let max_width = (DEPTH_HISTOGRAM_WIDTH - DEPTH_HISTOGRAM_GAP) as f64;
+    for row in rows {
+        let title = match row.depth {
+            0 => "all samples".to_string(),
+            depth => format!("{:.2}% of samples reach depth {}", row.share * 100.0, depth),
+        };
+        svg.write_event(Event::Start(BytesStart::new("g")))?;
+        svg.write_event(Event::Start(BytesStart::new("title")))?;
+        svg.write_event(Event::Text(BytesText::new(&title)))?;
+        svg.write_event(Event::End(BytesEnd::new("title")))?;
+        svg.write_event(Event::Empty(BytesStart::new("rect").with_attributes(vec![
+            ("x", &*DEPTH_HISTOGRAM_GAP.to_string()),
+            ("y", &*row.y1.to_string()),
+            ("width", &*format!("{:.1}", row.share * max_width)),
+            ("height", &*(row.y2 - row.y1).to_string()),
+            ("fill", &*style_options.uicolor),
+            ("fill-opacity", "0.5"),
+        ])))?;
+        svg.write_event(Event::End(BytesEnd::new("g")))?;
+    }
+    svg.write_event(Event::End(BytesEnd::new("svg")))
 }
 
 pub(super) fn write_str<'a, W, I>(
@@ -302,6 +1019,11 @@ where
 
 // Imported from the `enquote` crate @ 1.0.3.
 // It's "unlicense" licensed, so that's fine.
+// Whether the flame graph gets a script that drives its interactive controls.
+fn has_script(opt: &Options<'_>) -> bool {
+    !opt.no_javascript && opt.script_source != ScriptSource::None
+}
+
 fn enquote(quote: char, s: &str) -> String {
     // escapes any `quote` in `s`
     let escaped = s
@@ -0,0 +1,126 @@
+use std::borrow::Cow;
+
+use ahash::AHashMap;
+
+use super::merge::{self, StackSampleCount, StackSampleCountExt, TimedFrame};
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The samples of every frame of time-series input in each of its time windows, by the path of
+/// the frame: the frames from the root down to it, separated by `;` as in the input.
+#[derive(Debug, Default)]
+pub(super) struct Trends<'a> {
+    by_path: AHashMap<&'a str, Vec<usize>>,
+}
+
+impl<'a> Trends<'a> {
+    /// Split folded lines with `windows` sample counts each into lines with the samples of
+    /// `width_window`, or of all windows together, and collect the trends of their frames.
+    ///
+    /// Lines without as many sample counts are passed on as they are, to be reported as invalid
+    /// when they are merged.
+    pub(super) fn split<I>(
+        lines: I,
+        windows: usize,
+        width_window: Option<usize>,
+    ) -> (Vec<Cow<'a, str>>, Self)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut trends = Trends::default();
+        let mut split = Vec::new();
+        for line in lines {
+            let Some((stack, counts)) = parse_windows(line, windows) else {
+                split.push(Cow::Borrowed(line));
+                continue;
+            };
+            let width = match width_window {
+                Some(window) => counts.get(window).copied().unwrap_or(0),
+                None => counts.iter().sum(),
+            };
+            split.push(Cow::Owned(format!("{} {}", stack, width)));
+
+            trends.add("", &counts);
+            for frame in merge::split_frames(stack) {
+                // Frames are slices of the stack, so this is the stack up to the end of `frame`.
+                let end = frame.as_ptr() as usize - stack.as_ptr() as usize + frame.len();
+                trends.add(&stack[..end], &counts);
+            }
+        }
+        (split, trends)
+    }
+
+    fn add(&mut self, path: &'a str, counts: &[usize]) {
+        let trend = self
+            .by_path
+            .entry(path)
+            .or_insert_with(|| vec![0; counts.len()]);
+        trend.iter_mut().zip(counts).for_each(|(t, c)| *t += c);
+    }
+
+    /// Find the trend of every frame, if it has one.
+    ///
+    /// Frames are visited by where they start, parents before their children, keeping the paths
+    /// of the frames that are open at the start of each one on a stack.
+    pub(super) fn of_frames<CountType>(
+        &self,
+        frames: &[TimedFrame<'_, CountType>],
+    ) -> Vec<Option<&[usize]>>
+    where
+        StackSampleCount<CountType>: StackSampleCountExt,
+    {
+        let mut order: Vec<usize> = (0..frames.len()).collect();
+        order.sort_by_key(|&i| (frames[i].start_time.visual(), frames[i].location.depth));
+
+        let mut trends = vec![None; frames.len()];
+        let mut open: Vec<(usize, String)> = Vec::new();
+        for i in order {
+            let frame = &frames[i];
+            while let Some((top, _)) = open.last() {
+                let parent = &frames[*top];
+                if parent.location.depth < frame.location.depth
+                    && parent.end_time.visual() > frame.start_time.visual()
+                {
+                    break;
+                }
+                open.pop();
+            }
+            let path = match open.last() {
+                Some((_, parent)) if parent.is_empty() => frame.location.function.to_string(),
+                Some((_, parent)) => format!("{};{}", parent, frame.location.function),
+                None => frame.location.function.to_string(),
+            };
+            trends[i] = self.by_path.get(path.as_str()).map(Vec::as_slice);
+            open.push((i, path));
+        }
+        trends
+    }
+}
+
+// Splits the `windows` sample counts off the end of a line.
+fn parse_windows(line: &str, windows: usize) -> Option<(&str, Vec<usize>)> {
+    let mut stack = line.trim();
+    let mut counts = vec![0; windows];
+    for count in counts.iter_mut().rev() {
+        let (samplesi, _) = merge::rfind_samples(stack)?;
+        *count = stack[samplesi..].parse::<f64>().ok()?.round() as usize;
+        stack = stack[..samplesi].trim_end();
+    }
+    if stack.is_empty() {
+        return None;
+    }
+    Some((stack, counts))
+}
+
+/// Draw a series of counts as a line of bars, from the lowest for none to the highest for the
+/// largest count of the series.
+pub(super) fn sparkline(series: &[usize]) -> String {
+    let max = series.iter().copied().max().unwrap_or(0);
+    series
+        .iter()
+        .map(|&count| match max {
+            0 => BARS[0],
+            _ => BARS[(count as f64 / max as f64 * (BARS.len() - 1) as f64).round() as usize],
+        })
+        .collect()
+}
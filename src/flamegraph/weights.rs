@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use regex::Regex;
+
+/// Multiplies the sample counts of every stack that matches a regular expression.
+///
+/// This is meant for correcting known biases of a profile before it is drawn, such as discounting
+/// the samples of threads that a profiler samples more often than others. Patterns are matched
+/// against whole stacks as they are merged, with frames separated by `;` and without the sample
+/// counts. When several patterns match the same stack, all of their factors apply. Weighted counts
+/// are rounded to whole samples.
+#[derive(Clone, Debug, Default)]
+pub struct StackWeights(Vec<(Regex, f64)>);
+
+impl PartialEq for StackWeights {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|((re1, f1), (re2, f2))| re1.as_str() == re2.as_str() && f1 == f2)
+    }
+}
+
+impl StackWeights {
+    /// Multiply the sample counts of every stack that matches `pattern` by `factor`.
+    ///
+    /// Fails if the pattern is invalid, or if the factor is negative or not finite.
+    pub fn add(&mut self, pattern: &str, factor: f64) -> io::Result<()> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if !factor.is_finite() || factor < 0.0 {
+            return Err(invalid(format!(
+                "invalid weight {} for `{}`: must be a finite, non-negative number",
+                factor, pattern
+            )));
+        }
+        let regex = Regex::new(pattern)
+            .map_err(|e| invalid(format!("invalid pattern `{}`: {}", pattern, e)))?;
+        self.0.push((regex, factor));
+        Ok(())
+    }
+
+    /// Parse stack weights from a file.
+    ///
+    /// See [`StackWeights::from_reader`] for the expected format.
+    pub fn from_file(path: &Path) -> io::Result<StackWeights> {
+        let file = BufReader::new(File::open(path)?);
+        StackWeights::from_reader(file)
+    }
+
+    /// Parse stack weights from a `BufRead`.
+    ///
+    /// Each line should consist of a stack regular expression, whitespace, and then the factor to
+    /// multiply the sample counts of matching stacks by. Empty lines and lines starting with `#`
+    /// are ignored:
+    ///
+    /// ```text
+    /// # The I/O threads are sampled twice as often as the others.
+    /// ^io-worker; 0.5
+    /// ;tokio::runtime::park 0
+    /// ```
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<StackWeights> {
+        let mut weights = StackWeights::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((pattern, factor)) = line.rsplit_once(char::is_whitespace) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {} of stack weights has no factor: {}", i + 1, line),
+                ));
+            };
+            let factor = factor.parse().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid weight `{}` on line {}: {}", factor, i + 1, e),
+                )
+            })?;
+            weights.add(pattern.trim_end(), factor)?;
+        }
+        Ok(weights)
+    }
+
+    /// Add the weights of `other` after those of `self`.
+    pub(super) fn extend(&mut self, other: StackWeights) {
+        self.0.extend(other.0);
+    }
+
+    /// Return the product of the factors of all patterns matching the given stack, or `None` if
+    /// none match.
+    pub(super) fn factor_for(&self, stack: &str) -> Option<f64> {
+        self.0
+            .iter()
+            .filter(|(regex, _)| regex.is_match(stack))
+            .map(|&(_, factor)| factor)
+            .reduce(|a, b| a * b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn stack_weights_from_reader() {
+        let weights = r"
+            # The I/O threads are sampled twice as often as the others.
+            ^io-worker; 0.5
+            ;poll$ 0
+            ^io-worker;.*;read$	3
+        ";
+        let weights = StackWeights::from_reader(weights.as_bytes()).unwrap();
+
+        let mut expected = StackWeights::default();
+        expected.add("^io-worker;", 0.5).unwrap();
+        expected.add(";poll$", 0.0).unwrap();
+        expected.add("^io-worker;.*;read$", 3.0).unwrap();
+        assert_eq!(weights, expected);
+
+        assert_eq!(weights.factor_for("main;fs::read;read"), None);
+        assert_eq!(weights.factor_for("io-worker;poll"), Some(0.0));
+        assert_eq!(weights.factor_for("io-worker;fs::read;read"), Some(1.5));
+    }
+
+    #[test]
+    fn stack_weights_from_reader_invalid() {
+        for (weights, error) in [
+            ("main", "line 1 of stack weights has no factor: main"),
+            (
+                "main; 2\nidle two",
+                "invalid weight `two` on line 2: invalid float literal",
+            ),
+            ("main( 2", "invalid pattern `main(`"),
+            ("main -1", "invalid weight -1 for `main`"),
+        ] {
+            let e = StackWeights::from_reader(weights.as_bytes()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert!(e.to_string().starts_with(error), "{}", e);
+        }
+    }
+}
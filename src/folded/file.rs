@@ -0,0 +1,182 @@
+use std::io::{self, BufReader, Read, Write};
+
+use serde_json::{json, Map, Value};
+
+use crate::flamegraph::{defaults, Options};
+
+// One of the magic numbers zstd sets aside for skippable frames, followed by what marks the frame
+// as the header of a folded file.
+const MAGIC: u32 = 0x184D_2A5E;
+const HEADER: &[u8] = b"inferno-folded\n";
+const VERSION: u64 = 1;
+
+/// What the stacks in a folded file measure, and where they came from.
+///
+/// Every field is optional, and readers ignore what they don't know about.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Metadata {
+    /// What the counts are, such as `samples` or `ns`.
+    pub count_name: Option<String>,
+
+    /// A factor to scale the counts by, such as to turn them into seconds.
+    pub factor: Option<f64>,
+
+    /// A title for flame graphs of the stacks.
+    pub title: Option<String>,
+
+    /// The tool that produced the stacks, such as `inferno-collapse-perf`.
+    pub source: Option<String>,
+
+    /// When recording the stacks started, in seconds since the Unix epoch.
+    pub start_time: Option<u64>,
+
+    /// When recording the stacks ended, in seconds since the Unix epoch.
+    pub end_time: Option<u64>,
+}
+
+impl Metadata {
+    fn to_json(&self) -> Value {
+        let mut json = Map::new();
+        json.insert("version".to_string(), json!(VERSION));
+        let fields = [
+            ("count_name", self.count_name.as_ref().map(|v| json!(v))),
+            ("factor", self.factor.map(|v| json!(v))),
+            ("title", self.title.as_ref().map(|v| json!(v))),
+            ("source", self.source.as_ref().map(|v| json!(v))),
+            ("start_time", self.start_time.map(|v| json!(v))),
+            ("end_time", self.end_time.map(|v| json!(v))),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                json.insert(key.to_string(), value);
+            }
+        }
+        Value::Object(json)
+    }
+
+    fn from_json(json: &Value) -> Self {
+        let string = |key: &str| json[key].as_str().map(str::to_string);
+        Metadata {
+            count_name: string("count_name"),
+            factor: json["factor"].as_f64(),
+            title: string("title"),
+            source: string("source"),
+            start_time: json["start_time"].as_u64(),
+            end_time: json["end_time"].as_u64(),
+        }
+    }
+
+    /// Set the options of a flame graph that are still at their defaults from the metadata.
+    pub fn apply_to(&self, opt: &mut Options<'_>) {
+        if let Some(count_name) = &self.count_name {
+            if opt.count_name == defaults::COUNT_NAME {
+                opt.count_name = count_name.clone();
+            }
+        }
+        if let Some(factor) = self.factor {
+            if opt.factor == defaults::FACTOR {
+                opt.factor = factor;
+            }
+        }
+        if let Some(title) = &self.title {
+            if opt.title == defaults::TITLE || opt.title == defaults::CHART_TITLE {
+                opt.title = title.clone();
+            }
+        }
+    }
+}
+
+/// Returns whether `start`, the beginning of a file, is the beginning of a folded file.
+pub fn is_folded(start: &[u8]) -> bool {
+    start.len() >= 4 && start[..4] == MAGIC.to_le_bytes()
+}
+
+/// Writes a folded file.
+///
+/// Write folded stack lines to it, and call [`finish`](Writer::finish) when done.
+pub struct Writer<W: Write> {
+    encoder: zstd::stream::write::Encoder<'static, W>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Start a folded file with the given metadata.
+    pub fn new(mut writer: W, metadata: &Metadata) -> io::Result<Self> {
+        let json = metadata.to_json().to_string();
+        let len = u32::try_from(HEADER.len() + json.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "metadata is too large"))?;
+        writer.write_all(&MAGIC.to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(HEADER)?;
+        writer.write_all(json.as_bytes())?;
+        Ok(Writer {
+            encoder: zstd::stream::write::Encoder::new(writer, 0)?,
+        })
+    }
+
+    /// Finish the folded file, and return the writer it was written to.
+    pub fn finish(self) -> io::Result<W> {
+        self.encoder.finish()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+/// Reads the folded stack lines of a folded file.
+pub struct Reader<R: Read> {
+    metadata: Metadata,
+    decoder: zstd::stream::read::Decoder<'static, BufReader<R>>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Read the metadata of a folded file, and get ready to read its folded stack lines.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if !is_folded(&magic) {
+            return Err(invalid_data("not a folded file".to_string()));
+        }
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let mut header = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut header)?;
+        let Some(json) = header.strip_prefix(HEADER) else {
+            return Err(invalid_data("not a folded file".to_string()));
+        };
+        let json: Value = serde_json::from_slice(json)?;
+        if json["version"].as_u64().map_or(true, |v| v > VERSION) {
+            return Err(invalid_data(format!(
+                "unsupported folded file version: {}",
+                json["version"]
+            )));
+        }
+
+        Ok(Reader {
+            metadata: Metadata::from_json(&json),
+            decoder: zstd::stream::read::Decoder::new(reader)?,
+        })
+    }
+
+    /// The metadata of the folded file.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
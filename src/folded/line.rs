@@ -0,0 +1,242 @@
+use std::borrow::Cow;
+use std::iter;
+
+/// A folded stack line, such as `main;foo;bar 12`, split into its stack and its sample counts.
+///
+/// Parsing doesn't copy anything: the stack and its frames are slices of the line. Lines are
+/// parsed the way [`flamegraph`](crate::flamegraph) parses them:
+///
+///  - Leading and trailing whitespace is ignored, and so is whitespace between the stack and its
+///    counts.
+///  - The count is whatever follows the last space, and may have a fractional part, which is
+///    truncated.
+///  - Lines of differential input, as written by
+///    [`differential`](crate::differential), end in two counts, from before and after. Whether
+///    an input is differential is decided once, by whether its first line with counts has two
+///    (see [`Line::is_differential`]), and the lines of an input that isn't keep a number at the
+///    end of their stack as part of it.
+///  - Frames are separated by semicolons, except by those escaped as `\;`, which stay in the
+///    frames. Backslashes only escape in front of a semicolon or at the end of a frame, where
+///    `\\` stands for one backslash.
+///
+/// ```
+/// use inferno::folded::Line;
+///
+/// let line = Line::parse("main;<[u8\\; 8] as Debug>::fmt 12.5", false).unwrap();
+/// assert_eq!(line.stack(), "main;<[u8\\; 8] as Debug>::fmt");
+/// assert_eq!(line.frames().collect::<Vec<_>>(), ["main", "<[u8\\; 8] as Debug>::fmt"]);
+/// assert_eq!(line.samples(), 12);
+/// assert_eq!(line.samples_before(), None);
+///
+/// assert_eq!(Line::is_differential("main;foo 10 20"), Some(true));
+/// let line = Line::parse("main;foo 10 20", true).unwrap();
+/// assert_eq!(line.stack(), "main;foo");
+/// assert_eq!(line.samples_before(), Some(10));
+/// assert_eq!(line.samples(), 20);
+///
+/// // Without differential input, the first number is part of the stack.
+/// let line = Line::parse("main;foo 10 20", false).unwrap();
+/// assert_eq!(line.stack(), "main;foo 10");
+/// assert_eq!(line.samples(), 20);
+///
+/// assert_eq!(Line::parse("main;foo", false), None);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Line<'a> {
+    stack: &'a str,
+    samples_before: Option<usize>,
+    samples: usize,
+}
+
+impl<'a> Line<'a> {
+    /// Parse a folded stack line, with two counts if it's from `differential` input, and with one
+    /// otherwise.
+    ///
+    /// Returns `None` for lines without a stack or without as many valid counts at their end,
+    /// which [`flamegraph`](crate::flamegraph) ignores.
+    pub fn parse(line: &'a str, differential: bool) -> Option<Self> {
+        let (stack, samples) = split_count(line.trim())?;
+        let (stack, samples_before) = if differential {
+            let (stack, samples_before) = split_count(stack)?;
+            (stack, Some(samples_before))
+        } else {
+            (stack, None)
+        };
+        if stack.is_empty() {
+            return None;
+        }
+        Some(Line {
+            stack,
+            samples_before,
+            samples,
+        })
+    }
+
+    /// Whether a folded stack line has two counts, as the lines of differential input do, or
+    /// `None` if it doesn't have a stack with a count at all.
+    ///
+    /// [`flamegraph`](crate::flamegraph) decides whether an input is differential by its first
+    /// line for which this isn't `None`.
+    pub fn is_differential(line: &str) -> Option<bool> {
+        if Line::parse(line, true).is_some() {
+            Some(true)
+        } else if Line::parse(line, false).is_some() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// The stack of the line, with its frames still separated by semicolons.
+    pub fn stack(&self) -> &'a str {
+        self.stack
+    }
+
+    /// The frames of the stack, from the outermost to the innermost.
+    pub fn frames(&self) -> Frames<'a> {
+        split_frames(self.stack)
+    }
+
+    /// The sample count of the line, or the second one of a differential line.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The first sample count of a differential line, or `None` for lines with one count.
+    pub fn samples_before(&self) -> Option<usize> {
+        self.samples_before
+    }
+}
+
+// Splits a count, with its fractional part truncated, off the end of a line.
+fn split_count(line: &str) -> Option<(&str, usize)> {
+    let (samplesi, doti) = rfind_samples(line)?;
+    let samples = line[samplesi..][..doti].parse().ok()?;
+    Some((line[..samplesi].trim_end(), samples))
+}
+
+/// Splits a stack into its frames, at the semicolons that aren't escaped as `\;`.
+///
+/// Escaped semicolons are left in the frames, and only unescaped (with [`unescape_frame`]) for
+/// display, so frames can still borrow from the line they're in. A semicolon is escaped by an odd
+/// number of backslashes in front of it, so a frame that ends with a backslash has it doubled as
+/// `\\`, and so do backslashes in front of an escaped semicolon.
+pub(crate) fn split_frames(stack: &str) -> Frames<'_> {
+    Frames { rest: Some(stack) }
+}
+
+/// Turns the escaped semicolons (`\;`) of a frame back into semicolons, and the doubled
+/// backslashes in front of them or at the end of the frame back into single ones.
+///
+/// Other backslashes are kept as they are, so that frames such as `C:\\Windows\\ntdll.dll`
+/// show the way they always have.
+pub(crate) fn unescape_frame(frame: &str) -> Cow<'_, str> {
+    if !frame.contains(ESCAPED_SEMICOLON) && !frame.ends_with(ESCAPED_BACKSLASH) {
+        return frame.into();
+    }
+    let mut unescaped = String::with_capacity(frame.len());
+    let mut rest = frame;
+    while let Some(i) = rest.find('\\') {
+        unescaped.push_str(&rest[..i]);
+        let backslashes = rest[i..].bytes().take_while(|&b| b == b'\\').count();
+        rest = &rest[i + backslashes..];
+        // In front of a semicolon, the odd backslash is the one that escapes it.
+        let kept = if rest.starts_with(';') {
+            backslashes / 2
+        } else if rest.is_empty() {
+            backslashes / 2 + backslashes % 2
+        } else {
+            backslashes
+        };
+        unescaped.extend(iter::repeat('\\').take(kept));
+    }
+    unescaped.push_str(rest);
+    unescaped.into()
+}
+
+const ESCAPED_SEMICOLON: &str = "\\;";
+const ESCAPED_BACKSLASH: &str = "\\\\";
+
+/// An iterator over the frames of a stack, as returned by [`Line::frames`].
+#[derive(Clone, Debug)]
+pub struct Frames<'a> {
+    rest: Option<&'a str>,
+}
+
+impl<'a> Frames<'a> {
+    // A semicolon is escaped by an odd number of backslashes in front of it, as the others
+    // escape each other.
+    fn is_separator(stack: &str, i: usize) -> bool {
+        let backslashes = stack.as_bytes()[..i]
+            .iter()
+            .rev()
+            .take_while(|&&b| b == b'\\')
+            .count();
+        backslashes % 2 == 0
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest?;
+        let separator = rest
+            .match_indices(';')
+            .map(|(i, _)| i)
+            .find(|&i| Self::is_separator(rest, i));
+        match separator {
+            Some(i) => {
+                self.rest = Some(&rest[i + 1..]);
+                Some(&rest[..i])
+            }
+            None => self.rest.take(),
+        }
+    }
+}
+
+impl<'a> DoubleEndedIterator for Frames<'a> {
+    fn next_back(&mut self) -> Option<&'a str> {
+        let rest = self.rest?;
+        let separator = rest
+            .rmatch_indices(';')
+            .map(|(i, _)| i)
+            .find(|&i| Self::is_separator(rest, i));
+        match separator {
+            Some(i) => {
+                self.rest = Some(&rest[..i]);
+                Some(&rest[i + 1..])
+            }
+            None => self.rest.take(),
+        }
+    }
+}
+
+// Tries to find a sample count at the end of a line.
+//
+// On success, the first value of the returned tuple will be the index to the sample count.
+// If the sample count is fractional, the second value will be the offset of the dot within
+// the sample count.
+// If the sample count is not fractional, the second value returned is the offset
+// to the last digit in the sample count.
+//
+// If no sample count is found, `None` will be returned.
+pub(crate) fn rfind_samples(line: &str) -> Option<(usize, usize)> {
+    let samplesi = line.rfind(' ')? + 1;
+    let samples = &line[samplesi..];
+    if let Some(doti) = samples.find('.') {
+        if samples[..doti]
+            .chars()
+            .chain(samples[doti + 1..].chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            Some((samplesi, doti))
+        } else {
+            None
+        }
+    } else if !samples.chars().all(|c| c.is_ascii_digit()) {
+        None
+    } else {
+        Some((samplesi, line.len() - samplesi))
+    }
+}
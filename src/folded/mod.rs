@@ -0,0 +1,89 @@
+//! Parsing and summary statistics of folded stack lines, and a compressed file format for them
+//! that also records what they measure.
+//!
+//! # Lines
+//!
+//! [`Line`] splits a folded stack line into its stack, its frames and its counts the way
+//! [`flamegraph`](crate::flamegraph) does, without copying them, so tools that read folded stack
+//! lines don't have to repeat its rules for whitespace, fractional and differential counts, and
+//! escaped semicolons:
+//!
+//! ```
+//! let line = inferno::folded::Line::parse("main;parse;memcpy 30", false).unwrap();
+//! assert_eq!(line.frames().last(), Some("memcpy"));
+//! assert_eq!(line.samples(), 30);
+//! ```
+//!
+//! # Statistics
+//!
+//! [`stats`] sums up folded stack lines without drawing anything, which is enough to check a
+//! profile programmatically, such as to fail a CI job when a function takes too much of it:
+//!
+//! ```
+//! let stats = inferno::folded::stats(["main;parse;memcpy 30", "main;render 70"]);
+//! assert_eq!(stats.total_samples, 100);
+//! assert_eq!(stats.max_depth, 3);
+//! assert_eq!(stats.top_self(1), [("render".to_string(), 70)]);
+//! assert!(stats.self_percent("memcpy") <= 30.0);
+//! ```
+//!
+//! # Transformations
+//!
+//! [`reverse`] reverses the stacks of folded stack lines exactly the way
+//! [`flamegraph`](crate::flamegraph) does for
+//! [`reverse_stack_order`](crate::flamegraph::Options::reverse_stack_order), for pipelines that
+//! want to write out the reversed stacks themselves:
+//!
+//! ```
+//! let reversed: Vec<_> = inferno::folded::reverse(["main;parse;memcpy 30"]).collect();
+//! assert_eq!(reversed, ["memcpy;parse;main 30"]);
+//! ```
+//!
+//! # Folded files
+//!
+//! Plain folded stack lines don't say what their counts are, so whoever draws a flame graph from
+//! them has to know to pass, say, `--countname ns`. A folded file instead starts with a
+//! [`Metadata`] header that records the unit of the counts, a factor to scale them by, a title,
+//! the tool the stacks came from, and when they were recorded, followed by the folded stack lines
+//! themselves, compressed with [zstd]. Reading and writing them needs the `zstd` feature.
+//!
+//! The header is a zstd [skippable frame], so `zstd -d` turns a folded file back into plain folded
+//! stack lines. [`flamegraph::from_files`](crate::flamegraph::from_files) and friends recognize
+//! folded files among their inputs, and use their metadata for the options that were left at
+//! their defaults.
+//!
+//! ```
+//! # #[cfg(feature = "zstd")] {
+//! use std::io::{Read, Write};
+//! use inferno::folded::{Metadata, Reader, Writer};
+//!
+//! let mut metadata = Metadata::default();
+//! metadata.count_name = Some("ns".to_string());
+//! metadata.source = Some("inferno-collapse-perf".to_string());
+//!
+//! let mut writer = Writer::new(Vec::new(), &metadata).unwrap();
+//! writer.write_all(b"main;work 1500\n").unwrap();
+//! let file = writer.finish().unwrap();
+//!
+//! let mut reader = Reader::new(&file[..]).unwrap();
+//! assert_eq!(reader.metadata(), &metadata);
+//! let mut folded = String::new();
+//! reader.read_to_string(&mut folded).unwrap();
+//! assert_eq!(folded, "main;work 1500\n");
+//! # }
+//! ```
+//!
+//!   [zstd]: https://facebook.github.io/zstd/
+//!   [skippable frame]: https://github.com/facebook/zstd/blob/dev/doc/zstd_compression_format.md#skippable-frames
+
+#[cfg(feature = "zstd")]
+mod file;
+pub(crate) mod line;
+pub(crate) mod reverse;
+mod stats;
+
+#[cfg(feature = "zstd")]
+pub use self::file::{is_folded, Metadata, Reader, Writer};
+pub use self::line::{Frames, Line};
+pub use self::reverse::reverse;
+pub use self::stats::{stats, Stats};
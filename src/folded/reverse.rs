@@ -0,0 +1,71 @@
+use super::line::{rfind_samples, split_frames};
+
+/// Reverse the stacks of folded stack lines, so that their innermost frames come first, as
+/// [`flamegraph::Options::reverse_stack_order`](crate::flamegraph::Options::reverse_stack_order)
+/// does before drawing them.
+///
+/// The counts of every line are kept, including both counts of differential input. Leading and
+/// trailing whitespace is ignored, blank lines are skipped, and semicolons escaped as `\;` stay
+/// in their frames. Reversed lines are trimmed, for frames whose names contain an unescaped `; `,
+/// such as `[u8; 8]` in Rust, which leave a space at the start of the reversed stack.
+///
+/// The reversed lines come in the order of the input, so they need to be sorted again before
+/// they are drawn with [`flamegraph::Options::no_sort`](crate::flamegraph::Options::no_sort).
+///
+/// ```
+/// let reversed: Vec<_> = inferno::folded::reverse(["main;parse;memcpy 30", "main;render 5 7"])
+///     .collect();
+/// assert_eq!(reversed, ["memcpy;parse;main 30", "render;main 5 7"]);
+/// ```
+pub fn reverse<'a, I>(lines: I) -> impl Iterator<Item = String> + 'a
+where
+    I: IntoIterator<Item = &'a str>,
+    I::IntoIter: 'a,
+{
+    let mut reversed = String::new();
+    lines
+        .into_iter()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(move |line| {
+            let rfind = |line: &str| rfind_samples(line).map(|(i, _)| i);
+            reverse_line(line, usize::MAX, rfind, &mut reversed).to_string()
+        })
+}
+
+/// Reverses the stack of a non-empty folded stack line into `reversed`, keeping at most `depth`
+/// of its innermost frames, and returns the reversed line.
+///
+/// `rfind_samples` finds the start of the count at the end of a line, if there is one.
+pub(crate) fn reverse_line<'b, F>(
+    line: &str,
+    depth: usize,
+    rfind_samples: F,
+    reversed: &'b mut String,
+) -> &'b str
+where
+    F: Fn(&str) -> Option<usize>,
+{
+    reversed.clear();
+    let samples_idx = rfind_samples(line).unwrap_or(line.len());
+    // Look for a second count before the space in front of the first one, or before the last
+    // character of lines without a count, which may take more than one byte.
+    let last_len = line[..samples_idx]
+        .chars()
+        .next_back()
+        .map_or(0, char::len_utf8);
+    let samples_idx = rfind_samples(&line[..samples_idx - last_len]).unwrap_or(samples_idx);
+    let stack_funcs = split_frames(line[..samples_idx].trim()).rev().take(depth);
+    for (i, func) in stack_funcs.enumerate() {
+        if i != 0 {
+            reversed.push(';');
+        }
+        reversed.push_str(func);
+    }
+    reversed.push(' ');
+    reversed.push_str(&line[samples_idx..]);
+    // Trim to handle the case where functions names internally contain an unescaped `;`.
+    // This can happen, for example, with types like `[u8; 8]` in Rust.
+    // See https://github.com/jonhoo/inferno/pull/338.
+    reversed.trim()
+}
@@ -0,0 +1,108 @@
+use ahash::{AHashMap, AHashSet};
+use log::warn;
+
+use super::Line;
+
+/// A summary of a set of folded stack lines.
+///
+/// See [`stats`] for how it's computed.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The sum of the counts of all stacks.
+    pub total_samples: usize,
+
+    /// The number of different stacks, counting repeated stacks once.
+    pub distinct_stacks: usize,
+
+    /// The number of different function names across all frames of all stacks.
+    pub distinct_functions: usize,
+
+    /// The number of frames in the deepest stack.
+    pub max_depth: usize,
+
+    /// The samples of each function in which it was the innermost frame, that is, the function's
+    /// self time, from most samples to fewest. Functions that never are the innermost frame of a
+    /// stack are left out.
+    pub self_samples: Vec<(String, usize)>,
+}
+
+impl Stats {
+    /// The `n` functions with the most self samples, or all of them if there are fewer.
+    pub fn top_self(&self, n: usize) -> &[(String, usize)] {
+        &self.self_samples[..n.min(self.self_samples.len())]
+    }
+
+    /// The self samples of `function` as a percentage of all samples, or 0 if it has none.
+    pub fn self_percent(&self, function: &str) -> f64 {
+        if self.total_samples == 0 {
+            return 0.0;
+        }
+        let samples = self
+            .self_samples
+            .iter()
+            .find(|(name, _)| name == function)
+            .map_or(0, |&(_, samples)| samples);
+        samples as f64 * 100.0 / self.total_samples as f64
+    }
+}
+
+/// Compute summary statistics of folded stack lines, such as `main;foo;bar 12`.
+///
+/// Lines of differential input, which end in two counts, count as their second count. As with
+/// [`flamegraph`](crate::flamegraph), the input is differential if its first line with counts
+/// has two. Lines without samples, and lines that can't be parsed, are ignored.
+pub fn stats<'a, I>(lines: I) -> Stats
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut stacks = AHashSet::default();
+    let mut functions = AHashSet::default();
+    let mut self_samples: AHashMap<&str, usize> = AHashMap::default();
+    let mut stats = Stats::default();
+    let mut ignored = 0;
+    let mut differential = None;
+
+    for line in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if differential.is_none() {
+            differential = Line::is_differential(line);
+        }
+        let Some(line) = Line::parse(line, differential.unwrap_or(false)) else {
+            ignored += 1;
+            continue;
+        };
+        let (stack, samples) = (line.stack(), line.samples());
+        if samples == 0 {
+            continue;
+        }
+
+        stats.total_samples += samples;
+        stacks.insert(stack);
+        let mut depth = 0;
+        let mut leaf = stack;
+        for frame in line.frames() {
+            functions.insert(frame);
+            depth += 1;
+            leaf = frame;
+        }
+        stats.max_depth = stats.max_depth.max(depth);
+        *self_samples.entry(leaf).or_default() += samples;
+    }
+    if ignored > 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+
+    stats.distinct_stacks = stacks.len();
+    stats.distinct_functions = functions.len();
+    stats.self_samples = self_samples
+        .into_iter()
+        .map(|(name, samples)| (name.to_string(), samples))
+        .collect();
+    stats
+        .self_samples
+        .sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    stats
+}
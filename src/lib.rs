@@ -141,9 +141,30 @@
 //! # Feature flags
 //! All features below are enabled by default
 //! - `cli`: Also builds the `inferno` command-line tools
+//! - `json`: Adds the stack collapsers for profilers that write JSON (`php`, `tracing`, `dart`,
+//!   `wasm`, `signpost`, `gpu`, `jfr`, `react`, `ips`, `unity` and `spans`), and the
+//!   [`flamegraph::layout`] and [`flamegraph::patch`] modules. `zstd` needs it too
 //! - `multithreaded`: Enables multithreaded stack-collapsing
 //! - `nameattr`: Allows for adding customizing and adding attributes to the svg of [`flamegraph`]. See the `--nameattr` option for the flamegraph cli
 //!
+//! The following features are not enabled by default
+//! - `annotations`: Adds the regular expression keyed options of [`flamegraph::Options`]:
+//!   `func_annotations`, `stack_weights`, `weights_file` and `group_rules`. Annotations are read
+//!   from JSON with `json` and from TOML with `serde`. Also adds the `--annotations`, `--weights`
+//!   and `--group` options of the flamegraph cli
+//! - `gzip`: Adds the [`collapse::pprof`] collapser, deflated PerfView zip archives, and
+//!   [`flamegraph::Options::svgz`] output. `cli` enables it
+//! - `mmap`: Makes [`flamegraph::from_files`] memory-map regular input files and make the flame
+//!   graph from the mapped lines in place, rather than reading them all into memory first
+//! - `reqwest`: Adds [`collapse::pprof::Folder::collapse_url`] and the `--url` option of
+//!   `inferno-collapse-pprof`, for fetching profiles from HTTP endpoints. It enables `gzip`
+//! - `serde`: Implements `Serialize` and `Deserialize` for [`flamegraph::Options`], and adds
+//!   [`flamegraph::Options::from_toml_str`] for loading them from a configuration file
+//! - `serve`: Adds [`serve`], for picking the format to serve a flame graph in from the `Accept`
+//!   header of an HTTP request
+//! - `zstd`: Adds the zstd-compressed folded files of [`folded`], with a header that records what
+//!   their counts measure, and lets [`flamegraph`] read them
+//!
 //! # Development
 //!
 //! This crate was initially developed through [a series of live coding sessions]. If you want to
@@ -192,9 +213,29 @@ pub mod collapse;
 ///   [crate-level documentation]: ../index.html
 pub mod differential;
 
+// Command-line helpers shared by the binaries. This is public because the binaries are separate
+// crates, but it doesn't need to be exposed to library users, hence #[doc(hidden)].
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli;
+
+/// Parsing and summary statistics of folded stack traces, and a compressed file format for them
+/// with metadata.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod folded;
+
 /// Tools for producing flame graphs from folded stack traces.
 ///
 /// See the [crate-level documentation] for details.
 ///
 ///   [crate-level documentation]: ../index.html
 pub mod flamegraph;
+
+/// Helpers for serving flame graphs over HTTP in the format that a client asks for.
+///
+/// This only picks and renders the format; it does not depend on any HTTP library.
+#[cfg(feature = "serve")]
+pub mod serve;
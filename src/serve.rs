@@ -0,0 +1,191 @@
+use std::io::{self, Write};
+
+use crate::flamegraph::{self, Options, OutputSink};
+
+/// A format that a flame graph can be served in, as one of the [`OutputSink`]s of
+/// [`flamegraph::render`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    /// The flame graph as an SVG image, served as `image/svg+xml`.
+    Svg,
+
+    /// The merged stacks as a tree of frames in JSON, served as `application/json`. See
+    /// [`OutputSink::Json`] for the format.
+    Json,
+
+    /// The merged stacks as folded stack lines, served as `text/plain`.
+    Folded,
+}
+
+impl Format {
+    /// All the formats, in the order they are preferred in when a client accepts several of them
+    /// equally.
+    pub const ALL: &'static [Format] = &[Format::Svg, Format::Json, Format::Folded];
+
+    /// The value of the `Content-Type` header of a response in this format.
+    ///
+    /// Flame graphs compressed with [`Options::svgz`] also need a `Content-Encoding: gzip`
+    /// header, which is left to the server.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Svg => "image/svg+xml",
+            Format::Json => "application/json",
+            Format::Folded => "text/plain; charset=utf-8",
+        }
+    }
+
+    fn media_type(self) -> (&'static str, &'static str) {
+        match self {
+            Format::Svg => ("image", "svg+xml"),
+            Format::Json => ("application", "json"),
+            Format::Folded => ("text", "plain"),
+        }
+    }
+
+    /// Picks the format to serve for the value of an `Accept` header, out of all formats.
+    ///
+    /// See [`Format::negotiate`].
+    pub fn from_accept(accept: &str) -> Option<Format> {
+        Format::negotiate(accept, Format::ALL)
+    }
+
+    /// Picks the format to serve for the value of an `Accept` header, out of `offered`.
+    ///
+    /// Every format gets the quality (`q`) of the most specific media range that matches it, such
+    /// as `image/svg+xml` over `image/*` over `*/*`, and the format with the highest quality is
+    /// picked, or the one that comes first in `offered` among equals. A missing or empty header
+    /// accepts anything. Returns `None` if the client accepts none of the offered formats, which
+    /// calls for a `406 Not Acceptable` response.
+    pub fn negotiate(accept: &str, offered: &[Format]) -> Option<Format> {
+        if accept.trim().is_empty() {
+            return offered.first().copied();
+        }
+        let ranges: Vec<MediaRange<'_>> = accept.split(',').filter_map(MediaRange::parse).collect();
+        let mut best: Option<(Format, f32)> = None;
+        for &format in offered {
+            let quality = ranges
+                .iter()
+                .filter_map(|range| range.specificity(format).map(|s| (s, range.quality)))
+                .max_by_key(|&(specificity, _)| specificity)
+                .map_or(0.0, |(_, quality)| quality);
+            if quality > 0.0 && best.map_or(true, |(_, best)| quality > best) {
+                best = Some((format, quality));
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    /// The output sink that writes this format to `writer`, to serve several formats from a
+    /// single [`flamegraph::render`] that merges the stacks once.
+    pub fn sink(self, writer: &mut dyn Write) -> OutputSink<'_> {
+        match self {
+            Format::Svg => OutputSink::Svg(writer),
+            Format::Json => OutputSink::Json(writer),
+            Format::Folded => OutputSink::Folded(writer),
+        }
+    }
+}
+
+// A media range of an `Accept` header, such as `image/*;q=0.8`.
+struct MediaRange<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    quality: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(range: &'a str) -> Option<Self> {
+        let mut params = range.split(';');
+        let (kind, subtype) = params.next()?.trim().split_once('/')?;
+        let mut quality = 1.0;
+        for param in params {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse().ok()?;
+                }
+            }
+        }
+        Some(MediaRange {
+            kind: kind.trim(),
+            subtype: subtype.trim(),
+            quality,
+        })
+    }
+
+    // How specifically this range matches a format, if it does: 2 for its exact media type, 1
+    // for all subtypes of its type and 0 for all media types.
+    fn specificity(&self, format: Format) -> Option<u8> {
+        let (kind, subtype) = format.media_type();
+        match (self.kind, self.subtype) {
+            ("*", "*") => Some(0),
+            (k, "*") if k.eq_ignore_ascii_case(kind) => Some(1),
+            (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// Draw the flame graph of `lines` with `opt` in the format that the `Accept` header `accept`
+/// asks for, and write it to `writer`.
+///
+/// Returns the format that was written, to set the `Content-Type` of the response from, or `None`
+/// if the client accepts none of them, in which case nothing is written.
+///
+/// ```
+/// use inferno::flamegraph::Options;
+/// use inferno::serve::{self, Format};
+///
+/// let folded = "main;parse 3\nmain;render 5\n";
+/// let mut body = Vec::new();
+/// let format = serve::render(
+///     &mut Options::default(),
+///     folded.lines(),
+///     "application/json, image/svg+xml;q=0.9",
+///     &mut body,
+/// )
+/// .unwrap();
+/// assert_eq!(format, Some(Format::Json));
+/// assert_eq!(format.unwrap().content_type(), "application/json");
+/// ```
+pub fn render<'a, I>(
+    opt: &mut Options<'_>,
+    lines: I,
+    accept: &str,
+    writer: &mut dyn Write,
+) -> io::Result<Option<Format>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let Some(format) = Format::from_accept(accept) else {
+        return Ok(None);
+    };
+    flamegraph::render(opt, lines, &mut [format.sink(writer)])?;
+    Ok(Some(format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        let cases = [
+            ("", Some(Format::Svg)),
+            ("*/*", Some(Format::Svg)),
+            ("application/json", Some(Format::Json)),
+            ("text/html, text/*;q=0.5", Some(Format::Folded)),
+            ("image/*;q=0.2, application/json;q=0.9", Some(Format::Json)),
+            ("*/*;q=0.1, image/svg+xml;q=0", Some(Format::Json)),
+            ("TEXT/PLAIN", Some(Format::Folded)),
+            ("text/html, image/png", None),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(Format::from_accept(accept), expected, "{}", accept);
+        }
+        assert_eq!(
+            Format::negotiate("*/*", &[Format::Folded, Format::Svg]),
+            Some(Format::Folded)
+        );
+    }
+}
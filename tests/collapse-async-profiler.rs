@@ -0,0 +1,78 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::async_profiler::{Folder, Options};
+use log::Level;
+
+fn test_collapse_async_profiler(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_async_profiler_warnings(test_file: &str, options: Options, expected: &[&str]) {
+    common::test_collapse_logs(Folder::from(options), test_file, |captured_logs| {
+        let warnings: Vec<_> = captured_logs
+            .iter()
+            .filter(|log| log.level == Level::Warn)
+            .map(|log| log.body.as_str())
+            .collect();
+        assert_eq!(warnings, expected);
+    });
+}
+
+#[test]
+fn collapse_async_profiler_all_events() {
+    let test_file = "./tests/data/collapse-async-profiler/spark.txt";
+    let result_file = "./tests/data/collapse-async-profiler/results/spark.txt";
+    test_collapse_async_profiler(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_async_profiler_wall_event() {
+    let test_file = "./tests/data/collapse-async-profiler/spark.txt";
+    let result_file = "./tests/data/collapse-async-profiler/results/spark-wall.txt";
+    let mut options = Options::default();
+    options.event = Some("wall".to_string());
+    test_collapse_async_profiler(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_async_profiler_wall_event_without_state() {
+    let test_file = "./tests/data/collapse-async-profiler/spark.txt";
+    let result_file = "./tests/data/collapse-async-profiler/results/spark-wall-strip-state.txt";
+    let mut options = Options::default();
+    options.event = Some("wall".to_string());
+    options.strip_state = true;
+    test_collapse_async_profiler(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_async_profiler_should_warn_about_mixed_events() {
+    test_collapse_async_profiler_warnings(
+        "./tests/data/collapse-async-profiler/spark.txt",
+        Options::default(),
+        &[
+            "Ignored 1 lines without a valid sample count",
+            "Folded stacks of several event types (cpu, wall, alloc, lock), which don't add up; \
+             select one of them to fold",
+        ],
+    );
+}
+
+#[test]
+fn collapse_async_profiler_should_warn_about_stacks_without_event() {
+    let mut options = Options::default();
+    options.event = Some("cpu".to_string());
+    test_collapse_async_profiler_warnings(
+        "./tests/data/collapse-async-profiler/spark.txt",
+        options,
+        &[
+            "Ignored 1 lines without a valid sample count",
+            "Skipped 1 stacks without an event frame, such as [cpu]",
+        ],
+    );
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::bcc::{Folder, Options};
+
+fn test_collapse_bcc(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_bcc_profile() {
+    let test_file = "./tests/data/collapse-bcc/profile.txt";
+    let result_file = "./tests/data/collapse-bcc/results/profile.txt";
+    test_collapse_bcc(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bcc_profile_kernel_pid() {
+    let test_file = "./tests/data/collapse-bcc/profile.txt";
+    let result_file = "./tests/data/collapse-bcc/results/profile-kernel-pid.txt";
+
+    let mut options = Options::default();
+    options.annotate_kernel = true;
+    options.include_pid = true;
+
+    test_collapse_bcc(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_bcc_offcputime() {
+    let test_file = "./tests/data/collapse-bcc/offcputime.txt";
+    let result_file = "./tests/data/collapse-bcc/results/offcputime.txt";
+    test_collapse_bcc(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bcc_offcputime_kernel() {
+    let test_file = "./tests/data/collapse-bcc/offcputime.txt";
+    let result_file = "./tests/data/collapse-bcc/results/offcputime-kernel.txt";
+
+    let mut options = Options::default();
+    options.annotate_kernel = true;
+
+    test_collapse_bcc(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::clickhouse::{Folder, Options};
+use log::Level;
+
+fn test_collapse_clickhouse(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_clickhouse_warnings(test_file: &str, options: Options, expected: &[&str]) {
+    common::test_collapse_logs(Folder::from(options), test_file, |captured_logs| {
+        let warnings: Vec<_> = captured_logs
+            .iter()
+            .filter(|log| log.level == Level::Warn)
+            .map(|log| log.body.as_str())
+            .collect();
+        assert_eq!(warnings, expected);
+    });
+}
+
+#[test]
+fn collapse_clickhouse_trace_log() {
+    let test_file = "./tests/data/collapse-clickhouse/trace_log.tsv";
+    let result_file = "./tests/data/collapse-clickhouse/results/trace_log.txt";
+    test_collapse_clickhouse(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_clickhouse_trace_log_per_query() {
+    let test_file = "./tests/data/collapse-clickhouse/trace_log.tsv";
+    let result_file = "./tests/data/collapse-clickhouse/results/trace_log-query-id.txt";
+    let mut options = Options::default();
+    options.include_query_id = true;
+    test_collapse_clickhouse(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_clickhouse_aggregated() {
+    let test_file = "./tests/data/collapse-clickhouse/aggregated.tsv";
+    let result_file = "./tests/data/collapse-clickhouse/results/aggregated.txt";
+    test_collapse_clickhouse(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_clickhouse_should_warn_about_invalid_rows() {
+    test_collapse_clickhouse_warnings(
+        "./tests/data/collapse-clickhouse/trace_log.tsv",
+        Options::default(),
+        &["Ignored 1 rows without a trace or a valid sample count"],
+    );
+}
+
+#[test]
+fn collapse_clickhouse_should_warn_about_mixed_trace_types() {
+    test_collapse_clickhouse_warnings(
+        "./tests/data/collapse-clickhouse/mixed-trace-types.tsv",
+        Options::default(),
+        &[
+            "Ignored 1 rows without a trace or a valid sample count",
+            "Folded samples of several trace types (CPU, Real), which don't add up; select one \
+             of them to fold",
+        ],
+    );
+}
+
+#[test]
+fn collapse_clickhouse_trace_type() {
+    let test_file = "./tests/data/collapse-clickhouse/mixed-trace-types.tsv";
+    let result_file = "./tests/data/collapse-clickhouse/results/trace_log.txt";
+    let mut options = Options::default();
+    options.trace_type = Some("CPU".to_string());
+    test_collapse_clickhouse(test_file, result_file, options).unwrap()
+}
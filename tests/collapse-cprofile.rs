@@ -0,0 +1,119 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::cprofile::{Folder, Options};
+use inferno::collapse::Collapse;
+
+fn test_collapse_cprofile(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_cprofile_pstats() {
+    let test_file = "./tests/data/collapse-cprofile/cprofile.pstats";
+    let result_file = "./tests/data/collapse-cprofile/results/cprofile-pstats.txt";
+    test_collapse_cprofile(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_cprofile_pstats_no_filenames() {
+    let test_file = "./tests/data/collapse-cprofile/cprofile.pstats";
+    let result_file = "./tests/data/collapse-cprofile/results/cprofile-pstats-no-filenames.txt";
+
+    let mut options = Options::default();
+    options.no_filenames = true;
+
+    test_collapse_cprofile(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_cprofile_text() {
+    let test_file = "./tests/data/collapse-cprofile/cprofile.txt";
+    let result_file = "./tests/data/collapse-cprofile/results/cprofile-text.txt";
+    test_collapse_cprofile(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn cprofile_is_applicable() {
+    let mut folder = Folder::default();
+    let text = std::fs::read_to_string("./tests/data/collapse-cprofile/cprofile.txt").unwrap();
+    assert_eq!(folder.is_applicable(&text), Some(true));
+    assert_eq!(folder.is_applicable("{)\x03z\x06app.py"), Some(true));
+    assert_eq!(folder.is_applicable("{)"), None);
+    assert_ne!(folder.is_applicable("{\"traceEvents\": []}\n"), Some(true));
+    assert_ne!(folder.is_applicable("{main} 1\n"), Some(true));
+}
+
+// A minimal writer of the marshal format of `pstats` dumps.
+fn marshal_str(out: &mut Vec<u8>, s: &str) {
+    out.push(b'z');
+    out.push(s.len() as u8);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn marshal_key(out: &mut Vec<u8>, func: &str) {
+    out.extend_from_slice(&[b')', 3]);
+    marshal_str(out, "app.py");
+    out.push(b'i');
+    out.extend_from_slice(&1i32.to_le_bytes());
+    marshal_str(out, func);
+}
+
+fn marshal_timings(out: &mut Vec<u8>, tottime: f64, cumtime: f64) {
+    for calls in [1i32, 1] {
+        out.push(b'i');
+        out.extend_from_slice(&calls.to_le_bytes());
+    }
+    for time in [tottime, cumtime] {
+        out.push(b'g');
+        out.extend_from_slice(&time.to_le_bytes());
+    }
+}
+
+#[test]
+fn collapse_cprofile_diamonds() {
+    // Every function of a layer calls both functions of the next one, which makes for 2^40
+    // distinct paths through the call graph.
+    const LAYERS: usize = 40;
+    let name = |layer: usize, i: usize| format!("f{}_{}", layer, i);
+    let mut dump = vec![b'{'];
+    marshal_key(&mut dump, "main");
+    dump.extend_from_slice(&[b')', 5]);
+    marshal_timings(&mut dump, 0.0, 1.0);
+    dump.extend_from_slice(b"{0");
+    for layer in 0..LAYERS {
+        for i in 0..2 {
+            marshal_key(&mut dump, &name(layer, i));
+            dump.extend_from_slice(&[b')', 5]);
+            marshal_timings(&mut dump, 0.01, 0.5);
+            dump.push(b'{');
+            let callers = if layer == 0 {
+                vec!["main".to_string()]
+            } else {
+                vec![name(layer - 1, 0), name(layer - 1, 1)]
+            };
+            for caller in callers {
+                marshal_key(&mut dump, &caller);
+                dump.extend_from_slice(&[b')', 4]);
+                marshal_timings(&mut dump, 0.005, 0.25);
+            }
+            dump.push(b'0');
+        }
+    }
+    dump.push(b'0');
+
+    let mut folded = Vec::new();
+    Folder::default().collapse(&dump[..], &mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    let total: usize = folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse::<usize>().unwrap())
+        .sum();
+    assert!(total > 0);
+    assert!(total <= 1_000_000 + folded.lines().count());
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::dart::{Folder, Options};
+
+fn test_collapse_dart(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_dart_cpu_samples() {
+    let test_file = "./tests/data/collapse-dart/cpu-samples.json";
+    let result_file = "./tests/data/collapse-dart/results/cpu-samples.txt";
+    test_collapse_dart(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_dart_cpu_samples_include_isolate() {
+    let test_file = "./tests/data/collapse-dart/cpu-samples.json";
+    let result_file = "./tests/data/collapse-dart/results/cpu-samples-include-isolate.txt";
+
+    let mut options = Options::default();
+    options.include_isolate = true;
+
+    test_collapse_dart(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_dart_timeline_include_isolate() {
+    let test_file = "./tests/data/collapse-dart/timeline.json";
+    let result_file = "./tests/data/collapse-dart/results/timeline-include-isolate.txt";
+
+    let mut options = Options::default();
+    options.include_isolate = true;
+
+    test_collapse_dart(test_file, result_file, options).unwrap()
+}
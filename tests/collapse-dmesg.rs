@@ -0,0 +1,42 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::dmesg::{Folder, Options};
+
+fn test_collapse_dmesg(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_dmesg() {
+    let test_file = "./tests/data/collapse-dmesg/dmesg.txt";
+    let result_file = "./tests/data/collapse-dmesg/results/dmesg.txt";
+    test_collapse_dmesg(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_dmesg_include_unreliable_no_modules() {
+    let test_file = "./tests/data/collapse-dmesg/dmesg.txt";
+    let result_file = "./tests/data/collapse-dmesg/results/dmesg-include-unreliable-no-modules.txt";
+
+    let mut options = Options::default();
+    options.include_unreliable = true;
+    options.no_modules = true;
+
+    test_collapse_dmesg(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_dmesg_journal() {
+    let test_file = "./tests/data/collapse-dmesg/journal.txt";
+    let result_file = "./tests/data/collapse-dmesg/results/journal.txt";
+    test_collapse_dmesg(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_dmesg_ftrace() {
+    let test_file = "./tests/data/collapse-dmesg/ftrace.txt";
+    let result_file = "./tests/data/collapse-dmesg/results/ftrace.txt";
+    test_collapse_dmesg(test_file, result_file, Options::default()).unwrap()
+}
@@ -125,6 +125,17 @@ fn collapse_dtrace_rust_names() {
     test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_dtrace_escape_semicolons() {
+    let test_file = "./tests/data/collapse-dtrace/semicolons.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/semicolons-escaped.txt";
+
+    let mut options = Options::default();
+    options.escape_semicolons = true;
+
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
 #[test]
 fn collapse_dtrace_cli() {
     let input_file = "./flamegraph/example-dtrace-stacks.txt";
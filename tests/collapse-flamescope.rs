@@ -0,0 +1,69 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::flamescope::{Folder, Options};
+use log::Level;
+
+fn test_collapse_flamescope(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_flamescope_seconds() {
+    let test_file = "./tests/data/collapse-flamescope/perf.txt";
+    let result_file = "./tests/data/collapse-flamescope/results/seconds.txt";
+    test_collapse_flamescope(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_flamescope_range() {
+    let test_file = "./tests/data/collapse-flamescope/perf.txt";
+    let result_file = "./tests/data/collapse-flamescope/results/range.txt";
+    let mut options = Options::default();
+    options.bucket = 0.1;
+    options.start = Some(1.0);
+    options.end = Some(1.5);
+    test_collapse_flamescope(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_flamescope_event_filter() {
+    let test_file = "./tests/data/collapse-flamescope/perf.txt";
+    let result_file = "./tests/data/collapse-flamescope/results/instructions.txt";
+    let mut options = Options::default();
+    options.perf.event_filter = Some("instructions".to_string());
+    test_collapse_flamescope(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_flamescope_should_warn_about_events_without_time() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-flamescope/perf.txt",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(
+                warnings,
+                &["Ignored 1 events without a time; run perf script with -F ...,time,..."]
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_flamescope_should_return_error_for_input_without_time() {
+    let error = common::test_collapse_error(
+        Folder::default(),
+        "./tests/data/collapse-flamescope/no-time.txt",
+    );
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
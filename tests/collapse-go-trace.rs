@@ -0,0 +1,50 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::go_trace::{Folder, Options, Stacks};
+use inferno::collapse::Collapse;
+
+fn test_collapse_go_trace(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_go_trace_running() {
+    let test_file = "./tests/data/collapse-go-trace/trace.out";
+    let result_file = "./tests/data/collapse-go-trace/results/trace-running.txt";
+    test_collapse_go_trace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_go_trace_creation() {
+    let test_file = "./tests/data/collapse-go-trace/trace.out";
+    let result_file = "./tests/data/collapse-go-trace/results/trace-creation.txt";
+    let mut options = Options::default();
+    options.stacks = Stacks::Creation;
+    test_collapse_go_trace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_go_trace_old_version() {
+    let mut trace = b"go 1.21 trace\0\0\0".to_vec();
+    trace.extend_from_slice(&[0; 16]);
+    let err = Folder::default()
+        .collapse(&trace[..], io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    assert_eq!(
+        Folder::default().is_applicable("go 1.21 trace\0\0\0"),
+        Some(false)
+    );
+    assert_eq!(
+        Folder::default().is_applicable("go 1.22 trace\0\0\0"),
+        Some(true)
+    );
+    assert_eq!(Folder::default().is_applicable("main;foo 1\n"), None);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::gprof::{Folder, Options};
+
+fn test_collapse_gprof(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_gprof_call_graph() {
+    let test_file = "./tests/data/collapse-gprof/gprof.txt";
+    let result_file = "./tests/data/collapse-gprof/results/gprof.txt";
+    test_collapse_gprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_gprof_flat() {
+    let test_file = "./tests/data/collapse-gprof/gprof.txt";
+    let result_file = "./tests/data/collapse-gprof/results/gprof-flat.txt";
+
+    let mut options = Options::default();
+    options.flat = true;
+
+    test_collapse_gprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_gprof_no_profile() {
+    let mut folder = Folder::default();
+    let error = inferno::collapse::Collapse::collapse(
+        &mut folder,
+        "no profile here\n".as_bytes(),
+        io::sink(),
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::fs;
+use std::io;
+
+use inferno::collapse::gpu::{Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+
+fn test_collapse_gpu(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_gpu_renderdoc() {
+    let test_file = "./tests/data/collapse-gpu/renderdoc.csv";
+    let result_file = "./tests/data/collapse-gpu/results/renderdoc.txt";
+    test_collapse_gpu(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_gpu_renderdoc_strip_arguments() {
+    let test_file = "./tests/data/collapse-gpu/renderdoc.csv";
+    let result_file = "./tests/data/collapse-gpu/results/renderdoc-strip-arguments.txt";
+    let mut options = Options::default();
+    options.strip_arguments = true;
+    test_collapse_gpu(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_gpu_pix_indented() {
+    let test_file = "./tests/data/collapse-gpu/pix.csv";
+    let result_file = "./tests/data/collapse-gpu/results/pix.txt";
+    test_collapse_gpu(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_gpu_json() {
+    let test_file = "./tests/data/collapse-gpu/markers.json";
+    let result_file = "./tests/data/collapse-gpu/results/markers.txt";
+    test_collapse_gpu(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_gpu_should_warn_about_rows_without_name() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-gpu/pix.csv",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(warnings, ["Ignored 1 rows with invalid format"]);
+        },
+    );
+}
+
+#[test]
+fn collapse_gpu_is_applicable() {
+    for file in ["renderdoc.csv", "pix.csv", "markers.json"] {
+        let input = fs::read_to_string(format!("./tests/data/collapse-gpu/{}", file)).unwrap();
+        assert_eq!(Folder::default().is_applicable(&input), Some(true));
+    }
+    assert_eq!(Folder::default().is_applicable("main;foo 1\n"), Some(false));
+    assert_eq!(Folder::default().is_applicable("{\"samples\":[]}"), None);
+
+    let err = Folder::default()
+        .collapse(&b"Name,Count\nFrame,1\n"[..], io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
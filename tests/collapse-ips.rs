@@ -0,0 +1,71 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::ips::{Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+
+fn test_collapse_ips(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_ips_crash() {
+    let test_file = "./tests/data/collapse-ips/crash.ips";
+    let result_file = "./tests/data/collapse-ips/results/crash.txt";
+    test_collapse_ips(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_ips_crash_all_threads() {
+    let test_file = "./tests/data/collapse-ips/crash.ips";
+    let result_file = "./tests/data/collapse-ips/results/crash-all-threads.txt";
+    let mut options = Options::default();
+    options.all_threads = true;
+    options.include_process = true;
+    options.include_image = true;
+    test_collapse_ips(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_ips_multiple_reports() {
+    let test_file = "./tests/data/collapse-ips/multiple.ips";
+    let result_file = "./tests/data/collapse-ips/results/multiple.txt";
+    test_collapse_ips(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_ips_should_warn_about_reports_without_backtraces() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-ips/multiple.ips",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(warnings, ["Skipped 1 reports without thread backtraces"]);
+        },
+    );
+}
+
+#[test]
+fn collapse_ips_directory() {
+    let mut folder = Folder::default();
+    let mut folded = Vec::new();
+    folder
+        .collapse_paths(&["./tests/data/collapse-ips/hangs"], &mut folded)
+        .unwrap();
+    let expected = std::fs::read_to_string("./tests/data/collapse-ips/results/hangs.txt").unwrap();
+    assert_eq!(String::from_utf8(folded).unwrap(), expected);
+}
+
+#[test]
+fn collapse_ips_should_reject_input_without_reports() {
+    let mut folder = Folder::default();
+    let mut out = Vec::new();
+    let err = folder.collapse(&b"{}"[..], &mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
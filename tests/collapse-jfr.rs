@@ -0,0 +1,99 @@
+mod common;
+
+use std::fs;
+use std::io;
+
+use inferno::collapse::jfr::{Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+
+fn test_collapse_jfr(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_jfr_warnings(test_file: &str, expected_warnings: &[&str]) {
+    common::test_collapse_logs(Folder::default(), test_file, |captured_logs| {
+        let warnings: Vec<_> = captured_logs
+            .iter()
+            .filter(|log| log.level == Level::Warn)
+            .map(|log| log.body.as_str())
+            .collect();
+        assert_eq!(warnings, expected_warnings);
+    });
+}
+
+#[test]
+fn collapse_jfr_allocation_samples() {
+    let test_file = "./tests/data/collapse-jfr/allocation-samples.txt";
+    let result_file = "./tests/data/collapse-jfr/results/allocation-samples.txt";
+    test_collapse_jfr(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_jfr_allocation_samples_with_thread() {
+    let test_file = "./tests/data/collapse-jfr/allocation-samples.txt";
+    let result_file = "./tests/data/collapse-jfr/results/allocation-samples-thread.txt";
+    let mut options = Options::default();
+    options.include_thread = true;
+    test_collapse_jfr(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_jfr_allocation_samples_json() {
+    let test_file = "./tests/data/collapse-jfr/allocation-samples.json";
+    let result_file = "./tests/data/collapse-jfr/results/allocation-samples-json.txt";
+    test_collapse_jfr(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_jfr_tlab() {
+    let test_file = "./tests/data/collapse-jfr/tlab.txt";
+    let result_file = "./tests/data/collapse-jfr/results/tlab.txt";
+    test_collapse_jfr(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_jfr_mixed_prefers_allocation_samples() {
+    let test_file = "./tests/data/collapse-jfr/mixed.txt";
+    let result_file = "./tests/data/collapse-jfr/results/allocation-samples.txt";
+    test_collapse_jfr(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_jfr_should_warn_about_truncated_stacks() {
+    test_collapse_jfr_warnings(
+        "./tests/data/collapse-jfr/allocation-samples.txt",
+        &["1 stacks were truncated; give `jfr print` a larger --stack-depth to keep them whole"],
+    );
+}
+
+#[test]
+fn collapse_jfr_should_warn_about_skipped_events() {
+    test_collapse_jfr_warnings(
+        "./tests/data/collapse-jfr/mixed.txt",
+        &[
+            "Ignored 1 allocation events without a stack, class or size",
+            "1 stacks were truncated; give `jfr print` a larger --stack-depth to keep them whole",
+            "Skipped 3 TLAB allocation events, which the allocation samples already account for",
+        ],
+    );
+}
+
+#[test]
+fn collapse_jfr_is_applicable() {
+    for file in [
+        "allocation-samples.txt",
+        "tlab.txt",
+        "allocation-samples.json",
+    ] {
+        let input = fs::read_to_string(format!("./tests/data/collapse-jfr/{}", file)).unwrap();
+        assert_eq!(Folder::default().is_applicable(&input), Some(true));
+    }
+    assert_eq!(Folder::default().is_applicable("main;foo 1\n"), Some(false));
+    assert_eq!(Folder::default().is_applicable("{\"recording\":{}}"), None);
+
+    let err = Folder::default()
+        .collapse(&b"jdk.ExecutionSample {\n}\n"[..], io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::latency::{Folder, Options};
+use log::Level;
+
+fn test_collapse_latency(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_latency_stacks() {
+    let test_file = "./tests/data/collapse-latency/trace.txt";
+    let result_file = "./tests/data/collapse-latency/results/trace.txt";
+    test_collapse_latency(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_latency_stacks_weighted() {
+    let test_file = "./tests/data/collapse-latency/trace.txt";
+    let result_file = "./tests/data/collapse-latency/results/trace-weighted.txt";
+    let mut options = Options::default();
+    options.weighted = true;
+    test_collapse_latency(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_latency_band_width() {
+    let test_file = "./tests/data/collapse-latency/no-stacks.csv";
+    let result_file = "./tests/data/collapse-latency/results/no-stacks-band-width.txt";
+    let mut options = Options::default();
+    options.band_width = Some(250);
+    options.unit = "ms".to_string();
+    test_collapse_latency(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_latency_should_warn_about_invalid_lines() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-latency/trace.txt",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(
+                warnings,
+                &["Ignored 1 lines without a valid time and latency"]
+            );
+        },
+    );
+}
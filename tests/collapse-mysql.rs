@@ -0,0 +1,35 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::mysql::{Folder, Options};
+
+fn test_collapse_mysql(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_mysql_waits() {
+    let test_file = "./tests/data/collapse-mysql/waits.tsv";
+    let result_file = "./tests/data/collapse-mysql/results/waits.txt";
+    test_collapse_mysql(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_mysql_waits_event_name_depth() {
+    let test_file = "./tests/data/collapse-mysql/waits.tsv";
+    let result_file = "./tests/data/collapse-mysql/results/waits-event-name-depth.txt";
+
+    let mut options = Options::default();
+    options.event_name_depth = Some(3);
+    options.statement_width = Some(30);
+
+    test_collapse_mysql(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_mysql_statements_summary() {
+    let test_file = "./tests/data/collapse-mysql/statements-summary.csv";
+    let result_file = "./tests/data/collapse-mysql/results/statements-summary.txt";
+    test_collapse_mysql(test_file, result_file, Options::default()).unwrap()
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::nsys::{Folder, Options};
+
+fn test_collapse_nsys(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_nsys_samples() {
+    let test_file = "./tests/data/collapse-nsys/samples.csv";
+    let result_file = "./tests/data/collapse-nsys/results/samples.txt";
+    test_collapse_nsys(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_nsys_samples_nvtx() {
+    let test_file = "./tests/data/collapse-nsys/samples.csv";
+    let result_file = "./tests/data/collapse-nsys/results/samples-nvtx.txt";
+    let mut options = Options::default();
+    options.nvtx = true;
+    test_collapse_nsys(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_nsys_samples_thread_module() {
+    let test_file = "./tests/data/collapse-nsys/samples.csv";
+    let result_file = "./tests/data/collapse-nsys/results/samples-thread-module.txt";
+    let mut options = Options::default();
+    options.include_thread = true;
+    options.include_module = true;
+    test_collapse_nsys(test_file, result_file, options).unwrap()
+}
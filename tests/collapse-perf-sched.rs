@@ -0,0 +1,51 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::perf_sched::{Folder, Measure, Options};
+
+fn test_collapse_perf_sched(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_perf_sched_script() {
+    let test_file = "./tests/data/collapse-perf-sched/perf-script.txt";
+    let result_file = "./tests/data/collapse-perf-sched/results/perf-script.txt";
+    test_collapse_perf_sched(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_perf_sched_script_delay_wakers() {
+    let test_file = "./tests/data/collapse-perf-sched/perf-script.txt";
+    let result_file = "./tests/data/collapse-perf-sched/results/perf-script-delay-wakers.txt";
+
+    let mut options = Options::default();
+    options.measure = Measure::Delay;
+    options.wakers = true;
+
+    test_collapse_perf_sched(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_perf_sched_timehist() {
+    let test_file = "./tests/data/collapse-perf-sched/timehist.txt";
+    let result_file = "./tests/data/collapse-perf-sched/results/timehist.txt";
+    test_collapse_perf_sched(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_perf_sched_timehist_delay_tid() {
+    let test_file = "./tests/data/collapse-perf-sched/timehist.txt";
+    let result_file = "./tests/data/collapse-perf-sched/results/timehist-delay-tid.txt";
+
+    let mut options = Options::default();
+    options.measure = Measure::Delay;
+    options.include_tid = true;
+
+    test_collapse_perf_sched(test_file, result_file, options).unwrap()
+}
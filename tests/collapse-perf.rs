@@ -6,7 +6,8 @@ use std::path::Path;
 use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
-use inferno::collapse::perf::{Folder, Options};
+use inferno::collapse::perf::{Folder, MemWeight, Options};
+use inferno::collapse::Collapse;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -20,6 +21,7 @@ fn test_collapse_perf(
     for &n in &[1, 2] {
         let mut options = options.clone();
         options.nthreads = n;
+        options.multithreaded_min_bytes = 0;
         common::test_collapse(
             Folder::from(options),
             test_file,
@@ -55,6 +57,10 @@ fn options_from_vec(opt_vec: Vec<&str>) -> Options {
             "addrs" => options.include_addrs = true,
             "jit" => options.annotate_jit = true,
             "kernel" => options.annotate_kernel = true,
+            "memloads" => options.mem_data_source = Some(MemWeight::Loads),
+            "memlatency" => options.mem_data_source = Some(MemWeight::Latency),
+            "ocaml" => options.ocaml = true,
+            "kvm" => options.kvm = true,
             "all" => {
                 options.annotate_jit = true;
                 options.annotate_kernel = true;
@@ -234,7 +240,12 @@ collapse_perf_tests! {
     collapse_perf_go_stacks,
     collapse_perf_java_inline,
     collapse_perf_versioned_vmlinux__kernel,
-    collapse_perf_sourcepawn_jitdump__jit
+    collapse_perf_sourcepawn_jitdump__jit,
+    collapse_perf_perf_mem__memloads,
+    collapse_perf_perf_mem__memlatency,
+    collapse_perf_ocaml_stacks,
+    collapse_perf_ocaml_stacks__ocaml,
+    collapse_perf_perf_kvm__kvm
 }
 
 #[test]
@@ -248,6 +259,38 @@ fn collapse_perf_example_perf_stacks() {
     .unwrap();
 }
 
+#[test]
+fn collapse_perf_offline_symbols() {
+    let mut options = Options::default();
+    options.kallsyms = Some("./tests/data/collapse-perf/symbols/kallsyms".into());
+    options.symbol_maps = vec![
+        "./tests/data/collapse-perf/symbols/perf-1234.map".into(),
+        "./tests/data/collapse-perf/symbols/perf-5678.map".into(),
+    ];
+    options.annotate_kernel = true;
+    test_collapse_perf(
+        "./tests/data/collapse-perf/riscv-unsymbolized.txt",
+        "./tests/data/collapse-perf/results/riscv-unsymbolized-collapsed-symbols.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+fn collapse_perf_offline_symbols_missing_file() {
+    let mut options = Options::default();
+    options.kallsyms = Some("./tests/data/collapse-perf/symbols/missing".into());
+    let mut folder = Folder::from(options);
+    let error = folder
+        .collapse_file(
+            Some("./tests/data/collapse-perf/riscv-unsymbolized.txt"),
+            io::sink(),
+        )
+        .unwrap_err();
+    assert!(error.to_string().contains("symbols/missing"));
+}
+
 #[test]
 fn collapse_perf_should_warn_about_empty_input_lines() {
     test_collapse_perf_logs(
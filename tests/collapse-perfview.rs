@@ -0,0 +1,46 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::perfview::{Folder, Options};
+
+fn test_collapse_perfview(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_perfview_xml() {
+    let test_file = "./tests/data/collapse-perfview/perfview.perfView.xml";
+    let result_file = "./tests/data/collapse-perfview/results/perfview-xml.txt";
+    test_collapse_perfview(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_perfview_xml_zip() {
+    // The zipped export must produce exactly the same stacks as the plain XML.
+    let test_file = "./tests/data/collapse-perfview/perfview.perfView.xml.zip";
+    let result_file = "./tests/data/collapse-perfview/results/perfview-xml.txt";
+    test_collapse_perfview(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_perfview_xml_no_modules() {
+    let test_file = "./tests/data/collapse-perfview/perfview.perfView.xml";
+    let result_file = "./tests/data/collapse-perfview/results/perfview-xml-no-modules.txt";
+
+    let mut options = Options::default();
+    options.no_modules = true;
+
+    test_collapse_perfview(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_perfview_calltree() {
+    let test_file = "./tests/data/collapse-perfview/perfview-calltree.txt";
+    let result_file = "./tests/data/collapse-perfview/results/perfview-calltree.txt";
+    test_collapse_perfview(test_file, result_file, Options::default()).unwrap()
+}
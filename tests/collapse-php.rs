@@ -0,0 +1,46 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::php::{Folder, Options};
+
+fn test_collapse_php(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_php_phpspy() {
+    let test_file = "./tests/data/collapse-php/phpspy.txt";
+    let result_file = "./tests/data/collapse-php/results/phpspy.txt";
+    test_collapse_php(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_php_phpspy_strip_namespaces_group_by_uri() {
+    let test_file = "./tests/data/collapse-php/phpspy.txt";
+    let result_file = "./tests/data/collapse-php/results/phpspy-strip-namespaces-group-by-uri.txt";
+
+    let mut options = Options::default();
+    options.strip_namespaces = true;
+    options.group_by_uri = true;
+
+    test_collapse_php(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_php_excimer() {
+    let test_file = "./tests/data/collapse-php/excimer.json";
+    let result_file = "./tests/data/collapse-php/results/excimer.txt";
+    test_collapse_php(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_php_excimer_strip_namespaces() {
+    let test_file = "./tests/data/collapse-php/excimer.json";
+    let result_file = "./tests/data/collapse-php/results/excimer-strip-namespaces.txt";
+
+    let mut options = Options::default();
+    options.strip_namespaces = true;
+
+    test_collapse_php(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use inferno::collapse::pprof::{Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+
+fn test_collapse_pprof(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_pprof_paths(paths: &[&str], expected_file: &str, options: Options) {
+    let mut result = Vec::new();
+    Folder::from(options)
+        .collapse_paths(paths, &mut result)
+        .unwrap();
+    let expected = BufReader::new(fs::File::open(expected_file).unwrap());
+    common::compare_results(&result[..], expected, expected_file, false);
+}
+
+fn unix_time(seconds: u64) -> Option<SystemTime> {
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[test]
+fn collapse_pprof_heap_default_sample_type() {
+    let test_file = "./tests/data/collapse-pprof/heap.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/heap.txt";
+    test_collapse_pprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_pprof_heap_sample_type() {
+    let test_file = "./tests/data/collapse-pprof/heap.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/heap-alloc-space.txt";
+    let mut options = Options::default();
+    options.sample_type = Some("alloc_space".to_string());
+    test_collapse_pprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_pprof_gzipped() {
+    // Unlike `test_collapse`, read the profile without decompressing it first.
+    test_collapse_pprof_paths(
+        &["./tests/data/collapse-pprof/heap.pb.gz"],
+        "./tests/data/collapse-pprof/results/heap.txt",
+        Options::default(),
+    );
+}
+
+#[test]
+fn collapse_pprof_directory() {
+    test_collapse_pprof_paths(
+        &["./tests/data/collapse-pprof/profiles"],
+        "./tests/data/collapse-pprof/results/profiles.txt",
+        Options::default(),
+    );
+}
+
+#[test]
+fn collapse_pprof_directory_since_until() {
+    let mut options = Options::default();
+    options.since = unix_time(1714557600);
+    options.until = unix_time(1714561200);
+    test_collapse_pprof_paths(
+        &["./tests/data/collapse-pprof/profiles"],
+        "./tests/data/collapse-pprof/results/profiles-since-until.txt",
+        options,
+    );
+}
+
+#[test]
+fn collapse_pprof_files_sample_type() {
+    let mut options = Options::default();
+    options.sample_type = Some("samples".to_string());
+    test_collapse_pprof_paths(
+        &["./tests/data/collapse-pprof/profiles/2024-05-01T10-00-00.pb.gz"],
+        "./tests/data/collapse-pprof/results/cpu-samples.txt",
+        options,
+    );
+}
+
+#[test]
+fn collapse_pprof_should_warn_about_skipped_files_and_profiles() {
+    let mut options = Options::default();
+    options.since = unix_time(1714557600);
+    options.until = unix_time(1714561200);
+    testing_logger::setup();
+    Folder::from(options)
+        .collapse_paths(&["./tests/data/collapse-pprof/profiles"], io::sink())
+        .unwrap();
+    testing_logger::validate(|captured_logs| {
+        let warnings: Vec<_> = captured_logs
+            .iter()
+            .filter(|log| log.level == Level::Warn)
+            .map(|log| log.body.as_str())
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "Skipped ./tests/data/collapse-pprof/profiles/notes.txt, which is not a profile: \
+                 Invalid profile: unknown wire type 7",
+                "Skipped 1 profiles outside of the time range",
+            ]
+        );
+    });
+}
+
+#[test]
+fn collapse_pprof_errors() {
+    let err = Folder::default()
+        .collapse_paths(
+            &["./tests/data/collapse-pprof/profiles/notes.txt"],
+            io::sink(),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let err = Folder::default()
+        .collapse_paths(
+            &[PathBuf::from("./tests/data/collapse-pprof/results")],
+            io::sink(),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "No profiles found in input");
+}
+
+#[test]
+fn collapse_pprof_is_applicable() {
+    // A profile with a `cpu` sample type, and a string table of "" and "cpu".
+    let profile = "\n\u{2}\u{8}\u{1}2\u{0}2\u{3}cpu";
+    assert_eq!(Folder::default().is_applicable(profile), Some(true));
+    assert_eq!(Folder::default().is_applicable("main;foo 1\n"), Some(false));
+}
+
+#[test]
+#[cfg(feature = "reqwest")]
+fn collapse_pprof_url() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // Serves a profile to a single request, and returns the request.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buffer).unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..n]);
+        }
+        let profile = fs::read("./tests/data/collapse-pprof/heap.pb.gz").unwrap();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            profile.len()
+        )
+        .unwrap();
+        stream.write_all(&profile).unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let mut result = Vec::new();
+    Folder::default()
+        .collapse_url(
+            &format!("http://{}/debug/pprof/heap?gc=1", address),
+            &mut result,
+        )
+        .unwrap();
+    assert!(server
+        .join()
+        .unwrap()
+        .starts_with("GET /debug/pprof/heap?gc=1 HTTP/1.1\r\n"));
+
+    let expected_file = "./tests/data/collapse-pprof/results/heap.txt";
+    let expected = BufReader::new(fs::File::open(expected_file).unwrap());
+    common::compare_results(&result[..], expected, expected_file, false);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::react::{Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+
+fn test_collapse_react(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_react_profile() {
+    let test_file = "./tests/data/collapse-react/profile.json";
+    let result_file = "./tests/data/collapse-react/results/profile.txt";
+    test_collapse_react(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_react_profile_with_commit() {
+    let test_file = "./tests/data/collapse-react/profile.json";
+    let result_file = "./tests/data/collapse-react/results/profile-commit.txt";
+    let mut options = Options::default();
+    options.include_commit = true;
+    test_collapse_react(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_react_unsupported() {
+    let test_file = "./tests/data/collapse-react/unsupported.json";
+    let result_file = "./tests/data/collapse-react/results/unsupported.txt";
+    test_collapse_react(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_react_should_warn_about_unsupported_input() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-react/unsupported.json",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(
+                warnings,
+                [
+                    "Stopped rebuilding the component tree in 1 commits at tree operations that \
+                     are not supported; some components may be placed under the wrong parent",
+                    "1 components that rendered are missing from the component tree; they are \
+                     folded under [unknown]",
+                ]
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_react_should_reject_other_json() {
+    let mut folder = Folder::default();
+    let mut out = Vec::new();
+    let err = folder
+        .collapse(&br#"{"traceEvents": []}"#[..], &mut out)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
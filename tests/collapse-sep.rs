@@ -0,0 +1,28 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::sep::{Folder, Options};
+
+fn test_collapse_sep(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_sep_default() {
+    let test_file = "./tests/data/collapse-sep/stacks.txt";
+    let result_file = "./tests/data/collapse-sep/results/default.txt";
+    test_collapse_sep(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_sep_include_module_event() {
+    let test_file = "./tests/data/collapse-sep/stacks.txt";
+    let result_file = "./tests/data/collapse-sep/results/include-module-event.txt";
+
+    let mut options = Options::default();
+    options.include_module = true;
+    options.event = Some("INST_RETIRED.ANY".to_string());
+
+    test_collapse_sep(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::fs;
+use std::io;
+
+use inferno::collapse::signpost::{Folder, Options};
+use inferno::collapse::Collapse;
+use log::Level;
+
+fn test_collapse_signpost(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_signpost_ndjson() {
+    let test_file = "./tests/data/collapse-signpost/log.ndjson";
+    let result_file = "./tests/data/collapse-signpost/results/log.txt";
+    test_collapse_signpost(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_signpost_json() {
+    // `log show --style json` prints the same events as one array.
+    let test_file = "./tests/data/collapse-signpost/log.json";
+    let result_file = "./tests/data/collapse-signpost/results/log.txt";
+    test_collapse_signpost(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_signpost_json_process() {
+    let test_file = "./tests/data/collapse-signpost/log.ndjson";
+    let result_file = "./tests/data/collapse-signpost/results/log-process.txt";
+    let mut options = Options::default();
+    options.include_process = true;
+    test_collapse_signpost(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_signpost_instruments() {
+    let test_file = "./tests/data/collapse-signpost/os-signpost-interval.xml";
+    let result_file = "./tests/data/collapse-signpost/results/os-signpost-interval.txt";
+    test_collapse_signpost(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_signpost_instruments_process() {
+    let test_file = "./tests/data/collapse-signpost/os-signpost-interval.xml";
+    let result_file = "./tests/data/collapse-signpost/results/os-signpost-interval-process.txt";
+    let mut options = Options::default();
+    options.include_process = true;
+    test_collapse_signpost(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_signpost_should_warn_about_unmatched_intervals() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-signpost/log.ndjson",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(
+                warnings,
+                [
+                    "Ignored 1 signpost intervals that ended without beginning",
+                    "Ignored 1 signpost intervals that didn't end",
+                ]
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_signpost_is_applicable() {
+    for file in ["log.ndjson", "log.json", "os-signpost-interval.xml"] {
+        let input = fs::read_to_string(format!("./tests/data/collapse-signpost/{}", file)).unwrap();
+        assert_eq!(Folder::default().is_applicable(&input), Some(true));
+    }
+    assert_eq!(Folder::default().is_applicable("main;foo 1\n"), Some(false));
+    assert_eq!(Folder::default().is_applicable("{\"samples\":[]}"), None);
+
+    let err = Folder::default()
+        .collapse(&b"{\"eventType\":\"logEvent\"}"[..], io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::spans::{Folder, Options};
+use log::Level;
+
+fn test_collapse_spans(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_spans_jaeger() {
+    let test_file = "./tests/data/collapse-spans/jaeger.json";
+    let result_file = "./tests/data/collapse-spans/results/jaeger.txt";
+    test_collapse_spans(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_spans_jaeger_omit_services() {
+    let test_file = "./tests/data/collapse-spans/jaeger.json";
+    let result_file = "./tests/data/collapse-spans/results/jaeger-omit-services.txt";
+    let mut options = Options::default();
+    options.omit_services = true;
+    test_collapse_spans(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_spans_zipkin() {
+    let test_file = "./tests/data/collapse-spans/zipkin.json";
+    let result_file = "./tests/data/collapse-spans/results/zipkin.txt";
+    test_collapse_spans(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_spans_otlp() {
+    let test_file = "./tests/data/collapse-spans/otlp.jsonl";
+    let result_file = "./tests/data/collapse-spans/results/otlp.txt";
+    test_collapse_spans(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_spans_should_warn_about_invalid_spans() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-spans/jaeger.json",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(
+                warnings,
+                &["Ignored 1 spans without an id, a name or times"]
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_spans_should_warn_about_cycles() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-spans/zipkin.json",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(warnings, &["Ignored 2 spans whose parents form a cycle"]);
+        },
+    );
+}
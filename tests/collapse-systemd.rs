@@ -0,0 +1,52 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::systemd::{Folder, Options};
+use log::Level;
+
+fn test_collapse_systemd(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_systemd_plot_and_dump() {
+    let test_file = "./tests/data/collapse-systemd/boot.txt";
+    let result_file = "./tests/data/collapse-systemd/results/boot.txt";
+    test_collapse_systemd(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_systemd_plot_and_dump_flat() {
+    let test_file = "./tests/data/collapse-systemd/boot.txt";
+    let result_file = "./tests/data/collapse-systemd/results/boot-flat.txt";
+    let mut options = Options::default();
+    options.flat = true;
+    test_collapse_systemd(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_systemd_plot() {
+    let test_file = "./tests/data/collapse-systemd/plot.svg";
+    let result_file = "./tests/data/collapse-systemd/results/boot-flat.txt";
+    test_collapse_systemd(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_systemd_should_warn_about_dump_without_plot() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-systemd/dump.txt",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(
+                warnings,
+                &["No activation times found; the input needs the output of systemd-analyze plot"]
+            );
+        },
+    );
+}
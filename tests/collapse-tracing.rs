@@ -0,0 +1,35 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::tracing::{Folder, Options};
+
+fn test_collapse_tracing(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_tracing_fmt() {
+    let test_file = "./tests/data/collapse-tracing/fmt.log";
+    let result_file = "./tests/data/collapse-tracing/results/fmt.txt";
+    test_collapse_tracing(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_tracing_fmt_idle_include_fields() {
+    let test_file = "./tests/data/collapse-tracing/fmt.log";
+    let result_file = "./tests/data/collapse-tracing/results/fmt-idle-include-fields.txt";
+
+    let mut options = Options::default();
+    options.idle = true;
+    options.include_fields = true;
+
+    test_collapse_tracing(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_tracing_json() {
+    let test_file = "./tests/data/collapse-tracing/json.log";
+    let result_file = "./tests/data/collapse-tracing/results/json.txt";
+    test_collapse_tracing(test_file, result_file, Options::default()).unwrap()
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::unity::{Folder, Options};
+
+fn test_collapse_unity(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_unity_capture() {
+    let test_file = "./tests/data/collapse-unity/capture.json";
+    let result_file = "./tests/data/collapse-unity/results/capture.txt";
+    test_collapse_unity(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_unity_capture_with_threads() {
+    let test_file = "./tests/data/collapse-unity/capture.json";
+    let result_file = "./tests/data/collapse-unity/results/capture-threads.txt";
+    let mut options = Options::default();
+    options.include_thread = true;
+    test_collapse_unity(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_unity_capture_of_one_thread() {
+    let test_file = "./tests/data/collapse-unity/capture.json";
+    let result_file = "./tests/data/collapse-unity/results/capture-render-thread.txt";
+    let mut options = Options::default();
+    options.thread = Some("Render Thread".to_string());
+    test_collapse_unity(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::unreal::{Folder, Options};
+use log::Level;
+
+fn test_collapse_unreal(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_unreal_events() {
+    let test_file = "./tests/data/collapse-unreal/events.csv";
+    let result_file = "./tests/data/collapse-unreal/results/events.txt";
+    test_collapse_unreal(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_unreal_events_with_threads() {
+    let test_file = "./tests/data/collapse-unreal/events.csv";
+    let result_file = "./tests/data/collapse-unreal/results/events-threads.txt";
+    let mut options = Options::default();
+    options.include_thread = true;
+    test_collapse_unreal(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_unreal_events_without_names() {
+    let test_file = "./tests/data/collapse-unreal/events-ids.csv";
+    let result_file = "./tests/data/collapse-unreal/results/events-ids.txt";
+    let mut options = Options::default();
+    options.include_thread = true;
+    test_collapse_unreal(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_unreal_should_warn_about_invalid_rows() {
+    common::test_collapse_logs(
+        Folder::default(),
+        "./tests/data/collapse-unreal/events.csv",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert_eq!(
+                warnings,
+                &["Ignored 1 rows without a timer or valid start and end times"]
+            );
+        },
+    );
+}
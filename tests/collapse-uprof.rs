@@ -0,0 +1,28 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::uprof::{Folder, Options};
+
+fn test_collapse_uprof(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_uprof_default() {
+    let test_file = "./tests/data/collapse-uprof/callstacks.csv";
+    let result_file = "./tests/data/collapse-uprof/results/default.txt";
+    test_collapse_uprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_uprof_include_module_event() {
+    let test_file = "./tests/data/collapse-uprof/callstacks.csv";
+    let result_file = "./tests/data/collapse-uprof/results/include-module-event.txt";
+
+    let mut options = Options::default();
+    options.include_module = true;
+    options.event = Some("retired_inst samples".to_string());
+
+    test_collapse_uprof(test_file, result_file, options).unwrap()
+}
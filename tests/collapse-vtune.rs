@@ -124,3 +124,19 @@ fn collapse_vtune_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
 }
+
+#[test]
+fn collapse_vtune_cli_config() {
+    let input_file = "./tests/data/collapse-vtune/vtune.csv";
+    let expected_file = "./tests/data/collapse-vtune/results/vtune-no-modules.txt";
+
+    let output = Command::cargo_bin("inferno-collapse-vtune")
+        .unwrap()
+        .arg("--config")
+        .arg("./tests/data/config/inferno.toml")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
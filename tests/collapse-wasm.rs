@@ -0,0 +1,41 @@
+mod common;
+
+use std::fs;
+use std::io;
+
+use inferno::collapse::wasm::{self, Folder, Options};
+
+fn test_collapse_wasm(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_wasm_guest_profile() {
+    let test_file = "./tests/data/collapse-wasm/guest-profile.json";
+    let result_file = "./tests/data/collapse-wasm/results/guest-profile.txt";
+    test_collapse_wasm(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_wasm_guest_profile_module() {
+    let test_file = "./tests/data/collapse-wasm/guest-profile.json";
+    let result_file = "./tests/data/collapse-wasm/results/guest-profile-module.txt";
+
+    let module = fs::read("./tests/data/collapse-wasm/app.wasm").unwrap();
+    let mut options = Options::default();
+    options.function_names = wasm::function_names(&module).unwrap();
+
+    test_collapse_wasm(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_wasm_function_names() {
+    let module = fs::read("./tests/data/collapse-wasm/app.wasm").unwrap();
+    let names = wasm::function_names(&module).unwrap();
+    assert_eq!(names.len(), 4);
+    assert_eq!(names[&300], "memcpy");
+
+    assert!(wasm::function_names(b"\0asm\x01\0\0\0").unwrap().is_empty());
+    assert!(wasm::function_names(b"not wasm").is_err());
+    assert!(wasm::function_names(&module[..module.len() - 1]).is_err());
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use std::fs;
+use std::io;
+
+use inferno::collapse::xray::{self, Folder, Options};
+use inferno::collapse::Collapse;
+
+fn test_collapse_xray(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_xray_yaml() {
+    let test_file = "./tests/data/collapse-xray/trace.yaml";
+    let result_file = "./tests/data/collapse-xray/results/trace.txt";
+    test_collapse_xray(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xray_binary() {
+    let test_file = "./tests/data/collapse-xray/xray-log.app";
+    let result_file = "./tests/data/collapse-xray/results/xray-log-ids.txt";
+    test_collapse_xray(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xray_binary_instr_map() {
+    let test_file = "./tests/data/collapse-xray/xray-log.app";
+    // Named after the instrumentation map, the binary log folds the same as the YAML trace.
+    let result_file = "./tests/data/collapse-xray/results/trace.txt";
+
+    let instr_map = fs::read_to_string("./tests/data/collapse-xray/instr-map.yaml").unwrap();
+    let mut options = Options::default();
+    options.function_names = xray::function_names(&instr_map);
+    assert_eq!(options.function_names.len(), 6);
+    assert_eq!(options.function_names[&2], "parse(char const*)");
+
+    test_collapse_xray(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xray_is_applicable() {
+    let yaml = fs::read_to_string("./tests/data/collapse-xray/trace.yaml").unwrap();
+    assert_eq!(Folder::default().is_applicable(&yaml), Some(true));
+    assert_eq!(
+        Folder::default().is_applicable("\u{3}\0\0\0\u{3}\0\0\0"),
+        Some(true)
+    );
+    assert_eq!(Folder::default().is_applicable("main;foo 1\n"), None);
+
+    let fdr = [&[3, 0, 1, 0][..], &[0; 28]].concat();
+    let err = Folder::default()
+        .collapse(&fdr[..], io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
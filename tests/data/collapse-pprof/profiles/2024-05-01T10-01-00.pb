@@ -0,0 +1,11 @@
+
+
+* * * *	 *
+ * ""
+""
+""
+""
+"
+"""
+
+	2 2samples2count2cpu2nanoseconds2main.go2main2run2parse2lex2std::mem::swap2Vec<[u8; 4]>::pushHPȯ%
\ No newline at end of file
@@ -206,3 +206,28 @@ fn diff_folded_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     compare_results(Cursor::new(output.stdout), expected, expected_file);
 }
+
+#[test]
+fn diff_folded_cli_reverse() {
+    let infile1 = "./tests/data/diff-folded/before.txt";
+    let infile2 = "./tests/data/diff-folded/after.txt";
+
+    let output = Command::cargo_bin("inferno-diff-folded")
+        .unwrap()
+        .arg("--reverse")
+        .arg(infile1)
+        .arg(infile2)
+        .output()
+        .expect("failed to execute process");
+
+    let mut differential = Vec::new();
+    differential::from_files(Options::default(), infile1, infile2, &mut differential).unwrap();
+    let differential = String::from_utf8(differential).unwrap();
+    // The lines of a differential come in no particular order.
+    let mut expected: Vec<String> = inferno::folded::reverse(differential.lines()).collect();
+    expected.sort();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut reversed: Vec<&str> = stdout.lines().collect();
+    reversed.sort();
+    assert_eq!(reversed, expected);
+}
@@ -1,16 +1,17 @@
 mod common;
 
 use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Cursor};
+use std::io::{self, BufRead, BufReader, Cursor, Read};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
 
 use assert_cmd::cargo::CommandCargoExt;
 use clap::ValueEnum;
-use inferno::flamegraph::color::{BackgroundColor, PaletteMap};
+use inferno::flamegraph::color::{BackgroundColor, ColorModifier, PaletteMap, PaletteRange};
 use inferno::flamegraph::{
-    self, Direction, FrameWidthSource, Options, Palette, TextTruncateDirection,
+    self, report, Direction, FrameWidthSource, Options, Palette, ScriptSource,
+    TextTruncateDirection,
 };
 use log::Level;
 use pretty_assertions::assert_eq;
@@ -187,6 +188,78 @@ fn flamegraph_differential() {
     test_flamegraph(input_file, expected_result_file, Default::default()).unwrap();
 }
 
+#[test]
+fn flamegraph_differential_mixed_counts() {
+    test_flamegraph_logs(
+        "./tests/data/flamegraph/differential/missing-counts.txt",
+        |captured_logs| {
+            let warnings: Vec<_> = captured_logs
+                .iter()
+                .filter(|log| log.level == Level::Warn)
+                .map(|log| log.body.as_str())
+                .collect();
+            assert!(warnings.contains(
+                &"2 lines have one sample counts where the first line has two (lines 2, 4)"
+            ));
+        },
+    );
+}
+
+#[test]
+fn flamegraph_differential_missing_count_as_zero() {
+    let input_file = "./tests/data/flamegraph/differential/missing-counts.txt";
+    let expected_result_file = "./tests/data/flamegraph/differential/missing-counts.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.missing_count_as_zero = true;
+    options.frame_width_source = FrameWidthSource::Max;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_differential_color_by_age() {
+    let input_file = "./tests/data/flamegraph/differential/age.txt";
+    let expected_result_file = "./tests/data/flamegraph/differential/age.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.color_by_age = true;
+    options.frame_width_source = FrameWidthSource::Max;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_differential_diff_summary() {
+    let input_file = "./tests/data/flamegraph/differential/age.txt";
+    let expected_result_file = "./tests/data/flamegraph/differential/diff-summary.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.diff_summary = true;
+    options.include_children = true;
+    options.frame_width_source = FrameWidthSource::Max;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_diff_summary_without_differential_input() {
+    let mut options = flamegraph::Options::default();
+    options.diff_summary = true;
+    test_flamegraph_logs_with_options(
+        "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| log.body.starts_with("The diff summary needs"))
+                .count();
+            assert_eq!(nwarnings, 1);
+        },
+        options,
+    );
+}
+
 #[test]
 fn flamegraph_differential_including_children() {
     let input_file =
@@ -288,6 +361,92 @@ fn flamegraph_nameattr() {
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
+#[test]
+#[cfg(all(feature = "annotations", feature = "json"))]
+fn flamegraph_annotations() {
+    let input_file = "./tests/data/flamegraph/annotations/annotations.txt";
+    let expected_result_file = "./tests/data/flamegraph/annotations/annotations.svg";
+    let annotations_file = "./tests/data/flamegraph/annotations/annotations.json";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.func_annotations =
+        flamegraph::FuncFrameAnnotations::from_file(&PathBuf::from(annotations_file)).unwrap();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+#[cfg(all(feature = "nameattr", feature = "annotations", feature = "json"))]
+fn flamegraph_annotations_panel() {
+    let input_file = "./tests/data/flamegraph/annotations/annotations.txt";
+    let expected_result_file = "./tests/data/flamegraph/annotations/annotations-panel.svg";
+    let annotations_file = "./tests/data/flamegraph/annotations/annotations.json";
+    let nameattr_file = "./tests/data/flamegraph/annotations/nameattr.txt";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.annotations_panel = true;
+    options.func_annotations =
+        flamegraph::FuncFrameAnnotations::from_file(&PathBuf::from(annotations_file)).unwrap();
+    options.func_frameattrs =
+        flamegraph::FuncFrameAttrsMap::from_file(&PathBuf::from(nameattr_file)).unwrap();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+#[cfg(feature = "annotations")]
+fn flamegraph_stack_weights() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/stack-weights/stack-weights.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.weights_file = Some(PathBuf::from(
+        "./tests/data/flamegraph/stack-weights/weights.txt",
+    ));
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+#[cfg(feature = "annotations")]
+fn flamegraph_stack_weights_missing_file() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+    let mut options = flamegraph::Options::default();
+    options.weights_file = Some(PathBuf::from(
+        "./tests/data/flamegraph/stack-weights/none.txt",
+    ));
+
+    let e = flamegraph::from_files(&mut options, &input_files, io::sink()).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    assert!(e
+        .to_string()
+        .starts_with("Error reading stack weights from"));
+}
+
+#[test]
+#[cfg(feature = "annotations")]
+fn flamegraph_group_rules() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/group-rules/group-rules.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options
+        .group_rules
+        .add("^(parse|lex)$", "frontend")
+        .unwrap();
+    options
+        .group_rules
+        .add("^(lookup|apply)$", "runtime")
+        .unwrap();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
 #[test]
 #[cfg(feature = "nameattr")]
 fn flamegraph_nameattr_empty_line() {
@@ -474,6 +633,42 @@ fn flamegraph_palette_map_should_warn_about_invalid_lines() {
     });
 }
 
+#[test]
+fn flamegraph_embed_warnings() {
+    let input_file = "./tests/data/flamegraph/bad-lines/bad-lines.txt";
+    let expected_result_file = "./tests/data/flamegraph/bad-lines/embed-warnings.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.notes = "Test notes".to_string();
+    options.embed_warnings = true;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+#[cfg(feature = "mmap")]
+fn flamegraph_mmap_files_like_read_files() {
+    let input_files = vec![
+        PathBuf::from(
+            "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt",
+        ),
+        PathBuf::from("./tests/data/flamegraph/bad-lines/bad-lines.txt"),
+    ];
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.embed_warnings = true;
+    options.no_javascript = true;
+
+    let mut mapped = Vec::new();
+    flamegraph::from_files(&mut options, &input_files, &mut mapped).unwrap();
+    let readers = input_files.iter().map(|file| File::open(file).unwrap());
+    let mut read = Vec::new();
+    flamegraph::from_readers(&mut options, readers, &mut read).unwrap();
+
+    assert_eq!(String::from_utf8(mapped), String::from_utf8(read));
+}
+
 #[test]
 fn flamegraph_should_warn_about_bad_input_lines() {
     test_flamegraph_logs(
@@ -545,261 +740,1402 @@ fn flamegraph_should_prune_narrow_blocks() {
 }
 
 #[test]
-fn flamegraph_inverted() {
-    let input_file = "./flamegraph/test/results/perf-vertx-stacks-01-collapsed-all.txt";
-    let expected_result_file = "./tests/data/flamegraph/inverted/inverted.svg";
+fn flamegraph_should_aggregate_narrow_blocks() {
+    let input_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks.txt";
+    let expected_result_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks-aggregated.svg";
 
     let mut options = flamegraph::Options::default();
     options.hash = true;
-    options.title = "Icicle Graph".to_string();
-    options.direction = Direction::Inverted;
+    options.aggregate_narrow_frames = true;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_grey_frames() {
-    let input_file = "./tests/data/flamegraph/grey-frames/grey-frames.txt";
-    let expected_result_file = "./tests/data/flamegraph/grey-frames/grey-frames.svg";
+fn flamegraph_should_not_aggregate_narrow_blocks_of_flame_charts() {
+    let input_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks.txt";
+    let input_files = vec![PathBuf::from(input_file)];
 
     let mut options = flamegraph::Options::default();
     options.hash = true;
+    options.flame_chart = true;
+    let mut expected = Vec::new();
+    flamegraph::from_files(&mut options, &input_files, &mut expected).unwrap();
 
-    test_flamegraph(input_file, expected_result_file, options).unwrap();
+    options.aggregate_narrow_frames = true;
+    let mut result = Vec::new();
+    flamegraph::from_files(&mut options, &input_files, &mut result).unwrap();
+
+    assert_eq!(String::from_utf8(result), String::from_utf8(expected));
 }
 
 #[test]
-fn flamegraph_example_perf_stacks() {
-    let input_file = "./tests/data/collapse-perf/results/example-perf-stacks-collapsed.txt";
+fn flamegraph_should_not_label_narrow_frames() {
+    let input_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks.txt";
     let expected_result_file =
-        "./tests/data/flamegraph/example-perf-stacks/example-perf-stacks.svg";
-    let palette_file = "./tests/data/flamegraph/example-perf-stacks/palette.map";
-    let mut palette_map = load_palette_map_file(palette_file);
+        "./tests/data/flamegraph/narrow-blocks/narrow-blocks-min-frame-pixels.svg";
 
     let mut options = flamegraph::Options::default();
-    options.palette_map = Some(&mut palette_map);
+    options.hash = true;
+    options.min_frame_pixels = 60.0;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_default_options() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/default.svg";
+fn flamegraph_should_shorten_tooltips_of_narrow_frames() {
+    let input_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks.txt";
+    let expected_result_file =
+        "./tests/data/flamegraph/narrow-blocks/narrow-blocks-short-tooltips.svg";
 
-    let options = flamegraph::Options::default();
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.min_frame_pixels = 60.0;
+    options.short_narrow_tooltips = true;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_title_simple() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/title_simple.svg";
+fn flamegraph_should_scale_short_tooltips_by_factor() {
+    let input_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks.txt";
+    let expected_result_file =
+        "./tests/data/flamegraph/narrow-blocks/narrow-blocks-short-tooltips-factor.svg";
 
     let mut options = flamegraph::Options::default();
-    options.title = "Test Graph".to_owned();
+    options.hash = true;
+    options.min_frame_pixels = 60.0;
+    options.short_narrow_tooltips = true;
+    options.factor = 0.5;
+    options.count_name = "ms".to_string();
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
+// The script takes the names of frames from their tooltips, which are just the name when short,
+// and escapes them again when it copies a stack.
 #[test]
-fn flamegraph_title_with_symbols() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/title_with_symbols.svg";
-
+fn flamegraph_short_tooltips_keep_names() {
     let mut options = flamegraph::Options::default();
-    options.title = "Test <& ' \"".to_owned();
+    options.min_frame_pixels = 60.0;
+    options.short_narrow_tooltips = true;
+    let input = "main;operator() (int) 1\nmain;a\\;b 1000\n";
+    let mut svg = Vec::new();
+    flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
 
-    test_flamegraph(input_file, expected_result_file, options).unwrap();
+    assert!(svg.contains(r#"<title fg:short="">operator() (int)</title>"#));
+    assert!(svg.contains("<title>a;b (1,000 samples, 99.90%)</title>"));
+    assert!(svg.contains("g_to_path(e).map(escape_frame).join(\";\")"));
 }
 
 #[test]
-fn flamegraph_subtitle_simple() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/subtitle_simple.svg";
+fn flamegraph_signed_counts() {
+    let input_file = "./tests/data/flamegraph/signed-counts/signed-counts.txt";
+    let expected_result_file = "./tests/data/flamegraph/signed-counts/signed-counts.svg";
 
     let mut options = flamegraph::Options::default();
-    options.subtitle = Some("Test Subtitle".to_owned());
+    options.hash = true;
+    options.signed_counts = true;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_subtitle_with_symbols() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/subtitle_with_symbols.svg";
+fn flamegraph_signed_counts_embed_warnings() {
+    let input = "main;parse 10\nmain;parse -25\nmain;idle -5\nmain;render 30\n";
 
     let mut options = flamegraph::Options::default();
-    options.subtitle = Some("Test Subtitle <& ' \"".to_owned());
-
-    test_flamegraph(input_file, expected_result_file, options).unwrap();
+    options.signed_counts = true;
+    options.embed_warnings = true;
+    let mut result = Vec::new();
+    flamegraph::from_reader(&mut options, input.as_bytes(), &mut result).unwrap();
+    let result = String::from_utf8(result).unwrap();
+    assert!(result.contains(
+        "[inferno warnings: ignored_lines=0 truncated_fractional_samples=false clamped_stacks=2]"
+    ));
 }
 
 #[test]
-fn flamegraph_notes_simple() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/notes_simple.svg";
+fn flamegraph_signed_counts_flame_chart() {
+    // The lines of `main;parse` aren't adjacent, but are still netted out before clamping.
+    let input = "main;parse -5\nmain;render 30\nmain;parse 10\nmain;idle 5\nmain;idle -10\n";
 
     let mut options = flamegraph::Options::default();
-    options.notes = "Test Notes".to_owned();
-
-    test_flamegraph(input_file, expected_result_file, options).unwrap();
+    options.signed_counts = true;
+    options.flame_chart = true;
+    options.embed_warnings = true;
+    let mut result = Vec::new();
+    flamegraph::from_reader(&mut options, input.as_bytes(), &mut result).unwrap();
+    let result = String::from_utf8(result).unwrap();
+    assert!(result.contains("<title>all (35 samples, 100%)"));
+    assert!(result.contains("<title>parse (5 samples, 14.29%)"));
+    assert!(result.contains(
+        "[inferno warnings: ignored_lines=0 truncated_fractional_samples=false clamped_stacks=1]"
+    ));
 }
 
 #[test]
-fn flamegraph_notes_with_symbols() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/notes_with_symbols.svg";
+fn flamegraph_signed_counts_no_sort() {
+    // Sorted as whole lines, `main;a !x` sits between the lines of `main;a`.
+    let input = "main;a 10\nmain;a !x 1\nmain;a -15\nmain;b -2\nmain;b 4\n";
 
     let mut options = flamegraph::Options::default();
-    options.notes = "Test Notes <& ' \"".to_owned();
-
-    test_flamegraph(input_file, expected_result_file, options).unwrap();
+    options.signed_counts = true;
+    options.no_sort = true;
+    options.embed_warnings = true;
+    let mut result = Vec::new();
+    flamegraph::from_reader(&mut options, input.as_bytes(), &mut result).unwrap();
+    let result = String::from_utf8(result).unwrap();
+    assert!(result.contains("<title>all (3 samples, 100%)</title>"));
+    assert!(result.contains(
+        "[inferno warnings: ignored_lines=0 truncated_fractional_samples=false clamped_stacks=1]"
+    ));
 }
 
 #[test]
-fn flamegraph_count_name_simple() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/count_name_simple.svg";
+fn flamegraph_compact_header() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/compact-header/compact-header.svg";
 
     let mut options = flamegraph::Options::default();
-    options.count_name = "test-samples".to_owned();
+    options.hash = true;
+    options.subtitle = Some("Dashboard tile".to_string());
+    options.compact_header = true;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_count_name_with_symbols() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/count_name_with_symbols.svg";
+fn flamegraph_compact_header_inverted() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/compact-header/compact-header-inverted.svg";
 
     let mut options = flamegraph::Options::default();
-    options.count_name = "test-samples <& ' \"".to_owned();
+    options.hash = true;
+    options.compact_header = true;
+    options.direction = Direction::Inverted;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_name_type_simple() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/name_type_simple.svg";
+fn flamegraph_bottom_up() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/bottom-up/bottom-up.svg";
 
     let mut options = flamegraph::Options::default();
-    options.name_type = "Tfunction:".to_owned();
+    options.hash = true;
+    options.bottom_up = Some(2);
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_name_type_with_quote() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/name_type_with_quote.svg";
+fn flamegraph_top_stacks() {
+    let input_file = "./tests/data/flamegraph/top-stacks/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/top-stacks/top-stacks.svg";
 
     let mut options = flamegraph::Options::default();
-    options.name_type = "Test: '".to_owned();
+    options.hash = true;
+    options.top_stacks = Some(3);
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_name_type_with_backslash() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/name_type_with_backslash.svg";
+fn flamegraph_time_windows() {
+    let input_file = "./tests/data/flamegraph/trends/windows.txt";
+    let expected_result_file = "./tests/data/flamegraph/trends/windows.svg";
 
     let mut options = flamegraph::Options::default();
-    options.name_type = "Test: \\".to_owned();
+    options.hash = true;
+    options.time_windows = 4;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_font_type_simple() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/font_type_simple.svg";
-
+fn flamegraph_time_windows_width_window() {
+    let input = fs::read_to_string("./tests/data/flamegraph/trends/windows.txt").unwrap();
     let mut options = flamegraph::Options::default();
-    options.font_type = "Andale Mono".to_owned();
+    options.time_windows = 4;
+    options.width_window = Some(3);
+    let mut svg = Vec::new();
+    flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
 
-    test_flamegraph(input_file, expected_result_file, options).unwrap();
+    assert!(svg.contains("<title>lex (16 samples, 76.19%)\nTrend: ▁▃▅█ (1 → 16 samples)</title>"));
+    assert!(svg.contains("<title>eval (3 samples, 14.29%)\nTrend: █▆▄▂ (15 → 3 samples)</title>"));
+    // Frames without samples in the window are too narrow to draw, whatever their trend.
+    assert!(!svg.contains("<title>apply"));
+    assert!(svg.contains(r#"total_samples="21""#));
 }
 
 #[test]
-fn flamegraph_font_type_with_quote() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/font_type_with_quote.svg";
+fn flamegraph_self_time_shading() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/self-time-shading/self-time-shading.svg";
 
     let mut options = flamegraph::Options::default();
-    options.font_type = "Andale Mono\"".to_owned();
+    options.hash = true;
+    options.self_time_shading = true;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn flamegraph_font_type_generic_families() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-
-    let generic_families = &["cursive", "fantasy", "monospace", "serif", "sans-serif"];
-    for family in generic_families {
-        let expected_result_file =
-            format!("./tests/data/flamegraph/options/font_type_{}.svg", family);
+fn flamegraph_escaped_semicolons() {
+    let input_file = "./tests/data/flamegraph/escaped-semicolons/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/escaped-semicolons/escaped-semicolons.svg";
 
-        let mut options = flamegraph::Options::default();
-        options.font_type = family.to_string();
+    let options = flamegraph::Options::default();
 
-        test_flamegraph(input_file, &expected_result_file, options).unwrap();
-    }
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn search_color_non_default() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/search_color.svg";
+fn flamegraph_escaped_semicolons_reversed() {
+    let input_file = "./tests/data/flamegraph/escaped-semicolons/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/escaped-semicolons/reversed.svg";
 
     let mut options = flamegraph::Options::default();
-    options.search_color = "#7d7d7d".parse().unwrap();
+    options.reverse_stack_order = true;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
+// Backslashes are shown as they are, as they always have been, except for the doubled ones at the
+// end of a frame that keep it from escaping the semicolon after it.
 #[test]
-fn stroke_color_non_default() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/stroke_color.svg";
+fn flamegraph_backslashes() {
+    let input_file = "./tests/data/flamegraph/backslashes/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/backslashes/backslashes.svg";
 
     let mut options = flamegraph::Options::default();
-    options.stroke_color = "#7d7d7d".parse().unwrap();
+    options.hash = true;
 
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
-fn ui_color_non_default() {
-    let input_file =
-        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
-    let expected_result_file = "./tests/data/flamegraph/options/uicolor_color.svg";
+fn flamegraph_inverted() {
+    let input_file = "./flamegraph/test/results/perf-vertx-stacks-01-collapsed-all.txt";
+    let expected_result_file = "./tests/data/flamegraph/inverted/inverted.svg";
 
     let mut options = flamegraph::Options::default();
-    options.uicolor = rgb::RGB8 { r: 255, g: 0, b: 0 };
-
-    test_flamegraph(input_file, expected_result_file, options).unwrap();
+    options.hash = true;
+    options.title = "Icicle Graph".to_string();
+    options.direction = Direction::Inverted;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_grey_frames() {
+    let input_file = "./tests/data/flamegraph/grey-frames/grey-frames.txt";
+    let expected_result_file = "./tests/data/flamegraph/grey-frames/grey-frames.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_example_perf_stacks() {
+    let input_file = "./tests/data/collapse-perf/results/example-perf-stacks-collapsed.txt";
+    let expected_result_file =
+        "./tests/data/flamegraph/example-perf-stacks/example-perf-stacks.svg";
+    let palette_file = "./tests/data/flamegraph/example-perf-stacks/palette.map";
+    let mut palette_map = load_palette_map_file(palette_file);
+
+    let mut options = flamegraph::Options::default();
+    options.palette_map = Some(&mut palette_map);
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_default_options() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/default.svg";
+
+    let options = flamegraph::Options::default();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_title_simple() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/title_simple.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.title = "Test Graph".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_title_with_symbols() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/title_with_symbols.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.title = "Test <& ' \"".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_subtitle_simple() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/subtitle_simple.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.subtitle = Some("Test Subtitle".to_owned());
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_subtitle_with_symbols() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/subtitle_with_symbols.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.subtitle = Some("Test Subtitle <& ' \"".to_owned());
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_notes_simple() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/notes_simple.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.notes = "Test Notes".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_notes_with_symbols() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/notes_with_symbols.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.notes = "Test Notes <& ' \"".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_count_name_simple() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/count_name_simple.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.count_name = "test-samples".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_count_name_with_symbols() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/count_name_with_symbols.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.count_name = "test-samples <& ' \"".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_name_type_simple() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/name_type_simple.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.name_type = "Tfunction:".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_name_type_with_quote() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/name_type_with_quote.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.name_type = "Test: '".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_name_type_with_backslash() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/name_type_with_backslash.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.name_type = "Test: \\".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_width_sizing_fluid_max() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/width_sizing_fluid_max.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.width_sizing = Some(flamegraph::WidthSizing::FluidMax(1600));
+    options.xpad = 20;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_width_sizing_viewbox() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/width_sizing_viewbox.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.image_width = Some(800);
+    options.width_sizing = Some(flamegraph::WidthSizing::ViewBox);
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_flame_strip() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/flame_strip.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.strip_depth = Some(2);
+    options.full_graph_link = Some("default.svg".to_string());
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_omit_root() {
+    let input_file = "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/omit_root.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.deterministic = true;
+    options.omit_root = true;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_omit_root_inverted() {
+    let input_file = "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/omit_root-inverted.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.deterministic = true;
+    options.omit_root = true;
+    options.direction = Direction::Inverted;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+// Without the root frame, the script must keep the bottom frame of a path, and count search
+// matches against all samples rather than against the widest frame.
+#[test]
+fn flamegraph_omit_root_script() {
+    let input = "main;foo 10\nmain;bar 1\nother 5\n";
+    let render = |omit_root| {
+        let mut options = flamegraph::Options::default();
+        options.omit_root = omit_root;
+        let mut svg = Vec::new();
+        flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let with_root = render(false);
+    assert!(!with_root.contains("var omitroot"));
+    assert!(with_root.contains("<title>all ("));
+
+    let without_root = render(true);
+    assert!(without_root.contains("var omitroot = true;"));
+    assert!(!without_root.contains("<title>all ("));
+    assert!(without_root.contains(r#"if (typeof omitroot == "undefined") stack.shift();"#));
+    assert!(without_root.contains("var pct = 100 * count / total;"));
+    assert!(without_root.contains(r#"total_samples="16""#));
+}
+
+#[test]
+fn flamegraph_depth_histogram() {
+    let input_file = "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/depth-histogram.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.deterministic = true;
+    options.depth_histogram = true;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_layout_diff() {
+    let before = ["main;parse;lex 20", "main;eval 70", "main;idle 10"];
+    let after = ["main;parse;lex 45", "main;eval 50", "main;gc 5"];
+
+    let mut options = flamegraph::Options::default();
+    let changes = flamegraph::layout::diff(&mut options, before, after, 5.0).unwrap();
+    let changes: Vec<_> = changes
+        .iter()
+        .map(|change| (change.stack.as_str(), change.delta_pct().round()))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            ("main;parse", 25.0),
+            ("main;parse;lex", 25.0),
+            ("main;eval", -20.0),
+            ("main;idle", -10.0),
+        ]
+    );
+
+    // Frames are those of the flame graph that the options draw.
+    options.reverse_stack_order = true;
+    let changes = flamegraph::layout::diff(&mut options, before, after, 5.0).unwrap();
+    assert_eq!(changes[0].stack, "lex");
+    assert_eq!(changes[0].before_pct, 20.0);
+    assert_eq!(changes[0].after_pct, 45.0);
+
+    // Stacks stay escaped, and their frames don't.
+    options.reverse_stack_order = false;
+    let before = [r"main;<[u8\; 8] as Debug>::fmt 10", "main;eval 10"];
+    let after = [r"main;<[u8\; 8] as Debug>::fmt 30", "main;eval 10"];
+    let changes = flamegraph::layout::diff(&mut options, before, after, 20.0).unwrap();
+    assert_eq!(changes[0].stack, r"main;<[u8\; 8] as Debug>::fmt");
+    assert_eq!(
+        changes[0].frames().collect::<Vec<_>>(),
+        ["main", "<[u8; 8] as Debug>::fmt"]
+    );
+}
+
+#[test]
+fn flamegraph_patch() {
+    let mut options = flamegraph::Options::default();
+    options.deterministic = true;
+    let snapshot = |lines: &[&str], options: &mut flamegraph::Options<'_>| {
+        let mut svg = Vec::new();
+        flamegraph::patch::Snapshot::render(options, lines.iter().copied(), &mut svg).unwrap()
+    };
+    let before = snapshot(&["main;parse;lex 20", "main;eval 80"], &mut options);
+    let after = snapshot(
+        &["main;parse;lex 20", "main;eval 60", "main;gc 20"],
+        &mut options,
+    );
+
+    let patch = before.patch(&after).unwrap();
+    assert_eq!(
+        patch.changed().collect::<Vec<_>>(),
+        ["all;main;eval", "all;main;gc"]
+    );
+    assert!(patch.removed().is_empty());
+    let json: serde_json::Value = serde_json::from_str(&patch.to_json()).unwrap();
+    assert_eq!(json["total_samples"], 100);
+    assert_eq!(json["frames"][1]["key"], "all;main;gc");
+    assert!(json["frames"][1]["svg"]
+        .as_str()
+        .unwrap()
+        .starts_with("<g><title>gc (20 samples, 20.00%)</title>"));
+
+    let patch = after.patch(&before).unwrap();
+    assert_eq!(patch.removed(), ["all;main;gc"]);
+    assert!(before.patch(&before).unwrap().is_empty());
+
+    // Frames that change with the total, such as their positions, are laid out by the script.
+    let bigger = snapshot(&["main;parse;lex 40", "main;eval 160"], &mut options);
+    let patch = before.patch(&bigger).unwrap();
+    assert!(!patch.is_empty());
+    assert_eq!(patch.changed().count(), 5);
+
+    // Anything else that changes needs a reload.
+    options.title = "Another title".to_string();
+    let retitled = snapshot(&["main;parse;lex 20", "main;eval 80"], &mut options);
+    assert_eq!(before.patch(&retitled), None);
+}
+
+#[test]
+fn flamegraph_patch_flame_chart() {
+    let mut options = flamegraph::Options::default();
+    options.deterministic = true;
+    options.flame_chart = true;
+    let mut svg = Vec::new();
+    let lines = ["main;work 10", "main;idle 10", "main;work 10"];
+    let before = flamegraph::patch::Snapshot::render(&mut options, lines, &mut svg).unwrap();
+    let lines = ["main;work 10", "main;idle 10"];
+    let after = flamegraph::patch::Snapshot::render(&mut options, lines, &mut svg).unwrap();
+
+    // Runs of the same stack are told apart by their order.
+    let patch = before.patch(&after).unwrap();
+    assert_eq!(patch.removed(), ["all;main;work#1"]);
+}
+
+#[test]
+fn flamegraph_live_updates() {
+    let mut options = flamegraph::Options::default();
+    options.live_updates = Some("/updates".to_string());
+    let mut svg = Vec::new();
+    flamegraph::from_reader(&mut options, "main 1".as_bytes(), &mut svg).unwrap();
+    assert!(String::from_utf8(svg)
+        .unwrap()
+        .contains("var liveupdates = '/updates';"));
+}
+
+#[test]
+fn flamegraph_status_bar() {
+    let render = |show_status_bar| {
+        let mut options = flamegraph::Options::default();
+        options.show_status_bar = show_status_bar;
+        options.details_template = Some("{name}: {percent}%".to_string());
+        let mut svg = Vec::new();
+        flamegraph::from_reader(&mut options, "main 1".as_bytes(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let shown = render(true);
+    assert!(shown.contains(r#"id="details""#));
+    assert!(shown.contains("var detailstemplate = '{name}: {percent}%';"));
+
+    let hidden = render(false);
+    assert!(!hidden.contains(r#"id="details""#));
+    assert!(!hidden.contains("var detailstemplate"));
+    assert!(hidden.contains(r#"id="matched""#));
+}
+
+// The details line works its counts out from the frame widths, so it has to scale them like the
+// tooltips do.
+#[test]
+fn flamegraph_status_bar_factor() {
+    let mut options = flamegraph::Options::default();
+    options.factor = 2.5;
+    options.details_template = Some("{name}: {samples}".to_string());
+    let mut svg = Vec::new();
+    flamegraph::from_reader(&mut options, "main 2".as_bytes(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    assert!(svg.contains("var factor = 2.5;"));
+    assert!(svg.contains(r#"samples: scaled_count(w).toLocaleString("en"),"#));
+    assert!(svg.contains("<title>main (5 samples, 100.00%)</title>"));
+}
+
+#[test]
+fn flamegraph_filter_button_only_with_javascript() {
+    let input = "main;foo 10\nmain;bar 1\n";
+    let render = |no_javascript| {
+        let mut options = flamegraph::Options::default();
+        options.no_javascript = no_javascript;
+        let mut svg = Vec::new();
+        flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let with_js = render(false);
+    assert!(with_js.contains(r#"id="filter""#));
+    assert!(with_js.contains(".filtered { display:none; }"));
+
+    let without_js = render(true);
+    assert!(!without_js.contains(r#"id="filter""#));
+    assert!(!without_js.contains(".filtered"));
+}
+
+#[test]
+fn flamegraph_script_source() {
+    let input = "main;foo 10\nmain;bar 1\n";
+    let render = |script_source| {
+        let mut options = flamegraph::Options::default();
+        options.script_source = script_source;
+        let mut svg = Vec::new();
+        flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let embedded = render(ScriptSource::Embedded);
+    assert!(embedded.contains(flamegraph::SCRIPT));
+    assert!(embedded.contains(r#"onload="init(evt)""#));
+    assert!(!embedded.contains("var scriptversion"));
+
+    let url = "https://example.com/inferno/flamegraph-1.js";
+    let external = render(ScriptSource::External(url.to_string()));
+    assert!(!external.contains("function init(evt)"));
+    assert!(external.contains(&format!(
+        r#"<script type="text/ecmascript" xlink:href="{}"/>"#,
+        url
+    )));
+    assert!(external.contains(&format!(
+        "var scriptversion = '{}';",
+        flamegraph::SCRIPT_VERSION
+    )));
+    assert!(external.contains(r#"id="filter""#));
+    assert!(external.len() < embedded.len());
+
+    let none = render(ScriptSource::None);
+    assert!(!none.contains("<script"));
+    assert!(!none.contains("onload"));
+    assert!(!none.contains(r#"id="filter""#));
+}
+
+#[test]
+fn flamegraph_svgz() {
+    let input = "main;foo 10\nmain;bar 1\n";
+    let render = |svgz| {
+        let mut options = flamegraph::Options::default();
+        options.svgz = svgz;
+        options.hash = true;
+        let mut svg = Vec::new();
+        flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+        svg
+    };
+
+    let svg = render(false);
+    let svgz = render(true);
+    assert!(svgz.len() < svg.len());
+
+    let mut decompressed = Vec::new();
+    libflate::gzip::Decoder::new(&svgz[..])
+        .unwrap()
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(decompressed).unwrap(),
+        String::from_utf8(svg).unwrap()
+    );
+}
+
+#[test]
+fn flamegraph_fragment() {
+    let input = "main;foo 10\nmain;bar 1\n";
+    let render = |fragment| {
+        let mut options = flamegraph::Options::default();
+        options.fragment = fragment;
+        options.no_javascript = true;
+        options.hash = true;
+        let mut svg = Vec::new();
+        flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let document = render(false);
+    assert!(document.starts_with("<?xml"));
+    assert!(document.contains("<!DOCTYPE svg"));
+
+    let fragment = render(true);
+    assert!(fragment.starts_with("<svg "));
+    assert!(!fragment.contains("<!DOCTYPE"));
+    assert_eq!(fragment, &document[document.find("<svg ").unwrap()..]);
+}
+
+#[test]
+fn flamegraph_frame_hook() {
+    let input_file = "./tests/data/flamegraph/frame-hook/frame-hook.txt";
+    let expected_result_file = "./tests/data/flamegraph/frame-hook/frame-hook.svg";
+
+    let mut seen = Vec::new();
+    let mut hook = |frame: &mut flamegraph::FrameInfo<'_>| {
+        seen.push((frame.function.to_string(), frame.depth, frame.samples));
+        match frame.function {
+            "tokio::runtime::park" => {
+                frame.name = "park".to_string();
+                frame.tooltip = format!("{} [idle]", frame.tooltip);
+                frame.color = Some(flamegraph::color::Color { r: 0, g: 0, b: 255 });
+            }
+            "std::io::read" => {
+                frame.href = Some("https://doc.rust-lang.org/std/io/trait.Read.html".to_string());
+            }
+            "worker" => return false,
+            _ => {}
+        }
+        true
+    };
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.frame_hook = Some(flamegraph::FrameHook::new(&mut hook));
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+
+    assert!(seen.contains(&("".to_string(), 0, 100)));
+    assert!(seen.contains(&("worker".to_string(), 2, 60)));
+}
+
+#[test]
+fn flamegraph_seeded_random() {
+    let input_file = "./tests/data/flamegraph/frame-hook/frame-hook.txt";
+    let expected_result_file = "./tests/data/flamegraph/seeded-random/seeded-random.svg";
+
+    // Colors are picked at random, but from the same numbers every time.
+    let mut random = flamegraph::SeededRandom::new(42);
+    let mut options = flamegraph::Options::default();
+    options.random_source = Some(&mut random);
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+
+    let render = |seed| {
+        let mut random = flamegraph::SeededRandom::new(seed);
+        let mut options = flamegraph::Options::default();
+        options.random_source = Some(&mut random);
+        let mut svg = Vec::new();
+        flamegraph::from_files(&mut options, &[PathBuf::from(input_file)], &mut svg).unwrap();
+        svg
+    };
+    assert_eq!(render(7), render(7));
+    assert_ne!(render(7), render(8));
+}
+
+#[test]
+fn flamegraph_footer_and_watermark_path() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/watermark/path.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.footer = Some("Build 2024.06.1 (a1b2c3d)".to_string());
+    let mut watermark = flamegraph::Watermark::new(flamegraph::WatermarkImage::Path {
+        data: "M12 2L2 22h20z".to_string(),
+        width: 24.0,
+        height: 24.0,
+    });
+    watermark.corner = flamegraph::Corner::TopRight;
+    options.watermark = Some(watermark);
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_watermark_data_uri() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/watermark/data-uri.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    let mut watermark = flamegraph::Watermark::new(flamegraph::WatermarkImage::DataUri(
+        "data:image/gif;base64,R0lGODlhAQABAAAAACw=".to_string(),
+    ));
+    watermark.corner = flamegraph::Corner::BottomLeft;
+    watermark.width = 64;
+    watermark.height = 32;
+    watermark.opacity = 0.5;
+    options.watermark = Some(watermark);
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+fn test_flamegraph_hot_cold(expected_result_file: &str, mut options: Options<'_>) {
+    let on_cpu = [PathBuf::from("./tests/data/flamegraph/hot-cold/on-cpu.txt")];
+    let off_cpu = [PathBuf::from(
+        "./tests/data/flamegraph/hot-cold/off-cpu.txt",
+    )];
+    options.pretty_xml = true;
+    options.no_javascript = true;
+    options.hash = true;
+    // off-CPU time is in microseconds, on-CPU stacks are 100 Hz samples
+    options.off_cpu_factor = 100.0 / 1_000_000.0;
+
+    if !Path::new(expected_result_file).exists() || std::env::var("INFERNO_BLESS_TESTS").is_ok() {
+        let mut f = File::create(expected_result_file).unwrap();
+        flamegraph::from_hot_cold_files(&mut options, &on_cpu, &off_cpu, &mut f).unwrap();
+    }
+
+    let mut result = Cursor::new(Vec::new());
+    flamegraph::from_hot_cold_files(&mut options, &on_cpu, &off_cpu, &mut result).unwrap();
+    result.set_position(0);
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    compare_results(result, expected, expected_result_file);
+}
+
+#[test]
+fn flamegraph_hot_cold_side_by_side() {
+    test_flamegraph_hot_cold(
+        "./tests/data/flamegraph/hot-cold/side-by-side.svg",
+        Options::default(),
+    );
+}
+
+#[test]
+fn flamegraph_hot_cold_interleaved() {
+    let mut options = Options::default();
+    options.hot_cold_layout = flamegraph::HotColdLayout::Interleaved;
+    options.off_cpu_colors = Palette::from_str("io").unwrap();
+    test_flamegraph_hot_cold("./tests/data/flamegraph/hot-cold/interleaved.svg", options);
+}
+
+fn test_flamegraph_stacked(expected_result_file: &str, mut options: Options<'_>) {
+    let files = ["host-a.txt", "host-b.txt", "host-c.txt"]
+        .map(|file| PathBuf::from("./tests/data/flamegraph/stacked").join(file));
+    options.pretty_xml = true;
+    options.no_javascript = true;
+    options.hash = true;
+    options.stacked_inputs = true;
+
+    if !Path::new(expected_result_file).exists() || std::env::var("INFERNO_BLESS_TESTS").is_ok() {
+        let mut f = File::create(expected_result_file).unwrap();
+        flamegraph::from_files(&mut options, &files, &mut f).unwrap();
+    }
+
+    let mut result = Cursor::new(Vec::new());
+    flamegraph::from_files(&mut options, &files, &mut result).unwrap();
+    result.set_position(0);
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    compare_results(result, expected, expected_result_file);
+}
+
+#[test]
+fn flamegraph_stacked_inputs() {
+    test_flamegraph_stacked(
+        "./tests/data/flamegraph/stacked/common.svg",
+        Options::default(),
+    );
+}
+
+#[test]
+fn flamegraph_stacked_inputs_independent() {
+    let mut options = Options::default();
+    options.stacked_scale = flamegraph::StackedScale::Independent;
+    test_flamegraph_stacked("./tests/data/flamegraph/stacked/independent.svg", options);
+}
+
+#[test]
+fn flamegraph_suffix_annotations() {
+    let input_file = "./tests/data/flamegraph/suffix-annotations/suffix-annotations.txt";
+    let expected_result_file = "./tests/data/flamegraph/suffix-annotations/suffix-annotations.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.suffix_annotations.insert(
+        0,
+        flamegraph::SuffixAnnotation::from_str("_[g]=GPU:green").unwrap(),
+    );
+    options.suffix_annotations[1].label = Some("kernel".to_string());
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_suffix_annotation_from_str() {
+    use flamegraph::SuffixAnnotation;
+
+    let annotation = SuffixAnnotation::from_str("_[g]").unwrap();
+    assert_eq!(annotation, SuffixAnnotation::new("_[g]"));
+
+    let annotation = SuffixAnnotation::from_str("_[g]=GPU: shaders:blue").unwrap();
+    assert_eq!(annotation.label.as_deref(), Some("GPU: shaders"));
+    assert_eq!(annotation.colors, Some(Palette::from_str("blue").unwrap()));
+
+    let annotation = SuffixAnnotation::from_str("_[g]=:blue").unwrap();
+    assert_eq!(annotation.label, None);
+
+    assert!(SuffixAnnotation::from_str("_[g]=GPU:no-such-palette").is_err());
+    assert!(SuffixAnnotation::from_str("=GPU").is_err());
+}
+
+#[test]
+fn flamegraph_font_type_simple() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/font_type_simple.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.font_type = "Andale Mono".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_font_type_with_quote() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/font_type_with_quote.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.font_type = "Andale Mono\"".to_owned();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_font_type_generic_families() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+
+    let generic_families = &["cursive", "fantasy", "monospace", "serif", "sans-serif"];
+    for family in generic_families {
+        let expected_result_file =
+            format!("./tests/data/flamegraph/options/font_type_{}.svg", family);
+
+        let mut options = flamegraph::Options::default();
+        options.font_type = family.to_string();
+
+        test_flamegraph(input_file, &expected_result_file, options).unwrap();
+    }
+}
+
+#[test]
+fn search_color_non_default() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/search_color.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.search_color = "#7d7d7d".parse().unwrap();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn sync_group() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/sync_group.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.sync_group = Some("it's-a-group".to_string());
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+fn test_report(input_file: &str, expected_result_file: &str, options: report::Options) {
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+    let mut result = Vec::new();
+    report::from_files(&options, &input_files, &mut result).unwrap();
+    if !Path::new(expected_result_file).exists() || std::env::var("INFERNO_BLESS_TESTS").is_ok() {
+        fs::write(expected_result_file, &result).unwrap();
+    }
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    compare_results(Cursor::new(result), expected, expected_result_file);
+}
+
+#[test]
+fn report_text() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/report/flames.txt";
+    test_report(input_file, expected_result_file, report::Options::default());
+}
+
+#[test]
+fn report_markdown_diff() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/report/diff.md";
+
+    let mut options = report::Options::default();
+    options.format = report::ReportFormat::Markdown;
+    options.top = 5;
+    options.name_width = 40;
+
+    test_report(input_file, expected_result_file, options);
+}
+
+#[test]
+fn report_bottom_up() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/report/bottom-up.txt";
+
+    let mut options = report::Options::default();
+    options.top = 3;
+    options.bottom_up = Some(2);
+
+    test_report(input_file, expected_result_file, options);
+}
+
+fn test_gate(
+    input_file: &str,
+    expected_result_file: &str,
+    options: report::GateOptions,
+) -> report::Verdict {
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+    let mut result = Vec::new();
+    let verdict = report::gate_from_files(&options, &input_files, &mut result).unwrap();
+    compare_or_create(result, expected_result_file);
+    verdict
+}
+
+#[test]
+fn gate_json_diff() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/report/gate.json";
+
+    let mut options = report::GateOptions::default();
+    options.fail_threshold_pct = 0.1;
+    options.top = 3;
+
+    let verdict = test_gate(input_file, expected_result_file, options);
+    assert_eq!(verdict, report::Verdict::Fail);
+}
+
+#[test]
+fn gate_openmetrics_pass() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/report/gate-pass.txt";
+
+    let mut options = report::GateOptions::default();
+    options.format = report::GateFormat::OpenMetrics;
+    options.fail_threshold_pct = 50.0;
+    options.include_children = true;
+
+    let verdict = test_gate(input_file, expected_result_file, options);
+    assert_eq!(verdict, report::Verdict::Pass);
+}
+
+#[test]
+fn gate_needs_differential_input() {
+    let input_files = vec![PathBuf::from("./tests/data/flamegraph/base/flames.txt")];
+    let e = report::gate_from_files(&report::GateOptions::default(), &input_files, io::sink())
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+}
+
+fn compare_or_create(result: Vec<u8>, expected_result_file: &str) {
+    if !Path::new(expected_result_file).exists() || std::env::var("INFERNO_BLESS_TESTS").is_ok() {
+        fs::write(expected_result_file, &result).unwrap();
+    }
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    compare_results(Cursor::new(result), expected, expected_result_file);
+}
+
+#[test]
+fn render_all_outputs() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+
+    let mut options = flamegraph::Options::default();
+    options.base = vec!["Final".to_string()];
+    options.deterministic = true;
+    options.pretty_xml = true;
+    options.no_javascript = true;
+
+    let (mut svg, mut json, mut folded) = (Vec::new(), Vec::new(), Vec::new());
+    flamegraph::render_files(
+        &mut options,
+        &input_files,
+        &mut [
+            flamegraph::OutputSink::Svg(&mut svg),
+            flamegraph::OutputSink::Json(&mut json),
+            flamegraph::OutputSink::Folded(&mut folded),
+        ],
+    )
+    .unwrap();
+
+    // The flame graph is the same as without the other outputs.
+    let mut expected_svg = Vec::new();
+    flamegraph::from_files(&mut options, &input_files, &mut expected_svg).unwrap();
+    assert_eq!(String::from_utf8(svg), String::from_utf8(expected_svg));
+
+    compare_or_create(json, "./tests/data/flamegraph/render/single-base.json");
+    compare_or_create(folded, "./tests/data/flamegraph/render/single-base.txt");
+}
+
+#[test]
+fn render_differential_folded() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+
+    let mut options = flamegraph::Options::default();
+    options.reverse_stack_order = true;
+
+    let mut folded = Vec::new();
+    flamegraph::render_files(
+        &mut options,
+        &input_files,
+        &mut [flamegraph::OutputSink::Folded(&mut folded)],
+    )
+    .unwrap();
+
+    compare_or_create(folded, "./tests/data/flamegraph/render/reversed-diff.txt");
+}
+
+#[test]
+#[cfg(feature = "annotations")]
+fn render_stack_weights_folded() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+
+    let mut options = flamegraph::Options::default();
+    options.stack_weights.add("^main;parse", 2.0).unwrap();
+    options.stack_weights.add(";lookup$", 0.5).unwrap();
+
+    let mut folded = Vec::new();
+    flamegraph::render_files(
+        &mut options,
+        &input_files,
+        &mut [flamegraph::OutputSink::Folded(&mut folded)],
+    )
+    .unwrap();
+
+    compare_or_create(folded, "./tests/data/flamegraph/render/stack-weights.txt");
+}
+
+#[test]
+#[cfg(feature = "annotations")]
+fn render_group_rules_folded() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+
+    let mut options = flamegraph::Options::default();
+    options
+        .group_rules
+        .add("^(parse|lex)$", "frontend")
+        .unwrap();
+    options
+        .group_rules
+        .add("^(eval|lookup|apply)$", "runtime")
+        .unwrap();
+
+    let mut folded = Vec::new();
+    flamegraph::render_files(
+        &mut options,
+        &input_files,
+        &mut [flamegraph::OutputSink::Folded(&mut folded)],
+    )
+    .unwrap();
+
+    compare_or_create(folded, "./tests/data/flamegraph/render/group-rules.txt");
+}
+
+#[test]
+fn render_top_stacks_folded() {
+    let input_file = "./tests/data/flamegraph/top-stacks/stacks.txt";
+    let input_files = vec![PathBuf::from_str(input_file).unwrap()];
+
+    let mut options = flamegraph::Options::default();
+    options.top_stacks = Some(3);
+
+    let mut folded = Vec::new();
+    flamegraph::render_files(
+        &mut options,
+        &input_files,
+        &mut [flamegraph::OutputSink::Folded(&mut folded)],
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "(other stacks) 18\nidle 20\nmain;eval;apply 25\nmain;parse;lex 32\n"
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn flamegraph_folded_file_metadata() {
+    use std::io::Write;
+    use std::slice;
+
+    use inferno::folded;
+
+    let input_file = "./tests/data/flamegraph/base/flames.txt";
+    let folded_file =
+        std::env::temp_dir().join(format!("inferno-test-{}.folded", rand::random::<u64>()));
+
+    let mut metadata = folded::Metadata::default();
+    metadata.count_name = Some("ns".to_string());
+    metadata.factor = Some(0.5);
+    metadata.title = Some("Query Latency".to_string());
+    metadata.source = Some("test".to_string());
+    let mut writer = folded::Writer::new(File::create(&folded_file).unwrap(), &metadata).unwrap();
+    io::copy(&mut File::open(input_file).unwrap(), &mut writer).unwrap();
+    writer.finish().unwrap();
+
+    // The metadata fills in the options that were left at their defaults...
+    let mut options = flamegraph::Options::default();
+    options.deterministic = true;
+    let mut result = Vec::new();
+    flamegraph::from_files(&mut options, slice::from_ref(&folded_file), &mut result).unwrap();
+    fs::remove_file(&folded_file).unwrap();
+
+    let mut expected_options = flamegraph::Options::default();
+    expected_options.deterministic = true;
+    expected_options.count_name = "ns".to_string();
+    expected_options.factor = 0.5;
+    expected_options.title = "Query Latency".to_string();
+    let mut expected = Vec::new();
+    flamegraph::from_files(
+        &mut expected_options,
+        &[PathBuf::from(input_file)],
+        &mut expected,
+    )
+    .unwrap();
+    assert_eq!(String::from_utf8(result), String::from_utf8(expected));
+
+    // ...but not the ones that were set.
+    let mut writer = folded::Writer::new(Vec::new(), &metadata).unwrap();
+    writer.write_all(b"main;work 2\n").unwrap();
+    let input = writer.finish().unwrap();
+    let mut options = flamegraph::Options::default();
+    options.count_name = "bytes".to_string();
+    let mut result = Vec::new();
+    flamegraph::from_reader(&mut options, &input[..], &mut result).unwrap();
+    let result = String::from_utf8(result).unwrap();
+    assert!(result.contains("(1 bytes, 100%)"));
+    assert!(result.contains("Query Latency"));
+}
+
+#[test]
+fn stroke_color_non_default() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/stroke_color.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.stroke_color = "#7d7d7d".parse().unwrap();
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn rounded_frames_with_gaps() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/rounded_frames.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.stroke_color = "#7d7d7d".parse().unwrap();
+    options.stroke_width = 0.5;
+    options.frame_radius = 3.0;
+    options.frame_gap = 1.5;
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn ui_color_non_default() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/uicolor_color.svg";
+
+    let mut options = flamegraph::Options::default();
+    options.uicolor = rgb::RGB8 { r: 255, g: 0, b: 0 };
+
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
 #[test]
@@ -840,6 +2176,31 @@ fn flamegraph_no_sort_should_return_error_on_unsorted_input() {
     assert!(test_flamegraph(input_file, expected_result_file, options).is_err());
 }
 
+#[test]
+fn flamegraph_sibling_order_is_stable() {
+    let render = |input: &str| {
+        let mut options = flamegraph::Options::default();
+        options.hash = true;
+        let mut svg = Vec::new();
+        flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let sorted = render("main;parse 2\nmain;parse 3\nmain;parse;lex 5\nmain;parse::lex 5\n");
+    for shuffled in [
+        "main;parse::lex 5\nmain;parse;lex 5\nmain;parse 3\nmain;parse 2\n",
+        "main;parse 3\nmain;parse::lex 5\nmain;parse 2\nmain;parse;lex 5\n",
+    ] {
+        assert_eq!(render(shuffled), sorted);
+    }
+
+    // Input sorted as whole lines is still taken as sorted.
+    let mut options = flamegraph::Options::default();
+    options.no_sort = true;
+    let input = "main;parse 5\nmain;parse::lex 5\nmain;parse;lex 5\n";
+    flamegraph::from_reader(&mut options, input.as_bytes(), io::sink()).unwrap();
+}
+
 #[test]
 fn flamegraph_reversed_stack_ordering() {
     let input_file =
@@ -927,6 +2288,62 @@ fn flamegraph_should_warn_about_bad_input_lines_with_reversed_stack_ordering() {
     );
 }
 
+#[test]
+fn flamegraph_cli_config() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let config_file = "./tests/data/config/inferno.toml";
+
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--config")
+        .arg(config_file)
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.contains(">House Style</text>"));
+
+    // The library reads the same file to the same flame graph
+    #[cfg(feature = "serde")]
+    {
+        let config = fs::read_to_string(config_file).unwrap();
+        let mut options = Options::from_toml_str(&config).unwrap();
+        let mut library_svg = Vec::new();
+        flamegraph::from_files(&mut options, &[PathBuf::from(input_file)], &mut library_svg)
+            .unwrap();
+        assert_eq!(String::from_utf8(library_svg).unwrap(), svg);
+    }
+
+    // Flags on the command line take precedence over the config file
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--config")
+        .arg(config_file)
+        .arg("--title")
+        .arg("Override")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.contains(">Override</text>"));
+    assert!(!svg.contains("House Style"));
+
+    // Unknown keys are rejected
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--config")
+        .arg("./tests/data/config/unknown-key.toml")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown field `titel`"));
+}
+
 #[test]
 fn flamegraph_cli() {
     let input_file = "./flamegraph/test/results/perf-vertx-stacks-01-collapsed-all.txt";
@@ -979,6 +2396,19 @@ fn flamegraph_cli() {
     compare_results(Cursor::new(output.stdout), expected, expected_file);
 }
 
+#[test]
+fn flamegraph_shrink_text_to_fit() {
+    let input_file = "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/shrink_text.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.deterministic = true;
+    opts.shrink_text_to_fit = true;
+    opts.min_font_size = 8;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
 #[test]
 fn flamegraph_colors_truncate_right() {
     let input_file = "./flamegraph/test/results/perf-java-stacks-01-collapsed-all.txt";
@@ -993,6 +2423,42 @@ fn flamegraph_colors_truncate_right() {
     test_flamegraph(input_file, expected_result_file, opts).unwrap();
 }
 
+#[test]
+fn flamegraph_color_modifier_self_ratio() {
+    let input_file = "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt";
+    let expected_result_file = "./tests/data/flamegraph/colors/self-ratio.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.hash = true;
+    opts.color_modifier = Some(ColorModifier::SelfRatio);
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_palette_range() {
+    let input_file = "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt";
+    let expected_result_file = "./tests/data/flamegraph/colors/palette-range.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.hash = true;
+    opts.palette_ranges = vec!["hot=#0050a0..#40c0ff".parse::<PaletteRange>().unwrap()];
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_truncate_middle() {
+    let input_file = "./tests/data/flamegraph/colors/async-profiler-collapsed-part.txt";
+    let expected_result_file = "./tests/data/flamegraph/options/truncate-middle.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.deterministic = true;
+    opts.text_truncate_direction = TextTruncateDirection::Middle;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
 #[test]
 fn flamegraph_flamechart() {
     let input_file = "./tests/data/flamegraph/flamechart/flames.txt";
@@ -1006,6 +2472,178 @@ fn flamegraph_flamechart() {
     test_flamegraph(input_file, expected_result_file, opts).unwrap();
 }
 
+#[test]
+fn flamegraph_flamechart_scaled_spans() {
+    let input_file = "./tests/data/flamegraph/flamechart/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/flamechart/scaled.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.title = flamegraph::defaults::CHART_TITLE.to_owned();
+    opts.flame_chart = true;
+    opts.factor = 0.25;
+    opts.count_name = "ms".to_owned();
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_flamechart_time_range() {
+    let input_file = "./tests/data/flamegraph/flamechart/flames.txt";
+    let expected_result_file = "./tests/data/flamegraph/flamechart/time-range.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.title = flamegraph::defaults::CHART_TITLE.to_owned();
+    opts.flame_chart = true;
+    opts.hash = true;
+    opts.factor = 0.001;
+    opts.time_range = Some((150.0, 300.0));
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_flamechart_time_range_clips_frames() {
+    // Flame charts run from the last line to the first.
+    let lines = ["main;c 10", "main;b 10", "main;a 10"];
+    let mut options = flamegraph::Options::default();
+    options.flame_chart = true;
+    options.time_range = Some((5.0, 25.0));
+
+    let mut folded = Vec::new();
+    let mut svg = Vec::new();
+    flamegraph::render(
+        &mut options,
+        lines,
+        &mut [
+            flamegraph::OutputSink::Folded(&mut folded),
+            flamegraph::OutputSink::Svg(&mut svg),
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "main;a 5\nmain;b 10\nmain;c 5\n"
+    );
+    // Frames still tell when they start and end in the whole recording.
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.contains(r#"data-start="5" data-end="25""#));
+    assert!(svg.contains(r#"data-start="10" data-end="20""#));
+}
+
+#[test]
+fn flamegraph_time_range_without_flamechart() {
+    let mut options = flamegraph::Options::default();
+    options.time_range = Some((0.0, 10.0));
+
+    test_flamegraph_logs_with_options(
+        "./tests/data/flamegraph/flamechart/flames.txt",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| log.body.starts_with("Time ranges are only supported"))
+                .count();
+            assert_eq!(nwarnings, 1);
+        },
+        options,
+    );
+}
+
+#[test]
+fn flamegraph_flamechart_show_occurrences() {
+    let input_file = "./tests/data/flamegraph/flamechart/loop.txt";
+    let expected_result_file = "./tests/data/flamegraph/flamechart/occurrences.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.title = flamegraph::defaults::CHART_TITLE.to_owned();
+    opts.flame_chart = true;
+    opts.show_occurrences = true;
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_show_pct_of_parent() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/pct-of-parent/pct-of-parent.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.show_pct_of_parent = true;
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_primary_metric_self() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/primary-metric/self.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.primary_metric = flamegraph::PrimaryMetric::SelfCount;
+    opts.count_name = "bytes".to_string();
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_primary_metric_percent() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/primary-metric/percent.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.primary_metric = flamegraph::PrimaryMetric::Percent;
+    opts.show_pct_of_parent = true;
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_all_frame_stats() {
+    let input_file = "./tests/data/flamegraph/all-frame-stats/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/all-frame-stats/all-frame-stats.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.all_frame_stats = true;
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_all_frame_stats_time() {
+    let input_file = "./tests/data/flamegraph/all-frame-stats/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/all-frame-stats/all-frame-stats-time.svg";
+
+    // Samples taken every 10 ms.
+    let mut opts = flamegraph::Options::default();
+    opts.all_frame_stats = true;
+    opts.factor = 10.0;
+    opts.count_name = "ms".to_string();
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
+#[test]
+fn flamegraph_bookmarks() {
+    let input_file = "./tests/data/flamegraph/pct-of-parent/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/bookmarks/bookmarks.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.bookmarks = vec![
+        ("Parser".to_string(), "parse".to_string()),
+        ("Lookups".to_string(), "lookup".to_string()),
+    ];
+    opts.subtitle = Some("Bookmarks go below the subtitle".to_string());
+    opts.hash = true;
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
 #[test]
 fn flamegraph_base_symbol() {
     let input_file = "./tests/data/flamegraph/base/flames.txt";
@@ -0,0 +1,120 @@
+use inferno::folded::{self, Line, Stats};
+
+#[test]
+fn folded_stats() {
+    let lines = [
+        "main;parse;memcpy 30",
+        "main;render;draw 50",
+        "main;render;draw 10",
+        "main;render 10",
+        "worker;memcpy 20",
+        "",
+        "not a line",
+        "main;idle 0",
+    ];
+    let stats = folded::stats(lines);
+    assert_eq!(stats.total_samples, 120);
+    assert_eq!(stats.distinct_stacks, 4);
+    assert_eq!(stats.distinct_functions, 6);
+    assert_eq!(stats.max_depth, 3);
+    assert_eq!(
+        stats.self_samples,
+        [
+            ("draw".to_string(), 60),
+            ("memcpy".to_string(), 50),
+            ("render".to_string(), 10),
+        ]
+    );
+    assert_eq!(stats.top_self(2), &stats.self_samples[..2]);
+    assert_eq!(stats.top_self(10), &stats.self_samples[..]);
+    assert_eq!(stats.self_percent("draw"), 50.0);
+    assert_eq!(stats.self_percent("main"), 0.0);
+}
+
+#[test]
+fn folded_stats_differential() {
+    let stats = folded::stats(["main;foo 10 20", "main;bar 5.0 7.5"]);
+    assert_eq!(stats.total_samples, 27);
+    assert_eq!(stats.distinct_stacks, 2);
+    assert_eq!(stats.top_self(1), [("foo".to_string(), 20)]);
+
+    // Whether the input is differential is up to its first line, as with flamegraph.
+    let stats = folded::stats(["main;foo 10", "main;retry 3 7"]);
+    assert_eq!(stats.total_samples, 17);
+    assert_eq!(stats.top_self(1), [("foo".to_string(), 10)]);
+    assert_eq!(stats.self_samples[1], ("retry 3".to_string(), 7));
+}
+
+#[test]
+fn folded_stats_empty() {
+    let stats = folded::stats(Vec::new());
+    assert_eq!(stats, Stats::default());
+    assert_eq!(stats.self_percent("main"), 0.0);
+}
+
+#[test]
+fn folded_line() {
+    let line = Line::parse("  main;parse;memcpy   30.7 \n", false).unwrap();
+    assert_eq!(line.stack(), "main;parse;memcpy");
+    assert_eq!(line.samples(), 30);
+    assert_eq!(line.samples_before(), None);
+    assert_eq!(
+        line.frames().rev().collect::<Vec<_>>(),
+        ["memcpy", "parse", "main"]
+    );
+
+    let line = Line::parse("main;a\\;b 1 2", true).unwrap();
+    assert_eq!(line.frames().collect::<Vec<_>>(), ["main", "a\\;b"]);
+    assert_eq!(line.samples_before(), Some(1));
+    assert_eq!(line.samples(), 2);
+
+    // A stack that looks like a count is still a stack.
+    let line = Line::parse("404 5", false).unwrap();
+    assert_eq!(line.stack(), "404");
+    assert_eq!(line.samples_before(), None);
+    assert_eq!(Line::is_differential("404 5"), Some(false));
+
+    // A number at the end of a stack is part of it, unless the input is differential.
+    let line = Line::parse("main;retry 3 7", false).unwrap();
+    assert_eq!(line.stack(), "main;retry 3");
+    assert_eq!(line.samples_before(), None);
+    assert_eq!(line.samples(), 7);
+    assert_eq!(Line::parse("main;foo 7", true), None);
+    assert_eq!(Line::parse("5 7", true), None);
+
+    for invalid in [
+        "",
+        "main;foo",
+        "main;foo bar",
+        "main;foo -3",
+        "5",
+        "main;foo 1.x",
+    ] {
+        assert_eq!(Line::parse(invalid, false), None, "{:?}", invalid);
+        assert_eq!(Line::is_differential(invalid), None, "{:?}", invalid);
+    }
+}
+
+#[test]
+fn folded_reverse() {
+    let lines = [
+        "  main;parse;memcpy 30.5 ",
+        "",
+        "main;foo 10 20",
+        "main;a\\;b 1",
+        // An unescaped `; ` leaves a space at the start of the reversed stack, which is trimmed.
+        "main;<[u8; 8] as Debug>::fmt 5",
+        // Lines without a count are reversed as a whole, whatever their last character.
+        "main;café",
+    ];
+    assert_eq!(
+        folded::reverse(lines).collect::<Vec<_>>(),
+        [
+            "memcpy;parse;main 30.5",
+            "foo;main 10 20",
+            "a\\;b;main 1",
+            "8] as Debug>::fmt;<[u8;main 5",
+            "café;main",
+        ]
+    );
+}